@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use document::{self, Document};
+use error::Result;
+use forms::fields;
+use pages::{self, Page};
+use parsers::cos::{self, DictNode};
+use trees;
+
+/*
+ * The annotations on a page, from its /Annots: links, comments, highlights,
+ * form widgets and so on.
+ *
+ *   for annotation in annotations::annotations(&mut doc, &page)? {
+ *       if let Some(Target::Uri(ref uri)) = annotation.target {
+ *           println!("{:?} links to {}", annotation.rect, uri);
+ *       }
+ *   }
+ *
+ * Each comes with its subtype, its rectangle and its /Contents. Those with
+ * a /Dest, or an /A action (links mostly, widgets and screen annotations
+ * can have one too), also get where they lead as a `Target`: a URI, a page
+ * of the document, a page of another file, or a named action such as
+ * NextPage. Other kinds of actions (JavaScript, SubmitForm...) only have
+ * their type kept.
+ *
+ * Destinations are arrays, [page /XYZ left top zoom] and the like, with the
+ * page given as a reference to the page object. They can also be given by
+ * name, looked up in the catalog's /Dests dictionary (PDF 1.1) or in the
+ * /Dests name tree of its /Names. Named destinations are dictionaries with
+ * the array in /D as often as not. Remote ones (GoToR) give the page as a
+ * number instead, the other file isn't opened.
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Annotation {
+    // None for annotations written directly into /Annots
+    pub id: Option<u64>,
+    // Link, Text, Widget, Highlight...
+    pub subtype: String,
+    // [llx lly urx ury] in default user space
+    pub rect: [f64; 4],
+    pub contents: Option<String>,
+    pub target: Option<Target>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Target {
+    Uri(String),
+    Page(Destination),
+    // another file, and the page there if the destination gives one
+    Remote { file: String, page: Option<usize> },
+    // NextPage, PrevPage, FirstPage, LastPage, or anything a viewer defines
+    Named(String),
+    // an action of some other type, /S
+    Other(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Destination {
+    // index of the page in the document, None when the destination doesn't
+    // lead to one of its pages
+    pub page: Option<usize>,
+    // XYZ, Fit, FitH, FitV, FitR, FitB, FitBH or FitBV
+    pub fit: String,
+    // the numbers after it, None for a null (keep what the viewer has)
+    pub position: Vec<Option<f64>>,
+    // what the destination was looked up by, if it was named
+    pub name: Option<String>,
+}
+
+// What resolving targets needs to know about the document.
+struct Context {
+    // object number of each page, to its index
+    pages: HashMap<u64, usize>,
+    // named destinations, resolved but for the page references in them
+    names: HashMap<Vec<u8>, DictNode>,
+}
+
+// The page's annotations, in /Annots order.
+pub fn annotations(doc: &mut Document, page: &Page) -> Result<Vec<Annotation>> {
+    let pages = pages::pages(doc)?;
+    let context = Context::new(doc, &pages)?;
+    read(doc, page, &context)
+}
+
+// The annotations of every page, by page index.
+pub fn by_page(doc: &mut Document) -> Result<Vec<Vec<Annotation>>> {
+    let pages = pages::pages(doc)?;
+    let context = Context::new(doc, &pages)?;
+    let mut annotations = Vec::new();
+    for page in &pages {
+        annotations.push(read(doc, page, &context)?);
+    }
+    Ok(annotations)
+}
+
+impl Context {
+    fn new(doc: &mut Document, pages: &[Page]) -> Result<Context> {
+        let pages = pages.iter().filter_map(|page| page.id.map(|id| (id, page.index))).collect();
+        let mut names = HashMap::new();
+        let catalog = match doc.trailer_entry("Root")? {
+            Some((_, catalog)) => catalog,
+            None => return Ok(Context { pages, names }),
+        };
+        // the name tree wins over the older dictionary
+        if let Some(DictNode::Dict(dests)) = catalog.get_resolved(doc, "Dests")? {
+            for (name, dest) in dests {
+                names.insert(name.into_bytes(), dest);
+            }
+        }
+        if let Some(tree) = catalog.get_resolved(doc, "Names")?.and_then(|names| names.get("Dests").cloned()) {
+            for (name, dest) in trees::read_name_tree(doc, &tree)? {
+                names.insert(name, dest);
+            }
+        }
+        for dest in names.values_mut() {
+            *dest = resolve_destination(doc, dest)?;
+        }
+        Ok(Context { pages, names })
+    }
+}
+
+// A destination with the references around it followed: to the array, or
+// the dictionary and the array in its /D. The page reference in the array
+// is what tells which page it is, that one stays.
+fn resolve_destination(doc: &mut Document, dest: &DictNode) -> Result<DictNode> {
+    let dest = doc.resolve(dest)?;
+    match dest.get_resolved(doc, "D")? {
+        Some(array) => Ok(DictNode::Dict(HashMap::from([("D".to_string(), array)]))),
+        None => Ok(dest),
+    }
+}
+
+fn read(doc: &mut Document, page: &Page, context: &Context) -> Result<Vec<Annotation>> {
+    let mut annotations = Vec::new();
+    let annots = match page.dict.get_resolved(doc, "Annots")? {
+        Some(DictNode::Array(annots)) => annots,
+        _ => return Ok(annotations),
+    };
+    for annot in annots {
+        let id = annot.as_reference().filter(|&(id, _)| id >= 0).map(|(id, _)| id as u64);
+        let dict = doc.resolve(&annot)?;
+        if dict.as_dict().is_none() {
+            continue;
+        }
+        let rect = dict.get_resolved(doc, "Rect")?.and_then(|rect| rect.as_rect()).unwrap_or([0.0; 4]);
+        let target = match (dict.get("Dest"), dict.get_resolved(doc, "A")?) {
+            (Some(dest), _) => {
+                let dest = resolve_destination(doc, dest)?;
+                destination(&dest, context, 0).map(Target::Page)
+            },
+            (None, Some(action)) => {
+                // what the action's entries point to, but for page references
+                let mut resolved = HashMap::new();
+                for key in &["S", "URI", "F", "N"] {
+                    if let Some(value) = action.get_resolved(doc, key)? {
+                        resolved.insert(key.to_string(), value);
+                    }
+                }
+                if let Some(dest) = action.get("D") {
+                    resolved.insert("D".to_string(), resolve_destination(doc, dest)?);
+                }
+                target(&DictNode::Dict(resolved), context)
+            },
+            (None, None) => None,
+        };
+        annotations.push(Annotation {
+            id,
+            subtype: dict.get("Subtype").and_then(DictNode::as_name).unwrap_or("").to_string(),
+            rect: [rect[0].min(rect[2]), rect[1].min(rect[3]), rect[0].max(rect[2]), rect[1].max(rect[3])],
+            contents: dict.get_resolved(doc, "Contents")?.as_ref().and_then(fields::text),
+            target,
+        });
+    }
+    Ok(annotations)
+}
+
+// Where an action leads.
+fn target(action: &DictNode, context: &Context) -> Option<Target> {
+    let kind = action.get("S").and_then(DictNode::as_name)?;
+    Some(match kind {
+        "URI" => Target::Uri(action.get("URI").and_then(DictNode::as_string).map(cos::latin1).unwrap_or_default()),
+        "GoTo" => Target::Page(destination(action.get("D")?, context, 0)?),
+        "GoToR" => Target::Remote {
+            file: action.get("F").and_then(document::file_name).unwrap_or_default(),
+            // only a page number means anything without the other file
+            page: action.get("D")
+                .and_then(|dest| dest.as_array().or_else(|| dest.get("D").and_then(DictNode::as_array)))
+                .and_then(<[DictNode]>::first)
+                .and_then(DictNode::as_int)
+                .filter(|&page| page >= 0)
+                .map(|page| page as usize),
+        },
+        "Named" => Target::Named(action.get("N").and_then(DictNode::as_name).unwrap_or("").to_string()),
+        other => Target::Other(other.to_string()),
+    })
+}
+
+// An explicit destination, or the one a name stands for. `depth` keeps
+// names standing for names from going round in circles.
+fn destination(dest: &DictNode, context: &Context, depth: usize) -> Option<Destination> {
+    let name = match *dest {
+        DictNode::Array(ref items) => {
+            let page = match items.first() {
+                Some(&DictNode::ObjectReference(id, _)) if id >= 0 => context.pages.get(&(id as u64)).cloned(),
+                _ => None,
+            };
+            return Some(Destination {
+                page,
+                fit: items.get(1).and_then(DictNode::as_name).unwrap_or("XYZ").to_string(),
+                position: items.iter().skip(2).map(DictNode::as_float).collect(),
+                name: None,
+            });
+        },
+        DictNode::Dict(_) => return destination(dest.get("D")?, context, depth),
+        DictNode::Str(ref name) => name.trim_start_matches('/').as_bytes().to_vec(),
+        DictNode::LiteralString(ref name) | DictNode::HexString(ref name) => name.clone(),
+        _ => return None,
+    };
+    if depth > 0 {
+        return None;
+    }
+    let found = match context.names.get(&name) {
+        Some(found) => destination(found, context, depth + 1),
+        None => Some(Destination { page: None, fit: "XYZ".to_string(), position: Vec::new(), name: None }),
+    };
+    found.map(|found| Destination { name: Some(cos::latin1(&name)), ..found })
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Uri(ref uri) => write!(f, "{}", uri),
+            Target::Page(ref dest) => {
+                match dest.page {
+                    Some(page) => write!(f, "page {}", page + 1)?,
+                    None => write!(f, "no page")?,
+                }
+                match dest.name {
+                    Some(ref name) => write!(f, " (\"{}\")", name),
+                    None => Ok(()),
+                }
+            },
+            Target::Remote { ref file, page: Some(page) } => write!(f, "{}, page {}", file, page + 1),
+            Target::Remote { ref file, page: None } => write!(f, "{}", file),
+            Target::Named(ref name) => write!(f, "{}", name),
+            Target::Other(ref kind) => write!(f, "{} action", kind),
+        }
+    }
+}
+
+#[test]
+fn test_targets() {
+    let context = Context {
+        pages: hashmap!{ 12 => 0, 40 => 1 },
+        names: hashmap!{
+            b"chapter2".to_vec() => DictNode::Dict(hashmap!{ "D".to_string() => DictNode::Array(vec![
+                DictNode::reference(40), DictNode::name("XYZ"),
+                DictNode::Int(72), DictNode::Int(700), DictNode::Null,
+            ]) }),
+            b"loop".to_vec() => DictNode::LiteralString(b"loop".to_vec()),
+        },
+    };
+    let action = |entries: Vec<(&str, DictNode)>| DictNode::Dict(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+
+    let uri = action(vec![("S", DictNode::name("URI")), ("URI", DictNode::LiteralString(b"mailto:human@alumni.ubc.ca".to_vec()))]);
+    assert_eq!(target(&uri, &context), Some(Target::Uri("mailto:human@alumni.ubc.ca".to_string())));
+
+    let goto = action(vec![("S", DictNode::name("GoTo")), ("D", DictNode::Array(vec![DictNode::reference(12), DictNode::name("Fit")]))]);
+    assert_eq!(target(&goto, &context), Some(Target::Page(Destination { page: Some(0), fit: "Fit".to_string(), position: Vec::new(), name: None })));
+
+    let named = action(vec![("S", DictNode::name("GoTo")), ("D", DictNode::LiteralString(b"chapter2".to_vec()))]);
+    let dest = match target(&named, &context) {
+        Some(Target::Page(dest)) => dest,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!((dest.page, &dest.position[..]), (Some(1), &[Some(72.0), Some(700.0), None][..]));
+    assert_eq!(Target::Page(dest).to_string(), "page 2 (\"chapter2\")");
+
+    // a name standing for a name, and one that isn't defined
+    assert_eq!(destination(&DictNode::LiteralString(b"loop".to_vec()), &context, 0), None);
+    assert_eq!(destination(&DictNode::name("missing"), &context, 0).and_then(|dest| dest.page), None);
+
+    let remote = action(vec![("S", DictNode::name("GoToR")), ("F", DictNode::LiteralString(b"other.pdf".to_vec())),
+                             ("D", DictNode::Array(vec![DictNode::Int(4), DictNode::name("Fit")]))]);
+    assert_eq!(target(&remote, &context), Some(Target::Remote { file: "other.pdf".to_string(), page: Some(4) }));
+    assert_eq!(target(&action(vec![("S", DictNode::name("Named")), ("N", DictNode::name("NextPage"))]), &context), Some(Target::Named("NextPage".to_string())));
+    assert_eq!(target(&action(vec![("S", DictNode::name("JavaScript"))]), &context).unwrap().to_string(), "JavaScript action");
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use forms::builder::text_string;
+use info::PdfDate;
+use writer::Writer;
+use parsers::cos::DictNode;
+use transcode;
+use trees::{self, NameTreeBuilder};
+
+/*
+ * Embedding files in a document, as attachments listed in the catalog's
+ * /Names /EmbeddedFiles tree.
+ *
+ *   let mut attachment = Attachment::from_file(Path::new("data.csv"))?;
+ *   attachment.description = Some("The numbers behind table 2".to_string());
+ *   attach::attach(input, output, &[attachment])?;
+ *
+ * Each file becomes an /EmbeddedFile stream, stored as it is, with its
+ * MIME type as /Subtype and its size and modification date in /Params,
+ * and a file specification with its name (/F and /UF) and description
+ * (/Desc) pointing at it. Files already attached stay, unless a new one
+ * has the same name: the tree keys on names, so the new one replaces it.
+ *
+ * `from_file` names attachments after the file, dates them with its
+ * modification time and guesses the MIME type from the extension.
+ *
+ * Like the other rewrites this is an incremental update, encrypted
+ * documents are refused.
+ */
+
+// By lowercased extension, the types people usually attach.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("gif", "image/gif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("txt", "text/plain"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip"),
+];
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Attachment {
+    // what it's listed as, and the name it's saved under
+    pub name: String,
+    pub data: Vec<u8>,
+    pub mime_type: Option<String>,
+    pub description: Option<String>,
+    pub modified: Option<PdfDate>,
+}
+
+impl Attachment {
+    pub fn from_file(path: &Path) -> Result<Attachment> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(PdfError::Syntax { what: "attachment file name", position: 0 }),
+        };
+        let modified = fs::metadata(path)?.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| PdfDate::from_unix(elapsed.as_secs() as i64));
+        Ok(Attachment { mime_type: mime_type(&name).map(str::to_string), data: fs::read(path)?, name, description: None, modified })
+    }
+
+    // The /EmbeddedFile stream's dictionary.
+    fn stream_dict(&self) -> DictNode {
+        let mut params = HashMap::from([("Size".to_string(), DictNode::Int(self.data.len() as i64))]);
+        if let Some(modified) = self.modified {
+            params.insert("ModDate".to_string(), DictNode::LiteralString(modified.to_pdf().into_bytes()));
+        }
+        let mut dict = HashMap::from([
+            ("Type".to_string(), DictNode::name("EmbeddedFile")),
+            ("Length".to_string(), DictNode::Int(self.data.len() as i64)),
+            ("Params".to_string(), DictNode::Dict(params)),
+        ]);
+        if let Some(ref mime_type) = self.mime_type {
+            dict.insert("Subtype".to_string(), DictNode::name(mime_type));
+        }
+        DictNode::Dict(dict)
+    }
+
+    // The file specification for it, with its stream as object `stream_id`.
+    fn file_spec(&self, stream_id: u64) -> DictNode {
+        let stream = DictNode::reference(stream_id);
+        let mut dict = HashMap::from([
+            ("Type".to_string(), DictNode::name("Filespec")),
+            ("F".to_string(), text_string(&self.name)),
+            ("UF".to_string(), text_string(&self.name)),
+            ("EF".to_string(), DictNode::Dict(HashMap::from([("F".to_string(), stream.clone()), ("UF".to_string(), stream)]))),
+        ]);
+        if let Some(ref description) = self.description {
+            dict.insert("Desc".to_string(), text_string(description));
+        }
+        DictNode::Dict(dict)
+    }
+}
+
+// The MIME type for a file name's extension, if it's a common one.
+pub fn mime_type(name: &str) -> Option<&'static str> {
+    let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+    MIME_TYPES.iter().find(|&&(known, _)| known == extension).map(|&(_, mime_type)| mime_type)
+}
+
+pub fn attach(input: &Path, output: &Path, attachments: &[Attachment]) -> Result<()> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options)?;
+    let (catalog_id, mut catalog) = match doc.trailer_entry("Root")? {
+        Some((Some(id), DictNode::Dict(catalog))) => (id, catalog),
+        _ => return Err(PdfError::Syntax { what: "document catalog", position: 0 }),
+    };
+    let names_id = match catalog.get("Names") {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => Some(id as u64),
+        _ => None,
+    };
+    let mut names = match catalog.get("Names") {
+        Some(names) => doc.resolve(names)?.as_dict().cloned().unwrap_or_default(),
+        None => HashMap::new(),
+    };
+
+    let mut tree = NameTreeBuilder::new();
+    if let Some(existing) = names.get("EmbeddedFiles") {
+        for (key, value) in trees::read_name_tree(&mut doc, existing)? {
+            tree.insert(key, value);
+        }
+    }
+    let xref = doc.xref.clone();
+    let mut out = Writer::appending(fs::read(input)?, xref);
+    for attachment in attachments {
+        let stream_id = out.reserve();
+        out.stream(stream_id, &attachment.stream_dict(), &attachment.data)?;
+        let spec_id = out.reserve();
+        out.object(spec_id, &attachment.file_spec(stream_id))?;
+        let key = text_string(&attachment.name).as_string().unwrap_or_default().to_vec();
+        tree.insert(key, DictNode::reference(spec_id));
+    }
+
+    // the tree's nodes are numbered from the root's number on
+    let root_id = out.reserve();
+    let tree = tree.build(root_id + 1);
+    for &(id, ref node) in &tree.nodes {
+        out.reserve();
+        out.object(id, node)?;
+    }
+    out.object(root_id, &tree.root)?;
+    names.insert("EmbeddedFiles".to_string(), DictNode::reference(root_id));
+    match names_id {
+        Some(names_id) => out.object(names_id, &DictNode::Dict(names))?,
+        None => {
+            catalog.insert("Names".to_string(), DictNode::Dict(names));
+            out.object(catalog_id, &DictNode::Dict(catalog))?;
+        },
+    }
+
+    let (mut data, xref) = (out.data, out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+#[test]
+fn test_attachment() {
+    assert_eq!(mime_type("Report.PDF"), Some("application/pdf"));
+    assert_eq!(mime_type("notes"), None);
+
+    let attachment = Attachment {
+        name: "data.csv".to_string(),
+        data: b"a,b\n1,2\n".to_vec(),
+        mime_type: mime_type("data.csv").map(str::to_string),
+        description: Some("Table 2".to_string()),
+        modified: PdfDate::parse("D:20240102030405Z"),
+    };
+    assert_eq!(attachment.stream_dict().to_string(),
+               "<< /Length 8 /Params << /ModDate (D:20240102030405Z) /Size 8 >> /Subtype /text#2fcsv /Type /EmbeddedFile >>");
+    assert_eq!(attachment.file_spec(12).to_string(),
+               "<< /Desc (Table 2) /EF << /F 12 0 R /UF 12 0 R >> /F (data.csv) /Type /Filespec /UF (data.csv) >>");
+}
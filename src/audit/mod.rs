@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use filters;
+use object_streams::ObjectStream;
+use parsers::content::Operations;
+use parsers::cos::{self, DictNode, MAX_DEPTH};
+use parsers::object;
+use parsers::xref;
+use streams;
+
+/*
+ * The robustness contract of the parsing core: whatever bytes a file holds,
+ * the COS, object, xref, content stream, filter and object stream code
+ * returns an error instead of panicking, and doesn't allocate or recurse
+ * in proportion to a number the file made up.
+ *
+ * Two things hold it up. The first is a lint over the sources below:
+ * outside of tests they may not call unwrap, expect or any of the panicking
+ * macros, which covers the easy way to break the contract. Code that knows
+ * better than the compiler falls back to a default instead
+ * (`unwrap_or_default`...). Building with `--features audit` has clippy
+ * check the same thing: each of those modules opens with a deny for it.
+ *
+ * The second is a set of hostile inputs for what the lint can't see,
+ * slicing and arithmetic: truncated everything, absurd numbers, deep
+ * nesting. Any of them panicking fails its test, what they return doesn't
+ * matter. Anything that ever crashed the parser belongs here.
+ */
+
+const CORE: &[(&str, &str)] = &[
+    ("parsers/content", include_str!("../parsers/content/mod.rs")),
+    ("parsers/cos", include_str!("../parsers/cos/mod.rs")),
+    ("parsers/object", include_str!("../parsers/object/mod.rs")),
+    ("parsers/xref", include_str!("../parsers/xref/mod.rs")),
+    ("filters", include_str!("../filters/mod.rs")),
+    ("streams", include_str!("../streams/mod.rs")),
+    ("object_streams", include_str!("../object_streams/mod.rs")),
+];
+
+const DENIED: &[&str] = &[".unwrap()", ".expect(", "panic!(", "unreachable!(", "unimplemented!(", "todo!("];
+
+// Everything before the tests, which sit at the bottom of each module.
+fn production(source: &str) -> &str {
+    let end = ["#[test]", "#[cfg(test)]"].iter()
+        .filter_map(|marker| source.find(marker))
+        .min()
+        .unwrap_or(source.len());
+    &source[..end]
+}
+
+// "module:line: code" for every denied call in the module's production code.
+fn violations(module: &str, source: &str) -> Vec<String> {
+    production(source).lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim_start().starts_with("//"))
+        .filter(|&(_, line)| DENIED.iter().any(|denied| line.contains(denied)))
+        .map(|(index, line)| format!("{}:{}: {}", module, index + 1, line.trim()))
+        .collect()
+}
+
+fn dict(entries: &[(&str, DictNode)]) -> DictNode {
+    DictNode::Dict(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect::<HashMap<_, _>>())
+}
+
+#[test]
+fn test_lint() {
+    assert_eq!(violations("example", "let a = b.unwrap();\n// c.unwrap()\n#[test]\nfn t() { d.unwrap(); }"),
+               vec!["example:1: let a = b.unwrap();"]);
+    let violations: Vec<String> = CORE.iter().flat_map(|&(module, source)| violations(module, source)).collect();
+    assert!(violations.is_empty(), "possible panics in the parsing core:\n{}", violations.join("\n"));
+}
+
+#[test]
+fn test_hostile_objects() {
+    let deep_array = "[".repeat(MAX_DEPTH * 40);
+    let deep_dict = "<</A ".repeat(MAX_DEPTH * 40);
+    let inputs: Vec<&[u8]> = vec![
+        b"",
+        b"1 0 obj",
+        b"1 0 obj\n<<",
+        b"1 0 obj\n<< /Length 5 >>\nstream",
+        b"1 0 obj\n<< /Length 9223372036854775807 >>\nstream\nabc\nendstream\nendobj",
+        b"1 0 obj\n<< /Length -1 >>\nstream\n",
+        b"99999999999999999999 0 obj\nnull\nendobj",
+        b"1 0 obj\n99999999999999999999 0 R\nendobj",
+        b"1 0 obj\n(unbalanced ((\\\nendobj",
+        b"1 0 obj\n<unterminated hex\nendobj",
+        b"1 0 obj\n/#\nendobj",
+        b"1 0 obj\n/#4\nendobj",
+        b"1 0 obj\n<< /A#zz 1 >>\nendobj",
+        b"1 0 obj\n(\\777\\\r)\nendobj",
+        b"1 0 obj\n\xff\xfe\x00\x80 2 0 obj",
+        deep_array.as_bytes(),
+        deep_dict.as_bytes(),
+    ];
+    for input in inputs {
+        let _ = object::scan(input);
+        let _ = object::parse(input);
+        let _ = cos::parse_bytes(input);
+        let _ = streams::raw_body(input);
+    }
+    assert!(cos::parse(&deep_array).is_err());
+    assert_eq!(streams::find(b"abc", b""), Some(0));
+}
+
+#[test]
+fn test_hostile_xref() {
+    let tables = [
+        "",
+        "xref\n",
+        "xref\n0 1\n",
+        "xref\n99999999999999999999 1\n0000000000 65535 f\ntrailer\n",
+        "xref\n18446744073709551615 2\n0000000000 65535 f\n0000000000 00000 n\ntrailer\n",
+        "xref\n0 1\n99999999999999999999 99999999999999999999 n\ntrailer\n",
+    ];
+    for table in &tables {
+        let _ = xref::parse(table);
+    }
+
+    let streams = [
+        dict(&[]),
+        DictNode::Null,
+        dict(&[("W", DictNode::Array(vec![DictNode::Int(1), DictNode::Int(2)]))]),
+        dict(&[("W", DictNode::Array(vec![DictNode::Int(0), DictNode::Int(0), DictNode::Int(0)])), ("Size", DictNode::Int(1))]),
+        dict(&[("W", DictNode::Array(vec![DictNode::Int(9), DictNode::Int(9), DictNode::Int(9)])), ("Size", DictNode::Int(1))]),
+        dict(&[("W", DictNode::Array(vec![DictNode::Int(-1), DictNode::Int(4), DictNode::Int(1)])), ("Size", DictNode::Int(1))]),
+        dict(&[("W", DictNode::Array(vec![DictNode::Int(1), DictNode::Int(4), DictNode::Int(1)])), ("Size", DictNode::Int(i64::MAX))]),
+        dict(&[
+            ("W", DictNode::Array(vec![DictNode::Int(1), DictNode::Int(4), DictNode::Int(1)])),
+            ("Index", DictNode::Array(vec![DictNode::Int(i64::MAX), DictNode::Int(i64::MAX), DictNode::Int(3)])),
+        ]),
+        dict(&[
+            ("W", DictNode::Array(vec![DictNode::Int(1), DictNode::Int(4), DictNode::Int(1)])),
+            ("Index", DictNode::Array(vec![DictNode::Int(1 << 40), DictNode::Int(2)])),
+        ]),
+    ];
+    let data = [1u8, 0, 0, 0, 10, 0, 2, 0, 0, 0, 3, 0];
+    for stream in &streams {
+        let _ = xref::parse_stream(stream, &data);
+        let _ = xref::parse_stream(stream, &[]);
+    }
+
+    // huge object numbers are dropped rather than allocated for
+    let mut table = xref::XRefTable::new(Vec::new());
+    table.set(usize::MAX, xref::XRefEntry::free());
+    assert!(table.is_empty());
+}
+
+#[test]
+fn test_hostile_filters() {
+    let inputs: Vec<&[u8]> = vec![
+        b"",
+        b"\x00",
+        b"\xff",
+        b"x\x9c",
+        b"x\x9c\xff\xff\xff\xff",
+        b"zzzz~>",
+        b"!~>",
+        b"uuuuu~>",
+        b"<~",
+        b"\x80",
+        b"\x00",
+        b"\xfe",
+        b"\x7f\x00",
+        b"4g>",
+        &[0xff; 64],
+        &[0x80, 0x0b, 0x60, 0x50, 0x22, 0x0c, 0x0c, 0x85, 0x01],
+    ];
+    let filter_names = [
+        "FlateDecode", "ASCIIHexDecode", "ASCII85Decode", "RunLengthDecode", "LZWDecode", "DCTDecode", "Unknown",
+    ];
+    let parameters = [
+        None,
+        Some(dict(&[("Predictor", DictNode::Int(12)), ("Columns", DictNode::Int(i64::MAX))])),
+        Some(dict(&[("Predictor", DictNode::Int(15)), ("Colors", DictNode::Int(i64::MAX)), ("Columns", DictNode::Int(i64::MAX))])),
+        Some(dict(&[("Predictor", DictNode::Int(2)), ("Colors", DictNode::Int(1 << 40)), ("Columns", DictNode::Int(1 << 40))])),
+        Some(dict(&[("Predictor", DictNode::Int(2)), ("BitsPerComponent", DictNode::Int(1)), ("Columns", DictNode::Int(1 << 20))])),
+        Some(dict(&[("Predictor", DictNode::Int(2)), ("BitsPerComponent", DictNode::Int(16)), ("Colors", DictNode::Int(3))])),
+        Some(dict(&[("Predictor", DictNode::Int(10)), ("Colors", DictNode::Int(0))])),
+        Some(dict(&[("Predictor", DictNode::Int(-1)), ("BitsPerComponent", DictNode::Int(-8))])),
+        Some(dict(&[("EarlyChange", DictNode::Int(0))])),
+    ];
+    for input in &inputs {
+        for filter_name in &filter_names {
+            for params in &parameters {
+                let _ = filters::decode(filter_name, params.as_ref(), input);
+            }
+        }
+    }
+
+    // the same through stream dictionaries, with predictors applied to data
+    // that is actually compressed
+    let deflated = filters::decode("ASCIIHexDecode", None, b"789c6364606002000007000301>").unwrap_or_default();
+    for params in &parameters {
+        let stream = dict(&[
+            ("Filter", DictNode::Array(vec![DictNode::name("FlateDecode"), DictNode::name("ASCII85Decode"), DictNode::Int(3)])),
+            ("DecodeParms", match *params {
+                Some(ref params) => DictNode::Array(vec![params.clone(), DictNode::Null]),
+                None => DictNode::Null,
+            }),
+        ]);
+        let _ = streams::decode(&stream, &deflated);
+        let flate = dict(&[("Filter", DictNode::name("FlateDecode")), ("DecodeParms", params.clone().unwrap_or(DictNode::Null))]);
+        let _ = streams::decode(&flate, &deflated);
+        if let Ok(mut reader) = streams::decoder(&flate, &deflated[..]) {
+            let _ = reader.read_to_end(&mut Vec::new());
+        }
+    }
+    let image = dict(&[("Filter", DictNode::name("DCTDecode")), ("Width", DictNode::Int(-1)), ("Height", DictNode::Float(1e300))]);
+    let _ = filters::encoded_image(&image, b"");
+}
+
+#[test]
+fn test_hostile_object_streams() {
+    let headers = [
+        (DictNode::Int(1), DictNode::Int(1)),
+        (DictNode::Int(i64::MAX), DictNode::Int(0)),
+        (DictNode::Int(1), DictNode::Int(i64::MAX)),
+        (DictNode::Int(-1), DictNode::Int(-1)),
+        (DictNode::Int(2), DictNode::Int(8)),
+        (DictNode::Int(1), DictNode::Float(3.0)),
+    ];
+    let inputs: Vec<&[u8]> = vec![b"", b"1 0", b"1 99999999999999999999 ", b"1 18446744073709551615 null", b"1 0 2 0 (a)(b)", b"x y"];
+    for (count, first) in &headers {
+        for input in &inputs {
+            let stream = dict(&[("N", count.clone()), ("First", first.clone())]);
+            if let Ok(stream) = ObjectStream::new(&stream, input.to_vec()) {
+                for index in 0..stream.len() + 1 {
+                    let _ = stream.id(index);
+                    let _ = stream.source(index);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_hostile_content() {
+    let deep_array = "[".repeat(MAX_DEPTH * 40);
+    let deep_dict = "<<".repeat(MAX_DEPTH * 40);
+    let inputs: Vec<&[u8]> = vec![
+        b"",
+        b"(",
+        b"(\\",
+        b"<",
+        b"<<",
+        b"<< 1 2 >>",
+        b"<</A>",
+        b"<zz>",
+        b"[",
+        b"]",
+        b"}{",
+        b"/",
+        b"%",
+        b"99999999999999999999 -.. +- 1e5 Td",
+        b"BI /W 1 /H 1 ID \x00\xff EI",
+        b"\xff\xfe\x00 Tj",
+        deep_array.as_bytes(),
+        deep_dict.as_bytes(),
+    ];
+    for input in inputs {
+        let operations: Vec<_> = Operations::new(input).collect();
+        // errors end the stream, it doesn't keep spinning
+        assert!(operations.iter().filter(|operation| operation.is_err()).count() <= 1);
+    }
+    assert!(Operations::new(deep_array.as_bytes()).any(|operation| operation.is_err()));
+}
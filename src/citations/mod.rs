@@ -0,0 +1,218 @@
+use document::Document;
+use error::Result;
+use pages;
+use text;
+
+/*
+ * The reference list of an academic paper, one entry per citation.
+ *
+ * The list starts after the last heading that names it ("References",
+ * "Bibliography", "Works Cited"... optionally numbered, so a table of
+ * contents mentioning it earlier doesn't count) and runs to an appendix
+ * heading or the end of the document. Lines that are only a number are
+ * page numbers and skipped.
+ *
+ * How entries are told apart depends on how the first one starts:
+ *
+ *   - "[12] ..." or "[Knu84] ...": every line starting with a bracketed
+ *     label starts an entry
+ *   - "12. ..." or "12 ...": a line starting with the next number does,
+ *     which keeps "2004. Proceedings of..." from passing for entry 2004
+ *   - anything else is author-year style: a line that starts like
+ *     "Surname, X." after one ending with a period does
+ *
+ * Entries broken over lines are joined with spaces, and words hyphenated
+ * at the end of a line put back together. Each entry has the page it
+ * starts on and its byte offset in that page's extracted text, as
+ * `Document::extract_text` has it.
+ */
+
+const HEADINGS: &[&str] = &["references", "bibliography", "works cited", "literature cited", "reference list", "cited literature"];
+const END_HEADINGS: &[&str] = &["appendix", "appendices", "supplementary material", "supplemental material"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Citation {
+    // "12" or "Knu84", without the brackets or the period
+    pub label: Option<String>,
+    pub text: String,
+    // index of the page it starts on, from 0
+    pub page: usize,
+    pub offset: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Style {
+    Bracketed,
+    Numbered,
+    AuthorYear,
+}
+
+pub fn citations(doc: &mut Document) -> Result<Vec<Citation>> {
+    let mut texts = Vec::new();
+    for page in pages::pages(doc)? {
+        texts.push(text::extract(doc, &page)?);
+    }
+    Ok(find(&texts))
+}
+
+// Citations in the text of each page.
+fn find(pages: &[String]) -> Vec<Citation> {
+    // (page, offset, line) for every line
+    let mut lines = Vec::new();
+    for (page, text) in pages.iter().enumerate() {
+        let mut offset = 0;
+        for line in text.split('\n') {
+            lines.push((page, offset, line));
+            offset += line.len() + 1;
+        }
+    }
+    let start = match lines.iter().rposition(|&(_, _, line)| HEADINGS.contains(&heading(line).as_str())) {
+        Some(start) => start + 1,
+        None => return Vec::new(),
+    };
+    let lines: Vec<(usize, usize, &str)> = lines[start..].iter()
+        .take_while(|&&(_, _, line)| !END_HEADINGS.iter().any(|end| heading(line).starts_with(end)))
+        .map(|&(page, offset, line)| {
+            // keep the offset pointing at the first character kept
+            let trimmed = line.trim_start();
+            (page, offset + line.len() - trimmed.len(), trimmed.trim_end())
+        })
+        .filter(|&(_, _, line)| !line.is_empty() && !line.chars().all(|c| c.is_ascii_digit()))
+        .collect();
+    let style = match lines.first() {
+        Some(&(_, _, line)) if bracketed_label(line).is_some() => Style::Bracketed,
+        Some(&(_, _, line)) if numbered_label(line) == Some(1) => Style::Numbered,
+        Some(_) => Style::AuthorYear,
+        None => return Vec::new(),
+    };
+
+    let mut citations: Vec<Citation> = Vec::new();
+    let mut previous = "";
+    for (page, offset, line) in lines {
+        let starts = match style {
+            Style::Bracketed => bracketed_label(line).is_some(),
+            Style::Numbered => numbered_label(line) == Some(citations.len() as u64 + 1),
+            Style::AuthorYear => citations.is_empty() || (previous.ends_with('.') && starts_with_author(line)),
+        };
+        previous = line;
+        if starts {
+            let (label, rest) = match style {
+                Style::Bracketed => bracketed_label(line).unwrap_or((None, line)),
+                Style::Numbered => (Some((citations.len() + 1).to_string()), after_number(line)),
+                Style::AuthorYear => (None, line),
+            };
+            citations.push(Citation { label, text: rest.to_string(), page, offset });
+            continue;
+        }
+        if let Some(citation) = citations.last_mut() {
+            join(&mut citation.text, line);
+        }
+    }
+    citations
+}
+
+// The line as a heading: in lower case, without a section number in front
+// ("7 References", "VII. REFERENCES") or a colon after.
+fn heading(line: &str) -> String {
+    let line = line.trim().trim_end_matches(':').to_lowercase();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.first() {
+        Some(first) if words.len() > 1 && is_section_number(first) => words[1..].join(" "),
+        _ => words.join(" "),
+    }
+}
+
+fn is_section_number(word: &str) -> bool {
+    let word = word.trim_end_matches('.');
+    !word.is_empty() && (word.chars().all(|c| c.is_ascii_digit() || c == '.') || word.chars().all(|c| "ivxlc".contains(c)))
+}
+
+// "[12] Rest" as (Some("12"), "Rest").
+fn bracketed_label(line: &str) -> Option<(Option<String>, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let end = rest.find(']')?;
+    let label = &rest[..end];
+    if label.is_empty() || label.len() > 20 || label.contains(' ') {
+        return None;
+    }
+    Some((Some(label.to_string()), rest[end + 1..].trim_start()))
+}
+
+// The number "12. Rest", "12) Rest" or "12 Rest" starts with.
+fn numbered_label(line: &str) -> Option<u64> {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits == 0 || digits > 4 {
+        return None;
+    }
+    match line[digits..].chars().next() {
+        Some('.') | Some(')') | Some(' ') => line[..digits].parse().ok(),
+        _ => None,
+    }
+}
+
+fn after_number(line: &str) -> &str {
+    line.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches(['.', ')']).trim_start()
+}
+
+// "Knuth, D. E.", "van Dijk, J." or "O'Brien, Pat": a capitalized surname
+// of up to three words, a comma, and a capitalized name or initial.
+fn starts_with_author(line: &str) -> bool {
+    let (surname, rest) = match line.split_once(',') {
+        Some(split) => split,
+        None => return false,
+    };
+    let words: Vec<&str> = surname.split_whitespace().collect();
+    words.len() <= 3
+        && words.last().is_some_and(|word| word.starts_with(char::is_uppercase))
+        && surname.chars().all(|c| c.is_alphabetic() || c == ' ' || c == '-' || c == '\'' || c == '\u{2019}')
+        && rest.trim_start().starts_with(char::is_uppercase)
+}
+
+// Adds the next line of an entry. A word hyphenated across the lines is put
+// back together, a hyphen before a capital ("Addison-Wesley") stays.
+fn join(text: &mut String, line: &str) {
+    if text.ends_with('-') {
+        let hyphenated = text.chars().rev().nth(1).is_some_and(char::is_lowercase) && line.starts_with(char::is_lowercase);
+        if hyphenated {
+            text.pop();
+        }
+    } else {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+#[test]
+fn test_numbered() {
+    let pages = vec![
+        "Contents\n1 Introduction\n7 References\n".to_string(),
+        "Body text.\n\n7 References\n1. D. E. Knuth. The Art of Computer Pro-\ngramming. Addison-\nWesley,\n1968.\n2. A. Turing. On computable\nnumbers. 1936.\n12\n".to_string(),
+        "3. C. Shannon. A mathematical theory.\n2004. Not an entry of its own.\nAppendix A\n1. Not a citation.\n".to_string(),
+    ];
+    let citations = find(&pages);
+    assert_eq!(citations.len(), 3);
+    assert_eq!(citations[0].label.as_deref(), Some("1"));
+    assert_eq!(citations[0].text, "D. E. Knuth. The Art of Computer Programming. Addison-Wesley, 1968.");
+    assert_eq!((citations[0].page, citations[0].offset), (1, 25));
+    assert_eq!(citations[1].text, "A. Turing. On computable numbers. 1936.");
+    assert_eq!(citations[2].text, "C. Shannon. A mathematical theory. 2004. Not an entry of its own.");
+    assert_eq!((citations[2].page, citations[2].offset), (2, 0));
+}
+
+#[test]
+fn test_bracketed_and_author_year() {
+    let pages = vec!["REFERENCES\n[Knu84] D. Knuth. Literate programming.\n  The Computer Journal.\n[2] A. Turing.\n".to_string()];
+    let citations = find(&pages);
+    assert_eq!(citations.len(), 2);
+    assert_eq!(citations[0].label.as_deref(), Some("Knu84"));
+    assert_eq!(citations[0].text, "D. Knuth. Literate programming. The Computer Journal.");
+    assert_eq!(citations[1].label.as_deref(), Some("2"));
+
+    let pages = vec!["Bibliography\nKnuth, D. (1984). Literate programming.\nThe Computer Journal, 27.\nvan Dijk, J., and Smith, A. (2001).\nA study.\n".to_string()];
+    let citations = find(&pages);
+    assert_eq!(citations.len(), 2);
+    assert_eq!(citations[0].text, "Knuth, D. (1984). Literate programming. The Computer Journal, 27.");
+    assert_eq!(citations[1].text, "van Dijk, J., and Smith, A. (2001). A study.");
+
+    assert!(find(&["No reference list here.\n".to_string()]).is_empty());
+}
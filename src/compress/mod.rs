@@ -0,0 +1,205 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use document::Document;
+use document::edit::EditedObject;
+use error::{PdfError, Result};
+use filters;
+use parsers::cos::DictNode;
+use pipeline;
+
+/*
+ * Recompressing streams: data that went in uncompressed or only ASCII
+ * encoded is deflated, and filter chains that undo each other's work
+ * (ASCII85 on top of Flate, Flate twice, LZW) come down to one FlateDecode.
+ *
+ *   let report = compress::compress(&mut doc, pipeline::default_workers())?;
+ *   writer::save(&mut doc, output, &SaveOptions::default())?;
+ *   println!("{}", report);
+ *
+ * Only the general purpose filters at the front of a stream's chain are
+ * undone: Flate, LZW, RunLength, ASCIIHex and ASCII85 lose nothing. The
+ * image codecs that can come after them (DCT, JPX, JBIG2, CCITT) stay,
+ * they do better than Flate on what they encode, so an ASCII85 encoded
+ * JPEG loses its ASCII85 and nothing else. A stream is only replaced when
+ * that makes it smaller; predictors are dropped with the filters they
+ * belong to, and an image deflated with one is usually better off left
+ * alone.
+ *
+ * Streams are read on one thread and decoded and deflated on a pool of
+ * them, see the pipeline module.
+ *
+ * Streams with their data in another file, and xref and object streams,
+ * which writing a file out whole does away with, are left as they are.
+ * So is a stream whose data doesn't decode. `SaveOptions::compress` does
+ * the same as the objects are written, without touching the document.
+ */
+
+// Filters that decode to exactly what was encoded, whatever the data.
+const GENERAL_FILTERS: &[&str] = &[
+    "FlateDecode", "Fl", "LZWDecode", "LZW", "RunLengthDecode", "RL", "ASCIIHexDecode", "AHx", "ASCII85Decode", "A85",
+];
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct CompressReport {
+    pub streams: usize,
+    pub recompressed: usize,
+    // the size of every stream's data, before and after
+    pub before: usize,
+    pub after: usize,
+}
+
+// "recompressed 12 of 30 streams, 81234 bytes to 40112"
+impl fmt::Display for CompressReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "recompressed {} of {} streams, {} bytes to {}", self.recompressed, self.streams, self.before, self.after)
+    }
+}
+
+// Recompresses every stream of `doc` worth it, as edits to the document,
+// on `workers` threads.
+pub fn compress(doc: &mut Document, workers: usize) -> Result<CompressReport> {
+    if doc.is_encrypted() {
+        return Err(PdfError::Encryption("encrypted documents can't be recompressed".to_string()));
+    }
+    let edits: HashMap<u64, Option<EditedObject>> = doc.edits().into_iter()
+        .map(|(id, edited)| (id, edited.cloned()))
+        .collect();
+    let mut ids: BTreeSet<u64> = doc.xref.entries()
+        .filter(|&(_, entry)| !entry.is_free)
+        .map(|(id, _)| id as u64)
+        .collect();
+    ids.extend(edits.keys());
+
+    let streams = {
+        let doc = &mut *doc;
+        ids.into_iter().filter_map(move |id| {
+            let read = match edits.get(&id) {
+                Some(Some(edited)) => Ok((edited.object.clone(), edited.data.clone())),
+                Some(None) => return None,
+                None => match doc.xref.get(id as usize).filter(|entry| !entry.is_free).cloned() {
+                    Some(entry) => doc.raw_object(&entry),
+                    None => return None,
+                },
+            };
+            match read {
+                Ok((object, Some(data))) => Some(Ok((id, object, data))),
+                Ok((_, None)) => None,
+                Err(err) => Some(Err(err)),
+            }
+        })
+    };
+    let recompress_stream = |(id, object, data): (u64, DictNode, Vec<u8>)| (id, data.len(), recompress(&object, &data));
+
+    let mut report = CompressReport::default();
+    let mut recompressed = Vec::new();
+    pipeline::map_parallel(streams, workers, recompress_stream, |(id, before, stream)| {
+        report.streams += 1;
+        report.before += before;
+        match stream {
+            Some((dict, data)) => {
+                report.recompressed += 1;
+                report.after += data.len();
+                recompressed.push((id, dict, data));
+            },
+            None => report.after += before,
+        }
+        Ok(())
+    })?;
+    for (id, dict, data) in recompressed {
+        doc.set_stream(id, dict, data);
+    }
+    Ok(report)
+}
+
+// The stream with dictionary `dict` and encoded `data` recompressed, or
+// None if that wouldn't make it any smaller. The dictionary's /Length is
+// left for the caller to set.
+pub fn recompress(dict: &DictNode, data: &[u8]) -> Option<(HashMap<String, DictNode>, Vec<u8>)> {
+    let map = dict.as_dict()?;
+    let skipped = map.contains_key("F") || map.get("Type").is_some_and(|kind| kind.is_name("XRef") || kind.is_name("ObjStm"));
+    let chain = filters::chain(dict);
+    // a /Filter that's neither a name nor an array of them
+    if skipped || (chain.is_empty() && map.contains_key("Filter")) {
+        return None;
+    }
+    let general = chain.iter().take_while(|(name, _)| GENERAL_FILTERS.contains(&name.as_str())).count();
+    let kept = &chain[general..];
+    if general == 0 && !kept.is_empty() {
+        return None;
+    }
+
+    let mut decoded = data.to_vec();
+    for (name, params) in &chain[..general] {
+        decoded = filters::decode(name, params.as_ref(), &decoded).ok()?;
+    }
+    let (encoded, chain) = match kept {
+        [] => (filters::flate_encode(&decoded), vec![("FlateDecode".to_string(), None)]),
+        kept => (decoded, kept.to_vec()),
+    };
+    if encoded.len() >= data.len() {
+        return None;
+    }
+
+    let mut map = map.clone();
+    map.remove("Filter");
+    map.remove("DecodeParms");
+    let names: Vec<DictNode> = chain.iter().map(|(name, _)| DictNode::name(name)).collect();
+    let params: Vec<DictNode> = chain.iter().map(|(_, params)| params.clone().unwrap_or(DictNode::Null)).collect();
+    match (names.as_slice(), params.as_slice()) {
+        ([name], [params]) => {
+            map.insert("Filter".to_string(), name.clone());
+            if *params != DictNode::Null {
+                map.insert("DecodeParms".to_string(), params.clone());
+            }
+        },
+        _ => {
+            map.insert("Filter".to_string(), DictNode::Array(names));
+            if params.iter().any(|params| *params != DictNode::Null) {
+                map.insert("DecodeParms".to_string(), DictNode::Array(params));
+            }
+        },
+    }
+    Some((map, encoded))
+}
+
+#[test]
+fn test_recompress() {
+    let content = b"0 0 m 100 100 l S\n".repeat(50);
+    let stream = |filter: Option<DictNode>| {
+        let mut dict = HashMap::new();
+        if let Some(filter) = filter {
+            dict.insert("Filter".to_string(), filter);
+        }
+        DictNode::Dict(dict)
+    };
+
+    // uncompressed
+    let (dict, data) = recompress(&stream(None), &content).unwrap();
+    assert_eq!(dict.get("Filter"), Some(&DictNode::name("FlateDecode")));
+    assert_eq!(filters::flate_decode(&data).unwrap(), content);
+
+    // hex encoded flate comes down to flate
+    let flate = filters::flate_encode(&content);
+    let hex: Vec<u8> = flate.iter().flat_map(|byte| format!("{:02x}", byte).into_bytes()).chain(b">".iter().cloned()).collect();
+    let (dict, data) = recompress(&stream(Some(DictNode::Array(vec![DictNode::name("AHx"), DictNode::name("Fl")]))), &hex).unwrap();
+    assert_eq!(dict.get("Filter"), Some(&DictNode::name("FlateDecode")));
+    assert_eq!(filters::flate_decode(&data).unwrap(), content);
+
+    // already as small as it gets
+    assert_eq!(recompress(&stream(Some(DictNode::name("FlateDecode"))), &flate), None);
+
+    // a hex encoded JPEG keeps its DCTDecode and parameters
+    let params = DictNode::Dict(HashMap::from([("ColorTransform".to_string(), DictNode::Int(0))]));
+    let mut jpeg = HashMap::new();
+    jpeg.insert("Filter".to_string(), DictNode::Array(vec![DictNode::name("ASCIIHexDecode"), DictNode::name("DCTDecode")]));
+    jpeg.insert("DecodeParms".to_string(), DictNode::Array(vec![DictNode::Null, params.clone()]));
+    let (dict, data) = recompress(&DictNode::Dict(jpeg), b"FFD8FFE0>").unwrap();
+    assert_eq!(dict.get("Filter"), Some(&DictNode::name("DCTDecode")));
+    assert_eq!(dict.get("DecodeParms"), Some(&params));
+    assert_eq!(data, vec![0xFF, 0xD8, 0xFF, 0xE0]);
+
+    // nothing to undo in front of an image codec, and broken data
+    assert_eq!(recompress(&stream(Some(DictNode::name("DCTDecode"))), b"\xff\xd8"), None);
+    assert_eq!(recompress(&stream(Some(DictNode::name("FlateDecode"))), &content), None);
+}
@@ -0,0 +1,291 @@
+/*
+ * The ciphers PDF encrypts strings and streams with.
+ *
+ *   let plain = cipher::rc4(&key, &encrypted);
+ *   let plain = cipher::aes_decrypt(&key, &encrypted);
+ *   let encrypted = cipher::aes_encrypt(&key, &iv, &plain);
+ *
+ * RC4 is symmetric, the same call encrypts and decrypts. AES (128 or 256
+ * bit keys) is used in CBC mode: PDF puts the 16 byte initialization
+ * vector in front of the data, and pads the data to whole blocks the
+ * PKCS #7 way. Key derivation for AES-256 needs it without either, that's
+ * what `Aes::cbc_encrypt` and `Aes::cbc_decrypt` are for.
+ */
+
+use error::{PdfError, Result};
+
+const BLOCK: usize = 16;
+
+pub fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        return data.to_vec();
+    }
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|&byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+pub struct Aes {
+    // one per round, and one for before the first
+    round_keys: Vec<[u8; BLOCK]>,
+    sbox: [u8; 256],
+    inverse_sbox: [u8; 256],
+}
+
+impl Aes {
+    // The key must be 16, 24 or 32 bytes long.
+    pub fn new(key: &[u8]) -> Aes {
+        assert!(matches!(key.len(), 16 | 24 | 32), "AES keys are 16, 24 or 32 bytes long");
+        let (sbox, inverse_sbox) = sboxes();
+        let words = key.len() / 4;
+        let rounds = words + 6;
+
+        let mut schedule: Vec<[u8; 4]> = key.chunks(4).map(|word| [word[0], word[1], word[2], word[3]]).collect();
+        let mut constant = 1u8;
+        for i in words..4 * (rounds + 1) {
+            let mut word = schedule[i - 1];
+            if i % words == 0 {
+                word.rotate_left(1);
+                word = word.map(|byte| sbox[byte as usize]);
+                word[0] ^= constant;
+                constant = double(constant);
+            } else if words > 6 && i % words == 4 {
+                word = word.map(|byte| sbox[byte as usize]);
+            }
+            let previous = schedule[i - words];
+            schedule.push([word[0] ^ previous[0], word[1] ^ previous[1], word[2] ^ previous[2], word[3] ^ previous[3]]);
+        }
+        let round_keys = schedule.chunks(4)
+            .map(|words| {
+                let mut key = [0; BLOCK];
+                for (bytes, word) in key.chunks_mut(4).zip(words) {
+                    bytes.copy_from_slice(word);
+                }
+                key
+            })
+            .collect();
+        Aes { round_keys, sbox, inverse_sbox }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; BLOCK]) {
+        let last = self.round_keys.len() - 1;
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..=last {
+            for byte in block.iter_mut() {
+                *byte = self.sbox[*byte as usize];
+            }
+            let old = *block;
+            for (index, byte) in block.iter_mut().enumerate() {
+                let (row, column) = (index % 4, index / 4);
+                *byte = old[row + 4 * ((column + row) % 4)];
+            }
+            if round != last {
+                mix_columns(block, [2, 3, 1, 1]);
+            }
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; BLOCK]) {
+        let last = self.round_keys.len() - 1;
+        for round in (1..=last).rev() {
+            add_round_key(block, &self.round_keys[round]);
+            if round != last {
+                mix_columns(block, [14, 11, 13, 9]);
+            }
+            let old = *block;
+            for (index, byte) in old.iter().enumerate() {
+                let (row, column) = (index % 4, index / 4);
+                block[row + 4 * ((column + row) % 4)] = *byte;
+            }
+            for byte in block.iter_mut() {
+                *byte = self.inverse_sbox[*byte as usize];
+            }
+        }
+        add_round_key(block, &self.round_keys[0]);
+    }
+
+    // `data` encrypted in CBC mode, without padding: whatever's left past
+    // the last whole block is dropped.
+    pub fn cbc_encrypt(&self, iv: &[u8; BLOCK], data: &[u8]) -> Vec<u8> {
+        let mut previous = *iv;
+        let mut encrypted = Vec::with_capacity(data.len());
+        for chunk in data.chunks_exact(BLOCK) {
+            for (byte, plain) in previous.iter_mut().zip(chunk) {
+                *byte ^= plain;
+            }
+            self.encrypt_block(&mut previous);
+            encrypted.extend_from_slice(&previous);
+        }
+        encrypted
+    }
+
+    // `data` decrypted in CBC mode, leaving any padding in.
+    pub fn cbc_decrypt(&self, iv: &[u8; BLOCK], data: &[u8]) -> Vec<u8> {
+        let mut previous = *iv;
+        let mut decrypted = Vec::with_capacity(data.len());
+        for chunk in data.chunks_exact(BLOCK) {
+            let mut block = [0; BLOCK];
+            block.copy_from_slice(chunk);
+            self.decrypt_block(&mut block);
+            for (byte, before) in block.iter_mut().zip(&previous) {
+                *byte ^= before;
+            }
+            decrypted.extend_from_slice(&block);
+            previous.copy_from_slice(chunk);
+        }
+        decrypted
+    }
+}
+
+// A string or stream the way PDF encrypts them with AES: initialization
+// vector first, then the data padded to whole blocks. Padding that doesn't
+// make sense is left in, and a truncated last block dropped, since broken
+// writers make both. Keys that aren't 16, 24 or 32 bytes long are an
+// error.
+pub fn aes_decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    if !matches!(key.len(), 16 | 24 | 32) {
+        return Err(PdfError::Encryption(format!("{} byte AES keys aren't possible", key.len())));
+    }
+    if data.len() < BLOCK {
+        return Ok(Vec::new());
+    }
+    let mut iv = [0; BLOCK];
+    iv.copy_from_slice(&data[..BLOCK]);
+    let mut decrypted = Aes::new(key).cbc_decrypt(&iv, &data[BLOCK..]);
+    let padding = decrypted.last().cloned().unwrap_or(0) as usize;
+    if (1..=BLOCK).contains(&padding) && padding <= decrypted.len()
+        && decrypted[decrypted.len() - padding..].iter().all(|&byte| byte as usize == padding) {
+        decrypted.truncate(decrypted.len() - padding);
+    }
+    Ok(decrypted)
+}
+
+// The other way round: `iv`, then `data` padded and encrypted. Every
+// string and stream needs an initialization vector of its own.
+pub fn aes_encrypt(key: &[u8], iv: &[u8; BLOCK], data: &[u8]) -> Vec<u8> {
+    let padding = BLOCK - data.len() % BLOCK;
+    let mut padded = data.to_vec();
+    padded.resize(data.len() + padding, padding as u8);
+    let mut encrypted = iv.to_vec();
+    encrypted.extend(Aes::new(key).cbc_encrypt(iv, &padded));
+    encrypted
+}
+
+// The S-box and its inverse, worked out rather than written out: each byte
+// goes to its multiplicative inverse in GF(2^8), then through an affine
+// transformation.
+fn sboxes() -> ([u8; 256], [u8; 256]) {
+    let mut sbox = [0x63; 256];
+    let (mut p, mut q) = (1u8, 1u8);
+    loop {
+        // p walks through every nonzero byte multiplying by 3, q divides by 3,
+        // so q is always p's inverse
+        p = p ^ double(p);
+        q ^= q << 1;
+        q ^= q << 2;
+        q ^= q << 4;
+        if q & 0x80 != 0 {
+            q ^= 0x09;
+        }
+        sbox[p as usize] = q ^ q.rotate_left(1) ^ q.rotate_left(2) ^ q.rotate_left(3) ^ q.rotate_left(4) ^ 0x63;
+        if p == 1 {
+            break;
+        }
+    }
+    let mut inverse = [0; 256];
+    for (byte, &substituted) in sbox.iter().enumerate() {
+        inverse[substituted as usize] = byte as u8;
+    }
+    (sbox, inverse)
+}
+
+// Multiplication by 2 in GF(2^8).
+fn double(byte: u8) -> u8 {
+    (byte << 1) ^ if byte & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = double(a);
+        b >>= 1;
+    }
+    product
+}
+
+// Each column multiplied by the polynomial with `factors` as coefficients:
+// 2 3 1 1 when encrypting, 14 11 13 9 to undo it.
+fn mix_columns(block: &mut [u8; BLOCK], factors: [u8; 4]) {
+    for column in block.chunks_mut(4) {
+        let old = [column[0], column[1], column[2], column[3]];
+        for (row, byte) in column.iter_mut().enumerate() {
+            *byte = (0..4).fold(0, |sum, index| sum ^ multiply(old[(row + index) % 4], factors[index]));
+        }
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK], key: &[u8; BLOCK]) {
+    for (byte, key) in block.iter_mut().zip(key) {
+        *byte ^= key;
+    }
+}
+
+#[cfg(test)]
+fn bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn test_rc4() {
+    assert_eq!(rc4(b"Key", b"Plaintext"), vec![0xbb, 0xf3, 0x16, 0xe8, 0xd9, 0x40, 0xaf, 0x0a, 0xd3]);
+    assert_eq!(rc4(b"Wiki", b"pedia"), vec![0x10, 0x21, 0xbf, 0x04, 0x20]);
+    assert_eq!(rc4(b"Key", &rc4(b"Key", b"round trip")), b"round trip");
+}
+
+#[test]
+fn test_aes() {
+    // FIPS 197's examples
+    let plain: [u8; 16] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+    for (key, encrypted) in [(bytes("000102030405060708090a0b0c0d0e0f"), bytes("69c4e0d86a7b0430d8cdb78070b4c55a")),
+                                 (bytes("000102030405060708090a0b0c0d0e0f1011121314151617"), bytes("dda97ca4864cdfe06eaf70a0ec0d7191")),
+                                 (bytes("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"), bytes("8ea2b7ca516745bfeafc49904b496089"))] {
+        let aes = Aes::new(&key);
+        let mut block = plain;
+        aes.encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), encrypted);
+        aes.decrypt_block(&mut block);
+        assert_eq!(block, plain);
+    }
+
+    let key = bytes("000102030405060708090a0b0c0d0e0f");
+    let aes = Aes::new(&key);
+    let iv = [7; 16];
+    let message = b"thirty-two bytes of message data";
+    assert_eq!(aes.cbc_decrypt(&iv, &aes.cbc_encrypt(&iv, message)), message);
+    // padded the PKCS #7 way, after the initialization vector
+    let mut padded = message[..20].to_vec();
+    padded.extend_from_slice(&[12; 12]);
+    let mut encrypted = iv.to_vec();
+    encrypted.extend(aes.cbc_encrypt(&iv, &padded));
+    assert_eq!(aes_decrypt(&key, &encrypted).unwrap(), &message[..20]);
+    assert_eq!(aes_encrypt(&key, &iv, &message[..20]), encrypted);
+    assert_eq!(aes_decrypt(&key, &aes_encrypt(&key, &iv, message)).unwrap(), message);
+    assert_eq!(aes_decrypt(&key, &encrypted[..10]).unwrap(), b"");
+    assert!(aes_decrypt(&key[..10], &encrypted).is_err());
+}
@@ -0,0 +1,755 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypt::cipher::{aes_decrypt, aes_encrypt, rc4, Aes};
+use crypt::digest::{md5, sha256, sha384, sha512};
+use error::{PdfError, Result};
+use filters;
+use parsers::cos::DictNode;
+
+pub mod cipher;
+pub mod digest;
+pub mod permissions;
+
+pub use crypt::permissions::{Password, Permissions};
+
+/*
+ * The standard security handler: the password based encryption the
+ * trailer's /Encrypt dictionary describes.
+ *
+ *   << /Filter /Standard /V 2 /R 3 /Length 128 /P -3904
+ *      /O <...32 bytes...> /U <...32 bytes...> >>
+ *
+ * Every string and stream in the file is encrypted under a key of its
+ * own, made from the file key and the object's number and generation. Up
+ * to revision 4 the file key comes from the user password, /O, /P and the
+ * first string of the trailer's /ID (PDF 32000 7.6.3):
+ *
+ *   - a password that makes the key /U was computed with is the user's
+ *   - the owner password decrypts /O into the user password, so it opens
+ *     the file too, and says so in `owner`
+ *
+ * Version 4 names its ciphers with crypt filters, one for streams (/StmF)
+ * and one for strings (/StrF), RC4 (/V2) or AES-128 (/AESV2):
+ *
+ *   /CF << /StdCF << /CFM /AESV2 /Length 16 >> >> /StmF /StdCF /StrF /StdCF
+ *
+ * and a stream can pick another one with a /Crypt filter of its own, the
+ * first in its /Filter. Version 5 (revision 5 and 6) is AES-256 (/AESV3):
+ * there's no per object key, and the file key is stored in /UE and /OE,
+ * encrypted with a hash of the password that /U and /O check it against.
+ * Revision 6's hash is the one from PDF 2.0, rounds of AES and SHA-2.
+ *
+ * Most encrypted files have an empty user password and only restrict what
+ * can be done with them (/P), those open with no password at all.
+ *
+ * Encrypting goes the other way, for files the writer writes (see its
+ * SaveOptions), always with AES-256 and revision 6:
+ *
+ *   let encryption = Encryption { user_password: b"user".to_vec(), ..Encryption::default() };
+ *   let (encrypt, handler) = encryption.security_handler();
+ *   let object = handler.encrypt_strings(id, 0, object);
+ *
+ * with a random file key, salts and initialization vectors.
+ *
+ * What isn't encrypted: the /Encrypt dictionary, xref streams, the objects
+ * inside object streams (the object stream is, as a whole), and metadata
+ * streams when /EncryptMetadata is false. Handlers other than the standard
+ * one aren't supported, and revision 6 passwords aren't normalized with
+ * SASLprep: ASCII ones work.
+ */
+
+// What passwords are padded (or made up) with, to 32 bytes.
+const PADDING: [u8; 32] = [
+    0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+    0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe, 0x64, 0x53, 0x69, 0x7a,
+];
+
+// Revision 5 and 6 passwords are cut to this many bytes.
+const MAX_PASSWORD: usize = 127;
+
+// What a crypt filter encrypts with.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Method {
+    Identity,
+    Rc4,
+    Aes,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SecurityHandler {
+    key: Vec<u8>,
+    revision: i64,
+    // the /Encrypt dictionary's object, whose strings aren't encrypted
+    encrypt_id: Option<u64>,
+    // the crypt filters by name, and the ones streams and strings use
+    filters: HashMap<String, Method>,
+    streams: Method,
+    strings: Method,
+    encrypt_metadata: bool,
+    // /P, what the user password allows, as bits
+    pub permissions: i64,
+    // whether the password given was the owner password
+    pub owner: bool,
+}
+
+// What key derivation needs out of the /Encrypt dictionary and trailer.
+#[derive(Debug, PartialEq, Clone)]
+struct Parameters {
+    revision: i64,
+    // of the file key, in bytes
+    length: usize,
+    owner: Vec<u8>,
+    user: Vec<u8>,
+    permissions: i64,
+    id: Vec<u8>,
+    encrypt_metadata: bool,
+    // /OE and /UE, the file key encrypted, from revision 5 on
+    owner_key: Vec<u8>,
+    user_key: Vec<u8>,
+}
+
+// How to encrypt a document that's written out: the passwords and what
+// the user password allows. An empty owner password is the user's.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Encryption {
+    pub user_password: Vec<u8>,
+    pub owner_password: Vec<u8>,
+    pub permissions: Permissions,
+}
+
+impl Default for Encryption {
+    fn default() -> Encryption {
+        Encryption { user_password: Vec::new(), owner_password: Vec::new(), permissions: Permissions::all() }
+    }
+}
+
+impl Encryption {
+    // A revision 6 /Encrypt dictionary for a new, random file key, and the
+    // handler that encrypts with it.
+    pub fn security_handler(&self) -> (DictNode, SecurityHandler) {
+        let cut = |password: &[u8]| password[..password.len().min(MAX_PASSWORD)].to_vec();
+        let user = cut(&self.user_password);
+        let owner = if self.owner_password.is_empty() { user.clone() } else { cut(&self.owner_password) };
+        let permissions = self.permissions.to_bits();
+        let key = random_bytes(32);
+        let wrap = |hashed: Vec<u8>| Aes::new(&hashed).cbc_encrypt(&[0; 16], &key);
+
+        // a validation salt and a key salt each, see unlock_aes256
+        let salts = random_bytes(32);
+        let mut user_entry = hash(6, &user, &salts[..8], &[]);
+        user_entry.extend_from_slice(&salts[..16]);
+        let user_key = wrap(hash(6, &user, &salts[8..16], &[]));
+        let mut owner_entry = hash(6, &owner, &salts[16..24], &user_entry);
+        owner_entry.extend_from_slice(&salts[16..32]);
+        let owner_key = wrap(hash(6, &owner, &salts[24..32], &user_entry));
+        // /P again, under the file key, so changing /P alone gives away
+        // that it was tampered with; one block, where CBC is ECB
+        let mut perms = (permissions as u32).to_le_bytes().to_vec();
+        perms.extend_from_slice(&[0xff; 4]);
+        perms.extend_from_slice(b"Tadb");
+        perms.extend(random_bytes(4));
+        let perms = Aes::new(&key).cbc_encrypt(&[0; 16], &perms);
+        let entries = |entries: Vec<(&str, DictNode)>| DictNode::Dict(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect());
+        let filter = entries(vec![("CFM", DictNode::name("AESV3")), ("AuthEvent", DictNode::name("DocOpen")), ("Length", DictNode::Int(32))]);
+        let encrypt = entries(vec![
+            ("Filter", DictNode::name("Standard")),
+            ("V", DictNode::Int(5)),
+            ("R", DictNode::Int(6)),
+            ("Length", DictNode::Int(256)),
+            ("P", DictNode::Int(permissions)),
+            ("O", DictNode::HexString(owner_entry)),
+            ("U", DictNode::HexString(user_entry)),
+            ("OE", DictNode::HexString(owner_key)),
+            ("UE", DictNode::HexString(user_key)),
+            ("Perms", DictNode::HexString(perms)),
+            ("CF", entries(vec![("StdCF", filter)])),
+            ("StmF", DictNode::name("StdCF")),
+            ("StrF", DictNode::name("StdCF")),
+        ]);
+        let handler = SecurityHandler {
+            key,
+            revision: 6,
+            encrypt_id: None,
+            filters: HashMap::from([("StdCF".to_string(), Method::Aes), ("Identity".to_string(), Method::Identity)]),
+            streams: Method::Aes,
+            strings: Method::Aes,
+            encrypt_metadata: true,
+            permissions,
+            owner: true,
+        };
+        (encrypt, handler)
+    }
+}
+
+impl SecurityHandler {
+    // Opens the file with `password`, tried as the user password and then
+    // as the owner's. `encrypt` is the /Encrypt dictionary, object
+    // `encrypt_id` if it's not direct, and `id` the first /ID string.
+    pub fn new(encrypt: &DictNode, encrypt_id: Option<u64>, id: &[u8], password: &[u8]) -> Result<SecurityHandler> {
+        let filter = encrypt.get("Filter").and_then(DictNode::as_name).unwrap_or("");
+        if filter != "Standard" {
+            return Err(PdfError::Encryption(format!("the /{} security handler isn't supported", filter)));
+        }
+        let integer = |key| encrypt.get(key).and_then(DictNode::as_int);
+        let version = integer("V").unwrap_or(0);
+        let bits = match version {
+            1 => 40,
+            2 => integer("Length").unwrap_or(40),
+            4 => integer("Length").unwrap_or(128),
+            5 => 256,
+            version => return Err(PdfError::Encryption(format!("encryption version {} isn't supported", version))),
+        };
+        let revision = integer("R").unwrap_or(0);
+        if !(2..=6).contains(&revision) {
+            return Err(PdfError::Encryption(format!("revision {} of the standard security handler isn't supported", revision)));
+        }
+        if bits % 8 != 0 || !(40..=128).contains(&bits) && version != 5 {
+            return Err(PdfError::Encryption(format!("{} bit keys aren't possible", bits)));
+        }
+        let hash_length = if revision >= 5 { 48 } else { 32 };
+        let string = |key| encrypt.get(key).and_then(DictNode::as_string).map(<[u8]>::to_vec);
+        let (owner, user) = match (string("O"), string("U")) {
+            (Some(owner), Some(user)) if owner.len() >= hash_length && user.len() >= hash_length => (owner, user),
+            _ => return Err(PdfError::Syntax { what: "encryption dictionary", position: 0 }),
+        };
+        let encrypt_metadata = encrypt.get("EncryptMetadata").and_then(DictNode::as_bool) != Some(false);
+        let parameters = Parameters {
+            revision,
+            length: if revision == 2 { 5 } else { bits as usize / 8 },
+            owner,
+            user,
+            permissions: integer("P").unwrap_or(0),
+            id: id.to_vec(),
+            encrypt_metadata,
+            owner_key: string("OE").unwrap_or_default(),
+            user_key: string("UE").unwrap_or_default(),
+        };
+
+        let (filters, streams, strings) = if version >= 4 {
+            let filters = crypt_filters(encrypt)?;
+            let method = |key| {
+                let name = encrypt.get(key).and_then(DictNode::as_name).unwrap_or("Identity");
+                filters.get(name).cloned().ok_or_else(|| PdfError::Encryption(format!("there's no /{} crypt filter", name)))
+            };
+            let (streams, strings) = (method("StmF")?, method("StrF")?);
+            (filters, streams, strings)
+        } else {
+            (HashMap::new(), Method::Rc4, Method::Rc4)
+        };
+        // object keys are 5 bytes longer than the file key, up to 16, and AES
+        // only takes 16 (or the 32 byte file key of revisions 5 and 6)
+        let aes = streams == Method::Aes || strings == Method::Aes || filters.values().any(|&method| method == Method::Aes);
+        if aes && revision < 5 && parameters.length != 16 {
+            return Err(PdfError::Encryption(format!("AES needs 128 bit keys, not {} bit ones", parameters.length * 8)));
+        }
+        let handler = |key, owner| SecurityHandler {
+            key,
+            revision,
+            encrypt_id,
+            filters: filters.clone(),
+            streams,
+            strings,
+            encrypt_metadata,
+            permissions: parameters.permissions,
+            owner,
+        };
+        if revision >= 5 {
+            return match parameters.unlock_aes256(password) {
+                Some((key, owner)) => Ok(handler(key, owner)),
+                None => Err(PdfError::Encryption("wrong password".to_string())),
+            };
+        }
+        if let Some(key) = parameters.authenticate(password) {
+            return Ok(handler(key, false));
+        }
+        if let Some(key) = parameters.authenticate(&parameters.user_password(password)) {
+            return Ok(handler(key, true));
+        }
+        Err(PdfError::Encryption("wrong password".to_string()))
+    }
+
+    // What /P allows, and which password opened the file.
+    pub fn allowed(&self) -> Permissions {
+        let password = if self.owner { Password::Owner } else { Password::User };
+        Permissions { password: Some(password), ..Permissions::from_bits(self.permissions, self.revision) }
+    }
+
+    // The key strings and streams of object `id` at `generation` are
+    // encrypted with, using `method`.
+    fn object_key(&self, method: Method, id: u64, generation: u64) -> Vec<u8> {
+        if self.revision >= 5 {
+            return self.key.clone();
+        }
+        let mut input = self.key.clone();
+        input.extend_from_slice(&(id as u32).to_le_bytes()[..3]);
+        input.extend_from_slice(&(generation as u16).to_le_bytes());
+        if method == Method::Aes {
+            input.extend_from_slice(b"sAlT");
+        }
+        md5(&input)[..(self.key.len() + 5).min(16)].to_vec()
+    }
+
+    // Stream data of object `id` at `generation`, decrypted with the
+    // streams' crypt filter.
+    pub fn decrypt(&self, id: u64, generation: u64, data: &[u8]) -> Vec<u8> {
+        self.streams.decrypt(&self.object_key(self.streams, id, generation), data)
+    }
+
+    // The data of stream `id` at `generation`, with dictionary `dict`,
+    // decrypted if it's encrypted at all, with the crypt filter the stream
+    // asks for.
+    pub fn decrypt_stream(&self, id: u64, generation: u64, dict: &DictNode, data: &[u8]) -> Vec<u8> {
+        if !self.encrypts_stream(id, dict) {
+            return data.to_vec();
+        }
+        let method = self.stream_method(dict);
+        method.decrypt(&self.object_key(method, id, generation), data)
+    }
+
+    // Object `id` at `generation` with its strings decrypted, all the way
+    // down.
+    pub fn decrypt_strings(&self, id: u64, generation: u64, object: DictNode) -> DictNode {
+        if Some(id) == self.encrypt_id {
+            return object;
+        }
+        let key = self.object_key(self.strings, id, generation);
+        map_strings(object, &|value| self.strings.decrypt(&key, value))
+    }
+
+    // The other way round, for writing: stream data encrypted with the
+    // streams' crypt filter, if `encrypts_stream` says it should be.
+    pub fn encrypt(&self, id: u64, generation: u64, data: &[u8]) -> Vec<u8> {
+        self.streams.encrypt(&self.object_key(self.streams, id, generation), data)
+    }
+
+    // Object `id` at `generation` with its strings encrypted.
+    pub fn encrypt_strings(&self, id: u64, generation: u64, object: DictNode) -> DictNode {
+        if Some(id) == self.encrypt_id {
+            return object;
+        }
+        let key = self.object_key(self.strings, id, generation);
+        map_strings(object, &|value| self.strings.encrypt(&key, value))
+    }
+
+    // Whether the data of stream `id`, with dictionary `dict`, is
+    // encrypted.
+    pub fn encrypts_stream(&self, id: u64, dict: &DictNode) -> bool {
+        let is_type = |kind| dict.get("Type").is_some_and(|value| value.is_name(kind));
+        Some(id) != self.encrypt_id && !is_type("XRef") && (self.encrypt_metadata || !is_type("Metadata"))
+            && self.stream_method(dict) != Method::Identity
+    }
+
+    // The crypt filter's method for a stream: its own /Crypt filter's, the
+    // default's otherwise.
+    fn stream_method(&self, dict: &DictNode) -> Method {
+        match filters::chain(dict).into_iter().next() {
+            Some((ref filter, ref params)) if filter == "Crypt" => {
+                let name = params.as_ref().and_then(|params| params.get("Name")).and_then(DictNode::as_name).unwrap_or("Identity");
+                self.filters.get(name).cloned().unwrap_or(Method::Identity)
+            },
+            _ => self.streams,
+        }
+    }
+}
+
+impl Method {
+    fn decrypt(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Method::Identity => data.to_vec(),
+            Method::Rc4 => rc4(key, data),
+            // SecurityHandler::new turns down keys AES can't use
+            Method::Aes => aes_decrypt(key, data).unwrap_or_default(),
+        }
+    }
+
+    fn encrypt(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Method::Aes => {
+                let mut iv = [0; 16];
+                iv.copy_from_slice(&random_bytes(16));
+                aes_encrypt(key, &iv, data)
+            },
+            // RC4 is its own inverse
+            method => method.decrypt(key, data),
+        }
+    }
+}
+
+impl Parameters {
+    // The file key `password` gives, if it's the user password.
+    fn authenticate(&self, password: &[u8]) -> Option<Vec<u8>> {
+        let key = self.file_key(password);
+        // revision 3 only fills in the first 16 bytes of /U
+        let compared = if self.revision == 2 { 32 } else { 16 };
+        Some(key).filter(|key| self.user_entry(key)[..compared] == self.user[..compared])
+    }
+
+    // The file key, from what would be the user password.
+    fn file_key(&self, password: &[u8]) -> Vec<u8> {
+        let mut input = pad(password);
+        input.extend_from_slice(&self.owner[..32]);
+        input.extend_from_slice(&(self.permissions as u32).to_le_bytes());
+        input.extend_from_slice(&self.id);
+        if self.revision >= 4 && !self.encrypt_metadata {
+            input.extend_from_slice(&[0xff; 4]);
+        }
+        let mut hash = md5(&input);
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&hash[..self.length]);
+            }
+        }
+        hash[..self.length].to_vec()
+    }
+
+    // What /U is for file key `key`.
+    fn user_entry(&self, key: &[u8]) -> Vec<u8> {
+        if self.revision == 2 {
+            return rc4(key, &PADDING);
+        }
+        let mut input = PADDING.to_vec();
+        input.extend_from_slice(&self.id);
+        let mut entry = rc4(key, &md5(&input));
+        for round in 1..20 {
+            entry = rc4(&xor(key, round), &entry);
+        }
+        entry.resize(32, 0);
+        entry
+    }
+
+    // The RC4 key /O is encrypted with, from the owner password.
+    fn owner_key(&self, password: &[u8]) -> Vec<u8> {
+        let mut hash = md5(&pad(password));
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = md5(&hash);
+            }
+        }
+        hash[..self.length].to_vec()
+    }
+
+    // The (padded) user password that /O holds, if `password` is the owner
+    // password.
+    fn user_password(&self, password: &[u8]) -> Vec<u8> {
+        let key = self.owner_key(password);
+        let mut user = self.owner[..32].to_vec();
+        if self.revision == 2 {
+            return rc4(&key, &user);
+        }
+        for round in (0..20).rev() {
+            user = rc4(&xor(&key, round), &user);
+        }
+        user
+    }
+
+    // Revision 5 and 6: the file key and whether `password` is the owner
+    // password, if it's either. /U is a hash, a salt to check the password
+    // with and one to make the key that decrypts /UE; /O the same, with /U
+    // hashed in.
+    fn unlock_aes256(&self, password: &[u8]) -> Option<(Vec<u8>, bool)> {
+        let password = &password[..password.len().min(MAX_PASSWORD)];
+        let user = &self.user[..48];
+        let candidates = [(user, &[][..], &self.user_key, false), (&self.owner[..48], user, &self.owner_key, true)];
+        for &(entry, extra, encrypted_key, owner) in candidates.iter() {
+            if hash(self.revision, password, &entry[32..40], extra) != entry[..32] {
+                continue;
+            }
+            if encrypted_key.len() < 32 {
+                return None;
+            }
+            let key = hash(self.revision, password, &entry[40..48], extra);
+            return Some((Aes::new(&key).cbc_decrypt(&[0; 16], &encrypted_key[..32]), owner));
+        }
+        None
+    }
+}
+
+// Revision 5 hashes once with SHA-256, revision 6 goes on with rounds of
+// AES-128 and SHA-2 (PDF 32000-2 7.6.4.3.4).
+fn hash(revision: i64, password: &[u8], salt: &[u8], extra: &[u8]) -> Vec<u8> {
+    let mut input = password.to_vec();
+    input.extend_from_slice(salt);
+    input.extend_from_slice(extra);
+    let mut hash = sha256(&input).to_vec();
+    if revision == 5 {
+        return hash;
+    }
+    let mut round = 0;
+    loop {
+        let mut repeated = password.to_vec();
+        repeated.extend_from_slice(&hash);
+        repeated.extend_from_slice(extra);
+        let mut iv = [0; 16];
+        iv.copy_from_slice(&hash[16..32]);
+        let encrypted = Aes::new(&hash[..16]).cbc_encrypt(&iv, &repeated.repeat(64));
+        // the first 16 bytes as a number, modulo 3, picks the next hash
+        hash = match encrypted[..16].iter().map(|&byte| byte as u32).sum::<u32>() % 3 {
+            0 => sha256(&encrypted).to_vec(),
+            1 => sha384(&encrypted).to_vec(),
+            _ => sha512(&encrypted).to_vec(),
+        };
+        round += 1;
+        if round >= 64 && encrypted[encrypted.len() - 1] as u32 <= round - 32 {
+            break;
+        }
+    }
+    hash.truncate(32);
+    hash
+}
+
+// The /CF entry's crypt filters, and the Identity one that's always there.
+fn crypt_filters(encrypt: &DictNode) -> Result<HashMap<String, Method>> {
+    let mut filters = HashMap::new();
+    if let Some(DictNode::Dict(defined)) = encrypt.get("CF") {
+        for (name, filter) in defined {
+            let method = match filter.get("CFM").and_then(DictNode::as_name).unwrap_or("None") {
+                "None" => Method::Identity,
+                "V2" => Method::Rc4,
+                "AESV2" | "AESV3" => Method::Aes,
+                other => return Err(PdfError::Encryption(format!("the /{} crypt filter method isn't supported", other))),
+            };
+            filters.insert(name.clone(), method);
+        }
+    }
+    filters.insert("Identity".to_string(), Method::Identity);
+    Ok(filters)
+}
+
+// The password cut or padded to 32 bytes.
+pub(crate) fn pad(password: &[u8]) -> Vec<u8> {
+    password.iter().chain(PADDING.iter()).take(32).cloned().collect()
+}
+
+fn xor(key: &[u8], round: u8) -> Vec<u8> {
+    key.iter().map(|&byte| byte ^ round).collect()
+}
+
+// `node` with `apply` done to every string in it, all the way down.
+fn map_strings(node: DictNode, apply: &dyn Fn(&[u8]) -> Vec<u8>) -> DictNode {
+    match node {
+        DictNode::LiteralString(value) => DictNode::LiteralString(apply(&value)),
+        DictNode::HexString(value) => DictNode::HexString(apply(&value)),
+        DictNode::Array(items) => DictNode::Array(items.into_iter().map(|item| map_strings(item, apply)).collect()),
+        DictNode::Dict(map) => DictNode::Dict(map.into_iter().map(|(name, value)| (name, map_strings(value, apply))).collect()),
+        node => node,
+    }
+}
+
+// Bytes for file keys, salts and initialization vectors. There's no
+// randomness crate to lean on; the standard library seeds its hash map
+// keys from the operating system's, and SipHash under those keys, of a
+// counter and the time, through SHA-256, is as good as needed here.
+pub(crate) fn random_bytes(count: usize) -> Vec<u8> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut bytes = Vec::new();
+    while bytes.len() < count {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+        let mut input = time.to_le_bytes().to_vec();
+        for _ in 0..2 {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+            input.extend_from_slice(&hasher.finish().to_le_bytes());
+        }
+        bytes.extend_from_slice(&sha256(&input));
+    }
+    bytes.truncate(count);
+    bytes
+}
+
+#[cfg(test)]
+fn bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn test_security_handler() {
+    // made with owner password "owner", user password "user"
+    let encrypt = |revision: i64, owner: &str, user: &str| DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::name("Standard"),
+        "V".to_string() => DictNode::Int(if revision == 2 { 1 } else { 2 }),
+        "R".to_string() => DictNode::Int(revision),
+        "Length".to_string() => DictNode::Int(128),
+        "P".to_string() => DictNode::Int(-4),
+        "O".to_string() => DictNode::HexString(bytes(owner)),
+        "U".to_string() => DictNode::HexString(bytes(user)),
+    });
+    let id: Vec<u8> = (0..16).collect();
+    let revision2 = encrypt(2, "94e8094419662a774442fb072e3d9f19e9d130ec09a4d0061e78fe920f7ab62f",
+                            "56a39de58f86fa456642db1674c3060f327a4e038275843997dccfd58ea7900e");
+    let revision3 = encrypt(3, "0ba3835f88f90388e74e54584125ce142be0de24c6b0d37746e075b891756671",
+                            "b5eeee15d9561a1fad6c9d5a195dde3300000000000000000000000000000000");
+
+    let handler = SecurityHandler::new(&revision2, Some(5), &id, b"user").unwrap();
+    assert_eq!(handler.key, bytes("2a136df655"));
+    assert!(!handler.owner);
+    assert_eq!(handler.decrypt(12, 0, &bytes("0df1008939")), b"Hello");
+    let handler = SecurityHandler::new(&revision3, Some(5), &id, b"owner").unwrap();
+    assert_eq!(handler.key, bytes("4c8652a72175cef37e14c485b59602d2"));
+    assert!(handler.owner);
+    assert_eq!(handler.permissions, -4);
+    assert_eq!(handler.allowed().password, Some(Password::Owner));
+    assert_eq!(handler.decrypt_strings(12, 0, DictNode::Array(vec![DictNode::HexString(bytes("b3cf7098f9")), DictNode::Int(1)])),
+               DictNode::Array(vec![DictNode::HexString(b"Hello".to_vec()), DictNode::Int(1)]));
+    // the /Encrypt dictionary's own strings stay as they are
+    assert_eq!(handler.decrypt_strings(5, 0, DictNode::HexString(bytes("b3cf7098f9"))), DictNode::HexString(bytes("b3cf7098f9")));
+
+    assert!(SecurityHandler::new(&revision3, None, &id, b"").is_err());
+    assert!(SecurityHandler::new(&revision3, None, &id[1..], b"user").is_err());
+    let future = DictNode::Dict(hashmap!{ "Filter".to_string() => DictNode::name("Standard"), "V".to_string() => DictNode::Int(6) });
+    assert_eq!(SecurityHandler::new(&future, None, &id, b"").unwrap_err().to_string(), "can't decrypt the document: encryption version 6 isn't supported");
+}
+
+#[test]
+fn test_aes_security_handler() {
+    let encrypt = |version: i64, revision: i64, method: &str, entries: Vec<(&str, &str)>| {
+        let mut map = hashmap!{
+            "Filter".to_string() => DictNode::name("Standard"),
+            "V".to_string() => DictNode::Int(version),
+            "R".to_string() => DictNode::Int(revision),
+            "P".to_string() => DictNode::Int(-4),
+            "CF".to_string() => DictNode::Dict(hashmap!{ "StdCF".to_string() => DictNode::Dict(hashmap!{ "CFM".to_string() => DictNode::name(method) }) }),
+            "StmF".to_string() => DictNode::name("StdCF"),
+            "StrF".to_string() => DictNode::name("StdCF"),
+        };
+        for (key, value) in entries {
+            map.insert(key.to_string(), DictNode::HexString(bytes(value)));
+        }
+        DictNode::Dict(map)
+    };
+    let id: Vec<u8> = (0..16).collect();
+    // "Hello" encrypted after an initialization vector of nines
+    let hello = |encrypted: &str| bytes(&format!("{}{}", "09".repeat(16), encrypted));
+
+    // made with owner password "owner", user password "user", and metadata
+    // left unencrypted
+    let mut revision4 = encrypt(4, 4, "AESV2", vec![
+        ("O", "0ba3835f88f90388e74e54584125ce142be0de24c6b0d37746e075b891756671"),
+        ("U", "90f938e985aec2730174ce893aedde6500000000000000000000000000000000"),
+    ]);
+    if let DictNode::Dict(ref mut map) = revision4 {
+        map.insert("EncryptMetadata".to_string(), DictNode::Bool(false));
+    }
+    let handler = SecurityHandler::new(&revision4, None, &id, b"user").unwrap();
+    assert_eq!(handler.key, bytes("d640913055548cf686a4134c2533d9f6"));
+    assert_eq!(handler.decrypt(12, 0, &hello("742ed12b0d0aa1d1f657f6e7070740d0")), b"Hello");
+    assert_eq!(handler.decrypt_strings(12, 0, DictNode::LiteralString(hello("742ed12b0d0aa1d1f657f6e7070740d0"))),
+               DictNode::LiteralString(b"Hello".to_vec()));
+    assert!(SecurityHandler::new(&revision4, None, &id, b"owner").unwrap().owner);
+    // metadata, and streams whose own crypt filter is Identity, aren't
+    // encrypted
+    let metadata = DictNode::Dict(hashmap!{ "Type".to_string() => DictNode::name("Metadata") });
+    assert!(!handler.encrypts_stream(12, &metadata));
+    let identity = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::Array(vec![DictNode::name("Crypt")]),
+        "DecodeParms".to_string() => DictNode::Array(vec![DictNode::Dict(hashmap!{ "Name".to_string() => DictNode::name("Identity") })]),
+    });
+    assert_eq!(handler.decrypt_stream(12, 0, &identity, b"Hello"), b"Hello");
+    assert!(handler.encrypts_stream(12, &DictNode::Dict(hashmap!{})));
+
+    // revision 5 and 6 with the same passwords, and a file key of 100 to 131
+    let file_key: Vec<u8> = (100..132).collect();
+    let revision6 = encrypt(5, 6, "AESV3", vec![
+        ("O", "a1beb286741571c1f87699a988140a14802c76840230b6bf3593889a8d6aec836f76616c73616c746f6b657973616c74"),
+        ("U", "ffdf5f2ee6b251856d72c8a0f38f37e9a745f7895ba8fadbd314415cc10fef827576616c73616c74756b657973616c74"),
+        ("OE", "03bcb586925a80f59f28b850075eb75ab97cdafb38994ee141ff6d8b5b514b16"),
+        ("UE", "9e0bf4d337b901e5d21abd379324eadca3a1d707ad57c97333f7b52968bff414"),
+    ]);
+    let revision5 = encrypt(5, 5, "AESV3", vec![
+        ("O", "bed5d609ba7d6eaad7531b7932d1be9132d88262b2f25c23fde069dfcee27e2c6f76616c73616c746f6b657973616c74"),
+        ("U", "30e78731473a33262221ce60b932cc3d9e299aca0a078ad3c59a82424a6e14747576616c73616c74756b657973616c74"),
+        ("OE", "dd5d4ee716650173598975c7ebf26ac626d7c9d29c042bd4279f86027b5a61c0"),
+        ("UE", "98a0bdde9691f1c9fa6282fb23bec5a96336acb5a055f696ba99b7ee9f3b6cc5"),
+    ]);
+    for encrypt in &[revision6, revision5] {
+        let handler = SecurityHandler::new(encrypt, None, &id, b"user").unwrap();
+        assert_eq!(handler.key, file_key);
+        assert!(!handler.owner);
+        let handler = SecurityHandler::new(encrypt, None, &id, b"owner").unwrap();
+        assert_eq!(handler.key, file_key);
+        assert!(handler.owner);
+        // no per object keys
+        assert_eq!(handler.decrypt(3, 0, &hello("87f4def9fdd883022ea65d5098bb5282")), b"Hello");
+        assert_eq!(handler.decrypt(40, 2, &hello("87f4def9fdd883022ea65d5098bb5282")), b"Hello");
+        assert!(SecurityHandler::new(encrypt, None, &id, b"").is_err());
+    }
+
+    let unknown = encrypt(4, 4, "AESV9", vec![("O", &"00".repeat(32)), ("U", &"00".repeat(32))]);
+    assert_eq!(SecurityHandler::new(&unknown, None, &id, b"").unwrap_err().to_string(),
+               "can't decrypt the document: the /AESV9 crypt filter method isn't supported");
+
+    // AESV2 with a short key, which would give 10 byte object keys
+    let mut short = encrypt(4, 4, "AESV2", vec![("O", &"00".repeat(32)), ("U", &"00".repeat(32))]);
+    if let DictNode::Dict(ref mut map) = short {
+        map.insert("Length".to_string(), DictNode::Int(40));
+    }
+    assert_eq!(SecurityHandler::new(&short, None, &id, b"").unwrap_err().to_string(),
+               "can't decrypt the document: AES needs 128 bit keys, not 40 bit ones");
+    // revision 2 always has 5 byte keys
+    let revision2 = encrypt(4, 2, "AESV2", vec![("O", &"00".repeat(32)), ("U", &"00".repeat(32))]);
+    assert!(SecurityHandler::new(&revision2, None, &id, b"").is_err());
+}
+
+#[test]
+fn test_encryption() {
+    let encryption = Encryption {
+        user_password: b"user".to_vec(),
+        owner_password: b"owner".to_vec(),
+        permissions: Permissions::from_bits(-3904 | 1 << 2, 3),
+    };
+    let (encrypt, handler) = encryption.security_handler();
+    assert_eq!(encrypt.get("R").and_then(DictNode::as_int), Some(6));
+    assert_eq!(encrypt.get("P").and_then(DictNode::as_int), Some(-3900));
+
+    // both passwords open it, to the same key, and nothing else does
+    let id = b"any id, revision 6 doesn't use it";
+    let opened = SecurityHandler::new(&encrypt, Some(7), id, b"user").unwrap();
+    assert_eq!((&opened.key, opened.owner), (&handler.key, false));
+    assert_eq!(opened.allowed().names(), vec!["print"]);
+    assert!(SecurityHandler::new(&encrypt, Some(7), id, b"owner").unwrap().owner);
+    assert!(SecurityHandler::new(&encrypt, Some(7), id, b"").is_err());
+    // /Perms is /P under the file key
+    let perms = Aes::new(&handler.key).cbc_decrypt(&[0; 16], encrypt.get("Perms").and_then(DictNode::as_string).unwrap());
+    assert_eq!(&perms[..12], b"\xc4\xf0\xff\xff\xff\xff\xff\xffTadb");
+
+    let object = DictNode::Array(vec![DictNode::LiteralString(b"Hello".to_vec()), DictNode::Int(1)]);
+    let encrypted = handler.encrypt_strings(12, 0, object.clone());
+    assert_ne!(encrypted, object);
+    assert_eq!(opened.decrypt_strings(12, 0, encrypted), object);
+    let data = handler.encrypt(12, 0, b"BT ET");
+    assert_eq!(data.len(), 32);
+    assert_eq!(opened.decrypt(12, 0, &data), b"BT ET");
+    // a new initialization vector every time
+    assert_ne!(handler.encrypt(12, 0, b"BT ET"), data);
+
+    // without an owner password the user's is both, not an empty one
+    let (encrypt, _) = Encryption { user_password: b"user".to_vec(), ..Encryption::default() }.security_handler();
+    assert!(SecurityHandler::new(&encrypt, None, id, b"user").is_ok());
+    assert!(SecurityHandler::new(&encrypt, None, id, b"").is_err());
+}
+
+#[test]
+fn test_encrypted_fixture() {
+    use document::{Document, DocumentPolicy, Options};
+    use fixtures::{Fixture, OWNER_PASSWORD};
+    use info;
+
+    let file = Fixture::Encrypted.write();
+    let open = |password: Option<&str>| {
+        Document::open(&file.path, Options { password: password.map(str::to_string), ..Options::default() })
+    };
+    // the empty user password, tried when there's none given
+    let mut doc = open(None).unwrap();
+    assert_eq!(doc.permissions().password, Some(Password::User));
+    assert_eq!(info::info(&mut doc).unwrap().unwrap().title.as_deref(), Some("Fixture"));
+    let mut doc = open(Some(OWNER_PASSWORD)).unwrap();
+    assert_eq!(doc.permissions().password, Some(Password::Owner));
+    assert_eq!(info::info(&mut doc).unwrap().unwrap().title.as_deref(), Some("Fixture"));
+    assert!(matches!(open(Some("wrong")), Err(PdfError::Encryption(_))));
+
+    let refuse = DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() };
+    assert!(matches!(Document::open(&file.path, Options { policy: refuse, ..Options::default() }), Err(PdfError::Policy(_))));
+}
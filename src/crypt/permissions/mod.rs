@@ -0,0 +1,133 @@
+use std::fmt;
+
+/*
+ * What an encrypted document lets its readers do, decoded from the /P
+ * entry of its /Encrypt dictionary (PDF 32000 table 22).
+ *
+ *   let permissions = doc.permissions();
+ *   if !permissions.copy { ... }
+ *
+ * /P is a 32 bit mask, a set bit allows something. Revision 2 only has
+ * bits 3 to 6, the finer grained ones that came with revision 3 follow
+ * the older bit they were split out of there: high quality printing
+ * follows printing, assembly follows modifying, and so on.
+ *
+ * The flags are what /P says whichever password opened the file. Whoever
+ * has the owner password isn't bound by them, `password` tells which one
+ * it was. Honouring them is up to the application, the encryption doesn't
+ * enforce any of it.
+ */
+
+// The password a document was opened with. An empty user password is
+// still the user password.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Password {
+    User,
+    Owner,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Permissions {
+    pub print: bool,
+    // at full resolution, rather than a degraded image of the page
+    pub print_high_quality: bool,
+    pub modify: bool,
+    // copying or otherwise extracting text and graphics
+    pub copy: bool,
+    // adding and changing annotations, filling in form fields
+    pub annotate: bool,
+    // filling in form fields, even when annotating isn't allowed
+    pub fill_forms: bool,
+    pub extract_for_accessibility: bool,
+    // inserting, rotating and deleting pages, making outlines and thumbnails
+    pub assemble: bool,
+    // None for documents that aren't encrypted
+    pub password: Option<Password>,
+}
+
+impl Permissions {
+    // Everything, what a document that isn't encrypted allows.
+    pub fn all() -> Permissions {
+        Permissions::from_bits(-1, 3)
+    }
+
+    // Decodes /P as revision `revision` of the standard security handler
+    // reads it.
+    pub fn from_bits(bits: i64, revision: i64) -> Permissions {
+        let bit = |n: u32| bits & (1 << (n - 1)) != 0;
+        let later = |n, older| if revision >= 3 { bit(n) } else { bit(older) };
+        Permissions {
+            print: bit(3),
+            print_high_quality: later(12, 3),
+            modify: bit(4),
+            copy: bit(5),
+            annotate: bit(6),
+            fill_forms: later(9, 6),
+            extract_for_accessibility: later(10, 5),
+            assemble: later(11, 4),
+            password: None,
+        }
+    }
+
+    // The other way round, /P for revision 3 and later: a bit for each
+    // thing allowed, and the reserved bits set as the spec asks.
+    pub fn to_bits(&self) -> i64 {
+        let flags = [
+            (self.print, 3), (self.modify, 4), (self.copy, 5), (self.annotate, 6), (self.fill_forms, 9),
+            (self.extract_for_accessibility, 10), (self.assemble, 11), (self.print_high_quality, 12),
+        ];
+        flags.iter().filter(|&&(allowed, _)| allowed).fold(-3904, |bits, &(_, n)| bits | 1 << (n - 1))
+    }
+
+    // The names of what's allowed, in table order.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.print, "print"),
+            (self.modify, "modify"),
+            (self.copy, "copy"),
+            (self.annotate, "annotate"),
+            (self.fill_forms, "fill forms"),
+            (self.extract_for_accessibility, "extract for accessibility"),
+            (self.assemble, "assemble"),
+            (self.print_high_quality, "print high quality"),
+        ].iter().filter(|&&(allowed, _)| allowed).map(|&(_, name)| name).collect()
+    }
+}
+
+// "print, copy (user password)", or "none" when nothing is allowed.
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let allowed = self.names();
+        if allowed.is_empty() {
+            write!(f, "none")?;
+        } else {
+            write!(f, "{}", allowed.join(", "))?;
+        }
+        match self.password {
+            Some(Password::User) => write!(f, " (user password)"),
+            Some(Password::Owner) => write!(f, " (owner password)"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_permissions() {
+    assert_eq!(Permissions::all().names().len(), 8);
+    assert_eq!(Permissions::all().to_string(),
+               "print, modify, copy, annotate, fill forms, extract for accessibility, assemble, print high quality");
+    // what Acrobat writes for "no printing, no changes, no copying"
+    assert_eq!(Permissions::from_bits(-3904, 3).to_string(), "none");
+
+    // print and copy, bits 3 and 5 (and the reserved 7, 8 and 13 on)
+    let permissions = Permissions { password: Some(Password::User), ..Permissions::from_bits(-3884, 2) };
+    assert_eq!(permissions.to_string(), "print, copy, extract for accessibility, print high quality (user password)");
+    let permissions = Permissions::from_bits(-3884, 3);
+    assert_eq!(permissions.names(), vec!["print", "copy"]);
+    let permissions = Permissions::from_bits(-3884 | 1 << 8, 3);
+    assert!(permissions.fill_forms && !permissions.annotate);
+
+    assert_eq!(Permissions::all().to_bits(), -4);
+    assert_eq!(Permissions::from_bits(-3904, 3).to_bits(), -3904);
+    assert_eq!(Permissions::from_bits(permissions.to_bits(), 3), permissions);
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use document::Document;
+use parsers::cos::DictNode;
+
+/*
+ * Editing a document in memory: objects set, added and removed on top of
+ * what's in the file, which stays untouched.
+ *
+ *   doc.checkpoint();
+ *   let id = doc.add_object(annotation);
+ *   doc.set_object(page_id, page_with_annotation);
+ *   ...
+ *   doc.rollback();    // undoes both
+ *
+ * Edited objects take the place of the file's version of them wherever
+ * objects are looked up by number (`cached_object`, `resolve`,
+ * `referenced_stream`, ...); `edits()` lists them, and the writer module
+ * writes the result out. Reading through xref entries (`object`, `stream`) still
+ * sees the file.
+ *
+ * Checkpoints nest: `rollback` undoes everything since the last one and
+ * drops it, `commit` drops it and keeps the changes. Undoing doesn't take
+ * a copy of the document, only a journal of what each change replaced,
+ * kept while there's a checkpoint to go back to. The trailer is small, a
+ * checkpoint keeps a copy of it, so edits to `trailer` are undone too.
+ *
+ * Observers hear about each change as it's made, so a GUI or a sync layer
+ * can keep up without polling:
+ *
+ *   doc.observe(|event| match *event {
+ *       EditEvent::PageRemoved { index, .. } => ...,
+ *       _ => (),
+ *   });
+ *
+ * Setting and removing objects reports objects inserted, modified and
+ * deleted, going by whether there was an object before and after; page
+ * events come from the page_tree module's `insert_page`, `remove_page`
+ * and `reorder_pages`, after the events for the objects they touched. A rollback
+ * reports the objects it puts back the way they were.
+ */
+
+// An object as edited: its value, and its data if it's a stream, encoded
+// the way the dictionary's /Filter says.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EditedObject {
+    pub object: DictNode,
+    pub data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EditEvent {
+    ObjectInserted(u64),
+    ObjectModified(u64),
+    ObjectDeleted(u64),
+    // the page object `id`, which is now, or was, the page at `index`
+    PageAdded { index: usize, id: u64 },
+    PageRemoved { index: usize, id: u64 },
+    // the page object `id` went from index `from` to `to`
+    PageMoved { from: usize, to: usize, id: u64 },
+}
+
+impl EditEvent {
+    // What a change to object `id` was, given whether there was an object
+    // before and after it. None when there wasn't either time.
+    fn object(id: u64, before: bool, after: bool) -> Option<EditEvent> {
+        match (before, after) {
+            (false, true) => Some(EditEvent::ObjectInserted(id)),
+            (true, true) => Some(EditEvent::ObjectModified(id)),
+            (true, false) => Some(EditEvent::ObjectDeleted(id)),
+            (false, false) => None,
+        }
+    }
+}
+
+pub type EditObserver = Box<dyn FnMut(&EditEvent) + Send>;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub(crate) struct Edits {
+    // by object number, None for removed objects
+    objects: HashMap<u64, Option<EditedObject>>,
+    // what `objects` had for an object number before each change, None
+    // when it wasn't edited yet
+    journal: Vec<(u64, Option<Option<EditedObject>>)>,
+    // the journal's length and the trailer at each checkpoint
+    checkpoints: Vec<(usize, DictNode)>,
+}
+
+impl Edits {
+    // Some(None) for removed objects, None for ones that weren't edited.
+    pub(crate) fn get(&self, id: u64) -> Option<Option<&EditedObject>> {
+        self.objects.get(&id).map(Option::as_ref)
+    }
+
+    fn set(&mut self, id: u64, edited: Option<EditedObject>) {
+        let before = self.objects.insert(id, edited);
+        if !self.checkpoints.is_empty() {
+            self.journal.push((id, before));
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // One more than the highest object number edited, 0 with no edits.
+    fn end(&self) -> u64 {
+        self.objects.keys().max().map_or(0, |&id| id + 1)
+    }
+
+    // The object numbers changed since the last checkpoint, in order.
+    fn pending(&self) -> Vec<u64> {
+        let length = self.checkpoints.last().map_or(self.journal.len(), |&(length, _)| length);
+        let mut ids: Vec<u64> = self.journal[length..].iter().map(|&(id, _)| id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    fn checkpoint(&mut self, trailer: &DictNode) {
+        self.checkpoints.push((self.journal.len(), trailer.clone()));
+    }
+
+    // Undoes the changes since the last checkpoint, and hands back the
+    // trailer as it was then.
+    fn rollback(&mut self) -> Option<DictNode> {
+        let (length, trailer) = self.checkpoints.pop()?;
+        for (id, before) in self.journal.drain(length..).rev() {
+            match before {
+                Some(before) => self.objects.insert(id, before),
+                None => self.objects.remove(&id),
+            };
+        }
+        Some(trailer)
+    }
+
+    fn commit(&mut self) -> bool {
+        if self.checkpoints.pop().is_none() {
+            return false;
+        }
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
+        true
+    }
+}
+
+impl Document {
+    // Replaces object `id`, or adds it if there's no such object.
+    pub fn set_object(&mut self, id: u64, object: DictNode) {
+        self.change(id, Some(EditedObject { object, data: None }));
+    }
+
+    // The same for a stream, with its data encoded as `dict` says. /Length
+    // is set from the data.
+    pub fn set_stream(&mut self, id: u64, mut dict: HashMap<String, DictNode>, data: Vec<u8>) {
+        dict.insert("Length".to_string(), DictNode::Int(data.len() as i64));
+        self.change(id, Some(EditedObject { object: DictNode::Dict(dict), data: Some(data) }));
+    }
+
+    // Adds `object` under a new object number, which it returns.
+    pub fn add_object(&mut self, object: DictNode) -> u64 {
+        let id = self.next_object_id();
+        self.set_object(id, object);
+        id
+    }
+
+    // An object number no object has, in the file or edited in.
+    pub fn next_object_id(&self) -> u64 {
+        (self.xref.len() as u64).max(self.edits.end()).max(1)
+    }
+
+    pub fn remove_object(&mut self, id: u64) {
+        self.change(id, None);
+    }
+
+    fn change(&mut self, id: u64, edited: Option<EditedObject>) {
+        let before = self.exists(id);
+        let after = edited.is_some();
+        self.edits.set(id, edited);
+        if let Some(event) = EditEvent::object(id, before, after) {
+            self.notify(&event);
+        }
+    }
+
+    // Whether there's an object `id`, edited in or in the file.
+    fn exists(&self, id: u64) -> bool {
+        match self.edits.get(id) {
+            Some(edited) => edited.is_some(),
+            None => self.xref.get(id as usize).is_some_and(|entry| !entry.is_free),
+        }
+    }
+
+    // Calls `observer` with every change from now on.
+    pub fn observe<F: FnMut(&EditEvent) + Send + 'static>(&mut self, observer: F) {
+        self.observers.push(Box::new(observer));
+    }
+
+    pub(crate) fn notify(&mut self, event: &EditEvent) {
+        for observer in &mut self.observers {
+            observer(event);
+        }
+    }
+
+    // Every object edited, by object number, None for removed ones.
+    pub fn edits(&self) -> Vec<(u64, Option<&EditedObject>)> {
+        let mut edits: Vec<(u64, Option<&EditedObject>)> = self.edits.objects.iter()
+            .map(|(&id, edited)| (id, edited.as_ref()))
+            .collect();
+        edits.sort_by_key(|&(id, _)| id);
+        edits
+    }
+
+    // Remembers the document as it is, for `rollback` to go back to.
+    pub fn checkpoint(&mut self) {
+        self.edits.checkpoint(&self.trailer);
+    }
+
+    // Undoes every edit since the last checkpoint, trailer included, and
+    // drops the checkpoint. False if there's none.
+    pub fn rollback(&mut self) -> bool {
+        let ids = self.edits.pending();
+        let before: Vec<bool> = ids.iter().map(|&id| self.exists(id)).collect();
+        match self.edits.rollback() {
+            Some(trailer) => {
+                self.trailer = trailer;
+                for (&id, before) in ids.iter().zip(before) {
+                    if let Some(event) = EditEvent::object(id, before, self.exists(id)) {
+                        self.notify(&event);
+                    }
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Drops the last checkpoint, keeping the edits made since. False if
+    // there's none.
+    pub fn commit(&mut self) -> bool {
+        self.edits.commit()
+    }
+}
+
+#[test]
+fn test_edits() {
+    let int = |value| Some(EditedObject { object: DictNode::Int(value), data: None });
+    let mut edits = Edits::default();
+    let trailer = DictNode::Dict(HashMap::new());
+
+    // without a checkpoint nothing is journaled
+    edits.set(3, int(1));
+    assert!(edits.journal.is_empty());
+    assert_eq!(edits.rollback(), None);
+
+    edits.checkpoint(&trailer);
+    edits.set(3, int(2));
+    edits.set(5, int(7));
+    edits.checkpoint(&trailer);
+    edits.set(3, None);
+    assert_eq!(edits.get(3), Some(None));
+    edits.set(3, int(4));
+    edits.set(1, None);
+    assert_eq!(edits.pending(), vec![1, 3]);
+    assert_eq!(edits.end(), 6);
+
+    assert_eq!(edits.rollback(), Some(trailer.clone()));
+    assert_eq!(edits.get(3).unwrap().unwrap().object, DictNode::Int(2));
+    assert_eq!(edits.rollback(), Some(trailer.clone()));
+    assert_eq!(edits.get(3).unwrap().unwrap().object, DictNode::Int(1));
+    assert_eq!(edits.get(5), None);
+    assert_eq!(edits.end(), 4);
+
+    // committing keeps the changes, and the journal goes once there's
+    // nothing left to roll back to
+    edits.checkpoint(&trailer);
+    edits.set(8, int(8));
+    assert!(edits.commit());
+    assert!(!edits.commit());
+    assert!(edits.journal.is_empty());
+    assert_eq!(edits.get(8).unwrap().unwrap().object, DictNode::Int(8));
+}
+
+#[test]
+fn test_edit_event() {
+    assert_eq!(EditEvent::object(4, false, true), Some(EditEvent::ObjectInserted(4)));
+    assert_eq!(EditEvent::object(4, true, true), Some(EditEvent::ObjectModified(4)));
+    assert_eq!(EditEvent::object(4, true, false), Some(EditEvent::ObjectDeleted(4)));
+    // removing an object that isn't there
+    assert_eq!(EditEvent::object(4, false, false), None);
+}
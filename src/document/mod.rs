@@ -0,0 +1,1796 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crypt::{Permissions, SecurityHandler};
+use error::{PdfError, Result};
+use filters;
+use filters::EncodedImage;
+use index_cache::{self, Index};
+use metrics::{CountingReader, Metrics, MetricsCallback};
+use object_streams::ObjectStream;
+use pages::{self, Leaf, Page};
+use parsers;
+use parsers::content::{Operations, Tokenizer};
+use parsers::cos::{self, DictNode};
+use parsers::object::{Scan, Terminator};
+use parsers::xref::{XRefEntry, XRefTable};
+use patch::{self, PatchOp};
+use pipeline::Job;
+use spill::SpillBuffer;
+use streams;
+use streams::StreamReader;
+use text;
+use timings::{Phase, Timings};
+use transcode;
+
+pub mod edit;
+
+use document::edit::{EditedObject, EditObserver, Edits};
+
+/*
+ * A PDF document opened from disk: the trailer, the xref table and a handle
+ * on the file so objects can be pulled out on demand.
+ *
+ * In lenient mode we try hard to get *something* out of broken files. The
+ * most common breakage by far is a half-downloaded file: the xref table and
+ * trailer live at the very end, so they're the first thing to go, and the
+ * last object is usually cut off somewhere in the middle. Anything we had to
+ * paper over is recorded in `warnings()`.
+ *
+ * Some files are beyond that: a generator that never wrote a trailer, or
+ * one whose trailer points at the wrong objects. `open_by_scanning` doesn't
+ * look at the end of the file at all. It indexes every "N G obj" header
+ * (and what object streams hold), and makes up a trailer whose /Root is
+ * the last catalog in the file. When that catalog has no usable page tree
+ * either, every /Type /Page object becomes a page, in file order, under a
+ * new /Pages node. The trailer and rebuilt tree are direct objects, they
+ * have no object numbers.
+ *
+ * Objects can be edited in memory, with checkpoints to roll back to, see
+ * the edit module.
+ *
+ * `refresh` catches up with a file something else keeps writing to. When
+ * all that happened is an incremental update, the file grew and its
+ * newest xref section leads back through /Prev to the one we read, only
+ * the appended sections are read and merged in, and only the objects they
+ * list are dropped from the caches. Anything else opens the file again.
+ */
+
+const CHUNK_SIZE: i64 = 10240;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Options {
+    pub lenient: bool,
+    pub duplicates: DuplicatePolicy,
+    // decoded streams bigger than this many bytes are buffered on disk
+    // instead of in memory, None to always use memory
+    pub spill_threshold: Option<usize>,
+    // keep a sidecar index of the xref, the page tree and the fonts next to
+    // the file, see index_cache; strict opens with the default duplicates
+    // policy only
+    pub index_cache: bool,
+    pub policy: DocumentPolicy,
+    // for encrypted documents, tried as the user password and then as the
+    // owner's, None for the empty password most of them have
+    pub password: Option<String>,
+}
+
+// What the caller allows a document to make us do, so a server can hold
+// every document to the same limits whatever it does with them. Breaking
+// the policy is a PdfError::Policy. The default allows everything except
+// following external references.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DocumentPolicy {
+    // read stream data kept in another file (a stream's /F), which has to
+    // be next to the document or below its directory
+    pub follow_external_references: bool,
+    // hand out decoded image streams, image decoders being where most
+    // decoder bugs live
+    pub decode_images: bool,
+    // refuse documents with more pages than this
+    pub max_pages: Option<usize>,
+    pub allow_encrypted: bool,
+}
+
+impl DocumentPolicy {
+    pub fn check_pages(&self, count: usize) -> Result<()> {
+        match self.max_pages {
+            Some(max_pages) if count > max_pages => {
+                Err(PdfError::Policy(format!("{} pages, more than the {} allowed", count, max_pages)))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Refuses to hand out decoded image data if images aren't allowed.
+    fn check_decode(&self, dict: &DictNode) -> Result<()> {
+        let image = match *dict {
+            DictNode::Dict(ref map) => map.get("Subtype") == Some(&DictNode::name("Image")),
+            _ => false,
+        };
+        if image && !self.decode_images {
+            return Err(PdfError::Policy("decoding images".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for DocumentPolicy {
+    fn default() -> DocumentPolicy {
+        DocumentPolicy { follow_external_references: false, decode_images: true, max_pages: None, allow_encrypted: true }
+    }
+}
+
+// Which definition to use when the same object number shows up more than
+// once in the file. Sloppy incremental editors love doing this. Finding out
+// takes a scan of the whole file, which every policy but PreferXRef does
+// on open, and so does a lenient open; each object defined more than once
+// is then a DuplicateObject warning. Strict PreferXRef opens only scan
+// when `scan_duplicates` or `object_versions` asks them to.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum DuplicatePolicy {
+    LastWins,
+    FirstWins,
+    // trust the xref table, falls back to last-wins when there isn't one
+    #[default]
+    PreferXRef,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Warning {
+    // no usable startxref/trailer at the end of the file, the xref table
+    // was rebuilt by scanning for object headers and the trailer is the
+    // last "trailer" dictionary in the file, if there's one
+    TruncatedDocument,
+    // the object ran into the next "N G obj" header without an endobj
+    MissingEndobj { offset: u64 },
+    // the object ran into the end of the file
+    TruncatedObject { offset: u64 },
+    // the object number is defined more than once, offsets in file order
+    DuplicateObject { id: u64, offsets: Vec<u64> },
+    // the /Prev xref section at this offset couldn't be read, objects only
+    // it (or older sections) know about are missing
+    BrokenPrevChain { offset: u64 },
+    // the /XRefStm stream of a hybrid file at this offset couldn't be read,
+    // objects in object streams are missing
+    BrokenXRefStm { offset: u64 },
+    // opened with `open_by_scanning`, the trailer is made up
+    RebuiltTrailer,
+    // the catalog had no page tree that led to pages, these many /Page
+    // objects were put in a new one
+    RebuiltPageTree { pages: usize },
+}
+
+impl Warning {
+    // What the warning is counted under in `Metrics::warnings`.
+    pub fn category(&self) -> &'static str {
+        match *self {
+            Warning::TruncatedDocument => "truncated_document",
+            Warning::MissingEndobj { .. } => "missing_endobj",
+            Warning::TruncatedObject { .. } => "truncated_object",
+            Warning::DuplicateObject { .. } => "duplicate_object",
+            Warning::BrokenPrevChain { .. } => "broken_prev_chain",
+            Warning::BrokenXRefStm { .. } => "broken_xref_stm",
+            Warning::RebuiltTrailer => "rebuilt_trailer",
+            Warning::RebuiltPageTree { .. } => "rebuilt_page_tree",
+        }
+    }
+}
+
+// What `refresh` found had happened to the file.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Refresh {
+    // incremental updates were appended, defining or freeing these objects
+    Appended(Vec<u64>),
+    // the file had to be read again from the start
+    Reopened,
+}
+
+// Where a stream's data starts in the file, and the number and generation
+// the stream was defined with, which decrypting the data needs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct StreamStart {
+    offset: u64,
+    id: u64,
+    generation: u64,
+}
+
+pub struct Document {
+    file: File,
+    path: PathBuf,
+    // how long the file was when the xref was read
+    length: u64,
+    // where external stream files are looked up
+    directory: PathBuf,
+    options: Options,
+    warnings: Vec<Warning>,
+    // every "N G obj" header in the file as (id, generation, offset),
+    // only filled in when something needs it
+    scanned: Option<Vec<(u64, u64, u64)>>,
+    // decoded object streams by object number
+    object_streams: HashMap<u64, ObjectStream>,
+    timings: Timings,
+    metrics: Metrics,
+    // bytes decoded through `stream_reader`, whose readers outlive the
+    // borrow of the document
+    streamed: Arc<AtomicU64>,
+    on_drop: Option<MetricsCallback>,
+    // objects parsed through `cached_object` and `resolve`, by object
+    // number and generation. A plain map: DictNode owns its arrays and
+    // dictionaries, an arena would only hold the outermost node of each
+    // object and leave the nested ones to the allocator.
+    objects: HashMap<(u64, u64), DictNode>,
+    // decrypts strings and streams as they're read, for encrypted documents
+    security: Option<SecurityHandler>,
+    // objects set, added or removed in memory, see the edit module
+    edits: Edits,
+    observers: Vec<EditObserver>,
+    // the offset of the newest xref section
+    startxref: Option<u64>,
+    // what the index cache had, when the document was opened from it
+    index: Option<Index>,
+    pub trailer: DictNode,
+    pub xref: XRefTable,
+}
+
+impl Document {
+    pub fn open(path: &Path, options: Options) -> Result<Document> {
+        let start = Instant::now();
+        let mut doc = Document::new(path, options)?;
+
+        // the index doesn't record warnings or which objects a policy
+        // picked, so it's only for strict opens with the default policy,
+        // both reading and writing it
+        let cacheable = doc.options.index_cache && !doc.options.lenient
+            && doc.options.duplicates == DuplicatePolicy::PreferXRef;
+        let cache = if cacheable {
+            Some((index_cache::sidecar_path(path), index_cache::fingerprint(&mut doc.file)?))
+        } else {
+            None
+        };
+        // a broken index is no reason not to open the document
+        let cached = match cache {
+            Some((ref sidecar, ref fingerprint)) => index_cache::read(sidecar, fingerprint).unwrap_or(None),
+            None => None,
+        };
+
+        let hit = cached.is_some();
+        match cached {
+            Some(index) => {
+                doc.trailer = index.trailer.clone();
+                doc.xref = index.xref.clone();
+                doc.index = Some(index);
+                // the fingerprint covers the tail, so this is the startxref
+                // the index was made from
+                doc.startxref = find_startxref(&mut doc.file)?;
+            },
+            None => doc.load_xref()?,
+        }
+
+        // reading an xref stream parses and decodes it, don't count that twice
+        let nested = doc.timings.parse + doc.timings.decode;
+        doc.timings.xref = start.elapsed().saturating_sub(nested);
+        doc.check_policy()?;
+
+        // only documents that loaded cleanly are cached, and walking the
+        // pages needs the security handler
+        if let (Some((sidecar, fingerprint)), false) = (cache, hit) {
+            if doc.warnings.is_empty() {
+                let index = Index::new(&mut doc);
+                // not being able to write next to the file is fine
+                let _ = index_cache::write(&sidecar, &fingerprint, &index);
+            }
+        }
+        Ok(doc)
+    }
+
+    // Builds the document from a scan of the whole file, see the top of the
+    // file. Always lenient, files that need this are broken in other ways
+    // too.
+    pub fn open_by_scanning(path: &Path, mut options: Options) -> Result<Document> {
+        let start = Instant::now();
+        options.lenient = true;
+        let mut doc = Document::new(path, options)?;
+        doc.warn(Warning::RebuiltTrailer);
+        doc.resolve_duplicates()?;
+        doc.index_object_streams();
+
+        // the last catalog in the file is the newest, whatever its number
+        let mut objects: Vec<(u64, (u64, u64), XRefEntry)> = doc.xref.entries()
+            .filter(|(_, entry)| !entry.is_free)
+            .map(|(id, entry)| (id as u64, file_position(&doc.xref, entry), entry.clone()))
+            .collect();
+        objects.sort_by_key(|&(_, position, _)| position);
+        let mut catalog = None;
+        let mut pages = Vec::new();
+        for (id, _, entry) in objects {
+            let object = match doc.object(&entry) {
+                Ok(object) => object,
+                Err(_) => continue,
+            };
+            let reference = DictNode::ObjectReference(id as i64, entry.generation_id as i64);
+            if has_type(&object, "Catalog") {
+                catalog = Some((reference, object));
+            } else if has_type(&object, "Page") {
+                pages.push(reference);
+            }
+        }
+
+        let (root, catalog) = match catalog {
+            Some(catalog) => catalog,
+            None => (DictNode::Null, DictNode::Dict(HashMap::new())),
+        };
+        doc.trailer = DictNode::Dict(HashMap::from([("Root".to_string(), root.clone())]));
+        let found = match root {
+            DictNode::Null => 0,
+            _ => page_count(&mut doc),
+        };
+        if found == 0 && !pages.is_empty() {
+            doc.warn(Warning::RebuiltPageTree { pages: pages.len() });
+            let mut catalog = catalog.as_dict().cloned().unwrap_or_default();
+            catalog.insert("Type".to_string(), DictNode::name("Catalog"));
+            catalog.insert("Pages".to_string(), page_tree(pages));
+            doc.trailer = DictNode::Dict(HashMap::from([("Root".to_string(), DictNode::Dict(catalog))]));
+        }
+
+        let nested = doc.timings.parse + doc.timings.decode;
+        doc.timings.xref = start.elapsed().saturating_sub(nested);
+        doc.check_policy()?;
+        Ok(doc)
+    }
+
+    fn new(path: &Path, options: Options) -> Result<Document> {
+        let file = File::open(path)?;
+        Ok(Document {
+            length: file.metadata()?.len(),
+            file,
+            path: path.to_path_buf(),
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            options,
+            warnings: Vec::new(),
+            scanned: None,
+            object_streams: HashMap::new(),
+            timings: Timings::default(),
+            metrics: Metrics::default(),
+            streamed: Arc::new(AtomicU64::new(0)),
+            on_drop: None,
+            objects: HashMap::new(),
+            security: None,
+            edits: Edits::default(),
+            observers: Vec::new(),
+            trailer: DictNode::Dict(Default::default()),
+            startxref: None,
+            index: None,
+            xref: XRefTable::new(Vec::new()),
+        })
+    }
+
+    // Adds the objects stored in the object streams the scan found to the
+    // xref. Definitions of their own in the file win, an object stream can't
+    // be told apart from an older one it replaces.
+    fn index_object_streams(&mut self) {
+        let streams: Vec<(u64, XRefEntry)> = self.xref.entries()
+            .filter(|(_, entry)| !entry.is_free && entry.object_stream.is_none())
+            .map(|(id, entry)| (id as u64, entry.clone()))
+            .collect();
+        for (stream_id, entry) in streams {
+            let is_object_stream = self.object(&entry).map(|object| has_type(&object, "ObjStm")).unwrap_or(false);
+            if !is_object_stream {
+                continue;
+            }
+            let stream = match self.stream(&entry) {
+                Ok(Some((dict, data))) => match ObjectStream::new(&dict, data) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            for index in 0..stream.len() {
+                let id = match stream.id(index) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                if self.xref.get(id as usize).is_none_or(|entry| entry.is_free) {
+                    self.xref.set(id as usize, XRefEntry::compressed(stream_id, index as u64));
+                }
+            }
+            self.object_streams.insert(stream_id, stream);
+        }
+    }
+
+    // Catches up with what was written to the file since it was read, see
+    // the top of the file. Edits made in memory are lost.
+    pub fn refresh(&mut self) -> Result<Refresh> {
+        let file = File::open(&self.path)?;
+        let length = file.metadata()?.len();
+        let appendable = self.edits.is_empty() && self.options.duplicates == DuplicatePolicy::PreferXRef;
+        match self.startxref {
+            Some(startxref) if appendable && length > self.length => {
+                let old = mem::replace(&mut self.file, file);
+                match self.read_appended(startxref) {
+                    Ok(Some(refresh)) => return Ok(refresh),
+                    Ok(None) | Err(_) => self.file = old,
+                }
+            },
+            _ => (),
+        }
+
+        let mut reopened = Document::open(&self.path, self.options.clone())?;
+        reopened.on_drop = self.on_drop.take();
+        reopened.observers = mem::take(&mut self.observers);
+        *self = reopened;
+        Ok(Refresh::Reopened)
+    }
+
+    // Reads the xref sections appended after the one at `startxref`, when
+    // they lead back to it and the file still ends the way it did where
+    // they start, and merges them in. None for anything that isn't an
+    // incremental update.
+    fn read_appended(&mut self, startxref: u64) -> Result<Option<Refresh>> {
+        let length = self.file.metadata()?.len();
+        // what we read is still there, ending where it ended
+        if startxref_before(&mut self.file, self.length)? != Some(startxref) {
+            return Ok(None);
+        }
+        let mut offset = match find_startxref(&mut self.file)? {
+            Some(offset) if offset >= self.length => offset,
+            _ => return Ok(None),
+        };
+
+        let (mut trailer, mut xref) = self.read_xref(offset)?;
+        let mut prev = int_entry(&trailer, "Prev");
+        // each update's section comes after the one before, so this ends
+        while prev != Some(startxref) {
+            offset = match prev {
+                Some(older) if older >= self.length && older < offset => older,
+                _ => return Ok(None),
+            };
+            let (older_trailer, older_xref) = self.read_xref(offset)?;
+            prev = int_entry(&older_trailer, "Prev");
+            xref.merge_older(older_xref);
+            merge_trailers(&mut trailer, older_trailer);
+        }
+        if trailer.get("Encrypt") != self.trailer.get("Encrypt") {
+            return Ok(None);
+        }
+
+        let changed: Vec<u64> = xref.entries().map(|(id, _)| id as u64).filter(|&id| id != 0).collect();
+        xref.merge_older(mem::replace(&mut self.xref, XRefTable::new(Vec::new())));
+        merge_trailers(&mut trailer, mem::replace(&mut self.trailer, DictNode::Null));
+        // objects read from an object stream that was replaced go with it
+        let stale = |id: u64| changed.contains(&id)
+            || xref.get(id as usize).and_then(|entry| entry.object_stream).is_some_and(|stream| changed.contains(&stream));
+        self.objects.retain(|&(id, _), _| !stale(id));
+        self.object_streams.retain(|&id, _| !stale(id));
+        self.xref = xref;
+        self.trailer = trailer;
+        self.startxref = find_startxref(&mut self.file)?;
+        self.length = length;
+        self.scanned = None;
+        self.index = None;
+        self.check_policy()?;
+        Ok(Some(Refresh::Appended(changed)))
+    }
+
+    pub fn policy(&self) -> &DocumentPolicy {
+        &self.options.policy
+    }
+
+    // The page tree and the fonts as the index cache has them, see the
+    // index_cache module. None when the document wasn't opened from an
+    // index, or the index didn't keep them, or edits may have changed them.
+    pub(crate) fn cached_pages(&self) -> Option<Vec<Leaf>> {
+        self.unedited_index().and_then(|index| index.pages.clone())
+    }
+
+    pub(crate) fn cached_fonts(&self) -> Option<Vec<(i64, i64)>> {
+        self.unedited_index().and_then(|index| index.fonts.clone())
+    }
+
+    fn unedited_index(&self) -> Option<&Index> {
+        self.index.as_ref().filter(|index| {
+            self.edits.is_empty() && index.trailer == self.trailer && index.xref == self.xref
+        })
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.trailer.get("Encrypt").is_some()
+    }
+
+    // Where the newest xref section is, what an incremental update's /Prev
+    // points at. None when the xref was rebuilt from a scan of the file.
+    pub fn startxref(&self) -> Option<u64> {
+        self.startxref
+    }
+
+    // How an encrypted document is decrypted, None for the others.
+    pub fn security(&self) -> Option<&SecurityHandler> {
+        self.security.as_ref()
+    }
+
+    // What the document allows its readers to do, everything if it isn't
+    // encrypted. See crypt::permissions.
+    pub fn permissions(&self) -> Permissions {
+        self.security.as_ref().map_or_else(Permissions::all, SecurityHandler::allowed)
+    }
+
+    // What can be checked up front: encryption, and the page count the
+    // page tree claims. page_tree checks the real count as it walks.
+    fn check_policy(&mut self) -> Result<()> {
+        if self.is_encrypted() {
+            if !self.options.policy.allow_encrypted {
+                return Err(PdfError::Policy("the document is encrypted".to_string()));
+            }
+            // before anything else is read, or it'd be cached encrypted
+            self.unlock()?;
+        }
+        if self.options.policy.max_pages.is_some() {
+            let pages = match self.trailer_entry("Root")? {
+                Some((_, DictNode::Dict(catalog))) => match catalog.get("Pages") {
+                    Some(pages) => self.resolve(pages)?,
+                    None => DictNode::Null,
+                },
+                _ => DictNode::Null,
+            };
+            if let DictNode::Dict(ref pages) = pages {
+                if let Some(&DictNode::Int(count)) = pages.get("Count") {
+                    self.options.policy.check_pages(count.max(0) as usize)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Sets up decryption with the password from the options, see crypt.
+    fn unlock(&mut self) -> Result<()> {
+        let (encrypt_id, encrypt) = match self.trailer_entry("Encrypt")? {
+            Some(encrypt) => encrypt,
+            None => return Ok(()),
+        };
+        let id = self.trailer.get("ID")
+            .and_then(DictNode::as_array)
+            .and_then(|ids| ids.first())
+            .and_then(DictNode::as_string)
+            .unwrap_or(&[])
+            .to_vec();
+        let password = self.options.password.clone().unwrap_or_default();
+        self.security = Some(SecurityHandler::new(&encrypt, encrypt_id, &id, password.as_bytes())?);
+        Ok(())
+    }
+
+    // Finds the xref through startxref, or rebuilds it by scanning the file
+    // when there isn't one, or it doesn't lead to an xref section, and
+    // we're lenient. The trailer is then the last one in the file, if any.
+    fn load_xref(&mut self) -> Result<()> {
+        let chain = match find_startxref(&mut self.file)? {
+            Some(offset) => match self.read_xref_chain(offset) {
+                Ok(chain) => Some((offset, chain)),
+                // hand-written files count their offsets by hand
+                Err(_) if self.options.lenient => None,
+                Err(err) => return Err(err),
+            },
+            None => None,
+        };
+        match chain {
+            Some((offset, (trailer, xref))) => {
+                self.startxref = Some(offset);
+                self.trailer = trailer;
+                self.xref = xref;
+                if self.options.duplicates != DuplicatePolicy::PreferXRef {
+                    self.resolve_duplicates()?;
+                } else if self.options.lenient {
+                    // the xref has the last word, but someone looking at a
+                    // broken file wants to know what it overrules
+                    self.scan_duplicates()?;
+                }
+            },
+            None if self.options.lenient => {
+                self.warn(Warning::TruncatedDocument);
+                self.resolve_duplicates()?;
+                let data = self.file_data()?;
+                if let Some(trailer) = last_trailer(&data).and_then(|bytes| parsers::cos::parse_bytes(bytes).ok()) {
+                    if trailer.as_dict().is_some() {
+                        self.trailer = trailer;
+                    }
+                }
+            },
+            None => return Err(PdfError::MissingStartXRef),
+        };
+        Ok(())
+    }
+
+    // Reads the newest xref section and then every older one its /Prev
+    // chain leads to. Incrementally updated files append a new section for
+    // each update, listing only what changed, so newer entries and trailer
+    // keys win over older ones.
+    fn read_xref_chain(&mut self, offset: u64) -> Result<(DictNode, XRefTable)> {
+        let (mut trailer, mut xref) = self.read_xref(offset)?;
+        let mut seen = vec![offset];
+        let mut prev = int_entry(&trailer, "Prev");
+        while let Some(offset) = prev {
+            if seen.contains(&offset) {
+                // a /Prev loop, we've read everything there is
+                break;
+            }
+            seen.push(offset);
+            let (older_trailer, older_xref) = match self.read_xref(offset) {
+                Ok(section) => section,
+                Err(_) if self.options.lenient => {
+                    self.warn(Warning::BrokenPrevChain { offset });
+                    break;
+                },
+                Err(err) => return Err(err),
+            };
+            prev = int_entry(&older_trailer, "Prev");
+            xref.merge_older(older_xref);
+            merge_trailers(&mut trailer, older_trailer);
+        }
+        Ok((trailer, xref))
+    }
+
+    // Reads a single xref section. That's either a classic "xref" table
+    // followed by the trailer, or since PDF 1.5 an xref stream whose
+    // dictionary doubles as the trailer.
+    fn read_xref(&mut self, offset: u64) -> Result<(DictNode, XRefTable)> {
+        let mut start = Vec::new();
+        self.file.seek(SeekFrom::Start(offset))?;
+        (&mut self.file).take(32).read_to_end(&mut start)?;
+        if !String::from_utf8_lossy(&start).trim_start().starts_with("xref") {
+            return self.read_xref_stream(offset);
+        }
+
+        let (trailer, mut xref) = parse_xref(&mut self.file, offset)?;
+        if let Some(stream_offset) = int_entry(&trailer, "XRefStm") {
+            match self.read_xref_stream(stream_offset) {
+                Ok((_, stream)) => xref.merge_hybrid(stream),
+                Err(_) if self.options.lenient => {
+                    self.warn(Warning::BrokenXRefStm { offset: stream_offset });
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((trailer, xref))
+    }
+
+    fn read_xref_stream(&mut self, offset: u64) -> Result<(DictNode, XRefTable)> {
+        match self.raw_stream(&XRefEntry::in_use(offset, 0))? {
+            Some((dict, raw)) if has_type(&dict, "XRef") => {
+                let data = self.timings.time(Phase::Decode, || streams::decode(&dict, &raw))?;
+                self.count_decoded(data.len());
+                let xref = parsers::xref::parse_stream(&dict, &data)?;
+                Ok((dict, xref))
+            },
+            _ => Err(PdfError::Syntax { what: "xref", position: offset as usize }),
+        }
+    }
+
+    // Time spent in each phase so far. Callers doing their own work on the
+    // document (extracting text, writing files) can add it through
+    // `timings_mut` to get the full picture.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub fn timings_mut(&mut self) -> &mut Timings {
+        &mut self.timings
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        self.metrics.warning(warning.category());
+        self.warnings.push(warning);
+    }
+
+    // Counts so far, see the metrics module.
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = self.metrics.clone();
+        metrics.bytes_decoded += self.streamed.load(Ordering::Relaxed);
+        metrics
+    }
+
+    // Called with the final counts when the document is dropped.
+    pub fn set_metrics_callback<F: FnMut(&Metrics) + Send + 'static>(&mut self, callback: F) {
+        self.on_drop = Some(Box::new(callback));
+    }
+
+    fn count_decoded(&mut self, bytes: usize) {
+        self.metrics.streams_decoded += 1;
+        self.metrics.bytes_decoded += bytes as u64;
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.warnings.iter().any(|w| matches!(*w, Warning::TruncatedDocument | Warning::TruncatedObject { .. }))
+    }
+
+    // All definitions of object `id` found in the file, oldest first,
+    // regardless of which one the xref table ended up pointing at.
+    pub fn object_versions(&mut self, id: u64) -> Result<Vec<XRefEntry>> {
+        Ok(self.scan()?.iter()
+            .filter(|&&(object_id, _, _)| object_id == id)
+            .map(|&(_, generation, offset)| {
+                XRefEntry::in_use(offset, generation)
+            })
+            .collect())
+    }
+
+    // Scans the whole file for object headers, which records a
+    // DuplicateObject warning for every object defined more than once.
+    pub fn scan_duplicates(&mut self) -> Result<()> {
+        self.scan().map(|_| ())
+    }
+
+    // The version in the file's "%PDF-M.m" header, if it has one.
+    pub(crate) fn header_version(&mut self) -> Result<Option<(u8, u8)>> {
+        let mut header = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        (&mut self.file).take(8).read_to_end(&mut header)?;
+        Ok(transcode::header_version(&header))
+    }
+
+    // The whole file as it is on disk, for what has to look at the bytes
+    // themselves rather than the objects in them.
+    pub(crate) fn file_data(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn scan(&mut self) -> Result<&Vec<(u64, u64, u64)>> {
+        let objects = match self.scanned.take() {
+            Some(objects) => objects,
+            None => {
+                let data = self.file_data()?;
+                let objects = scan_objects(&data);
+                let mut offsets: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+                for &(id, _, offset) in &objects {
+                    offsets.entry(id).or_default().push(offset);
+                }
+                for (id, offsets) in offsets {
+                    if offsets.len() > 1 {
+                        self.warn(Warning::DuplicateObject { id, offsets });
+                    }
+                }
+                objects
+            }
+        };
+        Ok(self.scanned.get_or_insert(objects))
+    }
+
+    // Points the xref at the definition picked by the duplicate policy for
+    // every object defined more than once. With no xref table at all this
+    // rebuilds it from scratch.
+    fn resolve_duplicates(&mut self) -> Result<()> {
+        let policy = self.options.duplicates;
+        let mut versions: BTreeMap<u64, Vec<XRefEntry>> = BTreeMap::new();
+        for &(id, generation, offset) in self.scan()? {
+            versions.entry(id).or_default().push(
+                XRefEntry::in_use(offset, generation));
+        }
+
+        for (id, versions) in versions {
+            let known = self.xref.get(id as usize).is_some_and(|entry| !entry.is_free);
+            if let Some(chosen) = pick_version(policy, known, versions) {
+                self.xref.set(id as usize, chosen);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn object(&mut self, xref_entry: &XRefEntry) -> Result<DictNode> {
+        self.read_object(xref_entry).map(|(object, _)| object)
+    }
+
+    // The trailer's `key` entry, following it if it's a reference, along
+    // with the object number it came from.
+    pub fn trailer_entry(&mut self, key: &str) -> Result<Option<(Option<u64>, DictNode)>> {
+        let value = match self.trailer {
+            DictNode::Dict(ref map) => map.get(key).cloned(),
+            _ => None,
+        };
+        match value {
+            Some(DictNode::ObjectReference(id, _)) if id >= 0 => {
+                Ok(self.cached_object(id as u64)?.map(|object| (Some(id as u64), object.clone())))
+            },
+            Some(value) => Ok(Some((None, value))),
+            None => Ok(None),
+        }
+    }
+
+    // Object number `id`, whatever its generation, parsed on first use and
+    // kept around after that. None if the xref has no such object.
+    pub fn cached_object(&mut self, id: u64) -> Result<Option<&DictNode>> {
+        if self.edits.get(id).is_some() {
+            return Ok(self.edited(id));
+        }
+        let generation = match self.xref.get(id as usize) {
+            Some(entry) if !entry.is_free => entry.generation_id,
+            _ => return Ok(None),
+        };
+        self.cached_version(id, generation)
+    }
+
+    // Object `id` at `generation`, parsed on first use and kept around after
+    // that. None if the xref has no such object in use, or has it at
+    // another generation. Lenient mode lets that last one slide, broken
+    // writers get generations wrong.
+    fn cached_version(&mut self, id: u64, generation: u64) -> Result<Option<&DictNode>> {
+        // edits replace an object whatever generation it had
+        if self.edits.get(id).is_some() {
+            return Ok(self.edited(id));
+        }
+        if self.objects.contains_key(&(id, generation)) {
+            self.metrics.cache_hits += 1;
+            return Ok(self.objects.get(&(id, generation)));
+        }
+        self.metrics.cache_misses += 1;
+        let entry = match self.xref.get(id as usize) {
+            Some(entry) if !entry.is_free && (entry.generation_id == generation || self.options.lenient) => entry.clone(),
+            _ => return Ok(None),
+        };
+        let object = self.object(&entry)?;
+        Ok(Some(self.objects.entry((id, generation)).or_insert(object)))
+    }
+
+    fn edited(&self, id: u64) -> Option<&DictNode> {
+        self.edits.get(id).flatten().map(|edited| &edited.object)
+    }
+
+    // What `node` points at when it's a reference, following references to
+    // references, and `node` itself otherwise. References to missing or
+    // free objects (and ones that go round in circles) are null, the way
+    // the spec says to read them.
+    pub fn resolve(&mut self, node: &DictNode) -> Result<DictNode> {
+        let mut node = node.clone();
+        let mut seen = Vec::new();
+        while let DictNode::ObjectReference(id, generation) = node {
+            if id < 0 || generation < 0 || seen.contains(&(id, generation)) {
+                return Ok(DictNode::Null);
+            }
+            seen.push((id, generation));
+            node = self.cached_version(id as u64, generation as u64)?.cloned().unwrap_or(DictNode::Null);
+        }
+        Ok(node)
+    }
+
+    // Same as `resolve`, but references nested inside arrays and
+    // dictionaries are replaced by what they point at too, all the way
+    // down. A reference back to an object being expanded (a page's
+    // /Parent, say) is left as it is, otherwise there'd be no end to it.
+    // Nesting more than cos::MAX_DEPTH deep, references included, is an
+    // error.
+    pub fn resolve_all(&mut self, node: &DictNode) -> Result<DictNode> {
+        let (expanded, _) = self.expand(node, &mut Vec::new(), &mut HashMap::new(), 0)?;
+        Ok(expanded)
+    }
+
+    // Also says whether a reference was left in. Objects expanded without
+    // leaving one come out the same wherever they're referenced from, so
+    // they're kept in `expanded` and each is only expanded once, however
+    // many objects share it.
+    fn expand(&mut self, node: &DictNode, path: &mut Vec<(i64, i64)>,
+              expanded: &mut HashMap<(i64, i64), DictNode>, depth: usize) -> Result<(DictNode, bool)> {
+        if depth > cos::MAX_DEPTH {
+            return Err(PdfError::Syntax { what: "object nesting", position: 0 });
+        }
+        match *node {
+            DictNode::ObjectReference(id, generation) => {
+                if path.contains(&(id, generation)) {
+                    return Ok((node.clone(), true));
+                }
+                if let Some(object) = expanded.get(&(id, generation)) {
+                    return Ok((object.clone(), false));
+                }
+                let target = self.resolve(node)?;
+                path.push((id, generation));
+                let result = self.expand(&target, path, expanded, depth + 1);
+                path.pop();
+                let (object, cut) = result?;
+                if !cut {
+                    expanded.insert((id, generation), object.clone());
+                }
+                Ok((object, cut))
+            },
+            DictNode::Array(ref items) => {
+                let mut array = Vec::with_capacity(items.len());
+                let mut cut = false;
+                for item in items {
+                    let (item, item_cut) = self.expand(item, path, expanded, depth + 1)?;
+                    array.push(item);
+                    cut |= item_cut;
+                }
+                Ok((DictNode::Array(array), cut))
+            },
+            DictNode::Dict(ref map) => {
+                let mut dict = HashMap::with_capacity(map.len());
+                let mut cut = false;
+                for (key, value) in map {
+                    let (value, value_cut) = self.expand(value, path, expanded, depth + 1)?;
+                    dict.insert(key.clone(), value);
+                    cut |= value_cut;
+                }
+                Ok((DictNode::Dict(dict), cut))
+            },
+            ref node => Ok((node.clone(), false)),
+        }
+    }
+
+    // The object's dictionary and its decoded stream data, or None if the
+    // object isn't a stream.
+    pub fn stream(&mut self, xref_entry: &XRefEntry) -> Result<Option<(DictNode, Vec<u8>)>> {
+        let (dict, raw) = match self.raw_stream(xref_entry)? {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        self.options.policy.check_decode(&dict)?;
+        let data = self.timings.time(Phase::Decode, || streams::decode(&dict, &raw))?;
+        self.count_decoded(data.len());
+        Ok(Some((dict, data)))
+    }
+
+    // Same as `stream`, for the object a reference points at. Anything but
+    // a reference to a stream is None.
+    pub fn referenced_stream(&mut self, node: &DictNode) -> Result<Option<(DictNode, Vec<u8>)>> {
+        if let DictNode::ObjectReference(id, _) = *node {
+            if let Some(edited) = self.edits.get(id as u64) {
+                let (dict, raw) = match edited {
+                    Some(&EditedObject { ref object, data: Some(ref data) }) => (object.clone(), data.clone()),
+                    _ => return Ok(None),
+                };
+                self.options.policy.check_decode(&dict)?;
+                let data = streams::decode(&dict, &raw)?;
+                return Ok(Some((dict, data)));
+            }
+        }
+        let entry = match *node {
+            DictNode::ObjectReference(id, _) if id >= 0 => match self.xref.get(id as usize) {
+                Some(entry) if !entry.is_free => entry.clone(),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.stream(&entry)
+    }
+
+    // What it takes to turn this document into `other`, object by object,
+    // see the patch module.
+    pub fn diff(&mut self, other: &mut Document) -> Result<Vec<PatchOp>> {
+        patch::diff(self, other)
+    }
+
+    // The text of a page, see the text module.
+    pub fn extract_text(&mut self, page: &Page) -> Result<String> {
+        text::extract(self, page)
+    }
+
+    // The still encoded data of a JPEG/JPEG2000 image, ready to be written
+    // out as is. None for any other kind of object.
+    pub fn encoded_image(&mut self, xref_entry: &XRefEntry) -> Result<Option<EncodedImage>> {
+        match self.raw_stream(xref_entry)? {
+            Some((dict, raw)) => self.timings.time(Phase::Decode, || filters::encoded_image(&dict, &raw)),
+            None => Ok(None),
+        }
+    }
+
+    // The raw, still encoded streams among `entries` as jobs for
+    // `pipeline::decode_parallel`, read from disk lazily as they're pulled.
+    // Entries that aren't streams are skipped.
+    pub fn stream_jobs<'a>(&'a mut self, entries: &'a [(u64, XRefEntry)]) -> impl Iterator<Item = Result<Job>> + 'a {
+        entries.iter()
+            .filter_map(move |&(id, ref entry)| match self.raw_stream(entry) {
+                Ok(Some((dict, raw))) => match self.options.policy.check_decode(&dict) {
+                    Ok(()) => Some(Ok((id, dict, raw))),
+                    Err(err) => Some(Err(err)),
+                },
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .enumerate()
+            .map(|(index, stream)| stream.map(|(id, dict, raw)| Job { index, id, dict, raw }))
+    }
+
+    // Same as `stream`, but the data is decoded on the fly as it's read, for
+    // streams too big to comfortably hold in memory.
+    pub fn stream_reader(&mut self, xref_entry: &XRefEntry) -> Result<Option<(DictNode, StreamReader<'_>)>> {
+        let (dict, start) = match self.read_object(xref_entry)? {
+            (dict, Some(start)) => (dict, start),
+            (_, None) => return Ok(None),
+        };
+        self.options.policy.check_decode(&dict)?;
+        self.metrics.streams_decoded += 1;
+        let streamed = self.streamed.clone();
+        if let Some((dict, raw)) = self.external_stream(&dict)? {
+            let reader = streams::decoder(&dict, Cursor::new(raw))?;
+            return Ok(Some((dict, Box::new(CountingReader::new(reader, streamed)))));
+        }
+
+        let raw: Box<dyn Read> = match self.stream_length(&dict, start.offset)? {
+            // RC4 could be streamed too, but encrypted documents are rare
+            // enough to decrypt in one go
+            _ if self.security.is_some() => Box::new(Cursor::new(self.read_raw(&dict, start)?)),
+            Some(length) => {
+                self.file.seek(SeekFrom::Start(start.offset))?;
+                Box::new((&mut self.file).take(length))
+            },
+            None => Box::new(Cursor::new(self.read_until_endstream(start.offset)?)),
+        };
+        let reader = streams::decoder(&dict, raw)?;
+        Ok(Some((dict, Box::new(CountingReader::new(reader, streamed)))))
+    }
+
+    // Tokenizes a content stream as it's decoded, without ever holding the
+    // whole decoded stream in memory.
+    pub fn content_tokens(&mut self, xref_entry: &XRefEntry) -> Result<Option<Tokenizer<BufReader<StreamReader<'_>>>>> {
+        Ok(self.stream_reader(xref_entry)?.map(|(_, reader)| Tokenizer::new(BufReader::new(reader))))
+    }
+
+    // Same as content_tokens, grouped into operations.
+    pub fn content_operations(&mut self, xref_entry: &XRefEntry) -> Result<Option<Operations<BufReader<StreamReader<'_>>>>> {
+        Ok(self.content_tokens(xref_entry)?.map(Operations::from_tokens))
+    }
+
+    // Decodes the whole stream into a buffer that moves to a temporary file
+    // once it outgrows `Options::spill_threshold`.
+    pub fn decoded_stream(&mut self, xref_entry: &XRefEntry) -> Result<Option<(DictNode, SpillBuffer)>> {
+        let threshold = self.options.spill_threshold;
+        let (dict, mut reader) = match self.stream_reader(xref_entry)? {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        let start = Instant::now();
+        let buffer = SpillBuffer::from_reader(&mut reader, threshold);
+        drop(reader);
+        self.timings.add(Phase::Decode, start.elapsed());
+        Ok(Some((dict, buffer?)))
+    }
+
+    fn raw_stream(&mut self, xref_entry: &XRefEntry) -> Result<Option<(DictNode, Vec<u8>)>> {
+        match self.raw_object(xref_entry)? {
+            (dict, Some(raw)) => Ok(Some((dict, raw))),
+            (_, None) => Ok(None),
+        }
+    }
+
+    // The object, along with its raw data if it's a stream: what it takes
+    // to copy it into another file as it is.
+    pub(crate) fn raw_object(&mut self, xref_entry: &XRefEntry) -> Result<(DictNode, Option<Vec<u8>>)> {
+        let (dict, start) = match self.read_object(xref_entry)? {
+            (dict, Some(start)) => (dict, start),
+            (object, None) => return Ok((object, None)),
+        };
+        if let Some((dict, raw)) = self.external_stream(&dict)? {
+            return Ok((dict, Some(raw)));
+        }
+        let raw = self.read_raw(&dict, start)?;
+        Ok((dict, Some(raw)))
+    }
+
+    // The data of the stream with dictionary `dict`, decrypted if the
+    // document is encrypted but not decoded.
+    fn read_raw(&mut self, dict: &DictNode, start: StreamStart) -> Result<Vec<u8>> {
+        let raw = match self.stream_length(dict, start.offset)? {
+            Some(length) => {
+                let mut raw = Vec::new();
+                self.file.seek(SeekFrom::Start(start.offset))?;
+                (&mut self.file).take(length).read_to_end(&mut raw)?;
+                raw
+            },
+            None => self.read_until_endstream(start.offset)?,
+        };
+        Ok(match self.security {
+            Some(ref security) => security.decrypt_stream(start.id, start.generation, dict, &raw),
+            None => raw,
+        })
+    }
+
+    // Streams with an /F keep their data in another file, which the policy
+    // has to allow reading. The data is decoded with /FFilter and
+    // /FDecodeParms, so the dictionary handed back has those moved to
+    // /Filter and /DecodeParms.
+    fn external_stream(&mut self, dict: &DictNode) -> Result<Option<(DictNode, Vec<u8>)>> {
+        let map = match *dict {
+            DictNode::Dict(ref map) => map,
+            _ => return Ok(None),
+        };
+        let spec = match map.get("F") {
+            Some(spec) => self.resolve(spec)?,
+            None => return Ok(None),
+        };
+        if !self.options.policy.follow_external_references {
+            return Err(PdfError::Policy("following external stream data".to_string()));
+        }
+        let name = match file_name(&spec) {
+            Some(name) => name,
+            None => return Err(PdfError::Syntax { what: "external stream file specification", position: 0 }),
+        };
+        // only plain relative paths, nothing that climbs out of the
+        // document's directory
+        let relative = Path::new(&name);
+        let inside = relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(PdfError::Policy(format!("external stream data outside the document's directory ({})", name)));
+        }
+        let raw = fs::read(self.directory.join(relative))?;
+
+        let mut decoding = map.clone();
+        decoding.remove("Filter");
+        decoding.remove("DecodeParms");
+        if let Some(filter) = decoding.remove("FFilter") {
+            decoding.insert("Filter".to_string(), filter);
+        }
+        if let Some(parms) = decoding.remove("FDecodeParms") {
+            decoding.insert("DecodeParms".to_string(), parms);
+        }
+        Ok(Some((DictNode::Dict(decoding), raw)))
+    }
+
+    // The stream's /Length, following it when it's a reference to an integer
+    // object (writers that stream out the data before they know its size do
+    // that). None means the length has to be found by looking for endstream:
+    // no usable /Length, or one that runs past the end of the file.
+    fn stream_length(&mut self, dict: &DictNode, start: u64) -> Result<Option<u64>> {
+        let length = match *dict {
+            DictNode::Dict(ref map) => match map.get("Length") {
+                // an object stream's length can't live in an object stream,
+                // reading it from there would need the stream we're reading
+                Some(&DictNode::ObjectReference(id, _)) if id >= 0 && !self.length_in_object_stream(map, id as u64) => match self.cached_object(id as u64) {
+                    Ok(Some(&DictNode::Int(length))) if length >= 0 => Some(length as u64),
+                    _ => None,
+                },
+                _ => streams::length(dict),
+            },
+            _ => None,
+        };
+        let file_len = self.file.seek(SeekFrom::End(0))?;
+        Ok(length.filter(|&length| start.saturating_add(length) <= file_len))
+    }
+
+    fn length_in_object_stream(&self, map: &HashMap<String, DictNode>, id: u64) -> bool {
+        map.get("Type") == Some(&DictNode::name("ObjStm"))
+            && self.xref.get(id as usize).is_some_and(|entry| entry.object_stream.is_some())
+    }
+
+    fn read_until_endstream(&mut self, start: u64) -> Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(start))?;
+        let mut raw = Vec::new();
+        loop {
+            let read = (&mut self.file).take(CHUNK_SIZE as u64).read_to_end(&mut raw)?;
+            if let Some(length) = streams::raw_body(&raw).map(|body| body.len()) {
+                raw.truncate(length);
+                break;
+            }
+            if (read as i64) < CHUNK_SIZE {
+                break;
+            }
+        }
+        Ok(raw)
+    }
+
+    // Object number `index` within object stream `stream_id`.
+    fn compressed_object(&mut self, stream_id: u64, index: u64) -> Result<DictNode> {
+        if !self.object_streams.contains_key(&stream_id) {
+            let entry = match self.xref.get(stream_id as usize) {
+                // an object stream inside an object stream would send us
+                // round in circles
+                Some(entry) if !entry.is_free && entry.object_stream.is_none() => entry.clone(),
+                _ => return Err(PdfError::Syntax { what: "object stream reference", position: stream_id as usize }),
+            };
+            let (dict, data) = match self.stream(&entry)? {
+                Some(stream) => stream,
+                None => return Err(PdfError::Syntax { what: "object stream", position: entry.offset as usize }),
+            };
+            self.object_streams.insert(stream_id, ObjectStream::new(&dict, data)?);
+        }
+        let stream = &self.object_streams[&stream_id];
+        let object = self.timings.time(Phase::Parse, || stream.object(index as usize))?;
+        self.metrics.objects_parsed += 1;
+        Ok(object)
+    }
+
+    // Reads the object's dictionary, along with the file offset its stream
+    // data starts at if it has any.
+    fn read_object(&mut self, xref_entry: &XRefEntry) -> Result<(DictNode, Option<StreamStart>)> {
+        if let Some(stream_id) = xref_entry.object_stream {
+            // objects in object streams can't be streams themselves
+            return Ok((self.compressed_object(stream_id, xref_entry.offset)?, None));
+        }
+        let start = Instant::now();
+        let object = self.parse_object(xref_entry);
+        self.timings.add(Phase::Parse, start.elapsed());
+        if object.is_ok() {
+            self.metrics.objects_parsed += 1;
+        }
+        object
+    }
+
+    fn parse_object(&mut self, xref_entry: &XRefEntry) -> Result<(DictNode, Option<StreamStart>)> {
+        let offset = xref_entry.offset;
+        self.file.seek(SeekFrom::Start(offset))?;
+
+        // objects can be bigger than a chunk, keep reading until we've seen
+        // the end of the dictionary or run out of file
+        let mut file_buffer = Vec::new();
+        let scan = loop {
+            let read = (&mut self.file).take(CHUNK_SIZE as u64).read_to_end(&mut file_buffer)?;
+            let scan = parsers::object::scan(&file_buffer)?;
+            if scan.terminator != Terminator::End || (read as i64) < CHUNK_SIZE {
+                break scan;
+            }
+        };
+
+        let body = &file_buffer[scan.body.clone()];
+        let stream_start = match scan.terminator {
+            Terminator::Stream { data_start } => Some(offset + data_start as u64),
+            Terminator::Endobj => None,
+            Terminator::NextObject => {
+                if !self.options.lenient {
+                    return Err(PdfError::MissingEndobj { offset });
+                }
+                self.warn(Warning::MissingEndobj { offset });
+                None
+            },
+            Terminator::End => {
+                if !self.options.lenient {
+                    return Err(PdfError::TruncatedObject { offset });
+                }
+                self.warn(Warning::TruncatedObject { offset });
+                let object = salvage(&parsers::cos::latin1(body));
+                return Ok((self.decrypt_strings(&scan, object), None));
+            },
+        };
+        let object = parsers::cos::parse_bytes(body)?;
+        let stream_start = stream_start.map(|offset| StreamStart { offset, id: scan.id, generation: scan.generation });
+        Ok((self.decrypt_strings(&scan, object), stream_start))
+    }
+
+    fn decrypt_strings(&self, scan: &Scan, object: DictNode) -> DictNode {
+        match self.security {
+            Some(ref security) => security.decrypt_strings(scan.id, scan.generation, object),
+            None => object,
+        }
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        if let Some(mut callback) = self.on_drop.take() {
+            callback(&self.metrics());
+        }
+    }
+}
+
+// Reads a classic xref table and the trailer dictionary that follows it.
+fn parse_xref(file: &mut File, offset: u64) -> Result<(DictNode, XRefTable)> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    // the trailer's strings (/ID especially) are often binary, read lines
+    // as bytes
+    let mut file_reader = BufReader::new(file);
+    let mut next_line = move || -> Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        if file_reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        while line.last().is_some_and(|&byte| byte == b'\n' || byte == b'\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    };
+
+    let mut xref_str = String::new();
+    let mut trailer_bytes = Vec::new();
+    while let Some(line) = next_line()? {
+        // hand-written files put the dictionary on the same line
+        if let Some(rest) = line.strip_prefix(b"trailer") {
+            xref_str.push_str("trailer\n");
+            trailer_bytes.extend_from_slice(rest);
+            trailer_bytes.push(b'\n');
+            break;
+        }
+        xref_str.push_str(&String::from_utf8_lossy(&line));
+        xref_str.push('\n');
+    }
+
+    while let Some(line) = next_line()? {
+        if line.starts_with(b"startxref") || line.starts_with(b"%%EOF") {
+            break;
+        }
+        trailer_bytes.extend_from_slice(&line);
+        trailer_bytes.push(b'\n');
+    }
+
+    let xref = parsers::xref::parse(&xref_str)?;
+    let trailer = parsers::cos::parse_bytes(&trailer_bytes)?;
+    Ok((trailer, xref))
+}
+
+// The path in a file specification, a string or a dictionary with /UF or
+// /F. PDF paths always use '/'. /UF is a text string, /F bytes in no
+// particular encoding, which is UTF-8 more often than not.
+pub(crate) fn file_name(spec: &DictNode) -> Option<String> {
+    match *spec {
+        DictNode::LiteralString(ref bytes) | DictNode::HexString(ref bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        DictNode::Dict(ref map) => map.get("UF").and_then(DictNode::as_text).or_else(|| map.get("F").and_then(file_name)),
+        _ => None,
+    }
+}
+
+// The offset of the newest xref section. Returns None when the tail of the
+// file has no startxref, which is what a truncated download looks like.
+fn find_startxref(file: &mut File) -> Result<Option<u64>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    startxref_before(file, len)
+}
+
+// The same for the file as it was when it was `len` bytes long.
+fn startxref_before(file: &mut File, len: u64) -> Result<Option<u64>> {
+    // files shorter than a chunk are read whole, seeking before the start
+    // is an error
+    let mut buffer = Vec::new();
+    file.seek(SeekFrom::Start(len.saturating_sub(CHUNK_SIZE as u64)))?;
+    file.take(len.min(CHUNK_SIZE as u64)).read_to_end(&mut buffer)?;
+    Ok(last_startxref(&buffer))
+}
+
+// The offset after the last "startxref" in the tail of a file. It's a u64
+// all the way: past 4 GB only xref streams can point, but they can.
+fn last_startxref(tail: &[u8]) -> Option<u64> {
+    // incremental updates each end with their own startxref, the last one
+    // is the one that counts. Lines can end in \r alone, and hand-written
+    // files put the offset on the same line.
+    let mut words = tail.split(|byte| byte.is_ascii_whitespace()).filter(|word| !word.is_empty());
+    let mut xref_offset = None;
+    while let Some(word) = words.next() {
+        if word == b"startxref" {
+            xref_offset = words.next()
+                .and_then(|word| ::std::str::from_utf8(word).ok())
+                .and_then(|word| word.parse::<u64>().ok());
+        }
+    }
+    xref_offset
+}
+
+// The bytes of the last trailer dictionary in `data`, for files whose
+// xref can't be used.
+fn last_trailer(data: &[u8]) -> Option<&[u8]> {
+    let start = data.windows(7).rposition(|window| window == b"trailer")? + 7;
+    let rest = &data[start..];
+    let end = [&b"startxref"[..], b"%%EOF"].iter()
+        .filter_map(|keyword| streams::find(rest, keyword))
+        .min()
+        .unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+fn int_entry(dict: &DictNode, key: &str) -> Option<u64> {
+    match *dict {
+        DictNode::Dict(ref map) => match map.get(key) {
+            Some(&DictNode::Int(value)) if value >= 0 => Some(value as u64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Adds the keys of an older trailer that the newer one doesn't override.
+fn merge_trailers(newer: &mut DictNode, older: DictNode) {
+    if let (&mut DictNode::Dict(ref mut newer), DictNode::Dict(older)) = (newer, older) {
+        for (key, value) in older {
+            newer.entry(key).or_insert(value);
+        }
+    }
+}
+
+// Where an object is in the file, for putting them in file order: its
+// offset, or its object stream's and its index in there.
+fn file_position(xref: &XRefTable, entry: &XRefEntry) -> (u64, u64) {
+    match entry.object_stream {
+        Some(stream) => (xref.get(stream as usize).map_or(0, |stream| stream.offset), entry.offset + 1),
+        None => (entry.offset, 0),
+    }
+}
+
+// How many pages the document's page tree leads to, 0 if it's unreadable.
+fn page_count(doc: &mut Document) -> usize {
+    pages::pages(doc).map_or(0, |pages| pages.len())
+}
+
+// A /Pages node with `kids` as its pages.
+fn page_tree(kids: Vec<DictNode>) -> DictNode {
+    DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Pages")),
+        ("Count".to_string(), DictNode::Int(kids.len() as i64)),
+        ("Kids".to_string(), DictNode::Array(kids)),
+    ]))
+}
+
+fn has_type(dict: &DictNode, name: &str) -> bool {
+    match *dict {
+        DictNode::Dict(ref map) => map.get("Type").is_some_and(|kind| kind.is_name(name)),
+        _ => false,
+    }
+}
+
+// Parses "12 0 obj" at the start of a line into (12, 0).
+pub(crate) fn object_header(line: &[u8]) -> Option<(u64, u64)> {
+    let text = match ::std::str::from_utf8(line) {
+        Ok(text) => text,
+        Err(_) => return None,
+    };
+    let mut parts = text.split_whitespace();
+    let id = parts.next().and_then(|p| p.parse::<u64>().ok());
+    let generation = parts.next().and_then(|p| p.parse::<u64>().ok());
+    match (id, generation, parts.next()) {
+        (Some(id), Some(generation), Some(keyword)) if keyword.starts_with("obj") => Some((id, generation)),
+        _ => None,
+    }
+}
+
+// Finds every "N G obj" header in the file, returning (id, generation, offset)
+// in file order. Lines can end in \r alone.
+pub(crate) fn scan_objects(data: &[u8]) -> Vec<(u64, u64, u64)> {
+    let mut objects = Vec::new();
+    let mut offset = 0;
+    for line in data.split(|byte| *byte == b'\n' || *byte == b'\r') {
+        if let Some((id, generation)) = object_header(line) {
+            objects.push((id, generation, offset as u64));
+        }
+        offset += line.len() + 1;
+    }
+    objects
+}
+
+// Picks one of the scanned definitions of an object, or None to leave the
+// xref entry alone. `known` is whether the xref table already has it in use.
+fn pick_version(policy: DuplicatePolicy, known: bool, mut versions: Vec<XRefEntry>) -> Option<XRefEntry> {
+    if versions.is_empty() || (known && (versions.len() == 1 || policy == DuplicatePolicy::PreferXRef)) {
+        return None;
+    }
+    match policy {
+        DuplicatePolicy::FirstWins => Some(versions.remove(0)),
+        DuplicatePolicy::LastWins | DuplicatePolicy::PreferXRef => versions.pop(),
+    }
+}
+
+// Closes whatever strings, arrays and dictionaries were left open when the
+// text was cut off, so "<< /Kids [ 1 0 R 2 0" becomes "<< /Kids [ 1 0 R 2 0]>>".
+fn close_delimiters(text: &str) -> String {
+    let mut closers = Vec::new();
+    let mut in_string = 0;
+    let mut in_hex = false;
+    let mut escaped = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_string > 0 {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '(' => in_string += 1,
+                ')' => in_string -= 1,
+                _ => (),
+            }
+            continue;
+        }
+        if in_hex {
+            in_hex = c != '>';
+            continue;
+        }
+        match c {
+            '(' => in_string = 1,
+            '[' => closers.push("]"),
+            ']' => { closers.pop(); },
+            '<' if chars.peek() == Some(&'<') => {
+                chars.next();
+                closers.push(">>");
+            },
+            '<' => in_hex = true,
+            '>' if chars.peek() == Some(&'>') => {
+                chars.next();
+                closers.pop();
+            },
+            _ => (),
+        }
+    }
+
+    let mut closed = text.to_owned();
+    for _ in 0..in_string {
+        closed.push(')');
+    }
+    if in_hex {
+        closed.push('>');
+    }
+    while let Some(closer) = closers.pop() {
+        closed.push_str(closer);
+    }
+    closed
+}
+
+// Drops the last token of a cut off object, e.g. the dangling "0" in
+// "/Length 5 0" which would otherwise be parsed as a key.
+fn drop_last_token(text: &str) -> &str {
+    let text = text.trim_end();
+    match text.rfind(|c: char| c.is_whitespace() || "/[<(".contains(c)) {
+        Some(index) => &text[..index],
+        None => "",
+    }
+}
+
+// Salvages as much of a truncated object as the grammar will accept.
+fn salvage(text: &str) -> DictNode {
+    let mut candidate = text;
+    while !candidate.trim().is_empty() {
+        if let Ok(node) = parsers::cos::parse(&close_delimiters(candidate)) {
+            return node;
+        }
+        candidate = drop_last_token(candidate);
+    }
+    DictNode::Dict(Default::default())
+}
+
+#[test]
+fn test_last_startxref() {
+    assert_eq!(last_startxref(b"trailer\n<< >>\nstartxref\n116\n%%EOF\n"), Some(116));
+    // past 2^32, from an xref stream
+    assert_eq!(last_startxref(b"endobj\nstartxref\n5000000000\n%%EOF"), Some(5_000_000_000));
+    // an update after the original end of file
+    assert_eq!(last_startxref(b"startxref\n116\n%%EOF\n1 0 obj\nstartxref\r\n900\r\n%%EOF\r\n"), Some(900));
+    assert_eq!(last_startxref(b"%PDF-1.4\n"), None);
+    // old Mac line endings, and the offset on the same line
+    assert_eq!(last_startxref(b"trailer\r<< >>\rstartxref\r173\r%%EOF\r"), Some(173));
+    assert_eq!(last_startxref(b">>\nstartxref 173\n%%EOF"), Some(173));
+    assert_eq!(last_startxref(b"startxref\n%%EOF\n"), None);
+}
+
+#[test]
+fn test_last_trailer() {
+    let file = b"%PDF-1.1\n1 0 obj<</Type/Catalog>>endobj\ntrailer<</Root 1 0 R>>\n%%EOF";
+    assert_eq!(last_trailer(file), Some(&b"<</Root 1 0 R>>\n"[..]));
+    let updated = b"trailer\n<< /Size 3 >>\nstartxref\n9\n%%EOF\ntrailer\n<< /Size 4 /Prev 9 >>\nstartxref\n99\n%%EOF\n";
+    assert_eq!(last_trailer(updated), Some(&b"\n<< /Size 4 /Prev 9 >>\n"[..]));
+    assert_eq!(last_trailer(b"1 0 obj null endobj"), None);
+}
+
+#[test]
+fn test_object_header() {
+    assert_eq!(object_header(b"12 0 obj"), Some((12, 0)));
+    assert_eq!(object_header(b"7 1 obj<</Type/Page>>"), Some((7, 1)));
+    assert_eq!(object_header(b"/Length 5 0 R"), None);
+    assert_eq!(object_header(b"endobj"), None);
+}
+
+#[test]
+fn test_scan_objects() {
+    let data = b"%PDF-1.4\n1 0 obj\n<< >>\nendobj\n2 0 obj\n[ 1 2";
+    assert_eq!(scan_objects(data), vec![(1, 0, 9), (2, 0, 30)]);
+    let data = b"%PDF-1.4\r1 0 obj\r<< >>\rendobj\r\n2 0 obj\r\n[ 1 2";
+    assert_eq!(scan_objects(data), vec![(1, 0, 9), (2, 0, 31)]);
+}
+
+#[test]
+fn test_pick_version() {
+    let first = XRefEntry::in_use(10, 0);
+    let last = XRefEntry::in_use(90, 0);
+    let both = vec![first.clone(), last.clone()];
+
+    assert_eq!(pick_version(DuplicatePolicy::FirstWins, true, both.clone()), Some(first.clone()));
+    assert_eq!(pick_version(DuplicatePolicy::LastWins, true, both.clone()), Some(last.clone()));
+    assert_eq!(pick_version(DuplicatePolicy::PreferXRef, true, both.clone()), None);
+    assert_eq!(pick_version(DuplicatePolicy::PreferXRef, false, both.clone()), Some(last.clone()));
+    assert_eq!(pick_version(DuplicatePolicy::LastWins, true, vec![first.clone()]), None);
+    assert_eq!(pick_version(DuplicatePolicy::LastWins, false, vec![first.clone()]), Some(first));
+}
+
+#[test]
+fn test_close_delimiters() {
+    assert_eq!(close_delimiters("<< /Kids [ 1 0 R 2 0"), "<< /Kids [ 1 0 R 2 0]>>");
+    assert_eq!(close_delimiters("<< /A [1] /B << /C 2 >>"), "<< /A [1] /B << /C 2 >>>>");
+    assert_eq!(close_delimiters("<< /Title (Half (a) sent"), "<< /Title (Half (a) sent)>>");
+    assert_eq!(close_delimiters("<< /ID [<d83a"), "<< /ID [<d83a>]>>");
+    assert_eq!(close_delimiters("<< /A 1 >>"), "<< /A 1 >>");
+}
+
+#[test]
+fn test_merge_trailers() {
+    let mut newer = DictNode::Dict(hashmap!{
+        "Size".to_string() => DictNode::Int(30),
+        "Prev".to_string() => DictNode::Int(1200),
+    });
+    let older = DictNode::Dict(hashmap!{
+        "Size".to_string() => DictNode::Int(20),
+        "Root".to_string() => DictNode::reference(1),
+    });
+    merge_trailers(&mut newer, older);
+    assert_eq!(newer, DictNode::Dict(hashmap!{
+        "Size".to_string() => DictNode::Int(30),
+        "Prev".to_string() => DictNode::Int(1200),
+        "Root".to_string() => DictNode::reference(1),
+    }));
+    assert_eq!(int_entry(&newer, "Prev"), Some(1200));
+}
+
+#[test]
+fn test_drop_last_token() {
+    assert_eq!(drop_last_token("<< /Length 5 0"), "<< /Length 5");
+    assert_eq!(drop_last_token("<< /Type /Pa"), "<< /Type ");
+    assert_eq!(drop_last_token("/A"), "");
+}
+
+#[test]
+fn test_policy() {
+    let policy = DocumentPolicy { max_pages: Some(10), decode_images: false, ..Default::default() };
+    assert!(policy.check_pages(10).is_ok());
+    assert!(policy.check_pages(11).is_err());
+    let image = DictNode::Dict(hashmap!{ "Subtype".to_string() => DictNode::name("Image") });
+    assert!(policy.check_decode(&image).is_err());
+    assert!(policy.check_decode(&DictNode::Dict(HashMap::new())).is_ok());
+    assert!(DocumentPolicy::default().check_decode(&image).is_ok());
+}
+
+#[test]
+fn test_file_name() {
+    assert_eq!(file_name(&DictNode::LiteralString(b"data/image.bin".to_vec())), Some("data/image.bin".to_string()));
+    let spec = DictNode::Dict(hashmap!{
+        "F".to_string() => DictNode::LiteralString(b"old.bin".to_vec()),
+        "UF".to_string() => DictNode::LiteralString(b"new.bin".to_vec()),
+    });
+    assert_eq!(file_name(&spec), Some("new.bin".to_string()));
+    let spec = DictNode::Dict(hashmap!{
+        "UF".to_string() => DictNode::HexString(b"\xfe\xff\x00\xe9\x00.\x00t".to_vec()),
+    });
+    assert_eq!(file_name(&spec), Some("é.t".to_string()));
+    assert_eq!(file_name(&DictNode::Int(1)), None);
+}
+
+#[test]
+fn test_rebuilt_page_tree() {
+    let tree = page_tree(vec![DictNode::reference(4), DictNode::reference(9)]);
+    assert!(has_type(&tree, "Pages"));
+    assert_eq!(tree.get("Count"), Some(&DictNode::Int(2)));
+
+    // objects in object streams go where their stream is
+    let mut xref = XRefTable::new(Vec::new());
+    xref.set(1, XRefEntry::in_use(500, 0));
+    xref.set(2, XRefEntry::in_use(100, 0));
+    xref.set(3, XRefEntry::compressed(2, 0));
+    let position = |id: usize| file_position(&xref, xref.get(id).unwrap());
+    assert!(position(2) < position(3) && position(3) < position(1));
+}
+
+#[test]
+fn test_resolve_all() {
+    use fixtures::Fixture;
+
+    let file = Fixture::Simple.write();
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+
+    // a node pointing back at its parent keeps the reference
+    doc.set_object(100, DictNode::Dict(hashmap!{ "Kids".to_string() => DictNode::Array(vec![DictNode::reference(101)]) }));
+    doc.set_object(101, DictNode::Dict(hashmap!{ "Parent".to_string() => DictNode::reference(100) }));
+    let kid = DictNode::Dict(hashmap!{ "Parent".to_string() => DictNode::reference(100) });
+    assert_eq!(doc.resolve_all(&DictNode::reference(100)).unwrap(),
+               DictNode::Dict(hashmap!{ "Kids".to_string() => DictNode::Array(vec![kid]) }));
+
+    // each object referencing the next one twice, 2^60 paths down but only
+    // 60 objects to expand
+    for id in 200..260 {
+        doc.set_object(id, DictNode::Array(vec![DictNode::reference(id + 1), DictNode::reference(id + 1)]));
+    }
+    doc.set_object(260, DictNode::Int(1));
+    let expanded = doc.resolve_all(&DictNode::reference(250)).unwrap();
+    assert_eq!(expanded.as_array().map(<[DictNode]>::len), Some(2));
+
+    // a chain deeper than any object should be
+    let end = 1000 + cos::MAX_DEPTH as u64;
+    for id in 1000..end {
+        doc.set_object(id, DictNode::Array(vec![DictNode::reference(id + 1)]));
+    }
+    assert!(doc.resolve_all(&DictNode::reference(1000)).is_err());
+    assert!(doc.resolve_all(&DictNode::reference(end - 10)).is_ok());
+}
+
+#[test]
+fn test_open_fixtures() {
+    use fixtures::{Fixture, ALL, OWNER_PASSWORD};
+
+    for &fixture in ALL {
+        let file = fixture.write();
+        let options = Options { password: Some(OWNER_PASSWORD.to_string()), ..Options::default() };
+        let mut doc = Document::open(&file.path, options).unwrap();
+        assert!(doc.startxref().is_some(), "{:?}", fixture);
+        assert_eq!(doc.warnings(), &[], "{:?}", fixture);
+
+        // object streams are found through the xref stream
+        let compressed = doc.xref.entries().filter(|(_, entry)| entry.object_stream.is_some()).count();
+        assert_eq!(compressed > 0, fixture == Fixture::ObjectStream, "{:?}", fixture);
+        let info = doc.resolve(&DictNode::reference(6)).unwrap();
+        assert_eq!(info.get("Title").and_then(DictNode::as_string), Some(&b"Fixture"[..]), "{:?}", fixture);
+        assert_eq!(doc.trailer_entry("Root").unwrap().and_then(|(id, _)| id), Some(1), "{:?}", fixture);
+
+        // the same objects when the xref is rebuilt, except for the
+        // encrypted one, whose /Encrypt only the trailer points at
+        if fixture != Fixture::Encrypted {
+            let mut scanned = Document::open_by_scanning(&file.path, Options::default()).unwrap();
+            assert_eq!(scanned.warnings(), &[Warning::RebuiltTrailer], "{:?}", fixture);
+            assert_eq!(scanned.resolve(&DictNode::reference(6)).unwrap(), info, "{:?}", fixture);
+            assert_eq!(pages::pages(&mut scanned).unwrap().len(), 1, "{:?}", fixture);
+        }
+    }
+}
+
+#[test]
+fn test_duplicate_objects() {
+    use fixtures::{Fixture, FixtureFile};
+    use writer;
+
+    // an update redefining the /Info, object 6
+    let file = Fixture::Simple.write();
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+    doc.set_object(6, DictNode::Dict(hashmap!{ "Title".to_string() => DictNode::LiteralString(b"Updated".to_vec()) }));
+    let updated = FixtureFile { path: file.path.with_extension("updated.pdf") };
+    fs::write(&updated.path, writer::append(&mut doc).unwrap()).unwrap();
+
+    let open = |options: Options| {
+        let mut doc = Document::open(&updated.path, options).unwrap();
+        let title = doc.resolve(&DictNode::reference(6)).unwrap().get("Title").and_then(DictNode::as_string).map(<[u8]>::to_vec);
+        let duplicates: Vec<u64> = doc.warnings().iter()
+            .filter_map(|warning| match *warning {
+                Warning::DuplicateObject { id, ref offsets } if offsets.len() == 2 => Some(id),
+                _ => None,
+            })
+            .collect();
+        (doc, title, duplicates)
+    };
+    let (mut doc, title, duplicates) = open(Options::default());
+    assert_eq!((title.as_deref(), duplicates), (Some(&b"Updated"[..]), vec![]));
+    // until asked for
+    doc.scan_duplicates().unwrap();
+    assert!(doc.warnings().iter().any(|warning| matches!(*warning, Warning::DuplicateObject { id: 6, .. })));
+    assert_eq!(doc.object_versions(6).unwrap().len(), 2);
+
+    let (_, title, duplicates) = open(Options { lenient: true, ..Options::default() });
+    assert_eq!((title.as_deref(), duplicates), (Some(&b"Updated"[..]), vec![6]));
+    let (_, title, duplicates) = open(Options { duplicates: DuplicatePolicy::FirstWins, ..Options::default() });
+    assert_eq!((title.as_deref(), duplicates), (Some(&b"Fixture"[..]), vec![6]));
+}
+
+#[test]
+fn test_refresh() {
+    use fixtures::Fixture;
+    use writer;
+
+    for &fixture in &[Fixture::Simple, Fixture::XRefStream] {
+        let file = fixture.write();
+        let mut doc = Document::open(&file.path, Options::default()).unwrap();
+        let title = |doc: &mut Document| doc.resolve(&DictNode::reference(6)).unwrap().get("Title").and_then(DictNode::as_string).map(<[u8]>::to_vec);
+        assert_eq!(title(&mut doc).as_deref(), Some(&b"Fixture"[..]));
+
+        // an update redefining the /Info, appended to the same file
+        let mut writing = Document::open(&file.path, Options::default()).unwrap();
+        writing.set_object(6, DictNode::Dict(hashmap!{ "Title".to_string() => DictNode::LiteralString(b"Updated".to_vec()) }));
+        fs::write(&file.path, writer::append(&mut writing).unwrap()).unwrap();
+        let changed = match doc.refresh().unwrap() {
+            Refresh::Appended(changed) => changed,
+            refresh => panic!("{:?}: {:?}", fixture, refresh),
+        };
+        assert!(changed.contains(&6), "{:?}", fixture);
+        assert_eq!(title(&mut doc).as_deref(), Some(&b"Updated"[..]), "{:?}", fixture);
+        assert_eq!(doc.startxref(), find_startxref(&mut File::open(&file.path).unwrap()).unwrap());
+
+        // written over from scratch
+        fs::write(&file.path, fixture.bytes()).unwrap();
+        assert_eq!(doc.refresh().unwrap(), Refresh::Reopened, "{:?}", fixture);
+        assert_eq!(title(&mut doc).as_deref(), Some(&b"Fixture"[..]), "{:?}", fixture);
+    }
+}
@@ -0,0 +1,130 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+/*
+ * Everything that can go wrong while reading a PDF. Real world files are
+ * corrupt surprisingly often, so none of this should ever take the process
+ * down: callers get a PdfError back and decide what to do with it.
+ */
+
+#[derive(Debug)]
+pub enum PdfError {
+    Io(io::Error),
+    Utf8(Utf8Error),
+    ParseInt(ParseIntError),
+    ParseFloat(ParseFloatError),
+    // the grammar rejected the input, `what` is what we were trying to parse
+    Syntax { what: &'static str, position: usize },
+    MissingStartXRef,
+    MissingEndobj { offset: u64 },
+    TruncatedObject { offset: u64 },
+    UnsupportedFilter(String),
+    // the stream data didn't decode with the filter it claims to use
+    Decode(String),
+    // a form field can't be filled in the way it was asked to
+    Form(String),
+    // the document needs something the DocumentPolicy doesn't allow
+    Policy(String),
+    // parsing in isolation failed: a panic, a crashed or stuck child
+    Sandbox(String),
+    // an image whose samples we can't turn into a file
+    UnsupportedImage(String),
+    // an encrypted document we can't decrypt: the wrong password, or
+    // encryption we don't support
+    Encryption(String),
+    // a patch that doesn't fit the document it's applied to, see src/patch
+    Patch(String),
+    // the caller asked for something the document can't give: a page past
+    // the last one, a rotation that isn't a multiple of 90
+    InvalidArgument(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, PdfError>;
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PdfError::Io(ref err) => write!(f, "I/O error: {}", err),
+            PdfError::Utf8(ref err) => write!(f, "invalid UTF-8: {}", err),
+            PdfError::ParseInt(ref err) => write!(f, "invalid integer: {}", err),
+            PdfError::ParseFloat(ref err) => write!(f, "invalid number: {}", err),
+            PdfError::Syntax { what, position } => {
+                write!(f, "syntax error in {} at position {}", what, position)
+            },
+            PdfError::MissingStartXRef => write!(f, "couldn't find startxref"),
+            PdfError::MissingEndobj { offset } => {
+                write!(f, "object at offset {} has no endobj", offset)
+            },
+            PdfError::TruncatedObject { offset } => {
+                write!(f, "object at offset {} is truncated", offset)
+            },
+            PdfError::UnsupportedFilter(ref name) => write!(f, "unsupported filter /{}", name),
+            PdfError::Decode(ref err) => write!(f, "couldn't decode stream: {}", err),
+            PdfError::Form(ref err) => write!(f, "form field: {}", err),
+            PdfError::Policy(ref what) => write!(f, "not allowed by the document policy: {}", what),
+            PdfError::Sandbox(ref err) => write!(f, "sandboxed parse failed: {}", err),
+            PdfError::UnsupportedImage(ref what) => write!(f, "unsupported image: {}", what),
+            PdfError::Encryption(ref why) => write!(f, "can't decrypt the document: {}", why),
+            PdfError::Patch(ref conflict) => write!(f, "patch doesn't apply: {}", conflict),
+            PdfError::InvalidArgument(ref what) => write!(f, "invalid argument: {}", what),
+        }
+    }
+}
+
+impl Error for PdfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            PdfError::Io(ref err) => Some(err),
+            PdfError::Utf8(ref err) => Some(err),
+            PdfError::ParseInt(ref err) => Some(err),
+            PdfError::ParseFloat(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PdfError {
+    fn from(err: io::Error) -> PdfError {
+        PdfError::Io(err)
+    }
+}
+
+impl From<Utf8Error> for PdfError {
+    fn from(err: Utf8Error) -> PdfError {
+        PdfError::Utf8(err)
+    }
+}
+
+impl From<FromUtf8Error> for PdfError {
+    fn from(err: FromUtf8Error) -> PdfError {
+        PdfError::Utf8(err.utf8_error())
+    }
+}
+
+impl From<ParseIntError> for PdfError {
+    fn from(err: ParseIntError) -> PdfError {
+        PdfError::ParseInt(err)
+    }
+}
+
+impl From<ParseFloatError> for PdfError {
+    fn from(err: ParseFloatError) -> PdfError {
+        PdfError::ParseFloat(err)
+    }
+}
+
+#[test]
+fn test_display() {
+    let err = PdfError::Syntax { what: "xref", position: 12 };
+    assert_eq!(err.to_string(), "syntax error in xref at position 12");
+
+    let err = PdfError::from("12x".parse::<i64>().unwrap_err());
+    assert_eq!(err.to_string(), "invalid integer: invalid digit found in string");
+
+    let err = PdfError::InvalidArgument("page 4 of 3".to_string());
+    assert_eq!(err.to_string(), "invalid argument: page 4 of 3");
+}
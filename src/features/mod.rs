@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet};
+
+use document::Document;
+use error::Result;
+use graphics::{area, intersection};
+use pages::Page;
+use text::{self, PageContent};
+
+/*
+ * Cheap numbers about a page for classifiers, what a pipeline would
+ * otherwise render the page to get at:
+ *
+ *   let features = page.features(&mut doc)?;
+ *   if features.image_area_fraction > 0.9 && features.text_length == 0 { ... }
+ *
+ * Everything comes out of one run over the content, the same one text
+ * extraction does, so it costs about as much as extracting the page's text.
+ * Areas are as a fraction of the crop box. Images overlapping each other
+ * count twice.
+ */
+
+// Operators that build a path, and ones that paint it (n ends a path
+// without painting it, mostly for clipping).
+const PATH_OPERATORS: &[&str] = &["m", "l", "c", "v", "y", "h", "re"];
+const PAINT_OPERATORS: &[&str] = &["S", "s", "f", "F", "f*", "B", "B*", "b", "b*", "sh"];
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PageFeatures {
+    // characters of extracted text, whitespace included
+    pub text_length: usize,
+    // how much of the page images cover, from 0 to 1
+    pub image_area_fraction: f64,
+    pub image_count: usize,
+    pub path_operators: usize,
+    pub paint_operators: usize,
+    // different fonts text was shown with
+    pub font_count: usize,
+    // by character, 0 without text
+    pub average_font_size: f64,
+    // whitespace characters out of text_length, 0 without text
+    pub whitespace_ratio: f64,
+}
+
+pub fn features(doc: &mut Document, page: &Page) -> Result<PageFeatures> {
+    Ok(from_content(page, &text::interpret(doc, page)?))
+}
+
+fn from_content(page: &Page, content: &PageContent) -> PageFeatures {
+    let text = text::layout(&content.spans);
+    let text_length = text.chars().count();
+    let whitespace = text.chars().filter(|c| c.is_whitespace()).count();
+
+    let mut characters = 0;
+    let mut size_total = 0.0;
+    for span in &content.spans {
+        let count = span.text.chars().count();
+        characters += count;
+        size_total += span.size * count as f64;
+    }
+    let fonts: HashSet<&str> = content.spans.iter().map(|span| span.font.as_str()).collect();
+
+    let page_area = area(page.crop_box);
+    let image_area: f64 = content.images.iter().map(|matrix| area(intersection(matrix.unit_bounds(), page.crop_box))).sum();
+
+    PageFeatures {
+        text_length,
+        image_area_fraction: if page_area > 0.0 { (image_area / page_area).min(1.0) } else { 0.0 },
+        image_count: content.images.len(),
+        path_operators: count(&content.operators, PATH_OPERATORS),
+        paint_operators: count(&content.operators, PAINT_OPERATORS),
+        font_count: fonts.len(),
+        average_font_size: if characters > 0 { size_total / characters as f64 } else { 0.0 },
+        whitespace_ratio: if text_length > 0 { whitespace as f64 / text_length as f64 } else { 0.0 },
+    }
+}
+
+fn count(operators: &HashMap<String, usize>, wanted: &[&str]) -> usize {
+    wanted.iter().filter_map(|&operator| operators.get(operator)).sum()
+}
+
+#[test]
+fn test_features() {
+    use fixtures;
+    use graphics::{Color, Matrix};
+    use text::TextSpan;
+
+    let page = fixtures::page([0.0, 0.0, 100.0, 200.0], 0);
+    let span = |text: &str, y: f64, size: f64, font: &str| TextSpan {
+        text: text.to_string(),
+        start: (10.0, y),
+        end: (50.0, y),
+        size,
+        font: font.to_string(),
+        color: Color::Gray(0.0),
+        render_mode: 0,
+    };
+    let content = PageContent {
+        spans: vec![span("a b", 150.0, 10.0, "Helvetica"), span("c", 100.0, 20.0, "Times-Roman")],
+        images: vec![
+            // half the page
+            Matrix::new(100.0, 0.0, 0.0, 100.0, 0.0, 0.0),
+            // mostly off the page, a quarter of it on
+            Matrix::new(-50.0, 0.0, 0.0, 100.0, 50.0, 150.0),
+        ],
+        operators: hashmap!{ "re".to_string() => 2, "m".to_string() => 1, "f".to_string() => 3, "n".to_string() => 1 },
+        ..PageContent::default()
+    };
+    let features = from_content(&page, &content);
+    // "a b\nc\n"
+    assert_eq!(features.text_length, 6);
+    assert_eq!(features.whitespace_ratio, 0.5);
+    assert_eq!(features.font_count, 2);
+    assert_eq!(features.average_font_size, 12.5);
+    assert_eq!(features.image_count, 2);
+    assert_eq!(features.image_area_fraction, 0.625);
+    assert_eq!(features.path_operators, 3);
+    assert_eq!(features.paint_operators, 3);
+
+    assert_eq!(from_content(&page, &PageContent::default()), PageFeatures::default());
+}
@@ -0,0 +1,200 @@
+use document::Document;
+use error::Result;
+use graphics::paths::{self, Path};
+use graphics::{area, intersection, union, Matrix};
+use pages::Page;
+use text::{self, TextSpan};
+
+/*
+ * Guesses where a page's figures are, for mining papers: the regions its
+ * graphics cluster into, with the caption that goes with each.
+ *
+ * Painted paths and placed images are grouped when they come within `GAP`
+ * of each other, so the bars, axes and ticks of a chart end up one region.
+ * Text inside a region is its labels and grows the region to cover them.
+ * What's left is then filtered:
+ *
+ *   - graphics covering most of the page are backgrounds, not figures
+ *   - regions smaller than `MIN_SIZE` either way are rules and bullets
+ *   - regions of only paths that are mostly text inside are boxed
+ *     paragraphs or sidebars
+ *
+ * The caption is the closest line above or below the region, within
+ * `MAX_CAPTION_DISTANCE` and overlapping it horizontally, that starts with
+ * "Figure" or "Fig.". Only its first line is taken.
+ *
+ * These are candidates: a table drawn with rules comes out as a figure, and
+ * one drawn with white space doesn't come out at all.
+ */
+
+// in points, a sixth of an inch or so
+const GAP: f64 = 12.0;
+const MIN_SIZE: f64 = 36.0;
+const MAX_CAPTION_DISTANCE: f64 = 72.0;
+// of the page's crop box
+const BACKGROUND_FRACTION: f64 = 0.9;
+// of a region's area, for regions without images
+const MAX_TEXT_FRACTION: f64 = 0.5;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Figure {
+    // [llx lly urx ury] in default user space
+    pub bbox: [f64; 4],
+    // what the region was made of
+    pub paths: usize,
+    pub images: usize,
+    pub caption: Option<String>,
+}
+
+pub fn figures(doc: &mut Document, page: &Page) -> Result<Vec<Figure>> {
+    let paths = paths::paths(doc, page)?;
+    let content = text::interpret(doc, page)?;
+    Ok(detect(page.crop_box, &paths, &content.images, &content.spans))
+}
+
+fn detect(page_box: [f64; 4], paths: &[Path], images: &[Matrix], spans: &[TextSpan]) -> Vec<Figure> {
+    let background = area(page_box) * BACKGROUND_FRACTION;
+    let pieces = paths.iter()
+        .filter_map(|path| path.bounds().map(|bounds| (bounds, 1, 0)))
+        .chain(images.iter().map(|image| (image.unit_bounds(), 0, 1)))
+        .map(|(bounds, paths, images)| Figure { bbox: intersection(bounds, page_box), paths, images, caption: None })
+        .filter(|piece| piece.bbox[0] <= piece.bbox[2] && piece.bbox[1] <= piece.bbox[3])
+        .filter(|piece| area(piece.bbox) < background);
+
+    // each piece swallows every cluster it's near, and whatever that brings
+    // near, before it becomes a cluster itself
+    let mut clusters: Vec<Figure> = Vec::new();
+    for mut piece in pieces {
+        while let Some(index) = clusters.iter().position(|cluster| near(cluster.bbox, piece.bbox)) {
+            let cluster = clusters.swap_remove(index);
+            piece.bbox = union(piece.bbox, cluster.bbox);
+            piece.paths += cluster.paths;
+            piece.images += cluster.images;
+        }
+        clusters.push(piece);
+    }
+
+    let mut figures = Vec::new();
+    for mut figure in clusters {
+        let region = figure.bbox;
+        let mut text_area = 0.0;
+        for span in spans {
+            let bounds = span_bounds(span);
+            let center = ((bounds[0] + bounds[2]) / 2.0, (bounds[1] + bounds[3]) / 2.0);
+            if center.0 >= region[0] && center.0 <= region[2] && center.1 >= region[1] && center.1 <= region[3] {
+                text_area += area(bounds);
+                figure.bbox = union(figure.bbox, bounds);
+            }
+        }
+        let (width, height) = (figure.bbox[2] - figure.bbox[0], figure.bbox[3] - figure.bbox[1]);
+        if width < MIN_SIZE || height < MIN_SIZE {
+            continue;
+        }
+        if figure.images == 0 && text_area > area(region) * MAX_TEXT_FRACTION {
+            continue;
+        }
+        figure.caption = caption(figure.bbox, spans);
+        figures.push(figure);
+    }
+    // reading order, more or less
+    figures.sort_by(|a, b| b.bbox[3].total_cmp(&a.bbox[3]).then(a.bbox[0].total_cmp(&b.bbox[0])));
+    figures
+}
+
+fn near(a: [f64; 4], b: [f64; 4]) -> bool {
+    a[0] - GAP <= b[2] && b[0] <= a[2] + GAP && a[1] - GAP <= b[3] && b[1] <= a[3] + GAP
+}
+
+// Roughly where a span's glyphs are, from the baseline up one em.
+fn span_bounds(span: &TextSpan) -> [f64; 4] {
+    let y = span.start.1.min(span.end.1);
+    [span.start.0.min(span.end.0), y, span.start.0.max(span.end.0), y + span.size]
+}
+
+fn caption(bbox: [f64; 4], spans: &[TextSpan]) -> Option<String> {
+    let mut best: Option<(f64, String)> = None;
+    for (index, span) in spans.iter().enumerate() {
+        let trimmed = span.text.trim_start();
+        if !trimmed.starts_with("Figure") && !trimmed.starts_with("Fig.") {
+            continue;
+        }
+        // the rest of the line, which may have been shown in pieces
+        let mut line: Vec<TextSpan> = spans[index..].iter()
+            .filter(|other| (other.start.1 - span.start.1).abs() < span.size / 2.0 && other.start.0 >= span.start.0)
+            .cloned()
+            .collect();
+        line.sort_by(|a, b| a.start.0.total_cmp(&b.start.0));
+        let extent = line.iter().map(span_bounds).fold(span_bounds(span), union);
+        if extent[0] > bbox[2] || extent[2] < bbox[0] {
+            continue;
+        }
+        let distance = if extent[3] <= bbox[1] {
+            bbox[1] - extent[3]
+        } else if extent[1] >= bbox[3] {
+            extent[1] - bbox[3]
+        } else {
+            0.0
+        };
+        if distance <= MAX_CAPTION_DISTANCE && best.as_ref().is_none_or(|&(closest, _)| distance < closest) {
+            best = Some((distance, text::layout(&line).trim().to_string()));
+        }
+    }
+    best.map(|(_, caption)| caption)
+}
+
+#[test]
+fn test_figures() {
+    use graphics::paths::Segment;
+    use graphics::Color;
+
+    let line = |x1: f64, y1: f64, x2: f64, y2: f64| Path {
+        segments: vec![Segment::MoveTo(x1, y1), Segment::LineTo(x2, y2)],
+        fill: None,
+        stroke: Some(Color::Gray(0.0)),
+        line_width: 1.0,
+        even_odd: false,
+    };
+    let span = |text: &str, x: f64, y: f64, width: f64| TextSpan {
+        text: text.to_string(),
+        start: (x, y),
+        end: (x + width, y),
+        size: 10.0,
+        font: "Helvetica".to_string(),
+        color: Color::Gray(0.0),
+        render_mode: 0,
+    };
+    let page = [0.0, 0.0, 600.0, 800.0];
+    let paths = vec![
+        // a chart's axes and a bar, near enough to be one figure
+        line(100.0, 500.0, 300.0, 500.0),
+        line(100.0, 500.0, 100.0, 650.0),
+        line(150.0, 505.0, 150.0, 600.0),
+        // a rule under a heading
+        line(100.0, 750.0, 500.0, 750.0),
+        // a page-sized background
+        Path { fill: Some(Color::Gray(1.0)), ..line(0.0, 0.0, 600.0, 800.0) },
+        // a box around a paragraph
+        line(100.0, 100.0, 300.0, 100.0),
+        line(100.0, 100.0, 100.0, 140.0),
+    ];
+    let spans = vec![
+        span("Body text before the figure.", 100.0, 700.0, 300.0),
+        // an axis label, sticking out left of the axis
+        span("100", 90.0, 640.0, 30.0),
+        span("Figure 1:", 100.0, 480.0, 40.0),
+        span("Results.", 145.0, 480.0, 35.0),
+        span("Figure 2: too far away.", 100.0, 300.0, 100.0),
+        span("A boxed paragraph, mostly text.", 100.0, 125.0, 200.0),
+        span("More of the boxed paragraph.", 100.0, 110.0, 200.0),
+        span("And more of it, filling the box.", 100.0, 102.0, 200.0),
+    ];
+    let images = vec![Matrix::new(300.0, 0.0, 0.0, 200.0, 250.0, 200.0)];
+
+    let figures = detect(page, &paths, &images, &spans);
+    assert_eq!(figures.len(), 2);
+    assert_eq!(figures[0].bbox, [90.0, 500.0, 300.0, 650.0]);
+    assert_eq!(figures[0].paths, 3);
+    assert_eq!(figures[0].caption.as_deref(), Some("Figure 1: Results."));
+    assert_eq!(figures[1].images, 1);
+    assert_eq!(figures[1].caption, None);
+}
@@ -0,0 +1,204 @@
+/*
+ * A deflate encoder, for FlateDecode streams we write ourselves:
+ *
+ *   let zlib = deflate::zlib(&content);
+ *
+ * LZ77 over the 32K window with hash chains, then one block with the fixed
+ * Huffman codes. Dynamic codes would squeeze out a little more; what gets
+ * compressed is mostly content streams, which are ASCII with plenty of
+ * repetition, and fixed codes already do well on those. The chains are cut
+ * short after MAX_CHAIN candidates, so a pathological input (a megabyte
+ * of one byte) costs time in its length and not its square.
+ */
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+const NONE: usize = usize::MAX;
+
+// Length codes 257 to 285: the shortest length each stands for, and how
+// many extra bits follow.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+// The same for distance codes 0 to 29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Deflate bits go out least significant first, Huffman codes most
+// significant first.
+struct Bits {
+    data: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.data.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    // The fixed literal/length code of `symbol`.
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn matched(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+        self.symbol(257 + code as u16);
+        self.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+        let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.code(code as u32, 5);
+        self.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.data.push(self.buffer as u8);
+        }
+        self.data
+    }
+}
+
+// Earlier positions by the hash of the three bytes there, the most
+// recent first.
+struct Chains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    previous: Vec<usize>,
+}
+
+impl<'a> Chains<'a> {
+    fn new(data: &'a [u8]) -> Chains<'a> {
+        Chains { data, head: vec![NONE; 1 << HASH_BITS], previous: vec![NONE; WINDOW] }
+    }
+
+    fn hash(&self, position: usize) -> Option<usize> {
+        match self.data.get(position..position + MIN_MATCH)? {
+            &[a, b, c] => Some(((a as usize) << 10 ^ (b as usize) << 5 ^ c as usize) & ((1 << HASH_BITS) - 1)),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        if let Some(hash) = self.hash(position) {
+            self.previous[position % WINDOW] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    // The longest earlier match for what's at `position`, as a length and
+    // a distance back.
+    fn longest(&self, position: usize) -> (usize, usize) {
+        let hash = match self.hash(position) {
+            Some(hash) => hash,
+            None => return (0, 0),
+        };
+        let limit = (self.data.len() - position).min(MAX_MATCH);
+        let (mut best, mut distance) = (0, 0);
+        let mut candidate = self.head[hash];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || position - candidate > WINDOW {
+                break;
+            }
+            let length = self.data[candidate..].iter().zip(&self.data[position..position + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best {
+                best = length;
+                distance = position - candidate;
+                if length == limit {
+                    break;
+                }
+            }
+            // a slot reused since points forward, which ends the chain
+            let next = self.previous[candidate % WINDOW];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        (best, distance)
+    }
+}
+
+// `data` deflated, behind a zlib header and followed by its checksum.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, default compression
+    let mut bits = Bits { data: vec![0x78, 0x9c], buffer: 0, count: 0 };
+    // the only block, with fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut chains = Chains::new(data);
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = chains.longest(position);
+        if length >= MIN_MATCH {
+            bits.matched(length, distance);
+            for skipped in position..position + length {
+                chains.insert(skipped);
+            }
+            position += length;
+        } else {
+            bits.symbol(data[position] as u16);
+            chains.insert(position);
+            position += 1;
+        }
+    }
+    bits.symbol(256);
+
+    let mut zlib = bits.finish();
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_zlib() {
+    use filters::flate_decode;
+
+    let roundtrip = |data: &[u8]| assert_eq!(flate_decode(&zlib(data)).unwrap(), data);
+    roundtrip(b"");
+    roundtrip(b"a");
+    roundtrip(b"BT /F1 12 Tf 72 720 Td (Hello) Tj ET\n");
+    // every literal, and a run matching itself up to the longest length
+    roundtrip(&(0..=255).collect::<Vec<u8>>());
+    roundtrip(&vec![b'x'; 1000]);
+    // more than a window's worth, with the chains wrapping around
+    let far: Vec<u8> = (0..70000u32).map(|index| (index % 251) as u8 ^ (index / 40000) as u8).collect();
+    roundtrip(&far);
+
+    let content = b"0 0 m 10 10 l S\n".repeat(200);
+    assert!(zlib(&content).len() < content.len() / 10);
+}
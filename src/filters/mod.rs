@@ -0,0 +1,677 @@
+// held to the audit's no-panic rule, see src/audit
+#![cfg_attr(feature = "audit", deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable,
+                                    clippy::todo, clippy::unimplemented))]
+
+#[cfg(not(feature = "fast-inflate"))]
+use inflate::{inflate_bytes, inflate_bytes_zlib};
+
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+
+pub mod deflate;
+
+/*
+ * Stream filters, i.e. the encodings listed in a stream's /Filter entry.
+ *
+ * A stream can go through several filters, e.g. /Filter [/ASCII85Decode
+ * /FlateDecode] means the data was deflated and then ASCII85 encoded, so it
+ * has to be decoded in that same order. Each filter can have parameters,
+ * which live at the same index in /DecodeParms.
+ *
+ * The short names (AHx, A85...) are the abbreviations inline images use.
+ *
+ * DCTDecode and JPXDecode are the odd ones out: their output is a raw pixel
+ * grid, but their input is a perfectly good .jpg/.jp2 file. Nobody wants us
+ * to decode a JPEG just to re-encode it, so those are left alone and the
+ * encoded bytes are handed out as an `EncodedImage` instead.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageCodec {
+    Jpeg,
+    Jpeg2000,
+}
+
+impl ImageCodec {
+    pub fn from_filter(filter_name: &str) -> Option<ImageCodec> {
+        match filter_name.trim_start_matches('/') {
+            "DCTDecode" | "DCT" => Some(ImageCodec::Jpeg),
+            "JPXDecode" => Some(ImageCodec::Jpeg2000),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageCodec::Jpeg => "jpg",
+            ImageCodec::Jpeg2000 => "jp2",
+        }
+    }
+}
+
+// Image data still in its JPEG/JPEG2000 encoding, along with what the image
+// dictionary says about it. JPEG2000 carries its own color space, so
+// `color_space` is often missing for those.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodedImage {
+    pub codec: ImageCodec,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub color_space: Option<DictNode>,
+    pub data: Vec<u8>,
+}
+
+// Decodes `data` with a single filter, e.g. "FlateDecode".
+pub fn decode(filter_name: &str, params: Option<&DictNode>, data: &[u8]) -> Result<Vec<u8>> {
+    match filter_name.trim_start_matches('/') {
+        "FlateDecode" | "Fl" => unpredict(params, flate_decode(data)?),
+        "ASCIIHexDecode" | "AHx" => ascii_hex_decode(data),
+        "ASCII85Decode" | "A85" => ascii85_decode(data),
+        "RunLengthDecode" | "RL" => run_length_decode(data),
+        "LZWDecode" | "LZW" => unpredict(params, lzw_decode(data, early_change(params))?),
+        // the document decrypts streams as it reads them
+        "Crypt" => Ok(data.to_vec()),
+        other => Err(PdfError::UnsupportedFilter(other.to_string())),
+    }
+}
+
+// The (filter, params) pairs a stream dictionary asks for, in the order
+// they have to be applied.
+pub fn chain(dict: &DictNode) -> Vec<(String, Option<DictNode>)> {
+    let map = match *dict {
+        DictNode::Dict(ref map) => map,
+        _ => return Vec::new(),
+    };
+    let names = match map.get("Filter") {
+        Some(DictNode::Str(name)) => vec![name.clone()],
+        Some(DictNode::Array(names)) => names.iter()
+            .filter_map(|name| match *name {
+                DictNode::Str(ref name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => return Vec::new(),
+    };
+    let params = match map.get("DecodeParms") {
+        Some(DictNode::Array(params)) => params.clone(),
+        Some(params) => vec![params.clone()],
+        None => Vec::new(),
+    };
+
+    names.into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let params = params.get(index).and_then(|params| match *params {
+                DictNode::Dict(_) => Some(params.clone()),
+                _ => None,
+            });
+            (name.trim_start_matches('/').to_string(), params)
+        })
+        .collect()
+}
+
+// Applies every filter in the stream's chain, stopping short of DCT/JPX so
+// images come out as .jpg/.jp2 data.
+pub fn decode_chain(dict: &DictNode, data: &[u8]) -> Result<Vec<u8>> {
+    let mut data = data.to_vec();
+    for (name, params) in chain(dict) {
+        if ImageCodec::from_filter(&name).is_some() {
+            break;
+        }
+        data = decode(&name, params.as_ref(), &data)?;
+    }
+    Ok(data)
+}
+
+// The image codec the stream is encoded with, if any.
+pub fn image_codec(dict: &DictNode) -> Option<ImageCodec> {
+    chain(dict).iter().filter_map(|(name, _)| ImageCodec::from_filter(name)).next()
+}
+
+// Undoes any filters layered on top of a DCT/JPX image (ASCII85 is a
+// common one) and returns the encoded image, or None for other streams.
+pub fn encoded_image(dict: &DictNode, data: &[u8]) -> Result<Option<EncodedImage>> {
+    let codec = match image_codec(dict) {
+        Some(codec) => codec,
+        None => return Ok(None),
+    };
+    let dimension = |key| int_param(Some(dict), key).filter(|&value| value >= 0).map(|value| value as u64);
+    let color_space = match *dict {
+        DictNode::Dict(ref map) => map.get("ColorSpace").cloned(),
+        _ => None,
+    };
+    Ok(Some(EncodedImage {
+        codec,
+        width: dimension("Width"),
+        height: dimension("Height"),
+        color_space,
+        data: decode_chain(dict, data)?,
+    }))
+}
+
+fn int_param(params: Option<&DictNode>, key: &str) -> Option<i64> {
+    match params {
+        Some(DictNode::Dict(map)) => match map.get(key) {
+            Some(&DictNode::Int(value)) => Some(value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn early_change(params: Option<&DictNode>) -> bool {
+    int_param(params, "EarlyChange").unwrap_or(1) != 0
+}
+
+fn decode_error<T>(message: &str) -> Result<T> {
+    Err(PdfError::Decode(message.to_string()))
+}
+
+// Flate streams are supposed to carry a zlib header, but enough writers
+// emit bare deflate data that it's worth retrying without it.
+#[cfg(not(feature = "fast-inflate"))]
+pub fn flate_decode(data: &[u8]) -> Result<Vec<u8>> {
+    match inflate_bytes_zlib(data) {
+        Ok(decoded) => Ok(decoded),
+        Err(_) => inflate_bytes(data).map_err(PdfError::Decode),
+    }
+}
+
+#[cfg(feature = "fast-inflate")]
+pub fn flate_decode(data: &[u8]) -> Result<Vec<u8>> {
+    use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+    match decompress_to_vec_zlib(data) {
+        Ok(decoded) => Ok(decoded),
+        Err(_) => decompress_to_vec(data).map_err(|err| PdfError::Decode(format!("{:?}", err.status))),
+    }
+}
+
+// The other way: `data` deflated with a zlib header, for /FlateDecode.
+#[cfg(not(feature = "fast-inflate"))]
+pub fn flate_encode(data: &[u8]) -> Vec<u8> {
+    deflate::zlib(data)
+}
+
+#[cfg(feature = "fast-inflate")]
+pub fn flate_encode(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+// Pairs of hex digits terminated by '>', whitespace is ignored and a
+// missing final digit counts as 0.
+pub fn ascii_hex_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len() / 2);
+    let mut high = None;
+    for &byte in data {
+        if byte == b'>' {
+            break;
+        }
+        if is_whitespace(byte) {
+            continue;
+        }
+        let digit = match (byte as char).to_digit(16) {
+            Some(digit) => digit as u8,
+            None => return decode_error("invalid character in ASCIIHexDecode data"),
+        };
+        high = match high {
+            None => Some(digit),
+            Some(high) => {
+                decoded.push(high << 4 | digit);
+                None
+            },
+        };
+    }
+    if let Some(high) = high {
+        decoded.push(high << 4);
+    }
+    Ok(decoded)
+}
+
+// Groups of five '!'..'u' characters encode four bytes in base 85, 'z' is
+// shorthand for four zero bytes and "~>" ends the data.
+pub fn ascii85_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let data = if data.starts_with(b"<~") { &data[2..] } else { data };
+    let mut decoded = Vec::with_capacity(data.len() * 4 / 5);
+    let mut group = [0u8; 5];
+    let mut count = 0;
+
+    for &byte in data {
+        match byte {
+            b'~' => break,
+            b'z' if count == 0 => decoded.extend_from_slice(&[0, 0, 0, 0]),
+            b'!'..=b'u' => {
+                group[count] = byte - b'!';
+                count += 1;
+                if count == 5 {
+                    decoded.extend_from_slice(&ascii85_group(&group)?);
+                    count = 0;
+                }
+            },
+            _ if is_whitespace(byte) => (),
+            _ => return decode_error("invalid character in ASCII85Decode data"),
+        }
+    }
+
+    match count {
+        0 => (),
+        1 => return decode_error("ASCII85Decode data ends with a single character"),
+        _ => {
+            // pad the last group with 'u' and only keep the bytes it encodes
+            for digit in group.iter_mut().skip(count) {
+                *digit = b'u' - b'!';
+            }
+            decoded.extend_from_slice(&ascii85_group(&group)?[..count - 1]);
+        },
+    }
+    Ok(decoded)
+}
+
+fn ascii85_group(group: &[u8; 5]) -> Result<[u8; 4]> {
+    let value = group.iter().fold(0u64, |value, &digit| value * 85 + digit as u64);
+    if value > u32::MAX as u64 {
+        return decode_error("ASCII85Decode group out of range");
+    }
+    Ok((value as u32).to_be_bytes())
+}
+
+// A length byte L followed by L + 1 literal bytes when L < 128, or by a
+// single byte to repeat 257 - L times when L > 128. 128 ends the data.
+pub fn run_length_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let length = data[position] as usize;
+        position += 1;
+        if length < 128 {
+            let end = position + length + 1;
+            if end > data.len() {
+                return decode_error("RunLengthDecode literal run goes past the end of the data");
+            }
+            decoded.extend_from_slice(&data[position..end]);
+            position = end;
+        } else if length > 128 {
+            match data.get(position) {
+                Some(&byte) => decoded.extend(::std::iter::repeat_n(byte, 257 - length)),
+                None => return decode_error("RunLengthDecode repeat run is missing its byte"),
+            }
+            position += 1;
+        } else {
+            break;
+        }
+    }
+    Ok(decoded)
+}
+
+// Flate and LZW data can be run through a predictor before compression,
+// which turns smooth images (and xref streams, which are tables of
+// mostly increasing offsets) into runs of small numbers that compress
+// better. /Predictor 2 is the TIFF one, 10 and up are the PNG ones where
+// each row starts with a byte saying which PNG filter it uses.
+fn unpredict(params: Option<&DictNode>, data: Vec<u8>) -> Result<Vec<u8>> {
+    let predictor = int_param(params, "Predictor").unwrap_or(1);
+    let colors = int_param(params, "Colors").unwrap_or(1);
+    let bits = int_param(params, "BitsPerComponent").unwrap_or(8);
+    let columns = int_param(params, "Columns").unwrap_or(1);
+    if colors < 1 || columns < 1 || ![1, 2, 4, 8, 16].contains(&bits) {
+        return decode_error("invalid predictor parameters");
+    }
+    let (colors, bits, columns) = (colors as usize, bits as usize, columns as usize);
+    // rows bigger than the data are fine, they just come out short, but
+    // the length has to be computable
+    if colors.checked_mul(bits).and_then(|bits| bits.checked_mul(columns)).is_none() {
+        return decode_error("invalid predictor parameters");
+    }
+    match predictor {
+        1 => Ok(data),
+        2 => tiff_unpredict(data, colors, bits, columns),
+        10..=15 => png_unpredict(&data, colors, bits, columns),
+        _ => decode_error("unknown predictor"),
+    }
+}
+
+// Every sample is stored as the difference with the sample of the same
+// color component to its left.
+fn tiff_unpredict(mut data: Vec<u8>, colors: usize, bits: usize, columns: usize) -> Result<Vec<u8>> {
+    let row_len = (colors * bits * columns).div_ceil(8);
+    match bits {
+        8 => {
+            for row in data.chunks_mut(row_len) {
+                for index in colors..row.len() {
+                    row[index] = row[index].wrapping_add(row[index - colors]);
+                }
+            }
+        },
+        16 => {
+            for row in data.chunks_mut(row_len) {
+                for index in (colors * 2..row.len() - row.len() % 2).step_by(2) {
+                    let left = u16::from_be_bytes([row[index - colors * 2], row[index - colors * 2 + 1]]);
+                    let value = u16::from_be_bytes([row[index], row[index + 1]]).wrapping_add(left);
+                    row[index..index + 2].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+        },
+        _ => {
+            let mask = (1u16 << bits) - 1;
+            for row in data.chunks_mut(row_len) {
+                let samples = (row.len() * 8 / bits).min(colors * columns);
+                for index in colors..samples {
+                    let value = (sample(row, index, bits) + sample(row, index - colors, bits)) & mask;
+                    set_sample(row, index, bits, value);
+                }
+            }
+        },
+    }
+    Ok(data)
+}
+
+fn sample(row: &[u8], index: usize, bits: usize) -> u16 {
+    let bit = index * bits;
+    let shift = 8 - bits - bit % 8;
+    (row[bit / 8] >> shift) as u16 & ((1 << bits) - 1)
+}
+
+fn set_sample(row: &mut [u8], index: usize, bits: usize, value: u16) {
+    let bit = index * bits;
+    let shift = 8 - bits - bit % 8;
+    let mask = (((1u16 << bits) - 1) << shift) as u8;
+    row[bit / 8] = (row[bit / 8] & !mask) | ((value as u8) << shift);
+}
+
+// PNG filters work on bytes rather than samples, "left" being the byte one
+// pixel back (at least one byte back for small samples).
+fn png_unpredict(data: &[u8], colors: usize, bits: usize, columns: usize) -> Result<Vec<u8>> {
+    let row_len = (colors * bits * columns).div_ceil(8);
+    let pixel_len = (colors * bits).div_ceil(8);
+    let mut decoded = Vec::with_capacity(data.len());
+    // only as much of the previous row as the data can fill
+    let mut previous = vec![0u8; row_len.min(data.len())];
+
+    for row in data.chunks(row_len.saturating_add(1)) {
+        let (filter, row) = (row[0], &row[1..]);
+        let mut current = row.to_vec();
+        if filter == 2 {
+            // Up is by far the most common (it's what xref streams use) and
+            // the only one without a dependency between bytes of a row
+            add_bytes(&mut current, &previous);
+            decoded.extend_from_slice(&current);
+            previous[..current.len()].copy_from_slice(&current);
+            continue;
+        }
+        for index in 0..current.len() {
+            let left = if index >= pixel_len { current[index - pixel_len] } else { 0 };
+            let up = previous[index];
+            let up_left = if index >= pixel_len { previous[index - pixel_len] } else { 0 };
+            current[index] = current[index].wrapping_add(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return decode_error("unknown PNG filter type"),
+            });
+        }
+        decoded.extend_from_slice(&current);
+        // a short last row only updates the start of `previous`
+        previous[..current.len()].copy_from_slice(&current);
+    }
+    Ok(decoded)
+}
+
+// row[i] += other[i] for the length of `row`.
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn add_bytes(row: &mut [u8], other: &[u8]) {
+    for (byte, &other) in row.iter_mut().zip(other) {
+        *byte = byte.wrapping_add(other);
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn add_bytes(row: &mut [u8], other: &[u8]) {
+    use std::arch::x86_64::{__m128i, _mm_add_epi8, _mm_loadu_si128, _mm_storeu_si128};
+
+    let vectorized = row.len() - row.len() % 16;
+    for start in (0..vectorized).step_by(16) {
+        // SSE2 is part of the x86_64 baseline, and the unaligned loads and
+        // stores stay within both slices
+        unsafe {
+            let a = _mm_loadu_si128(row.as_ptr().add(start) as *const __m128i);
+            let b = _mm_loadu_si128(other.as_ptr().add(start) as *const __m128i);
+            _mm_storeu_si128(row.as_mut_ptr().add(start) as *mut __m128i, _mm_add_epi8(a, b));
+        }
+    }
+    for (byte, &other) in row[vectorized..].iter_mut().zip(&other[vectorized..]) {
+        *byte = byte.wrapping_add(other);
+    }
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance_left = (estimate - left as i16).abs();
+    let distance_up = (estimate - up as i16).abs();
+    let distance_up_left = (estimate - up_left as i16).abs();
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        left
+    } else if distance_up <= distance_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+const LZW_CLEAR: usize = 256;
+const LZW_EOD: usize = 257;
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: usize) -> Option<usize> {
+        if self.position + bits > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 1;
+            value = value << 1 | bit as usize;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+// Variable width (9 to 12 bit) LZW codes, most significant bit first. With
+// EarlyChange the code width grows one code sooner than strictly needed,
+// which is what every writer actually does.
+pub fn lzw_decode(data: &[u8], early_change: bool) -> Result<Vec<u8>> {
+    let early = if early_change { 1 } else { 0 };
+    let mut reader = BitReader { data, position: 0 };
+    let mut table: Vec<Vec<u8>> = Vec::with_capacity(4096);
+    let mut decoded = Vec::new();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut width = 9;
+
+    let reset = |table: &mut Vec<Vec<u8>>| {
+        table.clear();
+        table.extend((0..256).map(|byte| vec![byte as u8]));
+        // placeholders for the clear and end of data codes
+        table.push(Vec::new());
+        table.push(Vec::new());
+    };
+    reset(&mut table);
+
+    while let Some(code) = reader.read(width) {
+        if code == LZW_CLEAR {
+            reset(&mut table);
+            width = 9;
+            previous = None;
+            continue;
+        }
+        if code == LZW_EOD {
+            break;
+        }
+
+        let entry = match previous {
+            _ if code < table.len() => table[code].clone(),
+            Some(ref previous) if code == table.len() => {
+                let mut entry = previous.clone();
+                entry.push(previous[0]);
+                entry
+            },
+            _ => return decode_error("invalid LZWDecode code"),
+        };
+        decoded.extend_from_slice(&entry);
+
+        if let Some(mut previous) = previous {
+            if table.len() < 4096 {
+                previous.push(entry[0]);
+                table.push(previous);
+            }
+        }
+        if table.len() + early >= 1 << width && width < 12 {
+            width += 1;
+        }
+        previous = Some(entry);
+    }
+    Ok(decoded)
+}
+
+#[test]
+fn test_ascii_hex_decode() {
+    assert_eq!(ascii_hex_decode(b"48 65 6C6c6f>").unwrap(), b"Hello".to_vec());
+    assert_eq!(ascii_hex_decode(b"7>").unwrap(), vec![0x70]);
+    assert!(ascii_hex_decode(b"4G>").is_err());
+}
+
+#[test]
+fn test_ascii85_decode() {
+    assert_eq!(ascii85_decode(b"87cURD]i,\"Ebo80~>").unwrap(), b"Hello World!".to_vec());
+    assert_eq!(ascii85_decode(b"<~87cURDZ~>").unwrap(), b"Hello".to_vec());
+    assert_eq!(ascii85_decode(b"z~>").unwrap(), vec![0, 0, 0, 0]);
+    assert!(ascii85_decode(b"s8W-\"~>").is_err());
+}
+
+#[test]
+fn test_run_length_decode() {
+    let data = [2, b'a', b'b', b'c', 253, b'z', 128, b'x'];
+    assert_eq!(run_length_decode(&data).unwrap(), b"abczzzz".to_vec());
+    assert!(run_length_decode(&[5, b'a']).is_err());
+}
+
+#[test]
+fn test_lzw_decode() {
+    // the example from the PDF spec, section 7.4.4.2
+    let data = [0x80, 0x0B, 0x60, 0x50, 0x22, 0x0C, 0x0C, 0x85, 0x01];
+    assert_eq!(lzw_decode(&data, true).unwrap(), vec![45, 45, 45, 45, 45, 65, 45, 45, 45, 66]);
+}
+
+#[test]
+fn test_png_predictor() {
+    // a 3 column xref stream with one row of each filter type
+    let params = DictNode::Dict(hashmap!{
+        "Predictor".to_string() => DictNode::Int(12),
+        "Columns".to_string() => DictNode::Int(3),
+    });
+    let data = vec![
+        0, 1, 2, 3,
+        1, 10, 1, 1,
+        2, 1, 1, 1,
+        3, 2, 2, 2,
+        4, 1, 0, 255,
+    ];
+    assert_eq!(unpredict(Some(&params), data).unwrap(), vec![
+        1, 2, 3,
+        10, 11, 12,
+        11, 12, 13,
+        7, 11, 14,
+        8, 11, 13,
+    ]);
+}
+
+#[test]
+fn test_png_up_predictor_long_rows() {
+    // long enough rows to go through the vectorized path, with a tail
+    let columns = 37;
+    let params = DictNode::Dict(hashmap!{
+        "Predictor".to_string() => DictNode::Int(12),
+        "Columns".to_string() => DictNode::Int(columns),
+    });
+    let mut data = vec![0];
+    data.extend((0..columns).map(|i| (i * 7) as u8));
+    data.push(2);
+    data.extend((0..columns).map(|i| (200 + i) as u8));
+    let decoded = unpredict(Some(&params), data).unwrap();
+    for i in 0..columns as usize {
+        assert_eq!(decoded[columns as usize + i], ((i * 7) as u8).wrapping_add((200 + i) as u8));
+    }
+}
+
+#[test]
+fn test_tiff_predictor() {
+    let params = DictNode::Dict(hashmap!{
+        "Predictor".to_string() => DictNode::Int(2),
+        "Colors".to_string() => DictNode::Int(2),
+        "Columns".to_string() => DictNode::Int(3),
+    });
+    let data = vec![10, 20, 1, 2, 1, 2, 5, 5, 0, 0, 255, 255];
+    assert_eq!(unpredict(Some(&params), data).unwrap(), vec![10, 20, 11, 22, 12, 24, 5, 5, 5, 5, 4, 4]);
+
+    // 4 bit samples, one color
+    let params = DictNode::Dict(hashmap!{
+        "Predictor".to_string() => DictNode::Int(2),
+        "BitsPerComponent".to_string() => DictNode::Int(4),
+        "Columns".to_string() => DictNode::Int(4),
+    });
+    assert_eq!(unpredict(Some(&params), vec![0x31, 0x1F]).unwrap(), vec![0x34, 0x54]);
+}
+
+#[test]
+fn test_filter_chain() {
+    // "Hello" run length encoded, then hex encoded
+    let dict = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::Array(vec![
+            DictNode::name("ASCIIHexDecode"),
+            DictNode::name("RunLengthDecode"),
+        ]),
+    });
+    assert_eq!(chain(&dict).len(), 2);
+    assert_eq!(decode_chain(&dict, b"0448656c6c6f80>").unwrap(), b"Hello".to_vec());
+}
+
+#[test]
+fn test_encoded_image() {
+    let dict = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Image"),
+        "Width".to_string() => DictNode::Int(640),
+        "Height".to_string() => DictNode::Int(480),
+        "ColorSpace".to_string() => DictNode::name("DeviceRGB"),
+        "Filter".to_string() => DictNode::Array(vec![
+            DictNode::name("ASCIIHexDecode"),
+            DictNode::name("DCTDecode"),
+        ]),
+    });
+    let image = encoded_image(&dict, b"FFD8FFE0>").unwrap().unwrap();
+    assert_eq!(image.codec, ImageCodec::Jpeg);
+    assert_eq!(image.codec.extension(), "jpg");
+    assert_eq!((image.width, image.height), (Some(640), Some(480)));
+    assert_eq!(image.color_space, Some(DictNode::name("DeviceRGB")));
+    assert_eq!(image.data, vec![0xFF, 0xD8, 0xFF, 0xE0]);
+    // decoding stops at the image codec as well
+    assert_eq!(decode_chain(&dict, b"FFD8FFE0>").unwrap(), image.data);
+
+    let dict = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::name("FlateDecode"),
+    });
+    assert_eq!(encoded_image(&dict, b"").unwrap(), None);
+}
+
+#[test]
+fn test_unknown_filter() {
+    assert!(decode("/JBIG2Decode", None, b"").is_err());
+}
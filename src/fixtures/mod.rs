@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crypt;
+use crypt::cipher::rc4;
+use crypt::digest::md5;
+use pages::Page;
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use transcode;
+use writer::Writer;
+
+/*
+ * Small PDFs made on the spot for tests, each one showing off something
+ * the reader has to handle, so that tests don't depend on binary files
+ * checked in next to them.
+ *
+ *   let file = Fixture::ObjectStream.write();
+ *   let mut doc = Document::open(&file.path, Options::default()).unwrap();
+ *
+ * They're all the same one page document, a line of Helvetica saying
+ * "Hello, world" and an /Info with a /Title, made the way the fixture's
+ * name says:
+ *
+ *   - Simple: a classic xref table and nothing else
+ *   - XRefStream: an xref stream instead of the table
+ *   - ObjectStream: everything but the content stream in an object
+ *     stream, which takes an xref stream
+ *   - Encrypted: RC4 with a 40 bit key (revision 2), an empty user
+ *     password and OWNER_PASSWORD for the owner
+ *   - Cjk: the text is "日本語", in an Identity-H Type0 font that only
+ *     /ToUnicode maps back to text
+ *   - BrokenLength: the content stream's /Length runs past the end of
+ *     the file
+ *
+ * Files go to the temp directory and are removed when their FixtureFile
+ * is dropped.
+ *
+ * `page` is a Page with no document behind it, for tests of what the
+ * boxes and rotation work out to.
+ */
+
+pub(crate) const OWNER_PASSWORD: &str = "owner";
+
+// The first /ID string, which the encryption key depends on.
+const ID: &[u8; 16] = b"0123456789abcdef";
+
+const CATALOG: u64 = 1;
+const INFO: u64 = 6;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Fixture {
+    Simple,
+    XRefStream,
+    ObjectStream,
+    Encrypted,
+    Cjk,
+    BrokenLength,
+}
+
+pub(crate) const ALL: &[Fixture] = &[
+    Fixture::Simple,
+    Fixture::XRefStream,
+    Fixture::ObjectStream,
+    Fixture::Encrypted,
+    Fixture::Cjk,
+    Fixture::BrokenLength,
+];
+
+// A fixture written out, deleted when dropped.
+pub(crate) struct FixtureFile {
+    pub(crate) path: PathBuf,
+}
+
+impl Drop for FixtureFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// An object of the document, with its data if it's a stream.
+type Object = (u64, DictNode, Option<Vec<u8>>);
+
+impl Fixture {
+    // The text on the page.
+    pub(crate) fn text(self) -> &'static str {
+        match self {
+            Fixture::Cjk => "日本語",
+            _ => "Hello, world",
+        }
+    }
+
+    pub(crate) fn bytes(self) -> Vec<u8> {
+        let mut objects = objects(self == Fixture::Cjk);
+        let mut trailer = HashMap::from([
+            ("Root".to_string(), DictNode::reference(CATALOG)),
+            ("Info".to_string(), DictNode::reference(INFO)),
+            ("ID".to_string(), DictNode::Array(vec![DictNode::HexString(ID.to_vec()), DictNode::HexString(ID.to_vec())])),
+        ]);
+        match self {
+            Fixture::Simple | Fixture::Cjk => table(&objects, trailer),
+            Fixture::XRefStream => xref_stream(&objects, trailer),
+            Fixture::ObjectStream => object_stream(&objects, trailer),
+            Fixture::Encrypted => {
+                let (encrypt, key) = encryption();
+                for (id, object, data) in &mut objects {
+                    let object_key = object_key(&key, *id);
+                    *object = encrypt_strings(&object_key, object.clone());
+                    if let Some(data) = data.as_mut() {
+                        *data = rc4(&object_key, data);
+                    }
+                }
+                trailer.insert("Encrypt".to_string(), encrypt);
+                table(&objects, trailer)
+            },
+            Fixture::BrokenLength => {
+                for (_, object, data) in &mut objects {
+                    if let (Some(data), DictNode::Dict(map)) = (data, object) {
+                        map.insert("Length".to_string(), DictNode::Int(data.len() as i64 + 100_000));
+                    }
+                }
+                table(&objects, trailer)
+            },
+        }
+    }
+
+    pub(crate) fn write(self) -> FixtureFile {
+        let name = format!("nails-pdf-fixture-{}-{}.pdf", process::id(), COUNTER.fetch_add(1, Ordering::SeqCst));
+        let path = env::temp_dir().join(name);
+        fs::write(&path, self.bytes()).unwrap();
+        FixtureFile { path }
+    }
+}
+
+// The first page, object 3, with nothing on it: its media box is
+// `crop_box`, turned `rotate` degrees.
+pub(crate) fn page(crop_box: [f64; 4], rotate: i64) -> Page {
+    Page {
+        index: 0,
+        id: Some(3),
+        dict: DictNode::Dict(HashMap::new()),
+        media_box: crop_box,
+        crop_box,
+        rotate,
+        user_unit: 1.0,
+        resources: DictNode::Dict(HashMap::new()),
+    }
+}
+
+// The document's objects: the catalog, page tree, page, font, content
+// stream and /Info, numbered from 1 in that order, then whatever the CJK
+// font needs.
+fn objects(cjk: bool) -> Vec<Object> {
+    let content = if cjk {
+        b"BT /F1 24 Tf 72 720 Td <000100020003> Tj ET\n".to_vec()
+    } else {
+        b"BT /F1 24 Tf 72 720 Td (Hello, world) Tj ET\n".to_vec()
+    };
+    let font = if cjk {
+        dict(&[
+            ("Type", DictNode::name("Font")),
+            ("Subtype", DictNode::name("Type0")),
+            ("BaseFont", DictNode::name("MS-Mincho")),
+            ("Encoding", DictNode::name("Identity-H")),
+            ("DescendantFonts", DictNode::Array(vec![DictNode::reference(7)])),
+            ("ToUnicode", DictNode::reference(8)),
+        ])
+    } else {
+        dict(&[("Type", DictNode::name("Font")), ("Subtype", DictNode::name("Type1")), ("BaseFont", DictNode::name("Helvetica"))])
+    };
+    let mut objects = vec![
+        (1, dict(&[("Type", DictNode::name("Catalog")), ("Pages", DictNode::reference(2))]), None),
+        (2, dict(&[("Type", DictNode::name("Pages")), ("Kids", DictNode::Array(vec![DictNode::reference(3)])), ("Count", DictNode::Int(1))]), None),
+        (3, dict(&[
+            ("Type", DictNode::name("Page")),
+            ("Parent", DictNode::reference(2)),
+            ("MediaBox", DictNode::Array([0, 0, 612, 792].iter().map(|&value| DictNode::Int(value)).collect())),
+            ("Resources", dict(&[("Font", dict(&[("F1", DictNode::reference(4))]))])),
+            ("Contents", DictNode::reference(5)),
+        ]), None),
+        (4, font, None),
+        (5, stream_dict(&[], content.len()), Some(content)),
+        (INFO, dict(&[("Title", DictNode::LiteralString(b"Fixture".to_vec()))]), None),
+    ];
+    if cjk {
+        let cmap = b"/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+                     /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+                     /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+                     1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n\
+                     3 beginbfchar\n<0001> <65E5>\n<0002> <672C>\n<0003> <8A9E>\nendbfchar\n\
+                     endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n".to_vec();
+        let system_info = dict(&[
+            ("Registry", DictNode::LiteralString(b"Adobe".to_vec())),
+            ("Ordering", DictNode::LiteralString(b"Identity".to_vec())),
+            ("Supplement", DictNode::Int(0)),
+        ]);
+        objects.push((7, dict(&[
+            ("Type", DictNode::name("Font")),
+            ("Subtype", DictNode::name("CIDFontType2")),
+            ("BaseFont", DictNode::name("MS-Mincho")),
+            ("CIDSystemInfo", system_info),
+            ("DW", DictNode::Int(1000)),
+        ]), None));
+        objects.push((8, stream_dict(&[], cmap.len()), Some(cmap)));
+    }
+    objects
+}
+
+fn written(objects: &[Object]) -> Writer {
+    let mut out = Writer::new();
+    for (id, object, data) in objects {
+        match data {
+            Some(data) => out.stream(*id, object, data).unwrap(),
+            None => out.object(*id, object).unwrap(),
+        }
+    }
+    out
+}
+
+fn table(objects: &[Object], trailer: HashMap<String, DictNode>) -> Vec<u8> {
+    written(objects).finish_with(trailer)
+}
+
+fn xref_stream(objects: &[Object], trailer: HashMap<String, DictNode>) -> Vec<u8> {
+    finish_stream(written(objects), trailer)
+}
+
+// The objects that aren't streams in an object stream, numbered after the
+// rest.
+fn object_stream(objects: &[Object], trailer: HashMap<String, DictNode>) -> Vec<u8> {
+    let streams: Vec<Object> = objects.iter().filter(|object| object.2.is_some()).cloned().collect();
+    let mut out = written(&streams);
+    let stream_id = objects.iter().map(|&(id, _, _)| id).max().unwrap_or(0) + 1;
+    let mut header = String::new();
+    let mut body = Vec::new();
+    let mut index = 0;
+    for (id, object, _) in objects.iter().filter(|object| object.2.is_none()) {
+        header.push_str(&format!("{} {} ", id, body.len()));
+        body.extend(format!("{}\n", object).into_bytes());
+        out.xref.set(*id as usize, XRefEntry::compressed(stream_id, index));
+        index += 1;
+    }
+    let first = header.len();
+    let mut data = header.into_bytes();
+    data.extend(body);
+    let dict = stream_dict(&[("Type", DictNode::name("ObjStm")), ("N", DictNode::Int(index as i64)), ("First", DictNode::Int(first as i64))], data.len());
+    out.stream(stream_id, &dict, &data).unwrap();
+    finish_stream(out, trailer)
+}
+
+fn finish_stream(mut out: Writer, trailer: HashMap<String, DictNode>) -> Vec<u8> {
+    let offset = out.data.len() as u64;
+    let section = transcode::format_stream(&out.xref, trailer, offset);
+    out.data.extend(section);
+    out.data
+}
+
+// The /Encrypt dictionary, and the file key it gives with an empty user
+// password (PDF 32000 7.6.3.3, algorithms 2 to 4).
+fn encryption() -> (DictNode, Vec<u8>) {
+    let permissions: i64 = -4;
+    let owner_key = md5(&crypt::pad(OWNER_PASSWORD.as_bytes()));
+    let owner = rc4(&owner_key[..5], &crypt::pad(b""));
+    let mut input = crypt::pad(b"");
+    input.extend_from_slice(&owner);
+    input.extend_from_slice(&(permissions as u32).to_le_bytes());
+    input.extend_from_slice(ID);
+    let key = md5(&input)[..5].to_vec();
+    let user = rc4(&key, &crypt::pad(b""));
+    let encrypt = dict(&[
+        ("Filter", DictNode::name("Standard")),
+        ("V", DictNode::Int(1)),
+        ("R", DictNode::Int(2)),
+        ("Length", DictNode::Int(40)),
+        ("P", DictNode::Int(permissions)),
+        ("O", DictNode::HexString(owner)),
+        ("U", DictNode::HexString(user)),
+    ]);
+    (encrypt, key)
+}
+
+// What the strings and streams of object `id` are encrypted with.
+fn object_key(key: &[u8], id: u64) -> Vec<u8> {
+    let mut input = key.to_vec();
+    input.extend_from_slice(&(id as u32).to_le_bytes()[..3]);
+    input.extend_from_slice(&[0, 0]);
+    md5(&input)[..key.len() + 5].to_vec()
+}
+
+fn encrypt_strings(key: &[u8], node: DictNode) -> DictNode {
+    match node {
+        DictNode::LiteralString(value) | DictNode::HexString(value) => DictNode::HexString(rc4(key, &value)),
+        DictNode::Array(items) => DictNode::Array(items.into_iter().map(|item| encrypt_strings(key, item)).collect()),
+        DictNode::Dict(map) => DictNode::Dict(map.into_iter().map(|(name, value)| (name, encrypt_strings(key, value))).collect()),
+        node => node,
+    }
+}
+
+fn dict(entries: &[(&str, DictNode)]) -> DictNode {
+    DictNode::Dict(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+}
+
+fn stream_dict(entries: &[(&str, DictNode)], length: usize) -> DictNode {
+    let mut dict = dict(entries);
+    if let DictNode::Dict(ref mut map) = dict {
+        map.insert("Length".to_string(), DictNode::Int(length as i64));
+    }
+    dict
+}
+
+#[test]
+fn test_encryption() {
+    use crypt::SecurityHandler;
+
+    let (encrypt, key) = encryption();
+    let handler = SecurityHandler::new(&encrypt, None, ID, b"").unwrap();
+    assert!(!handler.owner);
+    assert_eq!(handler.decrypt(5, 0, &rc4(&object_key(&key, 5), b"BT ET")), b"BT ET");
+    assert!(SecurityHandler::new(&encrypt, None, ID, OWNER_PASSWORD.as_bytes()).unwrap().owner);
+    assert!(SecurityHandler::new(&encrypt, None, ID, b"wrong").is_err());
+}
+
+#[test]
+fn test_fixtures() {
+    use document::{Document, Options};
+    use pages;
+    use text;
+
+    for &fixture in ALL {
+        let file = fixture.write();
+        let mut doc = Document::open(&file.path, Options::default()).unwrap();
+        assert_eq!(doc.is_encrypted(), fixture == Fixture::Encrypted, "{:?}", fixture);
+        let pages = pages::pages(&mut doc).unwrap();
+        assert_eq!(pages.len(), 1, "{:?}", fixture);
+        let extracted = text::extract(&mut doc, &pages[0]).unwrap();
+        assert!(extracted.contains(fixture.text()), "{:?}: {:?}", fixture, extracted);
+    }
+}
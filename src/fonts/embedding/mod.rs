@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use fonts::standard::{StandardFont, FIRST_CODE, LAST_CODE};
+use fonts::{self, Font, SYMBOLIC};
+use merge::Copier;
+use writer::Writer;
+use parsers::cos::DictNode;
+use text::encoding::{glyph_char, BaseEncoding};
+
+/*
+ * Taking embedded fonts out of a document, and putting standard ones in.
+ *
+ *   let replaced = embedding::unembed(input, smaller)?;
+ *   let programs = vec![(StandardFont::from_name("Helvetica").unwrap(), fs::read("NimbusSans-Regular.pfb")?)];
+ *   let embedded = embedding::embed(input, portable, &programs)?;
+ *
+ * `unembed` swaps embedded simple fonts for the standard font closest to
+ * them, where that font's metrics are close enough: the same family by
+ * name (Arial is Helvetica) or by descriptor flags, and widths within 5%
+ * on average, so Liberation Sans or Arial go and Calibri stays. The font
+ * keeps its /Widths, which is where viewers put glyphs, so text doesn't
+ * move even if the standard font isn't a perfect match. Left as they are:
+ *
+ *   - composite and Type 3 fonts, symbolic fonts
+ *   - fonts without an /Encoding naming glyphs the standard fonts have, a
+ *     subset's made-up encoding in particular
+ *   - fonts written directly into a resource dictionary
+ *
+ * `embed` does the reverse for fonts naming a standard font, or one of its
+ * aliases, without a program: the program given for that standard font is
+ * embedded with a descriptor from the standard font's metrics, and fonts
+ * with no /Widths get the standard ones (printable ASCII only, see
+ * `standard`). Font files can be:
+ *
+ *   - Type 1 as PFB, whose segment headers come off
+ *   - TrueType, the font becomes a /TrueType one in WinAnsiEncoding unless
+ *     it has an encoding already
+ *   - OpenType with CFF outlines, or bare CFF
+ *
+ * Both write the whole document over, as what its trailer reaches: taking
+ * a font program out only shrinks a file that's rewritten. Encrypted
+ * documents are refused.
+ */
+
+// A font file, ready to embed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    // the descriptor entry it goes in, /FontFile, /FontFile2 or /FontFile3
+    pub key: &'static str,
+    pub dict: DictNode,
+    pub data: Vec<u8>,
+}
+
+impl Program {
+    // Recognises the kind of font file `data` is.
+    pub fn from_file(data: &[u8]) -> Result<Program> {
+        let length = |length: usize| DictNode::Int(length as i64);
+        if data.first() == Some(&0x80) {
+            let (data, lengths) = unwrap_pfb(data)?;
+            let dict = HashMap::from([
+                ("Length".to_string(), length(data.len())),
+                ("Length1".to_string(), length(lengths[0])),
+                ("Length2".to_string(), length(lengths[1])),
+                ("Length3".to_string(), length(lengths[2])),
+            ]);
+            return Ok(Program { key: "FontFile", dict: DictNode::Dict(dict), data });
+        }
+        let (key, subtype) = if data.starts_with(b"\x00\x01\x00\x00") || data.starts_with(b"true") {
+            ("FontFile2", None)
+        } else if data.starts_with(b"OTTO") {
+            ("FontFile3", Some("/OpenType"))
+        } else if data.starts_with(b"\x01\x00") {
+            ("FontFile3", Some("/Type1C"))
+        } else {
+            return Err(PdfError::Syntax { what: "font file", position: 0 });
+        };
+        let mut dict = HashMap::from([("Length".to_string(), length(data.len()))]);
+        match subtype {
+            Some(subtype) => dict.insert("Subtype".to_string(), DictNode::Str(subtype.to_string())),
+            None => dict.insert("Length1".to_string(), length(data.len())),
+        };
+        Ok(Program { key, dict: DictNode::Dict(dict), data: data.to_vec() })
+    }
+}
+
+// A font dictionary to write in place of object `id`, with its references
+// still numbered as in the source, and a descriptor for it with the
+// program (an index into the programs written) to go in it.
+struct Replacement {
+    id: u64,
+    dict: DictNode,
+    descriptor: Option<(HashMap<String, DictNode>, usize)>,
+}
+
+// Replaces embedded fonts with standard ones where they can pass for them,
+// returning the names of the fonts replaced and what with.
+pub fn unembed(input: &Path, output: &Path) -> Result<Vec<(String, StandardFont)>> {
+    let mut doc = open(input)?;
+    let mut replacements = Vec::new();
+    let mut replaced = Vec::new();
+    for font in fonts::fonts(&mut doc)? {
+        let (id, standard) = match (font.id, substitute(&font)) {
+            (Some(id), Some(standard)) => (id, standard),
+            _ => continue,
+        };
+        let mut dict = doc.resolve(&DictNode::reference(id))?.as_dict().cloned().unwrap_or_default();
+        dict.insert("Subtype".to_string(), DictNode::name("Type1"));
+        dict.insert("BaseFont".to_string(), DictNode::name(standard.name()));
+        dict.remove("FontDescriptor");
+        replacements.push(Replacement { id, dict: DictNode::Dict(dict), descriptor: None });
+        replaced.push((font.base_font, standard));
+    }
+    rewrite(&mut doc, output, replacements, &[])?;
+    Ok(replaced)
+}
+
+// Embeds `programs` in the fonts that name their standard font and have
+// none, returning the names of the fonts embedded and what as.
+pub fn embed(input: &Path, output: &Path, programs: &[(StandardFont, Vec<u8>)]) -> Result<Vec<(String, StandardFont)>> {
+    let files = programs.iter().map(|(_, data)| Program::from_file(data)).collect::<Result<Vec<Program>>>()?;
+    let mut doc = open(input)?;
+    let mut replacements = Vec::new();
+    let mut embedded = Vec::new();
+    for font in fonts::fonts(&mut doc)? {
+        let simple = font.subtype == "Type1" || font.subtype == "TrueType";
+        let standard = StandardFont::from_name(&font.base_font);
+        let index = programs.iter().position(|&(program_font, _)| Some(program_font) == standard);
+        let (id, standard, index) = match (font.id, standard, index) {
+            (Some(id), Some(standard), Some(index)) if simple && !font.is_embedded() => (id, standard, index),
+            _ => continue,
+        };
+        let mut dict = doc.resolve(&DictNode::reference(id))?.as_dict().cloned().unwrap_or_default();
+        let truetype = files[index].key == "FontFile2";
+        dict.insert("Subtype".to_string(), DictNode::Str(if truetype { "/TrueType" } else { "/Type1" }.to_string()));
+        if truetype && !dict.contains_key("Encoding") {
+            dict.insert("Encoding".to_string(), DictNode::name("WinAnsiEncoding"));
+        }
+        if !dict.contains_key("Widths") {
+            let widths = (FIRST_CODE..=LAST_CODE).map(|code| DictNode::Int(standard.width(code).unwrap_or(0.0) as i64)).collect();
+            dict.insert("FirstChar".to_string(), DictNode::Int(FIRST_CODE as i64));
+            dict.insert("LastChar".to_string(), DictNode::Int(LAST_CODE as i64));
+            dict.insert("Widths".to_string(), DictNode::Array(widths));
+        }
+        replacements.push(Replacement { id, dict: DictNode::Dict(dict), descriptor: Some((descriptor(&font.base_font, standard), index)) });
+        embedded.push((font.base_font, standard));
+    }
+    rewrite(&mut doc, output, replacements, &files)?;
+    Ok(embedded)
+}
+
+fn open(input: &Path) -> Result<Document> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    Document::open(input, options)
+}
+
+// The standard font embedded `font` can be replaced with, if any.
+fn substitute(font: &Font) -> Option<StandardFont> {
+    let descriptor = font.descriptor.as_ref()?;
+    let simple = ["Type1", "MMType1", "TrueType"].contains(&font.subtype.as_str());
+    if !simple || !font.is_embedded() || descriptor.flags & SYMBOLIC != 0 {
+        return None;
+    }
+    let overridden = overridden_codes(font.encoding.as_ref()?)?;
+    let widths: Vec<(u8, f64)> = (FIRST_CODE..=LAST_CODE)
+        .filter(|code| !overridden.contains(code))
+        .filter_map(|code| {
+            let index = code as i64 - font.first_char;
+            if index < 0 {
+                return None;
+            }
+            font.widths.get(index as usize).map(|&width| (code, width))
+        })
+        .collect();
+    let standard = StandardFont::from_name(&font.base_font)
+        .unwrap_or_else(|| StandardFont::from_flags(descriptor.flags, descriptor.italic_angle, &font.base_font));
+    Some(standard).filter(|standard| standard.fits(&widths))
+}
+
+// The codes a simple font's encoding moves away from the standard
+// encodings, None if a standard font couldn't follow it.
+fn overridden_codes(encoding: &DictNode) -> Option<HashSet<u8>> {
+    let mut codes = HashSet::new();
+    let map = match *encoding {
+        DictNode::Dict(ref map) => map,
+        _ => return BaseEncoding::from_name(encoding.as_name()?).map(|_| codes),
+    };
+    if let Some(base) = map.get("BaseEncoding") {
+        BaseEncoding::from_name(base.as_name()?)?;
+    }
+    let mut code = 0;
+    for item in map.get("Differences").and_then(DictNode::as_array).unwrap_or(&[]) {
+        match *item {
+            DictNode::Int(start) => code = start,
+            _ => {
+                glyph_char(item.as_name()?)?;
+                if (0..256).contains(&code) {
+                    codes.insert(code as u8);
+                }
+                code += 1;
+            },
+        }
+    }
+    // the quotes differ between the standard encodings
+    codes.extend(b"'`");
+    Some(codes)
+}
+
+// A descriptor for `standard` named `name`, without its program.
+fn descriptor(name: &str, standard: StandardFont) -> HashMap<String, DictNode> {
+    let metrics = standard.metrics();
+    let number = |value: f64| if value.fract() == 0.0 { DictNode::Int(value as i64) } else { DictNode::Float(value) };
+    HashMap::from([
+        ("Type".to_string(), DictNode::name("FontDescriptor")),
+        ("FontName".to_string(), DictNode::name(name)),
+        ("Flags".to_string(), DictNode::Int(standard.flags())),
+        ("FontBBox".to_string(), DictNode::Array(metrics.font_bbox.iter().map(|&value| number(value)).collect())),
+        ("ItalicAngle".to_string(), number(metrics.italic_angle)),
+        ("Ascent".to_string(), number(metrics.ascent)),
+        ("Descent".to_string(), number(metrics.descent)),
+        ("CapHeight".to_string(), number(metrics.cap_height)),
+        ("StemV".to_string(), number(metrics.stem_v)),
+    ])
+}
+
+// The Type 1 program in a PFB file, and the lengths of its cleartext,
+// encrypted and trailing parts.
+fn unwrap_pfb(data: &[u8]) -> Result<(Vec<u8>, [usize; 3])> {
+    let mut program = Vec::with_capacity(data.len());
+    let mut lengths = [0; 3];
+    let mut position = 0;
+    // segments are 0x80, a type (1 text, 2 binary, 3 the end) and a little
+    // endian length
+    while let Some(&[0x80, kind]) = data.get(position..position + 2) {
+        if kind == 3 {
+            break;
+        }
+        let header = data.get(position + 2..position + 6).ok_or(PdfError::Syntax { what: "PFB segment", position })?;
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let start = position + 6;
+        let segment = data.get(start..start.saturating_add(length)).ok_or(PdfError::Syntax { what: "PFB segment", position })?;
+        let part = match kind {
+            1 if lengths[1] == 0 => 0,
+            1 => 2,
+            2 => 1,
+            _ => return Err(PdfError::Syntax { what: "PFB segment", position }),
+        };
+        lengths[part] += length;
+        program.extend_from_slice(segment);
+        position = start + length;
+    }
+    if lengths[0] == 0 || lengths[1] == 0 {
+        return Err(PdfError::Syntax { what: "PFB file", position: 0 });
+    }
+    Ok((program, lengths))
+}
+
+// Writes `doc` over into `output` with the font replacements made.
+// Nothing but what the trailer's /Root, /Info and /ID reach is copied, so
+// programs no font refers to any more are left behind.
+fn rewrite(doc: &mut Document, output: &Path, replacements: Vec<Replacement>, programs: &[Program]) -> Result<()> {
+    let mut out = Writer::new();
+    let mut program_ids = Vec::new();
+    for program in programs {
+        let id = out.reserve();
+        out.stream(id, &program.dict, &program.data)?;
+        program_ids.push(id);
+    }
+    let trailer = doc.trailer.as_dict().cloned().unwrap_or_default();
+    let mut copier = Copier::new(doc);
+    let copies: Vec<u64> = replacements.iter()
+        .map(|replacement| {
+            let copy = out.reserve();
+            copier.ids.insert(replacement.id, copy);
+            copy
+        })
+        .collect();
+    for (replacement, copy) in replacements.into_iter().zip(copies) {
+        let mut dict = copier.renumber(&replacement.dict, &mut out);
+        if let (Some((mut descriptor, index)), DictNode::Dict(ref mut map)) = (replacement.descriptor, &mut dict) {
+            descriptor.insert(programs[index].key.to_string(), DictNode::reference(program_ids[index]));
+            let descriptor_id = out.reserve();
+            out.object(descriptor_id, &DictNode::Dict(descriptor))?;
+            map.insert("FontDescriptor".to_string(), DictNode::reference(descriptor_id));
+        }
+        out.object(copy, &dict)?;
+    }
+
+    let mut kept = HashMap::new();
+    for key in &["Root", "Info", "ID"] {
+        if let Some(value) = trailer.get(*key) {
+            kept.insert(key.to_string(), copier.renumber(value, &mut out));
+        }
+    }
+    if !kept.contains_key("Root") {
+        return Err(PdfError::Syntax { what: "document catalog", position: 0 });
+    }
+    copier.copy_pending(&mut out)?;
+    fs::write(output, out.finish_with(kept))?;
+    Ok(())
+}
+
+#[test]
+fn test_program() {
+    let mut pfb = vec![0x80, 1, 5, 0, 0, 0];
+    pfb.extend_from_slice(b"%!PS ");
+    pfb.extend_from_slice(&[0x80, 2, 3, 0, 0, 0, 0xde, 0xad, 0xbe]);
+    pfb.extend_from_slice(&[0x80, 1, 2, 0, 0, 0]);
+    pfb.extend_from_slice(b"00");
+    pfb.extend_from_slice(&[0x80, 3]);
+    let program = Program::from_file(&pfb).unwrap();
+    assert_eq!(program.key, "FontFile");
+    assert_eq!(program.data, b"%!PS \xde\xad\xbe00");
+    assert_eq!(program.dict.to_string(), "<< /Length 10 /Length1 5 /Length2 3 /Length3 2 >>");
+    assert!(Program::from_file(&pfb[..8]).is_err());
+
+    assert_eq!(Program::from_file(b"\x00\x01\x00\x00tables").unwrap().dict.to_string(), "<< /Length 10 /Length1 10 >>");
+    assert_eq!(Program::from_file(b"OTTO").unwrap().dict.to_string(), "<< /Length 4 /Subtype /OpenType >>");
+    assert!(Program::from_file(b"%!PS-AdobeFont-1.0").is_err());
+}
+
+#[test]
+fn test_substitute() {
+    use fonts::{FontDescriptor, ProgramKind, NONSYMBOLIC};
+
+    let widths: Vec<DictNode> = (32..127u8).map(|code| {
+        DictNode::Int(StandardFont::from_name("Helvetica").unwrap().width(code).unwrap() as i64)
+    }).collect();
+    let mut font = Font::from_dict(Some(4), &DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("TrueType"),
+        "BaseFont".to_string() => DictNode::name("ABCDEF+LiberationSans"),
+        "Encoding".to_string() => DictNode::name("WinAnsiEncoding"),
+        "FirstChar".to_string() => DictNode::Int(32),
+        "Widths".to_string() => DictNode::Array(widths),
+    }));
+    font.descriptor = Some(FontDescriptor {
+        flags: NONSYMBOLIC,
+        font_file: Some((ProgramKind::TrueType, DictNode::reference(5))),
+        ..FontDescriptor::default()
+    });
+    assert_eq!(substitute(&font).map(StandardFont::name), Some("Helvetica"));
+
+    // a serif flag makes it Times, which Helvetica's widths don't fit
+    font.descriptor.as_mut().unwrap().flags |= fonts::SERIF;
+    assert_eq!(substitute(&font), None);
+
+    font.descriptor.as_mut().unwrap().flags = NONSYMBOLIC;
+    font.encoding = Some(DictNode::Dict(hashmap!{
+        "Differences".to_string() => DictNode::Array(vec![DictNode::Int(65), DictNode::name("g17")]),
+    }));
+    assert_eq!(substitute(&font), None);
+    font.encoding = None;
+    assert_eq!(substitute(&font), None);
+
+    let encoding = DictNode::Dict(hashmap!{
+        "BaseEncoding".to_string() => DictNode::name("WinAnsiEncoding"),
+        "Differences".to_string() => DictNode::Array(vec![DictNode::Int(65), DictNode::name("Aring"), DictNode::name("eacute")]),
+    });
+    let mut codes: Vec<u8> = overridden_codes(&encoding).unwrap().into_iter().collect();
+    codes.sort();
+    assert_eq!(codes, vec![b'\'', b'A', b'B', b'`']);
+}
@@ -0,0 +1,489 @@
+use std::collections::{HashMap, HashSet};
+
+use document::Document;
+use error::Result;
+use pages;
+use parsers::cos::DictNode;
+
+pub mod embedding;
+pub mod standard;
+pub mod subset;
+pub mod truetype;
+
+/*
+ * The fonts a document uses, as described by their /Font and
+ * /FontDescriptor dictionaries, and the font programs embedded in them.
+ *
+ *   for font in fonts::fonts(&mut doc)? {
+ *       if let Some((kind, data)) = font.program(&mut doc)? {
+ *           fs::write(format!("{}.{}", font.base_font, kind.extension()), data)?;
+ *       }
+ *   }
+ *
+ * The program lives in one of three streams of the descriptor:
+ *
+ *   /FontFile   Type 1, cleartext and eexec encrypted parts back to back
+ *               (a PFB file without its segment headers)
+ *   /FontFile2  TrueType
+ *   /FontFile3  whatever its /Subtype says: bare CFF for /Type1C and
+ *               /CIDFontType0C, a whole OpenType file for /OpenType
+ *
+ * Composite (Type0) fonts have no descriptor of their own, it's on their
+ * descendant CIDFont. The descendant has the widths too, by CID rather
+ * than by code, in a compact /W array:
+ *
+ *   /W [1 [500 600 700]    CIDs 1, 2 and 3
+ *       10 20 1000]        CIDs 10 to 20 all 1000 wide
+ *
+ * and a /CIDToGIDMap, which picks glyphs out of an embedded TrueType
+ * program and has nothing to do with text or widths.
+ *
+ * Fonts are found through page resources, and the resources of form
+ * XObjects drawn on pages; Type 3 glyph procedures and annotation
+ * appearances aren't looked into.
+ */
+
+// /Flags bits, numbered from 1 in the spec.
+pub const FIXED_PITCH: i64 = 1;
+pub const SERIF: i64 = 1 << 1;
+pub const SYMBOLIC: i64 = 1 << 2;
+pub const SCRIPT: i64 = 1 << 3;
+pub const NONSYMBOLIC: i64 = 1 << 5;
+pub const ITALIC: i64 = 1 << 6;
+pub const ALL_CAP: i64 = 1 << 16;
+pub const SMALL_CAP: i64 = 1 << 17;
+pub const FORCE_BOLD: i64 = 1 << 18;
+
+// Nesting of form XObjects we follow looking for fonts.
+const MAX_FORM_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProgramKind {
+    Type1,
+    TrueType,
+    // bare CFF, for simple and CID keyed fonts
+    Type1C,
+    CIDFontType0C,
+    OpenType,
+}
+
+impl ProgramKind {
+    // What the descriptor entry holding the program says about it. Unknown
+    // /FontFile3 subtypes are None.
+    pub fn from_entry(key: &str, subtype: Option<&str>) -> Option<ProgramKind> {
+        match (key, subtype) {
+            ("FontFile", _) => Some(ProgramKind::Type1),
+            ("FontFile2", _) => Some(ProgramKind::TrueType),
+            ("FontFile3", Some("Type1C")) => Some(ProgramKind::Type1C),
+            ("FontFile3", Some("CIDFontType0C")) => Some(ProgramKind::CIDFontType0C),
+            ("FontFile3", Some("OpenType")) => Some(ProgramKind::OpenType),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ProgramKind::Type1 => "t1",
+            ProgramKind::TrueType => "ttf",
+            ProgramKind::Type1C | ProgramKind::CIDFontType0C => "cff",
+            ProgramKind::OpenType => "otf",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FontDescriptor {
+    pub font_name: String,
+    pub flags: i64,
+    pub font_bbox: Option<[f64; 4]>,
+    pub italic_angle: f64,
+    pub ascent: f64,
+    pub descent: f64,
+    pub cap_height: f64,
+    pub stem_v: f64,
+    pub missing_width: f64,
+    // the stream holding the embedded program, as a reference
+    pub font_file: Option<(ProgramKind, DictNode)>,
+}
+
+impl FontDescriptor {
+    // `dict` resolved, except for the /FontFile entries which are kept as
+    // references to their streams. The kind of a /FontFile3 program is
+    // read from its stream's dictionary, given as `font_file3_subtype`.
+    pub fn from_dict(dict: &DictNode, font_file3_subtype: Option<&str>) -> FontDescriptor {
+        let number = |key| dict.get(key).and_then(DictNode::as_float).unwrap_or(0.0);
+        let font_file = ["FontFile", "FontFile2", "FontFile3"].iter()
+            .filter_map(|&key| {
+                let stream = dict.get(key)?;
+                let kind = ProgramKind::from_entry(key, font_file3_subtype)?;
+                Some((kind, stream.clone()))
+            })
+            .next();
+        FontDescriptor {
+            font_name: dict.get("FontName").and_then(DictNode::as_name).unwrap_or("").to_string(),
+            flags: dict.get("Flags").and_then(DictNode::as_int).unwrap_or(0),
+            font_bbox: dict.get("FontBBox").and_then(DictNode::as_rect),
+            italic_angle: number("ItalicAngle"),
+            ascent: number("Ascent"),
+            descent: number("Descent"),
+            cap_height: number("CapHeight"),
+            stem_v: number("StemV"),
+            missing_width: number("MissingWidth"),
+            font_file,
+        }
+    }
+
+    pub fn read(doc: &mut Document, node: &DictNode) -> Result<Option<FontDescriptor>> {
+        let dict = doc.resolve(node)?;
+        let map = match dict.as_dict() {
+            Some(map) => map,
+            None => return Ok(None),
+        };
+        let mut resolved = HashMap::new();
+        for (key, value) in map {
+            let value = if key.starts_with("FontFile") { value.clone() } else { doc.resolve_all(value)? };
+            resolved.insert(key.clone(), value);
+        }
+        let font_file3_subtype = match map.get("FontFile3") {
+            Some(stream) => doc.resolve(stream)?.get("Subtype").and_then(DictNode::as_name).map(str::to_string),
+            None => None,
+        };
+        Ok(Some(FontDescriptor::from_dict(&DictNode::Dict(resolved), font_file3_subtype.as_deref())))
+    }
+
+    pub fn is_symbolic(&self) -> bool {
+        self.flags & SYMBOLIC != 0
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.flags & ITALIC != 0
+    }
+
+    pub fn is_fixed_pitch(&self) -> bool {
+        self.flags & FIXED_PITCH != 0
+    }
+}
+
+// A CIDFont's glyph widths by CID, in thousandths of an em.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CidWidths {
+    // /DW, for CIDs /W doesn't list
+    pub default_width: f64,
+    // (first CID, last CID, widths), with a single width when it's the
+    // same for the whole range
+    ranges: Vec<(u32, u32, Vec<f64>)>,
+}
+
+impl Default for CidWidths {
+    fn default() -> CidWidths {
+        CidWidths { default_width: 1000.0, ranges: Vec::new() }
+    }
+}
+
+impl CidWidths {
+    // From a descendant font, resolved all the way down. Entries that
+    // don't make sense are skipped, along with the rest of the array.
+    pub fn from_dict(font: &DictNode) -> CidWidths {
+        let mut widths = CidWidths::default();
+        if let Some(width) = font.get("DW").and_then(DictNode::as_float) {
+            widths.default_width = width;
+        }
+        let mut items = font.get("W").and_then(DictNode::as_array).unwrap_or(&[]).iter();
+        while let Some(first) = items.next().and_then(DictNode::as_int).filter(|&cid| cid >= 0) {
+            let range = match items.next() {
+                Some(DictNode::Array(list)) => {
+                    let list: Vec<f64> = list.iter().filter_map(DictNode::as_float).collect();
+                    if list.is_empty() {
+                        continue;
+                    }
+                    (first as u32, (first as u32).saturating_add(list.len() as u32 - 1), list)
+                },
+                Some(&DictNode::Int(last)) if last >= first => match items.next().and_then(DictNode::as_float) {
+                    Some(width) => (first as u32, last.min(u32::MAX as i64) as u32, vec![width]),
+                    None => break,
+                },
+                _ => break,
+            };
+            widths.ranges.push(range);
+        }
+        widths
+    }
+
+    pub fn get(&self, cid: u32) -> f64 {
+        self.ranges.iter()
+            .find(|&&(first, last, _)| first <= cid && cid <= last)
+            .map_or(self.default_width, |(first, _, widths)| match widths.len() {
+                1 => widths[0],
+                _ => widths.get((cid - first) as usize).cloned().unwrap_or(self.default_width),
+            })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Font {
+    // None for fonts written directly into a resource dictionary
+    pub id: Option<u64>,
+    // Type1, MMType1, TrueType, Type3, Type0, or CIDFontType0/2 for
+    // descendants
+    pub subtype: String,
+    // without the slash, empty for Type 3 fonts that have none
+    pub base_font: String,
+    // resolved
+    pub encoding: Option<DictNode>,
+    pub first_char: i64,
+    pub widths: Vec<f64>,
+    // the ToUnicode stream, as a reference
+    pub to_unicode: Option<DictNode>,
+    pub descriptor: Option<FontDescriptor>,
+    // the CIDFont of a Type0 font
+    pub descendant: Option<Box<Font>>,
+    // for CIDFonts
+    pub cid_widths: Option<CidWidths>,
+    // /Identity, or a reference to the stream of glyph numbers by CID
+    pub cid_to_gid_map: Option<DictNode>,
+}
+
+impl Font {
+    // `dict` resolved all the way down, except for /ToUnicode and
+    // /CIDToGIDMap. The
+    // descriptor and descendant are filled in separately.
+    pub fn from_dict(id: Option<u64>, dict: &DictNode) -> Font {
+        let widths = dict.get("Widths").and_then(DictNode::as_array).unwrap_or(&[]);
+        let subtype = dict.get("Subtype").and_then(DictNode::as_name).unwrap_or("Type1");
+        Font {
+            id,
+            subtype: subtype.to_string(),
+            base_font: dict.get("BaseFont").and_then(DictNode::as_name).unwrap_or("").to_string(),
+            encoding: dict.get("Encoding").cloned(),
+            first_char: dict.get("FirstChar").and_then(DictNode::as_int).unwrap_or(0),
+            widths: widths.iter().map(|width| width.as_float().unwrap_or(0.0)).collect(),
+            to_unicode: dict.get("ToUnicode").cloned(),
+            descriptor: None,
+            descendant: None,
+            cid_widths: match subtype {
+                "CIDFontType0" | "CIDFontType2" => Some(CidWidths::from_dict(dict)),
+                _ => None,
+            },
+            cid_to_gid_map: dict.get("CIDToGIDMap").cloned(),
+        }
+    }
+
+    // The font `node` is or points at, None if it isn't a dictionary.
+    pub fn read(doc: &mut Document, node: &DictNode) -> Result<Option<Font>> {
+        Font::read_font(doc, node, true)
+    }
+
+    // Descendants only get read for the top level font, a descendant
+    // listing its parent would go round in circles otherwise.
+    fn read_font(doc: &mut Document, node: &DictNode, top_level: bool) -> Result<Option<Font>> {
+        let id = match *node {
+            DictNode::ObjectReference(id, _) if id >= 0 => Some(id as u64),
+            _ => None,
+        };
+        let dict = doc.resolve(node)?;
+        let map = match dict.as_dict() {
+            Some(map) => map,
+            None => return Ok(None),
+        };
+        let mut resolved = HashMap::new();
+        for key in &["Subtype", "BaseFont", "Encoding", "FirstChar", "Widths", "W", "DW"] {
+            if let Some(value) = map.get(*key) {
+                resolved.insert(key.to_string(), doc.resolve_all(value)?);
+            }
+        }
+        for key in &["ToUnicode", "CIDToGIDMap"] {
+            if let Some(value) = map.get(*key) {
+                resolved.insert(key.to_string(), value.clone());
+            }
+        }
+        let mut font = Font::from_dict(id, &DictNode::Dict(resolved));
+        if let Some(descriptor) = map.get("FontDescriptor") {
+            font.descriptor = FontDescriptor::read(doc, descriptor)?;
+        }
+        if top_level && font.subtype == "Type0" {
+            if let Some(DictNode::Array(descendants)) = dict.get_resolved(doc, "DescendantFonts")? {
+                if let Some(descendant) = descendants.first() {
+                    font.descendant = Font::read_font(doc, descendant, false)?.map(Box::new);
+                }
+            }
+        }
+        Ok(Some(font))
+    }
+
+    // The descriptor that goes with the font's glyphs, the descendant's for
+    // Type0 fonts.
+    pub fn descriptor(&self) -> Option<&FontDescriptor> {
+        match self.descendant {
+            Some(ref descendant) => descendant.descriptor.as_ref(),
+            None => self.descriptor.as_ref(),
+        }
+    }
+
+    pub fn is_embedded(&self) -> bool {
+        self.descriptor().is_some_and(|descriptor| descriptor.font_file.is_some())
+    }
+
+    // Subsets are named with a tag of six capital letters and a plus,
+    // "ABCDEF+Helvetica".
+    pub fn is_subset(&self) -> bool {
+        let name = self.base_font.as_bytes();
+        name.len() > 7 && name[6] == b'+' && name[..6].iter().all(u8::is_ascii_uppercase)
+    }
+
+    // The decoded embedded program, None if the font isn't embedded.
+    pub fn program(&self, doc: &mut Document) -> Result<Option<(ProgramKind, Vec<u8>)>> {
+        let (kind, stream) = match self.descriptor().and_then(|descriptor| descriptor.font_file.clone()) {
+            Some(font_file) => font_file,
+            None => return Ok(None),
+        };
+        Ok(doc.referenced_stream(&stream)?.map(|(_, data)| (kind, data)))
+    }
+}
+
+// Every font used by the document's pages, each once, in the order they
+// first turn up.
+pub fn fonts(doc: &mut Document) -> Result<Vec<Font>> {
+    // the index cache may have been through the resources already
+    let nodes = match doc.cached_fonts() {
+        Some(fonts) => fonts.into_iter().map(|(id, generation)| DictNode::ObjectReference(id, generation)).collect(),
+        None => font_nodes(doc)?,
+    };
+    let mut fonts = Vec::new();
+    for node in nodes {
+        if let Some(font) = Font::read(doc, &node)? {
+            fonts.push(font);
+        }
+    }
+    Ok(fonts)
+}
+
+// The font dictionaries of `fonts`, as they appear in the resources:
+// references mostly, but fonts written straight into a resource dictionary
+// happen too.
+pub(crate) fn font_nodes(doc: &mut Document) -> Result<Vec<DictNode>> {
+    let mut finder = Finder { seen_fonts: HashSet::new(), seen_resources: HashSet::new(), fonts: Vec::new() };
+    for page in pages::pages(doc)? {
+        finder.resources(doc, &page.resources, 0)?;
+    }
+    Ok(finder.fonts)
+}
+
+struct Finder {
+    // font and form XObject references already looked at
+    seen_fonts: HashSet<(i64, i64)>,
+    seen_resources: HashSet<(i64, i64)>,
+    fonts: Vec<DictNode>,
+}
+
+impl Finder {
+    fn resources(&mut self, doc: &mut Document, resources: &DictNode, depth: usize) -> Result<()> {
+        if let Some(DictNode::Dict(fonts)) = resources.get_resolved(doc, "Font")? {
+            for node in fonts.values() {
+                if let Some(reference) = node.as_reference() {
+                    if !self.seen_fonts.insert(reference) {
+                        continue;
+                    }
+                }
+                self.fonts.push(node.clone());
+            }
+        }
+        if depth >= MAX_FORM_DEPTH {
+            return Ok(());
+        }
+        if let Some(DictNode::Dict(xobjects)) = resources.get_resolved(doc, "XObject")? {
+            for node in xobjects.values() {
+                let reference = match node.as_reference() {
+                    Some(reference) => reference,
+                    None => continue,
+                };
+                if !self.seen_resources.insert(reference) {
+                    continue;
+                }
+                let xobject = doc.resolve(node)?;
+                if !xobject.get("Subtype").is_some_and(|subtype| subtype.is_name("Form")) {
+                    continue;
+                }
+                if let Some(form_resources) = xobject.get_resolved(doc, "Resources")? {
+                    self.resources(doc, &form_resources, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_descriptor() {
+    let dict = DictNode::Dict(hashmap!{
+        "Type".to_string() => DictNode::name("FontDescriptor"),
+        "FontName".to_string() => DictNode::name("CAAAAA+TimesNewRomanPSMT"),
+        "Flags".to_string() => DictNode::Int(6),
+        "FontBBox".to_string() => DictNode::Array(vec![DictNode::Int(-568), DictNode::Int(-306), DictNode::Int(2000), DictNode::Int(1007)]),
+        "ItalicAngle".to_string() => DictNode::Int(0),
+        "Ascent".to_string() => DictNode::Int(891),
+        "Descent".to_string() => DictNode::Int(-216),
+        "CapHeight".to_string() => DictNode::Int(1006),
+        "StemV".to_string() => DictNode::Int(80),
+        "FontFile2".to_string() => DictNode::reference(8),
+    });
+    let descriptor = FontDescriptor::from_dict(&dict, None);
+    assert_eq!(descriptor.font_name, "CAAAAA+TimesNewRomanPSMT");
+    assert_eq!(descriptor.font_bbox, Some([-568.0, -306.0, 2000.0, 1007.0]));
+    assert_eq!(descriptor.descent, -216.0);
+    assert!(descriptor.is_symbolic() && !descriptor.is_italic() && !descriptor.is_fixed_pitch());
+    assert_eq!(descriptor.font_file, Some((ProgramKind::TrueType, DictNode::reference(8))));
+
+    let cff = DictNode::Dict(hashmap!{ "FontFile3".to_string() => DictNode::reference(9) });
+    assert_eq!(FontDescriptor::from_dict(&cff, Some("Type1C")).font_file.map(|(kind, _)| kind.extension()), Some("cff"));
+    assert_eq!(FontDescriptor::from_dict(&cff, Some("Bogus")).font_file, None);
+}
+
+#[test]
+fn test_font() {
+    let dict = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Type0"),
+        "BaseFont".to_string() => DictNode::name("ABCDEF+NotoSans"),
+        "Encoding".to_string() => DictNode::name("Identity-H"),
+        "ToUnicode".to_string() => DictNode::reference(12),
+    });
+    let mut font = Font::from_dict(Some(10), &dict);
+    assert!(font.is_subset());
+    assert!(!font.is_embedded());
+    assert_eq!(font.to_unicode, Some(DictNode::reference(12)));
+
+    let mut descendant = Font::from_dict(Some(11), &DictNode::Dict(HashMap::new()));
+    descendant.descriptor = Some(FontDescriptor {
+        font_file: Some((ProgramKind::CIDFontType0C, DictNode::reference(13))),
+        ..FontDescriptor::default()
+    });
+    font.descendant = Some(Box::new(descendant));
+    assert!(font.is_embedded());
+
+    let plain = Font::from_dict(None, &DictNode::Dict(hashmap!{ "BaseFont".to_string() => DictNode::name("Helvetica") }));
+    assert_eq!(plain.subtype, "Type1");
+    assert!(!plain.is_subset());
+}
+
+#[test]
+fn test_cid_widths() {
+    let font = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("CIDFontType2"),
+        "DW".to_string() => DictNode::Int(500),
+        "W".to_string() => DictNode::Array(vec![
+            DictNode::Int(1), DictNode::Array(vec![DictNode::Int(250), DictNode::Float(333.5), DictNode::Int(400)]),
+            DictNode::Int(10), DictNode::Int(20), DictNode::Int(1000),
+            // no width for the range, the rest is ignored
+            DictNode::Int(30), DictNode::Int(40),
+        ]),
+        "CIDToGIDMap".to_string() => DictNode::name("Identity"),
+    });
+    let widths = Font::from_dict(None, &font).cid_widths.unwrap();
+    assert_eq!(widths.get(0), 500.0);
+    assert_eq!(widths.get(2), 333.5);
+    assert_eq!(widths.get(3), 400.0);
+    assert_eq!(widths.get(4), 500.0);
+    assert_eq!(widths.get(15), 1000.0);
+    assert_eq!(widths.get(35), 500.0);
+    assert_eq!(CidWidths::from_dict(&DictNode::Dict(HashMap::new())).get(7), 1000.0);
+    assert_eq!(Font::from_dict(None, &font).cid_to_gid_map, Some(DictNode::name("Identity")));
+}
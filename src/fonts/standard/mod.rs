@@ -0,0 +1,270 @@
+use fonts::{FIXED_PITCH, FORCE_BOLD, ITALIC, NONSYMBOLIC, SERIF};
+
+/*
+ * The Latin standard fonts every viewer has: Helvetica, Times and Courier
+ * in their four styles, which documents can name without embedding.
+ *
+ *   let standard = StandardFont::from_name("Arial,Bold");
+ *   assert_eq!(standard.map(StandardFont::name), Some("Helvetica-Bold"));
+ *
+ * Names are matched the way viewers substitute them: the standard names,
+ * and the Arial, Times New Roman and Courier New names that have the same
+ * metrics, with or without a subset tag, style suffixes (",Bold") and the
+ * PostScript decorations (PS, MT). Arial Narrow and the like are none of
+ * them.
+ *
+ * Widths are the Adobe metrics of printable ASCII (32 to 126, under
+ * WinAnsiEncoding), in thousandths of an em. That's what telling whether a
+ * font could pass for a standard one needs, and what the text of simple
+ * documents uses; the accented letters and symbols above aren't here.
+ *
+ * Symbol and ZapfDingbats aren't here either, they have no text to speak
+ * of and nothing stands in for them.
+ */
+
+// The codes there are widths for.
+pub const FIRST_CODE: u8 = 32;
+pub const LAST_CODE: u8 = 126;
+
+// How far, on average, a font's widths may be from a standard font's for
+// the standard font to stand in for it.
+const WIDTH_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Family {
+    Helvetica,
+    Times,
+    Courier,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct StandardFont {
+    pub family: Family,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+// What a font descriptor says about a standard font, from its AFM file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Metrics {
+    pub font_bbox: [f64; 4],
+    pub italic_angle: f64,
+    pub ascent: f64,
+    pub descent: f64,
+    pub cap_height: f64,
+    pub stem_v: f64,
+}
+
+// Spellings of family names, longest first, and the words a style is
+// spelled with after them.
+const FAMILIES: &[(&str, Family)] = &[
+    ("timesnewroman", Family::Times),
+    ("couriernew", Family::Courier),
+    ("helvetica", Family::Helvetica),
+    ("courier", Family::Courier),
+    ("arial", Family::Helvetica),
+    ("times", Family::Times),
+];
+const STYLE_WORDS: &[&str] = &["regular", "oblique", "italic", "roman", "bold", "ps", "mt"];
+
+impl StandardFont {
+    // The standard font `name` is, or is an alias of.
+    pub fn from_name(name: &str) -> Option<StandardFont> {
+        let name = match name.split_once('+') {
+            Some((tag, rest)) if tag.len() == 6 && tag.bytes().all(|byte| byte.is_ascii_uppercase()) => rest,
+            _ => name,
+        };
+        let name: String = name.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_lowercase();
+        let &(prefix, family) = FAMILIES.iter().find(|&&(prefix, _)| name.starts_with(prefix))?;
+        let mut rest = &name[prefix.len()..];
+        let mut font = StandardFont { family, bold: false, italic: false };
+        while !rest.is_empty() {
+            let word = STYLE_WORDS.iter().find(|&&word| rest.starts_with(word))?;
+            match *word {
+                "bold" => font.bold = true,
+                "italic" | "oblique" => font.italic = true,
+                _ => (),
+            }
+            rest = &rest[word.len()..];
+        }
+        Some(font)
+    }
+
+    // The closest standard font to one described by its flags, for fonts
+    // whose name doesn't say.
+    pub fn from_flags(flags: i64, italic_angle: f64, name: &str) -> StandardFont {
+        let family = if flags & FIXED_PITCH != 0 {
+            Family::Courier
+        } else if flags & SERIF != 0 {
+            Family::Times
+        } else {
+            Family::Helvetica
+        };
+        let name = name.to_lowercase();
+        StandardFont {
+            family,
+            bold: flags & FORCE_BOLD != 0 || ["bold", "black", "heavy"].iter().any(|word| name.contains(word)),
+            italic: flags & ITALIC != 0 || italic_angle != 0.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match (self.family, self.bold, self.italic) {
+            (Family::Helvetica, false, false) => "Helvetica",
+            (Family::Helvetica, true, false) => "Helvetica-Bold",
+            (Family::Helvetica, false, true) => "Helvetica-Oblique",
+            (Family::Helvetica, true, true) => "Helvetica-BoldOblique",
+            (Family::Times, false, false) => "Times-Roman",
+            (Family::Times, true, false) => "Times-Bold",
+            (Family::Times, false, true) => "Times-Italic",
+            (Family::Times, true, true) => "Times-BoldItalic",
+            (Family::Courier, false, false) => "Courier",
+            (Family::Courier, true, false) => "Courier-Bold",
+            (Family::Courier, false, true) => "Courier-Oblique",
+            (Family::Courier, true, true) => "Courier-BoldOblique",
+        }
+    }
+
+    // The /Flags of a descriptor for it.
+    pub fn flags(self) -> i64 {
+        let family = match self.family {
+            Family::Helvetica => 0,
+            Family::Times => SERIF,
+            Family::Courier => FIXED_PITCH | SERIF,
+        };
+        family | NONSYMBOLIC | if self.italic { ITALIC } else { 0 }
+    }
+
+    pub fn metrics(self) -> Metrics {
+        let (font_bbox, italic_angle, ascent, descent, cap_height, stem_v) = match (self.family, self.bold, self.italic) {
+            (Family::Helvetica, false, false) => ([-166.0, -225.0, 1000.0, 931.0], 0.0, 718.0, -207.0, 718.0, 88.0),
+            (Family::Helvetica, true, false) => ([-170.0, -228.0, 1003.0, 962.0], 0.0, 718.0, -207.0, 718.0, 140.0),
+            (Family::Helvetica, false, true) => ([-170.0, -225.0, 1116.0, 931.0], -12.0, 718.0, -207.0, 718.0, 88.0),
+            (Family::Helvetica, true, true) => ([-174.0, -228.0, 1114.0, 962.0], -12.0, 718.0, -207.0, 718.0, 140.0),
+            (Family::Times, false, false) => ([-168.0, -218.0, 1000.0, 898.0], 0.0, 683.0, -217.0, 662.0, 84.0),
+            (Family::Times, true, false) => ([-168.0, -218.0, 1000.0, 935.0], 0.0, 683.0, -217.0, 676.0, 139.0),
+            (Family::Times, false, true) => ([-169.0, -217.0, 1010.0, 883.0], -15.5, 683.0, -217.0, 653.0, 76.0),
+            (Family::Times, true, true) => ([-200.0, -218.0, 996.0, 921.0], -15.0, 683.0, -217.0, 669.0, 121.0),
+            (Family::Courier, false, false) => ([-23.0, -250.0, 715.0, 805.0], 0.0, 629.0, -157.0, 562.0, 51.0),
+            (Family::Courier, true, false) => ([-113.0, -250.0, 749.0, 801.0], 0.0, 629.0, -157.0, 562.0, 106.0),
+            (Family::Courier, false, true) => ([-27.0, -250.0, 849.0, 805.0], -12.0, 629.0, -157.0, 562.0, 51.0),
+            (Family::Courier, true, true) => ([-57.0, -250.0, 869.0, 801.0], -12.0, 629.0, -157.0, 562.0, 106.0),
+        };
+        Metrics { font_bbox, italic_angle, ascent, descent, cap_height, stem_v }
+    }
+
+    // The width of the glyph WinAnsiEncoding puts at `code`, for printable
+    // ASCII.
+    pub fn width(self, code: u8) -> Option<f64> {
+        if !(FIRST_CODE..=LAST_CODE).contains(&code) {
+            return None;
+        }
+        let widths = match (self.family, self.bold, self.italic) {
+            (Family::Courier, _, _) => return Some(600.0),
+            (Family::Helvetica, false, _) => &HELVETICA,
+            (Family::Helvetica, true, _) => &HELVETICA_BOLD,
+            (Family::Times, false, false) => &TIMES_ROMAN,
+            (Family::Times, true, false) => &TIMES_BOLD,
+            (Family::Times, false, true) => &TIMES_ITALIC,
+            (Family::Times, true, true) => &TIMES_BOLD_ITALIC,
+        };
+        Some(widths[(code - FIRST_CODE) as usize] as f64)
+    }
+
+    // Whether a font with these (code, width) pairs could pass for this
+    // one. Widths of 0, glyphs a subset left out, don't count.
+    pub fn fits(self, widths: &[(u8, f64)]) -> bool {
+        let differences: Vec<f64> = widths.iter()
+            .filter(|&&(_, width)| width > 0.0)
+            .filter_map(|&(code, width)| self.width(code).map(|standard| (width - standard).abs() / standard))
+            .collect();
+        !differences.is_empty() && differences.iter().sum::<f64>() / differences.len() as f64 <= WIDTH_TOLERANCE
+    }
+}
+
+// By code from 32, a row for punctuation, digits, more punctuation,
+// capitals, more punctuation, small letters and the last few.
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556,
+    278, 278, 584, 584, 584, 556, 1015,
+    667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611,
+    278, 278, 278, 469, 556, 333,
+    556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500,
+    334, 260, 334, 584,
+];
+
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556,
+    333, 333, 584, 584, 584, 611, 975,
+    722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611,
+    333, 278, 333, 584, 556, 333,
+    556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500,
+    389, 280, 389, 584,
+];
+
+const TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    278, 278, 564, 564, 564, 444, 921,
+    722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722, 556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611,
+    333, 278, 333, 469, 500, 333,
+    444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500, 500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444,
+    480, 200, 480, 541,
+];
+
+const TIMES_BOLD: [u16; 95] = [
+    250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 570, 570, 570, 500, 930,
+    722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778, 611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667,
+    333, 278, 333, 581, 500, 333,
+    500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500, 556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444,
+    394, 220, 394, 520,
+];
+
+const TIMES_ITALIC: [u16; 95] = [
+    250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 675, 675, 675, 500, 920,
+    611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722, 611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556,
+    389, 278, 389, 422, 500, 333,
+    500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500, 500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389,
+    400, 275, 400, 541,
+];
+
+const TIMES_BOLD_ITALIC: [u16; 95] = [
+    250, 389, 555, 500, 500, 833, 778, 278, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    333, 333, 570, 570, 570, 500, 832,
+    667, 667, 667, 722, 667, 667, 722, 778, 389, 500, 667, 611, 889, 722, 722, 611, 722, 667, 556, 611, 722, 667, 889, 667, 611, 611,
+    333, 278, 333, 570, 500, 333,
+    500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500, 278, 778, 556, 500, 500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389,
+    348, 220, 348, 570,
+];
+
+#[test]
+fn test_standard_fonts() {
+    let name = |name| StandardFont::from_name(name).map(StandardFont::name);
+    assert_eq!(name("Helvetica"), Some("Helvetica"));
+    assert_eq!(name("Arial,BoldItalic"), Some("Helvetica-BoldOblique"));
+    assert_eq!(name("ABCDEF+TimesNewRomanPS-BoldMT"), Some("Times-Bold"));
+    assert_eq!(name("Times-Roman"), Some("Times-Roman"));
+    assert_eq!(name("CourierNewPS-ItalicMT"), Some("Courier-Oblique"));
+    assert_eq!(name("ArialNarrow"), None);
+    assert_eq!(name("Calibri"), None);
+    assert_eq!(StandardFont::from_flags(SERIF | ITALIC, 0.0, "Georgia-Bold").name(), "Times-BoldItalic");
+
+    let helvetica = StandardFont::from_name("Helvetica").unwrap();
+    assert_eq!(helvetica.width(b'i'), Some(222.0));
+    assert_eq!(helvetica.width(b'W'), Some(944.0));
+    assert_eq!(helvetica.width(b'~'), Some(584.0));
+    assert_eq!(helvetica.width(b'\n'), None);
+    assert_eq!(StandardFont::from_name("Courier-Bold").unwrap().width(b'm'), Some(600.0));
+
+    assert!(helvetica.fits(&[(b'a', 556.0), (b'i', 222.0), (b'm', 850.0), (b'x', 0.0)]));
+    // Courier's widths are nothing like it
+    assert!(!helvetica.fits(&[(b'a', 600.0), (b'i', 600.0), (b'm', 600.0)]));
+    assert!(!helvetica.fits(&[(b'x', 0.0)]));
+}
@@ -0,0 +1,547 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use filters;
+use fonts::truetype::TrueType;
+use fonts::{self, Font, ProgramKind};
+use merge::Copier;
+use writer::Writer;
+use pages;
+use parsers::cos::DictNode;
+use text::{self, cmap::CMap, encoding::{BaseEncoding, Encoding}};
+
+/*
+ * Cutting embedded fonts down to the glyphs a document shows, after the
+ * fact.
+ *
+ *   let usage = subset::usage(&mut doc)?;
+ *   let subsetted = subset::subset(Path::new("merged.pdf"), Path::new("smaller.pdf"))?;
+ *
+ * Documents put together from others carry fonts embedded for pages that
+ * aren't there any more, or whole fonts where a few glyphs would do. What
+ * each font shows comes from running every page's content, form XObjects
+ * included (`text::interpret` keeps track of the codes). Then, for each
+ * font shown:
+ *
+ *   - a TrueType program (/FontFile2) only keeps the glyphs the codes map
+ *     to, through /CIDToGIDMap or the program's 'cmap', see truetype
+ *   - /FirstChar, /LastChar and /Widths, or a CIDFont's /W, only cover the
+ *     codes shown
+ *   - /ToUnicode only maps the codes shown
+ *
+ * Fonts that may be used where we don't look are left as they are: those
+ * of annotation appearances, of the form's /DR, of patterns and of Type 3
+ * glyphs, as are fonts written directly into a resource dictionary and
+ * programs or CMaps shared with them. So is a program when a code shown
+ * doesn't map to a glyph we can find, or a composite font uses a
+ * predefined CMap other than Identity. Type 1 and CFF programs are kept
+ * whole, only their dictionaries shrink.
+ *
+ * A subsetted program goes in deflated, and only when that's smaller than
+ * the program was.
+ */
+
+// Form XObjects, patterns and Type 3 fonts nest, in circles in broken files.
+const MAX_DEPTH: usize = 16;
+// Entries in a bfchar block, the most the spec allows.
+const BFCHAR_BLOCK: usize = 100;
+
+// What subsetting did to a font.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Subsetted {
+    pub name: String,
+    // distinct codes shown with it
+    pub codes: usize,
+    // the program's glyphs before and after, when it was subsetted
+    pub glyphs: Option<(usize, usize)>,
+}
+
+// An object of a source document to write out differently when copying
+// it: the object, and the data if it's a stream.
+pub(crate) type Replacement = (DictNode, Option<Vec<u8>>);
+pub(crate) type Replacements = HashMap<u64, Replacement>;
+
+// What goes into a shared program, CIDFont or ToUnicode CMap, None once
+// something that can't be subsetted uses it.
+type Needed<T> = HashMap<u64, Option<BTreeSet<T>>>;
+
+// The character codes shown with each font, by the font's object number.
+// Fonts that might be used where pages don't show them are left out.
+pub fn usage(doc: &mut Document) -> Result<HashMap<u64, BTreeSet<Vec<u8>>>> {
+    let hidden = hidden_fonts(doc)?;
+    shown_codes(doc, &hidden)
+}
+
+fn shown_codes(doc: &mut Document, hidden: &HashSet<u64>) -> Result<HashMap<u64, BTreeSet<Vec<u8>>>> {
+    let mut usage: HashMap<u64, BTreeSet<Vec<u8>>> = HashMap::new();
+    for page in pages::pages(doc)? {
+        for ((id, _), codes) in text::interpret(doc, &page)?.codes {
+            if id >= 0 {
+                usage.entry(id as u64).or_default().extend(codes);
+            }
+        }
+    }
+    usage.retain(|id, _| !hidden.contains(id));
+    Ok(usage)
+}
+
+// Writes `input` over into `output` with its fonts subsetted. Like the
+// other full rewrites, only what the trailer's /Root, /Info and /ID reach
+// is copied.
+pub fn subset(input: &Path, output: &Path) -> Result<Vec<Subsetted>> {
+    let policy = DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() };
+    let mut doc = Document::open(input, Options { policy, ..Options::default() })?;
+    let (replacements, subsetted) = replacements(&mut doc)?;
+
+    let trailer = doc.trailer.as_dict().cloned().unwrap_or_default();
+    let mut out = Writer::new();
+    let mut copier = Copier::new(&mut doc);
+    copier.replaced = replacements;
+    let mut kept = HashMap::new();
+    for key in &["Root", "Info", "ID"] {
+        if let Some(value) = trailer.get(*key) {
+            kept.insert(key.to_string(), copier.renumber(value, &mut out));
+        }
+    }
+    if !kept.contains_key("Root") {
+        return Err(PdfError::Syntax { what: "document catalog", position: 0 });
+    }
+    copier.copy_pending(&mut out)?;
+    fs::write(output, out.finish_with(kept))?;
+    Ok(subsetted)
+}
+
+// The font dictionaries, programs and CMaps of `doc` subsetted to what its
+// pages show, for a Copier to write out instead.
+pub(crate) fn replacements(doc: &mut Document) -> Result<(Replacements, Vec<Subsetted>)> {
+    let hidden = hidden_fonts(doc)?;
+    let usage = shown_codes(doc, &hidden)?;
+    let mut programs: Needed<u16> = HashMap::new();
+    let mut descendants: Needed<u32> = HashMap::new();
+    let mut to_unicode: Needed<Vec<u8>> = HashMap::new();
+    let mut shown = Vec::new();
+
+    // fonts that aren't subsetted keep what they share whole
+    let mut untouched: Vec<Font> = Vec::new();
+    for font in fonts::fonts(doc)? {
+        match font.id.and_then(|id| usage.get(&id)) {
+            Some(codes) if font.subtype != "Type3" => shown.push((font, codes.clone())),
+            _ => untouched.push(font),
+        }
+    }
+    for &id in &hidden {
+        if let Some(font) = Font::read(doc, &DictNode::reference(id))? {
+            untouched.push(font);
+        }
+    }
+    for font in &untouched {
+        need(&mut programs, program_id(font), None);
+        need(&mut descendants, font.descendant.as_ref().and_then(|descendant| descendant.id), None);
+        need(&mut to_unicode, reference_id(font.to_unicode.as_ref()), None);
+    }
+
+    let mut replacements = HashMap::new();
+    let mut subsetted = Vec::new();
+    for (font, codes) in &shown {
+        let cids = cids(doc, font, codes)?;
+        need(&mut to_unicode, reference_id(font.to_unicode.as_ref()), Some(codes.clone()));
+        if let Some(ref descendant) = font.descendant {
+            need(&mut descendants, descendant.id, cids.clone());
+        }
+        let glyphs = match program_id(font) {
+            Some(_) => glyphs(doc, font, codes, cids.as_ref())?,
+            None => None,
+        };
+        need(&mut programs, program_id(font), glyphs);
+
+        if font.descendant.is_none() {
+            if let (Some(id), Some(dict)) = (font.id, widths(doc, font, codes)?) {
+                replacements.insert(id, (dict, None));
+            }
+        }
+        subsetted.push(Subsetted { name: font.base_font.clone(), codes: codes.len(), glyphs: None });
+    }
+
+    for (&id, cids) in &descendants {
+        if let Some(cids) = cids {
+            if let Some(dict) = cid_widths(doc, id, cids)? {
+                replacements.insert(id, (dict, None));
+            }
+        }
+    }
+    for (&id, codes) in &to_unicode {
+        if let Some(stream) = codes.as_ref().and_then(|codes| rewrite_to_unicode(doc, id, codes).transpose()) {
+            replacements.insert(id, stream?);
+        }
+    }
+    for (&id, glyphs) in &programs {
+        let glyphs = match glyphs {
+            Some(glyphs) => glyphs,
+            None => continue,
+        };
+        if let Some((stream, counts)) = subset_program(doc, id, glyphs)? {
+            replacements.insert(id, stream);
+            for ((font, _), subsetted) in shown.iter().zip(subsetted.iter_mut()) {
+                if program_id(font) == Some(id) {
+                    subsetted.glyphs = Some(counts);
+                }
+            }
+        }
+    }
+    Ok((replacements, subsetted))
+}
+
+// Adds what a font needs of the shared object `id`, None meaning all of it.
+fn need<T: Ord>(needed: &mut Needed<T>, id: Option<u64>, what: Option<BTreeSet<T>>) {
+    let id = match id {
+        Some(id) => id,
+        None => return,
+    };
+    let entry = needed.entry(id).or_insert_with(|| Some(BTreeSet::new()));
+    match (entry.as_mut(), what) {
+        (Some(set), Some(what)) => set.extend(what),
+        _ => *entry = None,
+    }
+}
+
+fn reference_id(node: Option<&DictNode>) -> Option<u64> {
+    match node {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => Some(id as u64),
+        _ => None,
+    }
+}
+
+// The program stream, for TrueType programs; the others are never
+// subsetted, and don't need to be kept track of.
+fn program_id(font: &Font) -> Option<u64> {
+    match font.descriptor().and_then(|descriptor| descriptor.font_file.as_ref()) {
+        Some((ProgramKind::TrueType, stream)) => reference_id(Some(stream)),
+        _ => None,
+    }
+}
+
+// A composite font's CIDs for `codes`, None if its encoding is a predefined
+// CMap other than Identity, or isn't a composite font's.
+fn cids(doc: &mut Document, font: &Font, codes: &BTreeSet<Vec<u8>>) -> Result<Option<BTreeSet<u32>>> {
+    if font.descendant.is_none() {
+        return Ok(None);
+    }
+    let code_value = |code: &Vec<u8>| code.iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+    match font.encoding {
+        Some(ref name) if name.is_name("Identity-H") || name.is_name("Identity-V") => {
+            return Ok(Some(codes.iter().map(code_value).collect()));
+        },
+        Some(DictNode::Dict(_)) => (),
+        _ => return Ok(None),
+    }
+    // an embedded CMap; `encoding` has it resolved, the font has the reference
+    let dict = match font.id {
+        Some(id) => doc.resolve(&DictNode::reference(id))?,
+        None => return Ok(None),
+    };
+    let cmap = match dict.get("Encoding") {
+        Some(stream) => doc.referenced_stream(stream)?.map(|(_, data)| CMap::parse(&data)),
+        None => None,
+    };
+    Ok(cmap.filter(CMap::has_cids).map(|cmap| codes.iter().map(|code| cmap.cid(code).unwrap_or(0)).collect()))
+}
+
+// The glyphs of a TrueType program that `codes` show, None when one of
+// them can't be found.
+fn glyphs(doc: &mut Document, font: &Font, codes: &BTreeSet<Vec<u8>>, cids: Option<&BTreeSet<u32>>) -> Result<Option<BTreeSet<u16>>> {
+    if let Some(ref descendant) = font.descendant {
+        let cids = match cids {
+            Some(cids) => cids,
+            None => return Ok(None),
+        };
+        let map = match descendant.cid_to_gid_map {
+            Some(ref stream @ DictNode::ObjectReference(..)) => doc.referenced_stream(stream)?.map(|(_, data)| data),
+            _ => None,
+        };
+        let glyphs = cids.iter()
+            .map(|&cid| match map {
+                Some(ref map) => map.get(2 * cid as usize..2 * cid as usize + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])),
+                None => Some(cid).filter(|&cid| cid <= 0xffff).map(|cid| cid as u16),
+            })
+            .collect();
+        return Ok(glyphs);
+    }
+
+    let program = match font.program(doc)? {
+        Some((_, program)) => program,
+        None => return Ok(None),
+    };
+    let truetype = match TrueType::parse(&program) {
+        Ok(truetype) => truetype,
+        Err(_) => return Ok(None),
+    };
+    // viewers try the symbolic subtable, the Mac Roman one and the Unicode
+    // one in turn, keeping whatever any of them maps is safe
+    let encoding = Encoding::from_node(font.encoding.as_ref(), BaseEncoding::WinAnsi);
+    let mut glyphs = BTreeSet::new();
+    for code in codes {
+        let byte = match *code.as_slice() {
+            [byte] => byte as u32,
+            _ => return Ok(None),
+        };
+        let unicode = encoding.get(byte as u8).map(|c| c as u32);
+        let found: Vec<u16> = [(3, 0, Some(0xf000 + byte)), (3, 0, Some(byte)), (1, 0, Some(byte)), (3, 1, unicode)].iter()
+            .filter_map(|&(platform, encoding, code)| truetype.glyph(platform, encoding, code?))
+            .collect();
+        if found.is_empty() {
+            return Ok(None);
+        }
+        glyphs.extend(found);
+    }
+    Ok(Some(glyphs))
+}
+
+// A simple font's dictionary with /FirstChar, /LastChar and /Widths only
+// covering `codes`, None if it has no widths to cut down.
+fn widths(doc: &mut Document, font: &Font, codes: &BTreeSet<Vec<u8>>) -> Result<Option<DictNode>> {
+    let bytes: Vec<i64> = codes.iter().filter(|code| code.len() == 1).map(|code| code[0] as i64).collect();
+    let (first, last) = match (bytes.iter().min(), bytes.iter().max()) {
+        (Some(&first), Some(&last)) if !font.widths.is_empty() => (first, last),
+        _ => return Ok(None),
+    };
+    let missing = font.descriptor.as_ref().map_or(0.0, |descriptor| descriptor.missing_width);
+    let widths = (first..=last)
+        .map(|code| {
+            let width = Some(code - font.first_char).filter(|&index| index >= 0).and_then(|index| font.widths.get(index as usize));
+            number(width.cloned().unwrap_or(missing))
+        })
+        .collect();
+    let mut dict = match font.id.map(|id| doc.resolve(&DictNode::reference(id))).transpose()? {
+        Some(DictNode::Dict(dict)) => dict,
+        _ => return Ok(None),
+    };
+    dict.insert("FirstChar".to_string(), DictNode::Int(first));
+    dict.insert("LastChar".to_string(), DictNode::Int(last));
+    dict.insert("Widths".to_string(), DictNode::Array(widths));
+    Ok(Some(DictNode::Dict(dict)))
+}
+
+// CIDFont `id` with a /W only listing `cids`, the ones with a width other
+// than /DW, in runs of consecutive CIDs.
+fn cid_widths(doc: &mut Document, id: u64, cids: &BTreeSet<u32>) -> Result<Option<DictNode>> {
+    let mut dict = match doc.resolve(&DictNode::reference(id))? {
+        DictNode::Dict(dict) => dict,
+        _ => return Ok(None),
+    };
+    let mut resolved = HashMap::new();
+    for key in &["W", "DW"] {
+        if let Some(value) = dict.get(*key) {
+            resolved.insert(key.to_string(), doc.resolve_all(value)?);
+        }
+    }
+    let widths = fonts::CidWidths::from_dict(&DictNode::Dict(resolved));
+
+    let mut runs: Vec<(u32, Vec<DictNode>)> = Vec::new();
+    for &cid in cids {
+        let width = widths.get(cid);
+        if width == widths.default_width {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((first, run)) if *first + run.len() as u32 == cid => run.push(number(width)),
+            _ => runs.push((cid, vec![number(width)])),
+        }
+    }
+    let array: Vec<DictNode> = runs.into_iter().flat_map(|(first, run)| vec![DictNode::Int(first as i64), DictNode::Array(run)]).collect();
+    if array.is_empty() {
+        dict.remove("W");
+    } else {
+        dict.insert("W".to_string(), DictNode::Array(array));
+    }
+    Ok(Some(DictNode::Dict(dict)))
+}
+
+// ToUnicode CMap `id` over again with only `codes` in it. CMaps whose
+// codes aren't all as long are left alone.
+fn rewrite_to_unicode(doc: &mut Document, id: u64, codes: &BTreeSet<Vec<u8>>) -> Result<Option<Replacement>> {
+    let cmap = match doc.referenced_stream(&DictNode::reference(id))? {
+        Some((_, data)) => CMap::parse(&data),
+        None => return Ok(None),
+    };
+    let length = codes.iter().next().map_or(0, Vec::len);
+    if length == 0 || codes.iter().any(|code| code.len() != length) {
+        return Ok(None);
+    }
+    let mappings: Vec<(&Vec<u8>, String)> = codes.iter().filter_map(|code| Some((code, cmap.lookup(code)?))).collect();
+    if mappings.is_empty() {
+        return Ok(None);
+    }
+    let data = to_unicode_cmap(length, &mappings);
+    let dict = HashMap::from([("Length".to_string(), DictNode::Int(data.len() as i64))]);
+    Ok(Some((DictNode::Dict(dict), Some(data))))
+}
+
+// A ToUnicode CMap for codes `length` bytes long.
+fn to_unicode_cmap(length: usize, mappings: &[(&Vec<u8>, String)]) -> Vec<u8> {
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>();
+    let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+        /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+        /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n");
+    cmap.push_str(&format!("1 begincodespacerange\n<{}> <{}>\nendcodespacerange\n", "00".repeat(length), "FF".repeat(length)));
+    for block in mappings.chunks(BFCHAR_BLOCK) {
+        cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+        for (code, text) in block {
+            let units: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            cmap.push_str(&format!("<{}> <{}>\n", hex(code), hex(&units)));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+    cmap.push_str("endcmap\nCMapName currentdict /CMapResource defineresource pop\nend\nend\n");
+    cmap.into_bytes()
+}
+
+// TrueType program `id` with only `glyphs` (and .notdef) left, and the
+// number of glyphs before and after, if that makes it any smaller.
+fn subset_program(doc: &mut Document, id: u64, glyphs: &BTreeSet<u16>) -> Result<Option<(Replacement, (usize, usize))>> {
+    let entry = match doc.xref.get(id as usize) {
+        Some(entry) if !entry.is_free => entry.clone(),
+        _ => return Ok(None),
+    };
+    let (mut dict, program) = match doc.stream(&entry)? {
+        Some((DictNode::Dict(dict), program)) => (dict, program),
+        _ => return Ok(None),
+    };
+    let encoded = match doc.raw_object(&entry)? {
+        (_, Some(raw)) => raw.len(),
+        _ => return Ok(None),
+    };
+    let truetype = match TrueType::parse(&program) {
+        Ok(truetype) => truetype,
+        Err(_) => return Ok(None),
+    };
+    let (data, kept) = match truetype.subset(glyphs) {
+        Ok(subset) => subset,
+        Err(_) => return Ok(None),
+    };
+    let deflated = filters::flate_encode(&data);
+    if deflated.len() >= encoded {
+        return Ok(None);
+    }
+    dict.remove("DecodeParms");
+    dict.insert("Filter".to_string(), DictNode::name("FlateDecode"));
+    dict.insert("Length".to_string(), DictNode::Int(deflated.len() as i64));
+    dict.insert("Length1".to_string(), DictNode::Int(data.len() as i64));
+    Ok(Some(((DictNode::Dict(dict), Some(deflated)), (truetype.glyph_count(), kept))))
+}
+
+// Fonts that something other than a page's content might show: annotation
+// appearances, the form's default resources, patterns and Type 3 glyphs.
+fn hidden_fonts(doc: &mut Document) -> Result<HashSet<u64>> {
+    let mut finder = Hidden { seen: HashSet::new(), fonts: HashSet::new() };
+    for page in pages::pages(doc)? {
+        finder.resources(doc, &page.resources, false, 0)?;
+        let annots = match page.dict.get_resolved(doc, "Annots")? {
+            Some(DictNode::Array(annots)) => annots,
+            _ => continue,
+        };
+        for annot in annots {
+            let appearances = match doc.resolve(&annot)?.get_resolved(doc, "AP")? {
+                Some(DictNode::Dict(appearances)) => appearances,
+                _ => continue,
+            };
+            for appearance in appearances.values() {
+                // a stream, or a dictionary of them by state
+                let resolved = doc.resolve(appearance)?;
+                let streams = match resolved {
+                    DictNode::Dict(ref map) if !map.contains_key("BBox") => map.values().cloned().collect(),
+                    _ => vec![appearance.clone()],
+                };
+                for stream in streams {
+                    finder.xobject(doc, &stream, true, 0)?;
+                }
+            }
+        }
+    }
+    if let Some((_, catalog)) = doc.trailer_entry("Root")? {
+        if let Some(form) = catalog.get_resolved(doc, "AcroForm")? {
+            if let Some(resources) = form.get_resolved(doc, "DR")? {
+                finder.resources(doc, &resources, true, 0)?;
+            }
+        }
+    }
+    Ok(finder.fonts)
+}
+
+struct Hidden {
+    // resource owners looked at, and whether as hidden
+    seen: HashSet<((i64, i64), bool)>,
+    fonts: HashSet<u64>,
+}
+
+impl Hidden {
+    // Goes through `resources`, noting their fonts if `hidden`, and the
+    // fonts of their patterns and Type 3 fonts whatever it is.
+    fn resources(&mut self, doc: &mut Document, resources: &DictNode, hidden: bool, depth: usize) -> Result<()> {
+        if depth >= MAX_DEPTH {
+            return Ok(());
+        }
+        if let Some(DictNode::Dict(fonts)) = resources.get_resolved(doc, "Font")? {
+            for node in fonts.values() {
+                if let (true, Some(id)) = (hidden, reference_id(Some(node))) {
+                    self.fonts.insert(id);
+                }
+                if doc.resolve(node)?.get("Subtype").is_some_and(|subtype| subtype.is_name("Type3")) {
+                    self.xobject(doc, node, true, depth + 1)?;
+                }
+            }
+        }
+        if let Some(DictNode::Dict(xobjects)) = resources.get_resolved(doc, "XObject")? {
+            for node in xobjects.values() {
+                self.xobject(doc, node, hidden, depth + 1)?;
+            }
+        }
+        if let Some(DictNode::Dict(patterns)) = resources.get_resolved(doc, "Pattern")? {
+            for node in patterns.values() {
+                self.xobject(doc, node, true, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The same for the resources of a form, pattern or Type 3 font.
+    fn xobject(&mut self, doc: &mut Document, node: &DictNode, hidden: bool, depth: usize) -> Result<()> {
+        if let Some(reference) = node.as_reference() {
+            if !self.seen.insert((reference, hidden)) {
+                return Ok(());
+            }
+        }
+        match doc.resolve(node)?.get_resolved(doc, "Resources")? {
+            Some(resources) => self.resources(doc, &resources, hidden, depth),
+            None => Ok(()),
+        }
+    }
+}
+
+fn number(value: f64) -> DictNode {
+    if value.fract() == 0.0 { DictNode::Int(value as i64) } else { DictNode::Float(value) }
+}
+
+#[test]
+fn test_need() {
+    let mut needed: Needed<u16> = HashMap::new();
+    need(&mut needed, Some(4), Some([1, 2].iter().cloned().collect()));
+    need(&mut needed, Some(4), Some([3].iter().cloned().collect()));
+    need(&mut needed, Some(5), Some([1].iter().cloned().collect()));
+    need(&mut needed, Some(5), None);
+    need(&mut needed, None, None);
+    assert_eq!(needed, hashmap!{ 4 => Some([1, 2, 3].iter().cloned().collect()), 5 => None });
+}
+
+#[test]
+fn test_to_unicode_cmap() {
+    let (a, ffi) = (vec![0, 0x24], vec![0, 0x60]);
+    let cmap = to_unicode_cmap(2, &[(&a, "A".to_string()), (&ffi, "ffi".to_string())]);
+    let parsed = CMap::parse(&cmap);
+    assert_eq!(parsed.lookup(&a), Some("A".to_string()));
+    assert_eq!(parsed.lookup(&ffi), Some("ffi".to_string()));
+    assert_eq!(parsed.code_length(&[0, 0x24]), Some(2));
+    assert_eq!(parsed.lookup(&[0, 0x25]), None);
+}
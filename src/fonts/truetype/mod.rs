@@ -0,0 +1,340 @@
+use std::collections::BTreeSet;
+
+use error::{PdfError, Result};
+
+/*
+ * Just enough of TrueType to subset an embedded program: the table
+ * directory, character to glyph lookups in 'cmap', and 'glyf' and 'loca'
+ * written over with only some of the glyphs.
+ *
+ *   let font = TrueType::parse(&program)?;
+ *   let glyph = font.glyph(3, 1, 'A' as u32);
+ *   let (smaller, kept) = font.subset(&glyphs)?;
+ *
+ * Subsetting keeps glyph numbers as they are: the glyphs that go are left
+ * empty rather than taken out, so content streams, /CIDToGIDMap and the
+ * font's other tables ('hmtx', 'cmap', 'post') carry on working as they
+ * were. The outlines are what takes up the room anyway. Composite glyphs
+ * keep the glyphs they're made of, and glyph 0 (.notdef) always stays.
+ *
+ * Tables are big endian, and listed in a directory after a 12 byte header:
+ *
+ *   tag, checksum, offset, length   16 bytes per table
+ */
+
+// 0xb1b0afba minus the sum of the whole font goes in 'head'.
+const CHECKSUM_MAGIC: u32 = 0xb1b0_afba;
+// Where things are in 'head' and 'maxp'.
+const CHECKSUM_ADJUSTMENT: usize = 8;
+const INDEX_TO_LOC_FORMAT: usize = 50;
+const NUM_GLYPHS: usize = 4;
+
+// Composite glyph component flags.
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+pub struct TrueType<'a> {
+    data: &'a [u8],
+    // (tag, offset, length), in the directory's order
+    tables: Vec<([u8; 4], usize, usize)>,
+}
+
+impl<'a> TrueType<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<TrueType<'a>> {
+        let error = |position| PdfError::Syntax { what: "TrueType font", position };
+        if data.get(..4).is_none_or(|magic| magic != [0, 1, 0, 0] && magic != b"true") {
+            return Err(error(0));
+        }
+        let count = read_u16(data, 4).ok_or_else(|| error(4))? as usize;
+        let mut tables = Vec::with_capacity(count);
+        for index in 0..count {
+            let record = 12 + 16 * index;
+            let (offset, length) = match (read_u32(data, record + 8), read_u32(data, record + 12)) {
+                (Some(offset), Some(length)) => (offset as usize, length as usize),
+                _ => return Err(error(record)),
+            };
+            if offset.checked_add(length).is_none_or(|end| end > data.len()) {
+                return Err(error(record));
+            }
+            let mut tag = [0; 4];
+            tag.copy_from_slice(&data[record..record + 4]);
+            tables.push((tag, offset, length));
+        }
+        Ok(TrueType { data, tables })
+    }
+
+    pub fn table(&self, tag: &[u8; 4]) -> Option<&'a [u8]> {
+        self.tables.iter().find(|table| table.0 == *tag).map(|&(_, offset, length)| &self.data[offset..offset + length])
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.table(b"maxp").and_then(|maxp| read_u16(maxp, NUM_GLYPHS)).unwrap_or(0) as usize
+    }
+
+    // The glyph the (`platform`, `encoding`) subtable of 'cmap' maps
+    // `code` to, if there's that subtable and it maps the code to
+    // something other than .notdef. Formats 0, 4, 6 and 12 are read.
+    pub fn glyph(&self, platform: u16, encoding: u16, code: u32) -> Option<u16> {
+        let cmap = self.table(b"cmap")?;
+        let subtable = (0..read_u16(cmap, 2)? as usize)
+            .map(|index| 4 + 8 * index)
+            .find(|&record| read_u16(cmap, record) == Some(platform) && read_u16(cmap, record + 2) == Some(encoding))
+            .and_then(|record| read_u32(cmap, record + 4))?;
+        let subtable = cmap.get(subtable as usize..)?;
+        let glyph = match read_u16(subtable, 0)? {
+            0 if code < 256 => *subtable.get(6 + code as usize)? as u16,
+            4 if code <= 0xffff => format4(subtable, code as u16)?,
+            6 => {
+                let (first, entries) = (read_u16(subtable, 6)? as u32, read_u16(subtable, 8)? as u32);
+                let index = code.checked_sub(first).filter(|&index| index < entries)?;
+                read_u16(subtable, 10 + 2 * index as usize)?
+            },
+            12 => (0..read_u32(subtable, 12)? as usize)
+                .map(|index| 16 + 12 * index)
+                .find(|&group| read_u32(subtable, group).is_some_and(|start| start <= code)
+                    && read_u32(subtable, group + 4).is_some_and(|end| code <= end))
+                // a group running past the last glyph number maps nothing
+                .and_then(|group| read_u32(subtable, group + 8)?.checked_add(code.checked_sub(read_u32(subtable, group)?)?))
+                .filter(|&glyph| glyph <= 0xffff)? as u16,
+            _ => return None,
+        };
+        Some(glyph).filter(|&glyph| glyph != 0)
+    }
+
+    // The font with only `glyphs`, what they're made of and .notdef left
+    // in 'glyf', and how many glyphs that is.
+    pub fn subset(&self, glyphs: &BTreeSet<u16>) -> Result<(Vec<u8>, usize)> {
+        let error = || PdfError::Syntax { what: "TrueType glyph table", position: 0 };
+        let (head, loca, glyf) = match (self.table(b"head"), self.table(b"loca"), self.table(b"glyf")) {
+            (Some(head), Some(loca), Some(glyf)) => (head, loca, glyf),
+            _ => return Err(error()),
+        };
+        let long = read_u16(head, INDEX_TO_LOC_FORMAT).ok_or_else(error)? == 1;
+        let count = self.glyph_count();
+        let offsets: Vec<usize> = (0..=count)
+            .map(|index| match long {
+                true => read_u32(loca, 4 * index).map(|offset| offset as usize),
+                false => read_u16(loca, 2 * index).map(|offset| 2 * offset as usize),
+            })
+            .collect::<Option<Vec<usize>>>()
+            .filter(|offsets| offsets.windows(2).all(|pair| pair[0] <= pair[1]) && offsets[count] <= glyf.len())
+            .ok_or_else(error)?;
+        let outline = |glyph: u16| offsets.get(glyph as usize + 1).map(|&end| &glyf[offsets[glyph as usize]..end]);
+
+        // composite glyphs bring their components along
+        let mut kept = BTreeSet::new();
+        let mut pending: Vec<u16> = glyphs.iter().cloned().chain(Some(0)).collect();
+        while let Some(glyph) = pending.pop() {
+            if (glyph as usize) < count && kept.insert(glyph) {
+                pending.extend(components(outline(glyph).unwrap_or(&[])));
+            }
+        }
+
+        let mut new_glyf = Vec::new();
+        let mut new_loca = Vec::new();
+        for glyph in 0..=count {
+            match long {
+                true => new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes()),
+                false => new_loca.extend_from_slice(&((new_glyf.len() / 2) as u16).to_be_bytes()),
+            }
+            if glyph < count && kept.contains(&(glyph as u16)) {
+                // each outline keeps the padding it had, so the offsets stay
+                // as aligned as they were
+                new_glyf.extend_from_slice(outline(glyph as u16).unwrap_or(&[]));
+            }
+        }
+        Ok((self.rebuild(&[(*b"glyf", new_glyf), (*b"loca", new_loca)]), kept.len()))
+    }
+
+    // The font file over again with `replaced` tables, checksums and all.
+    fn rebuild(&self, replaced: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let directory = 12 + 16 * self.tables.len();
+        let mut font = self.data[..12].to_vec();
+        font.resize(directory, 0);
+        let mut head = None;
+        for (index, &(tag, offset, length)) in self.tables.iter().enumerate() {
+            let data = match replaced.iter().find(|table| table.0 == tag) {
+                Some((_, data)) => &data[..],
+                None => &self.data[offset..offset + length],
+            };
+            let start = font.len();
+            font.extend_from_slice(data);
+            if tag == *b"head" && data.len() >= CHECKSUM_ADJUSTMENT + 4 {
+                font[start + CHECKSUM_ADJUSTMENT..start + CHECKSUM_ADJUSTMENT + 4].copy_from_slice(&[0; 4]);
+                head = Some(start);
+            }
+            while !font.len().is_multiple_of(4) {
+                font.push(0);
+            }
+            let record = 12 + 16 * index;
+            font[record..record + 4].copy_from_slice(&tag);
+            let sum = checksum(&font[start..start + data.len()]);
+            font[record + 4..record + 8].copy_from_slice(&sum.to_be_bytes());
+            font[record + 8..record + 12].copy_from_slice(&(start as u32).to_be_bytes());
+            font[record + 12..record + 16].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        }
+        if let Some(head) = head {
+            let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&font));
+            font[head + CHECKSUM_ADJUSTMENT..head + CHECKSUM_ADJUSTMENT + 4].copy_from_slice(&adjustment.to_be_bytes());
+        }
+        font
+    }
+}
+
+// A format 4 subtable's lookup: segments of codes, each mapped with a delta
+// or through an array of glyphs.
+fn format4(subtable: &[u8], code: u16) -> Option<u16> {
+    let segments = read_u16(subtable, 6)? as usize / 2;
+    let ends = 14;
+    let starts = ends + 2 * segments + 2;
+    let deltas = starts + 2 * segments;
+    let range_offsets = deltas + 2 * segments;
+    let segment = (0..segments).find(|&segment| read_u16(subtable, ends + 2 * segment).is_some_and(|end| code <= end))?;
+    let start = read_u16(subtable, starts + 2 * segment)?;
+    if code < start {
+        return None;
+    }
+    let delta = read_u16(subtable, deltas + 2 * segment)?;
+    let range_offset = read_u16(subtable, range_offsets + 2 * segment)? as usize;
+    if range_offset == 0 {
+        return Some(code.wrapping_add(delta));
+    }
+    // the offset is from where it's stored
+    let glyph = read_u16(subtable, range_offsets + 2 * segment + range_offset + 2 * (code - start) as usize)?;
+    Some(if glyph == 0 { 0 } else { glyph.wrapping_add(delta) })
+}
+
+// The glyphs a composite glyph is made of, none for a simple one.
+fn components(outline: &[u8]) -> Vec<u16> {
+    let mut components = Vec::new();
+    // a negative number of contours
+    if outline.len() < 10 || outline[0] & 0x80 == 0 {
+        return components;
+    }
+    let mut position = 10;
+    while let (Some(flags), Some(glyph)) = (read_u16(outline, position), read_u16(outline, position + 2)) {
+        components.push(glyph);
+        position += 4 + if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        position += match flags {
+            _ if flags & WE_HAVE_A_SCALE != 0 => 2,
+            _ if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 => 4,
+            _ if flags & WE_HAVE_A_TWO_BY_TWO != 0 => 8,
+            _ => 0,
+        };
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+    components
+}
+
+// The sum of the data as big endian 32 bit numbers, zero padded.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// A font with `outlines` as its glyphs, and a format 4 'cmap' (3, 1)
+// subtable mapping the codes from 'A' on to glyph 1 and on.
+#[cfg(test)]
+pub(crate) fn test_font(outlines: &[Vec<u8>]) -> Vec<u8> {
+    let be16 = |value: usize| (value as u16).to_be_bytes().to_vec();
+    let mut head = vec![0; 54];
+    head[INDEX_TO_LOC_FORMAT + 1] = 1;
+    let mut maxp = vec![0, 0, 0x50, 0];
+    maxp.extend(be16(outlines.len()));
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for outline in outlines {
+        loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+        glyf.extend_from_slice(outline);
+    }
+    loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+    // two segments: 'A' onwards with a delta, and the closing 0xffff one
+    let last = b'A' as usize + outlines.len() - 2;
+    let format4 = [be16(4), be16(32), be16(0), be16(4), be16(4), be16(1), be16(0), be16(last), be16(0xffff), be16(0),
+                       be16(b'A' as usize), be16(0xffff), be16(1usize.wrapping_sub(b'A' as usize) & 0xffff), be16(1), be16(0), be16(0)].concat();
+    let cmap = [be16(0), be16(1), be16(3), be16(1), 12u32.to_be_bytes().to_vec(), format4].concat();
+    let tables: Vec<(&[u8; 4], Vec<u8>)> = vec![(b"cmap", cmap), (b"glyf", glyf), (b"head", head), (b"loca", loca), (b"maxp", maxp)];
+
+    let mut font = [vec![0, 1, 0, 0], be16(tables.len()), vec![0; 6]].concat();
+    font.resize(12 + 16 * tables.len(), 0);
+    for (index, (tag, data)) in tables.iter().enumerate() {
+        let record = 12 + 16 * index;
+        let offset = font.len();
+        font[record..record + 4].copy_from_slice(*tag);
+        font[record + 8..record + 12].copy_from_slice(&(offset as u32).to_be_bytes());
+        font[record + 12..record + 16].copy_from_slice(&(data.len() as u32).to_be_bytes());
+        font.extend_from_slice(data);
+        while !font.len().is_multiple_of(4) {
+            font.push(0);
+        }
+    }
+    font
+}
+
+#[test]
+fn test_truetype() {
+    // a simple glyph is its number of contours and bounding box, and then
+    // its points; glyph 3 is made of 1 and 2
+    let simple = |mark: u8| vec![0, 1, 0, 0, 0, 0, 0, 10, 0, 10, mark, mark];
+    let composite = vec![0xff, 0xff, 0, 0, 0, 0, 0, 10, 0, 10,
+                         0, 0x20, 0, 1, 0, 0, 0, 0x08, 0, 2, 0, 0, 0x40, 0];
+    let data = test_font(&[simple(0), simple(1), simple(2), composite, simple(4), simple(5)]);
+    let font = TrueType::parse(&data).unwrap();
+    assert_eq!(font.glyph_count(), 6);
+    assert_eq!(font.glyph(3, 1, 'A' as u32), Some(1));
+    assert_eq!(font.glyph(3, 1, 'E' as u32), Some(5));
+    assert_eq!(font.glyph(3, 1, 'F' as u32), None);
+    assert_eq!(font.glyph(3, 0, 'A' as u32), None);
+    assert_eq!(components(font.table(b"glyf").unwrap()), Vec::<u16>::new());
+
+    let (subset, kept) = font.subset(&[3].iter().cloned().collect()).unwrap();
+    assert_eq!(kept, 4);
+    let smaller = TrueType::parse(&subset).unwrap();
+    assert_eq!(smaller.glyph_count(), 6);
+    assert_eq!(smaller.table(b"glyf").unwrap(), [simple(0), simple(1), simple(2), font.table(b"glyf").unwrap()[36..60].to_vec()].concat());
+    // glyphs 4 and 5 are empty now
+    let loca: Vec<u32> = smaller.table(b"loca").unwrap().chunks(4).map(|bytes| read_u32(bytes, 0).unwrap()).collect();
+    assert_eq!(loca, vec![0, 12, 24, 36, 60, 60, 60]);
+    assert_eq!(smaller.glyph(3, 1, 'C' as u32), Some(3));
+    assert_eq!(checksum(&subset), CHECKSUM_MAGIC);
+
+    assert!(TrueType::parse(b"OTTO\0\0").is_err());
+    assert!(TrueType::parse(&data[..40]).is_err());
+}
+
+#[test]
+fn test_format12() {
+    let be32 = |value: u32| value.to_be_bytes().to_vec();
+    // emoji from glyph 10 on, and a group whose glyph numbers run off the
+    // end of a u32
+    let groups = [(0x1f600, 0x1f602, 10), (0x10, 0x20, u32::MAX - 5)];
+    let mut format12 = [vec![0, 12, 0, 0], be32(16 + 12 * groups.len() as u32), be32(0), be32(groups.len() as u32)].concat();
+    for &(start, end, glyph) in &groups {
+        format12.extend([be32(start), be32(end), be32(glyph)].concat());
+    }
+    let cmap = [vec![0, 0, 0, 1, 0, 3, 0, 10], be32(12), format12].concat();
+    let font = [vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0], b"cmap".to_vec(), be32(0), be32(28), be32(cmap.len() as u32), cmap].concat();
+
+    let font = TrueType::parse(&font).unwrap();
+    assert_eq!(font.glyph(3, 10, 0x1f600), Some(10));
+    assert_eq!(font.glyph(3, 10, 0x1f602), Some(12));
+    assert_eq!(font.glyph(3, 10, 0x1f603), None);
+    assert_eq!(font.glyph(3, 10, 0x10), None);
+    assert_eq!(font.glyph(3, 10, 0x20), None);
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+use error::{PdfError, Result};
+use parsers::content::{Token, Tokenizer};
+use parsers::cos::DictNode;
+
+/*
+ * Appearance streams for filled in form fields.
+ *
+ * A field's value only shows up in viewers that rebuild appearances
+ * themselves, unless the widget's /AP /N stream draws it. These build that
+ * stream from the field's /Rect and /DA ("default appearance", e.g.
+ * "/Helv 0 Tf 0 g"):
+ *
+ *   - text fields, single line or wrapped, left/center/right aligned, and
+ *     comb fields with one character per cell
+ *   - list boxes, one option per line with the selected ones highlighted
+ *   - check boxes and radio buttons, one stream for the on state and an
+ *     empty one for /Off
+ *
+ * We don't read font metrics here, text is laid out assuming glyphs are
+ * half an em wide on average, which is close enough for Helvetica and the
+ * usual field fonts.
+ */
+
+// Average glyph width as a fraction of the font size.
+const AVERAGE_WIDTH: f64 = 0.5;
+// Space kept clear between the field's border and its text.
+const PADDING: f64 = 2.0;
+// Font size for auto sized (0 Tf) multiline fields, single line ones are
+// sized to the field's height instead.
+const AUTO_MULTILINE_SIZE: f64 = 12.0;
+// Background of selected list box options, what most viewers use.
+const HIGHLIGHT: Color = Color::Rgb(0.6, 0.75, 0.85);
+// ZapfDingbats check mark and filled circle, and their widths in ems.
+const CHECK: (&str, f64) = ("4", 0.846);
+const CIRCLE: (&str, f64) = ("l", 0.791);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Color {
+    Gray(f64),
+    Rgb(f64, f64, f64),
+    Cmyk(f64, f64, f64, f64),
+}
+
+impl Color {
+    // The operator setting this as the fill color.
+    fn operator(&self) -> String {
+        match *self {
+            Color::Gray(gray) => format!("{} g", number(gray)),
+            Color::Rgb(r, g, b) => format!("{} {} {} rg", number(r), number(g), number(b)),
+            Color::Cmyk(c, m, y, k) => format!("{} {} {} {} k", number(c), number(m), number(y), number(k)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DefaultAppearance {
+    // the font's name in the form's /DR resources, with its slash
+    pub font: String,
+    // 0 means fit the field
+    pub size: f64,
+    pub color: Color,
+}
+
+impl DefaultAppearance {
+    // Reads the font, size and color out of a /DA string. Anything else in
+    // there is ignored.
+    pub fn parse(da: &[u8]) -> Result<DefaultAppearance> {
+        let mut font = None;
+        let mut color = Color::Gray(0.0);
+        let mut operands = Vec::new();
+        for token in Tokenizer::new(da) {
+            match token? {
+                Token::Operand(operand) => operands.push(operand),
+                Token::Operator(operator) => {
+                    let numbers: Vec<f64> = operands.iter().filter_map(DictNode::as_float).collect();
+                    match (operator.as_str(), &operands[..], &numbers[..]) {
+                        ("Tf", [DictNode::Str(name), _], [size]) => font = Some((name.clone(), *size)),
+                        ("g", _, &[gray]) => color = Color::Gray(gray),
+                        ("rg", _, &[r, g, b]) => color = Color::Rgb(r, g, b),
+                        ("k", _, &[c, m, y, k]) => color = Color::Cmyk(c, m, y, k),
+                        _ => (),
+                    }
+                    operands.clear();
+                },
+                Token::InlineImage(..) => operands.clear(),
+            }
+        }
+        match font {
+            Some((font, size)) => Ok(DefaultAppearance { font, size, color }),
+            None => Err(PdfError::Syntax { what: "default appearance", position: 0 }),
+        }
+    }
+}
+
+// Written back out as a /DA string.
+impl fmt::Display for DefaultAppearance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} Tf {}", self.font, number(self.size), self.color.operator())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Quadding {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl Quadding {
+    // From a field's /Q entry.
+    pub fn from_q(q: i64) -> Quadding {
+        match q {
+            1 => Quadding::Center,
+            2 => Quadding::Right,
+            _ => Quadding::Left,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextField<'a> {
+    pub value: &'a str,
+    // [x1 y1 x2 y2] as in /Rect
+    pub rect: [f64; 4],
+    pub da: DefaultAppearance,
+    pub quadding: Quadding,
+    pub multiline: bool,
+    // /MaxLen of a comb field, the value is spread over that many cells
+    pub comb: Option<usize>,
+}
+
+// A form XObject's content and bounding box, ready to be written as the
+// stream of an /AP entry.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Appearance {
+    pub bbox: [f64; 4],
+    pub content: Vec<u8>,
+}
+
+impl Appearance {
+    // The stream dictionary, `resources` should provide the fonts the
+    // content uses, usually the form's /DR.
+    pub fn xobject(&self, resources: Option<DictNode>) -> DictNode {
+        let mut dict = HashMap::new();
+        dict.insert("Type".to_string(), DictNode::name("XObject"));
+        dict.insert("Subtype".to_string(), DictNode::name("Form"));
+        dict.insert("BBox".to_string(), DictNode::Array(self.bbox.iter().map(|&value| DictNode::Float(value)).collect()));
+        dict.insert("Length".to_string(), DictNode::Int(self.content.len() as i64));
+        if let Some(resources) = resources {
+            dict.insert("Resources".to_string(), resources);
+        }
+        DictNode::Dict(dict)
+    }
+}
+
+pub fn text_appearance(field: &TextField) -> Appearance {
+    let (width, height) = size_of(&field.rect);
+    let size = match field.da.size {
+        size if size > 0.0 => size,
+        _ if field.multiline => AUTO_MULTILINE_SIZE,
+        // fill the height, but don't get silly on tall single line fields
+        _ => ((height - 2.0 * PADDING) * 0.75).clamp(4.0, 12.0),
+    };
+
+    let mut content = String::new();
+    writeln!(content, "/Tx BMC\nq").unwrap();
+    // keep the text inside the border
+    writeln!(content, "{} {} {} {} re W n", number(PADDING / 2.0), number(PADDING / 2.0),
+             number(width - PADDING), number(height - PADDING)).unwrap();
+    writeln!(content, "BT\n{} {} Tf\n{}", field.da.font, number(size), field.da.color.operator()).unwrap();
+
+    // roughly centered on the field's height, the baseline sits a little
+    // above the bottom of the glyphs
+    let baseline = (height - size) / 2.0 + size * 0.22;
+    let mut show = |x: f64, y: f64, text: &str| {
+        writeln!(content, "1 0 0 1 {} {} Tm {} Tj", number(x), number(y), literal(text)).unwrap();
+    };
+    match field.comb {
+        Some(cells) if cells > 0 && !field.multiline => {
+            let cell = width / cells as f64;
+            for (index, ch) in field.value.chars().take(cells).enumerate() {
+                let x = cell * index as f64 + (cell - size * AVERAGE_WIDTH) / 2.0;
+                show(x, baseline, &ch.to_string());
+            }
+        },
+        _ if field.multiline => {
+            let leading = size * 1.15;
+            let columns = ((width - 2.0 * PADDING) / (size * AVERAGE_WIDTH)).floor().max(1.0) as usize;
+            for (index, line) in wrap(field.value, columns).iter().enumerate() {
+                let y = height - PADDING - size - leading * index as f64;
+                show(align(field.quadding, line, width, size), y, line);
+            }
+        },
+        _ => show(align(field.quadding, field.value, width, size), baseline, field.value),
+    }
+    writeln!(content, "ET\nQ\nEMC").unwrap();
+    Appearance { bbox: [0.0, 0.0, width, height], content: content.into_bytes() }
+}
+
+// A list box showing `rows` from `top` down, with the `selected` ones
+// highlighted.
+pub fn list_appearance(rect: &[f64; 4], da: &DefaultAppearance, rows: &[String], selected: &[usize], top: usize) -> Appearance {
+    let (width, height) = size_of(rect);
+    let size = if da.size > 0.0 { da.size } else { AUTO_MULTILINE_SIZE };
+    let leading = size * 1.15;
+    // the bottom of each visible row, by index into `rows`
+    let visible: Vec<(usize, f64)> = (top..rows.len())
+        .map(|index| (index, height - PADDING / 2.0 - leading * (index - top + 1) as f64))
+        .take_while(|&(_, bottom)| bottom + leading > 0.0)
+        .collect();
+
+    let mut content = String::new();
+    writeln!(content, "/Tx BMC
+q").unwrap();
+    writeln!(content, "{} {} {} {} re W n", number(PADDING / 2.0), number(PADDING / 2.0),
+             number(width - PADDING), number(height - PADDING)).unwrap();
+    for &(index, bottom) in &visible {
+        if selected.contains(&index) {
+            writeln!(content, "{}
+{} {} {} {} re f", HIGHLIGHT.operator(), number(PADDING / 2.0), number(bottom),
+                     number(width - PADDING), number(leading)).unwrap();
+        }
+    }
+    writeln!(content, "BT\n{} {} Tf\n{}", da.font, number(size), da.color.operator()).unwrap();
+    for &(index, bottom) in &visible {
+        let baseline = bottom + (leading - size) / 2.0 + size * 0.22;
+        writeln!(content, "1 0 0 1 {} {} Tm {} Tj", number(PADDING), number(baseline), literal(&rows[index])).unwrap();
+    }
+    writeln!(content, "ET\nQ\nEMC").unwrap();
+    Appearance { bbox: [0.0, 0.0, width, height], content: content.into_bytes() }
+}
+
+// The /N appearances of a check box, by state name: a check mark for
+// `on_state` and nothing for /Off.
+pub fn checkbox_appearances(rect: &[f64; 4], color: Color, on_state: &str) -> HashMap<String, Appearance> {
+    symbol_appearances(rect, color, on_state, CHECK)
+}
+
+// Same for a radio button, drawn as a filled circle.
+pub fn radio_appearances(rect: &[f64; 4], color: Color, on_state: &str) -> HashMap<String, Appearance> {
+    symbol_appearances(rect, color, on_state, CIRCLE)
+}
+
+fn symbol_appearances(rect: &[f64; 4], color: Color, on_state: &str, (glyph, glyph_width): (&str, f64)) -> HashMap<String, Appearance> {
+    let (width, height) = size_of(rect);
+    let bbox = [0.0, 0.0, width, height];
+    let size = width.min(height) * 0.8;
+    let x = (width - size * glyph_width) / 2.0;
+    let y = (height - size) / 2.0 + size * 0.15;
+    let content = format!("q\nBT\n/ZaDb {} Tf\n{}\n{} {} Td {} Tj\nET\nQ\n",
+                          number(size), color.operator(), number(x), number(y), literal(glyph));
+
+    let mut states = HashMap::new();
+    states.insert(on_state.trim_start_matches('/').to_string(), Appearance { bbox, content: content.into_bytes() });
+    states.insert("Off".to_string(), Appearance { bbox, content: Vec::new() });
+    states
+}
+
+fn size_of(rect: &[f64; 4]) -> (f64, f64) {
+    ((rect[2] - rect[0]).abs(), (rect[3] - rect[1]).abs())
+}
+
+fn align(quadding: Quadding, text: &str, width: f64, size: f64) -> f64 {
+    let text_width = text.chars().count() as f64 * size * AVERAGE_WIDTH;
+    match quadding {
+        Quadding::Left => PADDING,
+        Quadding::Center => (width - text_width) / 2.0,
+        Quadding::Right => width - PADDING - text_width,
+    }
+}
+
+// Breaks `text` into lines of at most `columns` characters, at spaces where
+// possible. Explicit line breaks are kept.
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split(['\r', '\n']) {
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let mut word = word.to_string();
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(line);
+                line = String::new();
+            }
+            // words longer than a line get cut
+            while word.chars().count() > columns {
+                let rest = word.split_off(word.char_indices().nth(columns).map_or(word.len(), |(index, _)| index));
+                if !line.is_empty() {
+                    lines.push(line);
+                }
+                lines.push(word);
+                line = String::new();
+                word = rest;
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// `text` as a literal string, in Latin-1 since that's what the standard
+// fonts' encodings agree on. Anything outside it becomes '?'.
+fn literal(text: &str) -> DictNode {
+    DictNode::LiteralString(text.chars().map(|ch| if (ch as u32) < 256 { ch as u8 } else { b'?' }).collect())
+}
+
+// Numbers as short as they can be in content streams, two decimals is
+// plenty at 1/72 inch.
+fn number(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[test]
+fn test_default_appearance() {
+    let da = DefaultAppearance::parse(b"/Helv 12 Tf 0 0 1 rg").unwrap();
+    assert_eq!(da, DefaultAppearance { font: "/Helv".to_string(), size: 12.0, color: Color::Rgb(0.0, 0.0, 1.0) });
+    let da = DefaultAppearance::parse(b"0.5 g /Cour 0 Tf").unwrap();
+    assert_eq!(da, DefaultAppearance { font: "/Cour".to_string(), size: 0.0, color: Color::Gray(0.5) });
+    assert!(DefaultAppearance::parse(b"0 g").is_err());
+    assert_eq!(da.to_string(), "/Cour 0 Tf 0.5 g");
+}
+
+#[test]
+fn test_text_appearance() {
+    let field = TextField {
+        value: "Jane (Doe)",
+        rect: [100.0, 500.0, 300.0, 520.0],
+        da: DefaultAppearance { font: "/Helv".to_string(), size: 10.0, color: Color::Gray(0.0) },
+        quadding: Quadding::Right,
+        multiline: false,
+        comb: None,
+    };
+    let appearance = text_appearance(&field);
+    assert_eq!(appearance.bbox, [0.0, 0.0, 200.0, 20.0]);
+    assert_eq!(String::from_utf8(appearance.content).unwrap(),
+               "/Tx BMC\nq\n1 1 198 18 re W n\nBT\n/Helv 10 Tf\n0 g\n1 0 0 1 148 7.2 Tm (Jane \\(Doe\\)) Tj\nET\nQ\nEMC\n");
+}
+
+#[test]
+fn test_comb_field() {
+    let field = TextField {
+        value: "1234",
+        rect: [0.0, 0.0, 40.0, 10.0],
+        da: DefaultAppearance { font: "/Cour".to_string(), size: 8.0, color: Color::Gray(0.0) },
+        quadding: Quadding::Left,
+        multiline: false,
+        comb: Some(4),
+    };
+    let content = String::from_utf8(text_appearance(&field).content).unwrap();
+    let positions: Vec<&str> = content.lines().filter(|line| line.ends_with("Tj")).collect();
+    assert_eq!(positions, vec![
+        "1 0 0 1 3 2.76 Tm (1) Tj",
+        "1 0 0 1 13 2.76 Tm (2) Tj",
+        "1 0 0 1 23 2.76 Tm (3) Tj",
+        "1 0 0 1 33 2.76 Tm (4) Tj",
+    ]);
+}
+
+#[test]
+fn test_wrap() {
+    assert_eq!(wrap("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+    assert_eq!(wrap("abcdefghijkl mn", 5), vec!["abcde", "fghij", "kl mn"]);
+    assert_eq!(wrap("one\ntwo", 10), vec!["one", "two"]);
+}
+
+#[test]
+fn test_list_appearance() {
+    let da = DefaultAppearance { font: "/Helv".to_string(), size: 10.0, color: Color::Gray(0.0) };
+    let rows: Vec<String> = vec!["Red".to_string(), "Green".to_string(), "Blue".to_string(), "Cyan".to_string()];
+    let appearance = list_appearance(&[0.0, 0.0, 100.0, 25.0], &da, &rows, &[2], 1);
+    assert_eq!(String::from_utf8(appearance.content).unwrap(),
+               "/Tx BMC\nq\n1 1 98 23 re W n\n\
+                0.6 0.75 0.85 rg\n1 1 98 11.5 re f\n\
+                BT\n/Helv 10 Tf\n0 g\n\
+                1 0 0 1 2 15.45 Tm (Green) Tj\n\
+                1 0 0 1 2 3.95 Tm (Blue) Tj\n\
+                1 0 0 1 2 -7.55 Tm (Cyan) Tj\n\
+                ET\nQ\nEMC\n");
+}
+
+#[test]
+fn test_checkbox_appearances() {
+    let states = checkbox_appearances(&[0.0, 0.0, 10.0, 10.0], Color::Gray(0.0), "/Yes");
+    assert_eq!(states["Off"].content, Vec::<u8>::new());
+    assert_eq!(String::from_utf8(states["Yes"].content.clone()).unwrap(),
+               "q\nBT\n/ZaDb 8 Tf\n0 g\n1.62 2.2 Td (4) Tj\nET\nQ\n");
+    assert_eq!(number(-0.001), "0");
+    assert_eq!(number(2.5), "2.5");
+}
@@ -0,0 +1,519 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, Options};
+use error::{PdfError, Result};
+use forms::appearance::{self, Appearance, Color, DefaultAppearance, Quadding, TextField};
+use forms::fields;
+use pages;
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Adding new fields to a document's form.
+ *
+ *   let mut form = FormBuilder::new();
+ *   form.add(NewField::text("name", 0, [72.0, 700.0, 300.0, 720.0]));
+ *   form.add(NewField::check_box("subscribe", 0, [72.0, 670.0, 86.0, 684.0], true));
+ *   form.add(NewField::signature("approval", 1, [72.0, 600.0, 250.0, 640.0]));
+ *   builder::add_fields(input, output, &form)?;
+ *
+ * Each field gets a single widget annotation, merged into the field's own
+ * dictionary, and an appearance stream so it shows up without the viewer
+ * having to draw it. Dotted names ("address.city") get their parent fields
+ * created as needed. Widgets are added to each page's /Annots in the order
+ * the fields were added, which is the tab order unless `tab_order` asks
+ * for another.
+ *
+ * Text is drawn in Helvetica and check marks in ZapfDingbats, which are
+ * added to the form's /DR resources as /Helv and /ZaDb when missing. Like
+ * the other rewrite tools, the new and changed objects and a new xref
+ * section are appended after the original bytes.
+ */
+
+// /Ff bits
+const READ_ONLY: i64 = 1;
+const REQUIRED: i64 = 1 << 1;
+const MULTILINE: i64 = 1 << 12;
+const COMB: i64 = 1 << 24;
+// annotation /F bit, widgets are printed
+const PRINT: i64 = 1 << 2;
+// /SigFlags bit: the document has signature fields
+const SIGNATURES_EXIST: i64 = 1;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FieldKind {
+    Text { value: String, multiline: bool, quadding: Quadding, max_len: Option<usize>, comb: bool },
+    CheckBox { checked: bool },
+    // an empty signature field, for someone to sign later
+    Signature,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct NewField {
+    // fully qualified, parent fields are separated by dots
+    pub name: String,
+    // index of the page the widget goes on
+    pub page: usize,
+    // [x1 y1 x2 y2] in default user space
+    pub rect: [f64; 4],
+    pub kind: FieldKind,
+    pub da: DefaultAppearance,
+    // /TU, what viewers show as a tooltip
+    pub tooltip: Option<String>,
+    pub read_only: bool,
+    pub required: bool,
+}
+
+impl NewField {
+    // An empty single line text field, in auto-sized black Helvetica.
+    pub fn text(name: &str, page: usize, rect: [f64; 4]) -> NewField {
+        let kind = FieldKind::Text { value: String::new(), multiline: false, quadding: Quadding::Left, max_len: None, comb: false };
+        NewField::new(name, page, rect, kind, "/Helv")
+    }
+
+    pub fn check_box(name: &str, page: usize, rect: [f64; 4], checked: bool) -> NewField {
+        NewField::new(name, page, rect, FieldKind::CheckBox { checked }, "/ZaDb")
+    }
+
+    pub fn signature(name: &str, page: usize, rect: [f64; 4]) -> NewField {
+        NewField::new(name, page, rect, FieldKind::Signature, "/Helv")
+    }
+
+    fn new(name: &str, page: usize, rect: [f64; 4], kind: FieldKind, font: &str) -> NewField {
+        NewField {
+            name: name.to_string(),
+            page,
+            rect,
+            kind,
+            da: DefaultAppearance { font: font.to_string(), size: 0.0, color: Color::Gray(0.0) },
+            tooltip: None,
+            read_only: false,
+            required: false,
+        }
+    }
+}
+
+// The page's /Tabs.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TabOrder {
+    Row,
+    Column,
+    Structure,
+}
+
+impl TabOrder {
+    fn name(&self) -> &'static str {
+        match *self {
+            TabOrder::Row => "R",
+            TabOrder::Column => "C",
+            TabOrder::Structure => "S",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FormBuilder {
+    fields: Vec<NewField>,
+    // set on the pages that get new widgets, None leaves /Tabs alone
+    pub tab_order: Option<TabOrder>,
+}
+
+// What `build` lays out, for the caller to hook into the document.
+#[derive(Debug, PartialEq)]
+pub struct Form {
+    // the new top level fields, for /AcroForm /Fields
+    pub fields: Vec<u64>,
+    // the new widgets to add to each page's /Annots, by page object number
+    pub annots: Vec<(u64, Vec<u64>)>,
+    // (object number, object, stream data for streams)
+    pub objects: Vec<(u64, DictNode, Option<Vec<u8>>)>,
+    pub has_signatures: bool,
+}
+
+// A parent field `build` creates for dotted names.
+struct Parent {
+    // its full name
+    path: String,
+    id: u64,
+    partial: String,
+    parent: Option<u64>,
+    kids: Vec<u64>,
+}
+
+impl FormBuilder {
+    pub fn new() -> FormBuilder {
+        FormBuilder::default()
+    }
+
+    pub fn add(&mut self, field: NewField) {
+        self.fields.push(field);
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    // Lays out the fields, their widgets and appearances as objects numbered
+    // from `first_id`. `pages` are the document's page object numbers and
+    // `resources` the form's /DR, which appearance streams get as their
+    // resources.
+    pub fn build(&self, pages: &[u64], resources: &DictNode, first_id: u64) -> Result<Form> {
+        let mut form = Form { fields: Vec::new(), annots: Vec::new(), objects: Vec::new(), has_signatures: false };
+        let mut next_id = first_id;
+        let mut parents: Vec<Parent> = Vec::new();
+        let mut names = HashSet::new();
+
+        for field in &self.fields {
+            let page = match pages.get(field.page) {
+                Some(&page) => page,
+                None => return Err(PdfError::Form(format!("{} is on page {} but there are only {} pages",
+                                                          field.name, field.page + 1, pages.len()))),
+            };
+            if field.name.split('.').any(|part| part.is_empty()) {
+                return Err(PdfError::Form(format!("{:?} isn't a valid field name", field.name)));
+            }
+            if !names.insert(field.name.clone()) || parents.iter().any(|parent| parent.path == field.name) {
+                return Err(PdfError::Form(format!("there's already a field called {}", field.name)));
+            }
+
+            // create the parents this field needs, outermost first, `parent`
+            // is an index into `parents`
+            let mut parent: Option<usize> = None;
+            let segments: Vec<&str> = field.name.split('.').collect();
+            for depth in 1..segments.len() {
+                let path = segments[..depth].join(".");
+                if names.contains(&path) {
+                    return Err(PdfError::Form(format!("{} can't have kids, it's a field of its own", path)));
+                }
+                parent = Some(match parents.iter().position(|existing| existing.path == path) {
+                    Some(index) => index,
+                    None => {
+                        let id = next_id;
+                        next_id += 1;
+                        let outer = parent.map(|outer| parents[outer].id);
+                        match parent {
+                            Some(outer) => parents[outer].kids.push(id),
+                            None => form.fields.push(id),
+                        }
+                        parents.push(Parent { path, id, partial: segments[depth - 1].to_string(), parent: outer, kids: Vec::new() });
+                        parents.len() - 1
+                    },
+                });
+            }
+
+            let id = next_id;
+            next_id += 1;
+            match parent {
+                Some(parent) => parents[parent].kids.push(id),
+                None => form.fields.push(id),
+            }
+            match form.annots.iter().position(|&(existing, _)| existing == page) {
+                Some(index) => form.annots[index].1.push(id),
+                None => form.annots.push((page, vec![id])),
+            }
+
+            let mut dict = HashMap::new();
+            dict.insert("Type".to_string(), DictNode::name("Annot"));
+            dict.insert("Subtype".to_string(), DictNode::name("Widget"));
+            dict.insert("T".to_string(), text_string(segments[segments.len() - 1]));
+            dict.insert("Rect".to_string(), DictNode::Array(field.rect.iter().map(|&value| DictNode::Float(value)).collect()));
+            dict.insert("P".to_string(), DictNode::reference(page));
+            dict.insert("F".to_string(), DictNode::Int(PRINT));
+            dict.insert("DA".to_string(), DictNode::LiteralString(field.da.to_string().into_bytes()));
+            if let Some(parent) = parent {
+                dict.insert("Parent".to_string(), DictNode::reference(parents[parent].id));
+            }
+            if let Some(ref tooltip) = field.tooltip {
+                dict.insert("TU".to_string(), text_string(tooltip));
+            }
+            let mut flags = 0;
+            if field.read_only {
+                flags |= READ_ONLY;
+            }
+            if field.required {
+                flags |= REQUIRED;
+            }
+
+            let mut appearances = Vec::new();
+            match field.kind {
+                FieldKind::Text { ref value, multiline, quadding, max_len, comb } => {
+                    dict.insert("FT".to_string(), DictNode::name("Tx"));
+                    if !value.is_empty() {
+                        dict.insert("V".to_string(), text_string(value));
+                    }
+                    if quadding != Quadding::Left {
+                        // /Q counts in the same order as Quadding
+                        dict.insert("Q".to_string(), DictNode::Int(quadding as i64));
+                    }
+                    if let Some(max_len) = max_len {
+                        dict.insert("MaxLen".to_string(), DictNode::Int(max_len as i64));
+                    }
+                    if multiline {
+                        flags |= MULTILINE;
+                    }
+                    // comb fields need a /MaxLen to know how many cells there are
+                    let comb = if comb && !multiline { max_len } else { None };
+                    if comb.is_some() {
+                        flags |= COMB;
+                    }
+                    let text = TextField { value, rect: field.rect, da: field.da.clone(), quadding, multiline, comb };
+                    appearances.push((None, appearance::text_appearance(&text)));
+                },
+                FieldKind::CheckBox { checked } => {
+                    let state = if checked { DictNode::name("Yes") } else { DictNode::name("Off") };
+                    dict.insert("FT".to_string(), DictNode::name("Btn"));
+                    dict.insert("V".to_string(), state.clone());
+                    dict.insert("AS".to_string(), state);
+                    // the ZapfDingbats check mark, for viewers that redraw it
+                    let mut characteristics = HashMap::new();
+                    characteristics.insert("CA".to_string(), DictNode::LiteralString(b"4".to_vec()));
+                    dict.insert("MK".to_string(), DictNode::Dict(characteristics));
+                    let mut states: Vec<(String, Appearance)> = appearance::checkbox_appearances(&field.rect, field.da.color, "Yes")
+                        .into_iter()
+                        .collect();
+                    states.sort_by(|a, b| a.0.cmp(&b.0));
+                    appearances.extend(states.into_iter().map(|(state, appearance)| (Some(state), appearance)));
+                },
+                FieldKind::Signature => {
+                    dict.insert("FT".to_string(), DictNode::name("Sig"));
+                    form.has_signatures = true;
+                    let (width, height) = ((field.rect[2] - field.rect[0]).abs(), (field.rect[3] - field.rect[1]).abs());
+                    appearances.push((None, Appearance { bbox: [0.0, 0.0, width, height], content: Vec::new() }));
+                },
+            }
+            if flags != 0 {
+                dict.insert("Ff".to_string(), DictNode::Int(flags));
+            }
+
+            // /N is the stream itself, or a dictionary of them by state
+            let mut states = HashMap::new();
+            let mut normal = None;
+            for (state, appearance) in appearances {
+                let stream_id = next_id;
+                next_id += 1;
+                form.objects.push((stream_id, appearance.xobject(Some(resources.clone())), Some(appearance.content)));
+                match state {
+                    Some(state) => {
+                        states.insert(state, DictNode::reference(stream_id));
+                    },
+                    None => normal = Some(DictNode::reference(stream_id)),
+                }
+            }
+            let normal = normal.unwrap_or(DictNode::Dict(states));
+            let mut ap = HashMap::new();
+            ap.insert("N".to_string(), normal);
+            dict.insert("AP".to_string(), DictNode::Dict(ap));
+            form.objects.push((id, DictNode::Dict(dict), None));
+        }
+
+        for parent in parents {
+            let mut dict = HashMap::new();
+            dict.insert("T".to_string(), text_string(&parent.partial));
+            dict.insert("Kids".to_string(), DictNode::Array(parent.kids.into_iter().map(DictNode::reference).collect()));
+            if let Some(outer) = parent.parent {
+                dict.insert("Parent".to_string(), DictNode::reference(outer));
+            }
+            form.objects.push((parent.id, DictNode::Dict(dict), None));
+        }
+        form.objects.sort_by_key(|object| object.0);
+        Ok(form)
+    }
+}
+
+// Adds the fields of `form` to the document at `input`, writing the result
+// to `output`.
+pub fn add_fields(input: &Path, output: &Path, form: &FormBuilder) -> Result<()> {
+    let mut doc = Document::open(input, Options::default())?;
+    let mut data = fs::read(input)?;
+    let mut xref = doc.xref.clone();
+
+    for existing in fields::fields(&mut doc)? {
+        let clash = form.fields.iter().any(|field| {
+            field.name == existing.name || field.name.starts_with(&format!("{}.", existing.name))
+        });
+        if clash {
+            return Err(PdfError::Form(format!("the document already has a field called {}", existing.name)));
+        }
+    }
+    // kids have to be references, pages that aren't can't be pointed at
+    let pages: Vec<u64> = pages::pages(&mut doc)?.iter().filter_map(|page| page.id).collect();
+    let (catalog_id, mut catalog) = match doc.trailer_entry("Root")? {
+        Some((Some(id), DictNode::Dict(catalog))) => (id, catalog),
+        _ => return Err(PdfError::Syntax { what: "document catalog", position: 0 }),
+    };
+    let form_id = match catalog.get("AcroForm") {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => Some(id as u64),
+        _ => None,
+    };
+    let mut acro_form = fields::acro_form(&mut doc)?.unwrap_or_default();
+
+    // the fonts appearances use, added to /DR /Font when missing
+    let mut next_id = xref.len() as u64;
+    let mut resources = match acro_form.get("DR") {
+        Some(resources) => match doc.resolve(resources)? {
+            DictNode::Dict(resources) => resources,
+            _ => HashMap::new(),
+        },
+        None => HashMap::new(),
+    };
+    let mut fonts = match resources.get("Font") {
+        Some(fonts) => match doc.resolve(fonts)? {
+            DictNode::Dict(fonts) => fonts,
+            _ => HashMap::new(),
+        },
+        None => HashMap::new(),
+    };
+    for &(key, base_font) in &[("Helv", "Helvetica"), ("ZaDb", "ZapfDingbats")] {
+        if fonts.contains_key(key) {
+            continue;
+        }
+        let mut font = HashMap::new();
+        font.insert("Type".to_string(), DictNode::name("Font"));
+        font.insert("Subtype".to_string(), DictNode::name("Type1"));
+        font.insert("BaseFont".to_string(), DictNode::name(base_font));
+        if key == "Helv" {
+            font.insert("Encoding".to_string(), DictNode::name("WinAnsiEncoding"));
+        }
+        transcode::append_object(&mut data, &mut xref, next_id, &DictNode::Dict(font))?;
+        fonts.insert(key.to_string(), DictNode::reference(next_id));
+        next_id += 1;
+    }
+    resources.insert("Font".to_string(), DictNode::Dict(fonts));
+    let resources = DictNode::Dict(resources);
+
+    let built = form.build(&pages, &resources, next_id)?;
+    for (id, object, stream) in &built.objects {
+        match *stream {
+            Some(ref stream) => transcode::append_stream(&mut data, &mut xref, *id, object, stream)?,
+            None => transcode::append_object(&mut data, &mut xref, *id, object)?,
+        }
+    }
+
+    for (page_id, widgets) in &built.annots {
+        let mut page = match doc.cached_object(*page_id)? {
+            Some(DictNode::Dict(page)) => page.clone(),
+            _ => continue,
+        };
+        let mut annots = match page.get("Annots") {
+            Some(annots) => match doc.resolve(annots)? {
+                DictNode::Array(annots) => annots,
+                _ => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        annots.extend(widgets.iter().map(|&widget| DictNode::reference(widget)));
+        page.insert("Annots".to_string(), DictNode::Array(annots));
+        if let Some(tab_order) = form.tab_order {
+            page.insert("Tabs".to_string(), DictNode::name(tab_order.name()));
+        }
+        transcode::append_object(&mut data, &mut xref, *page_id, &DictNode::Dict(page))?;
+    }
+
+    let mut top_level = match acro_form.get("Fields") {
+        Some(fields) => match doc.resolve(fields)? {
+            DictNode::Array(fields) => fields,
+            _ => Vec::new(),
+        },
+        None => Vec::new(),
+    };
+    top_level.extend(built.fields.iter().map(|&field| DictNode::reference(field)));
+    acro_form.insert("Fields".to_string(), DictNode::Array(top_level));
+    acro_form.insert("DR".to_string(), resources);
+    acro_form.entry("DA".to_string()).or_insert_with(|| DictNode::LiteralString(b"/Helv 0 Tf 0 g".to_vec()));
+    if built.has_signatures {
+        let flags = match acro_form.get("SigFlags") {
+            Some(&DictNode::Int(flags)) => flags,
+            _ => 0,
+        };
+        acro_form.insert("SigFlags".to_string(), DictNode::Int(flags | SIGNATURES_EXIST));
+    }
+    match form_id {
+        Some(form_id) => transcode::append_object(&mut data, &mut xref, form_id, &DictNode::Dict(acro_form))?,
+        None => {
+            catalog.insert("AcroForm".to_string(), DictNode::Dict(acro_form));
+            transcode::append_object(&mut data, &mut xref, catalog_id, &DictNode::Dict(catalog))?;
+        },
+    }
+
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// A text string: Latin-1 when it fits, UTF-16BE with a byte order mark
+// otherwise.
+pub(crate) fn text_string(text: &str) -> DictNode {
+    if text.chars().all(|ch| (ch as u32) < 256) {
+        return DictNode::LiteralString(text.chars().map(|ch| ch as u8).collect());
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&[(unit >> 8) as u8, unit as u8]);
+    }
+    DictNode::HexString(bytes)
+}
+
+#[test]
+fn test_build() {
+    let mut form = FormBuilder::new();
+    form.add(NewField::text("address.city", 1, [0.0, 0.0, 100.0, 20.0]));
+    form.add(NewField::check_box("agree", 0, [0.0, 0.0, 10.0, 10.0], true));
+    form.add(NewField::text("address.zip", 1, [0.0, 30.0, 100.0, 50.0]));
+    let built = form.build(&[3, 7], &DictNode::Dict(HashMap::new()), 20).unwrap();
+
+    // address 20, city 21 and its appearance 22, agree 23 with Off 24 and
+    // Yes 25, zip 26 and its appearance 27
+    assert_eq!(built.fields, vec![20, 23]);
+    assert_eq!(built.annots, vec![(7, vec![21, 26]), (3, vec![23])]);
+    let ids: Vec<u64> = built.objects.iter().map(|object| object.0).collect();
+    assert_eq!(ids, (20..28).collect::<Vec<u64>>());
+    assert_eq!(built.objects[0].1, DictNode::Dict(hashmap!{
+        "T".to_string() => DictNode::LiteralString(b"address".to_vec()),
+        "Kids".to_string() => DictNode::Array(vec![DictNode::reference(21), DictNode::reference(26)]),
+    }));
+
+    let agree = match built.objects[3].1 {
+        DictNode::Dict(ref agree) => agree,
+        _ => panic!("fields are dictionaries"),
+    };
+    assert_eq!(agree["V"], DictNode::name("Yes"));
+    assert_eq!(agree["P"], DictNode::reference(3));
+    assert_eq!(agree["AP"], DictNode::Dict(hashmap!{
+        "N".to_string() => DictNode::Dict(hashmap!{ "Off".to_string() => DictNode::reference(24), "Yes".to_string() => DictNode::reference(25) }),
+    }));
+    assert!(built.objects[4].2.is_some());
+    assert!(!built.has_signatures);
+}
+
+#[test]
+fn test_build_errors() {
+    let build = |names: &[&str]| {
+        let mut form = FormBuilder::new();
+        for name in names {
+            form.add(NewField::signature(name, 0, [0.0, 0.0, 10.0, 10.0]));
+        }
+        form.build(&[1], &DictNode::Null, 10)
+    };
+    assert!(build(&["a", "b.c"]).unwrap().has_signatures);
+    assert!(build(&["a", "a"]).is_err());
+    assert!(build(&["a", "a.b"]).is_err());
+    assert!(build(&["a.b", "a"]).is_err());
+    assert!(build(&["a..b"]).is_err());
+
+    let mut form = FormBuilder::new();
+    form.add(NewField::text("far", 2, [0.0, 0.0, 10.0, 10.0]));
+    assert!(form.build(&[1], &DictNode::Null, 10).is_err());
+}
+
+#[test]
+fn test_text_string() {
+    assert_eq!(text_string("Café"), DictNode::LiteralString(b"Caf\xe9".to_vec()));
+    assert_eq!(text_string("€"), DictNode::HexString(vec![0xfe, 0xff, 0x20, 0xac]));
+}
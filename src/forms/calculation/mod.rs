@@ -0,0 +1,243 @@
+use std::fmt;
+
+use document::Document;
+use error::Result;
+use forms::fields::{self, Field};
+use parsers::cos::{self, DictNode};
+
+/*
+ * Which form fields get their values from JavaScript.
+ *
+ * Viewers run each field's calculate action (/AA /C) whenever another field
+ * changes, in the order of the form's /CO array. Filling a form outside a
+ * viewer runs none of that, so totals, dates and so on have to be computed
+ * by whoever fills it. This reports:
+ *
+ *   - calculated fields in /CO order, with the fields their script reads
+ *   - calculated fields missing from /CO, which viewers may never update
+ *   - every field with scripts, and what triggers them
+ *
+ * Dependencies are picked out of the scripts by looking for getField("...")
+ * calls and the field lists of Acrobat's AFSimple_Calculate helper, which
+ * covers what form designers generate. Hand written scripts can of course
+ * get at fields in ways this won't see.
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Calculation {
+    pub field: String,
+    // position in /CO, None if it isn't there
+    pub order: Option<usize>,
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScriptedField {
+    pub field: String,
+    // "calculate", "format", "keystroke" or "validate"
+    pub triggers: Vec<&'static str>,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CalculationReport {
+    pub calculations: Vec<Calculation>,
+    pub scripted: Vec<ScriptedField>,
+}
+
+// Additional-actions keys of fields and what they're for.
+const TRIGGERS: &[(&str, &str)] = &[("C", "calculate"), ("F", "format"), ("K", "keystroke"), ("V", "validate")];
+
+pub fn report(doc: &mut Document) -> Result<CalculationReport> {
+    let order: Vec<u64> = match fields::acro_form(doc)?.and_then(|mut form| form.remove("CO")) {
+        Some(order) => match doc.resolve(&order)? {
+            DictNode::Array(order) => order.iter()
+                .filter_map(|field| match *field {
+                    DictNode::ObjectReference(id, _) if id >= 0 => Some(id as u64),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        None => Vec::new(),
+    };
+
+    let mut report = CalculationReport::default();
+    for field in fields::fields(doc)? {
+        let scripts = scripts(doc, &field)?;
+        if scripts.is_empty() {
+            continue;
+        }
+        if let Some((_, script)) = scripts.iter().find(|&&(trigger, _)| trigger == "calculate") {
+            report.calculations.push(Calculation {
+                field: field.name.clone(),
+                order: field.id.and_then(|id| order.iter().position(|&ordered| ordered == id)),
+                depends_on: dependencies(script),
+            });
+        }
+        report.scripted.push(ScriptedField { field: field.name, triggers: scripts.iter().map(|&(trigger, _)| trigger).collect() });
+    }
+    // fields missing from /CO go last, in field order
+    report.calculations.sort_by_key(|calculation| calculation.order.unwrap_or(usize::MAX));
+    Ok(report)
+}
+
+// The field's JavaScript actions as (trigger, script).
+fn scripts(doc: &mut Document, field: &Field) -> Result<Vec<(&'static str, String)>> {
+    let actions = match field.get("AA") {
+        Some(actions) => match doc.resolve(actions)? {
+            DictNode::Dict(actions) => actions,
+            _ => return Ok(Vec::new()),
+        },
+        None => return Ok(Vec::new()),
+    };
+    let mut scripts = Vec::new();
+    for &(key, trigger) in TRIGGERS {
+        let action = match actions.get(key) {
+            Some(action) => doc.resolve(action)?,
+            None => continue,
+        };
+        if let Some(script) = javascript(doc, &action)? {
+            scripts.push((trigger, script));
+        }
+    }
+    Ok(scripts)
+}
+
+// The script of a JavaScript action, which is either a string or a stream.
+fn javascript(doc: &mut Document, action: &DictNode) -> Result<Option<String>> {
+    let action = match *action {
+        DictNode::Dict(ref action) if action.get("S") == Some(&DictNode::name("JavaScript")) => action,
+        _ => return Ok(None),
+    };
+    match action.get("JS") {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => {
+            let entry = match doc.xref.get(id as usize) {
+                Some(entry) => entry.clone(),
+                None => return Ok(None),
+            };
+            Ok(doc.stream(&entry)?.map(|(_, data)| cos::latin1(&data)))
+        },
+        Some(script) => Ok(fields::text(script)),
+        None => Ok(None),
+    }
+}
+
+// The names of the fields a calculate script reads.
+fn dependencies(script: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = script;
+    while let Some(at) = rest.find("getField(") {
+        rest = &rest[at + "getField(".len()..];
+        if let Some((name, _)) = quoted(rest.trim_start()) {
+            names.push(name.to_string());
+        }
+    }
+    // AFSimple_Calculate("SUM", new Array("a", "b")) or ("SUM", "a, b")
+    let mut rest = script;
+    while let Some(at) = rest.find("AFSimple_Calculate(") {
+        rest = &rest[at + "AFSimple_Calculate(".len()..];
+        let arguments = &rest[..closing_paren(rest)];
+        let mut strings = Vec::new();
+        let mut remaining = arguments;
+        while let Some(start) = remaining.find(['"', '\'']) {
+            match quoted(&remaining[start..]) {
+                Some((string, after)) => {
+                    strings.push(string);
+                    remaining = after;
+                },
+                None => break,
+            }
+        }
+        // the first one is the operation
+        for list in strings.iter().skip(1) {
+            names.extend(list.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()));
+        }
+    }
+    let mut unique = Vec::new();
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+// A "..." or '...' string at the start of `text`, and what follows it.
+fn quoted(text: &str) -> Option<(&str, &str)> {
+    let quote = text.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let end = text[1..].find(quote)? + 1;
+    Some((&text[1..end], &text[end + 1..]))
+}
+
+// Where the call whose arguments start `text` ends.
+fn closing_paren(text: &str) -> usize {
+    let mut depth = 1;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return index;
+                }
+            },
+            _ => (),
+        }
+    }
+    text.len()
+}
+
+impl fmt::Display for CalculationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.scripted.is_empty() {
+            return writeln!(f, "No fields use JavaScript");
+        }
+        if !self.calculations.is_empty() {
+            writeln!(f, "Calculated fields, in the order viewers compute them:")?;
+            for calculation in &self.calculations {
+                match calculation.order {
+                    Some(order) => write!(f, "  {}. {}", order + 1, calculation.field)?,
+                    None => write!(f, "  -. {} (not in /CO, viewers may not update it)", calculation.field)?,
+                }
+                if !calculation.depends_on.is_empty() {
+                    write!(f, " <- {}", calculation.depends_on.join(", "))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        writeln!(f, "Fields with JavaScript:")?;
+        for scripted in &self.scripted {
+            writeln!(f, "  {}: {}", scripted.field, scripted.triggers.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dependencies() {
+    assert_eq!(dependencies("AFSimple_Calculate(\"SUM\", new Array (\"price\", \"shipping\"));"), vec!["price", "shipping"]);
+    assert_eq!(dependencies("AFSimple_Calculate('PRD', 'qty, unit.price')"), vec!["qty", "unit.price"]);
+    assert_eq!(dependencies("var a = this.getField(\"a\").value; event.value = a * getField( 'b').value + getField(\"a\").value;"),
+               vec!["a", "b"]);
+    assert_eq!(dependencies("event.value = 42;"), Vec::<String>::new());
+}
+
+#[test]
+fn test_display() {
+    let report = CalculationReport {
+        calculations: vec![
+            Calculation { field: "total".to_string(), order: Some(0), depends_on: vec!["price".to_string(), "qty".to_string()] },
+            Calculation { field: "tax".to_string(), order: None, depends_on: Vec::new() },
+        ],
+        scripted: vec![
+            ScriptedField { field: "total".to_string(), triggers: vec!["calculate", "format"] },
+            ScriptedField { field: "tax".to_string(), triggers: vec!["calculate"] },
+        ],
+    };
+    assert_eq!(report.to_string(), "Calculated fields, in the order viewers compute them:\n  \
+                                    1. total <- price, qty\n  \
+                                    -. tax (not in /CO, viewers may not update it)\n\
+                                    Fields with JavaScript:\n  \
+                                    total: calculate, format\n  \
+                                    tax: calculate\n");
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use error::{PdfError, Result};
+use forms::appearance::{self, Appearance, DefaultAppearance, Quadding, TextField};
+use parsers::cos::{self, DictNode};
+
+/*
+ * Choice fields (/FT /Ch): list boxes and combo boxes.
+ *
+ * Each entry of /Opt is either a plain string or an [export display] pair:
+ * /V holds export values, the display text is what the user sees. Values
+ * given to `select` may be either, they're stored as export values so they
+ * round-trip through whatever reads the form data. Combo boxes with the
+ * Edit flag also take text that isn't one of the options.
+ *
+ * The field dictionary should already have its inheritable entries (/FT,
+ * /Ff, /V, /DA...) merged in from its parents.
+ */
+
+// /Ff bits
+const COMBO: i64 = 1 << 17;
+const EDIT: i64 = 1 << 18;
+const MULTI_SELECT: i64 = 1 << 21;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChoiceOption {
+    pub export: Vec<u8>,
+    pub display: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChoiceField {
+    pub options: Vec<ChoiceOption>,
+    pub combo: bool,
+    pub editable: bool,
+    pub multi_select: bool,
+    // export values of the current selection
+    pub selected: Vec<Vec<u8>>,
+    // /TI, the option shown at the top of a scrolled list box
+    pub top: usize,
+}
+
+impl ChoiceField {
+    pub fn from_dict(dict: &DictNode) -> Result<ChoiceField> {
+        let map = match *dict {
+            DictNode::Dict(ref map) if map.get("FT") == Some(&DictNode::name("Ch")) => map,
+            _ => return Err(PdfError::Form("not a choice field".to_string())),
+        };
+        let flags = match map.get("Ff") {
+            Some(&DictNode::Int(flags)) => flags,
+            _ => 0,
+        };
+        let options: Vec<ChoiceOption> = match map.get("Opt") {
+            Some(DictNode::Array(options)) => options.iter().filter_map(option).collect(),
+            _ => Vec::new(),
+        };
+
+        let mut selected: Vec<Vec<u8>> = match map.get("V") {
+            Some(DictNode::Array(values)) => values.iter().filter_map(text).collect(),
+            Some(value) => text(value).into_iter().collect(),
+            None => Vec::new(),
+        };
+        // /I is only there to tell apart options sharing an export value,
+        // but some writers only fill that in
+        if selected.is_empty() {
+            if let Some(DictNode::Array(indices)) = map.get("I") {
+                selected = indices.iter()
+                    .filter_map(|index| match *index {
+                        DictNode::Int(index) if index >= 0 => options.get(index as usize),
+                        _ => None,
+                    })
+                    .map(|option| option.export.clone())
+                    .collect();
+            }
+        }
+
+        Ok(ChoiceField {
+            options,
+            combo: flags & COMBO != 0,
+            editable: flags & COMBO != 0 && flags & EDIT != 0,
+            multi_select: flags & MULTI_SELECT != 0,
+            selected,
+            top: match map.get("TI") {
+                Some(&DictNode::Int(top)) if top >= 0 => top as usize,
+                _ => 0,
+            },
+        })
+    }
+
+    // Selects `values`, matched against export values first and display
+    // text second.
+    pub fn select(&mut self, values: &[&[u8]]) -> Result<()> {
+        if values.len() > 1 && !self.multi_select {
+            return Err(PdfError::Form("only one option can be selected".to_string()));
+        }
+        let mut selected = Vec::new();
+        for &value in values {
+            let option = self.options.iter().find(|option| option.export == value)
+                .or_else(|| self.options.iter().find(|option| option.display == value));
+            match option {
+                Some(option) => selected.push(option.export.clone()),
+                None if self.editable => selected.push(value.to_vec()),
+                None => return Err(PdfError::Form(format!("{} isn't one of the options", DictNode::LiteralString(value.to_vec())))),
+            }
+        }
+        self.selected = selected;
+        Ok(())
+    }
+
+    // Indices of the selected options, in option order.
+    pub fn selected_indices(&self) -> Vec<usize> {
+        self.options.iter().enumerate()
+            .filter(|&(_, option)| self.selected.contains(&option.export))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // What the selection looks like, display text where there's an option
+    // for it.
+    pub fn display_values(&self) -> Vec<&[u8]> {
+        self.selected.iter()
+            .map(|value| match self.options.iter().find(|option| &option.export == value) {
+                Some(option) => &option.display[..],
+                None => &value[..],
+            })
+            .collect()
+    }
+
+    // Writes the selection back into the field dictionary: /V, and /I for
+    // list boxes.
+    pub fn write_value(&self, dict: &mut HashMap<String, DictNode>) {
+        match self.selected.len() {
+            0 => {
+                dict.remove("V");
+            },
+            1 => {
+                dict.insert("V".to_string(), DictNode::LiteralString(self.selected[0].clone()));
+            },
+            _ => {
+                let values = self.selected.iter().map(|value| DictNode::LiteralString(value.clone())).collect();
+                dict.insert("V".to_string(), DictNode::Array(values));
+            },
+        }
+        let indices = self.selected_indices();
+        if self.combo || indices.is_empty() {
+            dict.remove("I");
+        } else {
+            dict.insert("I".to_string(), DictNode::Array(indices.iter().map(|&index| DictNode::Int(index as i64)).collect()));
+        }
+    }
+
+    // The /N appearance for a widget at `rect`: the selected text for combo
+    // boxes, the visible options for list boxes.
+    pub fn appearance(&self, rect: [f64; 4], da: &DefaultAppearance) -> Appearance {
+        if self.combo {
+            let value = self.display_values().first().map(|value| cos::text_string(value)).unwrap_or_default();
+            let field = TextField { value: &value, rect, da: da.clone(), quadding: Quadding::Left, multiline: false, comb: None };
+            return appearance::text_appearance(&field);
+        }
+        let rows: Vec<String> = self.options.iter().map(|option| cos::text_string(&option.display)).collect();
+        appearance::list_appearance(&rect, da, &rows, &self.selected_indices(), self.top)
+    }
+}
+
+fn text(node: &DictNode) -> Option<Vec<u8>> {
+    match *node {
+        DictNode::LiteralString(ref bytes) | DictNode::HexString(ref bytes) => Some(bytes.clone()),
+        _ => None,
+    }
+}
+
+fn option(node: &DictNode) -> Option<ChoiceOption> {
+    match *node {
+        DictNode::Array(ref pair) if pair.len() == 2 => {
+            Some(ChoiceOption { export: text(&pair[0])?, display: text(&pair[1])? })
+        },
+        _ => text(node).map(|value| ChoiceOption { export: value.clone(), display: value }),
+    }
+}
+
+#[cfg(test)]
+fn colors(flags: i64) -> ChoiceField {
+    let dict = DictNode::Dict(hashmap!{
+        "FT".to_string() => DictNode::name("Ch"),
+        "Ff".to_string() => DictNode::Int(flags),
+        "Opt".to_string() => DictNode::Array(vec![
+            DictNode::Array(vec![DictNode::LiteralString(b"R".to_vec()), DictNode::LiteralString(b"Red".to_vec())]),
+            DictNode::Array(vec![DictNode::LiteralString(b"G".to_vec()), DictNode::LiteralString(b"Green".to_vec())]),
+            DictNode::LiteralString(b"Blue".to_vec()),
+        ]),
+        "I".to_string() => DictNode::Array(vec![DictNode::Int(1)]),
+    });
+    ChoiceField::from_dict(&dict).unwrap()
+}
+
+#[test]
+fn test_from_dict() {
+    let field = colors(MULTI_SELECT);
+    assert!(field.multi_select && !field.combo && !field.editable);
+    assert_eq!(field.options[2], ChoiceOption { export: b"Blue".to_vec(), display: b"Blue".to_vec() });
+    assert_eq!(field.selected, vec![b"G".to_vec()]);
+    assert_eq!(field.display_values(), vec![&b"Green"[..]]);
+    assert!(ChoiceField::from_dict(&DictNode::Dict(HashMap::new())).is_err());
+}
+
+#[test]
+fn test_select() {
+    let mut field = colors(MULTI_SELECT);
+    field.select(&[b"Blue", b"Red"]).unwrap();
+    assert_eq!(field.selected, vec![b"Blue".to_vec(), b"R".to_vec()]);
+    assert_eq!(field.selected_indices(), vec![0, 2]);
+    assert!(field.select(&[b"Purple"]).is_err());
+
+    let mut values = HashMap::new();
+    field.write_value(&mut values);
+    assert_eq!(values, hashmap!{
+        "V".to_string() => DictNode::Array(vec![DictNode::LiteralString(b"Blue".to_vec()), DictNode::LiteralString(b"R".to_vec())]),
+        "I".to_string() => DictNode::Array(vec![DictNode::Int(0), DictNode::Int(2)]),
+    });
+
+    let mut combo = colors(COMBO);
+    assert!(combo.select(&[b"R", b"G"]).is_err());
+    assert!(combo.select(&[b"Purple"]).is_err());
+    let mut editable = colors(COMBO | EDIT);
+    editable.select(&[b"Purple"]).unwrap();
+    editable.write_value(&mut values);
+    assert_eq!(values, hashmap!{ "V".to_string() => DictNode::LiteralString(b"Purple".to_vec()) });
+}
@@ -0,0 +1,134 @@
+use std::collections::{HashMap, HashSet};
+
+use document::Document;
+use error::Result;
+use parsers::cos::DictNode;
+
+/*
+ * The fields of a document's interactive form, flattened out of the
+ * /AcroForm /Fields tree.
+ *
+ * Fields nest: "address.city" is a field /T (city) whose /Parent has
+ * /T (address). Only terminal fields, the ones that hold a value, are
+ * returned, with their fully qualified name and the inheritable entries
+ * of their ancestors merged into their dictionary. A terminal field's kids,
+ * if it has any, are its widget annotations.
+ */
+
+// Entries a field takes from its ancestors when it doesn't have its own.
+const INHERITABLE: &[&str] = &["FT", "Ff", "V", "DV", "DA", "Q"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Field {
+    // None for fields written directly into their parent's /Kids
+    pub id: Option<u64>,
+    pub name: String,
+    pub dict: DictNode,
+}
+
+impl Field {
+    pub fn get(&self, key: &str) -> Option<&DictNode> {
+        self.dict.get(key)
+    }
+}
+
+// The catalog's /AcroForm dictionary, if the document has a form.
+pub fn acro_form(doc: &mut Document) -> Result<Option<HashMap<String, DictNode>>> {
+    let catalog = match doc.trailer_entry("Root")? {
+        Some((_, DictNode::Dict(catalog))) => catalog,
+        _ => return Ok(None),
+    };
+    match catalog.get("AcroForm") {
+        Some(form) => match doc.resolve(form)? {
+            DictNode::Dict(form) => Ok(Some(form)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+// Every terminal field, in /Fields order.
+pub fn fields(doc: &mut Document) -> Result<Vec<Field>> {
+    let roots = match acro_form(doc)? {
+        Some(form) => match form.get("Fields") {
+            Some(fields) => doc.resolve(fields)?,
+            None => return Ok(Vec::new()),
+        },
+        None => return Ok(Vec::new()),
+    };
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    if let DictNode::Array(roots) = roots {
+        for root in roots {
+            walk(doc, &root, "", &HashMap::new(), &mut seen, &mut found)?;
+        }
+    }
+    Ok(found)
+}
+
+fn walk(doc: &mut Document, node: &DictNode, parent_name: &str, inherited: &HashMap<String, DictNode>,
+        seen: &mut HashSet<u64>, found: &mut Vec<Field>) -> Result<()> {
+    let id = match *node {
+        DictNode::ObjectReference(id, _) if id >= 0 => {
+            // shared or looping kids, each field only counts once
+            if !seen.insert(id as u64) {
+                return Ok(());
+            }
+            Some(id as u64)
+        },
+        _ => None,
+    };
+    let mut map = match doc.resolve(node)? {
+        DictNode::Dict(map) => map,
+        _ => return Ok(()),
+    };
+
+    let name = match map.get("T").and_then(text) {
+        Some(partial) if parent_name.is_empty() => partial,
+        Some(partial) => format!("{}.{}", parent_name, partial),
+        None => parent_name.to_string(),
+    };
+    let mut inherited = inherited.clone();
+    for &key in INHERITABLE {
+        match map.get(key) {
+            Some(value) => {
+                inherited.insert(key.to_string(), value.clone());
+            },
+            None => {
+                if let Some(value) = inherited.get(key) {
+                    map.insert(key.to_string(), value.clone());
+                }
+            },
+        }
+    }
+
+    let kids = match map.get("Kids") {
+        Some(kids) => match doc.resolve(kids)? {
+            DictNode::Array(kids) => kids,
+            _ => Vec::new(),
+        },
+        None => Vec::new(),
+    };
+    // kids without a name of their own are just this field's widgets
+    let mut child_fields = Vec::new();
+    for kid in &kids {
+        if let DictNode::Dict(ref kid_map) = doc.resolve(kid)? {
+            if kid_map.contains_key("T") {
+                child_fields.push(kid.clone());
+            }
+        }
+    }
+    if child_fields.is_empty() {
+        found.push(Field { id, name, dict: DictNode::Dict(map) });
+        return Ok(());
+    }
+    for kid in child_fields {
+        walk(doc, &kid, &name, &inherited, seen, found)?;
+    }
+    Ok(())
+}
+
+// A text string's value, decoded as UTF-16 or PDFDocEncoding.
+pub fn text(node: &DictNode) -> Option<String> {
+    node.as_text()
+}
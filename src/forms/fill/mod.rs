@@ -0,0 +1,514 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use forms::appearance::{self, Appearance, DefaultAppearance, Quadding, TextField};
+use forms::builder;
+use forms::choice::ChoiceField;
+use forms::fields::{self, Field};
+use graphics::{Matrix, IDENTITY};
+use writer::Writer;
+use pages;
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Filling in a document's form, and flattening it into its pages.
+ *
+ *   let values = HashMap::from([("name".to_string(), "Ada".to_string()), ("subscribe".to_string(), "Yes".to_string())]);
+ *   fill::fill(input, output, &values, false)?;
+ *
+ * Values go by fully qualified field name:
+ *
+ *   - text fields take the text as it is
+ *   - check boxes take "Off" (or "", "false", "no") to clear them, and
+ *     their on state's name, or "Yes", "On", "true", to check them
+ *   - radio buttons take the on state of the button to turn on
+ *   - choice fields take an option's export value or display text, ""
+ *     clears the selection
+ *
+ * Each widget of a filled field gets a new appearance stream, so the value
+ * shows up without the viewer having to draw it. Push buttons and signature
+ * fields have no value to set this way.
+ *
+ * Flattening then draws each visible widget's appearance into its page and
+ * drops the widgets and the catalog's /AcroForm, leaving plain page content.
+ * Other annotations stay. The page's own content is wrapped in q/Q first,
+ * the way letterhead does, so the appearances land where their widgets were.
+ *
+ * Like the other rewrites this is an incremental update. Fields written
+ * directly into their parent's /Kids can't be updated on their own and are
+ * refused, so are encrypted documents.
+ */
+
+// /Ff bits
+const MULTILINE: i64 = 1 << 12;
+const RADIO: i64 = 1 << 15;
+const PUSHBUTTON: i64 = 1 << 16;
+const COMB: i64 = 1 << 24;
+// annotation /F bit for widgets that aren't shown or printed
+const HIDDEN: i64 = 1 << 1;
+
+// Check box values, compared lowercased.
+const OFF_VALUES: &[&str] = &["off", "", "false", "no"];
+const ON_VALUES: &[&str] = &["yes", "on", "true"];
+
+// For fields with no /DA of their own or in the form.
+const DEFAULT_DA: &[u8] = b"/Helv 0 Tf 0 g";
+
+// What flattened appearances are drawn as in the pages' resources, with a
+// number after it when the name is taken.
+const RESOURCE_NAME: &str = "Field";
+
+// The document being filled in, and what's been written for it so far.
+struct Filling<'a> {
+    doc: &'a mut Document,
+    out: Writer,
+    // field and widget dictionaries as they're to be written, by object number
+    changed: BTreeMap<u64, HashMap<String, DictNode>>,
+    // dictionaries of the appearance streams written, for flattening
+    streams: HashMap<u64, DictNode>,
+    // the form's /DR and /DA
+    resources: Option<DictNode>,
+    da: Vec<u8>,
+}
+
+pub fn fill(input: &Path, output: &Path, values: &HashMap<String, String>, flatten: bool) -> Result<()> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options)?;
+    let acro_form = match fields::acro_form(&mut doc)? {
+        Some(acro_form) => acro_form,
+        None => return Err(PdfError::Form("the document has no form".to_string())),
+    };
+    let all = fields::fields(&mut doc)?;
+    let da = match acro_form.get("DA") {
+        Some(da) => doc.resolve(da)?.as_string().map(|da| da.to_vec()),
+        None => None,
+    };
+
+    let xref = doc.xref.clone();
+    let mut filling = Filling {
+        out: Writer::appending(fs::read(input)?, xref),
+        doc: &mut doc,
+        changed: BTreeMap::new(),
+        streams: HashMap::new(),
+        resources: acro_form.get("DR").cloned(),
+        da: da.unwrap_or_else(|| DEFAULT_DA.to_vec()),
+    };
+    // in name order, so the same values always give the same file
+    let mut names: Vec<&String> = values.keys().collect();
+    names.sort();
+    for name in names {
+        let field = match all.iter().find(|field| &field.name == name) {
+            Some(field) => field,
+            None => return Err(PdfError::Form(format!("there's no field called {}", name))),
+        };
+        filling.fill(field, &values[name])?;
+    }
+    if flatten {
+        filling.flatten()?;
+    }
+
+    let mut out = filling.out;
+    for (&id, dict) in &filling.changed {
+        out.object(id, &DictNode::Dict(dict.clone()))?;
+    }
+    let (mut data, xref) = (out.data, out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+impl<'a> Filling<'a> {
+    fn fill(&mut self, field: &Field, value: &str) -> Result<()> {
+        let id = match field.id {
+            Some(id) => id,
+            None => return Err(PdfError::Form(format!("{} isn't an object of its own, it can't be updated", field.name))),
+        };
+        match field.get("FT").and_then(DictNode::as_name) {
+            Some("Tx") => self.fill_text(field, id, value),
+            Some("Btn") => self.fill_button(field, id, value),
+            Some("Ch") => self.fill_choice(field, id, value),
+            _ => Err(PdfError::Form(format!("{} can't be filled in", field.name))),
+        }
+    }
+
+    fn fill_text(&mut self, field: &Field, id: u64, value: &str) -> Result<()> {
+        let mut dict = self.dict(id)?;
+        dict.insert("V".to_string(), builder::text_string(value));
+        self.changed.insert(id, dict);
+
+        let flags = flags(field);
+        let multiline = flags & MULTILINE != 0;
+        // comb fields need a /MaxLen to know how many cells there are
+        let comb = match field.get("MaxLen") {
+            Some(&DictNode::Int(max_len)) if max_len > 0 && flags & COMB != 0 && !multiline => Some(max_len as usize),
+            _ => None,
+        };
+        for widget in self.widgets(id)? {
+            let mut dict = self.dict(widget)?;
+            let q = dict.get("Q").or_else(|| field.get("Q")).and_then(DictNode::as_int).unwrap_or(0);
+            let text = TextField {
+                value,
+                rect: rect_of(&dict),
+                da: self.default_appearance(&dict, field)?,
+                quadding: Quadding::from_q(q),
+                multiline,
+                comb,
+            };
+            let normal = self.appearance(&appearance::text_appearance(&text))?;
+            dict.insert("AP".to_string(), DictNode::Dict(HashMap::from([("N".to_string(), normal)])));
+            self.changed.insert(widget, dict);
+        }
+        Ok(())
+    }
+
+    fn fill_button(&mut self, field: &Field, id: u64, value: &str) -> Result<()> {
+        let flags = flags(field);
+        if flags & PUSHBUTTON != 0 {
+            return Err(PdfError::Form(format!("{} is a push button, it has no value", field.name)));
+        }
+        let radio = flags & RADIO != 0;
+        // each widget's on state, and whether it has appearances for it
+        let mut widgets = Vec::new();
+        for widget in self.widgets(id)? {
+            let dict = self.dict(widget)?;
+            let normal = match dict.get("AP") {
+                Some(ap) => match self.doc.resolve(ap)?.get("N") {
+                    Some(normal) => self.doc.resolve(normal)?,
+                    None => DictNode::Null,
+                },
+                None => DictNode::Null,
+            };
+            let on_state = states(&normal).and_then(|states| {
+                let mut names: Vec<&String> = states.keys().filter(|&state| state != "Off").collect();
+                names.sort();
+                names.first().map(|&state| state.clone())
+            });
+            widgets.push((widget, dict, on_state.clone().unwrap_or_else(|| "Yes".to_string()), on_state.is_some()));
+        }
+
+        let on_states: Vec<String> = widgets.iter().map(|widget| widget.2.clone()).collect();
+        let state = match button_state(value, &on_states, radio) {
+            Some(state) => state,
+            None => return Err(PdfError::Form(format!("{:?} isn't one of the states of {}", value, field.name))),
+        };
+        let mut dict = self.dict(id)?;
+        dict.insert("V".to_string(), DictNode::name(&state));
+        self.changed.insert(id, dict);
+
+        for (widget, mut dict, on_state, drawn) in widgets {
+            let shown = if on_state == state { &state[..] } else { "Off" };
+            dict.insert("AS".to_string(), DictNode::name(shown));
+            if !drawn {
+                let rect = rect_of(&dict);
+                let color = self.default_appearance(&dict, field)?.color;
+                let appearances = if radio {
+                    appearance::radio_appearances(&rect, color, &on_state)
+                } else {
+                    appearance::checkbox_appearances(&rect, color, &on_state)
+                };
+                let mut appearances: Vec<(String, Appearance)> = appearances.into_iter().collect();
+                appearances.sort_by(|a, b| a.0.cmp(&b.0));
+                let mut states = HashMap::new();
+                for (state, appearance) in appearances {
+                    states.insert(state, self.appearance(&appearance)?);
+                }
+                dict.insert("AP".to_string(), DictNode::Dict(HashMap::from([("N".to_string(), DictNode::Dict(states))])));
+            }
+            if widget == id {
+                dict.insert("V".to_string(), DictNode::name(&state));
+            }
+            self.changed.insert(widget, dict);
+        }
+        Ok(())
+    }
+
+    fn fill_choice(&mut self, field: &Field, id: u64, value: &str) -> Result<()> {
+        let mut choice = ChoiceField::from_dict(&field.dict)?;
+        if value.is_empty() {
+            choice.select(&[])?;
+        } else {
+            let text = builder::text_string(value);
+            choice.select(&[text.as_string().unwrap_or_default()])?;
+        }
+        let mut dict = self.dict(id)?;
+        choice.write_value(&mut dict);
+        self.changed.insert(id, dict);
+
+        for widget in self.widgets(id)? {
+            let mut dict = self.dict(widget)?;
+            let da = self.default_appearance(&dict, field)?;
+            let normal = self.appearance(&choice.appearance(rect_of(&dict), &da))?;
+            dict.insert("AP".to_string(), DictNode::Dict(HashMap::from([("N".to_string(), normal)])));
+            self.changed.insert(widget, dict);
+        }
+        Ok(())
+    }
+
+    // Draws the widgets into their pages, takes them out of /Annots and the
+    // form out of the catalog.
+    fn flatten(&mut self) -> Result<()> {
+        let save_id = self.out.reserve();
+        self.out.stream(save_id, &stream_dict(2), b"q\n")?;
+        for page in pages::pages(self.doc)? {
+            let id = match page.id {
+                Some(id) => id,
+                None => continue,
+            };
+            let annots = match page.dict.get_resolved(self.doc, "Annots")? {
+                Some(DictNode::Array(annots)) => annots,
+                _ => continue,
+            };
+            let mut resources = page.resources.as_dict().cloned().unwrap_or_default();
+            let mut xobjects = match resources.get("XObject") {
+                Some(xobjects) => self.doc.resolve(xobjects)?.as_dict().cloned().unwrap_or_default(),
+                None => HashMap::new(),
+            };
+            let mut kept = Vec::new();
+            let mut content = String::new();
+            for annot in &annots {
+                let dict = self.lookup(annot)?;
+                if dict.get("Subtype").and_then(DictNode::as_name) != Some("Widget") {
+                    kept.push(annot.clone());
+                    continue;
+                }
+                let hidden = dict.get("F").and_then(DictNode::as_int).unwrap_or(0) & HIDDEN != 0;
+                if let (false, Some((form, bbox, matrix))) = (hidden, self.normal_appearance(&dict)?) {
+                    let name = resource_name(&xobjects);
+                    xobjects.insert(name.clone(), form);
+                    content.push_str(&draw(placement(bbox, matrix, rect_of(&dict)), &name));
+                }
+            }
+            if kept.len() == annots.len() {
+                continue;
+            }
+
+            let mut dict = page.dict.as_dict().cloned().unwrap_or_default();
+            if kept.is_empty() {
+                dict.remove("Annots");
+            } else {
+                dict.insert("Annots".to_string(), DictNode::Array(kept));
+            }
+            if !content.is_empty() {
+                // restore the state from before the page's own content first
+                let content = format!("\nQ\n{}", content);
+                let draw_id = self.out.reserve();
+                self.out.stream(draw_id, &stream_dict(content.len()), content.as_bytes())?;
+                let mut contents = vec![DictNode::reference(save_id)];
+                contents.extend(pages::content_streams(self.doc, &page)?);
+                contents.push(DictNode::reference(draw_id));
+                resources.insert("XObject".to_string(), DictNode::Dict(xobjects));
+                dict.insert("Contents".to_string(), DictNode::Array(contents));
+                dict.insert("Resources".to_string(), DictNode::Dict(resources));
+            }
+            self.out.object(id, &DictNode::Dict(dict))?;
+        }
+
+        match self.doc.trailer_entry("Root")? {
+            Some((Some(catalog_id), DictNode::Dict(mut catalog))) => {
+                catalog.remove("AcroForm");
+                self.out.object(catalog_id, &DictNode::Dict(catalog))
+            },
+            _ => Err(PdfError::Syntax { what: "document catalog", position: 0 }),
+        }
+    }
+
+    // The widget's /N appearance, the one for its /AS state if it has
+    // several: a reference to the stream, its /BBox and its /Matrix.
+    fn normal_appearance(&mut self, dict: &HashMap<String, DictNode>) -> Result<Option<(DictNode, [f64; 4], Matrix)>> {
+        let mut normal = match dict.get("AP") {
+            Some(ap) => match self.doc.resolve(ap)?.get("N") {
+                Some(normal) => normal.clone(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        let mut stream = self.stream_dict(&normal)?;
+        if let Some(states) = states(&stream) {
+            match dict.get("AS").and_then(DictNode::as_name).and_then(|state| states.get(state)) {
+                Some(state) => normal = state.clone(),
+                None => return Ok(None),
+            }
+            stream = self.stream_dict(&normal)?;
+        }
+        // streams are always indirect, anything else is broken
+        let bbox = match (&normal, stream.get("BBox").and_then(DictNode::as_rect)) {
+            (&DictNode::ObjectReference(..), Some(bbox)) => bbox,
+            _ => return Ok(None),
+        };
+        let matrix = stream.get("Matrix").and_then(Matrix::from_node).unwrap_or(IDENTITY);
+        Ok(Some((normal, bbox, matrix)))
+    }
+
+    // What `node` points at, including the appearance streams written here.
+    fn stream_dict(&mut self, node: &DictNode) -> Result<DictNode> {
+        match *node {
+            DictNode::ObjectReference(id, _) if id >= 0 && self.streams.contains_key(&(id as u64)) => Ok(self.streams[&(id as u64)].clone()),
+            _ => self.doc.resolve(node),
+        }
+    }
+
+    // Writes `appearance` as a form XObject using the form's resources, and
+    // returns a reference to it.
+    fn appearance(&mut self, appearance: &Appearance) -> Result<DictNode> {
+        let id = self.out.reserve();
+        let dict = appearance.xobject(self.resources.clone());
+        self.out.stream(id, &dict, &appearance.content)?;
+        self.streams.insert(id, dict);
+        Ok(DictNode::reference(id))
+    }
+
+    // The widget's /DA, its field's or the form's.
+    fn default_appearance(&mut self, widget: &HashMap<String, DictNode>, field: &Field) -> Result<DefaultAppearance> {
+        let da = match widget.get("DA").or_else(|| field.get("DA")) {
+            Some(da) => self.doc.resolve(da)?.as_string().map(|da| da.to_vec()),
+            None => None,
+        };
+        DefaultAppearance::parse(&da.unwrap_or_else(|| self.da.clone()))
+    }
+
+    // A terminal field's widgets: its kids, or the field itself when they're
+    // merged into one dictionary.
+    fn widgets(&mut self, id: u64) -> Result<Vec<u64>> {
+        let kids = match self.dict(id)?.get("Kids") {
+            Some(kids) => self.doc.resolve(kids)?,
+            None => return Ok(vec![id]),
+        };
+        Ok(kids.as_array().unwrap_or(&[]).iter()
+            .filter_map(|kid| match *kid {
+                DictNode::ObjectReference(kid, _) if kid >= 0 => Some(kid as u64),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Object `id` as it's to be written, a dictionary.
+    fn dict(&mut self, id: u64) -> Result<HashMap<String, DictNode>> {
+        match self.changed.get(&id) {
+            Some(dict) => Ok(dict.clone()),
+            None => match self.doc.cached_object(id)? {
+                Some(DictNode::Dict(dict)) => Ok(dict.clone()),
+                _ => Err(PdfError::Form(format!("object {} isn't a dictionary", id))),
+            },
+        }
+    }
+
+    // Same for a direct or indirect annotation, empty if it's not a
+    // dictionary.
+    fn lookup(&mut self, node: &DictNode) -> Result<HashMap<String, DictNode>> {
+        if let DictNode::ObjectReference(id, _) = *node {
+            if let Some(dict) = self.changed.get(&(id as u64)) {
+                return Ok(dict.clone());
+            }
+        }
+        Ok(self.doc.resolve(node)?.as_dict().cloned().unwrap_or_default())
+    }
+}
+
+// The state setting a button to `value` gives it, "Off" or one of its
+// widgets' `on_states`. Check boxes can also be just turned on.
+fn button_state(value: &str, on_states: &[String], radio: bool) -> Option<String> {
+    let lowercase = value.to_lowercase();
+    if OFF_VALUES.contains(&lowercase.as_str()) {
+        return Some("Off".to_string());
+    }
+    if let Some(state) = on_states.iter().find(|&state| state == value.trim_start_matches('/')) {
+        return Some(state.clone());
+    }
+    match on_states.first() {
+        Some(state) if !radio && ON_VALUES.contains(&lowercase.as_str()) => Some(state.clone()),
+        _ => None,
+    }
+}
+
+// An /N entry's appearances by state, None when it's a stream of its own.
+fn states(normal: &DictNode) -> Option<&HashMap<String, DictNode>> {
+    match *normal {
+        DictNode::Dict(ref map) if !map.contains_key("BBox") => Some(map),
+        _ => None,
+    }
+}
+
+// The matrix drawing an appearance into its widget's /Rect: the bounding
+// box, once through the form's /Matrix, scaled and moved onto the
+// rectangle. Drawing the form applies its /Matrix itself.
+fn placement(bbox: [f64; 4], matrix: Matrix, rect: [f64; 4]) -> Matrix {
+    let corners = [(bbox[0], bbox[1]), (bbox[2], bbox[1]), (bbox[0], bbox[3]), (bbox[2], bbox[3])];
+    let corners: Vec<(f64, f64)> = corners.iter().map(|&(x, y)| matrix.apply(x, y)).collect();
+    let (x0, x1) = corners.iter().fold((f64::MAX, f64::MIN), |(low, high), &(x, _)| (low.min(x), high.max(x)));
+    let (y0, y1) = corners.iter().fold((f64::MAX, f64::MIN), |(low, high), &(_, y)| (low.min(y), high.max(y)));
+    let (width, height) = ((rect[2] - rect[0]).abs(), (rect[3] - rect[1]).abs());
+    let scale_x = if x1 > x0 { width / (x1 - x0) } else { 1.0 };
+    let scale_y = if y1 > y0 { height / (y1 - y0) } else { 1.0 };
+    Matrix::translate(-x0, -y0) * Matrix::new(scale_x, 0.0, 0.0, scale_y, 0.0, 0.0) * Matrix::translate(rect[0].min(rect[2]), rect[1].min(rect[3]))
+}
+
+fn draw(matrix: Matrix, name: &str) -> String {
+    let numbers: Vec<String> = [matrix.a, matrix.b, matrix.c, matrix.d, matrix.e, matrix.f].iter().map(|&value| number(value)).collect();
+    format!("q {} cm {} Do Q\n", numbers.join(" "), DictNode::name(name))
+}
+
+fn resource_name(xobjects: &HashMap<String, DictNode>) -> String {
+    let mut name = RESOURCE_NAME.to_string();
+    let mut count = 1;
+    while xobjects.contains_key(&name) {
+        name = format!("{}{}", RESOURCE_NAME, count);
+        count += 1;
+    }
+    name
+}
+
+fn flags(field: &Field) -> i64 {
+    field.get("Ff").and_then(DictNode::as_int).unwrap_or(0)
+}
+
+fn rect_of(dict: &HashMap<String, DictNode>) -> [f64; 4] {
+    dict.get("Rect").and_then(DictNode::as_rect).unwrap_or_default()
+}
+
+fn stream_dict(length: usize) -> DictNode {
+    DictNode::Dict(HashMap::from([("Length".to_string(), DictNode::Int(length as i64))]))
+}
+
+// Four decimals, scales need more than positions do.
+fn number(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[test]
+fn test_button_state() {
+    let states = vec!["Yes".to_string()];
+    assert_eq!(button_state("off", &states, false), Some("Off".to_string()));
+    assert_eq!(button_state("", &states, false), Some("Off".to_string()));
+    assert_eq!(button_state("true", &states, false), Some("Yes".to_string()));
+    assert_eq!(button_state("maybe", &states, false), None);
+
+    let choices = vec!["Red".to_string(), "Green".to_string()];
+    assert_eq!(button_state("/Green", &choices, true), Some("Green".to_string()));
+    assert_eq!(button_state("On", &choices, true), None);
+    assert_eq!(button_state("On", &choices, false), Some("Red".to_string()));
+}
+
+#[test]
+fn test_placement() {
+    let rect = [50.0, 700.0, 150.0, 720.0];
+    assert_eq!(draw(placement([0.0, 0.0, 100.0, 20.0], IDENTITY, rect), "Field"), "q 1 0 0 1 50 700 cm /Field Do Q\n");
+    // turned a quarter by its /Matrix, then halved to fit
+    let turned = Matrix::new(0.0, 1.0, -1.0, 0.0, 0.0, 0.0);
+    assert_eq!(draw(placement([0.0, 0.0, 20.0, 100.0], turned, [10.0, 10.0, 60.0, 20.0]), "Field1"), "q 0.5 0 0 0.5 60 10 cm /Field1 Do Q\n");
+
+    let state = DictNode::Dict(hashmap!{ "Off".to_string() => DictNode::reference(4), "Yes".to_string() => DictNode::reference(5) });
+    assert!(states(&state).is_some());
+    let stream = DictNode::Dict(hashmap!{ "BBox".to_string() => DictNode::Array(vec![]) });
+    assert!(states(&stream).is_none());
+}
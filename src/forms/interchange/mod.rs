@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use document::{Document, Options};
+use error::{PdfError, Result};
+use forms::builder;
+use forms::fields;
+use parsers::cos::DictNode;
+
+/*
+ * Form data in and out of documents, as FDF or XFDF files: the usual way
+ * to fill the same form in for a batch of people, or to collect what they
+ * filled in.
+ *
+ *   fs::write("data.xfdf", interchange::export(&mut doc, Format::Xfdf, Some("form.pdf"))?)?;
+ *   let values = interchange::import(Path::new("data.xfdf"))?;
+ *   fill::fill(input, output, &values, false)?;
+ *
+ * FDF is PDF syntax: a single object with an /FDF dictionary whose /Fields
+ * nest like a form's, /T holding each part of a field's name. XFDF is the
+ * same thing in XML, <field name="..."> elements nesting around <value>
+ * ones. Both are written with fields nested by their dotted names.
+ *
+ * Only values travel, not appearances or annotations. Exported values are
+ * written as the document has them, check boxes and radio buttons by
+ * state name. Imported ones come back as text, one per field the way
+ * `fill` takes them: a multiple selection keeps its first value.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Fdf,
+    Xfdf,
+}
+
+impl Format {
+    // XFDF for .xfdf files, FDF otherwise.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("xfdf") => Format::Xfdf,
+            _ => Format::Fdf,
+        }
+    }
+}
+
+// A field, or a part of a dotted name with fields under it.
+#[derive(Debug, PartialEq, Clone)]
+struct Node {
+    name: String,
+    value: Option<DictNode>,
+    kids: Vec<Node>,
+}
+
+// The values of the document's fields as an FDF or XFDF file. `file` is
+// the form it's for, as the file refers to it.
+pub fn export(doc: &mut Document, format: Format, file: Option<&str>) -> Result<Vec<u8>> {
+    let mut values = Vec::new();
+    for field in fields::fields(doc)? {
+        if let Some(value) = field.get("V") {
+            let value = doc.resolve(value)?;
+            match value {
+                DictNode::LiteralString(_) | DictNode::HexString(_) | DictNode::Str(_) | DictNode::Array(_) => values.push((field.name, value)),
+                _ => (),
+            }
+        }
+    }
+    let nodes = tree(&values);
+    Ok(match format {
+        Format::Fdf => fdf(&nodes, file),
+        Format::Xfdf => xfdf(&nodes, file).into_bytes(),
+    })
+}
+
+// The values in an FDF or XFDF file, by full field name.
+pub fn import(path: &Path) -> Result<HashMap<String, String>> {
+    let data = fs::read(path)?;
+    if data.starts_with(b"%FDF") {
+        return import_fdf(path);
+    }
+    let text = String::from_utf8_lossy(&data);
+    if text.contains("<xfdf") {
+        return Ok(parse_xfdf(&text)?.into_iter().collect());
+    }
+    Err(PdfError::Form(format!("{} is neither FDF nor XFDF", path.display())))
+}
+
+// FDF files have a trailer but no xref, the scan finds the object.
+fn import_fdf(path: &Path) -> Result<HashMap<String, String>> {
+    let mut doc = Document::open_by_scanning(path, Options::default())?;
+    let entries: Vec<_> = doc.xref.entries().filter(|(_, entry)| !entry.is_free).map(|(_, entry)| entry.clone()).collect();
+    for entry in entries {
+        let fdf = match doc.object(&entry)?.get("FDF") {
+            Some(fdf) => doc.resolve(fdf)?,
+            None => continue,
+        };
+        let mut values = HashMap::new();
+        if let Some(roots) = fdf.get_resolved(&mut doc, "Fields")? {
+            for root in roots.as_array().unwrap_or(&[]) {
+                walk(&mut doc, root, "", &mut values, 0)?;
+            }
+        }
+        return Ok(values);
+    }
+    Err(PdfError::Syntax { what: "FDF dictionary", position: 0 })
+}
+
+fn walk(doc: &mut Document, node: &DictNode, parent: &str, values: &mut HashMap<String, String>, depth: usize) -> Result<()> {
+    // a tree this deep is a loop
+    if depth > 32 {
+        return Ok(());
+    }
+    let node = doc.resolve(node)?;
+    let name = match node.get("T").and_then(fields::text) {
+        Some(partial) if parent.is_empty() => partial,
+        Some(partial) => format!("{}.{}", parent, partial),
+        None => parent.to_string(),
+    };
+    if let Some(value) = node.get_resolved(doc, "V")? {
+        let first = match value {
+            DictNode::Array(ref values) => values.first().cloned(),
+            value => Some(value),
+        };
+        if let Some(text) = first.as_ref().and_then(text) {
+            values.insert(name.clone(), text);
+        }
+    }
+    if let Some(kids) = node.get_resolved(doc, "Kids")? {
+        for kid in kids.as_array().unwrap_or(&[]) {
+            walk(doc, kid, &name, values, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+// Fields nested by their dotted names, in the order they first come up.
+fn tree(values: &[(String, DictNode)]) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    for (name, value) in values {
+        insert(&mut nodes, name, value);
+    }
+    nodes
+}
+
+fn insert(nodes: &mut Vec<Node>, name: &str, value: &DictNode) {
+    let (first, rest) = match name.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (name, None),
+    };
+    let index = match nodes.iter().position(|node| node.name == first) {
+        Some(index) => index,
+        None => {
+            nodes.push(Node { name: first.to_string(), value: None, kids: Vec::new() });
+            nodes.len() - 1
+        },
+    };
+    match rest {
+        Some(rest) => insert(&mut nodes[index].kids, rest, value),
+        None => nodes[index].value = Some(value.clone()),
+    }
+}
+
+fn fdf(nodes: &[Node], file: Option<&str>) -> Vec<u8> {
+    let mut dict = HashMap::from([("Fields".to_string(), DictNode::Array(nodes.iter().map(fdf_field).collect()))]);
+    if let Some(file) = file {
+        dict.insert("F".to_string(), builder::text_string(file));
+    }
+    let root = DictNode::Dict(HashMap::from([("FDF".to_string(), DictNode::Dict(dict))]));
+    let mut out = b"%FDF-1.2\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    out.extend(format!("1 0 obj\n{}\nendobj\ntrailer\n<< /Root 1 0 R >>\n%%EOF\n", root).into_bytes());
+    out
+}
+
+fn fdf_field(node: &Node) -> DictNode {
+    let mut dict = HashMap::from([("T".to_string(), builder::text_string(&node.name))]);
+    if let Some(ref value) = node.value {
+        dict.insert("V".to_string(), value.clone());
+    }
+    if !node.kids.is_empty() {
+        dict.insert("Kids".to_string(), DictNode::Array(node.kids.iter().map(fdf_field).collect()));
+    }
+    DictNode::Dict(dict)
+}
+
+fn xfdf(nodes: &[Node], file: Option<&str>) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xfdf xmlns=\"http://ns.adobe.com/xfdf/\" xml:space=\"preserve\">\n");
+    if let Some(file) = file {
+        out.push_str(&format!("  <f href=\"{}\"/>\n", escape(file)));
+    }
+    out.push_str("  <fields>\n");
+    for node in nodes {
+        xfdf_field(node, 2, &mut out);
+    }
+    out.push_str("  </fields>\n</xfdf>\n");
+    out
+}
+
+fn xfdf_field(node: &Node, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    out.push_str(&format!("{}<field name=\"{}\">\n", indent, escape(&node.name)));
+    let values = match node.value {
+        Some(DictNode::Array(ref values)) => values.clone(),
+        Some(ref value) => vec![value.clone()],
+        None => Vec::new(),
+    };
+    for value in values.iter().filter_map(text) {
+        out.push_str(&format!("{}  <value>{}</value>\n", indent, escape(&value)));
+    }
+    for kid in &node.kids {
+        xfdf_field(kid, depth + 1, out);
+    }
+    out.push_str(&format!("{}</field>\n", indent));
+}
+
+// The values of an XFDF file in document order, the first <value> of each
+// field.
+fn parse_xfdf(text: &str) -> Result<Vec<(String, String)>> {
+    let mut values: Vec<(String, String)> = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut value: Option<String> = None;
+    let mut rest = text;
+    let mut position = 0;
+    while let Some(start) = rest.find('<') {
+        if let Some(ref mut value) = value {
+            value.push_str(&unescape(&rest[..start]));
+        }
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => return Err(PdfError::Syntax { what: "XFDF tag", position: position + start }),
+        };
+        let tag = &rest[start + 1..end];
+        position += end + 1;
+        rest = &rest[end + 1..];
+        // declarations, comments and processing instructions
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let empty = tag.ends_with('/');
+        let element = tag.trim_matches('/').split_whitespace().next().unwrap_or("");
+        match (element, closing) {
+            ("field", false) => {
+                path.push(attribute(tag, "name").unwrap_or_default());
+                if empty {
+                    path.pop();
+                }
+            },
+            ("field", true) => {
+                path.pop();
+            },
+            ("value", false) if empty => record(&mut values, &path, String::new()),
+            ("value", false) => value = Some(String::new()),
+            ("value", true) => {
+                if let Some(value) = value.take() {
+                    record(&mut values, &path, value);
+                }
+            },
+            _ => (),
+        }
+    }
+    Ok(values)
+}
+
+fn record(values: &mut Vec<(String, String)>, path: &[String], value: String) {
+    let name = path.join(".");
+    if !name.is_empty() && !values.iter().any(|existing| existing.0 == name) {
+        values.push((name, value));
+    }
+}
+
+// The value of attribute `name` in a tag's text.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(index) = rest.find(name) {
+        let preceded = rest[..index].ends_with(char::is_whitespace);
+        rest = &rest[index + name.len()..];
+        let after = rest.trim_start();
+        if preceded && after.starts_with('=') {
+            let after = after[1..].trim_start();
+            let quote = after.chars().next().filter(|&quote| quote == '"' || quote == '\'')?;
+            let value = &after[1..];
+            return value.find(quote).map(|end| unescape(&value[..end]));
+        }
+    }
+    None
+}
+
+// A value as text: strings read as Latin-1, names without their slash.
+fn text(value: &DictNode) -> Option<String> {
+    match *value {
+        DictNode::Str(_) => value.as_name().map(|name| name.to_string()),
+        _ => fields::text(value),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Resolves XML's entities and character references, anything else that
+// looks like one is kept as it is.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('&') {
+        out.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let ch = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok()),
+            }.and_then(char::from_u32),
+        };
+        match ch {
+            Some(ch) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+fn sample() -> Vec<Node> {
+    tree(&[
+        ("address.city".to_string(), DictNode::LiteralString(b"Paris & Lyon".to_vec())),
+        ("agree".to_string(), DictNode::name("Yes")),
+        ("address.zip".to_string(), DictNode::LiteralString(b"75001".to_vec())),
+    ])
+}
+
+#[test]
+fn test_fdf() {
+    let nodes = sample();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(nodes[0].kids.len(), 2);
+    let text = String::from_utf8_lossy(&fdf(&nodes[1..], Some("form.pdf"))).into_owned();
+    assert!(text.starts_with("%FDF-1.2\n"));
+    assert!(text.contains("1 0 obj\n<< /FDF << /F (form.pdf) /Fields [<< /T (agree) /V /Yes >>] >> >>\nendobj\n"));
+    assert!(text.ends_with("trailer\n<< /Root 1 0 R >>\n%%EOF\n"));
+}
+
+#[test]
+fn test_xfdf() {
+    let text = xfdf(&sample(), Some("form.pdf"));
+    assert!(text.contains("  <f href=\"form.pdf\"/>\n"));
+    assert!(text.contains("    <field name=\"address\">\n      <field name=\"city\">\n        <value>Paris &amp; Lyon</value>\n"));
+
+    // and back
+    let values = parse_xfdf(&text).unwrap();
+    assert_eq!(values, vec![
+        ("address.city".to_string(), "Paris & Lyon".to_string()),
+        ("address.zip".to_string(), "75001".to_string()),
+        ("agree".to_string(), "Yes".to_string()),
+    ]);
+
+    let values = parse_xfdf("<xfdf><fields><field name='a'><value/></field><field name=\"b\"><value>&#233;t&#xE9;</value>\
+                             <value>second</value></field><field name=\"c\"/></fields></xfdf>").unwrap();
+    assert_eq!(values, vec![("a".to_string(), String::new()), ("b".to_string(), "été".to_string())]);
+    assert!(parse_xfdf("<xfdf><fields").is_err());
+}
@@ -0,0 +1,12 @@
+/*
+ * Interactive forms (AcroForm): the fields of a document, what's needed
+ * to fill them in, filling them in, form data files, and adding new ones.
+ */
+
+pub mod appearance;
+pub mod builder;
+pub mod calculation;
+pub mod choice;
+pub mod fields;
+pub mod fill;
+pub mod interchange;
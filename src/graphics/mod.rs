@@ -0,0 +1,382 @@
+use std::ops::Mul;
+
+use document::Document;
+use error::Result;
+use parsers::content::ops::{self, Op};
+use parsers::cos::DictNode;
+
+pub mod paths;
+
+/*
+ * Geometry shared by everything that follows content streams around.
+ *
+ * PDF matrices are the six numbers [a b c d e f] of
+ *
+ *   | a b 0 |
+ *   | c d 0 |
+ *   | e f 1 |
+ *
+ * applied to row vectors, so a point (x, y) ends up at (a x + c y + e,
+ * b x + d y + f). `m1 * m2` is m1 followed by m2, which is the order the
+ * spec writes them in: "cm" makes the current transformation matrix
+ * `new * ctm`, and glyphs are placed with `text_matrix * ctm`.
+ *
+ * Colors are kept in the space they were set in. Spaces other than the
+ * device ones are read as whatever device space they stand in for (an
+ * ICCBased space with three components is RGB, a Separation is its tint),
+ * there's no color management here. `ColorState` follows the color
+ * operators, for interpreters that want to know what something is painted
+ * in.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Matrix {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+pub const IDENTITY: Matrix = Matrix { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+impl Matrix {
+    pub fn new(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Matrix {
+        Matrix { a, b, c, d, e, f }
+    }
+
+    pub fn translate(x: f64, y: f64) -> Matrix {
+        Matrix::new(1.0, 0.0, 0.0, 1.0, x, y)
+    }
+
+    // Six numbers, the operands of "cm" or a /Matrix entry.
+    pub fn from_numbers(numbers: &[f64]) -> Option<Matrix> {
+        match *numbers {
+            [a, b, c, d, e, f] => Some(Matrix::new(a, b, c, d, e, f)),
+            _ => None,
+        }
+    }
+
+    // An array of six numbers, e.g. a form XObject's /Matrix.
+    pub fn from_node(node: &DictNode) -> Option<Matrix> {
+        let numbers = node.as_array()?.iter().map(DictNode::as_float).collect::<Option<Vec<f64>>>()?;
+        Matrix::from_numbers(&numbers)
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f)
+    }
+
+    // How much the matrix stretches lengths along the y axis, which is what
+    // a font size turns into.
+    pub fn vertical_scale(&self) -> f64 {
+        self.c.hypot(self.d)
+    }
+
+    pub fn horizontal_scale(&self) -> f64 {
+        self.a.hypot(self.b)
+    }
+
+    // How much the matrix stretches lengths on average, what a line width
+    // turns into.
+    pub fn mean_scale(&self) -> f64 {
+        (self.a * self.d - self.b * self.c).abs().sqrt()
+    }
+
+    // The matrix undoing this one, None if it flattens everything onto a
+    // line or a point.
+    pub fn inverse(&self) -> Option<Matrix> {
+        let determinant = self.a * self.d - self.b * self.c;
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        let (a, b, c, d) = (self.d / determinant, -self.b / determinant, -self.c / determinant, self.a / determinant);
+        Some(Matrix::new(a, b, c, d, -(self.e * a + self.f * c), -(self.e * b + self.f * d)))
+    }
+
+    // The box the unit square ends up in, where an image is drawn.
+    pub fn unit_bounds(&self) -> [f64; 4] {
+        let corners = [self.apply(0.0, 0.0), self.apply(1.0, 0.0), self.apply(0.0, 1.0), self.apply(1.0, 1.0)];
+        let mut rect = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY];
+        for &(x, y) in &corners {
+            rect = [rect[0].min(x), rect[1].min(y), rect[2].max(x), rect[3].max(y)];
+        }
+        rect
+    }
+}
+
+// Rectangles are [llx lly urx ury], like PDF boxes once normalized.
+
+pub fn area(rect: [f64; 4]) -> f64 {
+    (rect[2] - rect[0]).max(0.0) * (rect[3] - rect[1]).max(0.0)
+}
+
+pub fn intersection(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [a[0].max(b[0]), a[1].max(b[1]), a[2].min(b[2]), a[3].min(b[3])]
+}
+
+pub fn union(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Color {
+    Gray(f64),
+    Rgb(f64, f64, f64),
+    Cmyk(f64, f64, f64, f64),
+    // a Separation or DeviceN tint, or components of a space we can't tell
+    // anything about
+    Other(Vec<f64>),
+    // a pattern, by its name in the resources
+    Pattern(String),
+}
+
+impl Color {
+    // The color as RGB, through the device formulas for gray and CMYK (PDF
+    // 32000 10.3). None for patterns and spaces we can't tell anything
+    // about.
+    pub fn to_rgb(&self) -> Option<(f64, f64, f64)> {
+        let (r, g, b) = match *self {
+            Color::Gray(gray) => (gray, gray, gray),
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Cmyk(c, m, y, k) => (1.0 - (c + k).min(1.0), 1.0 - (m + k).min(1.0), 1.0 - (y + k).min(1.0)),
+            Color::Other(_) | Color::Pattern(_) => return None,
+        };
+        Some((r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0)))
+    }
+}
+
+// What a color space comes down to, see the top of the file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorFamily {
+    Gray,
+    Rgb,
+    Cmyk,
+    Pattern,
+    Other,
+}
+
+impl ColorFamily {
+    // The family of a color space operand of cs/CS, looked up in the
+    // resources' /ColorSpace if it isn't one of the device spaces.
+    pub fn of(doc: &mut Document, space: &DictNode, resources: &DictNode) -> Result<ColorFamily> {
+        match space.as_name() {
+            Some(name) => ColorFamily::named(doc, name, resources),
+            None => Ok(ColorFamily::from_node(&doc.resolve_all(space)?)),
+        }
+    }
+
+    // The same for a space by its name, without the slash.
+    pub fn named(doc: &mut Document, name: &str, resources: &DictNode) -> Result<ColorFamily> {
+        if let Some(family) = ColorFamily::from_name(name) {
+            return Ok(family);
+        }
+        match resources.get_resolved(doc, "ColorSpace")? {
+            Some(spaces) => match spaces.get(name) {
+                Some(space) => {
+                    let space = doc.resolve_all(space)?;
+                    Ok(ColorFamily::from_node(&space))
+                },
+                None => Ok(ColorFamily::Other),
+            },
+            None => Ok(ColorFamily::Other),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorFamily> {
+        match name {
+            "DeviceGray" | "G" | "CalGray" => Some(ColorFamily::Gray),
+            "DeviceRGB" | "RGB" | "CalRGB" => Some(ColorFamily::Rgb),
+            "DeviceCMYK" | "CMYK" => Some(ColorFamily::Cmyk),
+            "Pattern" => Some(ColorFamily::Pattern),
+            _ => None,
+        }
+    }
+
+    // A color space resolved all the way down: a name, or an array such as
+    // [/ICCBased <<...>>].
+    pub fn from_node(space: &DictNode) -> ColorFamily {
+        if let Some(name) = space.as_name() {
+            return ColorFamily::from_name(name).unwrap_or(ColorFamily::Other);
+        }
+        let items = space.as_array().unwrap_or(&[]);
+        match items.first().and_then(DictNode::as_name) {
+            Some("ICCBased") => {
+                let stream = items.get(1);
+                match stream.and_then(|stream| stream.get("N")).and_then(DictNode::as_int) {
+                    Some(1) => ColorFamily::Gray,
+                    Some(3) => ColorFamily::Rgb,
+                    Some(4) => ColorFamily::Cmyk,
+                    _ => stream.and_then(|stream| stream.get("Alternate")).map_or(ColorFamily::Other, ColorFamily::from_node),
+                }
+            },
+            Some("Pattern") => ColorFamily::Pattern,
+            Some(name) => ColorFamily::from_name(name).unwrap_or(ColorFamily::Other),
+            None => ColorFamily::Other,
+        }
+    }
+
+    // The color a space starts out as when it's set.
+    pub fn initial(self) -> Color {
+        match self {
+            ColorFamily::Gray => Color::Gray(0.0),
+            ColorFamily::Rgb => Color::Rgb(0.0, 0.0, 0.0),
+            ColorFamily::Cmyk => Color::Cmyk(0.0, 0.0, 0.0, 1.0),
+            ColorFamily::Pattern => Color::Pattern(String::new()),
+            // full tint, what Separation and DeviceN start at
+            ColorFamily::Other => Color::Other(vec![1.0]),
+        }
+    }
+
+    // The color sc/scn set with these operands.
+    pub fn color(self, operands: &[DictNode]) -> Color {
+        if let Some(name) = operands.last().and_then(DictNode::as_name) {
+            return Color::Pattern(name.to_string());
+        }
+        let components: Vec<f64> = operands.iter().filter_map(DictNode::as_float).collect();
+        self.components(components)
+    }
+
+    // The same from a typed sc/scn operation.
+    pub fn typed_color(self, color: &ops::Color) -> Color {
+        match color.pattern {
+            Some(ref name) => Color::Pattern(name.clone()),
+            None => self.components(color.components.clone()),
+        }
+    }
+
+    fn components(self, components: Vec<f64>) -> Color {
+        match (self, components.as_slice()) {
+            (ColorFamily::Gray, &[gray]) => Color::Gray(gray),
+            (ColorFamily::Rgb, &[r, g, b]) => Color::Rgb(r, g, b),
+            (ColorFamily::Cmyk, &[c, m, y, k]) => Color::Cmyk(c, m, y, k),
+            _ => Color::Other(components),
+        }
+    }
+}
+
+// The fill and stroke colors of the graphics state, and the spaces they
+// were set in, which sc/scn and SC/SCN operands are read in. Both start
+// out as DeviceGray black; q and Q save and restore them with the rest of
+// the state.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ColorState {
+    pub fill: Color,
+    pub stroke: Color,
+    pub fill_family: ColorFamily,
+    pub stroke_family: ColorFamily,
+}
+
+impl Default for ColorState {
+    fn default() -> ColorState {
+        ColorState {
+            fill: Color::Gray(0.0),
+            stroke: Color::Gray(0.0),
+            fill_family: ColorFamily::Gray,
+            stroke_family: ColorFamily::Gray,
+        }
+    }
+}
+
+impl ColorState {
+    // Follows the color operators, ignoring the rest. A space cs/CS names
+    // is only known here if it's a device space, callers with the document
+    // at hand look the others up and call `set_space` instead.
+    pub fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::SetFillGray(gray) => self.set(true, ColorFamily::Gray, Color::Gray(gray)),
+            Op::SetStrokeGray(gray) => self.set(false, ColorFamily::Gray, Color::Gray(gray)),
+            Op::SetFillRgb(r, g, b) => self.set(true, ColorFamily::Rgb, Color::Rgb(r, g, b)),
+            Op::SetStrokeRgb(r, g, b) => self.set(false, ColorFamily::Rgb, Color::Rgb(r, g, b)),
+            Op::SetFillCmyk([c, m, y, k]) => self.set(true, ColorFamily::Cmyk, Color::Cmyk(c, m, y, k)),
+            Op::SetStrokeCmyk([c, m, y, k]) => self.set(false, ColorFamily::Cmyk, Color::Cmyk(c, m, y, k)),
+            Op::SetFillColorSpace(ref name) => self.set_space(true, ColorFamily::from_name(name).unwrap_or(ColorFamily::Other)),
+            Op::SetStrokeColorSpace(ref name) => self.set_space(false, ColorFamily::from_name(name).unwrap_or(ColorFamily::Other)),
+            Op::SetFillColor(ref color) => self.fill = self.fill_family.typed_color(color),
+            Op::SetStrokeColor(ref color) => self.stroke = self.stroke_family.typed_color(color),
+            _ => (),
+        }
+    }
+
+    // cs and CS, which also reset the color to the space's initial one.
+    pub fn set_space(&mut self, fill: bool, family: ColorFamily) {
+        self.set(fill, family, family.initial());
+    }
+
+    fn set(&mut self, fill: bool, family: ColorFamily, color: Color) {
+        if fill {
+            self.fill_family = family;
+            self.fill = color;
+        } else {
+            self.stroke_family = family;
+            self.stroke = color;
+        }
+    }
+}
+
+impl Mul for Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: Matrix) -> Matrix {
+        Matrix {
+            a: self.a * other.a + self.b * other.c,
+            b: self.a * other.b + self.b * other.d,
+            c: self.c * other.a + self.d * other.c,
+            d: self.c * other.b + self.d * other.d,
+            e: self.e * other.a + self.f * other.c + other.e,
+            f: self.e * other.b + self.f * other.d + other.f,
+        }
+    }
+}
+
+#[test]
+fn test_matrix() {
+    let scale = Matrix::new(2.0, 0.0, 0.0, 2.0, 0.0, 0.0);
+    let moved = Matrix::translate(10.0, 20.0);
+    // scaled first, then moved
+    assert_eq!((scale * moved).apply(1.0, 1.0), (12.0, 22.0));
+    assert_eq!((moved * scale).apply(1.0, 1.0), (22.0, 42.0));
+    let quarter_turn = Matrix::new(0.0, 1.0, -1.0, 0.0, 0.0, 0.0);
+    assert_eq!(quarter_turn.apply(1.0, 0.0), (0.0, 1.0));
+    assert_eq!((scale * quarter_turn).vertical_scale(), 2.0);
+    assert_eq!(IDENTITY * moved, moved);
+    assert_eq!(Matrix::from_numbers(&[1.0, 2.0]), None);
+    assert_eq!((scale * quarter_turn).mean_scale(), 2.0);
+    assert_eq!((scale * quarter_turn * moved).inverse().map(|inverse| inverse.apply(12.0, 22.0)), Some((1.0, -1.0)));
+    assert_eq!(Matrix::new(1.0, 2.0, 2.0, 4.0, 0.0, 0.0).inverse(), None);
+    assert_eq!(Matrix::new(-50.0, 0.0, 0.0, 100.0, 50.0, 150.0).unit_bounds(), [0.0, 150.0, 50.0, 250.0]);
+    assert_eq!(area(intersection([0.0, 0.0, 10.0, 10.0], [5.0, 5.0, 20.0, 20.0])), 25.0);
+    assert_eq!(union([0.0, 0.0, 10.0, 10.0], [5.0, 5.0, 20.0, 20.0]), [0.0, 0.0, 20.0, 20.0]);
+}
+
+#[test]
+fn test_color_family() {
+    let icc = DictNode::Array(vec![
+        DictNode::name("ICCBased"),
+        DictNode::Dict(hashmap!{ "N".to_string() => DictNode::Int(3) }),
+    ]);
+    assert_eq!(ColorFamily::from_node(&icc), ColorFamily::Rgb);
+    let separation = DictNode::Array(vec![DictNode::name("Separation"), DictNode::name("Spot")]);
+    assert_eq!(ColorFamily::from_node(&separation), ColorFamily::Other);
+    assert_eq!(ColorFamily::Rgb.color(&[DictNode::Int(1), DictNode::Float(0.5), DictNode::Int(0)]), Color::Rgb(1.0, 0.5, 0.0));
+    assert_eq!(ColorFamily::Pattern.color(&[DictNode::name("P1")]), Color::Pattern("P1".to_string()));
+    assert_eq!(ColorFamily::Other.color(&[DictNode::Float(0.25)]), Color::Other(vec![0.25]));
+}
+
+#[test]
+fn test_color_state() {
+    use parsers::content::Operations;
+
+    let mut colors = ColorState::default();
+    for op in Operations::new(&b"1 0 0 rg 0 0 1 0 K /DeviceRGB cs 0 1 0 sc /Pattern CS /P1 SCN"[..]).ops() {
+        colors.apply(&op.unwrap());
+    }
+    assert_eq!(colors.fill, Color::Rgb(0.0, 1.0, 0.0));
+    assert_eq!(colors.stroke, Color::Pattern("P1".to_string()));
+    assert_eq!(colors.stroke_family, ColorFamily::Pattern);
+
+    assert_eq!(Color::Cmyk(0.0, 1.0, 1.0, 0.0).to_rgb(), Some((1.0, 0.0, 0.0)));
+    assert_eq!(Color::Gray(1.0).to_rgb(), Some((1.0, 1.0, 1.0)));
+    assert_eq!(Color::Other(vec![0.5]).to_rgb(), None);
+}
@@ -0,0 +1,321 @@
+use document::Document;
+use error::Result;
+use graphics::{Color, ColorFamily, ColorState, Matrix, IDENTITY};
+use pages::{self, Page};
+use parsers::content::ops::Op;
+use parsers::content::Operations;
+use parsers::cos::DictNode;
+
+/*
+ * The vector graphics of a page, as the paths it paints, for CAD and figure
+ * analysis that want the geometry rather than pixels:
+ *
+ *   for path in page.paths(&mut doc)? {
+ *       if path.stroke.is_some() { ... path.segments ... }
+ *   }
+ *
+ * Points are in default user space (before the page's /Rotate), with the
+ * transformations in effect when each point was added already applied, and
+ * "re" comes out as the four lines it stands for. Line widths are scaled
+ * the same way, by the average stretch of the matrix; a width of 0 is the
+ * thinnest line the device can draw and stays 0.
+ *
+ * Paths ended with "n" paint nothing and are left out, clipping isn't
+ * applied, and shadings (sh) and inline images aren't paths. Form XObjects
+ * are followed, as text extraction does.
+ */
+
+// Form XObjects can nest, and broken files make them nest forever.
+const MAX_FORM_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Segment {
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    // two control points, then the end point
+    CurveTo(f64, f64, f64, f64, f64, f64),
+    Close,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Path {
+    pub segments: Vec<Segment>,
+    // None when the path isn't filled, or isn't stroked
+    pub fill: Option<Color>,
+    pub stroke: Option<Color>,
+    pub line_width: f64,
+    // filled with the even-odd rule rather than nonzero winding
+    pub even_odd: bool,
+}
+
+impl Path {
+    // The box around every point of the path, control points included, so
+    // a little loose around curves. None for a path that's only "h"s.
+    pub fn bounds(&self) -> Option<[f64; 4]> {
+        let mut points = Vec::new();
+        for segment in &self.segments {
+            match *segment {
+                Segment::MoveTo(x, y) | Segment::LineTo(x, y) => points.push((x, y)),
+                Segment::CurveTo(x1, y1, x2, y2, x3, y3) => points.extend_from_slice(&[(x1, y1), (x2, y2), (x3, y3)]),
+                Segment::Close => (),
+            }
+        }
+        let &(x, y) = points.first()?;
+        Some(points.iter().fold([x, y, x, y], |rect, &(x, y)| [rect[0].min(x), rect[1].min(y), rect[2].max(x), rect[3].max(y)]))
+    }
+}
+
+pub fn paths(doc: &mut Document, page: &Page) -> Result<Vec<Path>> {
+    let content = pages::content(doc, page)?;
+    let mut tracer = Tracer::new(IDENTITY);
+    let mut runner = Runner { doc, forms: Vec::new() };
+    runner.run(&mut tracer, &content, &page.resources)?;
+    Ok(tracer.paths)
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    ctm: Matrix,
+    line_width: f64,
+    colors: ColorState,
+}
+
+// Follows the operators that build and paint paths, without anything that
+// needs the document.
+struct Tracer {
+    state: State,
+    stack: Vec<State>,
+    // the path being built
+    segments: Vec<Segment>,
+    // where the current subpath started and the current point, in user
+    // space, for "h" and "v"
+    start: (f64, f64),
+    current: (f64, f64),
+    paths: Vec<Path>,
+}
+
+impl Tracer {
+    fn new(ctm: Matrix) -> Tracer {
+        Tracer {
+            state: State {
+                ctm,
+                line_width: 1.0,
+                colors: ColorState::default(),
+            },
+            stack: Vec::new(),
+            segments: Vec::new(),
+            start: (0.0, 0.0),
+            current: (0.0, 0.0),
+            paths: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Save => self.stack.push(self.state.clone()),
+            Op::Restore => {
+                // unbalanced Qs are common enough, they're ignored
+                if let Some(state) = self.stack.pop() {
+                    self.state = state;
+                }
+            },
+            Op::Transform(ref matrix) => {
+                if let Some(matrix) = Matrix::from_numbers(matrix) {
+                    self.state.ctm = matrix * self.state.ctm;
+                }
+            },
+            Op::SetLineWidth(width) => self.state.line_width = width,
+
+            Op::MoveTo(x, y) => self.move_to(x, y),
+            Op::LineTo(x, y) => self.line_to(x, y),
+            Op::CurveTo([x1, y1, x2, y2, x3, y3]) => self.curve_to((x1, y1), (x2, y2), (x3, y3)),
+            // the current point is the first control point
+            Op::CurveToInitial([x2, y2, x3, y3]) => {
+                let current = self.current;
+                self.curve_to(current, (x2, y2), (x3, y3));
+            },
+            // the end point is the second one
+            Op::CurveToFinal([x1, y1, x3, y3]) => self.curve_to((x1, y1), (x3, y3), (x3, y3)),
+            Op::ClosePath => self.close(),
+            Op::Rectangle([x, y, width, height]) => {
+                self.move_to(x, y);
+                self.line_to(x + width, y);
+                self.line_to(x + width, y + height);
+                self.line_to(x, y + height);
+                self.close();
+            },
+
+            Op::Stroke => self.paint(false, true, false, false),
+            Op::CloseStroke => self.paint(true, true, false, false),
+            Op::Fill { even_odd } => self.paint(false, false, even_odd, true),
+            Op::FillStroke { even_odd } => self.paint(false, true, even_odd, true),
+            Op::CloseFillStroke { even_odd } => self.paint(true, true, even_odd, true),
+            Op::EndPath => self.segments.clear(),
+            // spaces from the resources are looked up by the runner first
+            _ => self.state.colors.apply(op),
+        }
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.start = (x, y);
+        self.current = (x, y);
+        let (x, y) = self.state.ctm.apply(x, y);
+        self.segments.push(Segment::MoveTo(x, y));
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        self.current = (x, y);
+        let (x, y) = self.state.ctm.apply(x, y);
+        self.segments.push(Segment::LineTo(x, y));
+    }
+
+    fn curve_to(&mut self, (x1, y1): (f64, f64), (x2, y2): (f64, f64), (x3, y3): (f64, f64)) {
+        self.current = (x3, y3);
+        let ctm = self.state.ctm;
+        let ((x1, y1), (x2, y2), (x3, y3)) = (ctm.apply(x1, y1), ctm.apply(x2, y2), ctm.apply(x3, y3));
+        self.segments.push(Segment::CurveTo(x1, y1, x2, y2, x3, y3));
+    }
+
+    fn close(&mut self) {
+        if !self.segments.is_empty() {
+            self.segments.push(Segment::Close);
+        }
+        self.current = self.start;
+    }
+
+    // The four painting operators are b(*) = h B(*) and s = h S.
+    fn paint(&mut self, close: bool, stroke: bool, even_odd: bool, fill: bool) {
+        if close {
+            self.close();
+        }
+        let segments = std::mem::take(&mut self.segments);
+        if segments.is_empty() {
+            return;
+        }
+        self.paths.push(Path {
+            segments,
+            fill: if fill { Some(self.state.colors.fill.clone()) } else { None },
+            stroke: if stroke { Some(self.state.colors.stroke.clone()) } else { None },
+            line_width: self.state.line_width * self.state.ctm.mean_scale(),
+            even_odd: fill && even_odd,
+        });
+    }
+}
+
+// Runs content streams through a `Tracer`, looking up color spaces and form
+// XObjects in the document.
+struct Runner<'a> {
+    doc: &'a mut Document,
+    // the form XObjects being run, innermost last
+    forms: Vec<(i64, i64)>,
+}
+
+impl<'a> Runner<'a> {
+    fn run(&mut self, tracer: &mut Tracer, content: &[u8], resources: &DictNode) -> Result<()> {
+        for operation in Operations::new(content) {
+            // keep what was painted up to a syntax error
+            let operation = match operation {
+                Ok(operation) => operation,
+                Err(_) => break,
+            };
+            let op = Op::parse(operation);
+            match op {
+                Op::SetFillColorSpace(ref name) => tracer.state.colors.set_space(true, ColorFamily::named(self.doc, name, resources)?),
+                Op::SetStrokeColorSpace(ref name) => tracer.state.colors.set_space(false, ColorFamily::named(self.doc, name, resources)?),
+                Op::PaintXObject(ref name) => self.form(tracer, resources, name)?,
+                _ => tracer.apply(&op),
+            }
+        }
+        Ok(())
+    }
+
+    fn form(&mut self, tracer: &mut Tracer, resources: &DictNode, name: &str) -> Result<()> {
+        let node = match resources.get_resolved(self.doc, "XObject")? {
+            Some(xobjects) => match xobjects.get(name).and_then(DictNode::as_reference) {
+                Some(reference) => reference,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        if self.forms.contains(&node) || self.forms.len() >= MAX_FORM_DEPTH {
+            return Ok(());
+        }
+        let reference = DictNode::ObjectReference(node.0, node.1);
+        let dict = self.doc.resolve(&reference)?;
+        if !dict.get("Subtype").is_some_and(|subtype| subtype.is_name("Form")) {
+            return Ok(());
+        }
+        let content = match self.doc.referenced_stream(&reference)? {
+            Some((_, content)) => content,
+            None => return Ok(()),
+        };
+        let matrix = dict.get_resolved(self.doc, "Matrix")?.and_then(|matrix| Matrix::from_node(&matrix)).unwrap_or(IDENTITY);
+        // forms without resources of their own use the page's
+        let form_resources = match dict.get_resolved(self.doc, "Resources")? {
+            Some(form_resources @ DictNode::Dict(_)) => form_resources,
+            _ => resources.clone(),
+        };
+
+        let depth = tracer.stack.len();
+        tracer.stack.push(tracer.state.clone());
+        // a form starts a path of its own
+        let segments = std::mem::take(&mut tracer.segments);
+        tracer.state.ctm = matrix * tracer.state.ctm;
+        self.forms.push(node);
+        let result = self.run(tracer, &content, &form_resources);
+        self.forms.pop();
+        // whatever the form left on the stack goes with it
+        tracer.stack.truncate(depth + 1);
+        if let Some(state) = tracer.stack.pop() {
+            tracer.state = state;
+        }
+        tracer.segments = segments;
+        result
+    }
+}
+
+#[cfg(test)]
+fn trace(content: &[u8]) -> Vec<Path> {
+    let mut tracer = Tracer::new(IDENTITY);
+    for op in Operations::new(content).ops() {
+        tracer.apply(&op.unwrap());
+    }
+    tracer.paths
+}
+
+#[test]
+fn test_paths() {
+    let paths = trace(b"q 2 0 0 2 10 10 cm 1 0 0 rg 0.5 w 0 0 5 5 re f* 0 0 m 1 1 2 2 v S Q\n\
+                        /DeviceCMYK CS 0 0 1 0 SC 0 0 m 10 0 l s\n\
+                        0 0 m 5 5 l n 0 0 m 1 1 l B");
+    assert_eq!(paths.len(), 4);
+
+    assert_eq!(paths[0].segments, vec![
+        Segment::MoveTo(10.0, 10.0),
+        Segment::LineTo(20.0, 10.0),
+        Segment::LineTo(20.0, 20.0),
+        Segment::LineTo(10.0, 20.0),
+        Segment::Close,
+    ]);
+    assert_eq!(paths[0].fill, Some(Color::Rgb(1.0, 0.0, 0.0)));
+    assert_eq!(paths[0].stroke, None);
+    assert!(paths[0].even_odd);
+
+    // v takes the current point as its first control point
+    assert_eq!(paths[1].segments[1], Segment::CurveTo(10.0, 10.0, 12.0, 12.0, 14.0, 14.0));
+    assert_eq!(paths[1].stroke, Some(Color::Gray(0.0)));
+    assert_eq!(paths[1].line_width, 1.0);
+
+    // back outside q/Q, the CMYK stroke
+    assert_eq!(paths[2].segments.last(), Some(&Segment::Close));
+    assert_eq!(paths[2].stroke, Some(Color::Cmyk(0.0, 0.0, 1.0, 0.0)));
+    assert_eq!(paths[2].fill, None);
+    assert_eq!(paths[2].line_width, 1.0);
+
+    // n threw away its path
+    assert_eq!(paths[3].segments, vec![Segment::MoveTo(0.0, 0.0), Segment::LineTo(1.0, 1.0)]);
+    assert_eq!(paths[3].fill, Some(Color::Gray(0.0)));
+    assert!(paths[3].stroke.is_some());
+    assert!(!paths[3].even_odd);
+    assert_eq!(paths[1].bounds(), Some([10.0, 10.0, 14.0, 14.0]));
+}
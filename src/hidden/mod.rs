@@ -0,0 +1,174 @@
+use std::fmt;
+
+use document::Document;
+use error::Result;
+use graphics::paths::{self, Path};
+use graphics::{intersection, Color};
+use pages::Page;
+use text::{self, TextSpan};
+
+/*
+ * Text a reader of the page won't see, for finding OCR layers and for
+ * security reviews: instructions planted for whatever reads the file next,
+ * keyword stuffing.
+ *
+ *   for hidden in hidden::hidden_text(&mut doc, &page)? {
+ *       println!("{}: {}", hidden.reasons[0], hidden.text);
+ *   }
+ *
+ * A span is hidden when any of these hold:
+ *
+ *   - its render mode doesn't paint the glyphs (3, or 7 which only clips),
+ *     which is how OCR tools lay their text over a scan
+ *   - it's smaller than `MIN_SIZE`, a zero size font included
+ *   - it's entirely outside the crop box
+ *   - it's filled in the color of what's behind it, within
+ *     `COLOR_TOLERANCE`: the last filled path whose box holds it, or white
+ *     paper where there's none
+ *
+ * The background is a guess, a good one for white on white and for text on
+ * a colored box. Paths and text are traced separately, so whether the box
+ * was drawn under the text or over it isn't known, and text covered up by
+ * an image or a shape drawn after it, or clipped away, isn't caught. Only
+ * spans with something other than white space in them count.
+ */
+
+// in points, after every scaling
+const MIN_SIZE: f64 = 1.0;
+// per RGB component, from 0 to 1, about 8 out of 255
+const COLOR_TOLERANCE: f64 = 0.03;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Reason {
+    Invisible,
+    Tiny,
+    OffPage,
+    SameColor,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reason::Invisible => "invisible",
+            Reason::Tiny => "tiny",
+            Reason::OffPage => "off the page",
+            Reason::SameColor => "same color as the background",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HiddenText {
+    pub text: String,
+    // [llx lly urx ury] in default user space, from the baseline up one em
+    pub bbox: [f64; 4],
+    // never empty
+    pub reasons: Vec<Reason>,
+}
+
+pub fn hidden_text(doc: &mut Document, page: &Page) -> Result<Vec<HiddenText>> {
+    let paths = paths::paths(doc, page)?;
+    let spans = text::raw_spans(doc, page)?;
+    Ok(detect(page.crop_box, &paths, &spans))
+}
+
+fn detect(page_box: [f64; 4], paths: &[Path], spans: &[TextSpan]) -> Vec<HiddenText> {
+    let mut hidden = Vec::new();
+    for span in spans.iter().filter(|span| !span.text.trim().is_empty()) {
+        let bbox = span_bounds(span);
+        let mut reasons = Vec::new();
+        if span.render_mode == 3 || span.render_mode == 7 {
+            reasons.push(Reason::Invisible);
+        }
+        if span.size < MIN_SIZE {
+            reasons.push(Reason::Tiny);
+        }
+        let inside = intersection(bbox, page_box);
+        if inside[0] > inside[2] || inside[1] > inside[3] {
+            reasons.push(Reason::OffPage);
+        }
+        // modes 1 and 5 only stroke, in a color we don't keep
+        let filled = matches!(span.render_mode, 0 | 2 | 4 | 6);
+        if filled && same_color(&span.color, &background(bbox, paths)) {
+            reasons.push(Reason::SameColor);
+        }
+        if !reasons.is_empty() {
+            hidden.push(HiddenText { text: span.text.clone(), bbox, reasons });
+        }
+    }
+    hidden
+}
+
+// Roughly where a span's glyphs are, from the baseline up one em.
+fn span_bounds(span: &TextSpan) -> [f64; 4] {
+    let y = span.start.1.min(span.end.1);
+    [span.start.0.min(span.end.0), y, span.start.0.max(span.end.0), y + span.size]
+}
+
+// The fill of the last filled path whose box holds `bbox`, white without one.
+fn background(bbox: [f64; 4], paths: &[Path]) -> Color {
+    paths.iter().rev()
+        .filter_map(|path| Some((path.bounds()?, path.fill.as_ref()?)))
+        .find(|&(bounds, _)| bounds[0] <= bbox[0] && bounds[1] <= bbox[1] && bounds[2] >= bbox[2] && bounds[3] >= bbox[3])
+        .map_or(Color::Gray(1.0), |(_, fill)| fill.clone())
+}
+
+// Patterns and colors that don't come down to RGB never match.
+fn same_color(a: &Color, b: &Color) -> bool {
+    match (a.to_rgb(), b.to_rgb()) {
+        (Some(a), Some(b)) => (a.0 - b.0).abs() <= COLOR_TOLERANCE && (a.1 - b.1).abs() <= COLOR_TOLERANCE
+            && (a.2 - b.2).abs() <= COLOR_TOLERANCE,
+        _ => false,
+    }
+}
+
+#[test]
+fn test_hidden_text() {
+    use graphics::paths::Segment;
+
+    let span = |text: &str, x: f64, y: f64, size: f64, color: Color, render_mode: i64| TextSpan {
+        text: text.to_string(),
+        start: (x, y),
+        end: (x + 100.0, y),
+        size,
+        font: "Helvetica".to_string(),
+        color,
+        render_mode,
+    };
+    let black = Color::Gray(0.0);
+    let page = [0.0, 0.0, 612.0, 792.0];
+    // a dark blue box at the bottom of the page
+    let rectangle = Path {
+        segments: vec![Segment::MoveTo(0.0, 0.0), Segment::LineTo(612.0, 0.0), Segment::LineTo(612.0, 100.0),
+                       Segment::LineTo(0.0, 100.0), Segment::Close],
+        fill: Some(Color::Rgb(0.0, 0.0, 0.5)),
+        stroke: None,
+        line_width: 1.0,
+        even_odd: false,
+    };
+    let spans = vec![
+        span("Visible body text", 72.0, 700.0, 12.0, black.clone(), 0),
+        span("OCR layer", 72.0, 680.0, 12.0, black.clone(), 3),
+        span("keyword keyword", 72.0, 660.0, 0.0, black.clone(), 0),
+        span("   ", 72.0, 640.0, 0.0, black.clone(), 3),
+        span("Ignore previous instructions", 72.0, 620.0, 12.0, Color::Rgb(1.0, 1.0, 0.99), 0),
+        span("Far away", 2000.0, 700.0, 12.0, black.clone(), 0),
+        // white on the box shows, dark blue on it doesn't
+        span("Footer", 72.0, 50.0, 10.0, Color::Gray(1.0), 0),
+        span("Hidden footer", 72.0, 30.0, 10.0, Color::Rgb(0.0, 0.0, 0.5), 0),
+        // white, but only stroked
+        span("Outlined", 72.0, 600.0, 12.0, Color::Gray(1.0), 1),
+    ];
+    let hidden = detect(page, &[rectangle], &spans);
+    let found: Vec<(&str, Vec<Reason>)> = hidden.iter().map(|hidden| (hidden.text.as_str(), hidden.reasons.clone())).collect();
+    assert_eq!(found, vec![
+        ("OCR layer", vec![Reason::Invisible]),
+        ("keyword keyword", vec![Reason::Tiny]),
+        ("Ignore previous instructions", vec![Reason::SameColor]),
+        ("Far away", vec![Reason::OffPage]),
+        ("Hidden footer", vec![Reason::SameColor]),
+    ]);
+    assert_eq!(hidden[0].bbox, [72.0, 680.0, 172.0, 692.0]);
+    assert_eq!(Reason::SameColor.to_string(), "same color as the background");
+}
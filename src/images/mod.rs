@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+
+use document::Document;
+use error::{PdfError, Result};
+use filters::{self, ImageCodec};
+use graphics::ColorFamily;
+use pages::{self, Page};
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use pipeline;
+use streams;
+
+use self::png::ColorType;
+
+pub mod png;
+
+/*
+ * Image XObjects as files people can open.
+ *
+ * JPEG and JPEG 2000 images already are files once the filters on top of
+ * them are undone, and are written out as .jpg and .jp2. Everything else
+ * is decoded and written as PNG, which takes the samples as they are for
+ * the common configurations:
+ *
+ *   - DeviceGray (or a one component ICCBased/CalGray space) at 1, 2, 4,
+ *     8 or 16 bits per component, and image masks
+ *   - DeviceRGB (or three components) at 8 or 16 bits
+ *   - Indexed over either of those, at up to 8 bits
+ *
+ * A /Decode array that inverts every component is applied, other ones
+ * aren't supported. CMYK, Lab, Separation and DeviceN images, and ones
+ * needing a filter we don't have (CCITTFax, JBIG2), are
+ * `PdfError::UnsupportedImage` or `UnsupportedFilter`.
+ *
+ * An image with a soft mask (/SMask) comes out as an 8 bit RGBA PNG with
+ * the mask as its alpha channel, stretched to the image's size if it's
+ * smaller or bigger. Masks that can't be read leave the image opaque
+ * rather than lose it, and JPEGs stay JPEGs, without their mask. Colors
+ * premultiplied against a /Matte aren't unmultiplied, and /Mask (stencil
+ * and color key masking) isn't applied.
+ *
+ * Inline images (BI ... ID ... EI in a page's content) go the same way,
+ * once the content parser has expanded their abbreviations. A color space
+ * they name is looked up in the page's resources. Those in form XObjects
+ * aren't looked for.
+ *
+ * `extract_all` does what `extract` does for many images at once, on a
+ * pool of threads (see the pipeline module): everything that needs the
+ * document is read on one thread, while the workers decode the image data
+ * and encode the PNG files.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Jpeg2000,
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Jpeg2000 => "jp2",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExtractedImage {
+    pub width: u64,
+    pub height: u64,
+    pub format: ImageFormat,
+    // whether the soft mask made it in as an alpha channel
+    pub alpha: bool,
+    // the file's contents
+    pub data: Vec<u8>,
+}
+
+// The samples' layout, from the image dictionary.
+#[derive(Debug, PartialEq, Clone)]
+struct Samples {
+    width: u32,
+    height: u32,
+    bits: u8,
+    color_type: ColorType,
+    // RGB triples for an indexed image
+    palette: Option<Vec<u8>>,
+    inverted: bool,
+}
+
+// Whether the object is an image XObject, from its dictionary.
+pub fn is_image(object: &DictNode) -> bool {
+    object.get("Subtype").is_some_and(|subtype| subtype.is_name("Image"))
+}
+
+// The image at `xref_entry` as a file, None if it isn't an image.
+pub fn extract(doc: &mut Document, xref_entry: &XRefEntry) -> Result<Option<ExtractedImage>> {
+    let dict = doc.object(xref_entry)?;
+    if !is_image(&dict) {
+        return Ok(None);
+    }
+    if let Some(encoded) = doc.encoded_image(xref_entry)? {
+        return Ok(Some(ExtractedImage {
+            width: encoded.width.unwrap_or(0),
+            height: encoded.height.unwrap_or(0),
+            format: match encoded.codec {
+                ImageCodec::Jpeg => ImageFormat::Jpeg,
+                ImageCodec::Jpeg2000 => ImageFormat::Jpeg2000,
+            },
+            alpha: false,
+            data: encoded.data,
+        }));
+    }
+    let samples = read_samples(doc, &dict)?;
+    let data = match doc.stream(xref_entry)? {
+        Some((_, data)) => data,
+        None => return Ok(None),
+    };
+    let mask = match dict.get("SMask") {
+        Some(smask) => soft_mask(doc, smask).ok().and_then(|mask| mask),
+        None => None,
+    };
+    Ok(Some(png_image(&samples, data, mask)?))
+}
+
+// An image read from the document, still to be made a file.
+enum ImageJob {
+    // JPEGs and JPEG 2000s, already files
+    Done(ExtractedImage),
+    Raw { samples: Samples, dict: DictNode, raw: Vec<u8>, mask: Option<(Samples, Vec<u8>)> },
+    Failed(PdfError),
+}
+
+impl ImageJob {
+    fn finish(self) -> Result<ExtractedImage> {
+        match self {
+            ImageJob::Done(image) => Ok(image),
+            ImageJob::Raw { samples, dict, raw, mask } => png_image(&samples, streams::decode(&dict, &raw)?, mask),
+            ImageJob::Failed(err) => Err(err),
+        }
+    }
+}
+
+// `extract` for every image among `entries`, on `workers` threads. `handle`
+// gets each one's object number and the image, or what kept it from being
+// extracted, in the order of `entries`. Images done out of order wait for
+// the ones before them.
+pub fn extract_all<F>(doc: &mut Document, entries: &[(u64, XRefEntry)], workers: usize, mut handle: F) -> Result<()>
+    where F: FnMut(u64, Result<ExtractedImage>) -> Result<()>
+{
+    let jobs = entries.iter()
+        .filter_map(move |&(id, ref entry)| match read_job(doc, id, entry) {
+            Ok(Some(job)) => Some((id, job)),
+            Ok(None) => None,
+            Err(err) => Some((id, ImageJob::Failed(err))),
+        })
+        .enumerate()
+        .map(Ok);
+    let finish = |(index, (id, job)): (usize, (u64, ImageJob))| (index, id, job.finish());
+    let mut waiting = BTreeMap::new();
+    let mut next = 0;
+    pipeline::map_parallel(jobs, workers, finish, |(index, id, image)| {
+        waiting.insert(index, (id, image));
+        while let Some((id, image)) = waiting.remove(&next) {
+            handle(id, image)?;
+            next += 1;
+        }
+        Ok(())
+    })
+}
+
+// What `extract` reads from the document, leaving the decoding and encoding
+// for later.
+fn read_job(doc: &mut Document, id: u64, xref_entry: &XRefEntry) -> Result<Option<ImageJob>> {
+    let dict = doc.object(xref_entry)?;
+    if !is_image(&dict) {
+        return Ok(None);
+    }
+    if let Some(encoded) = doc.encoded_image(xref_entry)? {
+        return Ok(Some(ImageJob::Done(ExtractedImage {
+            width: encoded.width.unwrap_or(0),
+            height: encoded.height.unwrap_or(0),
+            format: match encoded.codec {
+                ImageCodec::Jpeg => ImageFormat::Jpeg,
+                ImageCodec::Jpeg2000 => ImageFormat::Jpeg2000,
+            },
+            alpha: false,
+            data: encoded.data,
+        })));
+    }
+    let samples = read_samples(doc, &dict)?;
+    let (dict, raw) = match doc.stream_jobs(&[(id, xref_entry.clone())]).next() {
+        Some(job) => job.map(|job| (job.dict, job.raw))?,
+        None => return Ok(None),
+    };
+    let mask = match dict.get("SMask") {
+        Some(smask) => soft_mask(doc, smask).ok().and_then(|mask| mask),
+        None => None,
+    };
+    Ok(Some(ImageJob::Raw { samples, dict, raw, mask }))
+}
+
+// Decoded samples as a PNG file, with the soft mask as alpha if there's one.
+fn png_image(samples: &Samples, data: Vec<u8>, mask: Option<(Samples, Vec<u8>)>) -> Result<ExtractedImage> {
+    let alpha = mask.is_some();
+    let data = match mask {
+        Some((mask, mask_data)) => to_rgba_png(samples, data, &mask, mask_data)?,
+        None => to_png(samples, data)?,
+    };
+    Ok(ExtractedImage {
+        width: samples.width as u64,
+        height: samples.height as u64,
+        format: ImageFormat::Png,
+        alpha,
+        data,
+    })
+}
+
+// The dictionary and data of each inline image in the page's content, in
+// the order they're drawn.
+pub fn inline_images(doc: &mut Document, page: &Page) -> Result<Vec<(DictNode, Vec<u8>)>> {
+    let mut images = Vec::new();
+    for operation in pages::operations(doc, page)? {
+        let operation = operation?;
+        if let Some((dict, data)) = operation.inline_image() {
+            images.push((dict.clone(), data.to_vec()));
+        }
+    }
+    Ok(images)
+}
+
+// An inline image as a file, `resources` being those of the content it's in.
+pub fn extract_inline(doc: &mut Document, resources: &DictNode, dict: &DictNode, data: &[u8]) -> Result<ExtractedImage> {
+    let mut dict = dict.clone();
+    let named = dict.get("ColorSpace").and_then(DictNode::as_name).map(str::to_string);
+    if let Some(name) = named.filter(|name| !name.starts_with("Device")) {
+        let space = match resources.get_resolved(doc, "ColorSpace")? {
+            Some(spaces) => spaces.get(&name).cloned(),
+            None => None,
+        };
+        match (space, &mut dict) {
+            (Some(space), DictNode::Dict(map)) => {
+                map.insert("ColorSpace".to_string(), space);
+            },
+            _ => return unsupported(format!("color space {} isn't in the resources", name)),
+        }
+    }
+    if let Some(encoded) = filters::encoded_image(&dict, data)? {
+        return Ok(ExtractedImage {
+            width: encoded.width.unwrap_or(0),
+            height: encoded.height.unwrap_or(0),
+            format: match encoded.codec {
+                ImageCodec::Jpeg => ImageFormat::Jpeg,
+                ImageCodec::Jpeg2000 => ImageFormat::Jpeg2000,
+            },
+            alpha: false,
+            data: encoded.data,
+        });
+    }
+    let samples = read_samples(doc, &dict)?;
+    let data = to_png(&samples, filters::decode_chain(&dict, data)?)?;
+    Ok(ExtractedImage { width: samples.width as u64, height: samples.height as u64, format: ImageFormat::Png, alpha: false, data })
+}
+
+// The samples of a soft mask, which is a DeviceGray image of its own.
+fn soft_mask(doc: &mut Document, smask: &DictNode) -> Result<Option<(Samples, Vec<u8>)>> {
+    let (dict, data) = match doc.referenced_stream(smask)? {
+        Some(stream) => stream,
+        None => return Ok(None),
+    };
+    let samples = read_samples(doc, &dict)?;
+    if samples.color_type != ColorType::Gray {
+        return unsupported("a soft mask that isn't gray".to_string());
+    }
+    Ok(Some((samples, data)))
+}
+
+fn unsupported<T>(what: String) -> Result<T> {
+    Err(PdfError::UnsupportedImage(what))
+}
+
+fn read_samples(doc: &mut Document, dict: &DictNode) -> Result<Samples> {
+    let width = int_entry(doc, dict, "Width")?.filter(|&width| width > 0 && width <= u32::MAX as i64);
+    let height = int_entry(doc, dict, "Height")?.filter(|&height| height > 0 && height <= u32::MAX as i64);
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width as u32, height as u32),
+        _ => return unsupported("no usable /Width and /Height".to_string()),
+    };
+    let mask = dict.get_resolved(doc, "ImageMask")?.and_then(|mask| mask.as_bool()).unwrap_or(false);
+    let bits = if mask { 1 } else { int_entry(doc, dict, "BitsPerComponent")?.unwrap_or(8) };
+
+    let (color_type, palette) = if mask {
+        (ColorType::Gray, None)
+    } else {
+        match dict.get_resolved(doc, "ColorSpace")? {
+            Some(space) => color_type(doc, &space)?,
+            None => return unsupported("no /ColorSpace".to_string()),
+        }
+    };
+    let allowed: &[i64] = match color_type {
+        ColorType::Gray => &[1, 2, 4, 8, 16],
+        ColorType::Rgb => &[8, 16],
+        ColorType::Indexed => &[1, 2, 4, 8],
+        // not a color space's
+        ColorType::Rgba => &[],
+    };
+    if !allowed.contains(&bits) {
+        return unsupported(format!("{:?} at {} bits per component", color_type, bits));
+    }
+
+    let decode = dict.get_resolved(doc, "Decode")?.map(|decode| doc.resolve_all(&decode)).transpose()?;
+    let decode: Vec<f64> = decode.as_ref().and_then(DictNode::as_array).unwrap_or(&[]).iter().filter_map(DictNode::as_float).collect();
+    let max = ((1u32 << bits) - 1) as f64;
+    let inverted = match decode.as_slice() {
+        [] => false,
+        _ if color_type == ColorType::Indexed => match decode.as_slice() {
+            &[low, high] if low == 0.0 && high == max => false,
+            _ => return unsupported("an indexed image with /Decode".to_string()),
+        },
+        pairs if pairs.chunks(2).all(|pair| pair == [0.0, 1.0]) => false,
+        pairs if pairs.chunks(2).all(|pair| pair == [1.0, 0.0]) => true,
+        _ => return unsupported("a /Decode array other than an inversion".to_string()),
+    };
+    Ok(Samples { width, height, bits: bits as u8, color_type, palette, inverted })
+}
+
+fn int_entry(doc: &mut Document, dict: &DictNode, key: &str) -> Result<Option<i64>> {
+    Ok(dict.get_resolved(doc, key)?.and_then(|value| value.as_int()))
+}
+
+// What PNG color type a color space comes down to, with the palette of an
+// Indexed space.
+fn color_type(doc: &mut Document, space: &DictNode) -> Result<(ColorType, Option<Vec<u8>>)> {
+    let items = space.as_array().unwrap_or(&[]);
+    if items.first().is_some_and(|first| first.is_name("Indexed") || first.is_name("I")) {
+        let base = match items.get(1) {
+            Some(base) => ColorFamily::from_node(&doc.resolve_all(base)?),
+            None => ColorFamily::Other,
+        };
+        let entries = items.get(2).and_then(DictNode::as_int).unwrap_or(0).clamp(0, 255) as usize + 1;
+        let lookup = match items.get(3) {
+            Some(reference @ DictNode::ObjectReference(..)) => match doc.resolve(reference)? {
+                DictNode::Dict(_) => doc.referenced_stream(reference)?.map(|(_, data)| data),
+                lookup => lookup.as_string().map(<[u8]>::to_vec),
+            },
+            Some(lookup) => lookup.as_string().map(<[u8]>::to_vec),
+            None => None,
+        };
+        let mut lookup = match lookup {
+            Some(lookup) => lookup,
+            None => return unsupported("an indexed color space without a lookup table".to_string()),
+        };
+        let palette = match base {
+            ColorFamily::Rgb => {
+                lookup.resize(entries * 3, 0);
+                lookup
+            },
+            ColorFamily::Gray => {
+                lookup.resize(entries, 0);
+                lookup.iter().flat_map(|&gray| vec![gray; 3]).collect()
+            },
+            other => return unsupported(format!("indexed over {:?}", other)),
+        };
+        return Ok((ColorType::Indexed, Some(palette)));
+    }
+    match ColorFamily::from_node(&doc.resolve_all(space)?) {
+        ColorFamily::Gray => Ok((ColorType::Gray, None)),
+        ColorFamily::Rgb => Ok((ColorType::Rgb, None)),
+        other => unsupported(format!("{:?} color", other)),
+    }
+}
+
+fn to_png(samples: &Samples, data: Vec<u8>) -> Result<Vec<u8>> {
+    let data = prepare(samples, data)?;
+    Ok(png::encode(samples.width, samples.height, samples.bits, samples.color_type, samples.palette.as_deref(), &data))
+}
+
+fn to_rgba_png(samples: &Samples, data: Vec<u8>, mask: &Samples, mask_data: Vec<u8>) -> Result<Vec<u8>> {
+    let colors = rgb(samples, &prepare(samples, data)?);
+    let alpha = rgb(mask, &prepare(mask, mask_data)?);
+    let (width, height) = (samples.width as usize, samples.height as usize);
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        // nearest neighbour, for masks of another size
+        let mask_y = y * mask.height as usize / height;
+        for x in 0..width {
+            let mask_x = x * mask.width as usize / width;
+            let pixel = (y * width + x) * 3;
+            rgba.extend_from_slice(&colors[pixel..pixel + 3]);
+            rgba.push(alpha[(mask_y * mask.width as usize + mask_x) * 3]);
+        }
+    }
+    Ok(png::encode(samples.width, samples.height, 8, ColorType::Rgba, None, &rgba))
+}
+
+// The sample data padded out to the size the dictionary says, with the
+// /Decode inversion applied.
+fn prepare(samples: &Samples, mut data: Vec<u8>) -> Result<Vec<u8>> {
+    let row = png::row_len(samples.width, samples.bits, samples.color_type);
+    let size = row.checked_mul(samples.height as usize);
+    match size {
+        // short data is common enough, viewers show what's there
+        Some(size) if size <= data.len() + row * 16 => data.resize(size, 0),
+        _ => return unsupported(format!("{} bytes of samples for {}x{}", data.len(), samples.width, samples.height)),
+    }
+    if samples.inverted {
+        // max - value is value with every bit flipped
+        for byte in &mut data {
+            *byte = !*byte;
+        }
+    }
+    Ok(data)
+}
+
+// Prepared samples as 8 bit RGB triples, palette looked up and gray
+// repeated.
+fn rgb(samples: &Samples, data: &[u8]) -> Vec<u8> {
+    let row = png::row_len(samples.width, samples.bits, samples.color_type);
+    let channels = samples.color_type.channels();
+    let bits = samples.bits as usize;
+    let max = (1u32 << bits) - 1;
+    let mut rgb = Vec::with_capacity(samples.width as usize * samples.height as usize * 3);
+    for line in data.chunks(row) {
+        for x in 0..samples.width as usize {
+            let mut pixel = [0u32; 3];
+            for (channel, value) in pixel.iter_mut().enumerate().take(channels) {
+                let bit = (x * channels + channel) * bits;
+                // 16 bit samples only keep their high byte
+                *value = match bits {
+                    8 | 16 => line[bit / 8] as u32,
+                    _ => (line[bit / 8] as u32 >> (8 - bits - bit % 8)) & max,
+                };
+            }
+            match samples.color_type {
+                ColorType::Indexed => {
+                    let palette = samples.palette.as_deref().unwrap_or(&[]);
+                    let entry = pixel[0] as usize * 3;
+                    rgb.extend_from_slice(palette.get(entry..entry + 3).unwrap_or(&[0, 0, 0]));
+                },
+                _ => {
+                    let scale = |value: u32| if bits >= 8 { value as u8 } else { (value * 255 / max) as u8 };
+                    match samples.color_type {
+                        ColorType::Gray => rgb.extend_from_slice(&[scale(pixel[0]); 3]),
+                        _ => rgb.extend(pixel.iter().map(|&value| scale(value))),
+                    }
+                },
+            }
+        }
+    }
+    rgb
+}
+
+#[test]
+fn test_to_png() {
+    let samples = Samples { width: 9, height: 2, bits: 1, color_type: ColorType::Gray, palette: None, inverted: true };
+    let png = to_png(&samples, vec![0xff, 0x80, 0x00, 0x00]).unwrap();
+    assert_eq!(&png[16..29], &[0, 0, 0, 9, 0, 0, 0, 2, 1, 0, 0, 0, 0]);
+    // one row short, padded with zeros
+    let png = to_png(&samples, vec![0xff, 0x80]).unwrap();
+    assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    assert!(to_png(&Samples { height: 1000, ..samples.clone() }, vec![0xff, 0x80]).is_err());
+
+    let indexed = Samples { width: 2, height: 1, bits: 8, color_type: ColorType::Indexed, palette: Some(vec![255, 0, 0, 0, 0, 255]), inverted: false };
+    let png = to_png(&indexed, vec![0, 1]).unwrap();
+    assert_eq!(&png[37..41], b"PLTE");
+}
+
+#[test]
+fn test_rgba() {
+    let gray = Samples { width: 4, height: 1, bits: 2, color_type: ColorType::Gray, palette: None, inverted: false };
+    // 0, 1, 2, 3
+    assert_eq!(rgb(&gray, &[0b00_01_10_11]), vec![0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255]);
+    let deep = Samples { width: 1, height: 1, bits: 16, color_type: ColorType::Rgb, palette: None, inverted: false };
+    assert_eq!(rgb(&deep, &[0xff, 0xff, 0x80, 0x00, 0x00, 0x10]), vec![255, 128, 0]);
+    let indexed = Samples { width: 2, height: 1, bits: 4, color_type: ColorType::Indexed, palette: Some(vec![1, 2, 3, 4, 5, 6]), inverted: false };
+    assert_eq!(rgb(&indexed, &[0x10]), vec![4, 5, 6, 1, 2, 3]);
+
+    // a 2x2 image with a 1x2 mask, transparent on top
+    let image = Samples { width: 2, height: 2, bits: 8, color_type: ColorType::Rgb, palette: None, inverted: false };
+    let mask = Samples { width: 1, height: 2, bits: 8, color_type: ColorType::Gray, palette: None, inverted: false };
+    let png = to_rgba_png(&image, vec![10; 12], &mask, vec![0, 255]).unwrap();
+    assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+    let len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+    let pixels = ::filters::flate_decode(&png[41..41 + len]).unwrap();
+    assert_eq!(pixels, vec![0, 10, 10, 10, 0, 10, 10, 10, 0, 0, 10, 10, 10, 255, 10, 10, 10, 255]);
+}
+
+#[test]
+fn test_is_image() {
+    assert!(is_image(&DictNode::Dict(hashmap!{ "Subtype".to_string() => DictNode::name("Image") })));
+    assert!(!is_image(&DictNode::Dict(hashmap!{ "Subtype".to_string() => DictNode::name("Form") })));
+}
+
+#[test]
+fn test_extract_all() {
+    use std::collections::HashMap;
+    use std::fs;
+    use fixtures::FixtureFile;
+    use writer::Writer;
+    use Options;
+
+    // gray images of different sizes, deflated or not, and an object that
+    // isn't an image
+    let mut out = Writer::new();
+    out.object(1, &DictNode::Dict(HashMap::from([("Type".to_string(), DictNode::name("Catalog"))]))).unwrap();
+    for id in 2..20u64 {
+        let size = id as i64 * 7;
+        let pixels: Vec<u8> = (0..size * size).map(|index| (index * id as i64) as u8).collect();
+        let mut dict = HashMap::from([
+            ("Subtype".to_string(), DictNode::name("Image")),
+            ("Width".to_string(), DictNode::Int(size)),
+            ("Height".to_string(), DictNode::Int(size)),
+            ("ColorSpace".to_string(), DictNode::name("DeviceGray")),
+            ("BitsPerComponent".to_string(), DictNode::Int(8)),
+        ]);
+        let data = if id % 2 == 0 {
+            dict.insert("Filter".to_string(), DictNode::name("FlateDecode"));
+            filters::flate_encode(&pixels)
+        } else {
+            pixels
+        };
+        dict.insert("Length".to_string(), DictNode::Int(data.len() as i64));
+        out.stream(id, &DictNode::Dict(dict), &data).unwrap();
+    }
+    let file = FixtureFile { path: ::std::env::temp_dir().join("nails-pdf-extract-all.pdf") };
+    fs::write(&file.path, out.finish_with(HashMap::from([("Root".to_string(), DictNode::reference(1))]))).unwrap();
+
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+    let entries: Vec<(u64, XRefEntry)> = doc.xref.entries().map(|(id, entry)| (id as u64, entry.clone())).collect();
+    let mut expected = Vec::new();
+    for (id, entry) in &entries {
+        if let Some(image) = extract(&mut doc, entry).unwrap() {
+            expected.push((*id, image));
+        }
+    }
+    assert_eq!(expected.len(), 18);
+    for &workers in &[1, 4] {
+        let mut extracted = Vec::new();
+        extract_all(&mut doc, &entries, workers, |id, image| {
+            extracted.push((id, image?));
+            Ok(())
+        }).unwrap();
+        assert_eq!(extracted, expected);
+    }
+}
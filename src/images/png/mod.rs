@@ -0,0 +1,106 @@
+/*
+ * Just enough of a PNG encoder to write out image XObjects: one IHDR, an
+ * optional PLTE, one IDAT and IEND.
+ *
+ * PDF sample rows are laid out the way PNG wants them (big-endian, each row
+ * padded to a whole byte), so the pixel data goes in as is, behind a "None"
+ * filter byte per row, and deflated with `filters::flate_encode`.
+ */
+
+use filters;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorType {
+    Gray = 0,
+    Rgb = 2,
+    Indexed = 3,
+    Rgba = 6,
+}
+
+impl ColorType {
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+            ColorType::Gray | ColorType::Indexed => 1,
+        }
+    }
+}
+
+// Bytes in one row of samples, padding included.
+pub fn row_len(width: u32, bits: u8, color_type: ColorType) -> usize {
+    (width as usize * color_type.channels() * bits as usize).div_ceil(8)
+}
+
+// `pixels` has `height` rows of `row_len` bytes, `palette` RGB triples for
+// an indexed image.
+pub fn encode(width: u32, height: u32, bits: u8, color_type: ColorType, palette: Option<&[u8]>, pixels: &[u8]) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // compression, filter and interlace methods are all 0
+    header.extend_from_slice(&[bits, color_type as u8, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+
+    if let Some(palette) = palette {
+        chunk(&mut png, b"PLTE", palette);
+    }
+
+    let row = row_len(width, bits, color_type);
+    let mut filtered = Vec::with_capacity((row + 1) * height as usize);
+    for index in 0..height as usize {
+        filtered.push(0);
+        filtered.extend_from_slice(&pixels[index * row..(index + 1) * row]);
+    }
+    chunk(&mut png, b"IDAT", &filters::flate_encode(&filtered));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_encode() {
+    use filters::flate_decode;
+
+    assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    assert_eq!(row_len(10, 1, ColorType::Gray), 2);
+    assert_eq!(row_len(3, 16, ColorType::Rgb), 18);
+
+    let png = encode(2, 2, 8, ColorType::Gray, None, &[0, 255, 128, 64]);
+    assert!(png.starts_with(SIGNATURE));
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+    assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+
+    let idat = 8 + 25;
+    assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+    let len = u32::from_be_bytes([png[idat], png[idat + 1], png[idat + 2], png[idat + 3]]) as usize;
+    assert_eq!(flate_decode(&png[idat + 8..idat + 8 + len]).unwrap(), vec![0, 0, 255, 0, 128, 64]);
+
+    // deflated, not stored
+    let png = encode(256, 256, 8, ColorType::Gray, None, &[7; 256 * 256]);
+    assert!(png.len() < 256 * 256 / 10);
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use document::Document;
+use error::Result;
+use fonts;
+use pages;
+use parsers;
+use parsers::cos::DictNode;
+use parsers::xref::{XRefEntry, XRefTable};
+
+/*
+ * Sidecar index files, so opening the same big document over and over
+ * (e.g. running the CLI on it repeatedly) doesn't pay for walking the xref
+ * chain and decoding xref streams every time.
+ *
+ * "report.pdf" gets a "report.pdf.nails-index" next to it holding the
+ * trailer, the merged xref table, the page tree and the fonts the pages
+ * use, in plain text:
+ *
+ *   nails-pdf index 2
+ *   fingerprint 1048576 1700000000.123456789 9f3a...
+ *   trailer << /Root 1 0 R /Size 40 >>
+ *   1 n 15 0
+ *   2 c 12 0
+ *   3 f 0 65535
+ *   page 4 0 << /MediaBox [0 0 612 792] /Resources 7 0 R >>
+ *   font 9 0
+ *
+ * Each page is its object and what it inherits from up the tree, so
+ * `pages::pages` doesn't walk the tree again, and each font a reference in
+ * the order `fonts::fonts` finds them, so it doesn't go through every
+ * page's resources. A tree with pages written straight into a /Kids array,
+ * or fonts straight into a resource dictionary, has no references to keep
+ * and is left out; so is one that couldn't be walked. Both only stand
+ * while the document isn't edited.
+ *
+ * The fingerprint is the file size, modification time and a hash of the
+ * tail of the file, which is where every incremental update writes its
+ * startxref. If any of them differs the index is stale and ignored.
+ */
+
+const HEADER: &str = "nails-pdf index 2";
+const EXTENSION: &str = "nails-index";
+const TAIL_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fingerprint {
+    size: u64,
+    modified: (u64, u32),
+    tail_hash: u64,
+}
+
+impl Fingerprint {
+    fn line(&self) -> String {
+        format!("fingerprint {} {}.{:09} {:016x}", self.size, self.modified.0, self.modified.1, self.tail_hash)
+    }
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(EXTENSION);
+    path.with_file_name(name)
+}
+
+pub fn fingerprint(file: &mut File) -> Result<Fingerprint> {
+    let metadata = file.metadata()?;
+    let modified = metadata.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|since| (since.as_secs(), since.subsec_nanos()))
+        .unwrap_or((0, 0));
+
+    let size = metadata.len();
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(size.saturating_sub(TAIL_SIZE)))?;
+    file.take(TAIL_SIZE).read_to_end(&mut tail)?;
+    Ok(Fingerprint { size, modified, tail_hash: fnv1a(&tail) })
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// What an index holds.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Index {
+    pub trailer: DictNode,
+    pub xref: XRefTable,
+    // each page's reference and what it inherits, None when the tree isn't
+    // kept
+    pub pages: Option<Vec<pages::Leaf>>,
+    // references to the fonts the pages use, None when they aren't kept
+    pub fonts: Option<Vec<(i64, i64)>>,
+}
+
+impl Index {
+    // The index of `doc`, freshly opened, walking its page tree and the
+    // resources of its pages.
+    pub fn new(doc: &mut Document) -> Index {
+        let pages = pages::leaves(doc).ok()
+            .filter(|leaves| leaves.iter().all(|leaf| leaf.node.as_reference().is_some()));
+        let fonts = fonts::font_nodes(doc).ok()
+            .and_then(|nodes| nodes.iter().map(DictNode::as_reference).collect());
+        Index { trailer: doc.trailer.clone(), xref: doc.xref.clone(), pages, fonts }
+    }
+}
+
+// The index at `path`, or None if there's no index or it was made for a
+// different version of the file.
+pub fn read(path: &Path, fingerprint: &Fingerprint) -> Result<Option<Index>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Ok(None),
+    };
+    Ok(parse_index(&text, fingerprint))
+}
+
+pub fn write(path: &Path, fingerprint: &Fingerprint, index: &Index) -> Result<()> {
+    // write then rename, so a concurrent open never sees half an index
+    let temp = path.with_extension(format!("{}.tmp", EXTENSION));
+    fs::write(&temp, format_index(fingerprint, index))?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn format_index(fingerprint: &Fingerprint, index: &Index) -> String {
+    let mut text = format!("{}\n{}\ntrailer {}\n", HEADER, fingerprint.line(), index.trailer);
+    for (id, entry) in index.xref.entries() {
+        let line = match (entry.is_free, entry.object_stream) {
+            (true, _) => format!("{} f {} {}\n", id, entry.offset, entry.generation_id),
+            (false, Some(stream)) => format!("{} c {} {}\n", id, stream, entry.offset),
+            (false, None) => format!("{} n {} {}\n", id, entry.offset, entry.generation_id),
+        };
+        text.push_str(&line);
+    }
+    for leaf in index.pages.iter().flatten() {
+        if let Some((id, generation)) = leaf.node.as_reference() {
+            text.push_str(&format!("page {} {} {}\n", id, generation, DictNode::Dict(leaf.inherited.clone())));
+        }
+    }
+    for (id, generation) in index.fonts.iter().flatten() {
+        text.push_str(&format!("font {} {}\n", id, generation));
+    }
+    text
+}
+
+fn parse_index(text: &str, fingerprint: &Fingerprint) -> Option<Index> {
+    let mut lines = text.lines();
+    if lines.next()? != HEADER || lines.next()? != fingerprint.line() {
+        return None;
+    }
+    let trailer = parsers::cos::parse(lines.next()?.strip_prefix("trailer ")?).ok()?;
+
+    let mut xref = XRefTable::new(Vec::new());
+    let (mut pages, mut fonts) = (None, None);
+    for line in lines {
+        if let Some(page) = line.strip_prefix("page ") {
+            let fields: Vec<&str> = page.splitn(3, ' ').collect();
+            let node = DictNode::ObjectReference(fields.first()?.parse().ok()?, fields.get(1)?.parse().ok()?);
+            let inherited = parsers::cos::parse(fields.get(2)?).ok()?.as_dict()?.clone();
+            pages.get_or_insert_with(Vec::new).push(pages::Leaf { node, inherited });
+            continue;
+        }
+        if let Some(font) = line.strip_prefix("font ") {
+            let (id, generation) = font.split_once(' ')?;
+            fonts.get_or_insert_with(Vec::new).push((id.parse().ok()?, generation.parse().ok()?));
+            continue;
+        }
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        let id = fields[0].parse::<usize>().ok()?;
+        let first = fields[2].parse::<u64>().ok()?;
+        let second = fields[3].parse::<u64>().ok()?;
+        let entry = match fields[1] {
+            "n" => XRefEntry::in_use(first, second),
+            "c" => XRefEntry::compressed(first, second),
+            "f" => XRefEntry { offset: first, generation_id: second, is_free: true, object_stream: None },
+            _ => return None,
+        };
+        xref.set(id, entry);
+    }
+    Some(Index { trailer, xref, pages, fonts })
+}
+
+#[test]
+fn test_index_round_trip() {
+    let fingerprint = Fingerprint { size: 1000, modified: (1700000000, 5), tail_hash: fnv1a(b"%%EOF") };
+    let trailer = DictNode::Dict(hashmap!{ "Size".to_string() => DictNode::Int(4) });
+    let mut xref = XRefTable::new(vec![XRefEntry::free(), XRefEntry::in_use(15, 0)]);
+    xref.set(3, XRefEntry::compressed(1, 7));
+    let leaf = pages::Leaf {
+        node: DictNode::reference(3),
+        inherited: hashmap!{ "Rotate".to_string() => DictNode::Int(90) },
+    };
+    let mut index = Index { trailer, xref, pages: Some(vec![leaf]), fonts: Some(vec![(5, 0)]) };
+
+    let text = format_index(&fingerprint, &index);
+    assert_eq!(text, "nails-pdf index 2\n\
+                      fingerprint 1000 1700000000.000000005 d9af44e82c80fdaf\n\
+                      trailer << /Size 4 >>\n\
+                      0 f 0 65535\n\
+                      1 n 15 0\n\
+                      3 c 1 7\n\
+                      page 3 0 << /Rotate 90 >>\n\
+                      font 5 0\n");
+    assert_eq!(parse_index(&text, &fingerprint), Some(index.clone()));
+
+    // a page tree or fonts that couldn't be kept are left out
+    index.pages = None;
+    index.fonts = None;
+    assert_eq!(parse_index(&format_index(&fingerprint, &index), &fingerprint), Some(index));
+
+    let stale = Fingerprint { size: 1001, ..fingerprint.clone() };
+    assert_eq!(parse_index(&text, &stale), None);
+    assert_eq!(parse_index("nails-pdf index 2\ngarbage", &fingerprint), None);
+    assert_eq!(parse_index(&text.replace("index 2", "index 1"), &fingerprint), None);
+}
+
+#[test]
+fn test_sidecar_path() {
+    assert_eq!(sidecar_path(Path::new("/tmp/report.pdf")), PathBuf::from("/tmp/report.pdf.nails-index"));
+}
+
+#[test]
+fn test_cached_pages_and_fonts() {
+    use document::{DuplicatePolicy, Options};
+    use fixtures::{Fixture, FixtureFile};
+
+    for fixture in [Fixture::Simple, Fixture::ObjectStream, Fixture::Cjk] {
+        let file = fixture.write();
+        let sidecar = FixtureFile { path: sidecar_path(&file.path) };
+        let mut doc = Document::open(&file.path, Options::default()).unwrap();
+        let (walked_pages, walked_fonts) = (pages::pages(&mut doc).unwrap(), fonts::fonts(&mut doc).unwrap());
+        assert!(!walked_fonts.is_empty());
+
+        let cached = Options { index_cache: true, ..Options::default() };
+        Document::open(&file.path, cached.clone()).unwrap();
+        let mut doc = Document::open(&file.path, cached.clone()).unwrap();
+        assert_eq!(doc.cached_pages().map(|leaves| leaves.len()), Some(walked_pages.len()));
+        assert_eq!(doc.cached_fonts().map(|fonts| fonts.len()), Some(walked_fonts.len()));
+        assert_eq!(pages::pages(&mut doc).unwrap(), walked_pages);
+        assert_eq!(fonts::fonts(&mut doc).unwrap(), walked_fonts);
+
+        // edits may have changed either
+        doc.set_object(6, DictNode::Dict(Default::default()));
+        assert_eq!(doc.cached_pages(), None);
+        assert_eq!(doc.cached_fonts(), None);
+
+        // the index is only read for the opens it's written for
+        assert!(sidecar.path.exists());
+        let last_wins = Options { duplicates: DuplicatePolicy::LastWins, ..cached.clone() };
+        assert_eq!(Document::open(&file.path, last_wins).unwrap().cached_pages(), None);
+        let lenient = Options { lenient: true, ..cached };
+        assert_eq!(Document::open(&file.path, lenient).unwrap().cached_pages(), None);
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+
+use document::Document;
+use error::Result;
+use parsers::cos::DictNode;
+
+/*
+ * The document information dictionary, /Info in the trailer: title,
+ * author and the like, as the producing application filled them in.
+ *
+ *   if let Some(info) = info::info(&mut doc)? {
+ *       println!("{}", info.title.unwrap_or_default());
+ *   }
+ *
+ * Text strings are UTF-16BE when they start with a byte order mark and
+ * read as Latin-1 otherwise (PDFDocEncoding mostly agrees with it). Dates
+ * are "D:YYYYMMDDHHmmSSOHH'mm'" with everything after the year optional;
+ * ones that don't read as that come out as None rather than garbage.
+ * Entries of the wrong type are skipped too.
+ */
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DocumentInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    pub creator: Option<String>,
+    pub producer: Option<String>,
+    pub creation_date: Option<PdfDate>,
+    pub mod_date: Option<PdfDate>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct PdfDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    // minutes east of UTC, None when the date doesn't say
+    pub utc_offset: Option<i16>,
+}
+
+impl PdfDate {
+    pub fn parse(text: &str) -> Option<PdfDate> {
+        let text = text.trim();
+        let text = text.strip_prefix("D:").unwrap_or(text);
+        let digits = text.chars().take_while(char::is_ascii_digit).count();
+        if !(4..=14).contains(&digits) || digits % 2 != 0 {
+            return None;
+        }
+        let field = |start: usize, len: usize, default: u16| -> u16 {
+            text.get(start..start + len).filter(|_| start + len <= digits).and_then(|value| value.parse().ok()).unwrap_or(default)
+        };
+        let date = PdfDate {
+            year: field(0, 4, 0),
+            month: field(4, 2, 1) as u8,
+            day: field(6, 2, 1) as u8,
+            hour: field(8, 2, 0) as u8,
+            minute: field(10, 2, 0) as u8,
+            second: field(12, 2, 0) as u8,
+            utc_offset: parse_offset(&text[digits..])?,
+        };
+        let valid = (1..=12).contains(&date.month) && (1..=31).contains(&date.day)
+            && date.hour < 24 && date.minute < 60 && date.second < 60;
+        if valid { Some(date) } else { None }
+    }
+
+    // `seconds` since 1970-01-01 UTC, in UTC.
+    pub fn from_unix(seconds: i64) -> PdfDate {
+        let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+        // Howard Hinnant's civil_from_days, eras of 400 years from March
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        PdfDate {
+            year: year.clamp(0, 9999) as u16,
+            month: month as u8,
+            day: (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+            utc_offset: Some(0),
+        }
+    }
+
+    // As written in a PDF, "D:YYYYMMDDHHmmSS" and the offset.
+    pub fn to_pdf(&self) -> String {
+        let mut text = format!("D:{:04}{:02}{:02}{:02}{:02}{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second);
+        match self.utc_offset {
+            Some(0) => text.push('Z'),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                text.push_str(&format!("{}{:02}'{:02}'", sign, offset.abs() / 60, offset.abs() % 60));
+            },
+            None => (),
+        }
+        text
+    }
+}
+
+// "Z", "+01'00'", "-05'00", "+0530" or nothing, as Some(minutes east of
+// UTC) or Some(None). None when it's something else.
+fn parse_offset(text: &str) -> Option<Option<i16>> {
+    let sign = match text.chars().next() {
+        None => return Some(None),
+        Some('Z') => return Some(Some(0)),
+        Some('+') => 1,
+        Some('-') => -1,
+        Some(_) => return None,
+    };
+    let digits: String = text[1..].chars().filter(|&c| c != '\'').collect();
+    if digits.len() != 2 && digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i16 = digits[..2].parse().ok()?;
+    let minutes: i16 = digits.get(2..).filter(|minutes| !minutes.is_empty()).map_or(Ok(0), str::parse).ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(Some(sign * (hours * 60 + minutes)))
+}
+
+// ISO 8601, which is what people expect to read.
+impl fmt::Display for PdfDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)?;
+        match self.utc_offset {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                write!(f, "{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+// One "key: value" line per entry there is.
+impl fmt::Display for DocumentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let texts = [
+            ("title", &self.title),
+            ("author", &self.author),
+            ("subject", &self.subject),
+            ("keywords", &self.keywords),
+            ("creator", &self.creator),
+            ("producer", &self.producer),
+        ];
+        for (key, value) in texts.iter() {
+            if let Some(value) = value {
+                writeln!(f, "{}: {}", key, value)?;
+            }
+        }
+        for (key, value) in [("created", &self.creation_date), ("modified", &self.mod_date)].iter() {
+            if let Some(value) = value {
+                writeln!(f, "{}: {}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The document's /Info, None if the trailer has none.
+pub fn info(doc: &mut Document) -> Result<Option<DocumentInfo>> {
+    let dict = match doc.trailer_entry("Info")? {
+        Some((_, dict @ DictNode::Dict(_))) => dict,
+        _ => return Ok(None),
+    };
+    let mut text = |key| -> Result<Option<String>> {
+        Ok(dict.get_resolved(doc, key)?.as_ref().and_then(DictNode::as_text))
+    };
+    let mut info = DocumentInfo {
+        title: text("Title")?,
+        author: text("Author")?,
+        subject: text("Subject")?,
+        keywords: text("Keywords")?,
+        creator: text("Creator")?,
+        producer: text("Producer")?,
+        ..DocumentInfo::default()
+    };
+    info.creation_date = text("CreationDate")?.as_deref().and_then(PdfDate::parse);
+    info.mod_date = text("ModDate")?.as_deref().and_then(PdfDate::parse);
+    Ok(Some(info))
+}
+
+#[test]
+fn test_dates() {
+    let date = PdfDate::parse("D:20230415093012+02'00'").unwrap();
+    assert_eq!(date, PdfDate { year: 2023, month: 4, day: 15, hour: 9, minute: 30, second: 12, utc_offset: Some(120) });
+    assert_eq!(date.to_string(), "2023-04-15T09:30:12+02:00");
+    assert_eq!(PdfDate::parse("D:1999").unwrap().to_string(), "1999-01-01T00:00:00");
+    assert_eq!(PdfDate::parse("20011231235959Z").unwrap().utc_offset, Some(0));
+    assert_eq!(PdfDate::parse("D:20011231235959-0530").unwrap().utc_offset, Some(-330));
+    assert_eq!(PdfDate::parse("D:20011231235959-05'").unwrap().to_string(), "2001-12-31T23:59:59-05:00");
+    assert!(PdfDate::parse("Tuesday").is_none());
+    assert!(PdfDate::parse("D:20231345").is_none());
+    assert!(PdfDate::parse("D:20230101 garbage").is_none());
+
+    assert_eq!(date.to_pdf(), "D:20230415093012+02'00'");
+    assert_eq!(PdfDate::from_unix(0).to_pdf(), "D:19700101000000Z");
+    assert_eq!(PdfDate::from_unix(951782400 + 3661).to_string(), "2000-02-29T01:01:01Z");
+    assert_eq!(PdfDate::from_unix(-1).to_string(), "1969-12-31T23:59:59Z");
+}
+
+#[test]
+fn test_text_strings() {
+    assert_eq!(DictNode::LiteralString(b"caf\xe9".to_vec()).as_text().as_deref(), Some("café"));
+    assert_eq!(DictNode::HexString(b"\xfe\xff\x00H\x00i".to_vec()).as_text().as_deref(), Some("Hi"));
+    assert_eq!(DictNode::Int(3).as_text(), None);
+
+    let info = DocumentInfo { title: Some("A Title".to_string()), creation_date: PdfDate::parse("D:2020"), ..DocumentInfo::default() };
+    assert_eq!(info.to_string(), "title: A Title\ncreated: 2020-01-01T00:00:00\n");
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use graphics::{Matrix, IDENTITY};
+use merge::Copier;
+use writer::Writer;
+use pages::{self, Page};
+use provenance::Provenance;
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Stationery: the first page of a template drawn under (or over) every
+ * page of a document, the way letterhead paper goes through a printer.
+ *
+ *   letterhead::letterhead(Path::new("letter.pdf"), Path::new("stationery.pdf"), Path::new("out.pdf"), Layer::Under)?;
+ *
+ * The template page is copied in once, as a form XObject with its content
+ * and resources, and each page gets a /Contents stream drawing it. Under
+ * the page that stream goes first. Over it, the page's own content is
+ * wrapped in q/Q first, so whatever state it leaves behind doesn't move or
+ * recolor the template.
+ *
+ * Pages the template doesn't fit are scaled to: uniformly, keeping its
+ * proportions, and centered. Both pages are taken as displayed, so the
+ * template comes out upright on rotated pages too.
+ *
+ * Like the other rewrites this is an incremental update: the changed pages
+ * and the new objects are appended after the original bytes. Pages written
+ * directly into their parent's /Kids have no object of their own to update
+ * and are left alone. Encrypted documents and templates are refused.
+ *
+ * The returned Provenance has the document's objects kept as they were and
+ * the template's copied ones, the drawing streams are new.
+ */
+
+// What the template is drawn as in the pages' resources, with a number
+// after it if a page has an XObject by that name already.
+const RESOURCE_NAME: &str = "Letterhead";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Layer {
+    Under,
+    Over,
+}
+
+pub fn letterhead(input: &Path, template_path: &Path, output: &Path, layer: Layer) -> Result<Provenance> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options.clone())?;
+    let mut stationery = Document::open(template_path, options)?;
+    let template = match pages::pages(&mut stationery)?.into_iter().next() {
+        Some(page) => page,
+        None => return Err(PdfError::Syntax { what: "template page", position: 0 }),
+    };
+
+    let xref = doc.xref.clone();
+    let mut provenance = Provenance::new();
+    let file = provenance.add_file(input);
+    provenance.keep(file, &xref);
+    let mut out = Writer::appending(fs::read(input)?, xref);
+    let form_id = out.reserve();
+    let form = form(&mut stationery, &template, form_id, &mut out, &mut provenance, template_path)?;
+    out.stream(form_id, &form.0, &form.1)?;
+
+    // "q" before a page's own content, shared by every page
+    let save_id = match layer {
+        Layer::Over => {
+            let id = out.reserve();
+            out.stream(id, &stream_dict(2), b"q\n")?;
+            Some(id)
+        },
+        Layer::Under => None,
+    };
+    let mut drawn = HashMap::new();
+    for page in pages::pages(&mut doc)? {
+        let id = match page.id {
+            Some(id) => id,
+            None => continue,
+        };
+        let mut resources = page.resources.as_dict().cloned().unwrap_or_default();
+        let mut xobjects = match resources.get("XObject") {
+            Some(xobjects) => doc.resolve(xobjects)?.as_dict().cloned().unwrap_or_default(),
+            None => HashMap::new(),
+        };
+        let name = resource_name(&xobjects);
+        xobjects.insert(name.clone(), DictNode::reference(form_id));
+        resources.insert("XObject".to_string(), DictNode::Dict(xobjects));
+
+        // pages of the same size and name share the stream drawing it
+        let content = draw(placement(&template, &page), &name, layer);
+        let draw_id = match drawn.get(&content) {
+            Some(&id) => id,
+            None => {
+                let id = out.reserve();
+                out.stream(id, &stream_dict(content.len()), content.as_bytes())?;
+                drawn.insert(content, id);
+                id
+            },
+        };
+        let existing = pages::content_streams(&mut doc, &page)?;
+        let mut dict = page.dict.as_dict().cloned().unwrap_or_default();
+        dict.insert("Contents".to_string(), DictNode::Array(stacked(existing, draw_id, save_id, layer)));
+        dict.insert("Resources".to_string(), DictNode::Dict(resources));
+        out.object(id, &DictNode::Dict(dict))?;
+    }
+
+    let (mut data, xref) = (out.data, out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(provenance)
+}
+
+// The template page as a form XObject numbered `id`: its dictionary and its
+// content, copying its resources into `out` and recording where they came
+// from.
+fn form(doc: &mut Document, page: &Page, id: u64, out: &mut Writer, provenance: &mut Provenance, path: &Path) -> Result<(DictNode, Vec<u8>)> {
+    let content = pages::content(doc, page)?;
+    let mut copier = Copier::new(doc);
+    if let Some(source) = page.id {
+        copier.ids.insert(source, id);
+    }
+    let resources = copier.renumber(&page.resources, out);
+    copier.copy_pending(out)?;
+    copier.record(provenance, path);
+    let [llx, lly, urx, ury] = page.crop_box;
+    let dict = HashMap::from([
+        ("Type".to_string(), DictNode::name("XObject")),
+        ("Subtype".to_string(), DictNode::name("Form")),
+        ("BBox".to_string(), DictNode::Array([llx, lly, urx, ury].iter().map(|&value| DictNode::Float(value)).collect())),
+        ("Resources".to_string(), resources),
+        ("Length".to_string(), DictNode::Int(content.len() as i64)),
+    ]);
+    Ok((DictNode::Dict(dict), content))
+}
+
+// Where the template goes on `page`: from its own space to upright, scaled
+// and centered on the page as displayed, then back to the page's space.
+fn placement(template: &Page, page: &Page) -> Matrix {
+    let (template_width, template_height) = template.display_size();
+    let (width, height) = page.display_size();
+    let scale = (width / template_width).min(height / template_height);
+    let scale = if scale.is_finite() && scale > 0.0 { scale } else { 1.0 };
+    let fit = Matrix::new(scale, 0.0, 0.0, scale, (width - template_width * scale) / 2.0, (height - template_height * scale) / 2.0);
+    let back = page.display_matrix().inverse().unwrap_or(IDENTITY);
+    template.display_matrix() * fit * back
+}
+
+// The content drawing the template with `matrix`. Over the page, it first
+// restores the state from before the page's content.
+fn draw(matrix: Matrix, name: &str, layer: Layer) -> String {
+    let numbers: Vec<String> = [matrix.a, matrix.b, matrix.c, matrix.d, matrix.e, matrix.f].iter().map(|&value| number(value)).collect();
+    let restore = if layer == Layer::Over { "\nQ " } else { "" };
+    format!("{}q {} cm {} Do Q\n", restore, numbers.join(" "), DictNode::name(name))
+}
+
+// The page's content streams, with the ones drawing the template added.
+fn stacked(existing: Vec<DictNode>, draw_id: u64, save_id: Option<u64>, layer: Layer) -> Vec<DictNode> {
+    let mut contents = Vec::new();
+    match layer {
+        Layer::Under => contents.push(DictNode::reference(draw_id)),
+        Layer::Over => contents.extend(save_id.map(DictNode::reference)),
+    }
+    contents.extend(existing);
+    if layer == Layer::Over {
+        contents.push(DictNode::reference(draw_id));
+    }
+    contents
+}
+
+fn resource_name(xobjects: &HashMap<String, DictNode>) -> String {
+    let mut name = RESOURCE_NAME.to_string();
+    let mut count = 1;
+    while xobjects.contains_key(&name) {
+        name = format!("{}{}", RESOURCE_NAME, count);
+        count += 1;
+    }
+    name
+}
+
+fn stream_dict(length: usize) -> DictNode {
+    DictNode::Dict(HashMap::from([("Length".to_string(), DictNode::Int(length as i64))]))
+}
+
+// Four decimals, scales need more than positions do.
+fn number(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[test]
+fn test_placement() {
+    use fixtures::page;
+
+    let letter = page([0.0, 0.0, 612.0, 792.0], 0);
+    // the same size, nothing to do
+    assert_eq!(draw(placement(&letter, &letter), "Letterhead", Layer::Under), "q 1 0 0 1 0 0 cm /Letterhead Do Q\n");
+    // half the size, and twice as wide: scaled down, and centered across
+    let half = page([0.0, 0.0, 306.0, 396.0], 0);
+    assert_eq!(placement(&letter, &half).apply(612.0, 792.0), (306.0, 396.0));
+    let wide = page([0.0, 0.0, 1224.0, 792.0], 0);
+    assert_eq!(placement(&letter, &wide).apply(0.0, 0.0), (306.0, 0.0));
+
+    // a landscape page turned upright: the template's top left corner goes
+    // where the page displays its top left, its origin
+    let turned = page([0.0, 0.0, 792.0, 612.0], 90);
+    let matrix = placement(&letter, &turned);
+    assert_eq!(matrix.apply(0.0, 792.0), (0.0, 0.0));
+    assert_eq!(matrix.apply(612.0, 0.0), (792.0, 612.0));
+    assert_eq!(draw(matrix, "Letterhead1", Layer::Over), "\nQ q 0 1 -1 0 792 0 cm /Letterhead1 Do Q\n");
+}
+
+#[test]
+fn test_stacked() {
+    let existing = vec![DictNode::reference(5), DictNode::reference(6)];
+    assert_eq!(stacked(existing.clone(), 20, None, Layer::Under), vec![DictNode::reference(20), DictNode::reference(5), DictNode::reference(6)]);
+    assert_eq!(stacked(existing, 20, Some(21), Layer::Over), vec![DictNode::reference(21), DictNode::reference(5), DictNode::reference(6), DictNode::reference(20)]);
+    let taken = hashmap!{ "Letterhead".to_string() => DictNode::Null, "Letterhead1".to_string() => DictNode::Null };
+    assert_eq!(resource_name(&taken), "Letterhead2");
+}
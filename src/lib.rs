@@ -0,0 +1,59 @@
+#![recursion_limit = "80"]
+
+extern crate inflate;
+#[cfg(feature = "fast-inflate")] extern crate miniz_oxide;
+
+#[macro_use] extern crate pest;
+#[cfg(test)] #[macro_use] extern crate maplit;
+
+// the parsing core has to survive any input, see src/audit
+#[cfg(test)] mod audit;
+// small PDFs generated for tests, see src/fixtures
+#[cfg(test)] mod fixtures;
+pub mod annotations;
+pub mod attach;
+pub mod citations;
+pub mod compress;
+pub mod crypt;
+pub mod document;
+pub mod error;
+pub mod features;
+pub mod figures;
+pub mod filters;
+pub mod fonts;
+pub mod forms;
+pub mod graphics;
+pub mod hidden;
+pub mod images;
+pub mod index_cache;
+pub mod info;
+pub mod letterhead;
+pub mod math;
+pub mod merge;
+pub mod metrics;
+pub mod naming;
+pub mod normalize;
+pub mod object_streams;
+pub mod page_tree;
+pub mod pages;
+pub mod parsers;
+pub mod patch;
+pub mod pipeline;
+pub mod provenance;
+pub mod recolor;
+pub mod resize;
+pub mod salvage;
+pub mod sandbox;
+pub mod signatures;
+pub mod spill;
+pub mod streams;
+pub mod text;
+pub mod timings;
+pub mod transcode;
+pub mod trees;
+pub mod validate;
+pub mod versions;
+pub mod writer;
+
+pub use document::{Document, DocumentPolicy, DuplicatePolicy, Options, Refresh, Warning};
+pub use error::{PdfError, Result};
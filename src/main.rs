@@ -1,145 +1,1690 @@
-#![recursion_limit = "80"]
+extern crate nails_pdf;
 
-extern crate inflate;
-
-use inflate::inflate_bytes;
-use pest::prelude::*;
+use nails_pdf::annotations;
+use nails_pdf::attach::{self, Attachment};
+use nails_pdf::figures;
+use nails_pdf::hidden;
+use nails_pdf::citations;
+use nails_pdf::compress;
+use nails_pdf::crypt::{Encryption, Permissions};
+use nails_pdf::fonts;
+use nails_pdf::fonts::embedding;
+use nails_pdf::fonts::standard::StandardFont;
+use nails_pdf::fonts::subset;
+use nails_pdf::images;
+use nails_pdf::info;
+use nails_pdf::letterhead::{self, Layer};
+use nails_pdf::math;
+use nails_pdf::merge::{self, MergeOptions, PageRanges};
+use nails_pdf::forms::{calculation, fill};
+use nails_pdf::forms::interchange::{self, Format};
+use nails_pdf::naming::{NameFields, OutputNamer, DEFAULT_TEMPLATE};
+use nails_pdf::normalize;
+use nails_pdf::page_tree;
+use nails_pdf::patch::{self, PatchOp};
+use nails_pdf::pages;
+use nails_pdf::pages::boxes::{self, PageBox};
+use nails_pdf::pages::coords::Origin;
+use nails_pdf::provenance::Provenance;
+use nails_pdf::recolor::{self, Target};
+use nails_pdf::resize;
+use nails_pdf::salvage;
+use nails_pdf::sandbox::{self, Sandbox};
+use nails_pdf::signatures;
+use nails_pdf::text;
+use nails_pdf::text::accuracy::{self, Accuracy};
+use nails_pdf::parsers::xref::XRefEntry;
+use nails_pdf::timings::{Phase, Timings};
+use nails_pdf::transcode::{transcode, XRefFormat};
+use nails_pdf::validate::{validate, Profile};
+use nails_pdf::versions;
+use nails_pdf::writer::{self, Numbering, SaveOptions};
+use nails_pdf::{filters, pipeline};
+use nails_pdf::{Document, DocumentPolicy, DuplicatePolicy, Options, PdfError, Refresh};
 use std::collections::HashMap;
-use std::error::Error;
+use std::env;
+use std::fs;
 use std::fs::File;
-use std::io::BufReader;
-use std::io::SeekFrom;
-use std::io::prelude::*;
-use std::path::Path;
-use std::str::from_utf8;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+
+// This is the main function
+fn main() {
+    // a password can go with any command, for the encrypted files it opens
+    let (passwords, args): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--password="));
+    let password = passwords.last().and_then(|arg| arg.strip_prefix("--password=")).map(str::to_string);
+    let options = Options { password, ..Options::default() };
+    match args.first().map(|arg| arg.as_str()) {
+        Some("watch") => watch(&args[1..], options),
+        Some("check") => check(&args[1..]),
+        Some("transcode") => transcode_command(&args[1..]),
+        Some("retarget") => retarget_command(&args[1..]),
+        Some("rewrite") => rewrite_command(&args[1..], options),
+        Some("compress") => compress_command(&args[1..], options),
+        Some("rebalance") => rebalance_command(&args[1..]),
+        Some("blank") => blank_command(&args[1..]),
+        Some("resize") => resize_command(&args[1..]),
+        Some("normalize") => normalize_command(&args[1..]),
+        Some("recolor") => recolor_command(&args[1..]),
+        Some("merge") => merge_command(&args[1..]),
+        Some("split") => split_command(&args[1..]),
+        Some("delete-pages") => delete_pages_command(&args[1..], options),
+        Some("reorder") => reorder_command(&args[1..], options),
+        Some("rotate") => rotate_command(&args[1..], options),
+        Some("set-box") => set_box_command(&args[1..], options),
+        Some("letterhead") => letterhead_command(&args[1..]),
+        Some("attach") => attach_command(&args[1..]),
+        Some("fill") => fill_command(&args[1..]),
+        Some("export-fields") => export_fields_command(&args[1..], options),
+        Some("calculations") => calculations_command(&args[1..], options),
+        Some("pages") => pages_command(&args[1..], options),
+        Some("summary") => summary_command(&args[1..], options),
+        Some("info") => info_command(&args[1..], options),
+        Some("salvage") => salvage_command(&args[1..]),
+        Some("text") => text_command(&args[1..], options),
+        Some("accuracy") => accuracy_command(&args[1..], options),
+        Some("fonts") => fonts_command(&args[1..], options),
+        Some("unembed-fonts") => unembed_fonts_command(&args[1..]),
+        Some("subset-fonts") => subset_fonts_command(&args[1..]),
+        Some("diff") => diff_command(&args[1..], options),
+        Some("patch") => patch_command(&args[1..], options),
+        Some("embed-fonts") => embed_fonts_command(&args[1..]),
+        Some("figures") => figures_command(&args[1..], options),
+        Some("hidden") => hidden_command(&args[1..], options),
+        Some("images") => images_command(&args[1..], options),
+        Some("math") => math_command(&args[1..], options),
+        Some("citations") => citations_command(&args[1..], options),
+        Some("links") => links_command(&args[1..], options),
+        Some("signatures") => signatures_command(&args[1..], options),
+        Some("dump") => dump_command(&args[1..], options),
+        _ => dump_command(&args, options),
+    }
+}
+
+fn dump_command(args: &[String], options: Options) {
+    let mut lenient = false;
+    let mut scan = false;
+    let mut duplicates = DuplicatePolicy::default();
+    let mut spill_threshold = None;
+    let mut jobs = 1;
+    let mut show_timings = false;
+    let mut index_cache = false;
+    let mut policy = DocumentPolicy::default();
+    let mut output_dir = None;
+    let mut template = DEFAULT_TEMPLATE.to_string();
+    let mut path = "politics.pdf".to_string();
+
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        if arg == "--lenient" {
+            lenient = true;
+        } else if arg == "--scan" {
+            scan = true;
+        } else if let Some(policy) = arg.strip_prefix("--duplicates=") {
+            duplicates = match policy {
+                "first" => DuplicatePolicy::FirstWins,
+                "last" => DuplicatePolicy::LastWins,
+                "xref" => DuplicatePolicy::PreferXRef,
+                other => usage(&format!("unknown duplicate policy {}, expected first, last or xref", other)),
+            };
+        } else if let Some(bytes) = arg.strip_prefix("--spill-threshold=") {
+            spill_threshold = Some(bytes.parse().unwrap_or_else(|_| usage("--spill-threshold needs a number of bytes")));
+        } else if let Some(count) = arg.strip_prefix("--jobs=") {
+            jobs = count.parse().unwrap_or_else(|_| usage("--jobs needs a number of threads"));
+        } else if arg == "-j" {
+            jobs = pipeline::default_workers();
+        } else if arg == "--index-cache" {
+            index_cache = true;
+        } else if let Some(count) = arg.strip_prefix("--max-pages=") {
+            policy.max_pages = Some(count.parse().unwrap_or_else(|_| usage("--max-pages needs a number of pages")));
+        } else if arg == "--no-encrypted" {
+            policy.allow_encrypted = false;
+        } else if arg == "--no-images" {
+            policy.decode_images = false;
+        } else if arg == "--external-references" {
+            policy.follow_external_references = true;
+        } else if arg == "--timings" {
+            show_timings = true;
+        } else if arg == "-o" {
+            output_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage("-o needs a directory"))));
+        } else if arg == "--name-template" {
+            template = args.next().unwrap_or_else(|| usage("--name-template needs a template"));
+        } else {
+            path = arg;
+        }
+    }
+
+    let options = Options { lenient, duplicates, spill_threshold, index_cache, policy, ..options };
+    let mut namer = OutputNamer::new(&template);
+    let output = output_dir.as_ref().map(|dir| (dir.as_path(), &mut namer));
+    if let Err(err) = dump(Path::new(&path), options, scan, jobs, show_timings, output) {
+        println!("couldn't read {}: {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn usage(message: &str) -> ! {
+    println!("{}", message);
+    println!("usage: nails-pdf [dump] [--lenient | --scan] [--duplicates=first|last|xref] [--spill-threshold=BYTES] [-j | --jobs=N] [--timings] [--index-cache] [--max-pages=N] [--no-encrypted] [--no-images] [--external-references] [-o DIR [--name-template TEMPLATE]] FILE");
+    println!("       nails-pdf check [--full] FILE");
+    println!("       nails-pdf transcode --xref-table|--xref-stream FILE OUTPUT");
+    println!("       nails-pdf retarget --version=M.m FILE OUTPUT");
+    println!("       nails-pdf rewrite [--numbering=preserve|depth-first|by-type] [--compress] [--linearize] [--user-password=PASSWORD] [--owner-password=PASSWORD] [--allow=print,copy,...] FILE OUTPUT");
+    println!("       nails-pdf compress [--jobs=N] FILE OUTPUT");
+    println!("       nails-pdf rebalance [--fanout=N] FILE OUTPUT");
+    println!("       nails-pdf blank --before=N[,N...] | --recto=N[,N...] FILE OUTPUT");
+    println!("       nails-pdf resize --size=letter|legal|tabloid|a3|a4|a5|WxH FILE OUTPUT");
+    println!("       nails-pdf normalize [--precision=N] FILE OUTPUT");
+    println!("       nails-pdf recolor --gray|--cmyk FILE OUTPUT");
+    println!("       nails-pdf merge [--outline] [--toc] [--subset-fonts] [--provenance] FILE... OUTPUT");
+    println!("       nails-pdf split FILE RANGES -o OUTPUT");
+    println!("       nails-pdf delete-pages FILE RANGES -o OUTPUT");
+    println!("       nails-pdf reorder FILE ORDER -o OUTPUT");
+    println!("       nails-pdf rotate [--pages=RANGES] --by=DEGREES|--to=DEGREES FILE OUTPUT");
+    println!("       nails-pdf set-box [--pages=RANGES] --box=media|crop|bleed|trim|art --rect=LLX,LLY,URX,URY FILE OUTPUT");
+    println!("       nails-pdf letterhead --under|--over TEMPLATE FILE -o OUTPUT [--provenance]");
+    println!("       nails-pdf attach [--description=TEXT] FILE ATTACHMENT... -o OUTPUT");
+    println!("       nails-pdf fill [--flatten] [--data=FDF|XFDF] [--set=NAME=VALUE...] FILE OUTPUT");
+    println!("       nails-pdf export-fields FILE OUTPUT.fdf|OUTPUT.xfdf");
+    println!("       nails-pdf calculations FILE");
+    println!("       nails-pdf pages FILE");
+    println!("       nails-pdf info FILE");
+    println!("       nails-pdf salvage [-o DIR [--name-template TEMPLATE]] FILE");
+    println!("       nails-pdf summary [--sandbox [--timeout=SECONDS]] [--lenient] [--max-pages=N] FILE");
+    println!("       nails-pdf text [--page=N] [--json [--raw | --top-left] | --coverage] FILE");
+    println!("       nails-pdf fonts [-o DIR [--name-template TEMPLATE]] FILE");
+    println!("       nails-pdf unembed-fonts FILE OUTPUT");
+    println!("       nails-pdf subset-fonts FILE OUTPUT");
+    println!("       nails-pdf diff OLD NEW");
+    println!("       nails-pdf patch BASE OLD NEW OUTPUT");
+    println!("       nails-pdf embed-fonts --font=STANDARD_NAME=FONT_FILE... FILE OUTPUT");
+    println!("       nails-pdf accuracy FILE REFERENCE.txt | DIR");
+    println!("       nails-pdf figures [--top-left] FILE");
+    println!("       nails-pdf hidden FILE");
+    println!("       nails-pdf images [--jobs=N] [-o DIR [--name-template TEMPLATE]] FILE");
+    println!("       nails-pdf math FILE");
+    println!("       nails-pdf citations FILE");
+    println!("       nails-pdf links [--all] [--top-left] FILE");
+    println!("       nails-pdf signatures FILE");
+    println!("       nails-pdf watch FILE -- SUBCOMMAND [ARGS...]");
+    println!("any of them takes --password=PASSWORD for encrypted files");
+    process::exit(2);
+}
 
-#[macro_use] extern crate pest;
-#[macro_use] extern crate maplit;
+// Prints every object in the file, and writes decoded streams out to
+// `output` if given. With `scan`, the file's trailer and xref are ignored
+// and rebuilt from what's in it, see Document::open_by_scanning.
+fn dump(path: &Path, options: Options, scan: bool, jobs: usize, show_timings: bool,
+        mut output: Option<(&Path, &mut OutputNamer)>) -> Result<(), PdfError> {
+    let mut doc = if scan { Document::open_by_scanning(path, options)? } else { Document::open(path, options)? };
 
-mod parsers;
+    if let Some(info) = info::info(&mut doc)? {
+        print!("Info:\n{}", info);
+    }
+    println!("Xref:\n{:?}", doc.xref);
+
+    if let Some((dir, _)) = output {
+        fs::create_dir_all(dir)?;
+    }
+
+    let parallel = jobs > 1 && output.is_some();
+    let mut pending = Vec::new();
+    let mut written = 0;
+    let entries: Vec<(usize, XRefEntry)> = doc.xref.entries().map(|(id, entry)| (id, entry.clone())).collect();
+    for (index, entry) in entries {
+        if !entry.is_free {
+            match entry.object_stream {
+                Some(stream) => println!("cat XObject {} in object stream {}", index, stream),
+                None => println!("cat XObject {} at offset {}", index, entry.offset),
+            }
+            let object = doc.object(&entry)?;
+            println!("Object: {:?}", object);
+            if parallel {
+                pending.push((index as u64, entry));
+                continue;
+            }
+            if let (Some((dir, ref mut namer)), true) = (&mut output, images::is_image(&object)) {
+                if let Some(image) = doc.encoded_image(&entry)? {
+                    let fields = NameFields { index: written, object: index as u64, ext: image.codec.extension(), ..Default::default() };
+                    let file = namer.path(dir, &fields);
+                    doc.timings_mut().time(Phase::Write, || fs::write(&file, &image.data))?;
+                    println!("Image {}x{} {:?} written to {}",
+                             image.width.unwrap_or(0), image.height.unwrap_or(0), image.codec, file.display());
+                    written += 1;
+                    continue;
+                }
+            }
+            if let Some((_, mut data)) = doc.decoded_stream(&entry)? {
+                match output {
+                    Some((dir, ref mut namer)) => {
+                        let fields = NameFields { index: written, object: index as u64, ext: "bin", ..Default::default() };
+                        let file = namer.path(dir, &fields);
+                        doc.timings_mut().time(Phase::Write, || -> io::Result<u64> {
+                            io::copy(&mut data, &mut File::create(&file)?)
+                        })?;
+                        println!("Stream ({} bytes) written to {}", data.len(), file.display());
+                        written += 1;
+                    },
+                    None => {
+                        let mut bytes = Vec::new();
+                        data.read_to_end(&mut bytes)?;
+                        println!("Stream ({} bytes):\n{}", bytes.len(), String::from_utf8_lossy(&bytes));
+                    },
+                }
+            }
+        }
+    }
+
+    if let (Some((dir, namer)), true) = (output, parallel) {
+        let timings = write_parallel(&mut doc, &pending, jobs, dir, namer)?;
+        doc.timings_mut().merge(&timings);
+    }
+
+    if doc.is_truncated() {
+        println!("Warning: document is truncated");
+    }
+    for warning in doc.warnings() {
+        println!("Warning: {:?}", warning);
+    }
+    if show_timings {
+        println!("Timings:\n{}", doc.timings());
+    }
+    Ok(())
+}
+
+// Decodes and writes out the streams among `entries` on `jobs` threads.
+// Decoding time is the sum over all workers, not wall clock time.
+fn write_parallel(doc: &mut Document, entries: &[(u64, XRefEntry)], jobs: usize, dir: &Path, namer: &mut OutputNamer) -> Result<Timings, PdfError> {
+    let mut timings = Timings::default();
+    pipeline::decode_parallel(doc.stream_jobs(entries), jobs, |decoded| {
+        timings.add(Phase::Decode, decoded.elapsed);
+        let data = decoded.data?;
+        let ext = filters::image_codec(&decoded.dict).map_or("bin", |codec| codec.extension());
+        let fields = NameFields { index: decoded.index, object: decoded.id, ext, ..Default::default() };
+        let file = namer.path(dir, &fields);
+        timings.time(Phase::Write, || fs::write(&file, &data))?;
+        println!("Stream {} ({} bytes) written to {}", decoded.id, data.len(), file.display());
+        Ok(())
+    })?;
+    Ok(timings)
+}
 
-const CHUNK_SIZE: i64 = 10240;
 
-fn parse_xref(file: &mut File, offset: u64) -> parsers::xref::XRefTable {
-    match file.seek(SeekFrom::Start(offset)) {
-        Err(_) => panic!("couldn't seek to xref"),
-        Ok(_) => (),
+// Validates the file's structure, exits with 1 if it has errors.
+fn check(args: &[String]) {
+    let mut profile = Profile::Structure;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--full" => profile = Profile::Full,
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("check needs a file"));
+    let report = validate(Path::new(path), profile);
+    println!("{}", report);
+    if !report.is_valid() {
+        process::exit(1);
+    }
+}
+
+// Rewrites FILE to OUTPUT with the other kind of cross-reference section.
+fn transcode_command(args: &[String]) {
+    let mut format = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--xref-table" => format = Some(XRefFormat::Table),
+            "--xref-stream" => format = Some(XRefFormat::Stream),
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    let format = format.unwrap_or_else(|| usage("transcode needs --xref-table or --xref-stream"));
+    if paths.len() != 2 {
+        usage("transcode needs a file and an output file");
+    }
+    if let Err(err) = transcode(paths[0], paths[1], format) {
+        println!("couldn't transcode {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
+
+// Rewrites FILE to OUTPUT for another PDF version, listing the features
+// that couldn't be converted.
+fn retarget_command(args: &[String]) {
+    let mut version = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--version=") {
+            Some(text) => version = Some(versions::parse_version(text).unwrap_or_else(|| usage("--version needs a version like 1.4"))),
+            None => paths.push(Path::new(arg)),
+        }
+    }
+    let version = version.unwrap_or_else(|| usage("retarget needs --version"));
+    if paths.len() != 2 {
+        usage("retarget needs a file and an output file");
+    }
+    match versions::retarget(paths[0], paths[1], version) {
+        Ok(incompatible) => {
+            for found in incompatible {
+                println!("Warning: {}", found);
+            }
+        },
+        Err(err) => {
+            println!("couldn't retarget {}: {}", paths[0].display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Writes FILE out anew as OUTPUT, one revision with a single xref table,
+// its objects numbered as --numbering says and its streams recompressed
+// with --compress.
+fn rewrite_command(args: &[String], options: Options) {
+    let mut save_options = SaveOptions::default();
+    let mut paths = Vec::new();
+    for arg in args {
+        if arg == "--compress" {
+            save_options.compress = true;
+            continue;
+        }
+        if arg == "--linearize" {
+            save_options.linearize = true;
+            continue;
+        }
+        if let Some(password) = arg.strip_prefix("--user-password=") {
+            save_options.encryption.get_or_insert_with(Encryption::default).user_password = password.as_bytes().to_vec();
+            continue;
+        }
+        if let Some(password) = arg.strip_prefix("--owner-password=") {
+            save_options.encryption.get_or_insert_with(Encryption::default).owner_password = password.as_bytes().to_vec();
+            continue;
+        }
+        if let Some(allowed) = arg.strip_prefix("--allow=") {
+            save_options.encryption.get_or_insert_with(Encryption::default).permissions = permissions_arg(allowed);
+            continue;
+        }
+        match arg.strip_prefix("--numbering=") {
+            Some(numbering) => save_options.numbering = match numbering {
+                "preserve" => Numbering::Preserve,
+                "depth-first" => Numbering::DepthFirst,
+                "by-type" => Numbering::ByType,
+                other => usage(&format!("unknown numbering {}, expected preserve, depth-first or by-type", other)),
+            },
+            None => paths.push(Path::new(arg)),
+        }
+    }
+    if paths.len() != 2 {
+        usage("rewrite needs a file and an output file");
+    }
+    let result = Document::open(paths[0], options).and_then(|mut doc| writer::save(&mut doc, paths[1], &save_options));
+    if let Err(err) = result {
+        println!("couldn't rewrite {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
+
+// What --allow=print,copy allows, the names Permissions::names gives with
+// dashes for spaces, or "none".
+fn permissions_arg(list: &str) -> Permissions {
+    let mut permissions = Permissions::from_bits(-3904, 3);
+    for name in list.split(',').filter(|name| !name.is_empty() && *name != "none") {
+        let allowed = match name {
+            "print" => &mut permissions.print,
+            "print-high-quality" => &mut permissions.print_high_quality,
+            "modify" => &mut permissions.modify,
+            "copy" => &mut permissions.copy,
+            "annotate" => &mut permissions.annotate,
+            "fill-forms" => &mut permissions.fill_forms,
+            "extract-for-accessibility" => &mut permissions.extract_for_accessibility,
+            "assemble" => &mut permissions.assemble,
+            other => usage(&format!("unknown permission {}", other)),
+        };
+        *allowed = true;
+    }
+    permissions
+}
+
+// Writes FILE out anew as OUTPUT with its streams recompressed, and says
+// how much that saved.
+fn compress_command(args: &[String], options: Options) {
+    let mut jobs = pipeline::default_workers();
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--jobs=") {
+            Some(count) => jobs = count.parse().unwrap_or_else(|_| usage("--jobs needs a number of threads")),
+            None => files.push(Path::new(arg)),
+        }
+    }
+    let (input, output) = match files[..] {
+        [input, output] => (input, output),
+        _ => usage("compress needs a file and an output file"),
     };
+    let result = Document::open(input, options).and_then(|mut doc| {
+        let report = compress::compress(&mut doc, jobs)?;
+        writer::save(&mut doc, output, &SaveOptions::default())?;
+        Ok(report)
+    });
+    match result {
+        Ok(report) => {
+            println!("{}", report);
+            if let (Ok(before), Ok(after)) = (fs::metadata(input), fs::metadata(output)) {
+                println!("file {} bytes to {}", before.len(), after.len());
+            }
+        },
+        Err(err) => {
+            println!("couldn't compress {}: {}", input.display(), err);
+            process::exit(1);
+        },
+    }
+}
 
-    let newline = "\n".to_string();
-    let file_reader = BufReader::new(file);
-    let mut xref_str: String = "".to_owned();
-    for line in file_reader.lines() {
-        let unwrapped = line.unwrap();
-        xref_str.push_str(&unwrapped);
-        xref_str.push_str(&newline);
-        if unwrapped == "trailer" {
-            break;
+// Rewrites FILE to OUTPUT with a page tree of bounded fanout.
+fn rebalance_command(args: &[String]) {
+    let mut fanout = page_tree::DEFAULT_FANOUT;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--fanout=") {
+            Some(count) => fanout = count.parse().unwrap_or_else(|_| usage("--fanout needs a number of kids per node")),
+            None => paths.push(Path::new(arg)),
         }
     }
+    if paths.len() != 2 {
+        usage("rebalance needs a file and an output file");
+    }
+    if let Err(err) = page_tree::rebalance(paths[0], paths[1], fanout) {
+        println!("couldn't rebalance {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
 
-    let mut xref_parser = parsers::xref::Rdp::new(StringInput::new(&xref_str));
-    xref_parser.xref();
-    return xref_parser.parse();
+// `merge FILE... OUTPUT` writes the files' pages one after the other to
+// OUTPUT. --provenance also writes OUTPUT.provenance.json, where each
+// object came from.
+fn merge_command(args: &[String]) {
+    let mut options = MergeOptions::default();
+    let mut sidecar = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--outline" => options.outline = true,
+            "--toc" => options.toc = true,
+            "--subset-fonts" => options.subset_fonts = true,
+            "--provenance" => sidecar = true,
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    let (output, inputs) = match paths.split_last() {
+        Some((output, inputs)) if !inputs.is_empty() => (output, inputs),
+        _ => usage("merge needs at least one file and an output file"),
+    };
+    let merged = merge::merge(inputs, output, &options);
+    if let Err(err) = merged.and_then(|provenance| write_provenance(&provenance, output, sidecar)) {
+        println!("couldn't merge into {}: {}", output.display(), err);
+        process::exit(1);
+    }
+}
+
+// Copies the pages of FILE that RANGES picks, "3-7" or "1,4-", into OUTPUT.
+fn split_command(args: &[String]) {
+    let (path, ranges, output) = match args {
+        [path, ranges, flag, output] if flag == "-o" => (Path::new(path), ranges, Path::new(output)),
+        _ => usage("split needs a file, page ranges and -o OUTPUT"),
+    };
+    let ranges = PageRanges::parse(ranges).unwrap_or_else(|| usage("split needs page ranges like 3-7 or 1,4-"));
+    if let Err(err) = merge::split(path, &ranges, output) {
+        println!("couldn't split {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+// `delete-pages FILE RANGES -o OUTPUT` leaves the pages in RANGES out.
+fn delete_pages_command(args: &[String], options: Options) {
+    let (path, ranges, output) = page_edit_args(args, "delete-pages");
+    edit_pages(path, options, output, "delete pages from", |doc| {
+        let indexes = page_indexes(doc, &ranges)?;
+        page_tree::delete_pages(doc, &indexes).map(|_| ())
+    });
 }
 
-fn get_doc_metadata(file: &mut File) -> (parsers::cos::DictNode, parsers::xref::XRefTable) {
-    let mut buffer = Vec::new();
-    let mut trailer = Vec::new();
+// `reorder FILE ORDER -o OUTPUT` puts the pages in the order given, as page
+// ranges naming every page once: 3,1-2,4- moves the third page to the front.
+fn reorder_command(args: &[String], options: Options) {
+    let (path, ranges, output) = page_edit_args(args, "reorder");
+    edit_pages(path, options, output, "reorder", |doc| {
+        let order = page_indexes(doc, &ranges)?;
+        page_tree::reorder_pages(doc, &order)
+    });
+}
 
-    match file.seek(SeekFrom::End(-CHUNK_SIZE)) {
-        Err(_) => panic!("couldn't seek to eof"),
-        Ok(_) => (),
+fn page_edit_args<'a>(args: &'a [String], command: &str) -> (&'a Path, PageRanges, &'a Path) {
+    match args {
+        [path, ranges, flag, output] if flag == "-o" => {
+            let ranges = PageRanges::parse(ranges).unwrap_or_else(|| usage(&format!("{} needs page ranges like 3-7 or 1,4-", command)));
+            (Path::new(path), ranges, Path::new(output))
+        },
+        _ => usage(&format!("{} needs a file, page ranges and -o OUTPUT", command)),
     }
-    file.take(CHUNK_SIZE as u64).read_to_end(&mut buffer).unwrap();
+}
+
+fn page_indexes(doc: &mut Document, ranges: &PageRanges) -> Result<Vec<usize>, PdfError> {
+    let count = pages::pages(doc)?.len();
+    ranges.indexes(count).ok_or_else(|| PdfError::InvalidArgument(format!("page range past the last of {} pages", count)))
+}
 
-    let mut found_xref = false;
-    let mut found_trailer = false;
-    let mut xref_offset= 0;
-    let newline = '\n' as u8;
+// Edits the pages of `path` with `edit` and writes the result to `output`.
+fn edit_pages<F: FnOnce(&mut Document) -> Result<(), PdfError>>(path: &Path, options: Options, output: &Path, what: &str, edit: F) {
+    let result = Document::open(path, options)
+        .and_then(|mut doc| edit(&mut doc).and_then(|_| writer::save(&mut doc, output, &SaveOptions::default())));
+    if let Err(err) = result {
+        println!("couldn't {} {}: {}", what, path.display(), err);
+        process::exit(1);
+    }
+}
 
-    for line in buffer.split(|byte| *byte == newline) {
-        if line == "trailer".as_bytes() {
-            found_trailer = true;
+// `rotate --by=90 FILE OUTPUT` turns the pages a further 90 degrees
+// clockwise, `--to=90` sets their rotation instead. --pages picks which,
+// all of them by default. Saved as an incremental update.
+fn rotate_command(args: &[String], options: Options) {
+    let mut rotation = None;
+    let mut ranges = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        let degrees = |value: &str| value.parse::<i64>().unwrap_or_else(|_| usage("--by and --to need degrees"));
+        if let Some(value) = arg.strip_prefix("--by=") {
+            rotation = Some((true, degrees(value)));
+        } else if let Some(value) = arg.strip_prefix("--to=") {
+            rotation = Some((false, degrees(value)));
+        } else if let Some(value) = arg.strip_prefix("--pages=") {
+            ranges = Some(PageRanges::parse(value).unwrap_or_else(|| usage("--pages needs page ranges like 3-7 or 1,4-")));
+        } else {
+            paths.push(Path::new(arg));
         }
-        else if line == "startxref".as_bytes() {
-            found_xref = true;
-        } else if found_trailer && !found_xref {
-            trailer.extend_from_slice(line);
+    }
+    let (by, degrees) = rotation.unwrap_or_else(|| usage("rotate needs --by or --to"));
+    if paths.len() != 2 {
+        usage("rotate needs a file and an output file");
+    }
+    update_pages(paths[0], options, paths[1], ranges, "rotate", |doc, page| if by {
+        boxes::rotate(doc, page, degrees)
+    } else {
+        boxes::set_rotation(doc, page, degrees)
+    });
+}
+
+// `set-box --box=crop --rect=LLX,LLY,URX,URY FILE OUTPUT` sets one of the
+// pages' boxes, in default user space. Saved as an incremental update.
+fn set_box_command(args: &[String], options: Options) {
+    let mut which = None;
+    let mut rect = None;
+    let mut ranges = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        if let Some(name) = arg.strip_prefix("--box=") {
+            which = Some(PageBox::parse(name).unwrap_or_else(|| usage("--box needs media, crop, bleed, trim or art")));
+        } else if let Some(values) = arg.strip_prefix("--rect=") {
+            let values: Vec<f64> = values.split(',').map(|value| value.trim().parse().ok()).collect::<Option<_>>()
+                .unwrap_or_default();
+            match *values.as_slice() {
+                [llx, lly, urx, ury] => rect = Some([llx, lly, urx, ury]),
+                _ => usage("--rect needs four numbers, LLX,LLY,URX,URY"),
+            }
+        } else if let Some(value) = arg.strip_prefix("--pages=") {
+            ranges = Some(PageRanges::parse(value).unwrap_or_else(|| usage("--pages needs page ranges like 3-7 or 1,4-")));
+        } else {
+            paths.push(Path::new(arg));
         }
-        else if found_xref {
-            let string = String::from_utf8(line.to_vec()).unwrap();
-            xref_offset = string.parse::<u64>().unwrap();
-            break;
+    }
+    let (which, rect) = match (which, rect) {
+        (Some(which), Some(rect)) => (which, rect),
+        _ => usage("set-box needs --box and --rect"),
+    };
+    if paths.len() != 2 {
+        usage("set-box needs a file and an output file");
+    }
+    update_pages(paths[0], options, paths[1], ranges, "set boxes in", |doc, page| boxes::set_box(doc, page, which, rect));
+}
+
+// Calls `update` on the pages `ranges` picks, every page without it, and
+// appends the changes to `path` as an incremental update in `output`.
+fn update_pages<F>(path: &Path, options: Options, output: &Path, ranges: Option<PageRanges>, what: &str, mut update: F)
+    where F: FnMut(&mut Document, &pages::Page) -> Result<(), PdfError> {
+    let result = Document::open(path, options).and_then(|mut doc| {
+        let pages = pages::pages(&mut doc)?;
+        let indexes = match ranges {
+            Some(ranges) => ranges.indexes(pages.len()).ok_or_else(|| PdfError::InvalidArgument(format!("page range past the last of {} pages", pages.len())))?,
+            None => (0..pages.len()).collect(),
+        };
+        for index in indexes {
+            update(&mut doc, &pages[index])?;
         }
+        writer::save_incremental(&mut doc, output)
+    });
+    if let Err(err) = result {
+        println!("couldn't {} {}: {}", what, path.display(), err);
+        process::exit(1);
     }
+}
 
-    let trailer_str = String::from_utf8(trailer).unwrap();
-    let mut trailer_parser = parsers::cos::Rdp::new(StringInput::new(&trailer_str));
-    trailer_parser.node();
-    let trailer = trailer_parser.parse();
-    let xref = parse_xref(file, xref_offset);
-    return (trailer, xref);
+// The JSON sidecar saying where the objects of `output` came from, when
+// --provenance asks for it.
+fn write_provenance(provenance: &Provenance, output: &Path, sidecar: bool) -> Result<(), PdfError> {
+    if sidecar {
+        provenance.write_sidecar(output)?;
+    }
+    Ok(())
 }
 
-fn cat_xobject(file: &mut File, xref_entry: parsers::xref::XRefEntry) {
-    match file.seek(SeekFrom::Start(xref_entry.offset)) {
-        Err(_) => panic!("couldn't seek to object"),
-        Ok(_) => (),
+// `letterhead --under TEMPLATE FILE -o OUTPUT` draws the first page of
+// TEMPLATE under every page of FILE, or over them with --over.
+fn letterhead_command(args: &[String]) {
+    let mut layer = None;
+    let mut template = None;
+    let mut output = None;
+    let mut path = None;
+    let mut sidecar = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--under" | "--over" => {
+                layer = Some(if arg == "--under" { Layer::Under } else { Layer::Over });
+                template = Some(Path::new(args.next().unwrap_or_else(|| usage("--under and --over need a template"))));
+            },
+            "-o" => output = Some(Path::new(args.next().unwrap_or_else(|| usage("-o needs an output file")))),
+            "--provenance" => sidecar = true,
+            _ => path = Some(Path::new(arg)),
+        }
+    }
+    let (layer, template, output, path) = match (layer, template, output, path) {
+        (Some(layer), Some(template), Some(output), Some(path)) => (layer, template, output, path),
+        _ => usage("letterhead needs --under or --over with a template, a file and -o with an output file"),
     };
+    let stacked = letterhead::letterhead(path, template, output, layer);
+    if let Err(err) = stacked.and_then(|provenance| write_provenance(&provenance, output, sidecar)) {
+        println!("couldn't put {} on {}: {}", template.display(), path.display(), err);
+        process::exit(1);
+    }
+}
 
-    let newline = '\n' as u8;
-    let mut dict_str = "".to_owned();
-    let mut file_buffer = Vec::new();
-    file.take(CHUNK_SIZE as u64).read_to_end(&mut file_buffer).unwrap();
-
-    for line in file_buffer.split(|byte| *byte == newline).skip(1) {
-        let line_str = String::from_utf8(line.to_vec()).unwrap();
-        if line_str.contains("stream") {
-            let v: Vec<&str> = line_str.split("stream").collect();
-            dict_str.push_str(v[0]);
-            break;
-        } else if line_str.contains("endobj") {
-            let v: Vec<&str> = line_str.split("endobj").collect();
-            dict_str.push_str(v[0]);
-            break;
+// `attach report.pdf data.csv -o out.pdf` embeds data.csv in the document,
+// --description describes every file attached.
+fn attach_command(args: &[String]) {
+    let mut description = None;
+    let mut output = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(text) = arg.strip_prefix("--description=") {
+            description = Some(text.to_string());
+        } else if arg == "-o" {
+            output = Some(Path::new(args.next().unwrap_or_else(|| usage("-o needs an output file"))));
         } else {
-            dict_str.push_str(&line_str);
+            paths.push(Path::new(arg));
+        }
+    }
+    let (output, path, files) = match (output, paths.split_first()) {
+        (Some(output), Some((path, files))) if !files.is_empty() => (output, path, files),
+        _ => usage("attach needs a file, the files to attach and -o with an output file"),
+    };
+    let mut attachments = Vec::new();
+    for file in files {
+        match Attachment::from_file(file) {
+            Ok(attachment) => attachments.push(Attachment { description: description.clone(), ..attachment }),
+            Err(err) => {
+                println!("couldn't read {}: {}", file.display(), err);
+                process::exit(1);
+            },
         }
-        dict_str.push_str("\n");
     }
+    if let Err(err) = attach::attach(path, output, &attachments) {
+        println!("couldn't attach files to {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
 
-    println!("Parsing: {:?}", dict_str);
+// `blank --before=3,9` inserts blank pages before pages 3 and 9,
+// `blank --recto=1,4,9` as many as it takes for those pages to start on
+// the right-hand side. Pages are numbered from 1.
+fn blank_command(args: &[String]) {
+    let mut before = Vec::new();
+    let mut paths = Vec::new();
+    for arg in args {
+        let (recto, numbers) = match (arg.strip_prefix("--before="), arg.strip_prefix("--recto=")) {
+            (Some(numbers), _) => (false, numbers),
+            (_, Some(numbers)) => (true, numbers),
+            _ => {
+                paths.push(Path::new(arg));
+                continue;
+            },
+        };
+        let indexes: Vec<usize> = numbers.split(',')
+            .map(|number| number.parse::<usize>().ok().filter(|&number| number > 0).map(|number| number - 1))
+            .collect::<Option<_>>()
+            .unwrap_or_else(|| usage("--before and --recto need page numbers, separated by commas"));
+        before.extend(if recto { page_tree::recto_padding(&indexes) } else { indexes });
+    }
+    if paths.len() != 2 || before.is_empty() {
+        usage("blank needs --before or --recto, a file and an output file");
+    }
+    if let Err(err) = page_tree::insert_blank_pages(paths[0], paths[1], &before) {
+        println!("couldn't insert blank pages into {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
 
-    let mut dict_parser = parsers::cos::Rdp::new(StringInput::new(&dict_str));
-    dict_parser.node();
-    let obj_dict = dict_parser.parse();
+fn resize_command(args: &[String]) {
+    let mut size = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--size=") {
+            Some(name) => size = Some(resize::paper_size(name).unwrap_or_else(|| usage("--size needs a paper size or WIDTHxHEIGHT in points"))),
+            None => paths.push(Path::new(arg)),
+        }
+    }
+    let size = size.unwrap_or_else(|| usage("resize needs --size"));
+    if paths.len() != 2 {
+        usage("resize needs a file and an output file");
+    }
+    if let Err(err) = resize::resize(paths[0], paths[1], size) {
+        println!("couldn't resize {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
 
-    println!("Object: {:?}", obj_dict);
+fn normalize_command(args: &[String]) {
+    let mut precision = normalize::DEFAULT_PRECISION;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--precision=") {
+            Some(digits) => precision = digits.parse().unwrap_or_else(|_| usage("--precision needs a number of decimals")),
+            None => paths.push(Path::new(arg)),
+        }
+    }
+    if paths.len() != 2 {
+        usage("normalize needs a file and an output file");
+    }
+    match normalize::normalize(paths[0], paths[1], precision) {
+        Ok(count) => println!("normalized {} content streams", count),
+        Err(err) => {
+            println!("couldn't normalize {}: {}", paths[0].display(), err);
+            process::exit(1);
+        },
+    }
 }
 
+fn recolor_command(args: &[String]) {
+    let mut target = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--gray" => target = Some(Target::Gray),
+            "--cmyk" => target = Some(Target::Cmyk),
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    let target = target.unwrap_or_else(|| usage("recolor needs --gray or --cmyk"));
+    if paths.len() != 2 {
+        usage("recolor needs a file and an output file");
+    }
+    match recolor::recolor(paths[0], paths[1], target) {
+        Ok(report) => {
+            println!("recolored {} content streams and {} images", report.streams, report.images);
+            if report.skipped_images > 0 {
+                println!("left {} images as they were (JPEG, or not 8 bits per component)", report.skipped_images);
+            }
+            if report.applied_profiles > 0 {
+                println!("converted colors through {} ICC profiles", report.applied_profiles);
+            }
+            if report.ignored_profiles > 0 {
+                println!("converted colors in {} ICC based spaces without their profiles, they may be off", report.ignored_profiles);
+            }
+        },
+        Err(err) => {
+            println!("couldn't recolor {}: {}", paths[0].display(), err);
+            process::exit(1);
+        },
+    }
+}
 
-// This is the main function
-fn main() {
-    let path = Path::new("politics.pdf");
-    let display = path.display();
-
-    // Open the path in read-only mode, returns `io::Result<File>`
-    let mut file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display,
-                           why.description()),
-        Ok(file) => file,
+// `fill --set=name=Ada --set=subscribe=Yes form.pdf filled.pdf` fills in
+// fields by their full name, --data=FILE takes values from an FDF or XFDF
+// file (--set wins over it) and --flatten turns the form into page content.
+fn fill_command(args: &[String]) {
+    let mut values = HashMap::new();
+    let mut data = HashMap::new();
+    let mut flatten = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        if arg == "--flatten" {
+            flatten = true;
+        } else if let Some(path) = arg.strip_prefix("--data=") {
+            data = interchange::import(Path::new(path)).unwrap_or_else(|err| {
+                println!("couldn't read form data from {}: {}", path, err);
+                process::exit(1);
+            });
+        } else if let Some(setting) = arg.strip_prefix("--set=") {
+            match setting.split_once('=') {
+                Some((name, value)) => values.insert(name.to_string(), value.to_string()),
+                None => usage("--set takes NAME=VALUE"),
+            };
+        } else {
+            paths.push(Path::new(arg));
+        }
+    }
+    if paths.len() != 2 {
+        usage("fill needs a file and an output file");
+    }
+    for (name, value) in data {
+        values.entry(name).or_insert(value);
+    }
+    if let Err(err) = fill::fill(paths[0], paths[1], &values, flatten) {
+        println!("couldn't fill in {}: {}", paths[0].display(), err);
+        process::exit(1);
+    }
+}
+
+// Writes the values of a document's fields to an FDF file, or XFDF when
+// the output's name ends in .xfdf.
+fn export_fields_command(args: &[String], options: Options) {
+    if args.len() != 2 {
+        usage("export-fields needs a file and an output file");
+    }
+    let (path, output) = (Path::new(&args[0]), Path::new(&args[1]));
+    let file = path.file_name().and_then(|name| name.to_str());
+    let exported = Document::open(path, options)
+        .and_then(|mut doc| interchange::export(&mut doc, Format::from_path(output), file))
+        .and_then(|data| fs::write(output, data).map_err(PdfError::from));
+    if let Err(err) = exported {
+        println!("couldn't export the fields of {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+// Lists the form fields whose values come from JavaScript, in the order
+// viewers calculate them.
+fn calculations_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("calculations needs a file");
+    }
+    let path = Path::new(&args[0]);
+    let report = Document::open(path, options).and_then(|mut doc| calculation::report(&mut doc));
+    match report {
+        Ok(report) => print!("{}", report),
+        Err(err) => {
+            println!("couldn't read the form of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Lists the pages with their size and rotation.
+fn pages_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("pages needs a file");
+    }
+    let path = Path::new(&args[0]);
+    let found = Document::open(path, options).and_then(|mut doc| pages::pages(&mut doc));
+    match found {
+        Ok(found) => {
+            for page in found {
+                let object = page.id.map(|id| format!(" ({} 0 R)", id)).unwrap_or_default();
+                let (width, height) = page.display_size();
+                print!("page {}{}: {} x {} pt", page.index + 1, object, width, height);
+                if page.rotate != 0 {
+                    print!(", rotated {}", page.rotate);
+                }
+                if page.user_unit != 1.0 {
+                    print!(", {} pt per unit", page.user_unit);
+                }
+                println!();
+            }
+        },
+        Err(err) => {
+            println!("couldn't read the pages of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Prints the text of every page, or of the one given with --page, each page
+// followed by a form feed like pdftotext does. With --json, prints the
+// positioned text runs instead, see text::json, placed on the upright page
+// (from its top left corner with --top-left) or with --raw in default user
+// space. With --coverage, prints how much of
+// each page's text could be decoded.
+fn text_command(args: &[String], options: Options) {
+    let mut only = None;
+    let mut as_json = false;
+    let mut show_coverage = false;
+    let mut raw = false;
+    let mut origin = Origin::BottomLeft;
+    let mut path = None;
+    for arg in args {
+        if arg == "--json" {
+            as_json = true;
+        } else if arg == "--raw" {
+            raw = true;
+        } else if arg == "--top-left" {
+            origin = Origin::TopLeft;
+        } else if arg == "--coverage" {
+            show_coverage = true;
+        } else if let Some(number) = arg.strip_prefix("--page=") {
+            only = Some(number.parse::<usize>().ok().filter(|&number| number > 0).unwrap_or_else(|| usage("--page needs a page number")));
+        } else {
+            path = Some(Path::new(arg));
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("text needs a file"));
+    let extracted = Document::open(path, options).and_then(|mut doc| {
+        let pages: Vec<_> = pages::pages(&mut doc)?.into_iter()
+            .filter(|page| only.is_none_or(|number| number == page.index + 1))
+            .collect();
+        if as_json {
+            let mut spans = Vec::new();
+            for page in &pages {
+                let page_spans = if raw { text::raw_spans(&mut doc, page)? } else { text::spans_from(&mut doc, page, origin)? };
+                spans.push((page, page_spans));
+            }
+            return Ok(text::json(&spans));
+        }
+        if show_coverage {
+            let mut report = String::new();
+            for page in &pages {
+                let coverage = text::coverage(&mut doc, page)?;
+                report.push_str(&format!("page {}: {:.0}% of {} runs mapped", page.index + 1, coverage.fraction() * 100.0, coverage.runs));
+                let unmapped: Vec<String> = coverage.unmapped_fonts.iter()
+                    .map(|(font, count)| format!("{} ({} codes)", font, count))
+                    .collect();
+                if !unmapped.is_empty() {
+                    report.push_str(&format!(", unmapped: {}", unmapped.join(", ")));
+                }
+                report.push('\n');
+            }
+            return Ok(report);
+        }
+        let mut text = String::new();
+        for page in &pages {
+            text.push_str(&doc.extract_text(page)?);
+            text.push('\x0c');
+        }
+        Ok(text)
+    });
+    match extracted {
+        Ok(text) => print!("{}", text),
+        Err(err) => {
+            println!("couldn't extract the text of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Compares the text extracted from FILE with REFERENCE, or for a directory
+// every PDF in it with the .txt file of the same name next to it, see
+// text::accuracy. With several files, the total comes last.
+fn accuracy_command(args: &[String], options: Options) {
+    let pairs: Vec<(PathBuf, PathBuf)> = match args {
+        [file, reference] => vec![(PathBuf::from(file), PathBuf::from(reference))],
+        [dir] => {
+            let entries = fs::read_dir(dir).unwrap_or_else(|err| {
+                println!("couldn't read {}: {}", dir, err);
+                process::exit(1);
+            });
+            let mut pairs: Vec<(PathBuf, PathBuf)> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pdf")))
+                .map(|path| (path.clone(), path.with_extension("txt")))
+                .filter(|(_, reference)| reference.is_file())
+                .collect();
+            pairs.sort();
+            pairs
+        },
+        _ => usage("accuracy needs a file and its reference text, or a directory of both"),
     };
+    let mut total = Accuracy::default();
+    for (path, reference) in &pairs {
+        let compared = fs::read_to_string(reference).map_err(PdfError::from).and_then(|reference| {
+            let mut doc = Document::open(path, options.clone())?;
+            let mut text = String::new();
+            for page in pages::pages(&mut doc)? {
+                text.push_str(&doc.extract_text(&page)?);
+                text.push('\x0c');
+            }
+            Ok(accuracy::compare(&text, &reference))
+        });
+        match compared {
+            Ok(accuracy) => {
+                println!("{}: {}", path.display(), accuracy);
+                total.add(&accuracy);
+            },
+            Err(err) => println!("couldn't compare {}: {}", path.display(), err),
+        }
+    }
+    if pairs.len() > 1 {
+        println!("total: {}", total);
+    }
+}
 
-    let (trailer, xref) = get_doc_metadata(&mut file);
+fn unembed_fonts_command(args: &[String]) {
+    if args.len() != 2 {
+        usage("unembed-fonts needs a file and an output file");
+    }
+    let path = Path::new(&args[0]);
+    match embedding::unembed(path, Path::new(&args[1])) {
+        Ok(replaced) => {
+            for (name, standard) in &replaced {
+                println!("{} -> {}", name, standard.name());
+            }
+            println!("replaced {} fonts", replaced.len());
+        },
+        Err(err) => {
+            println!("couldn't unembed the fonts of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
 
-    println!("Trailer:\n{:?}", trailer);
-    println!("Xref:\n{:?}", xref);
+// `subset-fonts FILE OUTPUT` cuts the embedded fonts down to the glyphs
+// the pages show.
+// The object-level changes from one file to another.
+fn object_changes(old: &Path, new: &Path, options: Options) -> Result<Vec<PatchOp>, PdfError> {
+    let mut old = Document::open(old, options.clone())?;
+    let mut new = Document::open(new, options)?;
+    old.diff(&mut new)
+}
 
-    for (index, entry) in xref.into_iter().enumerate() {
-        if !entry.is_free {
-            println!("cat XObject {} at offset {}", index, entry.offset);
-            cat_xobject(&mut file, entry);
+fn diff_command(args: &[String], options: Options) {
+    if args.len() != 2 {
+        usage("diff needs two files");
+    }
+    match object_changes(Path::new(&args[0]), Path::new(&args[1]), options) {
+        Ok(ops) => {
+            for op in ops {
+                println!("{}", op);
+            }
+        },
+        Err(err) => {
+            println!("couldn't compare {} and {}: {}", args[0], args[1], err);
+            process::exit(1);
+        },
+    }
+}
+
+// Applies what changed from OLD to NEW to BASE, a three-way merge.
+fn patch_command(args: &[String], options: Options) {
+    if args.len() != 4 {
+        usage("patch needs a base file, the old and new files to take the changes from and an output file");
+    }
+    let base = Path::new(&args[0]);
+    let merged = object_changes(Path::new(&args[1]), Path::new(&args[2]), options)
+        .and_then(|ops| patch::apply(base, &ops, Path::new(&args[3])));
+    if let Err(err) = merged {
+        println!("couldn't patch {}: {}", base.display(), err);
+        process::exit(1);
+    }
+}
+
+fn subset_fonts_command(args: &[String]) {
+    if args.len() != 2 {
+        usage("subset-fonts needs a file and an output file");
+    }
+    let path = Path::new(&args[0]);
+    match subset::subset(path, Path::new(&args[1])) {
+        Ok(subsetted) => {
+            for font in &subsetted {
+                match font.glyphs {
+                    Some((before, after)) => println!("{}: {} codes, {} of {} glyphs kept", font.name, font.codes, after, before),
+                    None => println!("{}: {} codes", font.name, font.codes),
+                }
+            }
+        },
+        Err(err) => {
+            println!("couldn't subset the fonts of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// `embed-fonts --font=Helvetica=NimbusSans-Regular.pfb doc.pdf out.pdf`
+// embeds the font file in the fonts naming Helvetica (or Arial) without a
+// program.
+fn embed_fonts_command(args: &[String]) {
+    let mut programs = Vec::new();
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--font=").map(|font| font.split_once('=')) {
+            Some(Some((name, file))) => {
+                let standard = StandardFont::from_name(name).unwrap_or_else(|| usage(&format!("{} isn't a standard font", name)));
+                let data = fs::read(file).unwrap_or_else(|err| usage(&format!("couldn't read {}: {}", file, err)));
+                programs.push((standard, data));
+            },
+            Some(None) => usage("--font needs a standard font name and a font file, --font=Helvetica=FILE"),
+            None => paths.push(Path::new(arg)),
+        }
+    }
+    if paths.len() != 2 || programs.is_empty() {
+        usage("embed-fonts needs --font, a file and an output file");
+    }
+    match embedding::embed(paths[0], paths[1], &programs) {
+        Ok(embedded) => {
+            for (name, standard) in &embedded {
+                println!("{} <- {}", name, standard.name());
+            }
+            println!("embedded {} fonts", embedded.len());
+        },
+        Err(err) => {
+            println!("couldn't embed fonts in {}: {}", paths[0].display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Lists the fonts the pages use, and writes the embedded ones out to the
+// directory given with -o.
+fn fonts_command(args: &[String], options: Options) {
+    let mut output_dir = None;
+    let mut template = "{obj}-{name}.{ext}".to_string();
+    let mut path = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage("-o needs a directory"))));
+        } else if arg == "--name-template" {
+            template = args.next().unwrap_or_else(|| usage("--name-template needs a template"));
+        } else {
+            path = Some(PathBuf::from(arg));
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("fonts needs a file"));
+    let listed = Document::open(&path, options).and_then(|mut doc| {
+        if let Some(ref dir) = output_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut namer = OutputNamer::new(&template);
+        for (index, font) in fonts::fonts(&mut doc)?.into_iter().enumerate() {
+            let object = font.id.map(|id| format!("{} 0 R", id)).unwrap_or_else(|| "direct".to_string());
+            let program = font.program(&mut doc)?;
+            let embedded = match program {
+                Some((kind, _)) => format!("embedded {}", kind.extension()),
+                None => "not embedded".to_string(),
+            };
+            println!("{}: {} ({}), {}", object, font.base_font, font.subtype, embedded);
+            if let (Some(dir), Some((kind, data))) = (output_dir.as_ref(), program) {
+                let fields = NameFields {
+                    index,
+                    object: font.id.unwrap_or(0),
+                    name: Some(&font.base_font),
+                    ext: kind.extension(),
+                    ..NameFields::default()
+                };
+                fs::write(namer.path(dir, &fields), data)?;
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = listed {
+        println!("couldn't read the fonts of {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+// Prints the entries of the paper's reference list, each with the page it
+// starts on, see the citations module.
+fn citations_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("citations needs a file");
+    }
+    let path = Path::new(&args[0]);
+    match Document::open(path, options).and_then(|mut doc| citations::citations(&mut doc)) {
+        Ok(found) => {
+            for citation in found {
+                let label = citation.label.map(|label| format!("[{}] ", label)).unwrap_or_default();
+                println!("page {}: {}{}", citation.page + 1, label, citation.text);
+            }
+        },
+        Err(err) => {
+            println!("couldn't find the citations of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Prints where the links on each page lead, or with --all every
+// annotation, links or not. Rectangles are in default user space, or with
+// --top-left on the displayed page from its top left corner.
+fn links_command(args: &[String], options: Options) {
+    let mut all = false;
+    let mut origin = None;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--all" => all = true,
+            "--top-left" => origin = Some(Origin::TopLeft),
+            _ => path = Some(Path::new(arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("links needs a file"));
+    let found = Document::open(path, options).and_then(|mut doc| {
+        let pages = pages::pages(&mut doc)?;
+        Ok(pages.into_iter().zip(annotations::by_page(&mut doc)?).collect::<Vec<_>>())
+    });
+    match found {
+        Ok(pages) => {
+            for (page, annotations) in &pages {
+                let index = page.index;
+                for annotation in annotations {
+                    let [llx, lly, urx, ury] = origin.map_or(annotation.rect, |origin| page.coords(origin).rect(annotation.rect));
+                    match annotation.target {
+                        Some(ref target) => println!("page {} [{} {} {} {}]: {}", index + 1, llx, lly, urx, ury, target),
+                        None if all => println!("page {} [{} {} {} {}]: {} annotation", index + 1, llx, lly, urx, ury, annotation.subtype),
+                        None => (),
+                    }
+                }
+            }
+        },
+        Err(err) => {
+            println!("couldn't read the links of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+fn signatures_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("signatures needs a file");
+    }
+    let path = Path::new(&args[0]);
+    match Document::open(path, options).and_then(|mut doc| signatures::signatures(&mut doc)) {
+        Ok(fields) => {
+            for field in fields {
+                match field.signature {
+                    Some(signature) => println!("{}: {}", field.name, signature),
+                    None => println!("{}: not signed", field.name),
+                }
+            }
+        },
+        Err(err) => {
+            println!("couldn't read the signatures of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Prints where each page likely has math, see the math module.
+fn math_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("math needs a file");
+    }
+    let path = Path::new(&args[0]);
+    let found = Document::open(path, options).and_then(|mut doc| {
+        let mut found = Vec::new();
+        for page in pages::pages(&mut doc)? {
+            for region in math::regions(&mut doc, &page)? {
+                found.push((page.index, region));
+            }
+        }
+        Ok(found)
+    });
+    match found {
+        Ok(found) => {
+            for (index, region) in found {
+                let [llx, lly, urx, ury] = region.bbox;
+                let kind = if region.display { "display" } else { "inline" };
+                println!("page {}: [{:.0} {:.0} {:.0} {:.0}] {}: {}", index + 1, llx, lly, urx, ury, kind, region.text.replace('\n', " / "));
+            }
+        },
+        Err(err) => {
+            println!("couldn't find the math of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Lists the image XObjects in the file, and writes them out as PNG/JPEG
+// files to the directory given with -o. Images that can't be converted are
+// reported and skipped.
+fn images_command(args: &[String], options: Options) {
+    let mut output_dir = None;
+    let mut template = "obj_{obj}_image.{ext}".to_string();
+    // inline images have no object number
+    let mut inline_template = "page_{page}_inline_{index}.{ext}".to_string();
+    let mut jobs = pipeline::default_workers();
+    let mut path = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage("-o needs a directory"))));
+        } else if arg == "--name-template" {
+            template = args.next().unwrap_or_else(|| usage("--name-template needs a template"));
+            inline_template = template.clone();
+        } else if let Some(count) = arg.strip_prefix("--jobs=") {
+            jobs = count.parse().unwrap_or_else(|_| usage("--jobs needs a number of threads"));
+        } else {
+            path = Some(PathBuf::from(arg));
         }
     }
+    let path = path.unwrap_or_else(|| usage("images needs a file"));
+    let listed = Document::open(&path, options).and_then(|mut doc| {
+        if let Some(ref dir) = output_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut namer = OutputNamer::new(&template);
+        let entries: Vec<(u64, XRefEntry)> = doc.xref.entries()
+            .filter(|(_, entry)| !entry.is_free)
+            .map(|(id, entry)| (id as u64, entry.clone()))
+            .collect();
+        let mut index = 0;
+        images::extract_all(&mut doc, &entries, jobs, |id, image| {
+            // one broken image shouldn't keep the others from being written
+            let image = match image {
+                Ok(image) => image,
+                Err(err) => {
+                    println!("{} 0 R: skipped, {}", id, err);
+                    return Ok(());
+                },
+            };
+            print!("{} 0 R: {}x{} {}", id, image.width, image.height, image.format.extension());
+            if image.alpha {
+                print!(" with alpha");
+            }
+            if let Some(ref dir) = output_dir {
+                let fields = NameFields { index, object: id, ext: image.format.extension(), ..NameFields::default() };
+                let file = namer.path(dir, &fields);
+                fs::write(&file, &image.data)?;
+                print!(" written to {}", file.display());
+            }
+            println!();
+            index += 1;
+            Ok(())
+        })?;
+
+        let mut namer = OutputNamer::new(&inline_template);
+        for page in pages::pages(&mut doc)? {
+            let number = page.index + 1;
+            for (dict, data) in images::inline_images(&mut doc, &page)? {
+                let image = match images::extract_inline(&mut doc, &page.resources, &dict, &data) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        println!("page {} inline: skipped, {}", number, err);
+                        continue;
+                    },
+                };
+                print!("page {} inline: {}x{} {}", number, image.width, image.height, image.format.extension());
+                if let Some(ref dir) = output_dir {
+                    let fields = NameFields { page: Some(number), index, ext: image.format.extension(), ..NameFields::default() };
+                    let file = namer.path(dir, &fields);
+                    fs::write(&file, &image.data)?;
+                    print!(" written to {}", file.display());
+                }
+                println!();
+                index += 1;
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = listed {
+        println!("couldn't read the images of {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+// Prints the candidate figures of each page with their captions, see the
+// figures module. --top-left gives their boxes on the displayed page from
+// its top left corner rather than in default user space.
+fn figures_command(args: &[String], options: Options) {
+    let (origin, path) = match args {
+        [path] => (None, path),
+        [flag, path] if flag == "--top-left" => (Some(Origin::TopLeft), path),
+        _ => usage("figures needs a file"),
+    };
+    let path = Path::new(path);
+    let found = Document::open(path, options).and_then(|mut doc| {
+        let mut found = Vec::new();
+        for page in pages::pages(&mut doc)? {
+            for figure in figures::figures(&mut doc, &page)? {
+                let bbox = origin.map_or(figure.bbox, |origin| page.coords(origin).rect(figure.bbox));
+                found.push((page.index, bbox, figure));
+            }
+        }
+        Ok(found)
+    });
+    match found {
+        Ok(found) => {
+            for (index, bbox, figure) in found {
+                let [llx, lly, urx, ury] = bbox;
+                print!("page {}: [{:.0} {:.0} {:.0} {:.0}], {} paths, {} images", index + 1, llx, lly, urx, ury, figure.paths, figure.images);
+                if let Some(caption) = figure.caption {
+                    print!(": {}", caption);
+                }
+                println!();
+            }
+        },
+        Err(err) => {
+            println!("couldn't find the figures of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Prints the text on each page a reader wouldn't see, and why, see the
+// hidden module.
+fn hidden_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("hidden needs a file");
+    }
+    let path = Path::new(&args[0]);
+    let found = Document::open(path, options).and_then(|mut doc| {
+        let mut found = Vec::new();
+        for page in pages::pages(&mut doc)? {
+            found.extend(hidden::hidden_text(&mut doc, &page)?.into_iter().map(|hidden| (page.index, hidden)));
+        }
+        Ok(found)
+    });
+    match found {
+        Ok(found) => {
+            for (index, hidden) in found {
+                let [llx, lly, urx, ury] = hidden.bbox;
+                let reasons: Vec<String> = hidden.reasons.iter().map(|reason| reason.to_string()).collect();
+                println!("page {}: [{:.0} {:.0} {:.0} {:.0}], {}: {}", index + 1, llx, lly, urx, ury, reasons.join(", "), hidden.text);
+            }
+        },
+        Err(err) => {
+            println!("couldn't look for hidden text in {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Prints what's in the file in the format sandbox::Summary reads. With
+// --sandbox the work happens in a child process.
+fn summary_command(args: &[String], mut options: Options) {
+    let mut sandboxed = false;
+    let mut timeout = sandbox::DEFAULT_TIMEOUT;
+    // the password goes to the child with the rest
+    let mut forwarded: Vec<String> = options.password.iter().map(|password| format!("--password={}", password)).collect();
+    let mut path = None;
+    for arg in args {
+        if arg == "--sandbox" {
+            sandboxed = true;
+        } else if let Some(seconds) = arg.strip_prefix("--timeout=") {
+            timeout = Duration::from_secs(seconds.parse().unwrap_or_else(|_| usage("--timeout needs a number of seconds")));
+        } else if arg == "--lenient" {
+            options.lenient = true;
+            forwarded.push(arg.clone());
+        } else if let Some(count) = arg.strip_prefix("--max-pages=") {
+            options.policy.max_pages = Some(count.parse().unwrap_or_else(|_| usage("--max-pages needs a number of pages")));
+            forwarded.push(arg.clone());
+        } else {
+            path = Some(Path::new(arg));
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("summary needs a file"));
+    let summary = if sandboxed {
+        let program = env::current_exe().unwrap_or_else(|_| PathBuf::from("nails-pdf"));
+        Sandbox { args: forwarded, timeout, ..Sandbox::new(program) }.summarize(path)
+    } else {
+        sandbox::summarize(path, options)
+    };
+    match summary {
+        Ok(summary) => print!("{}", summary),
+        Err(err) => {
+            println!("couldn't summarize {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// Lists what can be recovered from a broken file's streams, see the salvage
+// module, and writes it to the directory given with -o.
+fn salvage_command(args: &[String]) {
+    let mut output_dir = None;
+    let mut template = "salvaged_{index}.{ext}".to_string();
+    let mut path = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage("-o needs a directory"))));
+        } else if arg == "--name-template" {
+            template = args.next().unwrap_or_else(|| usage("--name-template needs a template"));
+        } else {
+            path = Some(PathBuf::from(arg));
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("salvage needs a file"));
+    let salvaged = fs::read(&path).map_err(PdfError::from).and_then(|data| {
+        if let Some(ref dir) = output_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut namer = OutputNamer::new(&template);
+        for (index, fragment) in salvage::salvage(&data).into_iter().enumerate() {
+            let object = fragment.id.map(|id| format!(" ({} 0 R)", id)).unwrap_or_default();
+            print!("offset {}{}: {}, {} bytes", fragment.offset, object, fragment.kind.extension(), fragment.data.len());
+            if let Some(ref dir) = output_dir {
+                let fields = NameFields {
+                    index,
+                    object: fragment.id.unwrap_or(0),
+                    ext: fragment.kind.extension(),
+                    ..NameFields::default()
+                };
+                let file = namer.path(dir, &fields);
+                fs::write(&file, &fragment.data)?;
+                print!(" written to {}", file.display());
+            }
+            println!();
+        }
+        Ok(())
+    });
+    if let Err(err) = salvaged {
+        println!("couldn't salvage {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+// Prints the document information dictionary: title, author, dates...
+fn info_command(args: &[String], options: Options) {
+    if args.len() != 1 {
+        usage("info needs a file");
+    }
+    let path = Path::new(&args[0]);
+    let found = Document::open(path, options)
+        .and_then(|mut doc| Ok((info::info(&mut doc)?, doc.security().map(|security| security.allowed()))));
+    match found {
+        Ok((info, permissions)) => {
+            match info {
+                Some(info) => print!("{}", info),
+                None => println!("no document info"),
+            }
+            if let Some(permissions) = permissions {
+                println!("permissions: {}", permissions);
+            }
+        },
+        Err(err) => {
+            println!("couldn't read the info of {}: {}", path.display(), err);
+            process::exit(1);
+        },
+    }
+}
+
+// `watch FILE -- SUBCOMMAND ARGS...` runs `nails-pdf SUBCOMMAND ARGS... FILE`
+// every time FILE changes, until interrupted. Each run is a fresh process
+// that parses the whole file again, appended revisions included: telling
+// an appended revision from a rewrite only changes what gets printed.
+fn watch(args: &[String], options: Options) -> ! {
+    let (path, command) = match args.iter().position(|arg| arg == "--") {
+        Some(1) => (Path::new(&args[0]), &args[2..]),
+        _ => usage("watch needs a file, then -- and the subcommand to run"),
+    };
+    if command.is_empty() {
+        usage("watch needs a subcommand to run after --");
+    }
+    let passwords: Vec<String> = options.password.iter().map(|password| format!("--password={}", password)).collect();
+    let exe = env::current_exe().unwrap_or_else(|_| PathBuf::from("nails-pdf"));
+
+    // the file as of the last run, and the document read from it
+    let mut last: Option<FileState> = None;
+    let mut doc: Option<Document> = None;
+    loop {
+        let state = match settled_state(path) {
+            Some(state) => state,
+            None => {
+                // mid-rewrite by whatever generates it, try again shortly
+                thread::sleep(WATCH_INTERVAL);
+                continue;
+            },
+        };
+        if last.as_ref() != Some(&state) {
+            // only the revisions appended since are read, when that's all
+            // that happened
+            let change = match doc.as_mut().map(Document::refresh) {
+                Some(Ok(Refresh::Appended(objects))) => format!("new revision appended, {} objects changed", objects.len()),
+                Some(Ok(Refresh::Reopened)) => "rewritten".to_string(),
+                // not readable as it is, the subcommand will say why
+                Some(Err(_)) | None => {
+                    doc = Document::open(path, options.clone()).ok();
+                    "rewritten".to_string()
+                },
+            };
+            if last.is_some() {
+                println!("--- {}: {}", path.display(), change);
+            }
+            match Command::new(&exe).args(&passwords).args(command).arg(path).status() {
+                Ok(status) if !status.success() => println!("--- exited with {}", status),
+                Ok(_) => (),
+                Err(err) => println!("--- couldn't run {}: {}", exe.display(), err),
+            }
+            last = Some(state);
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(PartialEq)]
+struct FileState {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+fn file_state(path: &Path) -> Option<FileState> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileState { len: metadata.len(), modified: metadata.modified().ok() })
+}
+
+// Generators usually write in several steps, only report a change once
+// the file has stopped moving.
+fn settled_state(path: &Path) -> Option<FileState> {
+    let state = file_state(path)?;
+    thread::sleep(WATCH_INTERVAL / 5);
+    let again = file_state(path)?;
+    if state == again {
+        Some(state)
+    } else {
+        None
+    }
 }
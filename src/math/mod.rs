@@ -0,0 +1,307 @@
+use std::collections::HashSet;
+
+use document::Document;
+use error::Result;
+use graphics::union;
+use pages::Page;
+use parsers::cos::DictNode;
+use text::{self, TextSpan};
+
+/*
+ * Guesses where a page has math, so it can be sent to math OCR rather than
+ * come out as the jumble text extraction makes of it.
+ *
+ * Text is split into lines, and lines into segments where there's a gap
+ * wider than `COLUMN_GAP` ems (two columns side by side). Each span gets a
+ * point for every sign of math about it:
+ *
+ *   - its font is a math font (Computer Modern math italic and symbols,
+ *     the AMS fonts, Symbol, anything "Math") or a Type3 font, which TeX
+ *     output turned through dvips is full of
+ *   - it has math characters: Greek, operators, arrows, math alphanumerics
+ *   - it's a script: smaller than the segment's text, above or below its
+ *     baseline
+ *   - it's off the baseline otherwise, or much bigger: fractions, limits,
+ *     big operators
+ *
+ * The segment's baseline and size are those of its longest span. A segment
+ * where most characters score is display math and comes out whole;
+ * elsewhere scoring spans close to each other make inline regions. Regions
+ * that overlap horizontally and nearly touch vertically, the lines of a
+ * multi-line equation, are merged.
+ *
+ * A lone superscript is a footnote mark as often as not, so regions need
+ * two points, or one for a math font.
+ */
+
+// in ems of the segment's text
+const COLUMN_GAP: f64 = 3.0;
+const INLINE_GAP: f64 = 1.5;
+const OFF_BASELINE: f64 = 0.15;
+const MAX_SCRIPT_OFFSET: f64 = 0.8;
+const SCRIPT_SIZE: f64 = 0.9;
+const LARGE_SIZE: f64 = 1.4;
+const MERGE_GAP: f64 = 0.5;
+// of a segment's characters
+const DISPLAY_FRACTION: f64 = 0.5;
+const MIN_POINTS: usize = 2;
+
+// Compared against font names in upper case, without punctuation or the
+// subset prefix.
+const MATH_FONTS: &[&str] = &[
+    "CMMI", "CMSY", "CMBSY", "CMEX", "MSAM", "MSBM", "EUFM", "EUSM", "EUEX", "RSFS",
+    "MTMI", "MTSY", "MTEX", "TXMI", "TXSY", "PXMI", "PXSY", "MATH", "SYMBOL",
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MathRegion {
+    // [llx lly urx ury] in default user space
+    pub bbox: [f64; 4],
+    // a line (or lines) of its own, rather than inside running text
+    pub display: bool,
+    // what text extraction makes of it
+    pub text: String,
+}
+
+pub fn regions(doc: &mut Document, page: &Page) -> Result<Vec<MathRegion>> {
+    let spans = text::raw_spans(doc, page)?;
+    let type3 = type3_fonts(doc, &page.resources)?;
+    Ok(detect(&spans, &type3))
+}
+
+// Names spans of the page's Type3 fonts go by. Those rarely have a
+// /BaseFont, so spans have their resource name instead.
+fn type3_fonts(doc: &mut Document, resources: &DictNode) -> Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    let fonts = match resources.get_resolved(doc, "Font")? {
+        Some(DictNode::Dict(fonts)) => fonts,
+        _ => return Ok(names),
+    };
+    for (name, font) in fonts {
+        let font = doc.resolve(&font)?;
+        if font.get("Subtype").is_some_and(|subtype| subtype.is_name("Type3")) {
+            if let Some(base_font) = font.get("BaseFont").and_then(DictNode::as_name) {
+                names.insert(base_font.to_string());
+            }
+            names.insert(name);
+        }
+    }
+    Ok(names)
+}
+
+fn detect(spans: &[TextSpan], type3: &HashSet<String>) -> Vec<MathRegion> {
+    let mut regions = Vec::new();
+    for segment in segments(spans) {
+        regions.extend(segment_regions(&segment, type3));
+    }
+    merge(regions)
+}
+
+// Spans split into lines, top to bottom, and lines into segments, each
+// sorted left to right.
+fn segments(spans: &[TextSpan]) -> Vec<Vec<&TextSpan>> {
+    let mut sorted: Vec<&TextSpan> = spans.iter().filter(|span| !span.text.trim().is_empty()).collect();
+    sorted.sort_by(|a, b| b.start.1.total_cmp(&a.start.1));
+    // each line with how far it goes vertically
+    let mut lines: Vec<(f64, f64, Vec<&TextSpan>)> = Vec::new();
+    for span in sorted {
+        let (low, high) = (span.start.1 - span.size * 0.25, span.start.1 + span.size * 0.75);
+        match lines.iter_mut().find(|(bottom, top, _)| low < *top && high > *bottom) {
+            Some(line) => {
+                line.0 = line.0.min(low);
+                line.1 = line.1.max(high);
+                line.2.push(span);
+            },
+            None => lines.push((low, high, vec![span])),
+        }
+    }
+    let mut segments = Vec::new();
+    for (_, _, mut line) in lines {
+        line.sort_by(|a, b| a.start.0.total_cmp(&b.start.0));
+        let mut segment: Vec<&TextSpan> = Vec::new();
+        for span in line {
+            if let Some(last) = segment.last() {
+                if span.start.0 - last.end.0 > COLUMN_GAP * last.size.max(span.size) {
+                    segments.push(segment);
+                    segment = Vec::new();
+                }
+            }
+            segment.push(span);
+        }
+        segments.push(segment);
+    }
+    segments
+}
+
+fn segment_regions(segment: &[&TextSpan], type3: &HashSet<String>) -> Vec<MathRegion> {
+    let main = match segment.iter().max_by_key(|span| span.text.chars().count()) {
+        Some(main) => *main,
+        None => return Vec::new(),
+    };
+    let scores: Vec<(usize, bool)> = segment.iter().map(|span| score(span, main, type3)).collect();
+
+    let characters: usize = segment.iter().map(|span| span.text.chars().count()).sum();
+    let scoring: usize = segment.iter().zip(&scores)
+        .filter(|(_, &(points, _))| points > 0)
+        .map(|(span, _)| span.text.chars().count())
+        .sum();
+    let display = scoring as f64 >= characters as f64 * DISPLAY_FRACTION;
+
+    // runs of scoring spans, or the whole segment for display math
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    if display {
+        groups.push((0..segment.len()).collect());
+    } else {
+        for (index, &(points, _)) in scores.iter().enumerate() {
+            if points == 0 {
+                continue;
+            }
+            let joins = groups.last()
+                .and_then(|group| group.last())
+                .is_some_and(|&last| segment[index].start.0 - segment[last].end.0 < INLINE_GAP * main.size);
+            match groups.last_mut() {
+                Some(group) if joins => group.push(index),
+                _ => groups.push(vec![index]),
+            }
+        }
+    }
+
+    let mut regions = Vec::new();
+    for group in groups {
+        let points: usize = group.iter().map(|&index| scores[index].0).sum();
+        let math_font = group.iter().any(|&index| scores[index].1);
+        if points < MIN_POINTS && !(math_font && points > 0) {
+            continue;
+        }
+        let spans: Vec<TextSpan> = group.iter().map(|&index| segment[index].clone()).collect();
+        let bbox = spans.iter().map(span_bounds).fold(span_bounds(&spans[0]), union);
+        let text = text::layout(&spans).trim().replace('\n', " ");
+        regions.push(MathRegion { bbox, display, text });
+    }
+    regions
+}
+
+// Signs of math about a span, see the top of the file, and whether its
+// font is one of them.
+fn score(span: &TextSpan, main: &TextSpan, type3: &HashSet<String>) -> (usize, bool) {
+    let font = is_math_font(&span.font) || type3.contains(&span.font);
+    let symbols = span.text.chars().any(is_math_char);
+    let offset = (span.start.1 - main.start.1).abs();
+    let off_baseline = offset > OFF_BASELINE * main.size;
+    let script = off_baseline && offset < MAX_SCRIPT_OFFSET * main.size && span.size < SCRIPT_SIZE * main.size;
+    let unusual = (off_baseline && !script) || span.size > LARGE_SIZE * main.size;
+    (font as usize + symbols as usize + script as usize + unusual as usize, font)
+}
+
+pub fn is_math_font(name: &str) -> bool {
+    // subsets are named ABCDEF+CMMI10
+    let name = match name.split_once('+') {
+        Some((prefix, rest)) if prefix.len() == 6 => rest,
+        _ => name,
+    };
+    let name: String = name.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_ascii_uppercase();
+    MATH_FONTS.iter().any(|math| name.contains(math))
+}
+
+fn is_math_char(c: char) -> bool {
+    match c {
+        // ± × ÷ ¬
+        '\u{b1}' | '\u{d7}' | '\u{f7}' | '\u{ac}' => true,
+        // Greek
+        '\u{391}'..='\u{3c9}' | '\u{3d1}'..='\u{3f5}' => true,
+        // primes, arrows, operators, technical, math symbols
+        '\u{2032}'..='\u{2037}' | '\u{2190}'..='\u{22ff}' | '\u{27c0}'..='\u{27ff}' | '\u{2980}'..='\u{2aff}' => true,
+        // mathematical alphanumerics
+        '\u{1d400}'..='\u{1d7ff}' => true,
+        _ => false,
+    }
+}
+
+// Roughly where a span's glyphs are, from a descender below the baseline
+// up to one em.
+fn span_bounds(span: &TextSpan) -> [f64; 4] {
+    let y = span.start.1.min(span.end.1);
+    [span.start.0.min(span.end.0), y - span.size * 0.25, span.start.0.max(span.end.0), y + span.size]
+}
+
+fn merge(regions: Vec<MathRegion>) -> Vec<MathRegion> {
+    let mut merged: Vec<MathRegion> = Vec::new();
+    for mut region in regions {
+        // regions come top to bottom, so what's merged into is above
+        while let Some(index) = merged.iter().position(|above| touches(above.bbox, region.bbox)) {
+            let above = merged.remove(index);
+            region = MathRegion {
+                bbox: union(above.bbox, region.bbox),
+                display: above.display || region.display,
+                text: format!("{}\n{}", above.text, region.text),
+            };
+        }
+        merged.push(region);
+    }
+    merged
+}
+
+// Whether two regions overlap horizontally and are less than half the
+// height of `b` apart vertically.
+fn touches(a: [f64; 4], b: [f64; 4]) -> bool {
+    let overlaps = a[0] < b[2] && b[0] < a[2];
+    let gap = (a[1] - b[3]).max(b[1] - a[3]);
+    overlaps && gap < (b[3] - b[1]) * MERGE_GAP
+}
+
+#[test]
+fn test_math_fonts() {
+    assert!(is_math_font("ABCDEF+CMMI10"));
+    assert!(is_math_font("CMSY7"));
+    assert!(is_math_font("Cambria Math"));
+    assert!(is_math_font("Symbol"));
+    assert!(!is_math_font("ABCDEF+CMR10"));
+    assert!(!is_math_font("Times-Roman"));
+}
+
+#[test]
+fn test_math_regions() {
+    use graphics::Color;
+
+    let span = |text: &str, font: &str, x: f64, y: f64, size: f64| TextSpan {
+        text: text.to_string(),
+        start: (x, y),
+        end: (x + text.chars().count() as f64 * size * 0.5, y),
+        size,
+        font: font.to_string(),
+        color: Color::Gray(0.0),
+        render_mode: 0,
+    };
+    let spans = vec![
+        // running text with x_i + y^2 in it, and a footnote mark
+        span("The sum of ", "CMR10", 72.0, 700.0, 10.0),
+        span("x", "CMMI10", 127.0, 700.0, 10.0),
+        span("i", "CMMI7", 132.0, 698.5, 7.0),
+        span(" + ", "CMR10", 136.0, 700.0, 10.0),
+        span("y", "CMMI10", 150.0, 700.0, 10.0),
+        span("2", "CMR7", 155.0, 704.0, 7.0),
+        span(" is small for all cases considered here.", "CMR10", 159.0, 700.0, 10.0),
+        span("1", "CMR7", 359.0, 704.0, 7.0),
+        // a displayed equation over two lines
+        span("\u{2211}", "CMEX10", 200.0, 650.0, 14.0),
+        span("\u{3b1}", "T3Font_1", 215.0, 652.0, 10.0),
+        span("=", "CMR10", 225.0, 652.0, 10.0),
+        span("\u{3b2}", "T3Font_1", 235.0, 652.0, 10.0),
+        span("\u{2264} \u{3b3}", "T3Font_1", 210.0, 632.0, 10.0),
+        // plain text below
+        span("Plain text follows the equation.", "CMR10", 72.0, 600.0, 10.0),
+    ];
+    let type3: HashSet<String> = ["T3Font_1".to_string()].iter().cloned().collect();
+    let regions = detect(&spans, &type3);
+    assert_eq!(regions.len(), 2);
+
+    assert!(!regions[0].display);
+    assert_eq!(regions[0].bbox[0], 127.0);
+    assert!(regions[0].text.starts_with("x"));
+    // the footnote mark is on its own
+    assert!(regions[0].bbox[2] < 359.0);
+
+    assert!(regions[1].display);
+    assert!(regions[1].text.contains('\n'));
+    assert!(regions[1].bbox[1] < 632.0 && regions[1].bbox[3] > 652.0);
+}
@@ -0,0 +1,527 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use fonts::subset::{self, Replacements};
+use info;
+use pages::{self, Page};
+use parsers::cos::DictNode;
+use provenance::Provenance;
+use writer::Writer;
+
+/*
+ * Merging documents into one, their pages one after the other.
+ *
+ *   let options = MergeOptions { outline: true, toc: true, subset_fonts: false };
+ *   merge::merge(&[Path::new("a.pdf"), Path::new("b.pdf")], Path::new("packet.pdf"), &options)?;
+ *
+ * Unlike the other rewrites this writes a new file from scratch: every page
+ * is copied along with everything it references (content, resources,
+ * annotations...), renumbered after what was copied before it. The
+ * returned Provenance says which input each object was copied from. Streams are
+ * copied still encoded. What a page inherited from its page tree is set on
+ * it directly, since the tree it came from isn't copied.
+ *
+ * Everything that hangs off a document's catalog is left behind: its
+ * outline, named destinations, form and structure tree. Links to pages of
+ * the same document keep working, named ones don't. Encrypted documents
+ * are refused, their strings and streams would only make sense with the
+ * key.
+ *
+ * `subset_fonts` cuts each document's embedded fonts down to what its pages
+ * show on the way, see fonts::subset: the same font embedded whole by
+ * every document of a packet adds up.
+ *
+ * For packets people have to find their way around, `outline` adds a
+ * bookmark per document and `toc` a contents page in front of them, with
+ * a line per document linking to its first page. Documents are named by
+ * their /Info title, or their file name if they have none.
+ *
+ * `split` goes the other way, copying some of a document's pages into a
+ * new one the same way, along with its /Info:
+ *
+ *   let ranges = PageRanges::parse("3-7").unwrap();
+ *   merge::split(Path::new("book.pdf"), &ranges, Path::new("chapter.pdf"))?;
+ *
+ * Only what the chosen pages lead to is copied. References to the pages
+ * left out (link destinations, an annotation's /P) become null rather
+ * than dragging those pages along.
+ */
+
+// Letter, which is what most packets are printed on.
+const TOC_SIZE: [f64; 4] = [0.0, 0.0, 612.0, 792.0];
+const TOC_MARGIN: f64 = 72.0;
+const TOC_FONT_SIZE: f64 = 12.0;
+const TOC_LEADING: f64 = 20.0;
+// lines of entries on a contents page, below the heading
+const TOC_LINES: usize = 28;
+// characters of a title that fit before the page number, in Helvetica
+const TOC_TITLE_LENGTH: usize = 72;
+// every digit is 556/1000 of an em in Helvetica
+const DIGIT_WIDTH: f64 = 0.556;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct MergeOptions {
+    // a bookmark per document
+    pub outline: bool,
+    // contents pages in front
+    pub toc: bool,
+    // fonts cut down to the glyphs shown
+    pub subset_fonts: bool,
+}
+
+// A document that went in, for the outline and contents.
+#[derive(Debug, PartialEq, Clone)]
+struct Entry {
+    title: String,
+    // its first page in the merged document, as an object and an index
+    page_id: u64,
+    page: usize,
+}
+
+// Copies objects out of one source document, each at most once.
+pub(crate) struct Copier<'a> {
+    doc: &'a mut Document,
+    // source object number to the one it's copied as
+    pub(crate) ids: HashMap<u64, u64>,
+    // source objects not to copy, references to them become null
+    pub(crate) left_out: HashSet<u64>,
+    // numbered but not written yet
+    pending: Vec<(u64, u64)>,
+    // source objects written out as something else, see fonts::subset
+    pub(crate) replaced: Replacements,
+}
+
+pub fn merge(inputs: &[&Path], output: &Path, options: &MergeOptions) -> Result<Provenance> {
+    let mut out = Writer::new();
+    let mut provenance = Provenance::new();
+    let catalog_id = out.reserve();
+    let root_id = out.reserve();
+
+    // the contents come first, so their pages are numbered up front
+    let toc_pages = if options.toc { inputs.len().div_ceil(TOC_LINES).max(1) } else { 0 };
+    let toc_ids: Vec<u64> = (0..toc_pages).map(|_| out.reserve()).collect();
+    let mut kids = toc_ids.clone();
+    let mut entries = Vec::new();
+
+    for input in inputs {
+        let policy = DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() };
+        let mut doc = Document::open(input, Options { policy, ..Options::default() })?;
+        let title = info::info(&mut doc)?.and_then(|info| info.title).filter(|title| !title.trim().is_empty());
+        let title = title.unwrap_or_else(|| input.file_stem().unwrap_or(input.as_os_str()).to_string_lossy().into_owned());
+        let pages = pages::pages(&mut doc)?;
+
+        // pages get their numbers first, for links and annotations pointing
+        // back at them
+        let ids: Vec<u64> = pages.iter().map(|_| out.reserve()).collect();
+        let replaced = if options.subset_fonts { subset::replacements(&mut doc)?.0 } else { HashMap::new() };
+        let mut copier = Copier::new(&mut doc);
+        copier.replaced = replaced;
+        for (page, &id) in pages.iter().zip(&ids) {
+            if let Some(source) = page.id {
+                copier.ids.insert(source, id);
+            }
+        }
+        for (page, &id) in pages.iter().zip(&ids) {
+            let mut dict = copier.renumber(&flatten(page), &mut out);
+            if let DictNode::Dict(ref mut map) = dict {
+                map.insert("Parent".to_string(), DictNode::reference(root_id));
+            }
+            out.object(id, &dict)?;
+            copier.copy_pending(&mut out)?;
+        }
+        copier.record(&mut provenance, input);
+        if let Some(&page_id) = ids.first() {
+            entries.push(Entry { title, page_id, page: kids.len() });
+        }
+        kids.extend(ids);
+    }
+
+    if options.toc {
+        let font_id = out.reserve();
+        out.object(font_id, &font())?;
+        for (index, &id) in toc_ids.iter().enumerate() {
+            let start = (index * TOC_LINES).min(entries.len());
+            let lines = &entries[start..(start + TOC_LINES).min(entries.len())];
+            let content = toc_content(lines, index == 0);
+            let content_id = out.reserve();
+            let stream = DictNode::Dict(HashMap::from([("Length".to_string(), DictNode::Int(content.len() as i64))]));
+            out.stream(content_id, &stream, &content)?;
+
+            let mut annots = Vec::new();
+            for (line, entry) in lines.iter().enumerate() {
+                let annot_id = out.reserve();
+                out.object(annot_id, &toc_link(entry, line, index == 0))?;
+                annots.push(DictNode::reference(annot_id));
+            }
+            let page = HashMap::from([
+                ("Type".to_string(), DictNode::name("Page")),
+                ("Parent".to_string(), DictNode::reference(root_id)),
+                ("MediaBox".to_string(), rect(TOC_SIZE)),
+                ("Resources".to_string(), DictNode::Dict(HashMap::from([
+                    ("Font".to_string(), DictNode::Dict(HashMap::from([("F1".to_string(), DictNode::reference(font_id))]))),
+                ]))),
+                ("Contents".to_string(), DictNode::reference(content_id)),
+                ("Annots".to_string(), DictNode::Array(annots)),
+            ]);
+            out.object(id, &DictNode::Dict(page))?;
+        }
+    }
+
+    let mut catalog = HashMap::from([
+        ("Type".to_string(), DictNode::name("Catalog")),
+        ("Pages".to_string(), DictNode::reference(root_id)),
+    ]);
+    if options.outline && !entries.is_empty() {
+        let mut items: Vec<(String, u64)> = entries.iter().map(|entry| (entry.title.clone(), entry.page_id)).collect();
+        if let Some(&first) = toc_ids.first() {
+            items.insert(0, ("Contents".to_string(), first));
+        }
+        let outlines_id = out.reserve();
+        for _ in &items {
+            out.reserve();
+        }
+        for (id, object) in outline(outlines_id, &items) {
+            out.object(id, &object)?;
+        }
+        catalog.insert("Outlines".to_string(), DictNode::reference(outlines_id));
+        catalog.insert("PageMode".to_string(), DictNode::name("UseOutlines"));
+    }
+    out.object(catalog_id, &DictNode::Dict(catalog))?;
+    let root = HashMap::from([
+        ("Type".to_string(), DictNode::name("Pages")),
+        ("Kids".to_string(), DictNode::Array(kids.iter().map(|&id| DictNode::reference(id)).collect())),
+        ("Count".to_string(), DictNode::Int(kids.len() as i64)),
+    ]);
+    out.object(root_id, &DictNode::Dict(root))?;
+
+    fs::write(output, out.finish(catalog_id))?;
+    Ok(provenance)
+}
+
+// Pages picked by number, "1-3,7,10-": ranges from 1, inclusive, an open
+// end running to the last page.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PageRanges(Vec<(usize, Option<usize>)>);
+
+impl PageRanges {
+    pub fn parse(spec: &str) -> Option<PageRanges> {
+        let number = |text: &str| text.trim().parse::<usize>().ok().filter(|&number| number > 0);
+        let ranges = spec.split(',')
+            .map(|range| match range.split_once('-') {
+                Some((first, last)) if last.trim().is_empty() => Some((number(first)?, None)),
+                Some((first, last)) => Some((number(first)?, Some(number(last)?))).filter(|&(first, last)| Some(first) <= last),
+                None => number(range).map(|page| (page, Some(page))),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(PageRanges(ranges))
+    }
+
+    // The page indexes, from 0, in the order given and each once. None if
+    // a range goes past the last of `count` pages.
+    pub fn indexes(&self, count: usize) -> Option<Vec<usize>> {
+        let mut indexes = Vec::new();
+        for &(first, last) in &self.0 {
+            let last = last.unwrap_or(count);
+            if last > count || first > count {
+                return None;
+            }
+            for index in first - 1..last {
+                if !indexes.contains(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        Some(indexes)
+    }
+}
+
+// Copies the pages `ranges` picks out of `input` into a new file.
+pub fn split(input: &Path, ranges: &PageRanges, output: &Path) -> Result<()> {
+    let policy = DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() };
+    let mut doc = Document::open(input, Options { policy, ..Options::default() })?;
+    let pages = pages::pages(&mut doc)?;
+    let indexes = ranges.indexes(pages.len())
+        .ok_or_else(|| PdfError::InvalidArgument(format!("page range past the last of {} pages", pages.len())))?;
+    let info = doc.trailer.get("Info").cloned();
+
+    let mut out = Writer::new();
+    let catalog_id = out.reserve();
+    let root_id = out.reserve();
+    let ids: Vec<u64> = indexes.iter().map(|_| out.reserve()).collect();
+    let mut copier = Copier::new(&mut doc);
+    copier.left_out = pages.iter().filter_map(|page| page.id).collect();
+    for (&index, &id) in indexes.iter().zip(&ids) {
+        if let Some(source) = pages[index].id {
+            copier.left_out.remove(&source);
+            copier.ids.insert(source, id);
+        }
+    }
+    for (&index, &id) in indexes.iter().zip(&ids) {
+        let mut dict = copier.renumber(&flatten(&pages[index]), &mut out);
+        if let DictNode::Dict(ref mut map) = dict {
+            map.insert("Parent".to_string(), DictNode::reference(root_id));
+        }
+        out.object(id, &dict)?;
+        copier.copy_pending(&mut out)?;
+    }
+    let info = info.map(|info| copier.renumber(&info, &mut out));
+    copier.copy_pending(&mut out)?;
+
+    out.object(catalog_id, &DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Catalog")),
+        ("Pages".to_string(), DictNode::reference(root_id)),
+    ])))?;
+    out.object(root_id, &DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Pages")),
+        ("Kids".to_string(), DictNode::Array(ids.iter().map(|&id| DictNode::reference(id)).collect())),
+        ("Count".to_string(), DictNode::Int(ids.len() as i64)),
+    ])))?;
+    let mut trailer = HashMap::from([("Root".to_string(), DictNode::reference(catalog_id))]);
+    if let Some(info @ DictNode::ObjectReference(..)) = info {
+        trailer.insert("Info".to_string(), info);
+    }
+    fs::write(output, out.finish_with(trailer))?;
+    Ok(())
+}
+
+impl<'a> Copier<'a> {
+    pub(crate) fn new(doc: &'a mut Document) -> Copier<'a> {
+        Copier { doc, ids: HashMap::new(), left_out: HashSet::new(), pending: Vec::new(), replaced: HashMap::new() }
+    }
+
+    // `node` with references to source objects replaced by references to
+    // their copies, numbering the ones not seen yet. References to objects
+    // the source doesn't have are null, as they'd read there.
+    pub(crate) fn renumber(&mut self, node: &DictNode, out: &mut Writer) -> DictNode {
+        match *node {
+            DictNode::ObjectReference(id, _) if id >= 0 => {
+                let id = id as u64;
+                if let Some(&copy) = self.ids.get(&id) {
+                    return DictNode::reference(copy);
+                }
+                if self.left_out.contains(&id) || self.doc.xref.get(id as usize).is_none_or(|entry| entry.is_free) {
+                    return DictNode::Null;
+                }
+                let copy = out.reserve();
+                self.ids.insert(id, copy);
+                self.pending.push((id, copy));
+                DictNode::reference(copy)
+            },
+            DictNode::ObjectReference(..) => DictNode::Null,
+            DictNode::Array(ref items) => DictNode::Array(items.iter().map(|item| self.renumber(item, out)).collect()),
+            DictNode::Dict(ref map) => DictNode::Dict(map.iter().map(|(key, value)| (key.clone(), self.renumber(value, out))).collect()),
+            ref node => node.clone(),
+        }
+    }
+
+    // Records what's been copied as coming from `file`.
+    pub(crate) fn record(&self, provenance: &mut Provenance, file: &Path) {
+        let file = provenance.add_file(file);
+        for (&source, &copy) in &self.ids {
+            provenance.record(copy, file, source);
+        }
+    }
+
+    // Writes out everything numbered so far, and what that references.
+    pub(crate) fn copy_pending(&mut self, out: &mut Writer) -> Result<()> {
+        while let Some((source, copy)) = self.pending.pop() {
+            if let Some((object, data)) = self.replaced.remove(&source) {
+                let object = self.renumber(&object, out);
+                match data {
+                    Some(data) => out.stream(copy, &object, &data)?,
+                    None => out.object(copy, &object)?,
+                }
+                continue;
+            }
+            let entry = match self.doc.xref.get(source as usize) {
+                Some(entry) => entry.clone(),
+                None => continue,
+            };
+            match self.doc.raw_object(&entry)? {
+                (DictNode::Dict(mut dict), Some(raw)) => {
+                    // an indirect /Length would be copied for nothing
+                    dict.insert("Length".to_string(), DictNode::Int(raw.len() as i64));
+                    let dict = self.renumber(&DictNode::Dict(dict), out);
+                    out.stream(copy, &dict, &raw)?;
+                },
+                (object, _) => {
+                    let object = self.renumber(&object, out);
+                    out.object(copy, &object)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+// The page's dictionary with what it inherited set on it, and without its
+// /Parent.
+fn flatten(page: &Page) -> DictNode {
+    let mut dict = page.dict.as_dict().cloned().unwrap_or_default();
+    dict.remove("Parent");
+    dict.insert("Type".to_string(), DictNode::name("Page"));
+    dict.insert("MediaBox".to_string(), rect(page.media_box));
+    if page.crop_box != page.media_box {
+        dict.insert("CropBox".to_string(), rect(page.crop_box));
+    } else {
+        dict.remove("CropBox");
+    }
+    if page.rotate != 0 {
+        dict.insert("Rotate".to_string(), DictNode::Int(page.rotate));
+    } else {
+        dict.remove("Rotate");
+    }
+    dict.insert("Resources".to_string(), page.resources.clone());
+    DictNode::Dict(dict)
+}
+
+fn rect(rect: [f64; 4]) -> DictNode {
+    DictNode::Array(rect.iter().map(|&value| number(value)).collect())
+}
+
+fn number(value: f64) -> DictNode {
+    if value.fract() == 0.0 { DictNode::Int(value as i64) } else { DictNode::Float(value) }
+}
+
+// A PDF text string: PDFDocEncoding, which Latin-1 mostly agrees with, or
+// UTF-16BE when that won't do.
+fn text_string(text: &str) -> DictNode {
+    if text.chars().all(|c| (c as u32) < 0x80 || (0xa0..=0xff).contains(&(c as u32))) {
+        return DictNode::LiteralString(text.chars().map(|c| c as u8).collect());
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    DictNode::HexString(bytes)
+}
+
+// The outline dictionary, numbered `first_id`, and an item per (title,
+// page object) after it.
+fn outline(first_id: u64, items: &[(String, u64)]) -> Vec<(u64, DictNode)> {
+    let last_id = first_id + items.len() as u64;
+    let mut objects = vec![(first_id, DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Outlines")),
+        ("First".to_string(), DictNode::reference(first_id + 1)),
+        ("Last".to_string(), DictNode::reference(last_id)),
+        ("Count".to_string(), DictNode::Int(items.len() as i64)),
+    ])))];
+    for (index, (title, page)) in items.iter().enumerate() {
+        let id = first_id + 1 + index as u64;
+        let mut item = HashMap::from([
+            ("Title".to_string(), text_string(title)),
+            ("Parent".to_string(), DictNode::reference(first_id)),
+            ("Dest".to_string(), DictNode::Array(vec![DictNode::reference(*page), DictNode::name("Fit")])),
+        ]);
+        if id > first_id + 1 {
+            item.insert("Prev".to_string(), DictNode::reference(id - 1));
+        }
+        if id < last_id {
+            item.insert("Next".to_string(), DictNode::reference(id + 1));
+        }
+        objects.push((id, DictNode::Dict(item)));
+    }
+    objects
+}
+
+// Plain Helvetica, one of the fonts every viewer has.
+fn font() -> DictNode {
+    DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Font")),
+        ("Subtype".to_string(), DictNode::name("Type1")),
+        ("BaseFont".to_string(), DictNode::name("Helvetica")),
+        ("Encoding".to_string(), DictNode::name("WinAnsiEncoding")),
+    ]))
+}
+
+// Where the baseline of line `line` of a contents page is, the first page
+// having the heading above its lines.
+fn toc_baseline(line: usize, first: bool) -> f64 {
+    let top = TOC_SIZE[3] - TOC_MARGIN - if first { 2.0 * TOC_LEADING } else { 0.0 };
+    top - TOC_FONT_SIZE - line as f64 * TOC_LEADING
+}
+
+// A contents page: a line per document, its title on the left and the
+// number of its first page on the right.
+fn toc_content(entries: &[Entry], first: bool) -> Vec<u8> {
+    let mut content = String::from("BT\n");
+    if first {
+        content.push_str(&format!("/F1 18 Tf 1 0 0 1 {} {} Tm (Contents) Tj\n", TOC_MARGIN, TOC_SIZE[3] - TOC_MARGIN - 18.0));
+    }
+    content.push_str(&format!("/F1 {} Tf\n", TOC_FONT_SIZE));
+    for (line, entry) in entries.iter().enumerate() {
+        let y = toc_baseline(line, first);
+        let number = (entry.page + 1).to_string();
+        let x = TOC_SIZE[2] - TOC_MARGIN - number.len() as f64 * DIGIT_WIDTH * TOC_FONT_SIZE;
+        let mut title: String = entry.title.chars().take(TOC_TITLE_LENGTH).collect();
+        if title.len() < entry.title.len() {
+            title.push_str("...");
+        }
+        // WinAnsiEncoding is Latin-1 for what's printable
+        let title = DictNode::LiteralString(title.chars().map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' }).collect());
+        content.push_str(&format!("1 0 0 1 {} {} Tm {} Tj\n", TOC_MARGIN, y, title));
+        content.push_str(&format!("1 0 0 1 {} {} Tm ({}) Tj\n", number_text(x), y, number));
+    }
+    content.push_str("ET\n");
+    content.into_bytes()
+}
+
+fn number_text(value: f64) -> String {
+    format!("{}", (value * 1000.0).round() / 1000.0)
+}
+
+// The link over line `line` of a contents page.
+fn toc_link(entry: &Entry, line: usize, first: bool) -> DictNode {
+    let y = toc_baseline(line, first);
+    DictNode::Dict(HashMap::from([
+        ("Type".to_string(), DictNode::name("Annot")),
+        ("Subtype".to_string(), DictNode::name("Link")),
+        ("Rect".to_string(), rect([TOC_MARGIN, y - 4.0, TOC_SIZE[2] - TOC_MARGIN, y + TOC_FONT_SIZE])),
+        ("Border".to_string(), DictNode::Array(vec![DictNode::Int(0), DictNode::Int(0), DictNode::Int(0)])),
+        ("Dest".to_string(), DictNode::Array(vec![DictNode::reference(entry.page_id), DictNode::name("Fit")])),
+    ]))
+}
+
+#[test]
+fn test_outline() {
+    let objects = outline(10, &[("Contents".to_string(), 3), ("Résumé".to_string(), 5), ("報告".to_string(), 9)]);
+    let ids: Vec<u64> = objects.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, vec![10, 11, 12, 13]);
+    assert_eq!(objects[0].1.to_string(), "<< /Count 3 /First 11 0 R /Last 13 0 R /Type /Outlines >>");
+    assert_eq!(objects[1].1.to_string(), "<< /Dest [3 0 R /Fit] /Next 12 0 R /Parent 10 0 R /Title (Contents) >>");
+    assert_eq!(objects[2].1.get("Title"), Some(&DictNode::LiteralString(b"R\xe9sum\xe9".to_vec())));
+    assert_eq!(objects[3].1.to_string(), "<< /Dest [9 0 R /Fit] /Parent 10 0 R /Prev 12 0 R /Title <feff5831544a> >>");
+}
+
+#[test]
+fn test_toc_content() {
+    let entries = vec![
+        Entry { title: "Cover letter".to_string(), page_id: 4, page: 1 },
+        Entry { title: "Appendix (scans)".to_string(), page_id: 9, page: 12 },
+    ];
+    let content = String::from_utf8(toc_content(&entries, true)).unwrap();
+    assert_eq!(content, "BT\n\
+                         /F1 18 Tf 1 0 0 1 72 702 Tm (Contents) Tj\n\
+                         /F1 12 Tf\n\
+                         1 0 0 1 72 668 Tm (Cover letter) Tj\n\
+                         1 0 0 1 533.328 668 Tm (2) Tj\n\
+                         1 0 0 1 72 648 Tm (Appendix \\(scans\\)) Tj\n\
+                         1 0 0 1 526.656 648 Tm (13) Tj\n\
+                         ET\n");
+    assert_eq!(toc_link(&entries[1], 1, true).get("Rect").unwrap().to_string(), "[72 644 540 660]");
+}
+
+#[test]
+fn test_page_ranges() {
+    let ranges = PageRanges::parse("3-5,1, 9-").unwrap();
+    assert_eq!(ranges.indexes(10), Some(vec![2, 3, 4, 0, 8, 9]));
+    // past the end
+    assert_eq!(ranges.indexes(8), None);
+    assert_eq!(PageRanges::parse("2-4,3").unwrap().indexes(5), Some(vec![1, 2, 3]));
+    assert_eq!(PageRanges::parse("0-2"), None);
+    assert_eq!(PageRanges::parse("5-3"), None);
+    assert_eq!(PageRanges::parse("a"), None);
+}
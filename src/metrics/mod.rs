@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/*
+ * Counters of what a document made us do, for services that want to graph
+ * it rather than grep logs for it. `Document::metrics` has the counts so
+ * far, and a callback set with `Document::set_metrics_callback` gets the
+ * final counts when the document is dropped, which is the natural place to
+ * add them to process-wide counters:
+ *
+ *   doc.set_metrics_callback(move |metrics| registry.add(metrics));
+ *
+ * Display writes the Prometheus text format, so a service without a
+ * Prometheus client can serve it as is.
+ *
+ * Streams decoded by `pipeline::decode_parallel` happen outside the
+ * document and aren't counted.
+ */
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Metrics {
+    // objects read out of the file or out of object streams
+    pub objects_parsed: u64,
+    // lookups by object number (references, the catalog...) answered from
+    // the document's cache, or not
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub streams_decoded: u64,
+    // what stream filters turned out, inflated Flate data mostly
+    pub bytes_decoded: u64,
+    // by `Warning::category`
+    pub warnings: BTreeMap<&'static str, u64>,
+}
+
+pub type MetricsCallback = Box<dyn FnMut(&Metrics) + Send>;
+
+impl Metrics {
+    // Adds up metrics collected separately, e.g. for several documents.
+    pub fn merge(&mut self, other: &Metrics) {
+        self.objects_parsed += other.objects_parsed;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.streams_decoded += other.streams_decoded;
+        self.bytes_decoded += other.bytes_decoded;
+        for (&category, &count) in &other.warnings {
+            *self.warnings.entry(category).or_insert(0) += count;
+        }
+    }
+
+    pub(crate) fn warning(&mut self, category: &'static str) {
+        *self.warnings.entry(category).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counters = [
+            ("objects_parsed", self.objects_parsed),
+            ("cache_hits", self.cache_hits),
+            ("cache_misses", self.cache_misses),
+            ("streams_decoded", self.streams_decoded),
+            ("bytes_decoded", self.bytes_decoded),
+        ];
+        for &(name, value) in &counters {
+            writeln!(f, "# TYPE nails_pdf_{}_total counter", name)?;
+            writeln!(f, "nails_pdf_{}_total {}", name, value)?;
+        }
+        writeln!(f, "# TYPE nails_pdf_warnings_total counter")?;
+        for (category, count) in &self.warnings {
+            writeln!(f, "nails_pdf_warnings_total{{category=\"{}\"}} {}", category, count)?;
+        }
+        Ok(())
+    }
+}
+
+// Counts what's read through it into a counter shared with the document,
+// for decoded streams handed out as readers.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub(crate) fn new(inner: R, count: Arc<AtomicU64>) -> CountingReader<R> {
+        CountingReader { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_metrics() {
+    let mut metrics = Metrics { objects_parsed: 3, bytes_decoded: 100, ..Metrics::default() };
+    metrics.warning("truncated_object");
+    let mut total = metrics.clone();
+    total.merge(&metrics);
+    assert_eq!(total.objects_parsed, 6);
+    assert_eq!(total.warnings["truncated_object"], 2);
+
+    let text = total.to_string();
+    assert!(text.contains("\nnails_pdf_objects_parsed_total 6\n"));
+    assert!(text.ends_with("nails_pdf_warnings_total{category=\"truncated_object\"} 2\n"));
+
+    let count = Arc::new(AtomicU64::new(0));
+    let mut reader = CountingReader::new(&b"hello"[..], count.clone());
+    reader.read_to_end(&mut Vec::new()).unwrap();
+    assert_eq!(count.load(Ordering::Relaxed), 5);
+}
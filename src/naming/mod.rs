@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/*
+ * File naming for anything we write out of a PDF: images, fonts, attached
+ * files, raw streams.
+ *
+ * Names embedded in a PDF (attachment filenames, font names) are attacker
+ * controlled, so they're never trusted as paths: "../../.bashrc" or
+ * "C:\Windows\evil.dll" get flattened into a single harmless file name.
+ * Output names come from a template such as "{page}-{index}.{ext}" with
+ * the following placeholders:
+ *
+ *   {page}   page number, 0 for things that don't belong to a page
+ *   {index}  running index of the output
+ *   {obj}    object number the data came from
+ *   {name}   the (sanitized) name embedded in the PDF, if any
+ *   {ext}    file extension
+ */
+
+pub const DEFAULT_TEMPLATE: &str = "{page}-{index}.{ext}";
+
+// Longest name we'll produce, comfortably under the 255 byte limit most
+// filesystems have.
+const MAX_NAME_LEN: usize = 200;
+
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct NameFields<'a> {
+    pub page: Option<usize>,
+    pub index: usize,
+    pub object: u64,
+    pub name: Option<&'a str>,
+    pub ext: &'a str,
+}
+
+// Turns an arbitrary string into a file name that is safe on Windows, macOS
+// and Linux, and can't escape the output directory.
+pub fn sanitize_filename(name: &str) -> String {
+    // only ever keep the last path component, whatever the separator
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let mut sanitized: String = name.chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows silently drops trailing dots and spaces, and leading dots
+    // make hidden files on everything else
+    sanitized = sanitized.trim_end_matches(['.', ' ']).to_string();
+    if sanitized.starts_with('.') {
+        sanitized = format!("_{}", &sanitized[1..]);
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or("").to_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        sanitized = format!("_{}", sanitized);
+    }
+
+    if sanitized.len() > MAX_NAME_LEN {
+        sanitized = truncate_keeping_extension(&sanitized, MAX_NAME_LEN);
+    }
+
+    if sanitized.is_empty() {
+        "unnamed".to_string()
+    } else {
+        sanitized
+    }
+}
+
+fn truncate_keeping_extension(name: &str, max_len: usize) -> String {
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if name.len() - dot <= 16 => (&name[..dot], &name[dot..]),
+        _ => (name, ""),
+    };
+    let mut end = max_len.saturating_sub(ext.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
+
+// Hands out unique, sanitized file names from a template.
+pub struct OutputNamer {
+    template: String,
+    used: HashSet<String>,
+}
+
+impl OutputNamer {
+    pub fn new(template: &str) -> OutputNamer {
+        OutputNamer { template: template.to_string(), used: HashSet::new() }
+    }
+
+    pub fn name(&mut self, fields: &NameFields) -> String {
+        let page = fields.page.unwrap_or(0).to_string();
+        let name = sanitize_filename(&self.template
+            .replace("{page}", &page)
+            .replace("{index}", &fields.index.to_string())
+            .replace("{obj}", &fields.object.to_string())
+            .replace("{name}", &sanitize_filename(fields.name.unwrap_or("unnamed")))
+            .replace("{ext}", &sanitize_filename(fields.ext)));
+        self.unique(name)
+    }
+
+    pub fn path(&mut self, dir: &Path, fields: &NameFields) -> PathBuf {
+        dir.join(self.name(fields))
+    }
+
+    // Appends -1, -2... before the extension until the name is free.
+    // Compared case-insensitively since that's how Windows and macOS see it.
+    fn unique(&mut self, name: String) -> String {
+        let (stem, ext) = match name.rfind('.') {
+            Some(dot) if dot > 0 => (name[..dot].to_string(), name[dot..].to_string()),
+            _ => (name.clone(), String::new()),
+        };
+        let mut candidate = name;
+        let mut counter = 1;
+        while self.used.contains(&candidate.to_lowercase()) {
+            candidate = format!("{}-{}{}", stem, counter, ext);
+            counter += 1;
+        }
+        self.used.insert(candidate.to_lowercase());
+        candidate
+    }
+}
+
+impl Default for OutputNamer {
+    fn default() -> OutputNamer {
+        OutputNamer::new(DEFAULT_TEMPLATE)
+    }
+}
+
+#[test]
+fn test_sanitize_path_traversal() {
+    assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+    assert_eq!(sanitize_filename("C:\\Windows\\evil.dll"), "evil.dll");
+    assert_eq!(sanitize_filename(".."), "unnamed");
+    assert_eq!(sanitize_filename(".bashrc"), "_bashrc");
+}
+
+#[test]
+fn test_sanitize_reserved() {
+    assert_eq!(sanitize_filename("what?<are>:these*.txt"), "what__are__these_.txt");
+    assert_eq!(sanitize_filename("tab\there"), "tab_here");
+    assert_eq!(sanitize_filename("con.txt"), "_con.txt");
+    assert_eq!(sanitize_filename("LPT1"), "_LPT1");
+    assert_eq!(sanitize_filename("report. "), "report");
+    assert_eq!(sanitize_filename(""), "unnamed");
+}
+
+#[test]
+fn test_sanitize_long_name() {
+    let long = format!("{}.pdf", "a".repeat(300));
+    let sanitized = sanitize_filename(&long);
+    assert_eq!(sanitized.len(), MAX_NAME_LEN);
+    assert!(sanitized.ends_with(".pdf"));
+}
+
+#[test]
+fn test_template() {
+    let mut namer = OutputNamer::default();
+    let fields = NameFields { page: Some(3), index: 1, object: 12, name: None, ext: "png" };
+    assert_eq!(namer.name(&fields), "3-1.png");
+
+    let mut namer = OutputNamer::new("{name}_{obj}.{ext}");
+    let fields = NameFields { page: None, index: 0, object: 7, name: Some("../logo"), ext: "jpg" };
+    assert_eq!(namer.name(&fields), "logo_7.jpg");
+}
+
+#[test]
+fn test_duplicate_names() {
+    let mut namer = OutputNamer::new("{name}");
+    let fields = NameFields { name: Some("Invoice.pdf"), ..Default::default() };
+    assert_eq!(namer.name(&fields), "Invoice.pdf");
+    assert_eq!(namer.name(&fields), "Invoice-1.pdf");
+    let fields = NameFields { name: Some("invoice.PDF"), ..Default::default() };
+    assert_eq!(namer.name(&fields), "invoice-2.PDF");
+}
@@ -0,0 +1,224 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, Options};
+use error::Result;
+use pages;
+use parsers::content::{Operation, Operations};
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Rewrites content streams into one canonical form, so two streams that
+ * draw the same thing the same way come out byte for byte the same and
+ * diff line by line:
+ *
+ *   - one operation per line, operands separated by a single space, and
+ *     no comments
+ *   - numbers rounded to `precision` decimals, without trailing zeros
+ *     ("1.50000" and "1.5" are both "1.5", "-0.0001" is "0")
+ *   - q/Q pairs with nothing in between dropped, and a pair wrapped
+ *     directly around another pair (q q ... Q Q) reduced to one
+ *
+ * Saves that aren't restored, or restores without a save, are left alone,
+ * what they do depends on what follows the stream.
+ *
+ * Inline images keep their data as is, between "ID " and a line with "EI",
+ * and have their dictionary written with the full key names, which are
+ * allowed there too.
+ *
+ * `normalize` does it for every page's content streams, appending them
+ * uncompressed with a new xref section like transcode does. Streams that
+ * don't tokenize are left as they are.
+ */
+
+pub const DEFAULT_PRECISION: usize = 3;
+
+// The content stream `content` in normal form, see above.
+pub fn normalize_content(content: &[u8], precision: usize) -> Result<Vec<u8>> {
+    let operations = Operations::new(content).collect::<Result<Vec<Operation>>>()?;
+    Ok(write_operations(&collapse(operations), precision))
+}
+
+// `operations` written out one per line, numbers rounded to `precision`
+// decimals.
+pub(crate) fn write_operations(operations: &[Operation], precision: usize) -> Vec<u8> {
+    let mut normalized = Vec::new();
+    for operation in operations {
+        let mut line = String::new();
+        if let Some((dict, data)) = operation.inline_image() {
+            line.push_str("BI");
+            let map = dict.as_dict().cloned().unwrap_or_default();
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys {
+                line.push_str(&format!(" {} ", DictNode::name(key)));
+                write_operand(&mut line, &map[key], precision);
+            }
+            line.push_str(" ID ");
+            normalized.extend_from_slice(line.as_bytes());
+            normalized.extend_from_slice(data);
+            normalized.extend_from_slice(b"\nEI\n");
+            continue;
+        }
+        for operand in &operation.operands {
+            write_operand(&mut line, operand, precision);
+            line.push(' ');
+        }
+        line.push_str(&operation.operator);
+        line.push('\n');
+        normalized.extend_from_slice(line.as_bytes());
+    }
+    normalized
+}
+
+// Normalizes the content streams of every page of `input` into `output`.
+// Returns how many streams were rewritten.
+pub fn normalize(input: &Path, output: &Path, precision: usize) -> Result<usize> {
+    let mut doc = Document::open(input, Options::default())?;
+    let mut data = fs::read(input)?;
+    let mut xref = doc.xref.clone();
+
+    let mut seen = HashSet::new();
+    let mut rewritten = 0;
+    for page in pages::pages(&mut doc)? {
+        let streams = match page.get("Contents") {
+            Some(contents @ DictNode::ObjectReference(..)) => match doc.resolve(contents)? {
+                DictNode::Array(streams) => streams,
+                _ => vec![contents.clone()],
+            },
+            Some(DictNode::Array(streams)) => streams.clone(),
+            _ => Vec::new(),
+        };
+        for stream in streams {
+            let id = match stream {
+                DictNode::ObjectReference(id, _) if id >= 0 && seen.insert(id) => id as u64,
+                _ => continue,
+            };
+            let (dict, content) = match doc.referenced_stream(&stream)? {
+                Some(found) => found,
+                None => continue,
+            };
+            let normalized = match normalize_content(&content, precision) {
+                Ok(normalized) => normalized,
+                Err(_) => continue,
+            };
+            let mut map: HashMap<String, DictNode> = dict.as_dict().cloned().unwrap_or_default();
+            for key in &["Filter", "DecodeParms", "DL", "F", "FFilter", "FDecodeParms"] {
+                map.remove(*key);
+            }
+            map.insert("Length".to_string(), DictNode::Int(normalized.len() as i64));
+            transcode::append_stream(&mut data, &mut xref, id, &DictNode::Dict(map), &normalized)?;
+            rewritten += 1;
+        }
+    }
+
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(rewritten)
+}
+
+// Drops redundant q/Q pairs until there are none left.
+fn collapse(mut operations: Vec<Operation>) -> Vec<Operation> {
+    loop {
+        // index of the matching Q for each q that has one
+        let mut matching = HashMap::new();
+        let mut saves = Vec::new();
+        for (index, operation) in operations.iter().enumerate() {
+            match operation.operator.as_str() {
+                "q" => saves.push(index),
+                "Q" => {
+                    if let Some(save) = saves.pop() {
+                        matching.insert(save, index);
+                    }
+                },
+                _ => (),
+            }
+        }
+        let mut dropped = HashSet::new();
+        for (&save, &restore) in &matching {
+            let empty = restore == save + 1;
+            let wraps_pair = matching.get(&(save + 1)) == Some(&(restore - 1));
+            if empty || wraps_pair {
+                dropped.insert(save);
+                dropped.insert(restore);
+            }
+        }
+        if dropped.is_empty() {
+            return operations;
+        }
+        operations = operations.into_iter()
+            .enumerate()
+            .filter(|(index, _)| !dropped.contains(index))
+            .map(|(_, operation)| operation)
+            .collect();
+    }
+}
+
+fn write_operand(out: &mut String, operand: &DictNode, precision: usize) {
+    match *operand {
+        DictNode::Float(value) => out.push_str(&number(value, precision)),
+        DictNode::Array(ref items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(' ');
+                }
+                write_operand(out, item, precision);
+            }
+            out.push(']');
+        },
+        DictNode::Dict(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push_str("<<");
+            for key in keys {
+                out.push_str(&format!(" {} ", DictNode::name(key)));
+                write_operand(out, &map[key], precision);
+            }
+            out.push_str(" >>");
+        },
+        ref other => out.push_str(&other.to_string()),
+    }
+}
+
+fn number(value: f64, precision: usize) -> String {
+    let mut formatted = format!("{:.*}", precision, value);
+    if formatted.contains('.') {
+        let end = formatted.trim_end_matches('0').trim_end_matches('.').len();
+        formatted.truncate(end);
+    }
+    if formatted == "-0" {
+        formatted = "0".to_string();
+    }
+    formatted
+}
+
+#[test]
+fn test_number() {
+    assert_eq!(number(1.5, 3), "1.5");
+    assert_eq!(number(2.0, 3), "2");
+    assert_eq!(number(0.33333, 3), "0.333");
+    assert_eq!(number(-0.0001, 3), "0");
+    assert_eq!(number(1234.5678, 0), "1235");
+}
+
+#[test]
+fn test_normalize_content() {
+    let content = b"% a comment\nq  1.00000 0 0 1.0 72.0001 700   cm q q BT /F1 12 Tf [(A) -250.5 (B)] TJ ET Q Q\nq Q Q Q\n\
+                    /P <</MCID 0.50 >>BDC EMC";
+    let normalized = normalize_content(content, 2).unwrap();
+    assert_eq!(String::from_utf8(normalized).unwrap(),
+        "q\n1 0 0 1 72 700 cm\nq\nBT\n/F1 12 Tf\n[(A) -250.5 (B)] TJ\nET\nQ\nQ\nQ\n/P << /MCID 0.5 >> BDC\nEMC\n");
+    // already normal
+    let again = normalize_content(b"q\n1 0 0 1 72 700 cm\nQ\n", 2).unwrap();
+    assert_eq!(again, b"q\n1 0 0 1 72 700 cm\nQ\n".to_vec());
+
+    // inline image data goes through untouched
+    let image = normalize_content(b"BI /W 2 /H 1 /BPC 8 /CS /G /F /AHx ID 0aEI\nEI Q", 2).unwrap();
+    assert_eq!(image, b"BI /BitsPerComponent 8 /ColorSpace /DeviceGray /Filter /ASCIIHexDecode /Height 1 /Width 2 ID 0aEI\nEI\nQ\n".to_vec());
+    assert_eq!(normalize_content(&image, 2).unwrap(), image);
+    assert!(normalize_content(b"q BI /W 1 ID \xff", 2).is_err());
+}
@@ -0,0 +1,129 @@
+// held to the audit's no-panic rule, see src/audit
+#![cfg_attr(feature = "audit", deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable,
+                                    clippy::todo, clippy::unimplemented))]
+
+use error::{PdfError, Result};
+use parsers;
+use parsers::cos::DictNode;
+
+/*
+ * Object streams (PDF 1.5+) pack lots of small non-stream objects into a
+ * single compressed stream so they can share a Flate dictionary:
+ *
+ *   12 0 obj
+ *   << /Type /ObjStm /N 3 /First 16 /Filter /FlateDecode >>
+ *   stream
+ *   ...
+ *
+ * Once decoded, the first /First bytes are N pairs of "object number,
+ * offset" and the objects themselves follow, offsets counting from /First.
+ * The xref entry of a compressed object gives the number of the object
+ * stream it lives in and its index in that stream.
+ */
+
+pub struct ObjectStream {
+    data: Vec<u8>,
+    // (object number, start of the object in `data`), in stream order
+    objects: Vec<(u64, usize)>,
+}
+
+impl ObjectStream {
+    // `data` is the decoded stream.
+    pub fn new(dict: &DictNode, data: Vec<u8>) -> Result<ObjectStream> {
+        let (count, first) = match *dict {
+            DictNode::Dict(ref map) => match (map.get("N"), map.get("First")) {
+                (Some(&DictNode::Int(count)), Some(&DictNode::Int(first))) if count >= 0 && first >= 0 => {
+                    (count as usize, first as usize)
+                },
+                _ => return header_error(),
+            },
+            _ => return header_error(),
+        };
+        if first > data.len() {
+            return header_error();
+        }
+
+        let header = String::from_utf8_lossy(&data[..first]).into_owned();
+        let numbers: Vec<u64> = header.split_whitespace()
+            .map(|number| number.parse::<u64>())
+            .collect::<::std::result::Result<_, _>>()?;
+        if numbers.len() / 2 < count {
+            return header_error();
+        }
+
+        let mut objects = Vec::with_capacity(count);
+        for pair in numbers.chunks(2).take(count) {
+            let start = first.saturating_add(pair[1] as usize);
+            if start > data.len() {
+                return header_error();
+            }
+            objects.push((pair[0], start));
+        }
+        Ok(ObjectStream { data, objects })
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Object number of the object at `index`.
+    pub fn id(&self, index: usize) -> Option<u64> {
+        self.objects.get(index).map(|&(id, _)| id)
+    }
+
+    // The raw bytes of the object at `index`, which run until the next
+    // object or the end of the stream.
+    pub fn source(&self, index: usize) -> Option<&[u8]> {
+        let &(_, start) = self.objects.get(index)?;
+        // offsets are supposed to be increasing, but don't rely on it
+        let end = self.objects.iter()
+            .map(|&(_, offset)| offset)
+            .filter(|&offset| offset > start)
+            .min()
+            .unwrap_or(self.data.len());
+        Some(&self.data[start..end])
+    }
+
+    pub fn object(&self, index: usize) -> Result<DictNode> {
+        match self.source(index) {
+            Some(source) => parsers::cos::parse_bytes(source),
+            None => Err(PdfError::InvalidArgument(format!("object {} of a stream with {}", index, self.len()))),
+        }
+    }
+}
+
+fn header_error<T>() -> Result<T> {
+    Err(PdfError::Syntax { what: "object stream header", position: 0 })
+}
+
+#[cfg(test)]
+fn object_stream_dict(count: i64, first: i64) -> DictNode {
+    DictNode::Dict(hashmap!{
+        "Type".to_string() => DictNode::name("ObjStm"),
+        "N".to_string() => DictNode::Int(count),
+        "First".to_string() => DictNode::Int(first),
+    })
+}
+
+#[test]
+fn test_object_stream() {
+    let data = b"11 0 12 9 13 17 <</A 1>> [1 2 3] true".to_vec();
+    let stream = ObjectStream::new(&object_stream_dict(3, 16), data).unwrap();
+    assert_eq!(stream.len(), 3);
+    assert_eq!(stream.id(1), Some(12));
+    assert_eq!(stream.source(0), Some(&b"<</A 1>> "[..]));
+    assert_eq!(stream.source(1), Some(&b"[1 2 3] "[..]));
+    assert_eq!(stream.source(2), Some(&b"true"[..]));
+    assert_eq!(stream.source(3), None);
+}
+
+#[test]
+fn test_object_stream_bad_header() {
+    assert!(ObjectStream::new(&object_stream_dict(3, 16), b"11 0 12 7 13 20 ".to_vec()).is_err());
+    assert!(ObjectStream::new(&object_stream_dict(2, 100), b"11 0 12 7".to_vec()).is_err());
+    assert!(ObjectStream::new(&object_stream_dict(1, 5), b"11 x true".to_vec()).is_err());
+}
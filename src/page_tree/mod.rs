@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use document::{Document, Options};
+use document::edit::EditEvent;
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use transcode;
+
+/*
+ * Page tree rebalancing.
+ *
+ * Naive writers hang every page straight off the root /Pages node, and a
+ * /Kids array with tens of thousands of entries makes some viewers crawl.
+ * `rebalance` rebuilds the tree with at most `fanout` kids per node, keeping
+ * page order.
+ *
+ * Pages inherit /Resources, /MediaBox, /CropBox and /Rotate from their
+ * ancestors. The root keeps its own, but intermediate nodes are replaced by
+ * new ones, so whatever a page inherited from them is copied onto the page
+ * itself first. The old intermediate nodes are freed.
+ *
+ * Like transcode, the rewritten objects and a new xref section are appended
+ * after the original bytes.
+ *
+ * Edits to the sequence of pages go through the same rebuild.
+ * `insert_blank_pages` adds empty pages, the size of the page before them,
+ * for print layouts such as chapters starting on a right-hand page:
+ *
+ *   let before = page_tree::recto_padding(&[0, 12, 31]);
+ *   page_tree::insert_blank_pages(input, output, &before)?;
+ *
+ * `insert_page` and `remove_page` edit a document in memory instead,
+ * through the edit module: the page's object is added or removed, and the
+ * nodes above it get their /Kids and /Count fixed up, without reshaping
+ * the tree. Observers hear PageAdded and PageRemoved after the objects.
+ * `delete_pages` removes several at once, and `reorder_pages` shuffles
+ * pages between the places in the tree they take up: every node keeps as
+ * many pages as it had, so /Count stays right all the way up, and a page
+ * that changes nodes takes along what it inherited from the old one. The
+ * result is written out with the writer module:
+ *
+ *   page_tree::reorder_pages(&mut doc, &[2, 0, 1])?;
+ *   writer::save(&mut doc, output, &SaveOptions::default())?;
+ */
+
+pub const DEFAULT_FANOUT: usize = 32;
+
+const INHERITABLE: &[&str] = &["Resources", "MediaBox", "CropBox", "Rotate"];
+
+// A new intermediate /Pages node.
+#[derive(Debug, PartialEq)]
+struct Node {
+    id: u64,
+    kids: Vec<u64>,
+    count: usize,
+}
+
+// A page's object number, and the /Pages nodes passed on the way down to it
+// from the root along with the position taken in each one's /Kids.
+type PagePath = (u64, Vec<(u64, usize)>);
+
+// A page found walking the tree, along with what it inherits from
+// intermediate nodes.
+struct Leaf {
+    id: u64,
+    page: DictNode,
+    inherited: HashMap<String, DictNode>,
+}
+
+pub fn rebalance(input: &Path, output: &Path, fanout: usize) -> Result<()> {
+    if fanout < 2 {
+        return Err(PdfError::Syntax { what: "page tree fanout", position: 0 });
+    }
+    rebuild(input, output, fanout, |_, _| ())
+}
+
+// Inserts a blank page before each of the pages at the indexes in `before`
+// (the page count to add one at the end), as many as an index is given.
+// Each is the size of the page before it, or after it for the first page.
+pub fn insert_blank_pages(input: &Path, output: &Path, before: &[usize]) -> Result<()> {
+    rebuild(input, output, DEFAULT_FANOUT, |leaves, next_id| {
+        let mut before = before.to_vec();
+        before.sort_unstable();
+        // from the back, so the indexes still point at the same pages
+        for &index in before.iter().rev() {
+            let index = index.min(leaves.len());
+            let neighbor = if index > 0 { leaves.get(index - 1) } else { leaves.first() };
+            let page = blank_page(neighbor);
+            leaves.insert(index, Leaf { id: *next_id, page, inherited: HashMap::new() });
+            *next_id += 1;
+        }
+    })
+}
+
+// Where blank pages go so that each section starting at the page indexes in
+// `starts` starts on a right-hand page, counting from a right-hand first
+// page: before any that would otherwise land on an odd index.
+pub fn recto_padding(starts: &[usize]) -> Vec<usize> {
+    let mut starts = starts.to_vec();
+    starts.sort_unstable();
+    starts.dedup();
+    let mut before = Vec::new();
+    for start in starts {
+        if (start + before.len()) % 2 == 1 {
+            before.push(start);
+        }
+    }
+    before
+}
+
+// Rebuilds the tree with `fanout` kids per node, after `edit` had its way
+// with the pages in order. New pages take their numbers from the second
+// argument, and shouldn't have a /Parent yet.
+fn rebuild<F: FnOnce(&mut Vec<Leaf>, &mut u64)>(input: &Path, output: &Path, fanout: usize, edit: F) -> Result<()> {
+    let mut doc = Document::open(input, Options::default())?;
+    let mut data = fs::read(input)?;
+    let mut xref = doc.xref.clone();
+
+    let (root_id, mut root) = match pages_root(&mut doc)? {
+        Some(root) => root,
+        None => return Err(PdfError::Syntax { what: "page tree root", position: 0 }),
+    };
+    let mut leaves = Vec::new();
+    let mut intermediate = Vec::new();
+    let mut seen = HashSet::new();
+    seen.insert(root_id);
+    collect(&mut doc, &root, &HashMap::new(), &mut seen, &mut intermediate, &mut leaves)?;
+    doc.policy().check_pages(leaves.len())?;
+    let mut next_id = xref.len() as u64;
+    edit(&mut leaves, &mut next_id);
+
+    let ids: Vec<u64> = leaves.iter().map(|leaf| leaf.id).collect();
+    let (nodes, top) = plan(&ids, fanout, next_id);
+
+    let mut parents = HashMap::new();
+    for node in &nodes {
+        for &kid in &node.kids {
+            parents.insert(kid, node.id);
+        }
+    }
+    for id in intermediate {
+        xref.set(id as usize, XRefEntry::free());
+    }
+    for leaf in leaves {
+        let mut page = leaf.page;
+        if let DictNode::Dict(ref mut map) = page {
+            for (key, value) in leaf.inherited {
+                map.entry(key).or_insert(value);
+            }
+            let parent = parents.get(&leaf.id).cloned().unwrap_or(root_id);
+            map.insert("Parent".to_string(), DictNode::reference(parent));
+        }
+        transcode::append_object(&mut data, &mut xref, leaf.id, &page)?;
+    }
+    for node in &nodes {
+        let parent = parents.get(&node.id).cloned().unwrap_or(root_id);
+        let mut map = HashMap::new();
+        map.insert("Type".to_string(), DictNode::name("Pages"));
+        map.insert("Parent".to_string(), DictNode::reference(parent));
+        map.insert("Kids".to_string(), DictNode::Array(node.kids.iter().map(|&kid| DictNode::reference(kid)).collect()));
+        map.insert("Count".to_string(), DictNode::Int(node.count as i64));
+        transcode::append_object(&mut data, &mut xref, node.id, &DictNode::Dict(map))?;
+    }
+    if let DictNode::Dict(ref mut map) = root {
+        map.insert("Kids".to_string(), DictNode::Array(top.iter().map(|&kid| DictNode::reference(kid)).collect()));
+        map.insert("Count".to_string(), DictNode::Int(ids.len() as i64));
+    }
+    transcode::append_object(&mut data, &mut xref, root_id, &root)?;
+
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// Inserts `page` as the page at `index`, before the page there now, or
+// after the last one for an index past the end. It goes into the same
+// /Pages node as its neighbour, and gets a /Parent and a /Type. Returns
+// its object number.
+pub fn insert_page(doc: &mut Document, index: usize, mut page: HashMap<String, DictNode>) -> Result<u64> {
+    let (root_id, pages) = page_paths(doc)?;
+    let index = index.min(pages.len());
+    let path = match pages.get(index).or_else(|| pages.last()) {
+        Some((_, path)) => {
+            let mut path = path.clone();
+            if index == pages.len() {
+                if let Some(last) = path.last_mut() {
+                    last.1 += 1;
+                }
+            }
+            path
+        },
+        // an empty tree, or one whose kids are all broken
+        None => vec![(root_id, usize::MAX)],
+    };
+    let parent = path.last().map_or(root_id, |&(node_id, _)| node_id);
+
+    let id = doc.next_object_id();
+    page.insert("Type".to_string(), DictNode::name("Page"));
+    page.insert("Parent".to_string(), DictNode::reference(parent));
+    doc.set_object(id, DictNode::Dict(page));
+    for (depth, &(node_id, position)) in path.iter().enumerate() {
+        let last = depth + 1 == path.len();
+        update_node(doc, node_id, 1, |kids| if last {
+            kids.insert(position.min(kids.len()), DictNode::reference(id));
+        })?;
+    }
+    doc.notify(&EditEvent::PageAdded { index, id });
+    Ok(id)
+}
+
+// Removes the page at `index`, returning its object number.
+pub fn remove_page(doc: &mut Document, index: usize) -> Result<u64> {
+    let (_, mut pages) = page_paths(doc)?;
+    if index >= pages.len() {
+        return Err(PdfError::InvalidArgument(format!("page {} of {}", index + 1, pages.len())));
+    }
+    let (id, path) = pages.swap_remove(index);
+    for (depth, &(node_id, position)) in path.iter().enumerate() {
+        let last = depth + 1 == path.len();
+        update_node(doc, node_id, -1, |kids| if last && position < kids.len() {
+            kids.remove(position);
+        })?;
+    }
+    doc.remove_object(id);
+    doc.notify(&EditEvent::PageRemoved { index, id });
+    Ok(id)
+}
+
+// Removes the pages at `indexes`, each once, returning their object numbers
+// in page order. Nothing is removed if an index is past the last page.
+pub fn delete_pages(doc: &mut Document, indexes: &[usize]) -> Result<Vec<u64>> {
+    let (_, pages) = page_paths(doc)?;
+    let mut indexes = indexes.to_vec();
+    indexes.sort_unstable();
+    indexes.dedup();
+    if let Some(&index) = indexes.iter().find(|&&index| index >= pages.len()) {
+        return Err(PdfError::InvalidArgument(format!("page {} of {}", index + 1, pages.len())));
+    }
+    let mut ids = Vec::new();
+    // from the back, so the indexes still point at the same pages
+    for &index in indexes.iter().rev() {
+        ids.push(remove_page(doc, index)?);
+    }
+    ids.reverse();
+    Ok(ids)
+}
+
+// Puts the pages in a new order, `order` giving the current index of each
+// page in turn: [2, 0, 1] moves the third page to the front. Every page has
+// to be there once.
+pub fn reorder_pages(doc: &mut Document, order: &[usize]) -> Result<()> {
+    let (_, pages) = page_paths(doc)?;
+    if !is_permutation(order, pages.len()) {
+        return Err(PdfError::InvalidArgument(format!("page order doesn't list each of the {} pages once", pages.len())));
+    }
+    // the new kids of each node, by position
+    let mut kids: BTreeMap<u64, Vec<(usize, u64)>> = BTreeMap::new();
+    let mut moves = Vec::new();
+    for (to, &from) in order.iter().enumerate() {
+        if to == from {
+            continue;
+        }
+        let (id, ref old_path) = pages[from];
+        let (_, ref new_path) = pages[to];
+        let (old_parent, new_parent) = match (old_path.last(), new_path.last()) {
+            (Some(&(old_parent, _)), Some(&(new_parent, position))) => {
+                kids.entry(new_parent).or_default().push((position, id));
+                (old_parent, new_parent)
+            },
+            _ => continue,
+        };
+        if old_parent != new_parent {
+            let old = inherited(doc, old_path)?;
+            let new = inherited(doc, new_path)?;
+            let mut page = match doc.cached_object(id)? {
+                Some(DictNode::Dict(map)) => map.clone(),
+                _ => continue,
+            };
+            for (key, value) in old {
+                page.entry(key).or_insert(value);
+            }
+            // and what the new node would hand down that the old one
+            // didn't, the page had the default
+            for &key in INHERITABLE {
+                if new.contains_key(key) && !page.contains_key(key) {
+                    if let Some(value) = default_value(key, &page) {
+                        page.insert(key.to_string(), value);
+                    }
+                }
+            }
+            page.insert("Parent".to_string(), DictNode::reference(new_parent));
+            doc.set_object(id, DictNode::Dict(page));
+        }
+        moves.push(EditEvent::PageMoved { from, to, id });
+    }
+    for (node_id, placed) in kids {
+        update_node(doc, node_id, 0, |kids| for (position, id) in placed {
+            if let Some(kid) = kids.get_mut(position) {
+                *kid = DictNode::reference(id);
+            }
+        })?;
+    }
+    for event in moves {
+        doc.notify(&event);
+    }
+    Ok(())
+}
+
+// Whether `order` has each of 0..count once.
+fn is_permutation(order: &[usize], count: usize) -> bool {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    sorted.len() == count && sorted.iter().enumerate().all(|(index, &value)| index == value)
+}
+
+// What a page at the end of `path` inherits from the nodes on it.
+fn inherited(doc: &mut Document, path: &[(u64, usize)]) -> Result<HashMap<String, DictNode>> {
+    let mut inherited = HashMap::new();
+    for &(node_id, _) in path {
+        if let Some(DictNode::Dict(map)) = doc.cached_object(node_id)? {
+            for &key in INHERITABLE {
+                if let Some(value) = map.get(key) {
+                    inherited.insert(key.to_string(), value.clone());
+                }
+            }
+        }
+    }
+    Ok(inherited)
+}
+
+// What an inheritable attribute is when no node has it.
+fn default_value(key: &str, page: &HashMap<String, DictNode>) -> Option<DictNode> {
+    match key {
+        "Resources" => Some(DictNode::Dict(HashMap::new())),
+        "CropBox" => page.get("MediaBox").cloned(),
+        "Rotate" => Some(DictNode::Int(0)),
+        _ => None,
+    }
+}
+
+// The root's object number, and each page's along with the way down to it.
+fn page_paths(doc: &mut Document) -> Result<(u64, Vec<PagePath>)> {
+    let root_id = match pages_root(doc)? {
+        Some((root_id, _)) => root_id,
+        None => return Err(PdfError::Syntax { what: "page tree root", position: 0 }),
+    };
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    seen.insert(root_id);
+    walk(doc, root_id, &mut Vec::new(), &mut seen, &mut pages)?;
+    Ok((root_id, pages))
+}
+
+// Pages under `node_id`, in order, `path` being the way down to it.
+fn walk(doc: &mut Document, node_id: u64, path: &mut Vec<(u64, usize)>, seen: &mut HashSet<u64>,
+        pages: &mut Vec<PagePath>) -> Result<()> {
+    let kids = match doc.cached_object(node_id)? {
+        Some(node) => node.get("Kids").cloned(),
+        None => None,
+    };
+    let kids = match kids {
+        Some(kids) => doc.resolve(&kids)?.as_array().map(<[DictNode]>::to_vec).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    for (position, kid) in kids.iter().enumerate() {
+        let id = match *kid {
+            DictNode::ObjectReference(id, _) if id >= 0 => id as u64,
+            _ => continue,
+        };
+        if !seen.insert(id) {
+            continue;
+        }
+        let is_node = match doc.cached_object(id)? {
+            Some(DictNode::Dict(map)) => map.contains_key("Kids"),
+            _ => continue,
+        };
+        path.push((node_id, position));
+        if is_node {
+            walk(doc, id, path, seen, pages)?;
+        } else {
+            pages.push((id, path.clone()));
+        }
+        path.pop();
+    }
+    Ok(())
+}
+
+// Adds `delta` to the /Count of node `id` and lets `edit` change its /Kids,
+// which end up a direct array.
+fn update_node<F: FnOnce(&mut Vec<DictNode>)>(doc: &mut Document, id: u64, delta: i64, edit: F) -> Result<()> {
+    let mut map = match doc.cached_object(id)? {
+        Some(DictNode::Dict(map)) => map.clone(),
+        _ => return Err(PdfError::Syntax { what: "page tree node", position: id as usize }),
+    };
+    let mut kids = match map.get("Kids") {
+        Some(kids) => doc.resolve(&kids.clone())?.as_array().map(<[DictNode]>::to_vec).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    edit(&mut kids);
+    let count = map.get("Count").and_then(DictNode::as_int).unwrap_or(0);
+    map.insert("Count".to_string(), DictNode::Int((count + delta).max(0)));
+    map.insert("Kids".to_string(), DictNode::Array(kids));
+    doc.set_object(id, DictNode::Dict(map));
+    Ok(())
+}
+
+fn pages_root(doc: &mut Document) -> Result<Option<(u64, DictNode)>> {
+    let catalog = match doc.trailer_entry("Root")? {
+        Some((_, DictNode::Dict(catalog))) => catalog,
+        _ => return Ok(None),
+    };
+    match catalog.get("Pages") {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => {
+            Ok(doc.cached_object(id as u64)?.map(|root| (id as u64, root.clone())))
+        },
+        _ => Ok(None),
+    }
+}
+
+// Walks the kids of `node` in order, collecting pages into `leaves` and the
+// object numbers of intermediate nodes into `intermediate`.
+fn collect(doc: &mut Document, node: &DictNode, inherited: &HashMap<String, DictNode>, seen: &mut HashSet<u64>,
+           intermediate: &mut Vec<u64>, leaves: &mut Vec<Leaf>) -> Result<()> {
+    let kids = match *node {
+        DictNode::Dict(ref map) => match map.get("Kids") {
+            Some(DictNode::Array(kids)) => kids.clone(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    for kid in kids {
+        let id = match kid {
+            DictNode::ObjectReference(id, _) if id >= 0 => id as u64,
+            _ => continue,
+        };
+        // a kid showing up twice would make the new tree lie about /Count
+        if !seen.insert(id) {
+            continue;
+        }
+        let object = match doc.cached_object(id)? {
+            Some(object) => object.clone(),
+            None => continue,
+        };
+        let map = match object {
+            DictNode::Dict(ref map) => map,
+            _ => continue,
+        };
+        if map.contains_key("Kids") {
+            let mut inherited = inherited.clone();
+            for &key in INHERITABLE {
+                if let Some(value) = map.get(key) {
+                    inherited.insert(key.to_string(), value.clone());
+                }
+            }
+            intermediate.push(id);
+            collect(doc, &object, &inherited, seen, intermediate, leaves)?;
+        } else {
+            leaves.push(Leaf { id, page: object.clone(), inherited: inherited.clone() });
+        }
+    }
+    Ok(())
+}
+
+// Groups `pages` into new nodes numbered from `first_id` until there are
+// at most `fanout` left for the root, which are returned along with the
+// new nodes.
+fn plan(pages: &[u64], fanout: usize, first_id: u64) -> (Vec<Node>, Vec<u64>) {
+    let mut nodes = Vec::new();
+    let mut level: Vec<(u64, usize)> = pages.iter().map(|&id| (id, 1)).collect();
+    let mut next_id = first_id;
+    while level.len() > fanout {
+        level = level.chunks(fanout)
+            .map(|chunk| {
+                let node = Node {
+                    id: next_id,
+                    kids: chunk.iter().map(|&(id, _)| id).collect(),
+                    count: chunk.iter().map(|&(_, count)| count).sum(),
+                };
+                next_id += 1;
+                let top = (node.id, node.count);
+                nodes.push(node);
+                top
+            })
+            .collect();
+    }
+    (nodes, level.into_iter().map(|(id, _)| id).collect())
+}
+
+// An empty page the size `neighbor` is displayed at.
+fn blank_page(neighbor: Option<&Leaf>) -> DictNode {
+    let mut page = HashMap::new();
+    page.insert("Type".to_string(), DictNode::name("Page"));
+    page.insert("Resources".to_string(), DictNode::Dict(HashMap::new()));
+    if let Some(neighbor) = neighbor {
+        for &key in &["MediaBox", "CropBox", "Rotate", "UserUnit"] {
+            if let Some(value) = neighbor.page.get(key).or_else(|| neighbor.inherited.get(key)) {
+                page.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+    DictNode::Dict(page)
+}
+
+#[test]
+fn test_plan() {
+    let (nodes, top) = plan(&[1, 2, 3], 4, 10);
+    assert!(nodes.is_empty());
+    assert_eq!(top, vec![1, 2, 3]);
+
+    let pages: Vec<u64> = (1..=10).collect();
+    let (nodes, top) = plan(&pages, 3, 100);
+    assert_eq!(nodes[0], Node { id: 100, kids: vec![1, 2, 3], count: 3 });
+    assert_eq!(nodes[3], Node { id: 103, kids: vec![10], count: 1 });
+    assert_eq!(nodes[4], Node { id: 104, kids: vec![100, 101, 102], count: 9 });
+    assert_eq!(nodes[5], Node { id: 105, kids: vec![103], count: 1 });
+    assert_eq!(nodes.len(), 6);
+    assert_eq!(top, vec![104, 105]);
+}
+
+#[test]
+fn test_is_permutation() {
+    assert!(is_permutation(&[2, 0, 1], 3));
+    assert!(is_permutation(&[], 0));
+    // a page left out, or twice
+    assert!(!is_permutation(&[1, 0], 3));
+    assert!(!is_permutation(&[0, 0, 1], 3));
+    assert!(!is_permutation(&[0, 1, 3], 3));
+}
+
+#[test]
+fn test_recto_padding() {
+    // chapters of 3, 4 and 2 pages: the second would start on a left-hand
+    // page, and pushing it over puts the third right
+    assert_eq!(recto_padding(&[0, 3, 7]), vec![3]);
+    assert_eq!(recto_padding(&[0, 1, 2]), vec![1, 2]);
+    assert_eq!(recto_padding(&[4, 2, 2]), Vec::<usize>::new());
+}
+
+#[test]
+fn test_blank_page() {
+    let neighbor = Leaf {
+        id: 4,
+        page: DictNode::Dict(hashmap!{ "Rotate".to_string() => DictNode::Int(90), "Contents".to_string() => DictNode::reference(5) }),
+        inherited: hashmap!{ "MediaBox".to_string() => DictNode::reference(2) },
+    };
+    assert_eq!(blank_page(Some(&neighbor)).to_string(), "<< /MediaBox 2 0 R /Resources << >> /Rotate 90 /Type /Page >>");
+    assert_eq!(blank_page(None).to_string(), "<< /Resources << >> /Type /Page >>");
+}
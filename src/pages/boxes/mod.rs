@@ -0,0 +1,101 @@
+use document::Document;
+use error::{PdfError, Result};
+use pages::Page;
+use parsers::cos::DictNode;
+
+/*
+ * Setting a page's rotation and boxes, the usual fix for a scan that came
+ * out sideways or with the glass's edges showing.
+ *
+ *   for page in pages::pages(&mut doc)? {
+ *       boxes::rotate(&mut doc, &page, 90)?;
+ *   }
+ *   writer::save_incremental(&mut doc, output)?;
+ *
+ * Changes are edits to the page objects (see the document's edit module),
+ * made on the page's own dictionary: a value it used to inherit from the
+ * page tree is overridden for that page only, and its neighbours keep
+ * theirs. Writing them out as an incremental update leaves the rest of
+ * the file as it was, signatures included.
+ *
+ * Rotations are multiples of 90, stored from 0 to 270. Boxes are stored
+ * normalized, [llx lly urx ury], and have to have some area; nothing
+ * checks that they nest the way the spec asks, a crop box sticking out of
+ * the media box is clipped when the page is read, as any other.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PageBox {
+    Media,
+    Crop,
+    Bleed,
+    Trim,
+    Art,
+}
+
+impl PageBox {
+    // "media", "crop", "bleed", "trim" or "art".
+    pub fn parse(name: &str) -> Option<PageBox> {
+        match name {
+            "media" => Some(PageBox::Media),
+            "crop" => Some(PageBox::Crop),
+            "bleed" => Some(PageBox::Bleed),
+            "trim" => Some(PageBox::Trim),
+            "art" => Some(PageBox::Art),
+            _ => None,
+        }
+    }
+
+    pub fn key(self) -> &'static str {
+        match self {
+            PageBox::Media => "MediaBox",
+            PageBox::Crop => "CropBox",
+            PageBox::Bleed => "BleedBox",
+            PageBox::Trim => "TrimBox",
+            PageBox::Art => "ArtBox",
+        }
+    }
+}
+
+// Turns `page` a further `degrees` clockwise.
+pub fn rotate(doc: &mut Document, page: &Page, degrees: i64) -> Result<()> {
+    set_rotation(doc, page, page.rotate + degrees)
+}
+
+// Sets `page`'s rotation to `degrees` clockwise, a multiple of 90.
+pub fn set_rotation(doc: &mut Document, page: &Page, degrees: i64) -> Result<()> {
+    if degrees % 90 != 0 {
+        return Err(PdfError::InvalidArgument(format!("rotation {} isn't a multiple of 90", degrees)));
+    }
+    set(doc, page, "Rotate", DictNode::Int(degrees.rem_euclid(360)))
+}
+
+// Sets one of `page`'s boxes to `rect`, any two opposite corners.
+pub fn set_box(doc: &mut Document, page: &Page, which: PageBox, rect: [f64; 4]) -> Result<()> {
+    let rect = super::normalize(rect);
+    if !rect.iter().all(|value| value.is_finite()) || rect[0] == rect[2] || rect[1] == rect[3] {
+        return Err(PdfError::InvalidArgument(format!("{:?} is an empty page box", rect)));
+    }
+    set(doc, page, which.key(), DictNode::Array(rect.iter().map(|&value| number(value)).collect()))
+}
+
+fn set(doc: &mut Document, page: &Page, key: &str, value: DictNode) -> Result<()> {
+    // pages written straight into a /Kids array have no object to edit
+    let id = page.id.ok_or_else(|| PdfError::InvalidArgument(format!("page {} has no object to edit", page.index + 1)))?;
+    let mut dict = page.dict.as_dict().cloned().unwrap_or_default();
+    dict.insert(key.to_string(), value);
+    doc.set_object(id, DictNode::Dict(dict));
+    Ok(())
+}
+
+fn number(value: f64) -> DictNode {
+    if value.fract() == 0.0 { DictNode::Int(value as i64) } else { DictNode::Float(value) }
+}
+
+#[test]
+fn test_page_box() {
+    assert_eq!(PageBox::parse("trim").map(PageBox::key), Some("TrimBox"));
+    assert_eq!(PageBox::parse("TrimBox"), None);
+    assert_eq!(number(612.0), DictNode::Int(612));
+    assert_eq!(number(8.5), DictNode::Float(8.5));
+}
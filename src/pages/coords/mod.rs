@@ -0,0 +1,118 @@
+use graphics::Matrix;
+use pages::Page;
+
+/*
+ * Coordinates on a page as it's displayed, with the origin where the
+ * caller wants it.
+ *
+ *   let coords = page.coords(Origin::TopLeft);
+ *   let [left, top, right, bottom] = coords.rect(annotation.rect);
+ *
+ * What the rest of the crate hands out is in default user space:
+ * annotation rectangles, figure and formula boxes, raw text spans, image
+ * matrices. That's the page before its crop box, /Rotate and /UserUnit
+ * apply, with y going up from wherever the media box puts 0. Images and UI
+ * toolkits want y going down from the top left corner of what's shown,
+ * and flipping y against the media box rather than the crop box, or
+ * forgetting the rotation, is off by a page height or worse.
+ *
+ * PageCoords does the whole conversion: onto the displayed page (see
+ * `Page::display_matrix`), in points, with the origin at its bottom left
+ * corner and y going up, the PDF way, or at its top left corner and y
+ * going down. Rectangles come back as [x0 y0 x1 y1] with x0 <= x1 and
+ * y0 <= y1 either way, so from the top left y0 is the top edge.
+ * `user_point` and `user_rect` go back, for placing new things on a page.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Origin {
+    BottomLeft,
+    TopLeft,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PageCoords {
+    pub origin: Origin,
+    // of the page as displayed, in points
+    pub width: f64,
+    pub height: f64,
+    // default user space onto the displayed page
+    pub matrix: Matrix,
+}
+
+impl PageCoords {
+    pub fn new(page: &Page, origin: Origin) -> PageCoords {
+        let (width, height) = page.display_size();
+        let matrix = match origin {
+            Origin::BottomLeft => page.display_matrix(),
+            Origin::TopLeft => page.display_matrix() * Matrix::new(1.0, 0.0, 0.0, -1.0, 0.0, height),
+        };
+        PageCoords { origin, width, height, matrix }
+    }
+
+    // A point of default user space.
+    pub fn point(&self, x: f64, y: f64) -> (f64, f64) {
+        self.matrix.apply(x, y)
+    }
+
+    // A rectangle of default user space, [llx lly urx ury].
+    pub fn rect(&self, rect: [f64; 4]) -> [f64; 4] {
+        bounds(&self.matrix, rect)
+    }
+
+    // Back from the displayed page to default user space.
+    pub fn user_point(&self, x: f64, y: f64) -> (f64, f64) {
+        self.inverse().apply(x, y)
+    }
+
+    pub fn user_rect(&self, rect: [f64; 4]) -> [f64; 4] {
+        bounds(&self.inverse(), rect)
+    }
+
+    // The display matrix always has an inverse, unless /UserUnit is 0,
+    // which the pages module doesn't let through.
+    fn inverse(&self) -> Matrix {
+        self.matrix.inverse().unwrap_or(self.matrix)
+    }
+}
+
+// The rectangle `rect`'s corners end up in.
+fn bounds(matrix: &Matrix, rect: [f64; 4]) -> [f64; 4] {
+    let [llx, lly, urx, ury] = rect;
+    let corners = [matrix.apply(llx, lly), matrix.apply(urx, lly), matrix.apply(llx, ury), matrix.apply(urx, ury)];
+    let mut bounds = [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY];
+    for &(x, y) in &corners {
+        bounds = [bounds[0].min(x), bounds[1].min(y), bounds[2].max(x), bounds[3].max(y)];
+    }
+    bounds
+}
+
+#[test]
+fn test_page_coords() {
+    use fixtures;
+
+    let page = |crop_box, rotate| Page {
+        media_box: [0.0, 0.0, 612.0, 792.0],
+        ..fixtures::page(crop_box, rotate)
+    };
+
+    // a Letter page cropped by an inch all round: the top left corner of
+    // what's shown is (72, 720) in user space, and 792 would be off by two
+    // inches
+    let cropped = page([72.0, 72.0, 540.0, 720.0], 0);
+    let coords = cropped.coords(Origin::TopLeft);
+    assert_eq!((coords.width, coords.height), (468.0, 648.0));
+    assert_eq!(coords.point(72.0, 720.0), (0.0, 0.0));
+    assert_eq!(coords.rect([100.0, 600.0, 200.0, 700.0]), [28.0, 20.0, 128.0, 120.0]);
+    assert_eq!(coords.user_rect([28.0, 20.0, 128.0, 120.0]), [100.0, 600.0, 200.0, 700.0]);
+    assert_eq!(cropped.coords(Origin::BottomLeft).rect([100.0, 600.0, 200.0, 700.0]), [28.0, 528.0, 128.0, 628.0]);
+
+    // turned a quarter clockwise it's shown landscape, and the user space
+    // top left corner ends up top right
+    let turned = page([0.0, 0.0, 612.0, 792.0], 90);
+    let coords = PageCoords::new(&turned, Origin::TopLeft);
+    assert_eq!((coords.width, coords.height), (792.0, 612.0));
+    assert_eq!(coords.point(0.0, 792.0), (792.0, 0.0));
+    assert_eq!(coords.rect([0.0, 692.0, 50.0, 792.0]), [692.0, 0.0, 792.0, 50.0]);
+    assert_eq!(coords.user_point(792.0, 0.0), (0.0, 792.0));
+}
@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+
+use document::Document;
+use error::Result;
+use features::{self, PageFeatures};
+use graphics::paths::{self, Path};
+use graphics::Matrix;
+use parsers::content::Operations;
+use parsers::cos::DictNode;
+
+use self::coords::{Origin, PageCoords};
+
+pub mod boxes;
+pub mod coords;
+
+/*
+ * The pages of a document, in order, walking the page tree from the
+ * catalog's /Pages through each node's /Kids.
+ *
+ * Pages inherit /Resources, /MediaBox, /CropBox and /Rotate from the nearest
+ * ancestor that has them, so `Page` comes with those already looked up. The
+ * spec's defaults fill in what nobody sets, except the media box: it's
+ * required, and for the files that forget it we go with US Letter, which is
+ * what viewers do too.
+ *
+ * Content is drawn in default user space, which /Rotate turns clockwise
+ * for display. `display_matrix` undoes that, taking default user space to
+ * the page as a viewer shows it: upright, with the origin at the lower left
+ * corner of the crop box and y going up as usual.
+ *
+ * A unit of default user space is a point, 1/72 inch, unless the page has
+ * a /UserUnit (PDF 1.6) saying how many points it is. That's how maps and
+ * banners get past the 14400 unit limit on page sizes, 200 inches. The
+ * displayed page, `display_matrix` and `display_size`, is in points, boxes
+ * and everything else are in the page's own units. `coords` converts
+ * between the two, from the bottom left or the top left corner, see the
+ * coords module. The boxes module sets a page's rotation and boxes.
+ *
+ * Broken trees are common enough that nothing here is fatal: kids that
+ * don't resolve are skipped, and a kid showing up twice (or a node that is
+ * its own ancestor) only counts the first time.
+ */
+
+const INHERITABLE: &[&str] = &["Resources", "MediaBox", "CropBox", "Rotate"];
+
+// US Letter, for pages without a media box anywhere up the tree.
+pub const DEFAULT_MEDIA_BOX: [f64; 4] = [0.0, 0.0, 612.0, 792.0];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Page {
+    // position in the document, from 0
+    pub index: usize,
+    // None for pages written directly into their parent's /Kids
+    pub id: Option<u64>,
+    // the page's own dictionary, without anything inherited
+    pub dict: DictNode,
+    // both boxes normalized to [llx lly urx ury], the crop box clipped to
+    // the media box as the spec says
+    pub media_box: [f64; 4],
+    pub crop_box: [f64; 4],
+    // clockwise, one of 0, 90, 180 or 270
+    pub rotate: i64,
+    // points per unit of default user space, 1 unless /UserUnit says
+    pub user_unit: f64,
+    // resolved, an empty dictionary if the page has none
+    pub resources: DictNode,
+}
+
+impl Page {
+    // An entry of the page's own dictionary.
+    pub fn get(&self, key: &str) -> Option<&DictNode> {
+        self.dict.get(key)
+    }
+
+    // The size as displayed, in points: the crop box, turned by the
+    // rotation.
+    pub fn display_size(&self) -> (f64, f64) {
+        let width = (self.crop_box[2] - self.crop_box[0]) * self.user_unit;
+        let height = (self.crop_box[3] - self.crop_box[1]) * self.user_unit;
+        if self.rotate % 180 == 0 {
+            (width, height)
+        } else {
+            (height, width)
+        }
+    }
+
+    // Maps default user space onto the page as displayed, see the top of
+    // the file.
+    pub fn display_matrix(&self) -> Matrix {
+        let [llx, lly, urx, ury] = self.crop_box;
+        let upright = match self.rotate {
+            90 => Matrix::new(0.0, -1.0, 1.0, 0.0, -lly, urx),
+            180 => Matrix::new(-1.0, 0.0, 0.0, -1.0, urx, ury),
+            270 => Matrix::new(0.0, 1.0, -1.0, 0.0, ury, -llx),
+            _ => Matrix::translate(-llx, -lly),
+        };
+        upright * Matrix::new(self.user_unit, 0.0, 0.0, self.user_unit, 0.0, 0.0)
+    }
+
+    // Converts default user space to the page as displayed and back.
+    pub fn coords(&self, origin: Origin) -> PageCoords {
+        PageCoords::new(self, origin)
+    }
+
+    // Numbers for classifiers, see the features module.
+    pub fn features(&self, doc: &mut Document) -> Result<PageFeatures> {
+        features::features(doc, self)
+    }
+
+    // What the page paints with paths, see the graphics::paths module.
+    pub fn paths(&self, doc: &mut Document) -> Result<Vec<Path>> {
+        paths::paths(doc, self)
+    }
+}
+
+// The page's decoded content. /Contents may be split over several streams,
+// which are joined with a newline between them since a split can fall
+// between two tokens.
+pub fn content(doc: &mut Document, page: &Page) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    for stream in content_streams(doc, page)? {
+        if let Some((_, data)) = doc.referenced_stream(&stream)? {
+            if !content.is_empty() {
+                content.push(b'\n');
+            }
+            content.extend(data);
+        }
+    }
+    Ok(content)
+}
+
+// The streams in /Contents, as references, however it gives them.
+pub(crate) fn content_streams(doc: &mut Document, page: &Page) -> Result<Vec<DictNode>> {
+    Ok(match page.get("Contents") {
+        Some(&DictNode::ObjectReference(id, generation)) => {
+            // a reference to an array of streams rather than to a stream
+            match doc.resolve(&DictNode::ObjectReference(id, generation))? {
+                DictNode::Array(streams) => streams,
+                _ => vec![DictNode::ObjectReference(id, generation)],
+            }
+        },
+        Some(DictNode::Array(streams)) => streams.clone(),
+        _ => Vec::new(),
+    })
+}
+
+// The page's content as operations, see parsers::content.
+pub fn operations(doc: &mut Document, page: &Page) -> Result<Operations<Cursor<Vec<u8>>>> {
+    Ok(Operations::new(Cursor::new(content(doc, page)?)))
+}
+
+// A page as the page tree gives it: the page object, and what it inherits
+// from the nodes above it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Leaf {
+    pub node: DictNode,
+    pub inherited: HashMap<String, DictNode>,
+}
+
+pub fn pages(doc: &mut Document) -> Result<Vec<Page>> {
+    // the index cache may have walked the tree already
+    let leaves = match doc.cached_pages() {
+        Some(leaves) => leaves,
+        None => leaves(doc)?,
+    };
+    doc.policy().check_pages(leaves.len())?;
+    let mut pages = Vec::new();
+    for (index, leaf) in leaves.iter().enumerate() {
+        pages.push(page(doc, index, leaf)?);
+    }
+    Ok(pages)
+}
+
+// The pages of the tree, in order, before anything is looked up.
+pub(crate) fn leaves(doc: &mut Document) -> Result<Vec<Leaf>> {
+    let catalog = match doc.trailer_entry("Root")? {
+        Some((_, catalog)) => catalog,
+        None => return Ok(Vec::new()),
+    };
+    let root = match catalog.get("Pages") {
+        Some(root) => root.clone(),
+        None => return Ok(Vec::new()),
+    };
+    let mut leaves = Vec::new();
+    walk(doc, &root, &HashMap::new(), &mut HashSet::new(), &mut leaves)?;
+    Ok(leaves)
+}
+
+fn walk(doc: &mut Document, node: &DictNode, inherited: &HashMap<String, DictNode>, seen: &mut HashSet<u64>,
+        leaves: &mut Vec<Leaf>) -> Result<()> {
+    if let DictNode::ObjectReference(id, _) = *node {
+        if id >= 0 && !seen.insert(id as u64) {
+            return Ok(());
+        }
+    }
+    let dict = doc.resolve(node)?;
+    let map = match dict.as_dict() {
+        Some(map) => map,
+        None => return Ok(()),
+    };
+
+    let mut inherited = inherited.clone();
+    for &key in INHERITABLE {
+        if let Some(value) = map.get(key) {
+            inherited.insert(key.to_string(), value.clone());
+        }
+    }
+    // a node is whatever has kids, /Type is wrong too often to go by
+    if let Some(kids) = dict.get_resolved(doc, "Kids")? {
+        for kid in kids.as_array().unwrap_or(&[]) {
+            walk(doc, kid, &inherited, seen, leaves)?;
+        }
+        return Ok(());
+    }
+    leaves.push(Leaf { node: node.clone(), inherited });
+    Ok(())
+}
+
+fn page(doc: &mut Document, index: usize, leaf: &Leaf) -> Result<Page> {
+    let id = match leaf.node {
+        DictNode::ObjectReference(id, _) if id >= 0 => Some(id as u64),
+        _ => None,
+    };
+    let dict = doc.resolve(&leaf.node)?;
+    let inherited = &leaf.inherited;
+    let media_box = match inherited.get("MediaBox") {
+        Some(media_box) => doc.resolve(media_box)?.as_rect().map(normalize).unwrap_or(DEFAULT_MEDIA_BOX),
+        None => DEFAULT_MEDIA_BOX,
+    };
+    let crop_box = match inherited.get("CropBox") {
+        Some(crop_box) => doc.resolve(crop_box)?.as_rect().map(|crop_box| clip(normalize(crop_box), media_box)),
+        None => None,
+    };
+    let rotate = match inherited.get("Rotate") {
+        Some(rotate) => doc.resolve(rotate)?.as_int().unwrap_or(0),
+        None => 0,
+    };
+    // not inheritable, unlike the boxes it scales
+    let user_unit = match dict.get("UserUnit") {
+        Some(user_unit) => doc.resolve(user_unit)?.as_float().filter(|&unit| unit > 0.0 && unit.is_finite()).unwrap_or(1.0),
+        None => 1.0,
+    };
+    let resources = match inherited.get("Resources") {
+        Some(resources) => match doc.resolve(resources)? {
+            DictNode::Dict(resources) => DictNode::Dict(resources),
+            _ => DictNode::Dict(HashMap::new()),
+        },
+        None => DictNode::Dict(HashMap::new()),
+    };
+    Ok(Page {
+        index,
+        id,
+        dict,
+        media_box,
+        crop_box: crop_box.unwrap_or(media_box),
+        rotate: normalize_rotation(rotate),
+        user_unit,
+        resources,
+    })
+}
+
+// Boxes may be given by any two opposite corners.
+fn normalize(rect: [f64; 4]) -> [f64; 4] {
+    [rect[0].min(rect[2]), rect[1].min(rect[3]), rect[0].max(rect[2]), rect[1].max(rect[3])]
+}
+
+// The part of `rect` inside `bounds`, or `bounds` if they don't overlap.
+fn clip(rect: [f64; 4], bounds: [f64; 4]) -> [f64; 4] {
+    let clipped = [rect[0].max(bounds[0]), rect[1].max(bounds[1]), rect[2].min(bounds[2]), rect[3].min(bounds[3])];
+    if clipped[0] < clipped[2] && clipped[1] < clipped[3] {
+        clipped
+    } else {
+        bounds
+    }
+}
+
+// /Rotate must be a multiple of 90, negative ones and ones past 360 turn up
+// all the same.
+fn normalize_rotation(rotate: i64) -> i64 {
+    (rotate / 90 * 90).rem_euclid(360)
+}
+
+#[test]
+fn test_boxes() {
+    assert_eq!(normalize([612.0, 792.0, 0.0, 0.0]), [0.0, 0.0, 612.0, 792.0]);
+    assert_eq!(clip([-10.0, 20.0, 300.0, 900.0], DEFAULT_MEDIA_BOX), [0.0, 20.0, 300.0, 792.0]);
+    assert_eq!(clip([700.0, 0.0, 800.0, 10.0], DEFAULT_MEDIA_BOX), DEFAULT_MEDIA_BOX);
+}
+
+#[test]
+fn test_rotation() {
+    use fixtures;
+
+    assert_eq!(normalize_rotation(0), 0);
+    assert_eq!(normalize_rotation(-90), 270);
+    assert_eq!(normalize_rotation(450), 90);
+    assert_eq!(normalize_rotation(95), 90);
+
+    let page = fixtures::page(DEFAULT_MEDIA_BOX, 270);
+    assert_eq!(page.display_size(), (792.0, 612.0));
+
+    // where the corners of the crop box go
+    let corners = |page: &Page| {
+        let matrix = page.display_matrix();
+        let [llx, lly, urx, ury] = page.crop_box;
+        [matrix.apply(llx, lly), matrix.apply(urx, lly), matrix.apply(urx, ury), matrix.apply(llx, ury)]
+    };
+    let page = Page { crop_box: [10.0, 20.0, 110.0, 220.0], ..page };
+    // the left edge ends up at the bottom
+    assert_eq!(corners(&page), [(200.0, 0.0), (200.0, 100.0), (0.0, 100.0), (0.0, 0.0)]);
+    assert_eq!(corners(&Page { rotate: 90, ..page.clone() }), [(0.0, 100.0), (0.0, 0.0), (200.0, 0.0), (200.0, 100.0)]);
+    assert_eq!(corners(&Page { rotate: 180, ..page.clone() }), [(100.0, 200.0), (0.0, 200.0), (0.0, 0.0), (100.0, 0.0)]);
+    assert_eq!(corners(&Page { rotate: 0, ..page.clone() }), [(0.0, 0.0), (100.0, 0.0), (100.0, 200.0), (0.0, 200.0)]);
+
+    // a banner of 20000 by 1000 units of 10 points, 2778 inches long
+    let banner = Page { crop_box: [0.0, 0.0, 20000.0, 1000.0], rotate: 0, user_unit: 10.0, ..page };
+    assert_eq!(banner.display_size(), (200000.0, 10000.0));
+    assert_eq!(corners(&banner)[2], (200000.0, 10000.0));
+}
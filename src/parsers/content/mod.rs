@@ -0,0 +1,645 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use error::{PdfError, Result};
+use filters;
+use parsers::cos;
+use parsers::cos::latin1;
+use parsers::cos::DictNode;
+
+use self::ops::Op;
+
+pub mod ops;
+
+/*
+ * Tokenizer for content streams, the postfix little language pages are
+ * drawn with (see README.md):
+ *
+ *   BT 318.8 197.8 Td /F2 11 Tf [<32> 79 <06>] TJ ET
+ *
+ * Operands come first, then the operator that consumes them. Decoded content
+ * streams can be enormous (maps, CAD exports...), so this works straight off
+ * a `BufRead` and hands out one token at a time instead of slurping the whole
+ * stream into a string for pest.
+ *
+ * `Operations` puts the tokens back together into what analysis wants, each
+ * operator with its operands:
+ *
+ *   for operation in Operations::new(&content[..]) {
+ *       let operation = operation?;
+ *       if operation.operator == "Tf" { ... }
+ *   }
+ *
+ * Inline images (BI <key value pairs> ID <binary> EI) come out as one
+ * token, with the abbreviated keys and names expanded (/W is /Width, /CS
+ * /G is /ColorSpace /DeviceGray...) so they read like an image XObject's
+ * dictionary. Where the data ends is known exactly for unfiltered images
+ * and ones with a /L length, otherwise it's at the first "EI" with
+ * whitespace on both sides. `Operations` turns it into a "BI" operation
+ * whose operands are the dictionary and the data:
+ *
+ *   if let Some((dict, data)) = operation.inline_image() { ... }
+ *
+ * `ops()` goes one step further and checks each operation's operands
+ * against what its operator takes, for code that would rather match on an
+ * `Op` than on strings (see the ops module).
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Operand(DictNode),
+    Operator(String),
+    // the expanded dictionary and the data of BI ... ID ... EI
+    InlineImage(HashMap<String, DictNode>, Vec<u8>),
+}
+
+// Inline image keys and the image dictionary keys they stand for.
+const INLINE_KEYS: &[(&str, &str)] = &[
+    ("BPC", "BitsPerComponent"),
+    ("CS", "ColorSpace"),
+    ("D", "Decode"),
+    ("DP", "DecodeParms"),
+    ("F", "Filter"),
+    ("H", "Height"),
+    ("IM", "ImageMask"),
+    ("I", "Interpolate"),
+    ("L", "Length"),
+    ("W", "Width"),
+];
+
+// Abbreviated names for the values of /ColorSpace and /Filter.
+const INLINE_NAMES: &[(&str, &str)] = &[
+    ("G", "DeviceGray"),
+    ("RGB", "DeviceRGB"),
+    ("CMYK", "DeviceCMYK"),
+    ("I", "Indexed"),
+    ("AHx", "ASCIIHexDecode"),
+    ("A85", "ASCII85Decode"),
+    ("LZW", "LZWDecode"),
+    ("Fl", "FlateDecode"),
+    ("RL", "RunLengthDecode"),
+    ("CCF", "CCITTFaxDecode"),
+    ("DCT", "DCTDecode"),
+];
+
+pub struct Tokenizer<R> {
+    reader: R,
+    position: usize,
+    failed: bool,
+    // arrays and dictionaries we're inside of
+    depth: usize,
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(byte, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+impl<R: BufRead> Tokenizer<R> {
+    pub fn new(reader: R) -> Tokenizer<R> {
+        Tokenizer { reader, position: 0, failed: false, depth: 0 }
+    }
+
+    // Byte offset into the decoded stream, handy for error messages.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().cloned())
+    }
+
+    fn bump(&mut self) {
+        self.reader.consume(1);
+        self.position += 1;
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek()?;
+        if byte.is_some() {
+            self.bump();
+        }
+        Ok(byte)
+    }
+
+    fn syntax_error<T>(&self) -> Result<T> {
+        Err(PdfError::Syntax { what: "content stream", position: self.position })
+    }
+
+    fn skip_whitespace(&mut self) -> Result<()> {
+        while let Some(byte) = self.peek()? {
+            if is_whitespace(byte) {
+                self.bump();
+            } else if byte == b'%' {
+                while let Some(byte) = self.next_byte()? {
+                    if byte == b'\n' || byte == b'\r' {
+                        break;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn regular(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = self.peek()? {
+            if is_whitespace(byte) || is_delimiter(byte) {
+                break;
+            }
+            bytes.push(byte);
+            self.bump();
+        }
+        Ok(bytes)
+    }
+
+    // "(...)" with balanced parentheses and backslash escapes, unescaped
+    // like the cos parser does.
+    fn literal_string(&mut self) -> Result<DictNode> {
+        let mut bytes = Vec::new();
+        let mut depth = 1;
+        self.bump();
+        loop {
+            let byte = match self.next_byte()? {
+                Some(byte) => byte,
+                None => return self.syntax_error(),
+            };
+            match byte {
+                b'\\' => {
+                    bytes.push(byte);
+                    match self.next_byte()? {
+                        Some(escaped) => bytes.push(escaped),
+                        None => return self.syntax_error(),
+                    }
+                    continue;
+                },
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+            bytes.push(byte);
+        }
+        Ok(DictNode::LiteralString(cos::unescape(&bytes)))
+    }
+
+    // "<...>", the opening '<' has already been consumed.
+    fn hex_string(&mut self) -> Result<DictNode> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.next_byte()? {
+                Some(b'>') => break,
+                Some(byte) => bytes.push(byte),
+                None => return self.syntax_error(),
+            }
+        }
+        match filters::ascii_hex_decode(&bytes) {
+            Ok(decoded) => Ok(DictNode::HexString(decoded)),
+            Err(_) => self.syntax_error(),
+        }
+    }
+
+    // Called on the way into an array or dictionary, which could otherwise
+    // nest until the stack runs out.
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > cos::MAX_DEPTH {
+            return self.syntax_error();
+        }
+        Ok(())
+    }
+
+    fn array(&mut self) -> Result<DictNode> {
+        self.nest()?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.peek()? == Some(b']') {
+                self.bump();
+                self.depth -= 1;
+                return Ok(DictNode::Array(items));
+            }
+            items.push(self.operand()?);
+        }
+    }
+
+    // "<< ... >>", the opening "<<" has already been consumed.
+    fn dictionary(&mut self) -> Result<DictNode> {
+        self.nest()?;
+        let mut map = HashMap::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.peek()? == Some(b'>') {
+                self.bump();
+                if self.next_byte()? != Some(b'>') {
+                    return self.syntax_error();
+                }
+                self.depth -= 1;
+                return Ok(DictNode::Dict(map));
+            }
+            let key = match self.operand()? {
+                DictNode::Str(ref name) if name.starts_with('/') => name[1..].to_string(),
+                _ => return self.syntax_error(),
+            };
+            self.skip_whitespace()?;
+            let value = self.operand()?;
+            map.insert(key, value);
+        }
+    }
+
+    // An operand nested inside an array or dictionary, where operators
+    // aren't allowed.
+    fn operand(&mut self) -> Result<DictNode> {
+        match self.token()? {
+            Some(Token::Operand(operand)) => Ok(operand),
+            _ => self.syntax_error(),
+        }
+    }
+
+    fn token(&mut self) -> Result<Option<Token>> {
+        self.skip_whitespace()?;
+        let byte = match self.peek()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        let operand = match byte {
+            b'/' => {
+                self.bump();
+                let name = self.regular()?;
+                // same as the cos parser, so names match resource dictionary keys
+                DictNode::Str(format!("/{}", String::from_utf8_lossy(&cos::decode_name(&name))))
+            },
+            b'(' => self.literal_string()?,
+            b'<' => {
+                self.bump();
+                if self.peek()? == Some(b'<') {
+                    self.bump();
+                    self.dictionary()?
+                } else {
+                    self.hex_string()?
+                }
+            },
+            b'[' => {
+                self.bump();
+                self.array()?
+            },
+            b')' | b'>' | b']' | b'{' | b'}' => {
+                self.bump();
+                return self.syntax_error();
+            },
+            _ => {
+                let word = latin1(&self.regular()?);
+                if let Ok(int) = word.parse::<i64>() {
+                    DictNode::Int(int)
+                } else if let Some(float) = parse_real(&word) {
+                    DictNode::Float(float)
+                } else if word == "true" || word == "false" {
+                    DictNode::Bool(word == "true")
+                } else if word == "null" {
+                    DictNode::Null
+                } else if word == "BI" {
+                    return self.inline_image().map(Some);
+                } else {
+                    return Ok(Some(Token::Operator(word)));
+                }
+            },
+        };
+        Ok(Some(Token::Operand(operand)))
+    }
+}
+
+impl<R: BufRead> Tokenizer<R> {
+    // What follows "BI", up to and including "EI".
+    fn inline_image(&mut self) -> Result<Token> {
+        let mut dict = HashMap::new();
+        loop {
+            match self.token()? {
+                Some(Token::Operand(DictNode::Str(ref key))) if key.starts_with('/') => {
+                    let key = expand(&key[1..], INLINE_KEYS);
+                    let value = self.operand()?;
+                    let value = if key == "ColorSpace" || key == "Filter" { expand_names(value) } else { value };
+                    dict.insert(key, value);
+                },
+                Some(Token::Operator(ref operator)) if operator == "ID" => break,
+                _ => return self.syntax_error(),
+            }
+        }
+        // a single whitespace byte separates ID from the data
+        if self.peek()?.is_some_and(is_whitespace) {
+            self.bump();
+        }
+
+        let data = match inline_length(&dict) {
+            Some(length) => {
+                let mut data = Vec::with_capacity(length.min(1 << 20));
+                for _ in 0..length {
+                    match self.next_byte()? {
+                        Some(byte) => data.push(byte),
+                        None => return self.syntax_error(),
+                    }
+                }
+                self.skip_whitespace()?;
+                if self.regular()? != b"EI" {
+                    return self.syntax_error();
+                }
+                data
+            },
+            None => self.inline_data()?,
+        };
+        Ok(Token::InlineImage(dict, data))
+    }
+
+    // Data up to the first "EI" that has whitespace before it (or is right
+    // at the start) and whitespace, a delimiter or the end after it.
+    fn inline_data(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let byte = match self.next_byte()? {
+                Some(byte) => byte,
+                None => return self.syntax_error(),
+            };
+            data.push(byte);
+            let ends = data.ends_with(b"EI") && (data.len() == 2 || is_whitespace(data[data.len() - 3]));
+            if ends && self.peek()?.is_none_or(|next| is_whitespace(next) || is_delimiter(next)) {
+                data.truncate(data.len().saturating_sub(3));
+                return Ok(data);
+            }
+        }
+    }
+}
+
+fn expand(name: &str, abbreviations: &[(&str, &str)]) -> String {
+    match abbreviations.iter().find(|&&(short, _)| short == name) {
+        Some(&(_, long)) => long.to_string(),
+        None => name.to_string(),
+    }
+}
+
+// Expands names in a /ColorSpace or /Filter value, arrays included
+// ([/I /RGB 1 <...>]).
+fn expand_names(value: DictNode) -> DictNode {
+    match value {
+        DictNode::Str(ref name) if name.starts_with('/') => DictNode::Str(format!("/{}", expand(&name[1..], INLINE_NAMES))),
+        DictNode::Array(items) => DictNode::Array(items.into_iter().map(expand_names).collect()),
+        other => other,
+    }
+}
+
+// How many bytes of data an inline image has, when that can be told
+// without looking for EI: it says so in /L, or isn't filtered and its
+// samples can be counted.
+fn inline_length(dict: &HashMap<String, DictNode>) -> Option<usize> {
+    let number = |key| dict.get(key).and_then(DictNode::as_int).filter(|&value| value >= 0).map(|value| value as usize);
+    if dict.contains_key("Length") {
+        return number("Length");
+    }
+    if dict.contains_key("Filter") {
+        return None;
+    }
+    let mask = dict.get("ImageMask").and_then(DictNode::as_bool).unwrap_or(false);
+    let (bits, components): (usize, usize) = if mask {
+        (1, 1)
+    } else {
+        let components = match dict.get("ColorSpace") {
+            Some(DictNode::Str(name)) if name == "/DeviceGray" => 1,
+            Some(DictNode::Str(name)) if name == "/DeviceRGB" => 3,
+            Some(DictNode::Str(name)) if name == "/DeviceCMYK" => 4,
+            Some(DictNode::Array(items)) if items.first().is_some_and(|first| first.is_name("Indexed")) => 1,
+            // a named space from the resources, we can't tell
+            _ => return None,
+        };
+        (number("BitsPerComponent")?, components)
+    };
+    let row = number("Width")?.checked_mul(bits * components)?.div_ceil(8);
+    row.checked_mul(number("Height")?)
+}
+
+// Reals in content streams look like "3.", "-.5" or "+12.25".
+fn parse_real(word: &str) -> Option<f64> {
+    let digits = word.trim_start_matches(['+', '-']);
+    let valid = !digits.is_empty()
+        && word.len() - digits.len() <= 1
+        && digits.bytes().all(|byte| byte.is_ascii_digit() || byte == b'.')
+        && digits.bytes().filter(|&byte| byte == b'.').count() == 1
+        && digits != ".";
+    if valid {
+        word.trim_start_matches('+').parse::<f64>().ok()
+    } else {
+        None
+    }
+}
+
+impl<R: BufRead> Iterator for Tokenizer<R> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Result<Token>> {
+        if self.failed {
+            return None;
+        }
+        let token = self.token();
+        if token.is_err() {
+            // don't keep spinning on a broken reader
+            self.failed = true;
+        }
+        token.transpose()
+    }
+}
+
+// An operator and the operands in front of it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Operation {
+    pub operator: String,
+    pub operands: Vec<DictNode>,
+}
+
+impl Operation {
+    // The operands as numbers, None if any of them isn't one. Handy for
+    // the many operators that take nothing else (cm, re, Td, m, l...).
+    pub fn numbers(&self) -> Option<Vec<f64>> {
+        self.operands.iter().map(DictNode::as_float).collect()
+    }
+
+    // The dictionary and data of an inline image ("BI").
+    pub fn inline_image(&self) -> Option<(&DictNode, &[u8])> {
+        match (self.operator.as_str(), self.operands.as_slice()) {
+            ("BI", [dict @ DictNode::Dict(_), DictNode::HexString(data)]) => Some((dict, data)),
+            _ => None,
+        }
+    }
+}
+
+pub struct Operations<R> {
+    tokens: Tokenizer<R>,
+    operands: Vec<DictNode>,
+}
+
+impl<R: BufRead> Operations<R> {
+    pub fn new(reader: R) -> Operations<R> {
+        Operations::from_tokens(Tokenizer::new(reader))
+    }
+
+    pub fn from_tokens(tokens: Tokenizer<R>) -> Operations<R> {
+        Operations { tokens, operands: Vec::new() }
+    }
+
+    // Byte offset into the decoded stream.
+    pub fn position(&self) -> usize {
+        self.tokens.position()
+    }
+
+    // The operations typed, see the ops module.
+    pub fn ops(self) -> impl Iterator<Item = Result<Op>> {
+        self.map(|operation| operation.map(Op::parse))
+    }
+}
+
+impl<R: BufRead> Iterator for Operations<R> {
+    type Item = Result<Operation>;
+
+    // Operands left over at the end, with no operator to take them, are
+    // dropped.
+    fn next(&mut self) -> Option<Result<Operation>> {
+        loop {
+            match self.tokens.next()? {
+                Ok(Token::Operand(operand)) => self.operands.push(operand),
+                Ok(Token::Operator(operator)) => {
+                    let operands = ::std::mem::take(&mut self.operands);
+                    return Some(Ok(Operation { operator, operands }));
+                },
+                Ok(Token::InlineImage(dict, data)) => {
+                    // BI takes no operands, whatever was there is dropped
+                    self.operands.clear();
+                    let operands = vec![DictNode::Dict(dict), DictNode::HexString(data)];
+                    return Some(Ok(Operation { operator: "BI".to_string(), operands }));
+                },
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn tokenize(content: &[u8]) -> Vec<Token> {
+    // a one byte buffer makes sure nothing depends on seeing ahead
+    let reader = ::std::io::BufReader::with_capacity(1, content);
+    Tokenizer::new(reader).map(|token| token.unwrap()).collect()
+}
+
+#[test]
+fn test_tokenize_text_object() {
+    let tokens = tokenize(b"BT\n318.8 197.8 Td /F2 11 Tf[<32>79<06>-1<13>]TJ\nET");
+    assert_eq!(tokens, vec![
+        Token::Operator("BT".to_string()),
+        Token::Operand(DictNode::Float(318.8)),
+        Token::Operand(DictNode::Float(197.8)),
+        Token::Operator("Td".to_string()),
+        Token::Operand(DictNode::Str("/F2".to_string())),
+        Token::Operand(DictNode::Int(11)),
+        Token::Operator("Tf".to_string()),
+        Token::Operand(DictNode::Array(vec![
+            DictNode::HexString(vec![0x32]),
+            DictNode::Int(79),
+            DictNode::HexString(vec![0x06]),
+            DictNode::Int(-1),
+            DictNode::HexString(vec![0x13]),
+        ])),
+        Token::Operator("TJ".to_string()),
+        Token::Operator("ET".to_string()),
+    ]);
+}
+
+#[test]
+fn test_tokenize_strings_and_comments() {
+    let tokens = tokenize(b"% a comment\n(nested (parens) and \\) escapes) Tj T* 0 -.5 Td null");
+    assert_eq!(tokens, vec![
+        Token::Operand(DictNode::LiteralString(b"nested (parens) and ) escapes".to_vec())),
+        Token::Operator("Tj".to_string()),
+        Token::Operator("T*".to_string()),
+        Token::Operand(DictNode::Int(0)),
+        Token::Operand(DictNode::Float(-0.5)),
+        Token::Operator("Td".to_string()),
+        Token::Operand(DictNode::Null),
+    ]);
+}
+
+#[test]
+fn test_tokenize_marked_content() {
+    let tokens = tokenize(b"/Span<</MCID 3>>BDC EMC");
+    assert_eq!(tokens, vec![
+        Token::Operand(DictNode::Str("/Span".to_string())),
+        Token::Operand(DictNode::Dict(hashmap!{ "MCID".to_string() => DictNode::Int(3) })),
+        Token::Operator("BDC".to_string()),
+        Token::Operator("EMC".to_string()),
+    ]);
+}
+
+#[test]
+fn test_tokenize_errors() {
+    let mut tokenizer = Tokenizer::new(&b"q ] Q"[..]);
+    assert_eq!(tokenizer.next().unwrap().unwrap(), Token::Operator("q".to_string()));
+    assert!(tokenizer.next().unwrap().is_err());
+    assert!(tokenizer.next().is_none());
+
+    let mut tokenizer = Tokenizer::new(&b"(unterminated"[..]);
+    assert!(tokenizer.next().unwrap().is_err());
+}
+
+#[test]
+fn test_inline_images() {
+    // unfiltered, so the length is known and "EI" in the data doesn't end it
+    let tokens = tokenize(b"q BI /W 2 /H 1 /BPC 8 /CS /G ID \x45\x49 EI Q");
+    assert_eq!(tokens, vec![
+        Token::Operator("q".to_string()),
+        Token::InlineImage(hashmap!{
+            "Width".to_string() => DictNode::Int(2),
+            "Height".to_string() => DictNode::Int(1),
+            "BitsPerComponent".to_string() => DictNode::Int(8),
+            "ColorSpace".to_string() => DictNode::Str("/DeviceGray".to_string()),
+        }, b"EI".to_vec()),
+        Token::Operator("Q".to_string()),
+    ]);
+
+    // filtered, up to the first EI between whitespace
+    let content = b"BI /W 4 /H 4 /CS [/I /RGB 1 <ff000000ff00>] /F [/AHx] /BPC 1 ID 0fEIf0 >\nEI\n/Im1 Do";
+    let operations: Vec<Operation> = Operations::new(&content[..]).map(|operation| operation.unwrap()).collect();
+    assert_eq!(operations.len(), 2);
+    let (dict, data) = operations[0].inline_image().unwrap();
+    assert_eq!(data, b"0fEIf0 >");
+    assert_eq!(dict.get("Filter"), Some(&DictNode::Array(vec![DictNode::Str("/ASCIIHexDecode".to_string())])));
+    assert_eq!(dict.get("ColorSpace").and_then(DictNode::as_array).map(|space| space[1].clone()), Some(DictNode::Str("/DeviceRGB".to_string())));
+    assert_eq!(operations[1].operator, "Do");
+
+    let mut tokenizer = Tokenizer::new(&b"BI /W 1 /H 1 /BPC 8 /CS /G ID"[..]);
+    assert!(tokenizer.next().unwrap().is_err());
+}
+
+#[test]
+fn test_parse_real() {
+    assert_eq!(parse_real("3."), Some(3.0));
+    assert_eq!(parse_real("+12.25"), Some(12.25));
+    assert_eq!(parse_real("-.5"), Some(-0.5));
+    assert_eq!(parse_real("."), None);
+    assert_eq!(parse_real("T*"), None);
+    assert_eq!(parse_real("1.2.3"), None);
+}
+
+#[test]
+fn test_operations() {
+    let content = b"q 1 0 0 1 72 720 cm BT /F1 12 Tf (Hi) Tj ET /Im0 Do 0 0 10 10 re f Q 1 2";
+    let operations: Vec<Operation> = Operations::new(&content[..]).map(|operation| operation.unwrap()).collect();
+    let operators: Vec<&str> = operations.iter().map(|operation| operation.operator.as_str()).collect();
+    assert_eq!(operators, vec!["q", "cm", "BT", "Tf", "Tj", "ET", "Do", "re", "f", "Q"]);
+    assert_eq!(operations[1].numbers(), Some(vec![1.0, 0.0, 0.0, 1.0, 72.0, 720.0]));
+    assert_eq!(operations[3].operands, vec![DictNode::Str("/F1".to_string()), DictNode::Int(12)]);
+    assert_eq!(operations[3].numbers(), None);
+    assert_eq!(operations[6].operands, vec![DictNode::Str("/Im0".to_string())]);
+    assert!(operations[9].operands.is_empty());
+}
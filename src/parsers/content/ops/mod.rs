@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use parsers::content::Operation;
+use parsers::cos::DictNode;
+
+/*
+ * Content stream operations with their operands checked and typed, so code
+ * interpreting a page matches on what an operator means rather than on its
+ * spelling and the shape of its operand stack:
+ *
+ *   for op in Operations::new(&content[..]).ops() {
+ *       match op? {
+ *           Op::SetFont { name, size } => ...,
+ *           Op::ShowText(string) => ...,
+ *           _ => (),
+ *       }
+ *   }
+ *
+ * Every operator of the spec's table A.1 has a variant, ID and EI aside,
+ * which only come out as part of an inline image. Operands have to be the
+ * count and types the spec asks for, integers standing in for reals as
+ * usual; names lose their slash. Operators that take nothing ignore
+ * whatever is left in front of them, since stray operands before a q or
+ * an ET are common and every viewer draws those pages anyway.
+ *
+ * What doesn't fit isn't an error, content streams are too often sloppy
+ * for that: an operator nobody knows is `Op::Unknown` (BX ... EX sections
+ * are meant to have those) and a known one with the wrong operands is
+ * `Op::Invalid`, both with the untouched operation.
+ */
+
+// The pieces of a TJ array.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TextItem {
+    Text(Vec<u8>),
+    // thousandths of an em, to the left
+    Adjust(f64),
+}
+
+// SC, sc, SCN and scn: the components, and the pattern's name for the
+// last two.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Color {
+    pub components: Vec<f64>,
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Op {
+    // graphics state
+    SetLineWidth(f64),
+    SetLineCap(i64),
+    SetLineJoin(i64),
+    SetMiterLimit(f64),
+    SetDash { array: Vec<f64>, phase: f64 },
+    SetRenderingIntent(String),
+    SetFlatness(f64),
+    SetGraphicsState(String),
+    Save,
+    Restore,
+    Transform([f64; 6]),
+
+    // path construction
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    CurveTo([f64; 6]),
+    // v, the first control point is the current point
+    CurveToInitial([f64; 4]),
+    // y, the second control point is the end point
+    CurveToFinal([f64; 4]),
+    ClosePath,
+    Rectangle([f64; 4]),
+
+    // painting, the bool is even-odd rather than nonzero filling
+    Stroke,
+    CloseStroke,
+    Fill { even_odd: bool },
+    FillStroke { even_odd: bool },
+    CloseFillStroke { even_odd: bool },
+    EndPath,
+    Clip { even_odd: bool },
+
+    // text
+    BeginText,
+    EndText,
+    SetCharSpacing(f64),
+    SetWordSpacing(f64),
+    SetHorizontalScaling(f64),
+    SetLeading(f64),
+    SetFont { name: String, size: f64 },
+    SetRenderMode(i64),
+    SetRise(f64),
+    MoveText(f64, f64),
+    // TD, which sets the leading too
+    MoveTextSetLeading(f64, f64),
+    SetTextMatrix([f64; 6]),
+    NextLine,
+    ShowText(Vec<u8>),
+    ShowTextAdjusted(Vec<TextItem>),
+    // '
+    NextLineShowText(Vec<u8>),
+    // "
+    NextLineShowTextSpaced { word_spacing: f64, char_spacing: f64, text: Vec<u8> },
+
+    // Type 3 glyphs
+    SetCharWidth(f64, f64),
+    SetCacheDevice([f64; 6]),
+
+    // color, stroking then nonstroking
+    SetStrokeColorSpace(String),
+    SetFillColorSpace(String),
+    SetStrokeColor(Color),
+    SetFillColor(Color),
+    SetStrokeGray(f64),
+    SetFillGray(f64),
+    SetStrokeRgb(f64, f64, f64),
+    SetFillRgb(f64, f64, f64),
+    SetStrokeCmyk([f64; 4]),
+    SetFillCmyk([f64; 4]),
+
+    // external objects
+    Shade(String),
+    PaintXObject(String),
+    InlineImage { dict: HashMap<String, DictNode>, data: Vec<u8> },
+
+    // marked content, with its properties dictionary or the name of one
+    // in the resources
+    MarkPoint(String),
+    MarkPointProperties(String, DictNode),
+    BeginMarkedContent(String),
+    BeginMarkedContentProperties(String, DictNode),
+    EndMarkedContent,
+
+    // compatibility sections
+    BeginCompatibility,
+    EndCompatibility,
+
+    Unknown(Operation),
+    Invalid(Operation),
+}
+
+impl Op {
+    pub fn parse(operation: Operation) -> Op {
+        match typed(&operation.operator, &operation.operands) {
+            Some(Some(op)) => op,
+            Some(None) => Op::Invalid(operation),
+            None => Op::Unknown(operation),
+        }
+    }
+}
+
+impl From<Operation> for Op {
+    fn from(operation: Operation) -> Op {
+        Op::parse(operation)
+    }
+}
+
+// None for an operator that isn't in the spec, Some(None) for one whose
+// operands don't fit.
+fn typed(operator: &str, operands: &[DictNode]) -> Option<Option<Op>> {
+    let numbers: Option<Vec<f64>> = operands.iter().map(DictNode::as_float).collect();
+    let numbers = numbers.as_deref();
+    let op = match operator {
+        "w" => fixed(numbers).map(|[width]| Op::SetLineWidth(width)),
+        "J" => integer(operands).map(Op::SetLineCap),
+        "j" => integer(operands).map(Op::SetLineJoin),
+        "M" => fixed(numbers).map(|[limit]| Op::SetMiterLimit(limit)),
+        "d" => match operands {
+            [array, phase] => match (array.as_array(), phase.as_float()) {
+                (Some(array), Some(phase)) => {
+                    let array: Option<Vec<f64>> = array.iter().map(DictNode::as_float).collect();
+                    array.map(|array| Op::SetDash { array, phase })
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+        "ri" => name(operands).map(Op::SetRenderingIntent),
+        "i" => fixed(numbers).map(|[flatness]| Op::SetFlatness(flatness)),
+        "gs" => name(operands).map(Op::SetGraphicsState),
+        "q" => Some(Op::Save),
+        "Q" => Some(Op::Restore),
+        "cm" => fixed(numbers).map(Op::Transform),
+
+        "m" => fixed(numbers).map(|[x, y]| Op::MoveTo(x, y)),
+        "l" => fixed(numbers).map(|[x, y]| Op::LineTo(x, y)),
+        "c" => fixed(numbers).map(Op::CurveTo),
+        "v" => fixed(numbers).map(Op::CurveToInitial),
+        "y" => fixed(numbers).map(Op::CurveToFinal),
+        "h" => Some(Op::ClosePath),
+        "re" => fixed(numbers).map(Op::Rectangle),
+
+        "S" => Some(Op::Stroke),
+        "s" => Some(Op::CloseStroke),
+        // F is an old spelling of f
+        "f" | "F" => Some(Op::Fill { even_odd: false }),
+        "f*" => Some(Op::Fill { even_odd: true }),
+        "B" => Some(Op::FillStroke { even_odd: false }),
+        "B*" => Some(Op::FillStroke { even_odd: true }),
+        "b" => Some(Op::CloseFillStroke { even_odd: false }),
+        "b*" => Some(Op::CloseFillStroke { even_odd: true }),
+        "n" => Some(Op::EndPath),
+        "W" => Some(Op::Clip { even_odd: false }),
+        "W*" => Some(Op::Clip { even_odd: true }),
+
+        "BT" => Some(Op::BeginText),
+        "ET" => Some(Op::EndText),
+        "Tc" => fixed(numbers).map(|[spacing]| Op::SetCharSpacing(spacing)),
+        "Tw" => fixed(numbers).map(|[spacing]| Op::SetWordSpacing(spacing)),
+        "Tz" => fixed(numbers).map(|[scaling]| Op::SetHorizontalScaling(scaling)),
+        "TL" => fixed(numbers).map(|[leading]| Op::SetLeading(leading)),
+        "Tf" => match operands {
+            [name, size] => match (name.as_name(), size.as_float()) {
+                (Some(name), Some(size)) => Some(Op::SetFont { name: name.to_string(), size }),
+                _ => None,
+            },
+            _ => None,
+        },
+        "Tr" => integer(operands).map(Op::SetRenderMode),
+        "Ts" => fixed(numbers).map(|[rise]| Op::SetRise(rise)),
+        "Td" => fixed(numbers).map(|[x, y]| Op::MoveText(x, y)),
+        "TD" => fixed(numbers).map(|[x, y]| Op::MoveTextSetLeading(x, y)),
+        "Tm" => fixed(numbers).map(Op::SetTextMatrix),
+        "T*" => Some(Op::NextLine),
+        "Tj" => string(operands).map(Op::ShowText),
+        "TJ" => match operands {
+            [DictNode::Array(items)] => items.iter().map(text_item).collect::<Option<Vec<TextItem>>>().map(Op::ShowTextAdjusted),
+            _ => None,
+        },
+        "'" => string(operands).map(Op::NextLineShowText),
+        "\"" => match operands {
+            [word_spacing, char_spacing, text] => match (word_spacing.as_float(), char_spacing.as_float(), text.as_string()) {
+                (Some(word_spacing), Some(char_spacing), Some(text)) => {
+                    Some(Op::NextLineShowTextSpaced { word_spacing, char_spacing, text: text.to_vec() })
+                },
+                _ => None,
+            },
+            _ => None,
+        },
+
+        "d0" => fixed(numbers).map(|[x, y]| Op::SetCharWidth(x, y)),
+        "d1" => fixed(numbers).map(Op::SetCacheDevice),
+
+        "CS" => name(operands).map(Op::SetStrokeColorSpace),
+        "cs" => name(operands).map(Op::SetFillColorSpace),
+        "SC" => color(operands, false).map(Op::SetStrokeColor),
+        "sc" => color(operands, false).map(Op::SetFillColor),
+        "SCN" => color(operands, true).map(Op::SetStrokeColor),
+        "scn" => color(operands, true).map(Op::SetFillColor),
+        "G" => fixed(numbers).map(|[gray]| Op::SetStrokeGray(gray)),
+        "g" => fixed(numbers).map(|[gray]| Op::SetFillGray(gray)),
+        "RG" => fixed(numbers).map(|[r, g, b]| Op::SetStrokeRgb(r, g, b)),
+        "rg" => fixed(numbers).map(|[r, g, b]| Op::SetFillRgb(r, g, b)),
+        "K" => fixed(numbers).map(Op::SetStrokeCmyk),
+        "k" => fixed(numbers).map(Op::SetFillCmyk),
+
+        "sh" => name(operands).map(Op::Shade),
+        "Do" => name(operands).map(Op::PaintXObject),
+        "BI" => match operands {
+            [DictNode::Dict(dict), DictNode::HexString(data)] => Some(Op::InlineImage { dict: dict.clone(), data: data.clone() }),
+            _ => None,
+        },
+
+        "MP" => name(operands).map(Op::MarkPoint),
+        "DP" => tagged(operands).map(|(tag, properties)| Op::MarkPointProperties(tag, properties)),
+        "BMC" => name(operands).map(Op::BeginMarkedContent),
+        "BDC" => tagged(operands).map(|(tag, properties)| Op::BeginMarkedContentProperties(tag, properties)),
+        "EMC" => Some(Op::EndMarkedContent),
+
+        "BX" => Some(Op::BeginCompatibility),
+        "EX" => Some(Op::EndCompatibility),
+
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Exactly N numbers.
+fn fixed<const N: usize>(numbers: Option<&[f64]>) -> Option<[f64; N]> {
+    numbers.and_then(|numbers| <[f64; N]>::try_from(numbers).ok())
+}
+
+fn integer(operands: &[DictNode]) -> Option<i64> {
+    match operands {
+        [operand] => operand.as_int(),
+        _ => None,
+    }
+}
+
+fn name(operands: &[DictNode]) -> Option<String> {
+    match operands {
+        [operand] => operand.as_name().map(str::to_string),
+        _ => None,
+    }
+}
+
+fn string(operands: &[DictNode]) -> Option<Vec<u8>> {
+    match operands {
+        [operand] => operand.as_string().map(<[u8]>::to_vec),
+        _ => None,
+    }
+}
+
+fn text_item(item: &DictNode) -> Option<TextItem> {
+    match item.as_float() {
+        Some(adjustment) => Some(TextItem::Adjust(adjustment)),
+        None => item.as_string().map(|text| TextItem::Text(text.to_vec())),
+    }
+}
+
+// One component per colorant, up to the 32 a DeviceN space can have, and
+// for SCN and scn a pattern's name last, with no components before it
+// unless the pattern is uncolored.
+fn color(operands: &[DictNode], pattern: bool) -> Option<Color> {
+    let (components, pattern) = match operands.split_last() {
+        Some((last, rest)) if pattern && last.as_name().is_some() => (rest, last.as_name().map(str::to_string)),
+        _ => (operands, None),
+    };
+    let components: Vec<f64> = components.iter().map(DictNode::as_float).collect::<Option<_>>()?;
+    if components.len() > 32 || (components.is_empty() && pattern.is_none()) {
+        return None;
+    }
+    Some(Color { components, pattern })
+}
+
+fn tagged(operands: &[DictNode]) -> Option<(String, DictNode)> {
+    match operands {
+        [tag, properties @ DictNode::Dict(_)] | [tag, properties @ DictNode::Str(_)] => {
+            Some((tag.as_name()?.to_string(), properties.clone()))
+        },
+        _ => None,
+    }
+}
+
+#[test]
+fn test_ops() {
+    use parsers::content::Operations;
+
+    let content = b"q 1 0 0 1 72 720 cm BT /F1 12 Tf [(A) -250 (B)] TJ 0 g ET /Sep1 cs 0.5 /P0 scn /P1 <</MCID 3>> BDC EMC Q";
+    let ops: Vec<Op> = Operations::new(&content[..]).ops().map(|op| op.unwrap()).collect();
+    assert_eq!(ops, vec![
+        Op::Save,
+        Op::Transform([1.0, 0.0, 0.0, 1.0, 72.0, 720.0]),
+        Op::BeginText,
+        Op::SetFont { name: "F1".to_string(), size: 12.0 },
+        Op::ShowTextAdjusted(vec![TextItem::Text(b"A".to_vec()), TextItem::Adjust(-250.0), TextItem::Text(b"B".to_vec())]),
+        Op::SetFillGray(0.0),
+        Op::EndText,
+        Op::SetFillColorSpace("Sep1".to_string()),
+        Op::SetFillColor(Color { components: vec![0.5], pattern: Some("P0".to_string()) }),
+        Op::BeginMarkedContentProperties("P1".to_string(), DictNode::Dict(HashMap::from([("MCID".to_string(), DictNode::Int(3))]))),
+        Op::EndMarkedContent,
+        Op::Restore,
+    ]);
+}
+
+#[test]
+fn test_invalid_ops() {
+    let parse = |operator: &str, operands: Vec<DictNode>| Op::parse(Operation { operator: operator.to_string(), operands });
+
+    // the wrong count or types
+    assert!(matches!(parse("Tf", vec![DictNode::Int(12)]), Op::Invalid(_)));
+    assert!(matches!(parse("cm", vec![DictNode::Int(1); 5]), Op::Invalid(_)));
+    assert!(matches!(parse("Tj", vec![DictNode::Int(1)]), Op::Invalid(_)));
+    assert!(matches!(parse("TJ", vec![DictNode::Array(vec![DictNode::Null])]), Op::Invalid(_)));
+    assert!(matches!(parse("Tr", vec![DictNode::Float(1.5)]), Op::Invalid(_)));
+    assert!(matches!(parse("sc", vec![]), Op::Invalid(_)));
+
+    // integers for reals, and leftovers before an operator without operands
+    assert_eq!(parse("Tz", vec![DictNode::Int(90)]), Op::SetHorizontalScaling(90.0));
+    assert_eq!(parse("ET", vec![DictNode::Int(0)]), Op::EndText);
+
+    let unknown = parse("foo", vec![DictNode::Int(1)]);
+    assert_eq!(unknown, Op::Unknown(Operation { operator: "foo".to_string(), operands: vec![DictNode::Int(1)] }));
+}
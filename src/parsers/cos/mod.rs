@@ -1,5 +1,10 @@
 use pest::prelude::*;
 use std::collections::HashMap;
+use std::fmt;
+
+use document::Document;
+use error::{PdfError, Result};
+use filters;
 
 /*
  * Parser for PDF COS object syntax. Think of COS kind of like a really
@@ -9,6 +14,10 @@ use std::collections::HashMap;
  *
  */
 
+// How deep arrays and dictionaries may nest, in objects and in content
+// streams. Real files stay in the single digits.
+pub const MAX_DEPTH: usize = 256;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum DictNode {
     Dict(HashMap<String, DictNode>),
@@ -18,6 +27,188 @@ pub enum DictNode {
     Int(i64),
     Float(f64),
     Str(String),
+    // a (...) string, unescaped
+    LiteralString(Vec<u8>),
+    // a <...> string, decoded to the bytes it stands for
+    HexString(Vec<u8>),
+    Null,
+}
+
+// Shorthands for the nodes every writer builds:
+//
+//   dict.insert("Type".to_string(), DictNode::name("Page"));
+//   dict.insert("Parent".to_string(), DictNode::reference(parent));
+impl DictNode {
+    // The name /`name`, given without its slash, the way `as_name` reads it.
+    pub fn name(name: &str) -> DictNode {
+        DictNode::Str(format!("/{}", name))
+    }
+
+    // A reference to object `id`, generation 0 like everything we add.
+    pub fn reference(id: u64) -> DictNode {
+        DictNode::ObjectReference(id as i64, 0)
+    }
+}
+
+// Typed access, for walking objects without matching on every level:
+//
+//   let bbox = font.get("FontBBox").and_then(DictNode::as_rect);
+//   let kids = pages.get_resolved(doc, "Kids")?;
+//
+// Each returns None when the node isn't of the type asked for.
+impl DictNode {
+    // The dictionary entry for `key`, None if this isn't a dictionary.
+    pub fn get(&self, key: &str) -> Option<&DictNode> {
+        self.as_dict().and_then(|map| map.get(key))
+    }
+
+    // Same as `get`, with the entry resolved if it's a reference.
+    pub fn get_resolved(&self, doc: &mut Document, key: &str) -> Result<Option<DictNode>> {
+        match self.get(key) {
+            Some(value) => doc.resolve(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&HashMap<String, DictNode>> {
+        match *self {
+            DictNode::Dict(ref map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[DictNode]> {
+        match *self {
+            DictNode::Array(ref items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            DictNode::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // Integers count as numbers too.
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            DictNode::Int(value) => Some(value as f64),
+            DictNode::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            DictNode::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // A name without its slash, "Page" for /Page.
+    pub fn as_name(&self) -> Option<&str> {
+        match *self {
+            DictNode::Str(ref value) => value.strip_prefix('/'),
+            _ => None,
+        }
+    }
+
+    // The bytes of a literal or hex string.
+    pub fn as_string(&self) -> Option<&[u8]> {
+        match *self {
+            DictNode::LiteralString(ref bytes) | DictNode::HexString(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    // A text string as what it reads: titles, bookmarks, field values,
+    // metadata. See `text_string`.
+    pub fn as_text(&self) -> Option<String> {
+        self.as_string().map(text_string)
+    }
+
+    pub fn as_reference(&self) -> Option<(i64, i64)> {
+        match *self {
+            DictNode::ObjectReference(id, generation) => Some((id, generation)),
+            _ => None,
+        }
+    }
+
+    // A rectangle or bounding box, [x1 y1 x2 y2] of any mix of integers
+    // and floats.
+    pub fn as_rect(&self) -> Option<[f64; 4]> {
+        match self.as_array()? {
+            [x1, y1, x2, y2] => Some([x1.as_float()?, y1.as_float()?, x2.as_float()?, y2.as_float()?]),
+            _ => None,
+        }
+    }
+
+    // Whether this is the name /`name`.
+    pub fn is_name(&self, name: &str) -> bool {
+        self.as_name() == Some(name)
+    }
+}
+
+// Writes the node back out in COS syntax, dictionary keys sorted so the
+// output is stable.
+impl fmt::Display for DictNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DictNode::Dict(ref map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                write!(f, "<<")?;
+                for key in keys {
+                    write!(f, " ")?;
+                    write_name(f, key)?;
+                    write!(f, " {}", map[key])?;
+                }
+                write!(f, " >>")
+            },
+            DictNode::Array(ref items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            DictNode::ObjectReference(id, generation) => write!(f, "{} {} R", id, generation),
+            DictNode::Bool(value) => write!(f, "{}", value),
+            DictNode::Int(value) => write!(f, "{}", value),
+            // keep the decimal point so it reads back as a float
+            DictNode::Float(value) if value.fract() == 0.0 => write!(f, "{:.1}", value),
+            DictNode::Float(value) => write!(f, "{}", value),
+            // names are stored with their slash
+            DictNode::Str(ref value) if value.starts_with('/') => write_name(f, &value[1..]),
+            DictNode::Str(ref value) => write!(f, "{}", value),
+            DictNode::LiteralString(ref bytes) => {
+                write!(f, "(")?;
+                for &byte in bytes {
+                    match byte {
+                        b'(' | b')' | b'\\' => write!(f, "\\{}", byte as char)?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\r' => write!(f, "\\r")?,
+                        b' '..=b'~' => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\{:03o}", byte)?,
+                    }
+                }
+                write!(f, ")")
+            },
+            DictNode::HexString(ref bytes) => {
+                write!(f, "<")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, ">")
+            },
+            DictNode::Null => write!(f, "null"),
+        }
+    }
 }
 
 impl_rdp! {
@@ -28,31 +219,46 @@ impl_rdp! {
         endarray = { ["]"] }
         dictionary = {  begindict ~ keypair* ~ enddict }
         keypair = { key ~ node }
-        node = _{ (array | reference | string |key | int | float | boolean | dictionary) }
+        node = _{ (array | reference | string | hex_string | key | int | float | boolean | null | dictionary) }
         array = { beginarray ~ node* ~ endarray }
         reference =  { int ~ int ~ ["R"] }
         key = @{ ["/"] ~ (!special ~ !whitespace ~ any)+ }
-        string = @{ (["("] ~ acceptable_string* ~ [")"]) | (["<"] ~ acceptable_string+ ~ [">"])}
-        acceptable_string = _{ (whitespace | ["/"] | ['a'..'z'] | ['A'..'Z'] | ['0'..'9'] | [":"] | ["."] | ["@"] | ["'"] ) }
+        string = @{ ["("] ~ string_char* ~ [")"] }
+        // escapes are checked when unescaping, parentheses only need to balance
+        string_char = _{ (["\\"] ~ any) | (["("] ~ string_char* ~ [")"]) | (!["("] ~ ![")"] ~ !["\\"] ~ any) }
+        hex_string = @{ ["<"] ~ (hex_digit | [" "] | ["\t"] | ["\r"] | ["\n"])* ~ [">"] }
+        hex_digit = _{ ['0'..'9'] | ['a'..'f'] | ['A'..'F'] }
         int =  @{ !float ~ ["-"]? ~ ['0'..'9']+ }
         float =  @{ ["-"]? ~ ['0'..'9']+ ~ ["."] ~ ['0'..'9']* }
         boolean = @{ ["true"] | ["false"] }
+        null = @{ ["null"] }
         whitespace = _{ [" "] | ["\t"] | ["\r"] | ["\n"] | ["endobj"] }
         special = { beginarray | begindict | endarray | enddict | ["\\"]| ["/"] | ["("] | [")"] }
     }
 
     process! {
         parse(&self) -> DictNode {
-            (&int: int) => DictNode::Int(int.parse::<i64>().unwrap()),
-            (&float: float) => DictNode::Float(float.parse::<f64>().unwrap()),
-            (&s: string) => DictNode::Str(s.to_string()),
-            (&b: boolean) => DictNode::Bool(b.parse::<bool>().unwrap()),
-            (&k: key) => DictNode::Str(k.to_string()),
+            (&int: int) => {
+                // the grammar guarantees digits, but not that they fit
+                match int.parse::<i64>() {
+                    Ok(int) => DictNode::Int(int),
+                    Err(_) => DictNode::Float(int.parse::<f64>().unwrap_or(0.0)),
+                }
+            },
+            (&float: float) => DictNode::Float(float.parse::<f64>().unwrap_or(0.0)),
+            (&s: string) => DictNode::LiteralString(unescape(&bytes(&s[1..s.len() - 1]))),
+            // the grammar only lets hex digits and whitespace through, so
+            // this can't fail, but nothing in here gets to panic
+            (&hex: hex_string) => DictNode::HexString(filters::ascii_hex_decode(&bytes(&hex[1..])).unwrap_or_default()),
+            (&b: boolean) => DictNode::Bool(b == "true"),
+            (&k: key) => DictNode::Str(format!("/{}", String::from_utf8_lossy(&decode_name(&bytes(&k[1..]))))),
+            (_: null) => DictNode::Null,
             (_: reference, u1: parse(), u2: parse()) => {
-                // this is fucking lame, given my grammar I know these are ints
+                // ints too big for an i64 come out as floats, and there's
+                // no object by that number anyway
                 match (u1, u2) {
                     (DictNode::Int(a), DictNode::Int(b)) => DictNode::ObjectReference(a, b),
-                    _ => unreachable!(),
+                    _ => DictNode::Null,
                 }
             },
             (_: array, _: beginarray, mut contents: _array()) => {
@@ -75,13 +281,281 @@ impl_rdp! {
         _dict(&self) -> HashMap<String, DictNode> {
             (_: enddict) => HashMap::new(),
             (_: keypair, &key: key, value: parse(), mut tail: _dict()) => {
-                tail.insert(key[1..].to_string(), value);
+                tail.insert(String::from_utf8_lossy(&decode_name(&bytes(&key[1..]))).into_owned(), value);
                 tail
             },
         }
     }
 }
 
+// `name` as a name, slash first. Anything that wouldn't read back as part
+// of the name is escaped, for dictionary keys as much as for values.
+fn write_name(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    write!(f, "/")?;
+    for &byte in name.as_bytes() {
+        match byte {
+            b'#' | b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%' => write!(f, "#{:02x}", byte)?,
+            b'!'..=b'~' => write!(f, "{}", byte as char)?,
+            _ => write!(f, "#{:02x}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+// Parses a single COS object (dictionary, array, atom...) out of `input`,
+// ignoring any leading whitespace. Objects straight out of a file should go
+// through `parse_bytes`, here every char stands for the byte with the same
+// value so only ASCII is safe.
+pub fn parse(input: &str) -> Result<DictNode> {
+    // the grammar recurses once per level, a few thousand '[' would
+    // overflow the stack
+    if let Some(position) = too_deep(input.as_bytes()) {
+        return Err(PdfError::Syntax { what: "object nesting", position });
+    }
+    let mut parser = Rdp::new(StringInput::new(input));
+    parser.skip();
+    if !parser.node() {
+        let (_, position) = parser.expected();
+        return Err(PdfError::Syntax { what: "object", position });
+    }
+    Ok(parser.parse())
+}
+
+// Where arrays and dictionaries get nested more than MAX_DEPTH deep, if
+// they do. Strings are skipped, a '[' in one doesn't count.
+fn too_deep(input: &[u8]) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut index = 0;
+    while index < input.len() {
+        match input[index] {
+            b'[' => depth += 1,
+            b'<' if input.get(index + 1) == Some(&b'<') => {
+                depth += 1;
+                index += 1;
+            },
+            b']' => depth = depth.saturating_sub(1),
+            b'>' if input.get(index + 1) == Some(&b'>') => {
+                depth = depth.saturating_sub(1);
+                index += 1;
+            },
+            b'(' => {
+                let mut strings = 1;
+                while strings > 0 && index + 1 < input.len() {
+                    index += 1;
+                    match input[index] {
+                        b'\\' => index += 1,
+                        b'(' => strings += 1,
+                        b')' => strings -= 1,
+                        _ => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+        if depth > MAX_DEPTH {
+            return Some(index);
+        }
+        index += 1;
+    }
+    None
+}
+
+// Parses an object from raw file bytes. Objects are mostly ASCII, but
+// strings can hold anything and names are often UTF-8, so the bytes are
+// handed to the grammar one char per byte and turned back into bytes by
+// `bytes` where it matters.
+pub fn parse_bytes(input: &[u8]) -> Result<DictNode> {
+    parse(&latin1(input))
+}
+
+// Bytes as Latin-1 chars, so text that isn't UTF-8 survives the trip
+// through a String.
+pub fn latin1(input: &[u8]) -> String {
+    input.iter().map(|&byte| byte as char).collect()
+}
+
+// PDFDocEncoding where it differs from Latin-1, from 0x18 to 0x1f and
+// from 0x7f to 0xad. The holes in the encoding come out as U+FFFD.
+const PDF_DOC_LOW: [char; 8] = ['\u{2d8}', '\u{2c7}', '\u{2c6}', '\u{2d9}', '\u{2dd}', '\u{2db}', '\u{2da}', '\u{2dc}'];
+const PDF_DOC_HIGH: [char; 47] = [
+    '\u{fffd}', '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{192}', '\u{2044}',
+    '\u{2039}', '\u{203a}', '\u{2212}', '\u{2030}', '\u{201e}', '\u{201c}', '\u{201d}', '\u{2018}', '\u{2019}',
+    '\u{201a}', '\u{2122}', '\u{fb01}', '\u{fb02}', '\u{141}', '\u{152}', '\u{160}', '\u{178}', '\u{17d}',
+    '\u{131}', '\u{142}', '\u{153}', '\u{161}', '\u{17e}', '\u{fffd}', '\u{20ac}', '\u{a1}', '\u{a2}', '\u{a3}',
+    '\u{a4}', '\u{a5}', '\u{a6}', '\u{a7}', '\u{a8}', '\u{a9}', '\u{aa}', '\u{ab}', '\u{ac}', '\u{fffd}',
+];
+
+// Decodes a text string, the kind of string meant to be read by people.
+// Those starting with the FE FF byte order mark are UTF-16BE and, since
+// PDF 2.0, those starting with EF BB BF are UTF-8; everything else is
+// PDFDocEncoding, which is Latin-1 but for typographic punctuation in
+// place of most control codes. The language escapes UTF-16 strings may
+// carry (ESC, a language code, ESC) are dropped, and bytes that don't
+// decode come out as U+FFFD.
+pub fn text_string(input: &[u8]) -> String {
+    if let Some(utf16) = input.strip_prefix(b"\xfe\xff") {
+        let units: Vec<u16> = utf16.chunks(2)
+            .map(|pair| match *pair {
+                [high, low] => u16::from_be_bytes([high, low]),
+                _ => 0xfffd,
+            })
+            .collect();
+        let mut text = String::new();
+        let mut escaped = false;
+        for ch in char::decode_utf16(units.iter().cloned()) {
+            match ch {
+                Ok('\u{1b}') => escaped = !escaped,
+                Ok(_) | Err(_) if escaped => {},
+                Ok(ch) => text.push(ch),
+                Err(_) => text.push('\u{fffd}'),
+            }
+        }
+        return text;
+    }
+    if let Some(utf8) = input.strip_prefix(b"\xef\xbb\xbf") {
+        return String::from_utf8_lossy(utf8).into_owned();
+    }
+    input.iter()
+        .map(|&byte| match byte {
+            0x18..=0x1f => PDF_DOC_LOW[byte as usize - 0x18],
+            0x7f..=0xad => PDF_DOC_HIGH[byte as usize - 0x7f],
+            _ => byte as char,
+        })
+        .collect()
+}
+
+fn bytes(text: &str) -> Vec<u8> {
+    text.chars().map(|ch| ch as u32 as u8).collect()
+}
+
+// Resolves the #xx escapes in a name, given what follows its slash. A #
+// without two hex digits after it is kept as is.
+pub fn decode_name(raw: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut index = 0;
+    while index < raw.len() {
+        let escaped = match raw.get(index + 1..index + 3) {
+            Some(&[high, low]) if raw[index] == b'#' => {
+                match ((high as char).to_digit(16), (low as char).to_digit(16)) {
+                    (Some(high), Some(low)) => Some((high * 16 + low) as u8),
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                index += 3;
+            },
+            None => {
+                bytes.push(raw[index]);
+                index += 1;
+            },
+        }
+    }
+    bytes
+}
+
+// The bytes a literal string stands for, given what's between its outer
+// parentheses.
+pub fn unescape(raw: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut rest = raw.iter().cloned().peekable();
+    while let Some(byte) = rest.next() {
+        match byte {
+            b'\\' => match rest.next() {
+                Some(b'n') => bytes.push(b'\n'),
+                Some(b'r') => bytes.push(b'\r'),
+                Some(b't') => bytes.push(b'\t'),
+                Some(b'b') => bytes.push(b'\x08'),
+                Some(b'f') => bytes.push(b'\x0c'),
+                Some(digit @ b'0'..=b'7') => {
+                    // up to three octal digits, overflow past a byte is ignored
+                    let mut value = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        match rest.peek() {
+                            Some(&digit @ b'0'..=b'7') => {
+                                value = value * 8 + u32::from(digit - b'0');
+                                rest.next();
+                            },
+                            _ => break,
+                        }
+                    }
+                    bytes.push(value as u8);
+                },
+                // a backslash at the end of a line continues the string on
+                // the next one
+                Some(b'\r') => {
+                    rest.next_if_eq(&b'\n');
+                },
+                Some(b'\n') => (),
+                // \(, \), \\ and unknown escapes stand for the character itself
+                Some(other) => bytes.push(other),
+                None => (),
+            },
+            // any end of line reads as a single \n
+            b'\r' => {
+                rest.next_if_eq(&b'\n');
+                bytes.push(b'\n');
+            },
+            _ => bytes.push(byte),
+        }
+    }
+    bytes
+}
+
+#[test]
+fn test_accessors() {
+    let font = DictNode::Dict(hashmap!{
+        "Type".to_string() => DictNode::Str("/Font".to_string()),
+        "FontBBox".to_string() => DictNode::Array(vec![DictNode::Int(-166), DictNode::Int(-225), DictNode::Float(1000.5), DictNode::Int(931)]),
+        "FirstChar".to_string() => DictNode::Int(32),
+        "BaseFont".to_string() => DictNode::LiteralString(b"not a name".to_vec()),
+    });
+    assert_eq!(font.get("FontBBox").and_then(DictNode::as_rect), Some([-166.0, -225.0, 1000.5, 931.0]));
+    assert_eq!(font.get("FirstChar").and_then(DictNode::as_int), Some(32));
+    assert_eq!(font.get("FirstChar").and_then(DictNode::as_float), Some(32.0));
+    assert_eq!(font.get("Type").and_then(DictNode::as_name), Some("Font"));
+    assert!(font.get("Type").unwrap().is_name("Font"));
+    assert_eq!(font.get("BaseFont").and_then(DictNode::as_name), None);
+    assert_eq!(font.get("BaseFont").and_then(DictNode::as_string), Some(&b"not a name"[..]));
+    assert_eq!(font.get("Missing"), None);
+    assert_eq!(DictNode::Int(1).get("Type"), None);
+    assert_eq!(DictNode::Array(vec![DictNode::Int(1)]).as_rect(), None);
+    assert_eq!(DictNode::ObjectReference(4, 0).as_reference(), Some((4, 0)));
+}
+
+#[test]
+fn test_text_string() {
+    // PDFDocEncoding, with its typographic quotes and euro sign
+    assert_eq!(text_string(b"caf\xe9"), "café");
+    assert_eq!(text_string(b"\x8dQuoted\x8e \xa05 \x84 \x93ne"), "\u{201c}Quoted\u{201d} €5 — \u{fb01}ne");
+    assert_eq!(text_string(b"\x18\x1f\x7f\x9f\xad"), "\u{2d8}\u{2dc}\u{fffd}\u{fffd}\u{fffd}");
+    // UTF-16BE, including a surrogate pair, a language escape, a dangling
+    // surrogate and an odd byte at the end
+    assert_eq!(text_string(b"\xfe\xff\x00H\x00i"), "Hi");
+    assert_eq!(text_string(b"\xfe\xff\xd8\x3d\xde\x00\x00 \x65\xe5"), "\u{1f600} 日");
+    assert_eq!(text_string(b"\xfe\xff\x00\x1b\x00f\x00r\x00\x1b\x00O\x00u\x00i"), "Oui");
+    assert_eq!(text_string(b"\xfe\xff\xd8\x00\x00a\x00"), "\u{fffd}a\u{fffd}");
+    assert_eq!(text_string(b"\xfe\xff"), "");
+    // UTF-8
+    assert_eq!(text_string(b"\xef\xbb\xbfna\xc3\xafve"), "naïve");
+
+    assert_eq!(DictNode::HexString(b"\xfe\xff\x00O\x00K".to_vec()).as_text().as_deref(), Some("OK"));
+    assert_eq!(DictNode::Str("/Name".to_string()).as_text(), None);
+}
+
+#[test]
+fn test_too_deep() {
+    assert_eq!(too_deep(b"<</Kids [[1 0 R] [2 0 R]] /A <</B [(]]]]])]>>>>"), None);
+    let deep = "[".repeat(MAX_DEPTH + 1);
+    assert_eq!(too_deep(deep.as_bytes()), Some(MAX_DEPTH));
+    // brackets in strings don't nest anything
+    assert_eq!(too_deep(format!("({})", deep).as_bytes()), None);
+    assert!(parse(&deep).is_err());
+}
+
 #[test]
 fn test_key() {
     let mut parser = Rdp::new(StringInput::new("/Hello"));
@@ -132,11 +606,77 @@ fn test_string() {
     assert!(parser.end());
 
     let mut parser = Rdp::new(StringInput::new("<d83abc5b1b9bea6e1b372681e568f886><d83abc5b1b9bea6e1b372681e568f886>"));
-    assert!(parser.string());
-    assert!(parser.string());
+    assert!(parser.hex_string());
+    assert!(parser.hex_string());
     assert!(parser.end());
 }
 
+#[test]
+fn test_binary_strings() {
+    let node = parse_bytes(b"<</ID [(\xff\x00\xe9)] /Name /Caf\xc3\xa9>>").unwrap();
+    assert_eq!(node, DictNode::Dict(hashmap!{
+        "ID".to_string() => DictNode::Array(vec![DictNode::LiteralString(vec![0xff, 0x00, 0xe9])]),
+        "Name".to_string() => DictNode::Str("/Caf\u{e9}".to_string()),
+    }));
+}
+
+#[test]
+fn test_name_escapes() {
+    assert_eq!(decode_name(b"A#20Name"), b"A Name".to_vec());
+    assert_eq!(decode_name(b"F#23oo"), b"F#oo".to_vec());
+    assert_eq!(decode_name(b"Lime#20Green#"), b"Lime Green#".to_vec());
+    assert_eq!(decode_name(b"Bad#2x#4"), b"Bad#2x#4".to_vec());
+
+    assert_eq!(DictNode::Str("/A Name".to_string()).to_string(), "/A#20Name");
+    assert_eq!(DictNode::Str("/F#oo(1)".to_string()).to_string(), "/F#23oo#281#29");
+}
+
+#[test]
+fn test_parsing_escaped_names() {
+    assert_eq!(parse("/A#20Name").unwrap(), DictNode::Str("/A Name".to_string()));
+    assert_eq!(parse("<</F#23oo 1>>").unwrap(), DictNode::Dict(hashmap!{ "F#oo".to_string() => DictNode::Int(1) }));
+}
+
+#[test]
+fn test_escaped_keys_round_trip() {
+    // a space, a # and a slash in keys, and a UTF-8 name as a value
+    let dict = parse("<</A#20Name 1 /F#23oo#2fbar /Caf#c3#a9 /Plain 2>>").unwrap();
+    assert_eq!(dict.to_string(), "<< /A#20Name 1 /F#23oo#2fbar /Caf#c3#a9 /Plain 2 >>");
+    assert_eq!(parse(&dict.to_string()).unwrap(), dict);
+}
+
+#[test]
+fn test_literal_string() {
+    assert_eq!(parse("(Smith \\(ed.\\))").unwrap(), DictNode::LiteralString(b"Smith (ed.)".to_vec()));
+    assert_eq!(parse("(a(b)c)").unwrap(), DictNode::LiteralString(b"a(b)c".to_vec()));
+    assert_eq!(parse("(\\050\\51)").unwrap(), DictNode::LiteralString(b"()".to_vec()));
+    assert!(parse("(unbalanced (paren)").is_err());
+}
+
+#[test]
+fn test_unescape() {
+    assert_eq!(unescape(b"\\050\\51\\0053\\501"), b"()\x053A".to_vec());
+    assert_eq!(unescape(b"one \\\ntwo\r\nthree\\n\rfour\\\r\n"), b"one two\nthree\n\nfour".to_vec());
+    assert_eq!(unescape(b"\\q\\\\\\(\\t"), b"q\\(\t".to_vec());
+
+    let node = DictNode::LiteralString(b"a (b) \\ \n\xe9".to_vec());
+    assert_eq!(node.to_string(), "(a \\(b\\) \\\\ \\n\\351)");
+}
+
+#[test]
+fn test_hex_string() {
+    assert_eq!(parse("<d83a BC5b\n0f>").unwrap(), DictNode::HexString(vec![0xd8, 0x3a, 0xbc, 0x5b, 0x0f]));
+    // a missing final digit counts as 0
+    assert_eq!(parse("<901fa>").unwrap(), DictNode::HexString(vec![0x90, 0x1f, 0xa0]));
+    assert_eq!(parse("<>").unwrap(), DictNode::HexString(Vec::new()));
+    assert_eq!(parse("[<01ff> <02>]").unwrap(), DictNode::Array(vec![
+        DictNode::HexString(vec![0x01, 0xff]),
+        DictNode::HexString(vec![0x02]),
+    ]));
+    assert!(parse("<12zz>").is_err());
+    assert_eq!(DictNode::HexString(vec![0xd8, 0x0a]).to_string(), "<d80a>");
+}
+
 #[test]
 fn test_object_reference() {
     let mut parser = Rdp::new(StringInput::new("34 0 R"));
@@ -299,7 +839,7 @@ fn test_parsing_atoms() {
     let mut parser = Rdp::new(StringInput::new("(Bonjour)"));
     assert!(parser.string());
     let node = parser.parse();
-    assert_eq!(node, DictNode::Str("(Bonjour)".to_string()));
+    assert_eq!(node, DictNode::LiteralString(b"Bonjour".to_vec()));
 
     let mut parser = Rdp::new(StringInput::new("true"));
     assert!(parser.boolean());
@@ -419,3 +959,37 @@ fn test_special_chars_in_string() {
     let mut parser = Rdp::new(StringInput::new(dict));
     assert!(parser.dictionary());
 }
+
+#[test]
+fn test_parse_entry_point() {
+    assert_eq!(parse("  42").unwrap(), DictNode::Int(42));
+    assert_eq!(parse("99999999999999999999").unwrap(), DictNode::Float(99999999999999999999.0));
+    assert!(parse(">> garbage").is_err());
+}
+
+#[test]
+fn test_display() {
+    let dict = DictNode::Dict(hashmap!{
+        "Type".to_string() => DictNode::Str("/Catalog".to_string()),
+        "Pages".to_string() => DictNode::ObjectReference(2, 0),
+        "Box".to_string() => DictNode::Array(vec![DictNode::Int(0), DictNode::Float(612.0), DictNode::Float(-0.5)]),
+        "Open".to_string() => DictNode::Bool(true),
+        "Title".to_string() => DictNode::Str("(hello)".to_string()),
+    });
+    assert_eq!(dict.to_string(), "<< /Box [0 612.0 -0.5] /Open true /Pages 2 0 R /Title (hello) /Type /Catalog >>");
+}
+
+#[test]
+fn test_null() {
+    let mut parser = Rdp::new(StringInput::new("null"));
+    assert!(parser.null());
+    assert!(parser.end());
+
+    let corresponding_map = hashmap!{
+        "Parent".to_string() => DictNode::Null,
+        "Count".to_string() => DictNode::Int(0),
+    };
+    assert_eq!(parse("<</Parent null /Count 0>>").unwrap(), DictNode::Dict(corresponding_map));
+    assert_eq!(parse("[null 1]").unwrap(), DictNode::Array(vec![DictNode::Null, DictNode::Int(1)]));
+    assert_eq!(DictNode::Null.to_string(), "null");
+}
@@ -1,2 +1,8 @@
+// held to the audit's no-panic rule, see src/audit
+#![cfg_attr(feature = "audit", deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable,
+                                    clippy::todo, clippy::unimplemented))]
+
+pub mod content;
 pub mod cos;
+pub mod object;
 pub mod xref;
@@ -0,0 +1,258 @@
+use std::ops::Range;
+
+use error::{PdfError, Result};
+use parsers::cos::{self, DictNode};
+use streams;
+
+/*
+ * Complete indirect objects:
+ *
+ *   12 0 obj
+ *   << /Length 5 /Title (not a stream) >>
+ *   stream
+ *   ...
+ *   endstream
+ *   endobj
+ *
+ * Finding where the body ends means knowing where strings, comments and
+ * names are, otherwise "stream" inside a string or a /stream name ends it
+ * early. `scan` walks the body token by token (without building anything)
+ * until the keyword that ends it, and the body is then handed to the cos
+ * parser. Stream data isn't looked at, it can be anything.
+ */
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndirectObject {
+    pub id: u64,
+    pub generation: u64,
+    pub node: DictNode,
+    // where the stream data is in the input, if the object is a stream
+    pub stream_range: Option<Range<usize>>,
+}
+
+// What ended an object's body.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Terminator {
+    // "stream", the data starts at this position
+    Stream { data_start: usize },
+    Endobj,
+    // the next object's "N G obj", the endobj is missing
+    NextObject,
+    // the input ran out first
+    End,
+}
+
+// Where the body of the object starting at the beginning of `input` is,
+// and what ended it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Scan {
+    pub id: u64,
+    pub generation: u64,
+    pub body: Range<usize>,
+    pub terminator: Terminator,
+}
+
+// Reads the "N G obj" header and finds the end of the body after it.
+pub fn scan(input: &[u8]) -> Result<Scan> {
+    let mut lexer = Lexer { input, position: 0 };
+    let header = (lexer.next_word(), lexer.next_word(), lexer.next_word());
+    let (id, generation) = match header {
+        (Some(id), Some(generation), Some(keyword)) if &input[keyword.clone()] == b"obj" => {
+            match (integer(&input[id]), integer(&input[generation])) {
+                (Some(id), Some(generation)) => (id, generation),
+                _ => return Err(PdfError::Syntax { what: "object header", position: 0 }),
+            }
+        },
+        _ => return Err(PdfError::Syntax { what: "object header", position: 0 }),
+    };
+
+    let start = lexer.position;
+    // the last two words, to back out of a following object's "N G obj"
+    let mut previous: [Option<Range<usize>>; 2] = [None, None];
+    while let Some(token) = lexer.next_token() {
+        let word = match token {
+            Some(word) => word,
+            None => {
+                previous = [None, None];
+                continue;
+            },
+        };
+        match &input[word.clone()] {
+            b"endobj" => return Ok(Scan { id, generation, body: start..word.start, terminator: Terminator::Endobj }),
+            b"stream" => {
+                let data_start = after_eol(input, word.end);
+                return Ok(Scan { id, generation, body: start..word.start, terminator: Terminator::Stream { data_start } });
+            },
+            b"obj" => {
+                if let [Some(ref first), Some(ref second)] = previous {
+                    if integer(&input[first.clone()]).is_some() && integer(&input[second.clone()]).is_some() {
+                        return Ok(Scan { id, generation, body: start..first.start, terminator: Terminator::NextObject });
+                    }
+                }
+            },
+            _ => (),
+        }
+        previous = [previous[1].take(), Some(word)];
+    }
+    Ok(Scan { id, generation, body: start..input.len(), terminator: Terminator::End })
+}
+
+// Parses a whole indirect object out of `input`, which must hold all of
+// it: through endobj, or through the stream data for streams.
+pub fn parse(input: &[u8]) -> Result<IndirectObject> {
+    let scan = scan(input)?;
+    let node = cos::parse_bytes(&input[scan.body.clone()])?;
+    let stream_range = match scan.terminator {
+        Terminator::Endobj => None,
+        Terminator::Stream { data_start } => {
+            let end = match streams::length(&node) {
+                Some(length) => data_start.saturating_add(length as usize),
+                // an indirect /Length, fall back to looking for endstream
+                None => match streams::raw_body(&input[data_start..]) {
+                    Some(data) => data_start + data.len(),
+                    None => input.len() + 1,
+                },
+            };
+            if end > input.len() {
+                return Err(PdfError::TruncatedObject { offset: 0 });
+            }
+            Some(data_start..end)
+        },
+        Terminator::NextObject => return Err(PdfError::MissingEndobj { offset: 0 }),
+        Terminator::End => return Err(PdfError::TruncatedObject { offset: 0 }),
+    };
+    Ok(IndirectObject { id: scan.id, generation: scan.generation, node, stream_range })
+}
+
+struct Lexer<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Lexer<'a> {
+    // The next regular word, skipping anything else.
+    fn next_word(&mut self) -> Option<Range<usize>> {
+        loop {
+            if let Some(word) = self.next_token()? {
+                return Some(word);
+            }
+        }
+    }
+
+    // Skips one token: Some(word) for regular words (numbers, keywords),
+    // None for everything else. The outer None is the end of the input.
+    fn next_token(&mut self) -> Option<Option<Range<usize>>> {
+        let input = self.input;
+        while self.position < input.len() && is_whitespace(input[self.position]) {
+            self.position += 1;
+        }
+        let start = self.position;
+        let byte = *input.get(start)?;
+        self.position += 1;
+        match byte {
+            b'%' => {
+                while self.position < input.len() && input[self.position] != b'\n' && input[self.position] != b'\r' {
+                    self.position += 1;
+                }
+            },
+            b'(' => {
+                let mut depth = 1;
+                while depth > 0 && self.position < input.len() {
+                    match input[self.position] {
+                        b'\\' => self.position += 1,
+                        b'(' => depth += 1,
+                        b')' => depth -= 1,
+                        _ => (),
+                    }
+                    self.position += 1;
+                }
+            },
+            b'<' if input.get(self.position) == Some(&b'<') => self.position += 1,
+            b'<' => {
+                while self.position < input.len() && input[self.position] != b'>' {
+                    self.position += 1;
+                }
+                self.position += 1;
+            },
+            b'/' => self.skip_regular(),
+            _ if is_delimiter(byte) => (),
+            _ => {
+                self.skip_regular();
+                return Some(Some(start..self.position));
+            },
+        }
+        self.position = self.position.min(input.len());
+        Some(None)
+    }
+
+    fn skip_regular(&mut self) {
+        while self.position < self.input.len() {
+            let byte = self.input[self.position];
+            if is_whitespace(byte) || is_delimiter(byte) {
+                break;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(byte, b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%')
+}
+
+fn integer(word: &[u8]) -> Option<u64> {
+    ::std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+// Stream data starts after the end of line following "stream", CRLF or LF
+// by the spec, a lone CR in the wild.
+fn after_eol(input: &[u8], position: usize) -> usize {
+    match input.get(position..position + 2) {
+        Some(b"\r\n") => position + 2,
+        _ => match input.get(position) {
+            Some(b'\n') | Some(b'\r') => position + 1,
+            _ => position,
+        },
+    }
+}
+
+#[test]
+fn test_scan_keywords_in_strings() {
+    let input = b"7 0 obj\n<< /Title (the stream of endobj) /Kind /stream % endobj\n>>\nendobj\n";
+    let scan = scan(input).unwrap();
+    assert_eq!((scan.id, scan.generation), (7, 0));
+    assert_eq!(scan.terminator, Terminator::Endobj);
+    assert_eq!(&input[scan.body], &b"\n<< /Title (the stream of endobj) /Kind /stream % endobj\n>>\n"[..]);
+}
+
+#[test]
+fn test_scan_stream() {
+    let input = b"12 0 obj<</Length 4>>stream\r\n\xff\x00ob\nendstream\nendobj";
+    let scan = scan(input).unwrap();
+    assert_eq!(&input[scan.body], &b"<</Length 4>>"[..]);
+    assert_eq!(scan.terminator, Terminator::Stream { data_start: 29 });
+}
+
+#[test]
+fn test_scan_missing_endobj() {
+    let input = b"3 0 obj\n[1 2 (3 0 obj)]\n4 0 obj\n<<>>\nendobj";
+    let scan = scan(input).unwrap();
+    assert_eq!(scan.terminator, Terminator::NextObject);
+    assert_eq!(&input[scan.body], &b"\n[1 2 (3 0 obj)]\n"[..]);
+
+    let scan = self::scan(b"3 0 obj\n<< /A (unterminated").unwrap();
+    assert_eq!(scan.terminator, Terminator::End);
+    assert!(self::scan(b"<< /A 1 >>").is_err());
+}
+
+#[test]
+fn test_parse_stream_range() {
+    let input = b"12 0 obj<</Length 4>>stream\r\n\xff\x00ob\nendstream\nendobj";
+    let object = parse(input).unwrap();
+    assert_eq!(object.stream_range, Some(29..33));
+    assert!(parse(&input[..32]).is_err());
+}
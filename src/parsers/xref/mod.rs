@@ -1,5 +1,8 @@
 use pest::prelude::*;
 
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+
 /*
  * Parser for PDF X-Ref table. The X-Ref table is basically a table of contents of
  * indirect object (or XObjects), storing their exact byte offset in a file.
@@ -10,15 +13,85 @@ use pest::prelude::*;
  * the cat 'XObject', then the parser will consult the xref table to pull it out.
  */
 
+// The spec's limit on object numbers. Tables don't grow past it, a corrupt
+// number would otherwise have us allocate a table that size.
+pub const MAX_OBJECT_NUMBER: usize = 8_388_607;
+
+// Entries are indexed by object number. A table doesn't necessarily cover
+// every object, e.g. the xref section of an incremental update only lists
+// the objects that changed, so ids it says nothing about are None.
 #[derive(Debug, PartialEq, Clone)]
-pub struct XRefTable(Vec<XRefEntry>);
+pub struct XRefTable(Vec<Option<XRefEntry>>);
+
+impl XRefTable {
+    pub fn new(entries: Vec<XRefEntry>) -> XRefTable {
+        XRefTable(entries.into_iter().map(Some).collect())
+    }
+
+    pub fn get(&self, id: usize) -> Option<&XRefEntry> {
+        self.0.get(id).and_then(|entry| entry.as_ref())
+    }
+
+    // Overwrites the entry for `id`, growing the table if it doesn't reach
+    // that far yet. Ids past MAX_OBJECT_NUMBER are ignored.
+    pub fn set(&mut self, id: usize, entry: XRefEntry) {
+        if id > MAX_OBJECT_NUMBER {
+            return;
+        }
+        if self.0.len() <= id {
+            self.0.resize(id + 1, None);
+        }
+        self.0[id] = Some(entry);
+    }
 
+    // The entries the table actually has, with their object numbers.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, &XRefEntry)> {
+        self.0.iter().enumerate().filter_map(|(id, entry)| entry.as_ref().map(|entry| (id, entry)))
+    }
+
+    // One more than the highest object number in the table.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Fills in the objects this table doesn't know about from an older
+    // one, i.e. the xref section the /Prev of this one points at.
+    pub fn merge_older(&mut self, older: XRefTable) {
+        for (id, entry) in older.0.into_iter().enumerate() {
+            if let (Some(entry), None) = (entry, self.get(id)) {
+                self.set(id, entry);
+            }
+        }
+    }
+}
+
+impl XRefTable {
+    // Merges the xref stream of a hybrid file (the one the trailer's
+    // /XRefStm points at) into its classic table. Hybrid tables list the
+    // objects that live in object streams as free so older readers skip
+    // them, the stream has their real entries.
+    pub fn merge_hybrid(&mut self, stream: XRefTable) {
+        for (id, entry) in stream.0.into_iter().enumerate() {
+            let hidden = self.get(id).is_none_or(|entry| entry.is_free);
+            if let (Some(entry), true) = (entry, hidden) {
+                self.set(id, entry);
+            }
+        }
+    }
+}
+
+// Yields an entry per object number, objects the table doesn't mention
+// come out as free.
 impl IntoIterator for XRefTable {
     type Item = XRefEntry;
-    type IntoIter = ::std::vec::IntoIter<XRefEntry>;
+    type IntoIter = ::std::iter::Map<::std::vec::IntoIter<Option<XRefEntry>>, fn(Option<XRefEntry>) -> XRefEntry>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.0.into_iter().map(|entry| entry.unwrap_or_else(XRefEntry::free))
     }
 }
 
@@ -27,11 +100,29 @@ pub struct XRefEntry {
     pub offset: u64,
     pub generation_id: u64,
     pub is_free: bool,
+    // Set for objects stored inside an object stream (PDF 1.5+), to the
+    // object number of that stream. `offset` is then the object's index
+    // within the stream rather than a file offset.
+    pub object_stream: Option<u64>,
+}
+
+impl XRefEntry {
+    pub fn free() -> XRefEntry {
+        XRefEntry { offset: 0, generation_id: 65535, is_free: true, object_stream: None }
+    }
+
+    pub fn in_use(offset: u64, generation_id: u64) -> XRefEntry {
+        XRefEntry { offset, generation_id, is_free: false, object_stream: None }
+    }
+
+    pub fn compressed(object_stream: u64, index: u64) -> XRefEntry {
+        XRefEntry { offset: index, generation_id: 0, is_free: false, object_stream: Some(object_stream) }
+    }
 }
 
 impl_rdp! {
     grammar! {
-        xref = { xref_begin ~ xref_header ~ xref_entry+ ~ xref_end }
+        xref = { xref_begin ~ (xref_header ~ xref_entry*)+ ~ xref_end }
         xref_begin = { newline* ~ ["xref\n"] }
         xref_end = { newline* ~ ["trailer\n"] }
         xref_header = { newline* ~ int ~ int ~ newline }
@@ -43,36 +134,161 @@ impl_rdp! {
    }
 
    process! {
-        parse(&self) -> XRefTable {
-            (mut entries: _parse_xref()) => {
-                entries.reverse();
-                XRefTable(entries)
+        parse(&self) -> Result<XRefTable> {
+            (_: xref, _: xref_begin, subsections: _parse_subsections()) => {
+                subsections.map(|subsections| {
+                    let mut table = XRefTable::new(Vec::new());
+                    for (first, entries) in subsections {
+                        for (index, entry) in entries.into_iter().enumerate() {
+                            table.set(first.saturating_add(index), entry);
+                        }
+                    }
+                    table
+                })
             }
         }
 
-        _parse_xref(&self) -> Vec<XRefEntry> {
-            (_: xref, _: xref_begin, tail: _parse_xref()) => tail,
-            (_: xref_header, _: int, _:int, tail: _parse_xref()) => tail,
-            (_: xref_end) => Vec::new(),
-            (entry: _parse_xref_entry(), mut tail: _parse_xref()) => {
-                tail.push(entry);
-                tail
+        // (first object number, entries) for each "first count" header
+        _parse_subsections(&self) -> Result<Vec<(usize, Vec<XRefEntry>)>> {
+            (_: xref_end) => Ok(Vec::new()),
+            (_: xref_header, &first: int, _: int, entries: _parse_entries(), tail: _parse_subsections()) => {
+                match (first.parse::<usize>(), entries, tail) {
+                    (Ok(first), Ok(mut entries), Ok(mut tail)) => {
+                        entries.reverse();
+                        tail.push((first, entries));
+                        Ok(tail)
+                    },
+                    (Err(err), _, _) => Err(PdfError::from(err)),
+                    (_, Err(err), _) | (_, _, Err(err)) => Err(err),
+                }
             },
         }
 
-        _parse_xref_entry(&self) -> XRefEntry {
-            (_: xref_entry, &o: int, &g: int, &u: usage) => {
-                XRefEntry{
-                    offset: o.parse::<u64>().unwrap(),
-                    generation_id: g.parse::<u64>().unwrap(),
-                    is_free: u == "f"
+        _parse_entries(&self) -> Result<Vec<XRefEntry>> {
+            (_: xref_entry, &o: int, &g: int, &u: usage, tail: _parse_entries()) => {
+                match (parse_entry(o, g, u), tail) {
+                    (Ok(entry), Ok(mut tail)) => {
+                        tail.push(entry);
+                        Ok(tail)
+                    },
+                    (Err(err), _) | (_, Err(err)) => Err(err),
                 }
-            }
+            },
+            () => Ok(Vec::new()),
         }
 
    }
 }
 
+fn parse_entry(offset: &str, generation: &str, usage: &str) -> Result<XRefEntry> {
+    // ints are unbounded in the grammar, a corrupt table can overflow
+    match (offset.parse::<u64>(), generation.parse::<u64>()) {
+        (Ok(offset), Ok(generation_id)) => Ok(XRefEntry {
+            offset,
+            generation_id,
+            is_free: usage == "f",
+            object_stream: None,
+        }),
+        (Err(err), _) | (_, Err(err)) => Err(PdfError::from(err)),
+    }
+}
+
+// Parses a classic "xref ... trailer" table.
+pub fn parse(input: &str) -> Result<XRefTable> {
+    let mut parser = Rdp::new(StringInput::new(input));
+    parser.skip();
+    if !parser.xref() {
+        let (_, position) = parser.expected();
+        return Err(PdfError::Syntax { what: "xref", position });
+    }
+    parser.parse()
+}
+
+/*
+ * Since PDF 1.5 the xref can also be a stream object (/Type /XRef), whose
+ * decoded data is a table of fixed width binary rows. /W gives the width in
+ * bytes of the three fields of each row:
+ *
+ *   type  0 = free, 1 = in use at a file offset, 2 = inside an object stream
+ *   f2    offset (type 1) or object stream number (type 2)
+ *   f3    generation (type 1) or index within the object stream (type 2)
+ *
+ * A zero width means the field is absent and takes its default (type 1,
+ * generation 0). /Index lists the (first object, count) subsections the rows
+ * describe, [0 /Size] if missing. The stream dictionary doubles as trailer.
+ */
+pub fn parse_stream(dict: &DictNode, data: &[u8]) -> Result<XRefTable> {
+    let map = match *dict {
+        DictNode::Dict(ref map) => map,
+        _ => return stream_error(),
+    };
+    let widths = match map.get("W").and_then(int_array) {
+        Some(ref widths) if widths.len() == 3 && widths.iter().all(|&w| w <= 8) => {
+            [widths[0] as usize, widths[1] as usize, widths[2] as usize]
+        },
+        _ => return stream_error(),
+    };
+    let index = match map.get("Index") {
+        Some(index) => match int_array(index) {
+            Some(ref index) if index.len() % 2 == 0 => index.clone(),
+            _ => return stream_error(),
+        },
+        None => match map.get("Size") {
+            Some(&DictNode::Int(size)) if size >= 0 => vec![0, size as u64],
+            _ => return stream_error(),
+        },
+    };
+
+    let row_len = widths.iter().sum::<usize>();
+    if row_len == 0 {
+        return stream_error();
+    }
+    let mut rows = data.chunks(row_len).filter(|row| row.len() == row_len);
+    let mut table = XRefTable::new(Vec::new());
+    for subsection in index.chunks(2) {
+        let (first, count) = (subsection[0], subsection[1]);
+        for id in first..first.saturating_add(count) {
+            let row = match rows.next() {
+                Some(row) => row,
+                // fewer rows than /Index promises, keep what we have
+                None => return Ok(table),
+            };
+            let (kind, row) = row.split_at(widths[0]);
+            let (field2, field3) = row.split_at(widths[1]);
+            let kind = if widths[0] == 0 { 1 } else { be_uint(kind) };
+            let entry = match kind {
+                0 => XRefEntry::free(),
+                1 => XRefEntry::in_use(be_uint(field2), be_uint(field3)),
+                2 => XRefEntry::compressed(be_uint(field2), be_uint(field3)),
+                // reserved for future use, the spec says to treat them as null
+                _ => XRefEntry::free(),
+            };
+            table.set(id as usize, entry);
+        }
+    }
+    Ok(table)
+}
+
+fn stream_error<T>() -> Result<T> {
+    Err(PdfError::Syntax { what: "xref stream", position: 0 })
+}
+
+fn int_array(node: &DictNode) -> Option<Vec<u64>> {
+    match *node {
+        DictNode::Array(ref items) => items.iter()
+            .map(|item| match *item {
+                DictNode::Int(value) if value >= 0 => Some(value as u64),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as u64)
+}
+
 #[test]
 fn test_parsing_int() {
     let mut parser = Rdp::new(StringInput::new("0"));
@@ -115,14 +331,94 @@ fn test_parsing_xref_elements() {
 #[test]
 fn test_parsing_xref() {
     let xref = "\n    xref\n  0 65\n 0000000000 65535 f\n 0000118424 00000 n\ntrailer\n";
-    let expected_xref = XRefTable([
-        XRefEntry{ offset: 0, generation_id: 65535, is_free: true},
-        XRefEntry{ offset: 118424, generation_id: 0, is_free: false},
+    let expected_xref = XRefTable::new([
+        XRefEntry{ offset: 0, generation_id: 65535, is_free: true, object_stream: None},
+        XRefEntry{ offset: 118424, generation_id: 0, is_free: false, object_stream: None},
     ].to_vec());
 
     let mut parser = Rdp::new(StringInput::new(xref));
     parser.skip();
     assert!(parser.xref());
-    let xref = parser.parse();
+    let xref = parser.parse().unwrap();
     assert_eq!(xref, expected_xref);
 }
+
+#[test]
+fn test_parsing_xref_subsections() {
+    let xref = "xref\n0 1\n0000000000 65535 f\n17 2\n0000001234 00000 n\n0000005678 00001 n\ntrailer\n";
+    let xref = parse(xref).unwrap();
+    assert_eq!(xref.get(0), Some(&XRefEntry::free()));
+    assert_eq!(xref.get(1), None);
+    assert_eq!(xref.get(17), Some(&XRefEntry::in_use(1234, 0)));
+    assert_eq!(xref.get(18), Some(&XRefEntry::in_use(5678, 1)));
+    assert_eq!(xref.len(), 19);
+}
+
+#[test]
+fn test_parsing_overflowing_xref() {
+    let xref = "xref\n0 1\n99999999999999999999 00000 n\ntrailer\n";
+    assert!(parse(xref).is_err());
+}
+
+#[test]
+fn test_parsing_xref_stream() {
+    let dict = DictNode::Dict(hashmap!{
+        "Type".to_string() => DictNode::name("XRef"),
+        "W".to_string() => DictNode::Array(vec![DictNode::Int(1), DictNode::Int(2), DictNode::Int(1)]),
+        "Index".to_string() => DictNode::Array(vec![
+            DictNode::Int(0), DictNode::Int(2), DictNode::Int(5), DictNode::Int(1),
+        ]),
+    });
+    let data = [
+        0, 0x00, 0x00, 0xff,
+        1, 0x01, 0x0f, 0x00,
+        2, 0x00, 0x07, 0x03,
+    ];
+    let xref = parse_stream(&dict, &data).unwrap();
+    assert_eq!(xref.get(0), Some(&XRefEntry::free()));
+    assert_eq!(xref.get(1), Some(&XRefEntry::in_use(0x10f, 0)));
+    assert_eq!(xref.get(2), None);
+    assert_eq!(xref.get(5), Some(&XRefEntry::compressed(7, 3)));
+
+    let dict = DictNode::Dict(hashmap!{
+        "W".to_string() => DictNode::Array(vec![DictNode::Int(0), DictNode::Int(1)]),
+    });
+    assert!(parse_stream(&dict, &data).is_err());
+}
+
+#[test]
+fn test_merge_older() {
+    let mut newer = XRefTable::new(Vec::new());
+    newer.set(2, XRefEntry::in_use(500, 1));
+    newer.set(3, XRefEntry::free());
+    let older = XRefTable::new(vec![
+        XRefEntry::free(),
+        XRefEntry::in_use(10, 0),
+        XRefEntry::in_use(20, 0),
+        XRefEntry::in_use(30, 0),
+        XRefEntry::in_use(40, 0),
+    ]);
+    newer.merge_older(older);
+    assert_eq!(newer.len(), 5);
+    assert_eq!(newer.get(1), Some(&XRefEntry::in_use(10, 0)));
+    assert_eq!(newer.get(2), Some(&XRefEntry::in_use(500, 1)));
+    assert_eq!(newer.get(3), Some(&XRefEntry::free()));
+    assert_eq!(newer.get(4), Some(&XRefEntry::in_use(40, 0)));
+}
+
+#[test]
+fn test_merge_hybrid() {
+    let mut table = XRefTable::new(vec![
+        XRefEntry::free(),
+        XRefEntry::in_use(15, 0),
+        XRefEntry::free(),
+    ]);
+    let mut stream = XRefTable::new(Vec::new());
+    stream.set(1, XRefEntry::compressed(9, 0));
+    stream.set(2, XRefEntry::compressed(9, 1));
+    stream.set(3, XRefEntry::compressed(9, 2));
+    table.merge_hybrid(stream);
+    assert_eq!(table.get(1), Some(&XRefEntry::in_use(15, 0)));
+    assert_eq!(table.get(2), Some(&XRefEntry::compressed(9, 1)));
+    assert_eq!(table.get(3), Some(&XRefEntry::compressed(9, 2)));
+}
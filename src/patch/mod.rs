@@ -0,0 +1,359 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::{PdfError, Result};
+use writer::Writer;
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use transcode;
+
+/*
+ * Object-level differences between two documents, and applying them to a
+ * third: the building block for three-way merges of generated files.
+ *
+ *   let ops = old.diff(&mut new)?;
+ *   patch::apply(base, &ops, output)?;
+ *
+ * Objects are matched by object number, which is what generators that
+ * rewrite a file from the same model tend to keep stable. Changes inside
+ * an object are narrowed down to the dictionary entry or array item that
+ * changed, with the path to it (`/Resources/Font/F1`, `/Kids[2]`), so two
+ * edits to different parts of the same object merge cleanly. Arrays that
+ * changed length are replaced as a whole. Stream data is compared still
+ * encoded, /Length is left out since it follows the data. Xref and object
+ * streams are how a file is stored rather than what's in it, they don't
+ * take part. The trailer's /Root and /Info count as well.
+ *
+ * Every change carries what it replaces. Applying one where the base has
+ * something else there is a conflict, reported as PdfError::Patch; finding
+ * it already made is fine. Removals aren't checked, an object that's
+ * gone from one side is gone whatever the other did to it.
+ *
+ * The patched file is an incremental update of the base, encrypted bases
+ * are refused.
+ */
+
+// Trailer entries that are part of the document's content.
+const TRAILER_KEYS: &[&str] = &["Root", "Info"];
+
+// An object's value and its raw data if it's a stream.
+type Content = (DictNode, Option<Vec<u8>>);
+
+// A step into an object, from a dictionary to one of its entries or from
+// an array to one of its items.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatchOp {
+    Add { id: u64, object: DictNode, data: Option<Vec<u8>> },
+    Remove { id: u64 },
+    // the value at `path` inside object `id`, None for a dictionary entry
+    // that isn't there
+    Modify { id: u64, path: Vec<Step>, old: Option<DictNode>, new: Option<DictNode> },
+    // a stream's data, still encoded
+    Data { id: u64, old: Vec<u8>, new: Vec<u8> },
+    Trailer { key: String, old: Option<DictNode>, new: Option<DictNode> },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Key(ref key) => write!(f, "/{}", key),
+            Step::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+impl fmt::Display for PatchOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchOp::Add { id, ref object, data: Some(ref data) } => write!(f, "add {}: {} with {} bytes", id, object, data.len()),
+            PatchOp::Add { id, ref object, data: None } => write!(f, "add {}: {}", id, object),
+            PatchOp::Remove { id } => write!(f, "remove {}", id),
+            PatchOp::Modify { id, ref path, ref old, ref new } => {
+                write!(f, "modify {}{}: {} -> {}", id, path_string(path), value_string(old), value_string(new))
+            },
+            PatchOp::Data { id, ref old, ref new } => write!(f, "data {}: {} -> {} bytes", id, old.len(), new.len()),
+            PatchOp::Trailer { ref key, ref old, ref new } => {
+                write!(f, "trailer /{}: {} -> {}", key, value_string(old), value_string(new))
+            },
+        }
+    }
+}
+
+fn path_string(path: &[Step]) -> String {
+    path.iter().map(Step::to_string).collect()
+}
+
+fn value_string(value: &Option<DictNode>) -> String {
+    value.as_ref().map_or("none".to_string(), DictNode::to_string)
+}
+
+// What it takes to turn `doc` into `other`, see Document::diff.
+pub fn diff(doc: &mut Document, other: &mut Document) -> Result<Vec<PatchOp>> {
+    let mut ops = Vec::new();
+    for &key in TRAILER_KEYS {
+        let (old, new) = (doc.trailer.get(key).cloned(), other.trailer.get(key).cloned());
+        if old != new {
+            ops.push(PatchOp::Trailer { key: key.to_string(), old, new });
+        }
+    }
+
+    let ids: BTreeSet<u64> = in_use(doc).chain(in_use(other)).collect();
+    for id in ids {
+        match (content(doc, id)?, content(other, id)?) {
+            (None, None) => {},
+            (Some(_), None) => ops.push(PatchOp::Remove { id }),
+            (None, Some((object, data))) => ops.push(PatchOp::Add { id, object, data }),
+            (Some((_, old_data)), Some((object, data))) if old_data.is_some() != data.is_some() => {
+                // a stream on one side only, there's nothing to narrow down
+                ops.push(PatchOp::Remove { id });
+                ops.push(PatchOp::Add { id, object, data });
+            },
+            (Some((old, old_data)), Some((new, new_data))) => {
+                diff_nodes(id, &mut Vec::new(), &old, &new, &mut ops);
+                if let (Some(old), Some(new)) = (old_data, new_data) {
+                    if old != new {
+                        ops.push(PatchOp::Data { id, old, new });
+                    }
+                }
+            },
+        }
+    }
+    Ok(ops)
+}
+
+fn in_use(doc: &Document) -> impl Iterator<Item = u64> + '_ {
+    doc.xref.entries().filter(|&(_, entry)| !entry.is_free).map(|(id, _)| id as u64)
+}
+
+// Object `id` as the diff sees it, None if it's missing or free or only
+// there to store the file.
+fn content(doc: &mut Document, id: u64) -> Result<Option<Content>> {
+    let entry = match doc.xref.get(id as usize) {
+        Some(entry) if !entry.is_free && id != 0 => entry.clone(),
+        _ => return Ok(None),
+    };
+    let (mut object, data) = doc.raw_object(&entry)?;
+    if data.is_some() {
+        if object.get("Type").is_some_and(|kind| kind.is_name("XRef") || kind.is_name("ObjStm")) {
+            return Ok(None);
+        }
+        if let DictNode::Dict(ref mut map) = object {
+            map.remove("Length");
+        }
+    }
+    Ok(Some((object, data)))
+}
+
+fn diff_nodes(id: u64, path: &mut Vec<Step>, old: &DictNode, new: &DictNode, ops: &mut Vec<PatchOp>) {
+    match (old, new) {
+        (DictNode::Dict(old), DictNode::Dict(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                path.push(Step::Key(key.clone()));
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => diff_nodes(id, path, old, new, ops),
+                    (old, new) => ops.push(PatchOp::Modify { id, path: path.clone(), old: old.cloned(), new: new.cloned() }),
+                }
+                path.pop();
+            }
+        },
+        (DictNode::Array(old), DictNode::Array(new)) if old.len() == new.len() => {
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                path.push(Step::Index(index));
+                diff_nodes(id, path, old, new, ops);
+                path.pop();
+            }
+        },
+        _ if old != new => {
+            ops.push(PatchOp::Modify { id, path: path.clone(), old: Some(old.clone()), new: Some(new.clone()) });
+        },
+        _ => {},
+    }
+}
+
+// Writes `input` with `ops` applied to `output`.
+pub fn apply(input: &Path, ops: &[PatchOp], output: &Path) -> Result<()> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options)?;
+
+    // the objects touched, as the patch leaves them: None once removed
+    let mut changed: BTreeMap<u64, Option<Content>> = BTreeMap::new();
+    for op in ops {
+        match *op {
+            PatchOp::Add { id, ref object, ref data } => {
+                let current = load(&mut doc, &mut changed, id)?;
+                let added = (object.clone(), data.clone());
+                if current.as_ref().is_some_and(|current| *current != added) {
+                    return Err(PdfError::Patch(format!("object {} already exists", id)));
+                }
+                *current = Some(added);
+            },
+            PatchOp::Remove { id } => {
+                *load(&mut doc, &mut changed, id)? = None;
+            },
+            PatchOp::Modify { id, ref path, ref old, ref new } => {
+                let object = match *load(&mut doc, &mut changed, id)? {
+                    Some((ref mut object, _)) => object,
+                    None => return Err(PdfError::Patch(format!("object {} is gone", id))),
+                };
+                let found = lookup(object, path).cloned();
+                if found == *new {
+                    continue;
+                }
+                if found != *old || !replace(object, path, new.clone()) {
+                    return Err(PdfError::Patch(format!("{}{} is {}, expected {}", id, path_string(path), value_string(&found), value_string(old))));
+                }
+            },
+            PatchOp::Data { id, ref old, ref new } => {
+                let data = match *load(&mut doc, &mut changed, id)? {
+                    Some((_, Some(ref mut data))) => data,
+                    _ => return Err(PdfError::Patch(format!("object {} isn't a stream", id))),
+                };
+                if data != new && data != old {
+                    return Err(PdfError::Patch(format!("the data of {} changed", id)));
+                }
+                *data = new.clone();
+            },
+            PatchOp::Trailer { ref key, ref old, ref new } => {
+                let trailer = match doc.trailer {
+                    DictNode::Dict(ref mut trailer) => trailer,
+                    _ => return Err(PdfError::Syntax { what: "trailer", position: 0 }),
+                };
+                let found = trailer.get(key).cloned();
+                if found != *new && found != *old {
+                    return Err(PdfError::Patch(format!("trailer /{} is {}, expected {}", key, value_string(&found), value_string(old))));
+                }
+                match *new {
+                    Some(ref new) => trailer.insert(key.clone(), new.clone()),
+                    None => trailer.remove(key),
+                };
+            },
+        }
+    }
+
+    let mut out = Writer::appending(fs::read(input)?, doc.xref.clone());
+    for (id, content) in changed {
+        match content {
+            Some((object, None)) => out.object(id, &object)?,
+            Some((DictNode::Dict(mut dict), Some(data))) => {
+                dict.insert("Length".to_string(), DictNode::Int(data.len() as i64));
+                out.stream(id, &DictNode::Dict(dict), &data)?;
+            },
+            Some((_, Some(_))) => return Err(PdfError::Patch(format!("stream {} has no dictionary", id))),
+            None => out.xref.set(id as usize, XRefEntry::free()),
+        }
+    }
+    let (mut data, xref) = (out.data, out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// Object `id` of the patched document, read from the base the first time.
+fn load<'a>(doc: &mut Document, changed: &'a mut BTreeMap<u64, Option<Content>>, id: u64) -> Result<&'a mut Option<Content>> {
+    Ok(match changed.entry(id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(content(doc, id)?),
+    })
+}
+
+fn lookup<'a>(node: &'a DictNode, path: &[Step]) -> Option<&'a DictNode> {
+    path.iter().try_fold(node, |node, step| match *step {
+        Step::Key(ref key) => node.get(key),
+        Step::Index(index) => node.as_array().and_then(|items| items.get(index)),
+    })
+}
+
+fn lookup_mut<'a>(node: &'a mut DictNode, path: &[Step]) -> Option<&'a mut DictNode> {
+    path.iter().try_fold(node, |node, step| match (node, step) {
+        (DictNode::Dict(map), Step::Key(key)) => map.get_mut(key),
+        (DictNode::Array(items), &Step::Index(index)) => items.get_mut(index),
+        _ => None,
+    })
+}
+
+// Sets the value at `path` in `node`, removing it for None. False if
+// there's nowhere to put it.
+fn replace(node: &mut DictNode, path: &[Step], value: Option<DictNode>) -> bool {
+    let (last, parent) = match path.split_last() {
+        Some((last, parents)) => match lookup_mut(node, parents) {
+            Some(parent) => (last, parent),
+            None => return false,
+        },
+        None => match value {
+            Some(value) => {
+                *node = value;
+                return true;
+            },
+            None => return false,
+        },
+    };
+    match (parent, last, value) {
+        (DictNode::Dict(map), Step::Key(key), Some(value)) => {
+            map.insert(key.clone(), value);
+        },
+        (DictNode::Dict(map), Step::Key(key), None) => {
+            map.remove(key);
+        },
+        (DictNode::Array(items), &Step::Index(index), Some(value)) if index < items.len() => {
+            items[index] = value;
+        },
+        _ => return false,
+    }
+    true
+}
+
+#[test]
+fn test_diff_nodes() {
+    let page = |width, height, font, kids: &[u64]| DictNode::Dict(hashmap! {
+        "Type".to_string() => DictNode::name("Page"),
+        "MediaBox".to_string() => DictNode::Array([0, 0, width, height].iter().map(|&n| DictNode::Int(n)).collect()),
+        "Resources".to_string() => DictNode::Dict(hashmap! {
+            "Font".to_string() => DictNode::Dict(hashmap! { "F1".to_string() => DictNode::reference(font) }),
+        }),
+        "Kids".to_string() => DictNode::Array(kids.iter().map(|&id| DictNode::reference(id)).collect()),
+    });
+    let old = page(612, 792, 4, &[1]);
+    let mut new = page(595, 842, 9, &[1, 2]);
+    if let DictNode::Dict(ref mut map) = new {
+        map.insert("Rotate".to_string(), DictNode::Int(90));
+    }
+    let mut ops = Vec::new();
+    diff_nodes(3, &mut Vec::new(), &old, &new, &mut ops);
+    let lines: Vec<String> = ops.iter().map(PatchOp::to_string).collect();
+    assert_eq!(lines, vec![
+        "modify 3/Kids: [1 0 R] -> [1 0 R 2 0 R]",
+        "modify 3/MediaBox[2]: 612 -> 595",
+        "modify 3/MediaBox[3]: 792 -> 842",
+        "modify 3/Resources/Font/F1: 4 0 R -> 9 0 R",
+        "modify 3/Rotate: none -> 90",
+    ]);
+
+    // replaying them turns one into the other, and replaying them twice
+    // changes nothing more
+    let mut patched = old.clone();
+    for _ in 0..2 {
+        for op in &ops {
+            if let PatchOp::Modify { ref path, ref new, .. } = *op {
+                assert!(replace(&mut patched, path, new.clone()));
+            }
+        }
+        assert_eq!(patched, new);
+    }
+
+    let path = [Step::Key("Resources".to_string()), Step::Key("XObject".to_string()), Step::Key("Im1".to_string())];
+    assert_eq!(lookup(&patched, &path), None);
+    assert!(!replace(&mut patched, &path, Some(DictNode::Null)));
+    assert!(!replace(&mut patched, &[Step::Key("Kids".to_string()), Step::Index(2)], Some(DictNode::Null)));
+}
@@ -0,0 +1,201 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+use streams;
+
+/*
+ * Decoding lots of streams at once, e.g. every image of a 500 page scan:
+ * `dump -o`, `images::extract_all`, `compress::compress` and writing with
+ * `SaveOptions::compress` all go through here.
+ *
+ * Reading stream bodies off disk and inflating them are very different
+ * kinds of work, so they're overlapped: one thread pulls raw streams from
+ * the job source while a pool of workers decodes them, and the results are
+ * handed back to the caller's thread as they're ready. Both queues are
+ * bounded, so at most a few streams per worker are ever in memory no matter
+ * how big the document is.
+ *
+ * Results come back in completion order, use `Decoded::index` to put them
+ * back in document order if that matters. A thread that panics makes the
+ * whole call a PdfError::Decode.
+ *
+ * `map_parallel` does the same for any work, e.g. decoding an image and
+ * encoding it as a PNG file.
+ *
+ * On one core the pipeline costs about as much as the noise between runs:
+ * extracting the images of a 300 page scan (850x1100 gray, deflated) took
+ * 19-21 s serially, 19.6-20.7 s with one worker and 20.1-22.8 s with four,
+ * and recompressing 300 uncompressed ones 18.5 s against 19.5 and 20.6 s.
+ * Speedups on several cores haven't been measured, there wasn't a machine
+ * with more than one to measure them on.
+ */
+
+// How many jobs/results can wait in each queue, per worker.
+const QUEUE_PER_WORKER: usize = 2;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Job {
+    // position of the job in the source, carried over to the result
+    pub index: usize,
+    pub id: u64,
+    pub dict: DictNode,
+    pub raw: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Decoded {
+    pub index: usize,
+    pub id: u64,
+    pub dict: DictNode,
+    pub data: Result<Vec<u8>>,
+    // how long the worker spent decoding
+    pub elapsed: Duration,
+}
+
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map(|count| count.get()).unwrap_or(1)
+}
+
+// Decodes every job from `jobs` on `workers` threads and calls `handle` with
+// each result on the calling thread. Stops at the first error from either
+// the job source or `handle`; decoding errors are passed to `handle`, which
+// decides whether they're fatal.
+pub fn decode_parallel<I, F>(jobs: I, workers: usize, handle: F) -> Result<()>
+    where I: Iterator<Item = Result<Job>> + Send,
+          F: FnMut(Decoded) -> Result<()>
+{
+    let decode = |job: Job| {
+        let start = Instant::now();
+        let data = streams::decode(&job.dict, &job.raw);
+        let elapsed = start.elapsed();
+        Decoded { index: job.index, id: job.id, dict: job.dict, data, elapsed }
+    };
+    map_parallel(jobs, workers, decode, handle)
+}
+
+// The same for any work: `work` runs on every job from `jobs` on `workers`
+// threads, and `handle` gets each result on the calling thread, in
+// completion order. Errors stop everything the same way.
+pub fn map_parallel<T, R, I, W, F>(jobs: I, workers: usize, work: W, mut handle: F) -> Result<()>
+    where T: Send,
+          R: Send,
+          I: Iterator<Item = Result<T>> + Send,
+          W: Fn(T) -> R + Sync,
+          F: FnMut(R) -> Result<()>
+{
+    let workers = workers.max(1);
+    let (job_sender, job_receiver) = sync_channel::<T>(workers * QUEUE_PER_WORKER);
+    let (result_sender, result_receiver) = sync_channel::<R>(workers * QUEUE_PER_WORKER);
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+    let work = &work;
+
+    thread::scope(|scope| {
+        let reader = scope.spawn(move || -> Result<()> {
+            for job in jobs {
+                if job_sender.send(job?).is_err() {
+                    // the workers are gone, the caller bailed out
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        let mut pool = Vec::with_capacity(workers);
+        for _ in 0..workers {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            pool.push(scope.spawn(move || {
+                while let Some(job) = next_job(&job_receiver) {
+                    if result_sender.send(work(job)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        // only the workers hold these now, so the reader notices when they
+        // all stop and the loop below ends when they're done
+        drop(job_receiver);
+        drop(result_sender);
+
+        let mut handled = Ok(());
+        for result in result_receiver.iter() {
+            handled = handle(result);
+            if handled.is_err() {
+                break;
+            }
+        }
+        // unblocks the workers, and through them the reader
+        drop(result_receiver);
+
+        // every thread is joined, one that panicked without being joined
+        // would take the scope down with it
+        let read = reader.join().unwrap_or_else(|payload| Err(panicked("stream reader", payload)));
+        let mut worked = Ok(());
+        for worker in pool {
+            if let Err(payload) = worker.join() {
+                worked = Err(panicked("decoding", payload));
+            }
+        }
+        handled.and(read).and(worked)
+    })
+}
+
+fn panicked(thread: &str, payload: Box<dyn Any + Send>) -> PdfError {
+    let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    PdfError::Decode(format!("the {} thread panicked: {}", thread, message))
+}
+
+fn next_job<T>(receiver: &Mutex<Receiver<T>>) -> Option<T> {
+    receiver.lock().ok()?.recv().ok()
+}
+
+#[cfg(test)]
+fn hex_job(index: usize, data: &str) -> Result<Job> {
+    let dict = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::name("ASCIIHexDecode"),
+    });
+    Ok(Job { index, id: index as u64 + 1, dict, raw: data.as_bytes().to_vec() })
+}
+
+#[test]
+fn test_decode_parallel() {
+    let jobs = (0..50).map(|index| hex_job(index, &format!("{:02x}>", index)));
+    let mut results = Vec::new();
+    decode_parallel(jobs, 4, |decoded| {
+        results.push((decoded.index, decoded.id, decoded.data?));
+        Ok(())
+    }).unwrap();
+    results.sort();
+    assert_eq!(results.len(), 50);
+    for (index, &(position, id, ref data)) in results.iter().enumerate() {
+        assert_eq!((position, id, data.clone()), (index, index as u64 + 1, vec![index as u8]));
+    }
+}
+
+#[test]
+fn test_decode_parallel_errors() {
+    // a bad stream is reported to the handler, which can give up
+    let jobs = (0..50).map(|index| hex_job(index, if index == 10 { "zz>" } else { "00>" }));
+    let result = decode_parallel(jobs, 3, |decoded| decoded.data.map(|_| ()));
+    assert!(result.is_err());
+
+    // an error from the source stops everything too
+    let jobs = (0..50).map(|index| if index == 5 {
+        Err(::error::PdfError::Decode("unreadable".to_string()))
+    } else {
+        hex_job(index, "00>")
+    });
+    assert!(decode_parallel(jobs, 2, |_| Ok(())).is_err());
+
+    // and so does a panic in it
+    let jobs = (0..50).map(|index| if index == 5 { panic!("bad job") } else { hex_job(index, "00>") });
+    let result = decode_parallel(jobs, 2, |_| Ok(()));
+    assert_eq!(result.unwrap_err().to_string(), "couldn't decode stream: the stream reader thread panicked: bad job");
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use error::Result;
+use parsers::xref::XRefTable;
+use text::json_string;
+
+/*
+ * Where the objects of a file we wrote came from: for each object number
+ * in the output, the source file and object number it was copied from.
+ *
+ *   let provenance = merge::merge(&inputs, output, &MergeOptions::default())?;
+ *   if let Some((file, id)) = provenance.source(12) {
+ *       println!("12 0 R is {} 0 R of {}", id, file.display());
+ *   }
+ *   provenance.write_sidecar(output)?;
+ *
+ * Objects with no source were made up by the tool (page trees, outlines,
+ * content drawing a template...). An object rewritten in place by an
+ * incremental update counts as coming from the one it replaces.
+ *
+ * The sidecar is JSON, next to the output with ".provenance.json" added to
+ * its name:
+ *
+ *   {"files": ["a.pdf", "b.pdf"],
+ *    "objects": [{"id": 3, "file": 0, "source": 12}, ...]}
+ *
+ * where "file" indexes "files".
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Origin {
+    // index into `Provenance::files`
+    pub file: usize,
+    pub id: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Provenance {
+    pub files: Vec<PathBuf>,
+    // by output object number
+    pub objects: BTreeMap<u64, Origin>,
+}
+
+impl Provenance {
+    pub fn new() -> Provenance {
+        Provenance::default()
+    }
+
+    // The index `path` is recorded under, adding it the first time.
+    pub fn add_file(&mut self, path: &Path) -> usize {
+        match self.files.iter().position(|file| file == path) {
+            Some(index) => index,
+            None => {
+                self.files.push(path.to_path_buf());
+                self.files.len() - 1
+            },
+        }
+    }
+
+    pub fn record(&mut self, id: u64, file: usize, source: u64) {
+        self.objects.insert(id, Origin { file, id: source });
+    }
+
+    // Records every object `xref` has in use as kept under its own number,
+    // what an incremental update does with the file it appends to.
+    pub fn keep(&mut self, file: usize, xref: &XRefTable) {
+        for (id, entry) in xref.entries() {
+            if !entry.is_free {
+                self.record(id as u64, file, id as u64);
+            }
+        }
+    }
+
+    // The file and object number output object `id` was copied from.
+    pub fn source(&self, id: u64) -> Option<(&Path, u64)> {
+        let origin = self.objects.get(&id)?;
+        Some((self.files.get(origin.file)?.as_path(), origin.id))
+    }
+
+    pub fn to_json(&self) -> String {
+        let files: Vec<String> = self.files.iter().map(|file| json_string(&file.to_string_lossy())).collect();
+        let mut json = format!("{{\"files\": [{}],\n \"objects\": [", files.join(", "));
+        for (index, (id, origin)) in self.objects.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!("\n  {{\"id\": {}, \"file\": {}, \"source\": {}}}", id, origin.file, origin.id));
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+
+    // Writes the JSON next to `output`, see above.
+    pub fn write_sidecar(&self, output: &Path) -> Result<PathBuf> {
+        let path = sidecar_path(output);
+        fs::write(&path, self.to_json())?;
+        Ok(path)
+    }
+}
+
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".provenance.json");
+    PathBuf::from(name)
+}
+
+#[test]
+fn test_provenance() {
+    use parsers::xref::XRefEntry;
+
+    let mut provenance = Provenance::new();
+    let a = provenance.add_file(Path::new("a.pdf"));
+    let b = provenance.add_file(Path::new("dir/\"b\".pdf"));
+    assert_eq!(provenance.add_file(Path::new("a.pdf")), a);
+    provenance.keep(a, &XRefTable::new(vec![XRefEntry::free(), XRefEntry::in_use(15, 0), XRefEntry::free()]));
+    provenance.record(7, b, 3);
+
+    assert_eq!(provenance.source(1), Some((Path::new("a.pdf"), 1)));
+    assert_eq!(provenance.source(7), Some((Path::new("dir/\"b\".pdf"), 3)));
+    assert_eq!(provenance.source(2), None);
+    assert_eq!(provenance.to_json(), "{\"files\": [\"a.pdf\", \"dir/\\\"b\\\".pdf\"],\n \"objects\": [\
+                                      \n  {\"id\": 1, \"file\": 0, \"source\": 1},\
+                                      \n  {\"id\": 7, \"file\": 1, \"source\": 3}\n]}\n");
+    assert_eq!(sidecar_path(Path::new("out/merged.pdf")), PathBuf::from("out/merged.pdf.provenance.json"));
+}
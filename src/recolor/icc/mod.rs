@@ -0,0 +1,270 @@
+use graphics::Color;
+
+/*
+ * The part of ICC profiles (ICC.1:2010) that takes colors to XYZ: the
+ * matrix/TRC model of RGB and gray profiles, which is what scanners,
+ * cameras and screens (sRGB, Adobe RGB, Display P3, gray gamma 2.2) embed.
+ *
+ *   let profile = Profile::parse(&data)?;
+ *   let xyz = profile.xyz(&Color::Rgb(1.0, 0.0, 0.0));
+ *
+ * Each component goes through its tone curve (rTRC, gTRC, bTRC, or kTRC
+ * for gray), 'curv' tables and gammas or 'para' functions, and RGB then
+ * through the matrix of the rXYZ, gXYZ and bXYZ columns. The result is in
+ * the profile connection space, XYZ with a D50 white of Y = 1.
+ *
+ * CMYK and Lab profiles, and RGB ones with only lookup tables (A2B0), take
+ * multidimensional tables and their own curves to get there. Those aren't
+ * read, parse gives None for them and the colors are left to the device
+ * formulas.
+ *
+ * A profile starts with a 128 byte header, the tag table follows:
+ *
+ *   count                          4 bytes
+ *   signature, offset, size        12 bytes per tag
+ */
+
+const HEADER: usize = 128;
+// Where the header keeps the color space of the data and of the PCS.
+const DATA_SPACE: usize = 16;
+const CONNECTION_SPACE: usize = 20;
+
+// D50 XYZ to linear sRGB, with the Bradford adaptation from D65.
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+
+#[derive(Debug, PartialEq, Clone)]
+enum Curve {
+    Gamma(f64),
+    // samples spread evenly over 0 to 1
+    Table(Vec<f64>),
+    // a 'para' function type and its parameters g, a, b, c, d, e, f
+    Parametric(u16, [f64; 7]),
+}
+
+impl Curve {
+    fn parse(data: &[u8]) -> Option<Curve> {
+        match data.get(..4)? {
+            b"curv" => {
+                match read_u32(data, 8)? as usize {
+                    0 => Some(Curve::Gamma(1.0)),
+                    1 => Some(Curve::Gamma(read_u16(data, 12)? as f64 / 256.0)),
+                    count => (0..count)
+                        .map(|index| read_u16(data, 12 + 2 * index).map(|sample| sample as f64 / 65535.0))
+                        .collect::<Option<Vec<f64>>>()
+                        .map(Curve::Table),
+                }
+            },
+            b"para" => {
+                let function = read_u16(data, 8)?;
+                let count = [1, 3, 4, 5, 7].get(function as usize)?;
+                let mut parameters = [1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+                for (index, parameter) in parameters.iter_mut().take(*count).enumerate() {
+                    *parameter = read_fixed(data, 12 + 4 * index)?;
+                }
+                Some(Curve::Parametric(function, parameters))
+            },
+            _ => None,
+        }
+    }
+
+    fn apply(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match *self {
+            Curve::Gamma(gamma) => x.powf(gamma),
+            Curve::Table(ref samples) => {
+                let position = x * (samples.len() - 1) as f64;
+                let below = position.floor() as usize;
+                let above = (below + 1).min(samples.len() - 1);
+                samples[below] + (samples[above] - samples[below]) * (position - below as f64)
+            },
+            Curve::Parametric(function, [g, a, b, c, d, e, f]) => match function {
+                0 => x.powf(g),
+                1 if x >= -b / a => (a * x + b).powf(g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(g) + c,
+                2 => c,
+                3 if x >= d => (a * x + b).powf(g),
+                3 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            },
+        };
+        if y.is_finite() { y } else { 0.0 }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Model {
+    Gray(Curve),
+    // the curves of r, g and b, and the matrix with their XYZ as columns
+    Rgb([Curve; 3], [[f64; 3]; 3]),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Profile {
+    model: Model,
+}
+
+impl Profile {
+    // The profile in `data`, None if it isn't a matrix/TRC one.
+    pub fn parse(data: &[u8]) -> Option<Profile> {
+        if data.get(CONNECTION_SPACE..CONNECTION_SPACE + 4)? != b"XYZ " {
+            return None;
+        }
+        let tag = |signature: &[u8]| -> Option<&[u8]> {
+            let count = read_u32(data, HEADER)? as usize;
+            (0..count.min(data.len() / 12)).find_map(|index| {
+                let entry = HEADER + 4 + 12 * index;
+                if data.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_u32(data, entry + 4)? as usize;
+                data.get(offset..offset.checked_add(read_u32(data, entry + 8)? as usize)?)
+            })
+        };
+        let model = match data.get(DATA_SPACE..DATA_SPACE + 4)? {
+            b"GRAY" => Model::Gray(Curve::parse(tag(b"kTRC")?)?),
+            b"RGB " => {
+                let curves = [Curve::parse(tag(b"rTRC")?)?, Curve::parse(tag(b"gTRC")?)?, Curve::parse(tag(b"bTRC")?)?];
+                let mut matrix = [[0.0; 3]; 3];
+                for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+                    let xyz = tag(&signature[..])?;
+                    if xyz.get(..4)? != b"XYZ " {
+                        return None;
+                    }
+                    for (row, values) in matrix.iter_mut().enumerate() {
+                        values[column] = read_fixed(xyz, 8 + 4 * row)?;
+                    }
+                }
+                Model::Rgb(curves, matrix)
+            },
+            _ => return None,
+        };
+        Some(Profile { model })
+    }
+
+    // `color` in the profile connection space, None if it has the wrong
+    // number of components for the profile.
+    pub fn xyz(&self, color: &Color) -> Option<[f64; 3]> {
+        match (&self.model, color) {
+            (Model::Gray(curve), &Color::Gray(gray)) => {
+                // the D50 white scaled by the luminance
+                let y = curve.apply(gray);
+                Some([0.9642 * y, y, 0.8249 * y])
+            },
+            (Model::Rgb(curves, matrix), &Color::Rgb(r, g, b)) => {
+                let linear = [curves[0].apply(r), curves[1].apply(g), curves[2].apply(b)];
+                let mut xyz = [0.0; 3];
+                for (value, row) in xyz.iter_mut().zip(matrix.iter()) {
+                    *value = row.iter().zip(linear.iter()).map(|(a, b)| a * b).sum();
+                }
+                Some(xyz)
+            },
+            _ => None,
+        }
+    }
+}
+
+// An XYZ color as sRGB, clipped to the gamut.
+pub fn srgb(xyz: [f64; 3]) -> [f64; 3] {
+    let mut rgb = [0.0; 3];
+    for (value, row) in rgb.iter_mut().zip(XYZ_TO_SRGB.iter()) {
+        let linear: f64 = row.iter().zip(xyz.iter()).map(|(a, b)| a * b).sum();
+        *value = encode(linear);
+    }
+    rgb
+}
+
+// A luminance as a gray level, with the sRGB tone curve.
+pub fn gray(xyz: [f64; 3]) -> f64 {
+    encode(xyz[1])
+}
+
+fn encode(linear: f64) -> f64 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// An s15Fixed16Number.
+fn read_fixed(data: &[u8], offset: usize) -> Option<f64> {
+    read_u32(data, offset).map(|value| value as i32 as f64 / 65536.0)
+}
+
+// An RGB profile with sRGB's primaries and a plain `gamma` on each
+// component.
+#[cfg(test)]
+pub(crate) fn test_profile(gamma: f64) -> Vec<u8> {
+    let fixed = |value: f64| ((value * 65536.0).round() as i32).to_be_bytes().to_vec();
+    let mut curve = b"curv\0\0\0\0\0\0\0\x01".to_vec();
+    curve.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+    curve.extend_from_slice(&[0, 0]);
+    let columns = [[0.4361, 0.2225, 0.0139], [0.3851, 0.7169, 0.0971], [0.1431, 0.0606, 0.7141]];
+    let mut tags: Vec<(&[u8], Vec<u8>)> = vec![(b"rTRC", curve.clone()), (b"gTRC", curve.clone()), (b"bTRC", curve)];
+    for (signature, column) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().zip(columns.iter()) {
+        let mut xyz = b"XYZ \0\0\0\0".to_vec();
+        xyz.extend(column.iter().flat_map(|&value| fixed(value)));
+        tags.push((&signature[..], xyz));
+    }
+
+    let mut data = vec![0; HEADER];
+    data[DATA_SPACE..DATA_SPACE + 4].copy_from_slice(b"RGB ");
+    data[CONNECTION_SPACE..CONNECTION_SPACE + 4].copy_from_slice(b"XYZ ");
+    data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    let mut offset = HEADER + 4 + 12 * tags.len();
+    let mut contents = Vec::new();
+    for (signature, tag) in &tags {
+        data.extend_from_slice(signature);
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        offset += tag.len();
+        contents.extend_from_slice(tag);
+    }
+    data.extend(contents);
+    let size = data.len() as u32;
+    data[..4].copy_from_slice(&size.to_be_bytes());
+    data
+}
+
+#[test]
+fn test_profile_xyz() {
+    let profile = Profile::parse(&test_profile(1.0)).unwrap();
+    let xyz = profile.xyz(&Color::Rgb(1.0, 1.0, 1.0)).unwrap();
+    // the columns add up to the D50 white
+    assert!((xyz[0] - 0.9643).abs() < 1e-3 && (xyz[1] - 1.0).abs() < 1e-3 && (xyz[2] - 0.8251).abs() < 1e-3);
+    assert_eq!(srgb(xyz).map(|value| (value * 1000.0).round()), [1000.0, 1000.0, 1000.0]);
+    let red = profile.xyz(&Color::Rgb(1.0, 0.0, 0.0)).unwrap();
+    assert!((red[1] - 0.2225).abs() < 1e-4);
+    assert_eq!(srgb(red).map(|value| (value * 100.0).round()), [100.0, 0.0, 0.0]);
+    assert_eq!((gray(red) * 1000.0).round(), 509.0);
+    assert_eq!(profile.xyz(&Color::Gray(0.5)), None);
+
+    // gamma 2 halves the linear value of 0.5 twice over
+    let squared = Profile::parse(&test_profile(2.0)).unwrap().xyz(&Color::Rgb(0.5, 0.5, 0.5)).unwrap();
+    assert!((squared[1] - 0.25).abs() < 1e-3);
+
+    let mut cmyk = test_profile(1.0);
+    cmyk[DATA_SPACE..DATA_SPACE + 4].copy_from_slice(b"CMYK");
+    assert_eq!(Profile::parse(&cmyk), None);
+    assert_eq!(Profile::parse(b"not much of a profile"), None);
+
+    let table = Curve::Table(vec![0.0, 0.25, 1.0]);
+    assert_eq!((table.apply(0.25), table.apply(0.75)), (0.125, 0.625));
+    let srgb_curve = Curve::Parametric(3, [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045, 0.0, 0.0]);
+    assert!((srgb_curve.apply(encode(0.2)) - 0.2).abs() < 1e-6);
+}
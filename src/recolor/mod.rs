@@ -0,0 +1,535 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use document::{Document, DocumentPolicy, Options};
+use error::Result;
+use graphics::{Color, ColorFamily};
+use images;
+use normalize;
+use pages;
+use parsers::content::{Operation, Operations};
+use parsers::cos::DictNode;
+use transcode;
+use writer::Writer;
+
+use self::icc::Profile;
+
+mod icc;
+
+/*
+ * Converts a document's colors to grayscale or CMYK, for cheap printing or
+ * a print shop that wants everything in process colors.
+ *
+ *   let report = recolor::recolor(input, output, Target::Gray)?;
+ *
+ * What gets converted:
+ *
+ *   - color operators in page content and the form XObjects it draws: g,
+ *     rg and k (and their stroking versions) become the target's operator,
+ *     cs/CS naming a gray, RGB or CMYK space become the target's device
+ *     space, with the sc/scn colors set in it
+ *   - 8 bit RGB images, and CMYK ones when going to gray, rewritten
+ *     uncompressed in the target space
+ *
+ * Colors go through the device formulas of the spec (PDF 32000 10.3):
+ * gray is 0.3 r + 0.59 g + 0.11 b, CMYK takes its black from the darkest
+ * component. ICCBased spaces whose profile is an RGB or gray matrix/TRC
+ * one, the kind scans and photos carry, go through it instead: to XYZ,
+ * then to gray by the luminance or to sRGB and on to CMYK by the formulas
+ * (see icc). CMYK and lookup table profiles, and Cal spaces, are
+ * converted as the device space with the same number of components. The
+ * report counts the profiles passed over that way, so documents whose
+ * colors may come out off can be told apart.
+ *
+ * Left as they are: Separation, DeviceN, Lab and Indexed colors, patterns
+ * and shadings, inline images, annotation appearances, JPEG images (which
+ * would have to be decoded and re-encoded), and images with a /Decode
+ * array. Those images are counted in the report as skipped.
+ *
+ * Rewritten content streams come out one operation per line, the way
+ * `normalize` writes them, uncompressed. Like the other rewrites this is
+ * an incremental update, encrypted documents are refused.
+ */
+
+// Decimals kept in rewritten content streams, enough for any coordinate.
+const PRECISION: usize = 6;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    Gray,
+    Cmyk,
+}
+
+impl Target {
+    fn space(self) -> &'static str {
+        match self {
+            Target::Gray => "DeviceGray",
+            Target::Cmyk => "DeviceCMYK",
+        }
+    }
+
+    // The operator setting a color in the target space, stroking or not.
+    fn operator(self, stroke: bool) -> &'static str {
+        match (self, stroke) {
+            (Target::Gray, false) => "g",
+            (Target::Gray, true) => "G",
+            (Target::Cmyk, false) => "k",
+            (Target::Cmyk, true) => "K",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct RecolorReport {
+    // content streams and form XObjects rewritten
+    pub streams: usize,
+    pub images: usize,
+    // images in a convertible space that had to be left alone
+    pub skipped_images: usize,
+    // ICC profiles colors were converted through
+    pub applied_profiles: usize,
+    // ICC profiles of spaces converted as if they were device spaces
+    pub ignored_profiles: usize,
+}
+
+// A color space colors are converted from: its family, and its ICC
+// profile when it has one we can apply.
+#[derive(Debug, PartialEq, Clone)]
+struct Source {
+    family: ColorFamily,
+    profile: Option<Rc<Profile>>,
+}
+
+// The color spaces in effect, as colors set with sc/scn are converted
+// from. None while a space we don't convert is set.
+#[derive(Debug, PartialEq, Clone)]
+struct Spaces {
+    fill: Option<Source>,
+    stroke: Option<Source>,
+}
+
+struct Recoloring<'a> {
+    doc: &'a mut Document,
+    out: Writer,
+    target: Target,
+    seen: HashSet<u64>,
+    // the ICC profile streams of the spaces converted, and what they parse to
+    profiles: HashMap<(i64, i64), Option<Rc<Profile>>>,
+    report: RecolorReport,
+}
+
+pub fn recolor(input: &Path, output: &Path, target: Target) -> Result<RecolorReport> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options)?;
+    let xref = doc.xref.clone();
+    let mut recoloring = Recoloring {
+        out: Writer::appending(fs::read(input)?, xref),
+        doc: &mut doc,
+        target,
+        seen: HashSet::new(),
+        profiles: HashMap::new(),
+        report: RecolorReport::default(),
+    };
+    for page in pages::pages(recoloring.doc)? {
+        for stream in pages::content_streams(recoloring.doc, &page)? {
+            recoloring.content(&stream, &page.resources)?;
+        }
+        recoloring.xobjects(&page.resources)?;
+    }
+
+    let applied_profiles = recoloring.profiles.values().filter(|profile| profile.is_some()).count();
+    let report = RecolorReport {
+        applied_profiles,
+        ignored_profiles: recoloring.profiles.len() - applied_profiles,
+        ..recoloring.report
+    };
+    let (mut data, xref) = (recoloring.out.data, recoloring.out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(report)
+}
+
+impl<'a> Recoloring<'a> {
+    // Converts the images and forms in `resources`, and what the forms use.
+    fn xobjects(&mut self, resources: &DictNode) -> Result<()> {
+        let xobjects = match resources.get_resolved(self.doc, "XObject")? {
+            Some(DictNode::Dict(xobjects)) => xobjects,
+            _ => return Ok(()),
+        };
+        let mut names: Vec<&String> = xobjects.keys().collect();
+        names.sort();
+        for name in names {
+            let reference = &xobjects[name];
+            let id = match *reference {
+                DictNode::ObjectReference(id, _) if id >= 0 => id as u64,
+                _ => continue,
+            };
+            if self.seen.contains(&id) {
+                continue;
+            }
+            let dict = self.doc.resolve(reference)?;
+            if images::is_image(&dict) {
+                self.seen.insert(id);
+                self.image(id, &dict, resources)?;
+            } else if dict.get("Subtype").is_some_and(|subtype| subtype.is_name("Form")) {
+                // forms without resources of their own use their page's
+                let own = dict.get_resolved(self.doc, "Resources")?.filter(|own| own.as_dict().is_some());
+                let form_resources = own.unwrap_or_else(|| resources.clone());
+                self.content(reference, &form_resources)?;
+                self.xobjects(&form_resources)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Rewrites the content stream at `reference`, if anything in it changes.
+    fn content(&mut self, reference: &DictNode, resources: &DictNode) -> Result<()> {
+        let id = match *reference {
+            DictNode::ObjectReference(id, _) if id >= 0 && self.seen.insert(id as u64) => id as u64,
+            _ => return Ok(()),
+        };
+        let (dict, content) = match self.doc.referenced_stream(reference)? {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        let operations = match Operations::new(&content[..]).collect::<Result<Vec<Operation>>>() {
+            Ok(operations) => operations,
+            Err(_) => return Ok(()),
+        };
+        let doc = &mut *self.doc;
+        let profiles = &mut self.profiles;
+        let (operations, changed) = convert_operations(operations, self.target, |space| {
+            let family = ColorFamily::of(doc, space, resources)?;
+            let profile = match family {
+                // every gray, RGB and CMYK space set is replaced
+                ColorFamily::Gray | ColorFamily::Rgb | ColorFamily::Cmyk => icc_profile(doc, profiles, space, resources)?,
+                _ => None,
+            };
+            Ok(Source { family, profile })
+        })?;
+        if !changed {
+            return Ok(());
+        }
+        let content = normalize::write_operations(&operations, PRECISION);
+        let mut map = dict.as_dict().cloned().unwrap_or_default();
+        for key in &["Filter", "DecodeParms", "DL", "F", "FFilter", "FDecodeParms"] {
+            map.remove(*key);
+        }
+        map.insert("Length".to_string(), DictNode::Int(content.len() as i64));
+        self.out.stream(id, &DictNode::Dict(map), &content)?;
+        self.report.streams += 1;
+        Ok(())
+    }
+
+    // Converts image `id`, when it's one we can.
+    fn image(&mut self, id: u64, dict: &DictNode, resources: &DictNode) -> Result<()> {
+        let space = match dict.get("ColorSpace") {
+            Some(space) => space,
+            None => return Ok(()),
+        };
+        let family = ColorFamily::of(self.doc, space, resources)?;
+        let components = match (family, self.target) {
+            (ColorFamily::Rgb, _) => 3,
+            (ColorFamily::Cmyk, Target::Gray) => 4,
+            _ => return Ok(()),
+        };
+        let entry = match self.doc.xref.get(id as usize) {
+            Some(entry) => entry.clone(),
+            None => return Ok(()),
+        };
+        let usable = dict.get("BitsPerComponent").and_then(DictNode::as_int) == Some(8)
+            && dict.get("Decode").is_none()
+            && self.doc.encoded_image(&entry)?.is_none();
+        let data = match self.doc.stream(&entry) {
+            Ok(Some((_, data))) if usable => data,
+            _ => {
+                self.report.skipped_images += 1;
+                return Ok(());
+            },
+        };
+        let size = |key| dict.get(key).and_then(DictNode::as_int).unwrap_or(0).max(0) as usize;
+        let pixels = size("Width") * size("Height");
+        if data.len() < pixels * components {
+            self.report.skipped_images += 1;
+            return Ok(());
+        }
+
+        let profile = icc_profile(self.doc, &mut self.profiles, space, resources)?;
+        let samples = convert_samples(&data[..pixels * components], family, profile.as_deref(), self.target);
+        let mut map = dict.as_dict().cloned().unwrap_or_default();
+        for key in &["Filter", "DecodeParms", "DL", "F", "FFilter", "FDecodeParms"] {
+            map.remove(*key);
+        }
+        map.insert("ColorSpace".to_string(), DictNode::name(self.target.space()));
+        map.insert("Length".to_string(), DictNode::Int(samples.len() as i64));
+        self.out.stream(id, &DictNode::Dict(map), &samples)?;
+        self.report.images += 1;
+        Ok(())
+    }
+}
+
+// The ICC profile of `space`, a color space as cs/CS or an image's
+// /ColorSpace give it, if it's an ICCBased space with one we can apply.
+// Every profile seen goes in `profiles`, None for the others, and is only
+// read the first time.
+fn icc_profile(doc: &mut Document, profiles: &mut HashMap<(i64, i64), Option<Rc<Profile>>>, space: &DictNode, resources: &DictNode)
+    -> Result<Option<Rc<Profile>>>
+{
+    let space = match space.as_name() {
+        Some(name) => match resources.get_resolved(doc, "ColorSpace")? {
+            Some(spaces) => spaces.get(name).cloned().unwrap_or(DictNode::Null),
+            None => DictNode::Null,
+        },
+        None => space.clone(),
+    };
+    let space = doc.resolve(&space)?;
+    let items = space.as_array().unwrap_or(&[]);
+    let stream = match (items.first().and_then(DictNode::as_name), items.get(1)) {
+        (Some("ICCBased"), Some(stream)) => stream,
+        _ => return Ok(None),
+    };
+    let key = match stream.as_reference() {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    if let Some(profile) = profiles.get(&key) {
+        return Ok(profile.clone());
+    }
+    // a profile that doesn't decode is one more we can't apply
+    let profile = match doc.referenced_stream(stream) {
+        Ok(Some((_, data))) => Profile::parse(&data).map(Rc::new),
+        _ => None,
+    };
+    profiles.insert(key, profile.clone());
+    Ok(profile)
+}
+
+// `operations` with their colors converted, and whether any were.
+// `source_of` tells what the color spaces set with cs and CS are.
+fn convert_operations<F>(operations: Vec<Operation>, target: Target, mut source_of: F) -> Result<(Vec<Operation>, bool)>
+    where F: FnMut(&DictNode) -> Result<Source>
+{
+    let gray = Source { family: ColorFamily::Gray, profile: None };
+    let initial = Spaces { fill: Some(gray.clone()), stroke: Some(gray) };
+    let mut spaces = initial.clone();
+    let mut saved = Vec::new();
+    let mut changed = false;
+    let mut converted = Vec::with_capacity(operations.len());
+    for mut operation in operations {
+        let stroke = operation.operator.chars().all(|ch| ch.is_ascii_uppercase());
+        let space = if stroke { &mut spaces.stroke } else { &mut spaces.fill };
+        match operation.operator.as_str() {
+            "q" => saved.push(spaces.clone()),
+            "Q" => spaces = saved.pop().unwrap_or_else(|| initial.clone()),
+            "g" | "G" | "rg" | "RG" | "k" | "K" => {
+                let family = match operation.operator.to_lowercase().as_str() {
+                    "g" => ColorFamily::Gray,
+                    "rg" => ColorFamily::Rgb,
+                    _ => ColorFamily::Cmyk,
+                };
+                *space = Some(Source { family, profile: None });
+                if let Some(components) = convert(&family.color(&operation.operands), None, target) {
+                    operation = Operation { operator: target.operator(stroke).to_string(), operands: numbers(&components) };
+                    changed = true;
+                }
+            },
+            "cs" | "CS" => {
+                let source = match operation.operands.first() {
+                    Some(name) => source_of(name)?,
+                    None => Source { family: ColorFamily::Other, profile: None },
+                };
+                *space = match source.family {
+                    ColorFamily::Gray | ColorFamily::Rgb | ColorFamily::Cmyk => {
+                        operation.operands = vec![DictNode::name(target.space())];
+                        changed = true;
+                        Some(source)
+                    },
+                    _ => None,
+                };
+            },
+            "sc" | "SC" | "scn" | "SCN" => {
+                let converted = space.as_ref().and_then(|source| {
+                    convert(&source.family.color(&operation.operands), source.profile.as_deref(), target)
+                });
+                if let Some(components) = converted {
+                    operation.operands = numbers(&components);
+                    changed = true;
+                }
+            },
+            _ => (),
+        }
+        converted.push(operation);
+    }
+    Ok((converted, changed))
+}
+
+// `color` in the target space, through `profile` if there's one, None
+// for colors that aren't gray, RGB or CMYK.
+fn convert(color: &Color, profile: Option<&Profile>, target: Target) -> Option<Vec<f64>> {
+    if let Some(xyz) = profile.and_then(|profile| profile.xyz(color)) {
+        let color = match target {
+            Target::Gray => Color::Gray(icc::gray(xyz)),
+            Target::Cmyk => {
+                let [r, g, b] = icc::srgb(xyz);
+                Color::Rgb(r, g, b)
+            },
+        };
+        return convert(&color, None, target);
+    }
+    let converted = match (color, target) {
+        (&Color::Gray(gray), Target::Gray) => vec![gray],
+        (&Color::Rgb(r, g, b), Target::Gray) => vec![0.3 * r + 0.59 * g + 0.11 * b],
+        (&Color::Cmyk(c, m, y, k), Target::Gray) => vec![1.0 - (0.3 * c + 0.59 * m + 0.11 * y + k).min(1.0)],
+        (&Color::Gray(gray), Target::Cmyk) => vec![0.0, 0.0, 0.0, 1.0 - gray],
+        (&Color::Rgb(r, g, b), Target::Cmyk) => {
+            let (c, m, y) = (1.0 - r, 1.0 - g, 1.0 - b);
+            // black generation and undercolor removal both take the whole
+            // common part
+            let k = c.min(m).min(y);
+            vec![c - k, m - k, y - k, k]
+        },
+        (&Color::Cmyk(c, m, y, k), Target::Cmyk) => vec![c, m, y, k],
+        _ => return None,
+    };
+    Some(converted.into_iter().map(|value| value.clamp(0.0, 1.0)).collect())
+}
+
+// 8 bit samples of `family` converted to the target space, through
+// `profile` if there's one.
+fn convert_samples(data: &[u8], family: ColorFamily, profile: Option<&Profile>, target: Target) -> Vec<u8> {
+    let components = match family {
+        ColorFamily::Gray => 1,
+        ColorFamily::Rgb => 3,
+        _ => 4,
+    };
+    let mut converted = Vec::with_capacity(data.len() / components * if target == Target::Gray { 1 } else { 4 });
+    for pixel in data.chunks(components) {
+        let values: Vec<f64> = pixel.iter().map(|&sample| sample as f64 / 255.0).collect();
+        let color = match *values.as_slice() {
+            [gray] => Color::Gray(gray),
+            [r, g, b] => Color::Rgb(r, g, b),
+            [c, m, y, k] => Color::Cmyk(c, m, y, k),
+            _ => continue,
+        };
+        if let Some(values) = convert(&color, profile, target) {
+            converted.extend(values.iter().map(|&value| (value * 255.0).round() as u8));
+        }
+    }
+    converted
+}
+
+fn numbers(components: &[f64]) -> Vec<DictNode> {
+    components.iter().map(|&value| DictNode::Float(value)).collect()
+}
+
+#[test]
+fn test_convert() {
+    assert_eq!(convert(&Color::Rgb(1.0, 0.0, 0.0), None, Target::Gray), Some(vec![0.3]));
+    assert_eq!(convert(&Color::Cmyk(0.0, 0.0, 0.0, 1.0), None, Target::Gray), Some(vec![0.0]));
+    assert_eq!(convert(&Color::Gray(0.25), None, Target::Cmyk), Some(vec![0.0, 0.0, 0.0, 0.75]));
+    assert_eq!(convert(&Color::Rgb(1.0, 0.5, 0.0), None, Target::Cmyk), Some(vec![0.0, 0.5, 1.0, 0.0]));
+    assert_eq!(convert(&Color::Other(vec![1.0]), None, Target::Gray), None);
+
+    // a linear profile with sRGB's primaries: red's luminance is 0.2225,
+    // 0.509 with the sRGB tone curve, and it stays red in CMYK
+    let profile = Profile::parse(&icc::test_profile(1.0)).unwrap();
+    let gray = convert(&Color::Rgb(1.0, 0.0, 0.0), Some(&profile), Target::Gray).unwrap();
+    assert_eq!((gray[0] * 1000.0).round(), 509.0);
+    let cmyk = convert(&Color::Rgb(1.0, 0.0, 0.0), Some(&profile), Target::Cmyk).unwrap();
+    assert_eq!(cmyk.iter().map(|value| (value * 100.0).round()).collect::<Vec<_>>(), vec![0.0, 100.0, 100.0, 0.0]);
+    // CMYK colors don't fit an RGB profile and go by the formulas
+    assert_eq!(convert(&Color::Cmyk(0.0, 0.0, 0.0, 1.0), Some(&profile), Target::Gray), Some(vec![0.0]));
+
+    assert_eq!(convert_samples(&[255, 0, 0, 0, 0, 0], ColorFamily::Rgb, None, Target::Gray), vec![77, 0]);
+    assert_eq!(convert_samples(&[0, 0, 0, 255], ColorFamily::Cmyk, None, Target::Gray), vec![0]);
+    assert_eq!(convert_samples(&[255, 255, 255], ColorFamily::Rgb, None, Target::Cmyk), vec![0, 0, 0, 0]);
+    assert_eq!(convert_samples(&[255, 0, 0], ColorFamily::Rgb, Some(&profile), Target::Gray), vec![130]);
+}
+
+#[cfg(test)]
+fn recolored(content: &[u8], target: Target) -> String {
+    // /CS0 is an ICCBased RGB space with a linear profile
+    let profile = Rc::new(Profile::parse(&icc::test_profile(1.0)).unwrap());
+    let by_name = |space: &DictNode| Ok(match space.as_name() {
+        Some("CS0") => Source { family: ColorFamily::Rgb, profile: Some(profile.clone()) },
+        name => Source { family: name.and_then(ColorFamily::from_name).unwrap_or(ColorFamily::Other), profile: None },
+    });
+    let operations = Operations::new(content).collect::<Result<Vec<Operation>>>().unwrap();
+    let (operations, _) = convert_operations(operations, target, by_name).unwrap();
+    String::from_utf8(normalize::write_operations(&operations, PRECISION)).unwrap()
+}
+
+#[test]
+fn test_operations() {
+    assert_eq!(recolored(b"1 0 0 rg 0 0 1 RG 0 0 10 10 re B", Target::Gray), "0.3 g\n0.11 G\n0 0 10 10 re\nB\n");
+    assert_eq!(recolored(b"/DeviceRGB cs 0 1 0 sc 0.5 G", Target::Cmyk), "/DeviceCMYK cs\n1 0 1 0 sc\n0 0 0 0.5 K\n");
+    // colors in spaces that aren't converted stay, the space is restored by Q
+    assert_eq!(recolored(b"q /Lab cs 50 0 0 sc Q 1 sc", Target::Gray), "q\n/Lab cs\n50 0 0 sc\nQ\n1 sc\n");
+    // the profile is used for the space it belongs to, and only while it's set
+    assert_eq!(recolored(b"q /CS0 cs 1 1 1 sc Q 1 0 0 rg", Target::Gray), "q\n/DeviceGray cs\n1 sc\nQ\n0.3 g\n");
+}
+
+// The fixture, its page filling in an ICCBased RGB space with `profile`
+// and drawing an image in the same one, saved as a new file.
+#[cfg(test)]
+fn icc_document(profile: &[u8]) -> ::fixtures::FixtureFile {
+    use fixtures::{Fixture, FixtureFile};
+    use writer::{self, SaveOptions};
+
+    let file = Fixture::Simple.write();
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+    let icc = DictNode::Array(vec![DictNode::name("ICCBased"), DictNode::reference(10)]);
+    doc.set_stream(10, hashmap!{ "N".to_string() => DictNode::Int(3) }, profile.to_vec());
+    doc.set_stream(11, hashmap!{
+        "Subtype".to_string() => DictNode::name("Image"),
+        "Width".to_string() => DictNode::Int(1),
+        "Height".to_string() => DictNode::Int(1),
+        "BitsPerComponent".to_string() => DictNode::Int(8),
+        "ColorSpace".to_string() => icc.clone(),
+    }, vec![255, 0, 0]);
+    let mut page = doc.resolve(&DictNode::reference(3)).unwrap().as_dict().cloned().unwrap();
+    page.insert("Resources".to_string(), DictNode::Dict(hashmap!{
+        "ColorSpace".to_string() => DictNode::Dict(hashmap!{ "CS0".to_string() => icc }),
+        "XObject".to_string() => DictNode::Dict(hashmap!{ "Im0".to_string() => DictNode::reference(11) }),
+    }));
+    doc.set_object(3, DictNode::Dict(page));
+    doc.set_stream(5, hashmap!{}, b"/CS0 cs 1 0 0 sc 0 0 10 10 re f /DeviceRGB cs 0 1 0 sc /Im0 Do".to_vec());
+    let input = FixtureFile { path: file.path.with_extension("icc.pdf") };
+    writer::save(&mut doc, &input.path, &SaveOptions::default()).unwrap();
+    input
+}
+
+#[test]
+fn test_ignored_profiles() {
+    use fixtures::FixtureFile;
+
+    let input = icc_document(b"not much of a profile");
+    let output = FixtureFile { path: input.path.with_extension("gray.pdf") };
+    let report = recolor(&input.path, &output.path, Target::Gray).unwrap();
+    let expected = RecolorReport { streams: 1, images: 1, skipped_images: 0, applied_profiles: 0, ignored_profiles: 1 };
+    assert_eq!(report, expected);
+}
+
+#[test]
+fn test_applied_profiles() {
+    use fixtures::FixtureFile;
+
+    let input = icc_document(&icc::test_profile(1.0));
+    let output = FixtureFile { path: input.path.with_extension("gray.pdf") };
+    let report = recolor(&input.path, &output.path, Target::Gray).unwrap();
+    let expected = RecolorReport { streams: 1, images: 1, skipped_images: 0, applied_profiles: 1, ignored_profiles: 0 };
+    assert_eq!(report, expected);
+
+    // the profile's red for the fill and the image, the device formula for
+    // the DeviceRGB green
+    let mut doc = Document::open(&output.path, Options::default()).unwrap();
+    let (_, content) = doc.referenced_stream(&DictNode::reference(5)).unwrap().unwrap();
+    let content = String::from_utf8(content).unwrap();
+    assert!(content.starts_with("/DeviceGray cs\n0.509"), "{}", content);
+    assert!(content.contains("/DeviceGray cs\n0.59 sc\n"), "{}", content);
+    let (_, image) = doc.referenced_stream(&DictNode::reference(11)).unwrap().unwrap();
+    assert_eq!(image, vec![130]);
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use document::{Document, DocumentPolicy, Options};
+use error::Result;
+use graphics::Matrix;
+use writer::Writer;
+use pages::{self, Page};
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Puts every page on the same paper size, for printing a document put
+ * together from sources that didn't agree on one.
+ *
+ *   resize::resize(input, output, resize::paper_size("a4").unwrap())?;
+ *
+ * A page's content is scaled uniformly to fit the new size and centered on
+ * it, so nothing is cut off or stretched. Landscape pages get the paper
+ * turned landscape, which keeps /Rotate meaning what it did. Each page gets
+ * a new media box, at the origin, and a crop box to match. Bleed, trim and
+ * art boxes are dropped rather than guessed at.
+ *
+ * The content is wrapped in a "q ... cm" stream in front and a "Q" stream
+ * after, leaving the original streams alone. Annotation rectangles are
+ * moved along with it, their appearances being fitted to them. Other
+ * positions in annotations (/QuadPoints, /InkList...) aren't.
+ *
+ * Like the other rewrites this is an incremental update. Pages written
+ * directly into their parent's /Kids have no object of their own to update
+ * and are left as they are.
+ */
+
+// Width and height in points.
+pub const PAPER_SIZES: &[(&str, (f64, f64))] = &[
+    ("letter", (612.0, 792.0)),
+    ("legal", (612.0, 1008.0)),
+    ("tabloid", (792.0, 1224.0)),
+    ("a3", (842.0, 1191.0)),
+    ("a4", (595.0, 842.0)),
+    ("a5", (420.0, 595.0)),
+];
+
+const DROPPED_BOXES: &[&str] = &["BleedBox", "TrimBox", "ArtBox"];
+
+// A size from PAPER_SIZES by name, or given as "WIDTHxHEIGHT" in points.
+pub fn paper_size(name: &str) -> Option<(f64, f64)> {
+    let name = name.to_lowercase();
+    if let Some(&(_, size)) = PAPER_SIZES.iter().find(|&&(paper, _)| paper == name) {
+        return Some(size);
+    }
+    let (width, height) = name.split_once('x')?;
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    if size.0 > 0.0 && size.1 > 0.0 { Some(size) } else { None }
+}
+
+pub fn resize(input: &Path, output: &Path, (width, height): (f64, f64)) -> Result<()> {
+    let options = Options { policy: DocumentPolicy { allow_encrypted: false, ..DocumentPolicy::default() }, ..Options::default() };
+    let mut doc = Document::open(input, options)?;
+    let xref = doc.xref.clone();
+    let mut out = Writer::appending(fs::read(input)?, xref);
+
+    let restore_id = out.reserve();
+    out.stream(restore_id, &stream_dict(3), b"\nQ\n")?;
+    let mut transforms = HashMap::new();
+    for page in pages::pages(&mut doc)? {
+        let id = match page.id {
+            Some(id) => id,
+            None => continue,
+        };
+        let (media_box, matrix) = placement(&page, width, height);
+        let content = format!("q {} cm\n", matrix_text(matrix));
+        let transform_id = match transforms.get(&content) {
+            Some(&id) => id,
+            None => {
+                let id = out.reserve();
+                out.stream(id, &stream_dict(content.len()), content.as_bytes())?;
+                transforms.insert(content, id);
+                id
+            },
+        };
+
+        let mut dict = page.dict.as_dict().cloned().unwrap_or_default();
+        let mut contents = vec![DictNode::reference(transform_id)];
+        contents.extend(pages::content_streams(&mut doc, &page)?);
+        contents.push(DictNode::reference(restore_id));
+        dict.insert("Contents".to_string(), DictNode::Array(contents));
+        dict.insert("MediaBox".to_string(), rect(media_box));
+        dict.insert("CropBox".to_string(), rect(media_box));
+        for &key in DROPPED_BOXES {
+            dict.remove(key);
+        }
+
+        if let Some(annots) = page.dict.get_resolved(&mut doc, "Annots")? {
+            let mut moved = Vec::new();
+            for annot in annots.as_array().unwrap_or(&[]) {
+                match *annot {
+                    DictNode::ObjectReference(annot_id, _) if annot_id >= 0 => {
+                        let object = doc.resolve(annot)?;
+                        out.object(annot_id as u64, &move_annotation(&object, matrix))?;
+                        moved.push(annot.clone());
+                    },
+                    ref annot => moved.push(move_annotation(annot, matrix)),
+                }
+            }
+            dict.insert("Annots".to_string(), DictNode::Array(moved));
+        }
+        out.object(id, &DictNode::Dict(dict))?;
+    }
+
+    let (mut data, xref) = (out.data, out.xref);
+    let format = transcode::preferred_format(&xref);
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// The page's new media box, in its own units, and the matrix taking its
+// content there.
+fn placement(page: &Page, width: f64, height: f64) -> ([f64; 4], Matrix) {
+    let [llx, lly, urx, ury] = page.crop_box;
+    let (short, long) = (width.min(height) / page.user_unit, width.max(height) / page.user_unit);
+    let (width, height) = if urx - llx > ury - lly { (long, short) } else { (short, long) };
+    let scale = (width / (urx - llx)).min(height / (ury - lly));
+    let scale = if scale.is_finite() && scale > 0.0 { scale } else { 1.0 };
+    let offset = ((width - (urx - llx) * scale) / 2.0, (height - (ury - lly) * scale) / 2.0);
+    let matrix = Matrix::translate(-llx, -lly)
+        * Matrix::new(scale, 0.0, 0.0, scale, 0.0, 0.0)
+        * Matrix::translate(offset.0, offset.1);
+    ([0.0, 0.0, width, height], matrix)
+}
+
+// The annotation with its /Rect where `matrix` takes it.
+fn move_annotation(annot: &DictNode, matrix: Matrix) -> DictNode {
+    let mut annot = annot.clone();
+    if let DictNode::Dict(ref mut map) = annot {
+        if let Some([llx, lly, urx, ury]) = map.get("Rect").and_then(DictNode::as_rect) {
+            let (x0, y0) = matrix.apply(llx, lly);
+            let (x1, y1) = matrix.apply(urx, ury);
+            map.insert("Rect".to_string(), rect([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]));
+        }
+    }
+    annot
+}
+
+fn rect(rect: [f64; 4]) -> DictNode {
+    DictNode::Array(rect.iter().map(|&value| DictNode::Float((value * 1000.0).round() / 1000.0)).collect())
+}
+
+fn matrix_text(matrix: Matrix) -> String {
+    let numbers: Vec<String> = [matrix.a, matrix.b, matrix.c, matrix.d, matrix.e, matrix.f].iter().map(|&value| number(value)).collect();
+    numbers.join(" ")
+}
+
+// Four decimals, scales need more than positions do.
+fn number(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" {
+        "0".to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+fn stream_dict(length: usize) -> DictNode {
+    DictNode::Dict(HashMap::from([("Length".to_string(), DictNode::Int(length as i64))]))
+}
+
+#[test]
+fn test_paper_size() {
+    assert_eq!(paper_size("A4"), Some((595.0, 842.0)));
+    assert_eq!(paper_size("500x700.5"), Some((500.0, 700.5)));
+    assert_eq!(paper_size("0x700"), None);
+    assert_eq!(paper_size("folio"), None);
+}
+
+#[test]
+fn test_placement() {
+    use fixtures::page;
+
+    // A4 onto letter: scaled to the height, centered across
+    let (media_box, matrix) = placement(&page([0.0, 0.0, 595.0, 842.0], 0), 612.0, 792.0);
+    assert_eq!(media_box, [0.0, 0.0, 612.0, 792.0]);
+    assert_eq!(matrix_text(matrix), "0.9406 0 0 0.9406 26.1663 0");
+
+    // landscape, with its crop box off the origin: the paper turns
+    let (media_box, matrix) = placement(&page([100.0, 100.0, 942.0, 695.0], 90), 612.0, 792.0);
+    assert_eq!(media_box, [0.0, 0.0, 792.0, 612.0]);
+    assert_eq!(matrix_text(matrix), "0.9406 0 0 0.9406 -94.0618 -67.8955");
+
+    let annot = DictNode::Dict(hashmap!{ "Rect".to_string() => DictNode::Array(vec![DictNode::Int(10), DictNode::Int(20), DictNode::Int(30), DictNode::Int(40)]) });
+    let moved = move_annotation(&annot, Matrix::new(0.5, 0.0, 0.0, 0.5, 100.0, 0.0));
+    assert_eq!(moved.get("Rect").unwrap().to_string(), "[105.0 10.0 115.0 20.0]");
+}
@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+
+use document::scan_objects;
+use filters;
+use fonts::ProgramKind;
+use parsers::content::Operations;
+use parsers::cos::{self, DictNode};
+use parsers::object::{self, Terminator};
+use streams;
+
+/*
+ * Whatever can be got out of a file too broken to open, straight from its
+ * bytes: no trailer, xref or page tree needed.
+ *
+ *   for fragment in salvage::salvage(&fs::read(path)?) {
+ *       fs::write(format!("{}.{}", fragment.offset, fragment.kind.extension()), &fragment.data)?;
+ *   }
+ *
+ * Every stream is a candidate. Those after an "N G obj" header have their
+ * dictionary read and are decoded by its /Filter. A "stream" keyword
+ * anywhere else, whose header got lost, has Flate tried on its data, since
+ * that's what most streams are. The data is then recognized by how it
+ * starts, or what its dictionary says:
+ *
+ *   - JPEG and JPEG 2000 images
+ *   - font programs: TrueType, OpenType, bare CFF and Type 1
+ *   - content streams, whose shown strings are kept as text
+ *
+ * Without the fonts, strings are read as Latin-1 and only those that look
+ * like text are kept: text in composite fonts, two bytes a character, is
+ * lost. Anything else is left out. A stream whose /Length is wrong or
+ * indirect ends at "endstream", or at the end of the file for the last one
+ * of a truncated file.
+ */
+
+// Of a content stream's shown characters, how many have to be printable
+// for its text to be kept.
+const PRINTABLE_FRACTION: f64 = 0.8;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FragmentKind {
+    Jpeg,
+    Jpeg2000,
+    Font(ProgramKind),
+    Text,
+}
+
+impl FragmentKind {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FragmentKind::Jpeg => "jpg",
+            FragmentKind::Jpeg2000 => "jp2",
+            FragmentKind::Font(kind) => kind.extension(),
+            FragmentKind::Text => "txt",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fragment {
+    // where the stream's data starts in the file
+    pub offset: u64,
+    // the object it was in, when its header survived
+    pub id: Option<u64>,
+    pub kind: FragmentKind,
+    // the file's contents, UTF-8 for text
+    pub data: Vec<u8>,
+}
+
+// What's recognizable in the file, in file order.
+pub fn salvage(data: &[u8]) -> Vec<Fragment> {
+    let mut fragments = Vec::new();
+    let mut seen = HashSet::new();
+    for (id, _, offset) in scan_objects(data) {
+        let offset = offset as usize;
+        let scan = match object::scan(&data[offset..]) {
+            Ok(scan) => scan,
+            Err(_) => continue,
+        };
+        let start = match scan.terminator {
+            Terminator::Stream { data_start } => offset + data_start,
+            _ => continue,
+        };
+        seen.insert(start);
+        let dict = cos::parse_bytes(&data[offset + scan.body.start..offset + scan.body.end]).unwrap_or(DictNode::Null);
+        if let Some((kind, recovered)) = recognize(&dict, stream_data(data, start, &dict)) {
+            fragments.push(Fragment { offset: start as u64, id: Some(id), kind, data: recovered });
+        }
+    }
+
+    // streams without a header in front
+    let mut position = 0;
+    while let Some(found) = streams::find(&data[position..], b"stream") {
+        let keyword = position + found;
+        position = keyword + b"stream".len();
+        if data[..keyword].ends_with(b"end") {
+            continue;
+        }
+        let start = match (data.get(position), data.get(position + 1)) {
+            (Some(b'\r'), Some(b'\n')) => position + 2,
+            (Some(b'\n'), _) | (Some(b'\r'), _) => position + 1,
+            _ => continue,
+        };
+        if !seen.insert(start) {
+            continue;
+        }
+        if let Some((kind, recovered)) = recognize(&DictNode::Null, stream_data(data, start, &DictNode::Null)) {
+            fragments.push(Fragment { offset: start as u64, id: None, kind, data: recovered });
+        }
+    }
+    fragments.sort_by_key(|fragment| fragment.offset);
+    fragments
+}
+
+// The stream's data starting at `start`: /Length bytes if that's followed by
+// endstream, up to endstream otherwise.
+fn stream_data<'a>(data: &'a [u8], start: usize, dict: &DictNode) -> &'a [u8] {
+    let rest = &data[start..];
+    if let Some(length) = streams::length(dict).map(|length| length as usize).filter(|&length| length <= rest.len()) {
+        let after = &rest[length..];
+        let skipped = after.iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+        if after[skipped..].starts_with(b"endstream") {
+            return &rest[..length];
+        }
+    }
+    streams::raw_body(rest).unwrap_or(rest)
+}
+
+// What the stream is and what to keep of it, None for nothing we know.
+fn recognize(dict: &DictNode, raw: &[u8]) -> Option<(FragmentKind, Vec<u8>)> {
+    let decoded = match *dict {
+        DictNode::Dict(_) => streams::decode(dict, raw).ok(),
+        _ => None,
+    };
+    let decoded = decoded.or_else(|| filters::flate_decode(raw).ok()).unwrap_or_else(|| raw.to_vec());
+    let subtype = dict.get("Subtype").and_then(DictNode::as_name);
+    let kind = if decoded.starts_with(b"\xff\xd8\xff") {
+        FragmentKind::Jpeg
+    } else if decoded.starts_with(b"\0\0\0\x0cjP  ") || decoded.starts_with(b"\xff\x4f\xff\x51") {
+        FragmentKind::Jpeg2000
+    } else if decoded.starts_with(b"\0\x01\0\0") || decoded.starts_with(b"true") {
+        FragmentKind::Font(ProgramKind::TrueType)
+    } else if decoded.starts_with(b"OTTO") {
+        FragmentKind::Font(ProgramKind::OpenType)
+    } else if decoded.starts_with(b"%!PS-AdobeFont") || decoded.starts_with(b"%!FontType1") {
+        FragmentKind::Font(ProgramKind::Type1)
+    } else if subtype == Some("CIDFontType0C") {
+        FragmentKind::Font(ProgramKind::CIDFontType0C)
+    } else if subtype == Some("Type1C") || (subtype.is_none() && is_cff(&decoded)) {
+        FragmentKind::Font(ProgramKind::Type1C)
+    } else {
+        let text = shown_text(&decoded)?;
+        return Some((FragmentKind::Text, text.into_bytes()));
+    };
+    Some((kind, decoded))
+}
+
+// A CFF header: version 1.0, a 4 byte header and offsets of 1 to 4 bytes.
+fn is_cff(data: &[u8]) -> bool {
+    matches!(data, [1, 0, 4, 1..=4, ..])
+}
+
+// The strings a content stream shows, a line per line of text, if it's a
+// content stream and they read as text.
+fn shown_text(content: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    let mut showed_text = false;
+    for operation in Operations::new(content) {
+        let operation = match operation {
+            Ok(operation) => operation,
+            Err(_) => break,
+        };
+        let new_line = match operation.operator.as_str() {
+            "T*" | "'" | "\"" | "ET" => true,
+            "Td" | "TD" => operation.numbers().is_some_and(|numbers| numbers.get(1).is_some_and(|&y| y != 0.0)),
+            _ => false,
+        };
+        if new_line && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        match operation.operator.as_str() {
+            "Tj" | "'" | "\"" => {
+                showed_text = true;
+                if let Some(string) = operation.operands.last().and_then(DictNode::as_string) {
+                    text.push_str(&cos::latin1(string));
+                }
+            },
+            "TJ" => {
+                showed_text = true;
+                for item in operation.operands.first().and_then(DictNode::as_array).unwrap_or(&[]) {
+                    match item.as_string() {
+                        Some(string) => text.push_str(&cos::latin1(string)),
+                        // a big enough move back reads as a space
+                        None if item.as_float().is_some_and(|adjustment| adjustment < -200.0) => text.push(' '),
+                        None => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    let text = text.trim().to_string();
+    let total = text.chars().filter(|c| !c.is_whitespace()).count();
+    let printable = text.chars().filter(|c| !c.is_whitespace() && !c.is_control() && (*c as u32) < 0x7f).count();
+    if !showed_text || total == 0 || (printable as f64) < total as f64 * PRINTABLE_FRACTION {
+        return None;
+    }
+    Some(text)
+}
+
+#[test]
+fn test_shown_text() {
+    let content = b"BT /F1 12 Tf 72 700 Td (Hello,) Tj [(W) 80 (orld) -250 (again)] TJ 0 -14 Td (Next line) Tj ET";
+    assert_eq!(shown_text(content).as_deref(), Some("Hello,World again\nNext line"));
+    // two byte codes of a composite font aren't text without the font
+    assert_eq!(shown_text(b"BT /F2 12 Tf <00230045004c> Tj ET"), None);
+    assert_eq!(shown_text(b"0 0 100 100 re f"), None);
+}
+
+#[test]
+fn test_salvage_without_headers() {
+    let mut file = b"%PDF-1.7\ngarbage << /Length 99 >>\nstream\n\xff\xd8\xff\xe0JFIF\nendstream\n".to_vec();
+    file.extend_from_slice(b"more garbage stream\r\nOTTO\x00\x01 truncated");
+    let fragments = salvage(&file);
+    assert_eq!(fragments.len(), 2);
+    assert_eq!(fragments[0].kind, FragmentKind::Jpeg);
+    assert_eq!(fragments[0].data, b"\xff\xd8\xff\xe0JFIF");
+    assert_eq!(fragments[0].offset, 41);
+    assert_eq!(fragments[1].kind, FragmentKind::Font(ProgramKind::OpenType));
+    assert_eq!(fragments[1].kind.extension(), "otf");
+    assert_eq!(fragments[1].id, None);
+}
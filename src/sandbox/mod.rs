@@ -0,0 +1,310 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use document::{Document, Options};
+use error::{PdfError, Result};
+use pages;
+use parsers::cos::DictNode;
+use transcode;
+
+/*
+ * Looking at untrusted files without letting one of them take the caller
+ * down with it.
+ *
+ * `summarize` opens a file and reports what a mail gateway or upload
+ * service usually wants to know: page count, whether it's encrypted, has
+ * scripts or attachments, and what had to be papered over to read it. It
+ * runs in-process, with panics caught and turned into errors, which is as
+ * far as isolation goes without a process boundary:
+ *
+ *   let summary = sandbox::summarize(path, Options::default())?;
+ *
+ * `Sandbox` goes further and runs the nails-pdf binary's `summary` command
+ * on the file in a child process, killing it if it runs out of time. An
+ * infinite loop, stack overflow or abort then only costs the child. The
+ * summary comes back over stdout in the line format `Summary` prints and
+ * parses:
+ *
+ *   let summary = Sandbox::new("/usr/local/bin/nails-pdf").summarize(path)?;
+ *
+ * The child's address space is limited as well, to 2 GB unless the
+ * Sandbox says otherwise, by starting it through the shell's ulimit. A
+ * child that goes over fails to allocate and aborts. That needs /bin/sh,
+ * so elsewhere than Unix there's only the timeout; CPU time is bounded by
+ * the timeout already, and anything finer (cgroups, job objects) is for
+ * whoever deploys it.
+ */
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_MEMORY_LIMIT: u64 = 2 * 1024 * 1024 * 1024;
+// more output than a summary could ever be means something went wrong
+const MAX_OUTPUT: u64 = 1024 * 1024;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Summary {
+    // from the %PDF-M.m header
+    pub version: Option<(u8, u8)>,
+    pub pages: usize,
+    // objects in use in the xref
+    pub objects: usize,
+    pub encrypted: bool,
+    pub has_form: bool,
+    // document level JavaScript (/Names /JavaScript) or an /OpenAction
+    // that runs some
+    pub has_javascript: bool,
+    pub has_attachments: bool,
+    // what lenient parsing had to work around
+    pub warnings: Vec<String>,
+}
+
+// One "key: value" per line, warnings each on their own line.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            Some((major, minor)) => writeln!(f, "version: {}.{}", major, minor)?,
+            None => writeln!(f, "version: none")?,
+        }
+        writeln!(f, "pages: {}", self.pages)?;
+        writeln!(f, "objects: {}", self.objects)?;
+        writeln!(f, "encrypted: {}", self.encrypted)?;
+        writeln!(f, "form: {}", self.has_form)?;
+        writeln!(f, "javascript: {}", self.has_javascript)?;
+        writeln!(f, "attachments: {}", self.has_attachments)?;
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl Summary {
+    // Reads back what Display wrote. Unknown keys are skipped so older
+    // callers can read newer summaries.
+    pub fn parse(text: &str) -> Option<Summary> {
+        let mut summary = Summary::default();
+        let mut seen_pages = false;
+        for line in text.lines() {
+            let (key, value) = match line.split_once(": ") {
+                Some(entry) => entry,
+                None => continue,
+            };
+            match key {
+                "version" => {
+                    summary.version = match value.split_once('.') {
+                        Some((major, minor)) => Some((major.parse().ok()?, minor.parse().ok()?)),
+                        None => None,
+                    };
+                },
+                "pages" => {
+                    summary.pages = value.parse().ok()?;
+                    seen_pages = true;
+                },
+                "objects" => summary.objects = value.parse().ok()?,
+                "encrypted" => summary.encrypted = value.parse().ok()?,
+                "form" => summary.has_form = value.parse().ok()?,
+                "javascript" => summary.has_javascript = value.parse().ok()?,
+                "attachments" => summary.has_attachments = value.parse().ok()?,
+                "warning" => summary.warnings.push(value.to_string()),
+                _ => (),
+            }
+        }
+        // anything without a page count isn't a summary
+        if seen_pages {
+            Some(summary)
+        } else {
+            None
+        }
+    }
+}
+
+// Summarizes the file in this process, a panic anywhere in the parser
+// comes back as PdfError::Sandbox.
+pub fn summarize(path: &Path, options: Options) -> Result<Summary> {
+    guarded(|| summarize_unguarded(path, options))
+}
+
+fn guarded<T, F: FnOnce() -> Result<T>>(parse: F) -> Result<T> {
+    match panic::catch_unwind(AssertUnwindSafe(parse)) {
+        Ok(summary) => summary,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(PdfError::Sandbox(format!("the parser panicked: {}", message)))
+        },
+    }
+}
+
+fn summarize_unguarded(path: &Path, options: Options) -> Result<Summary> {
+    let mut header = Vec::new();
+    File::open(path)?.take(8).read_to_end(&mut header)?;
+    let mut doc = Document::open(path, options)?;
+
+    let catalog = doc.trailer_entry("Root")?.map(|(_, catalog)| catalog).unwrap_or(DictNode::Null);
+    let names = catalog.get_resolved(&mut doc, "Names")?.unwrap_or(DictNode::Null);
+    let open_action = catalog.get_resolved(&mut doc, "OpenAction")?.unwrap_or(DictNode::Null);
+    let form = catalog.get_resolved(&mut doc, "AcroForm")?.unwrap_or(DictNode::Null);
+    let fields = form.get_resolved(&mut doc, "Fields")?.unwrap_or(DictNode::Null);
+    Ok(Summary {
+        version: transcode::header_version(&header),
+        pages: pages::pages(&mut doc)?.len(),
+        objects: doc.xref.entries().filter(|&(_, entry)| !entry.is_free).count(),
+        encrypted: doc.trailer.get("Encrypt").is_some(),
+        has_form: fields.as_array().is_some_and(|fields| !fields.is_empty()),
+        has_javascript: names.get("JavaScript").is_some()
+            || open_action.get("S").is_some_and(|action| action.is_name("JavaScript")),
+        has_attachments: names.get("EmbeddedFiles").is_some(),
+        warnings: doc.warnings().iter().map(|warning| format!("{:?}", warning)).collect(),
+    })
+}
+
+pub struct Sandbox {
+    // the nails-pdf binary
+    pub program: PathBuf,
+    // passed to `summary` before the file, e.g. "--lenient"
+    pub args: Vec<String>,
+    pub timeout: Duration,
+    // bytes of address space the child gets, None for no limit
+    pub memory_limit: Option<u64>,
+}
+
+impl Sandbox {
+    pub fn new<P: Into<PathBuf>>(program: P) -> Sandbox {
+        Sandbox { program: program.into(), args: Vec::new(), timeout: DEFAULT_TIMEOUT, memory_limit: Some(DEFAULT_MEMORY_LIMIT) }
+    }
+
+    pub fn summarize(&self, path: &Path) -> Result<Summary> {
+        let mut command = match self.memory_limit {
+            Some(limit) if cfg!(unix) => {
+                // the shell sets the limit, then becomes the child
+                let mut command = Command::new("/bin/sh");
+                command.arg("-c").arg(format!("ulimit -v {} && exec \"$0\" \"$@\"", limit / 1024)).arg(&self.program);
+                command
+            },
+            _ => Command::new(&self.program),
+        };
+        let mut child = command
+            .arg("summary")
+            .args(&self.args)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // read on the side, a child stuck writing to a full pipe would
+        // otherwise look like it's taking forever
+        let stdout = child.stdout.take();
+        let reader = thread::spawn(move || -> Vec<u8> {
+            let mut output = Vec::new();
+            if let Some(stdout) = stdout {
+                let _ = stdout.take(MAX_OUTPUT).read_to_end(&mut output);
+            }
+            output
+        });
+
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(PdfError::Sandbox(format!("no summary after {:?}, gave up", self.timeout)));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let output = reader.join().unwrap_or_default();
+        let output = String::from_utf8_lossy(&output);
+        if !status.success() {
+            // the child prints why it couldn't read the file when it can
+            let reason = output.lines().last().unwrap_or("").trim().to_string();
+            return Err(match status.code() {
+                Some(code) if !reason.is_empty() => PdfError::Sandbox(format!("exited with {}: {}", code, reason)),
+                Some(code) => PdfError::Sandbox(format!("exited with {}", code)),
+                None => PdfError::Sandbox("killed by a signal".to_string()),
+            });
+        }
+        Summary::parse(&output).ok_or_else(|| PdfError::Sandbox("the summary couldn't be read".to_string()))
+    }
+}
+
+#[test]
+fn test_summary_round_trip() {
+    let summary = Summary {
+        version: Some((1, 7)),
+        pages: 12,
+        objects: 340,
+        encrypted: false,
+        has_form: true,
+        has_javascript: true,
+        has_attachments: false,
+        warnings: vec!["TruncatedObject { offset: 1200 }".to_string(), "TruncatedDocument".to_string()],
+    };
+    let text = summary.to_string();
+    assert!(text.starts_with("version: 1.7\npages: 12\n"));
+    assert_eq!(Summary::parse(&text), Some(summary));
+    assert_eq!(Summary::parse("version: none\npages: 0\nfuture: 1\n"), Some(Summary::default()));
+    assert_eq!(Summary::parse("couldn't read file.pdf: I/O error"), None);
+}
+
+#[test]
+fn test_summarize_missing_file() {
+    assert!(summarize(Path::new("/nonexistent/file.pdf"), Options::default()).is_err());
+}
+
+#[test]
+fn test_panics() {
+    let err = guarded::<(), _>(|| panic!("bad object")).unwrap_err();
+    assert_eq!(err.to_string(), "sandboxed parse failed: the parser panicked: bad object");
+    let err = guarded::<(), _>(|| panic!("bad object {}", 12)).unwrap_err();
+    assert_eq!(err.to_string(), "sandboxed parse failed: the parser panicked: bad object 12");
+    assert_eq!(guarded(|| Ok(1)).unwrap(), 1);
+}
+
+// A shell script standing in for the nails-pdf binary, next to `pdf`.
+#[cfg(all(test, unix))]
+fn stand_in(pdf: &Path, script: &str) -> ::fixtures::FixtureFile {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let program = ::fixtures::FixtureFile { path: pdf.with_extension("sh") };
+    fs::write(&program.path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&program.path, fs::Permissions::from_mode(0o755)).unwrap();
+    program
+}
+
+#[cfg(unix)]
+#[test]
+fn test_child() {
+    use fixtures::Fixture;
+
+    let file = Fixture::Simple.write();
+    let summarize = |script: &str, timeout: Duration| {
+        let program = stand_in(&file.path, script);
+        Sandbox { timeout, ..Sandbox::new(&program.path) }.summarize(&file.path)
+    };
+
+    // the summary comes back from a child running under the memory limit
+    let summary = summarize("echo \"pages: $(ulimit -v)\"", DEFAULT_TIMEOUT).unwrap();
+    assert_eq!(summary.pages as u64, DEFAULT_MEMORY_LIMIT / 1024);
+
+    // one that hangs is killed once its time is up
+    let start = Instant::now();
+    let err = summarize("exec sleep 10", Duration::from_millis(200)).unwrap_err();
+    assert_eq!(err.to_string(), "sandboxed parse failed: no summary after 200ms, gave up");
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // one that crashes or fails only takes itself down
+    let err = summarize("kill -ABRT $$", DEFAULT_TIMEOUT).unwrap_err();
+    assert_eq!(err.to_string(), "sandboxed parse failed: killed by a signal");
+    let err = summarize("echo \"couldn't read it: bad xref\"; exit 1", DEFAULT_TIMEOUT).unwrap_err();
+    assert_eq!(err.to_string(), "sandboxed parse failed: exited with 1: couldn't read it: bad xref");
+}
@@ -0,0 +1,337 @@
+use info::PdfDate;
+
+/*
+ * Just enough of CMS SignedData (RFC 5652, PKCS#7 as was) to tell who
+ * signed a PDF signature, when, and what digest they signed.
+ *
+ *   let signed = cms::parse(&contents)?;
+ *   signed.message_digest == Some(digest)
+ *
+ * The blob is DER, tag, length and content all the way down, though some
+ * signing software writes BER's indefinite lengths (and octet strings in
+ * chunks), those are read too. Nothing is verified here: certificates are
+ * only looked at for the signer's name, and the signature over the signed
+ * attributes is left alone.
+ */
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const CHUNKED_OCTET_STRING: u8 = 0x24;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const BMP_STRING: u8 = 0x1e;
+// context specific tags: [0] constructed and primitive, [3] constructed
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_0_PRIMITIVE: u8 = 0x80;
+const CONTEXT_3: u8 = 0xa3;
+
+const SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const MESSAGE_DIGEST: &str = "1.2.840.113549.1.9.4";
+const SIGNING_TIME: &str = "1.2.840.113549.1.9.5";
+const COMMON_NAME: &str = "2.5.4.3";
+const SUBJECT_KEY_IDENTIFIER: &str = "2.5.29.14";
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SignedData {
+    // the signer's digest algorithm, as a dotted OID
+    pub digest_algorithm: Option<String>,
+    // the messageDigest signed attribute
+    pub message_digest: Option<Vec<u8>>,
+    // the encapsulated content, absent for detached signatures
+    pub content: Option<Vec<u8>>,
+    // the signingTime signed attribute
+    pub signing_time: Option<PdfDate>,
+    // the common name of the signer's certificate
+    pub signer: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Element<'a> {
+    tag: u8,
+    content: &'a [u8],
+}
+
+impl<'a> Element<'a> {
+    fn children(&self) -> Vec<Element<'a>> {
+        let mut children = Vec::new();
+        let mut rest = self.content;
+        while let Some((child, tail)) = element(rest) {
+            children.push(child);
+            rest = tail;
+        }
+        children
+    }
+
+    fn child(&self, index: usize) -> Option<Element<'a>> {
+        self.children().get(index).cloned()
+    }
+
+    fn octets(&self) -> Vec<u8> {
+        match self.tag {
+            CHUNKED_OCTET_STRING => self.children().iter().flat_map(Element::octets).collect(),
+            _ => self.content.to_vec(),
+        }
+    }
+
+    fn oid(&self) -> Option<String> {
+        if self.tag != OBJECT_IDENTIFIER {
+            return None;
+        }
+        let (&first, rest) = self.content.split_first()?;
+        let mut parts = vec![(first / 40).min(2) as u64, (first - (first / 40).min(2) * 40) as u64];
+        let mut value = 0u64;
+        for &byte in rest {
+            value = value.checked_mul(128)? | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                parts.push(value);
+                value = 0;
+            }
+        }
+        Some(parts.iter().map(u64::to_string).collect::<Vec<_>>().join("."))
+    }
+}
+
+// The element at the start of `data`, and what follows it.
+fn element(data: &[u8]) -> Option<(Element<'_>, &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    // high tag numbers, nothing in CMS uses them
+    if tag & 0x1f == 0x1f {
+        return None;
+    }
+    let (&first, rest) = rest.split_first()?;
+    if first == 0x80 {
+        // an indefinite length, constructed elements only: the children up
+        // to an end of contents marker
+        if tag & 0x20 == 0 {
+            return None;
+        }
+        let mut tail = rest;
+        while !tail.starts_with(&[0, 0]) {
+            tail = element(tail)?.1;
+        }
+        let length = rest.len() - tail.len();
+        return Some((Element { tag, content: &rest[..length] }, &tail[2..]));
+    }
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        _ => {
+            let count = (first & 0x7f) as usize;
+            if count > 4 || rest.len() < count {
+                return None;
+            }
+            (rest[..count].iter().fold(0, |length, &byte| length << 8 | byte as usize), &rest[count..])
+        },
+    };
+    if rest.len() < length {
+        return None;
+    }
+    Some((Element { tag, content: &rest[..length] }, &rest[length..]))
+}
+
+// Reads a ContentInfo holding SignedData, and its first signer. None if it
+// isn't one.
+pub fn parse(data: &[u8]) -> Option<SignedData> {
+    let (info, _) = element(data)?;
+    if info.tag != SEQUENCE || info.child(0)?.oid()? != SIGNED_DATA {
+        return None;
+    }
+    let signed = info.child(1).filter(|wrapper| wrapper.tag == CONTEXT_0)?.child(0)?;
+    let parts = signed.children();
+    let mut data = SignedData::default();
+    if let Some(encapsulated) = parts.get(2) {
+        data.content = encapsulated.child(1).filter(|wrapper| wrapper.tag == CONTEXT_0)
+            .and_then(|wrapper| wrapper.child(0))
+            .map(|content| content.octets());
+    }
+    let certificates = parts.iter().find(|part| part.tag == CONTEXT_0).map(Element::children).unwrap_or_default();
+
+    let signer = parts.iter().rev().find(|part| part.tag == SET)?.child(0)?;
+    let fields = signer.children();
+    data.digest_algorithm = fields.get(2).and_then(|algorithm| algorithm.child(0)).and_then(|oid| oid.oid());
+    if let Some(attributes) = fields.iter().find(|field| field.tag == CONTEXT_0) {
+        for attribute in attributes.children() {
+            let value = match attribute.child(1).and_then(|values| values.child(0)) {
+                Some(value) => value,
+                None => continue,
+            };
+            match attribute.child(0).and_then(|oid| oid.oid()).as_deref() {
+                Some(MESSAGE_DIGEST) if value.tag == OCTET_STRING => data.message_digest = Some(value.content.to_vec()),
+                Some(SIGNING_TIME) => data.signing_time = time(&value),
+                _ => {},
+            }
+        }
+    }
+    data.signer = fields.get(1)
+        .and_then(|id| certificates.iter().find(|certificate| identifies(id, certificate)))
+        .and_then(|certificate| common_name(certificate));
+    Some(data)
+}
+
+// Whether the signer identifier `id` (issuer and serial number, or a
+// subject key identifier) is that of `certificate`.
+fn identifies(id: &Element, certificate: &Element) -> bool {
+    let fields = match certificate.child(0) {
+        Some(tbs) => tbs.children(),
+        None => return false,
+    };
+    // the version is optional, the serial number comes first without it
+    let fields = match fields.first() {
+        Some(version) if version.tag == CONTEXT_0 => &fields[1..],
+        _ => &fields[..],
+    };
+    match id.tag {
+        SEQUENCE => match (id.child(0), id.child(1), fields.first(), fields.get(2)) {
+            (Some(issuer), Some(serial), Some(their_serial), Some(their_issuer)) => {
+                serial.tag == INTEGER && serial.content == their_serial.content && issuer.content == their_issuer.content
+            },
+            _ => false,
+        },
+        CONTEXT_0_PRIMITIVE => {
+            let extensions = fields.iter().find(|field| field.tag == CONTEXT_3).and_then(|wrapper| wrapper.child(0));
+            extensions.map(|extensions| extensions.children()).unwrap_or_default().iter().any(|extension| {
+                extension.child(0).and_then(|oid| oid.oid()).as_deref() == Some(SUBJECT_KEY_IDENTIFIER)
+                    && extension.children().last().and_then(|value| element(value.content))
+                        .is_some_and(|(key, _)| key.content == id.content)
+            })
+        },
+        _ => false,
+    }
+}
+
+// The CN of a certificate's subject.
+fn common_name(certificate: &Element) -> Option<String> {
+    let fields = certificate.child(0)?.children();
+    let offset = if fields.first()?.tag == CONTEXT_0 { 1 } else { 0 };
+    let subject = fields.get(offset + 4)?;
+    for names in subject.children() {
+        for name in names.children() {
+            if name.child(0).and_then(|oid| oid.oid()).as_deref() != Some(COMMON_NAME) {
+                continue;
+            }
+            let value = name.child(1)?;
+            return Some(match value.tag {
+                BMP_STRING => {
+                    let units: Vec<u16> = value.content.chunks(2).filter(|pair| pair.len() == 2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect();
+                    String::from_utf16_lossy(&units)
+                },
+                _ => String::from_utf8_lossy(value.content).into_owned(),
+            });
+        }
+    }
+    None
+}
+
+// A UTCTime (two digit years, 1950 to 2049) or GeneralizedTime.
+fn time(value: &Element) -> Option<PdfDate> {
+    let text = ::std::str::from_utf8(value.content).ok()?;
+    match value.tag {
+        UTC_TIME => {
+            let century = if text.get(..2)? >= "50" { "19" } else { "20" };
+            PdfDate::parse(&format!("{}{}", century, text))
+        },
+        GENERALIZED_TIME => PdfDate::parse(text),
+        _ => None,
+    }
+}
+
+// DER for a few test structures.
+#[cfg(test)]
+pub(crate) fn encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        length @ 0..=0x7f => out.push(length as u8),
+        length @ 0x80..=0xff => out.extend_from_slice(&[0x81, length as u8]),
+        length => out.extend_from_slice(&[0x82, (length >> 8) as u8, length as u8]),
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+#[cfg(test)]
+pub(crate) fn encode_oid(oid: &str) -> Vec<u8> {
+    let parts: Vec<u64> = oid.split('.').map(|part| part.parse().unwrap()).collect();
+    let mut content = vec![(parts[0] * 40 + parts[1]) as u8];
+    for &part in &parts[2..] {
+        let mut bytes = vec![(part & 0x7f) as u8];
+        let mut rest = part >> 7;
+        while rest > 0 {
+            bytes.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        bytes.reverse();
+        content.extend(bytes);
+    }
+    encode(OBJECT_IDENTIFIER, &content)
+}
+
+// A SignedData blob signed by "Jane Doe", with a SHA-256 messageDigest of
+// `digest`, the way PDF signing software writes them (the signature itself
+// is made up).
+#[cfg(test)]
+pub(crate) fn test_signed_data(digest: &[u8]) -> Vec<u8> {
+    let sequence = |parts: &[Vec<u8>]| encode(SEQUENCE, &parts.concat());
+    let set = |parts: &[Vec<u8>]| encode(SET, &parts.concat());
+    let sha256 = sequence(&[encode_oid("2.16.840.1.101.3.4.2.1"), vec![0x05, 0x00]]);
+    let name = |cn: &str| sequence(&[set(&[sequence(&[encode_oid(COMMON_NAME), encode(0x0c, cn.as_bytes())])])]);
+    let serial = encode(INTEGER, &[0x12, 0x34]);
+    let tbs = sequence(&[
+        encode(CONTEXT_0, &encode(INTEGER, &[2])),
+        serial.clone(),
+        sequence(&[encode_oid("1.2.840.113549.1.1.11")]),
+        name("Test CA"),
+        sequence(&[encode(UTC_TIME, b"240101000000Z"), encode(UTC_TIME, b"340101000000Z")]),
+        name("Jane Doe"),
+    ]);
+    let certificate = sequence(&[tbs, sequence(&[encode_oid("1.2.840.113549.1.1.11")]), encode(0x03, &[0, 1, 2])]);
+    let attributes = [
+        sequence(&[encode_oid("1.2.840.113549.1.9.3"), set(&[encode_oid("1.2.840.113549.1.7.1")])]),
+        sequence(&[encode_oid(SIGNING_TIME), set(&[encode(UTC_TIME, b"240315093000Z")])]),
+        sequence(&[encode_oid(MESSAGE_DIGEST), set(&[encode(OCTET_STRING, digest)])]),
+    ];
+    let signer = sequence(&[
+        encode(INTEGER, &[1]),
+        sequence(&[name("Test CA"), serial]),
+        sha256.clone(),
+        encode(CONTEXT_0, &attributes.concat()),
+        sequence(&[encode_oid("1.2.840.113549.1.1.1")]),
+        encode(OCTET_STRING, &[0xaa; 16]),
+    ]);
+    let signed = sequence(&[
+        encode(INTEGER, &[1]),
+        set(&[sha256]),
+        sequence(&[encode_oid("1.2.840.113549.1.7.1")]),
+        encode(CONTEXT_0, &certificate),
+        set(&[signer]),
+    ]);
+    sequence(&[encode_oid(SIGNED_DATA), encode(CONTEXT_0, &signed)])
+}
+
+#[test]
+fn test_cms() {
+    let encoded = encode_oid("1.2.840.113549.1.9.4");
+    assert_eq!(element(&encoded).unwrap().0.oid().unwrap(), "1.2.840.113549.1.9.4");
+
+    let signed = parse(&test_signed_data(&[7; 32])).unwrap();
+    assert_eq!(signed.digest_algorithm.as_deref(), Some("2.16.840.1.101.3.4.2.1"));
+    assert_eq!(signed.message_digest, Some(vec![7; 32]));
+    assert_eq!(signed.signer.as_deref(), Some("Jane Doe"));
+    assert_eq!(signed.signing_time.unwrap().to_string(), "2024-03-15T09:30:00Z");
+    assert_eq!(signed.content, None);
+
+    // BER: indefinite lengths, and a chunked octet string
+    let chunks = [encode(OCTET_STRING, b"ab"), encode(OCTET_STRING, b"c")].concat();
+    let mut ber = vec![CHUNKED_OCTET_STRING, 0x80];
+    ber.extend(chunks);
+    ber.extend_from_slice(&[0, 0, 0x05, 0x00]);
+    let (chunked, rest) = element(&ber).unwrap();
+    assert_eq!(chunked.octets(), b"abc");
+    assert_eq!(rest, [0x05, 0x00]);
+
+    assert_eq!(parse(b"not DER"), None);
+    assert_eq!(parse(&encode(SEQUENCE, &encode_oid("1.2.840.113549.1.7.1"))), None);
+}
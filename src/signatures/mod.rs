@@ -0,0 +1,249 @@
+use std::fmt;
+
+use crypt::digest::{md5, sha1, sha256, sha384, sha512};
+use document::Document;
+use error::Result;
+use filters;
+use forms::fields;
+use info::PdfDate;
+use parsers::cos::DictNode;
+
+pub mod cms;
+
+/*
+ * The signature fields of a document, and whether each signature's digest
+ * matches the bytes it covers.
+ *
+ *   for field in signatures::signatures(&mut doc)? {
+ *       if let Some(signature) = field.signature { ... }
+ *   }
+ *
+ * A signed field's /V is a signature dictionary. Its /ByteRange lists the
+ * byte ranges that were signed, [offset length offset length]: the whole
+ * file as of signing except for /Contents, the hex string in between that
+ * holds the signature. For the usual /SubFilters (adbe.pkcs7.detached,
+ * ETSI.CAdES.detached, adbe.pkcs7.sha1) that's a CMS SignedData blob, see
+ * the cms module.
+ *
+ * The digest of the covered bytes is compared with the one that was
+ * signed: the messageDigest signed attribute, or, for adbe.pkcs7.sha1
+ * signatures without signed attributes, the SHA-1 digest in the
+ * encapsulated content. A match says the covered bytes are the ones the
+ * signer's software digested, nothing more: the signature over that
+ * digest and the signer's certificate chain aren't checked, so the name
+ * reported is the one the blob claims. Bytes appended after signing,
+ * incremental updates, aren't covered either, `covers_file` tells.
+ *
+ * /Contents is read from the file between the ranges rather than through
+ * the parser, which would try to decrypt it in encrypted documents (it's
+ * the one string that isn't encrypted).
+ */
+
+// The digest algorithms signers use, by OID.
+const DIGESTS: &[(&str, &str)] = &[
+    ("1.2.840.113549.2.5", "MD5"),
+    ("1.3.14.3.2.26", "SHA-1"),
+    ("2.16.840.1.101.3.4.2.1", "SHA-256"),
+    ("2.16.840.1.101.3.4.2.2", "SHA-384"),
+    ("2.16.840.1.101.3.4.2.3", "SHA-512"),
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SignatureField {
+    // fully qualified, see forms::fields
+    pub name: String,
+    // None for fields nobody has signed yet
+    pub signature: Option<Signature>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Signature {
+    pub sub_filter: Option<String>,
+    // (offset, length) of each range that was signed
+    pub byte_range: Vec<(u64, u64)>,
+    // whether the ranges reach the end of the file, i.e. nothing was
+    // appended after signing
+    pub covers_file: bool,
+    // the common name of the signer's certificate, else /Name
+    pub signer: Option<String>,
+    // the signingTime signed attribute, else /M
+    pub signing_time: Option<PdfDate>,
+    pub digest_algorithm: Option<&'static str>,
+    // None when there's no digest to compare with, the /Contents can't be
+    // read, or the ranges don't fit the file
+    pub digest_matches: Option<bool>,
+}
+
+// "Jane Doe, 2024-03-15T09:30:00Z, SHA-256 digest matches", the parts that
+// are known.
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        parts.push(self.signer.clone().unwrap_or_else(|| "unknown signer".to_string()));
+        if let Some(time) = self.signing_time {
+            parts.push(time.to_string());
+        }
+        let algorithm = self.digest_algorithm.unwrap_or("unknown");
+        parts.push(match self.digest_matches {
+            Some(true) => format!("{} digest matches", algorithm),
+            Some(false) => format!("{} digest DOESN'T match", algorithm),
+            None => "digest not checked".to_string(),
+        });
+        if !self.covers_file {
+            parts.push("changed since".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+// Every signature field, in /Fields order.
+pub fn signatures(doc: &mut Document) -> Result<Vec<SignatureField>> {
+    let mut found = Vec::new();
+    let mut data = None;
+    for field in fields::fields(doc)? {
+        if !field.get("FT").is_some_and(|kind| kind.is_name("Sig")) {
+            continue;
+        }
+        let signature = match field.get("V") {
+            Some(value) => match doc.resolve(value)? {
+                value @ DictNode::Dict(_) => {
+                    if data.is_none() {
+                        data = Some(doc.file_data()?);
+                    }
+                    Some(signature(&value, data.as_deref().unwrap_or_default()))
+                },
+                _ => None,
+            },
+            None => None,
+        };
+        found.push(SignatureField { name: field.name, signature });
+    }
+    Ok(found)
+}
+
+// Reads the signature dictionary `dict` of a file holding `data`.
+fn signature(dict: &DictNode, data: &[u8]) -> Signature {
+    let numbers: Vec<u64> = dict.get("ByteRange").and_then(DictNode::as_array).unwrap_or(&[]).iter()
+        .filter_map(DictNode::as_int)
+        .filter(|&number| number >= 0)
+        .map(|number| number as u64)
+        .collect();
+    let byte_range: Vec<(u64, u64)> = numbers.chunks(2).filter(|pair| pair.len() == 2).map(|pair| (pair[0], pair[1])).collect();
+    let covered = covered_bytes(&byte_range, data);
+    let covers_file = covered.is_some() && byte_range.last().is_some_and(|&(offset, length)| offset + length == data.len() as u64);
+
+    let contents = gap(&byte_range, data)
+        .or_else(|| dict.get("Contents").and_then(DictNode::as_string).map(<[u8]>::to_vec));
+    let signed = contents.as_ref().and_then(|contents| cms::parse(contents));
+    let sub_filter = dict.get("SubFilter").and_then(DictNode::as_name).map(str::to_string);
+
+    // what the covered bytes' digest should be, adbe.pkcs7.sha1 puts it
+    // in the content when there are no signed attributes
+    let (digest_algorithm, expected) = match signed {
+        Some(ref signed) if signed.message_digest.is_none() && sub_filter.as_deref() == Some("adbe.pkcs7.sha1") => {
+            (Some("SHA-1"), signed.content.clone())
+        },
+        Some(ref signed) => {
+            let algorithm = signed.digest_algorithm.as_ref()
+                .and_then(|oid| DIGESTS.iter().find(|&&(known, _)| known == oid))
+                .map(|&(_, name)| name);
+            (algorithm, signed.message_digest.clone())
+        },
+        None => (None, None),
+    };
+    let digest_matches = match (digest_algorithm, expected, covered) {
+        (Some(algorithm), Some(expected), Some(covered)) => Some(digest(algorithm, &covered) == expected),
+        _ => None,
+    };
+
+    let text = |key| dict.get(key).and_then(fields::text);
+    Signature {
+        digest_algorithm,
+        signer: signed.as_ref().and_then(|signed| signed.signer.clone()).or_else(|| text("Name")),
+        signing_time: signed.as_ref().and_then(|signed| signed.signing_time).or_else(|| text("M").and_then(|m| PdfDate::parse(&m))),
+        sub_filter,
+        byte_range,
+        covers_file,
+        digest_matches,
+    }
+}
+
+// The bytes the ranges cover, None if one of them is past the end of the
+// file or there are none.
+fn covered_bytes(byte_range: &[(u64, u64)], data: &[u8]) -> Option<Vec<u8>> {
+    if byte_range.is_empty() {
+        return None;
+    }
+    let mut covered = Vec::new();
+    for &(offset, length) in byte_range {
+        let end = offset.checked_add(length).filter(|&end| end <= data.len() as u64)?;
+        covered.extend_from_slice(&data[offset as usize..end as usize]);
+    }
+    Some(covered)
+}
+
+// The hex string between the first two ranges, decoded.
+fn gap(byte_range: &[(u64, u64)], data: &[u8]) -> Option<Vec<u8>> {
+    let (start, end) = match *byte_range {
+        [(offset, length), (next, _), ..] => (offset.checked_add(length)?, next),
+        _ => return None,
+    };
+    let hex = data.get(start as usize..end as usize)?;
+    let hex = hex.strip_prefix(b"<")?;
+    filters::ascii_hex_decode(hex).ok()
+}
+
+fn digest(algorithm: &str, data: &[u8]) -> Vec<u8> {
+    match algorithm {
+        "MD5" => md5(data).to_vec(),
+        "SHA-1" => sha1(data).to_vec(),
+        "SHA-384" => sha384(data).to_vec(),
+        "SHA-512" => sha512(data).to_vec(),
+        _ => sha256(data).to_vec(),
+    }
+}
+
+#[test]
+fn test_signature() {
+    use std::collections::HashMap;
+
+    // a made up file, signed everywhere but the <...> placeholder
+    let placeholder = |contents: &[u8]| {
+        let hex: String = contents.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("<{:0<2048}>", hex)
+    };
+    let file = |contents: &[u8], tail: &str| {
+        format!("%PDF-1.7\n1 0 obj << /Contents {} >> endobj\n%%EOF\n{}", placeholder(contents), tail).into_bytes()
+    };
+    let start = b"%PDF-1.7\n1 0 obj << /Contents ".len() as i64;
+    let signed_length = file(&[], "").len() as i64;
+    let dict = |sub_filter: &str| DictNode::Dict(HashMap::from([
+        ("SubFilter".to_string(), DictNode::name(sub_filter)),
+        ("ByteRange".to_string(), DictNode::Array([0, start, start + 2050, signed_length - start - 2050].iter().map(|&n| DictNode::Int(n)).collect())),
+        ("Name".to_string(), DictNode::LiteralString(b"J. Doe".to_vec())),
+        ("M".to_string(), DictNode::LiteralString(b"D:20240316120000Z".to_vec())),
+    ]));
+    let covered = |data: &[u8]| [&data[..start as usize], &data[start as usize + 2050..]].concat();
+
+    let blob = cms::test_signed_data(&sha256(&covered(&file(&[], ""))));
+    let data = file(&blob, "");
+    let signed = signature(&dict("adbe.pkcs7.detached"), &data);
+    assert_eq!(signed.byte_range.len(), 2);
+    assert!(signed.covers_file);
+    assert_eq!(signed.to_string(), "Jane Doe, 2024-03-15T09:30:00Z, SHA-256 digest matches");
+
+    // a byte changed in the signed part
+    let mut tampered = data.clone();
+    tampered[3] = b'X';
+    assert_eq!(signature(&dict("adbe.pkcs7.detached"), &tampered).digest_matches, Some(false));
+
+    // something appended after signing: still the same digest, but the
+    // signature doesn't cover it
+    let appended = signature(&dict("adbe.pkcs7.detached"), &file(&blob, "2 0 obj null endobj\n"));
+    assert_eq!(appended.to_string(), "Jane Doe, 2024-03-15T09:30:00Z, SHA-256 digest matches, changed since");
+
+    // no CMS to go by, the dictionary has the name and date
+    let unreadable = signature(&dict("adbe.x509.rsa_sha1"), &file(b"\x01\x02", ""));
+    assert_eq!(unreadable.to_string(), "J. Doe, 2024-03-16T12:00:00Z, digest not checked");
+    assert_eq!(unreadable.sub_filter.as_deref(), Some("adbe.x509.rsa_sha1"));
+}
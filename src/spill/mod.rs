@@ -0,0 +1,181 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+ * Memory bounded buffering for decoded streams.
+ *
+ * A few kilobytes of Flate data can legitimately decode to gigabytes (or
+ * maliciously, see zip bombs). A SpillBuffer keeps data in memory up to a
+ * threshold and then moves everything to a temporary file, so callers get
+ * the same Read + Seek interface either way and the process doesn't fall
+ * over on pathological files.
+ */
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A temporary file that is deleted when dropped.
+struct TempFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn new() -> io::Result<TempFile> {
+        loop {
+            let name = format!("nails-pdf-{}-{}.tmp", process::id(), TEMP_COUNTER.fetch_add(1, Ordering::SeqCst));
+            let path = env::temp_dir().join(name);
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(TempFile { file, path }),
+                // left over from a previous process with the same pid
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+enum Storage {
+    Memory(Cursor<Vec<u8>>),
+    Disk(TempFile),
+}
+
+pub struct SpillBuffer {
+    storage: Storage,
+    threshold: Option<usize>,
+    len: usize,
+}
+
+impl SpillBuffer {
+    // `threshold` is how many bytes to keep in memory before moving to
+    // disk, None to never spill.
+    pub fn new(threshold: Option<usize>) -> SpillBuffer {
+        SpillBuffer { storage: Storage::Memory(Cursor::new(Vec::new())), threshold, len: 0 }
+    }
+
+    // Copies all of `reader` into a new buffer, rewound and ready to read.
+    pub fn from_reader<R: Read>(reader: &mut R, threshold: Option<usize>) -> io::Result<SpillBuffer> {
+        let mut buffer = SpillBuffer::new(threshold);
+        io::copy(reader, &mut buffer)?;
+        buffer.seek(SeekFrom::Start(0))?;
+        Ok(buffer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.storage, Storage::Disk(_))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut temp = TempFile::new()?;
+        if let Storage::Memory(ref cursor) = self.storage {
+            temp.file.write_all(cursor.get_ref())?;
+            temp.file.seek(SeekFrom::Start(cursor.position()))?;
+        }
+        self.storage = Storage::Disk(temp);
+        Ok(())
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let over = match (&self.storage, self.threshold) {
+            (Storage::Memory(cursor), Some(threshold)) => cursor.position() as usize + buf.len() > threshold,
+            _ => false,
+        };
+        if over {
+            self.spill()?;
+        }
+        let written = match self.storage {
+            Storage::Memory(ref mut cursor) => cursor.write(buf)?,
+            Storage::Disk(ref mut temp) => temp.file.write(buf)?,
+        };
+        let position = self.stream_position()? as usize;
+        self.len = self.len.max(position);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.storage {
+            Storage::Memory(_) => Ok(()),
+            Storage::Disk(ref mut temp) => temp.file.flush(),
+        }
+    }
+}
+
+impl Read for SpillBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.storage {
+            Storage::Memory(ref mut cursor) => cursor.read(buf),
+            Storage::Disk(ref mut temp) => temp.file.read(buf),
+        }
+    }
+}
+
+impl Seek for SpillBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.storage {
+            Storage::Memory(ref mut cursor) => cursor.seek(pos),
+            Storage::Disk(ref mut temp) => temp.file.seek(pos),
+        }
+    }
+}
+
+#[test]
+fn test_stays_in_memory() {
+    let mut buffer = SpillBuffer::from_reader(&mut &b"small stream"[..], Some(1024)).unwrap();
+    assert!(!buffer.is_spilled());
+    assert_eq!(buffer.len(), 12);
+    let mut data = String::new();
+    buffer.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "small stream");
+}
+
+#[test]
+fn test_spills_to_disk() {
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    let mut buffer = SpillBuffer::new(Some(4096));
+    for chunk in data.chunks(1000) {
+        buffer.write_all(chunk).unwrap();
+    }
+    assert!(buffer.is_spilled());
+    assert_eq!(buffer.len(), data.len());
+
+    let path = match buffer.storage {
+        Storage::Disk(ref temp) => temp.path.clone(),
+        _ => unreachable!(),
+    };
+    assert!(path.exists());
+
+    buffer.seek(SeekFrom::Start(0)).unwrap();
+    let mut read_back = Vec::new();
+    buffer.read_to_end(&mut read_back).unwrap();
+    assert_eq!(read_back, data);
+
+    drop(buffer);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_never_spills_without_threshold() {
+    let mut buffer = SpillBuffer::new(None);
+    buffer.write_all(&vec![0; 100000]).unwrap();
+    assert!(!buffer.is_spilled());
+}
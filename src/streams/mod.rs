@@ -0,0 +1,191 @@
+// held to the audit's no-panic rule, see src/audit
+#![cfg_attr(feature = "audit", deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable,
+                                    clippy::todo, clippy::unimplemented))]
+
+use inflate::InflateStream;
+use std::io;
+use std::io::{Cursor, Read};
+
+use error::Result;
+use filters;
+use parsers::cos::DictNode;
+
+/*
+ * Stream objects are a dictionary followed by a blob of bytes:
+ *
+ *   5 0 obj
+ *   << /Length 1234 /Filter /FlateDecode >>
+ *   stream
+ *   ...1234 bytes of zlib data...
+ *   endstream
+ *   endobj
+ *
+ * The dictionary tells us how the bytes are encoded. Page contents, fonts,
+ * images, pretty much everything heavy in a PDF lives in a stream.
+ */
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// The spec puts an end of line marker between the data and "endstream"
+// that isn't counted in /Length.
+pub fn strip_eol(data: &[u8]) -> &[u8] {
+    if data.ends_with(b"\r\n") {
+        &data[..data.len() - 2]
+    } else if data.ends_with(b"\n") || data.ends_with(b"\r") {
+        &data[..data.len() - 1]
+    } else {
+        data
+    }
+}
+
+// Everything up to "endstream", for streams whose /Length we don't know.
+pub fn raw_body(data: &[u8]) -> Option<&[u8]> {
+    find(data, b"endstream").map(|end| strip_eol(&data[..end]))
+}
+
+pub fn length(dict: &DictNode) -> Option<u64> {
+    match *dict {
+        DictNode::Dict(ref map) => match map.get("Length") {
+            Some(&DictNode::Int(length)) if length >= 0 => Some(length as u64),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Decodes a stream body according to its dictionary's /Filter.
+pub fn decode(dict: &DictNode, data: &[u8]) -> Result<Vec<u8>> {
+    filters::decode_chain(dict, data)
+}
+
+pub type StreamReader<'a> = Box<dyn Read + 'a>;
+
+// Like `decode`, but decodes lazily as the returned reader is read from,
+// so huge streams never have to sit in memory in one piece. Only plain
+// Flate is decoded incrementally, other filter chains are rare enough on
+// big streams that they're simply decoded up front.
+pub fn decoder<'a, R: Read + 'a>(dict: &DictNode, mut raw: R) -> Result<StreamReader<'a>> {
+    let chain = filters::chain(dict);
+    match chain.as_slice() {
+        [] => Ok(Box::new(raw)),
+        [(name, None)] if name == "FlateDecode" => Ok(Box::new(FlateReader::new(raw))),
+        _ => {
+            let mut data = Vec::new();
+            raw.read_to_end(&mut data)?;
+            Ok(Box::new(Cursor::new(decode(dict, &data)?)))
+        },
+    }
+}
+
+const READ_SIZE: usize = 16 * 1024;
+
+pub struct FlateReader<R> {
+    inner: R,
+    stream: InflateStream,
+    input: Vec<u8>,
+    input_pos: usize,
+    output: Vec<u8>,
+    output_pos: usize,
+    done: bool,
+}
+
+impl<R: Read> FlateReader<R> {
+    pub fn new(inner: R) -> FlateReader<R> {
+        FlateReader {
+            inner,
+            stream: InflateStream::from_zlib(),
+            input: Vec::new(),
+            input_pos: 0,
+            output: Vec::new(),
+            output_pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for FlateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output.len() {
+            if self.done {
+                return Ok(0);
+            }
+            if self.input_pos == self.input.len() {
+                self.input.resize(READ_SIZE, 0);
+                let read = self.inner.read(&mut self.input)?;
+                self.input.truncate(read);
+                self.input_pos = 0;
+                if read == 0 {
+                    self.done = true;
+                    return Ok(0);
+                }
+            }
+
+            let (consumed, output) = self.stream.update(&self.input[self.input_pos..])
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            self.input_pos += consumed;
+            self.output.clear();
+            self.output.extend_from_slice(output);
+            self.output_pos = 0;
+            if consumed == 0 && self.output.is_empty() {
+                // the deflate stream is over, anything left is trailing junk
+                self.done = true;
+            }
+        }
+
+        let available = &self.output[self.output_pos..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.output_pos += count;
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_raw_body() {
+    assert_eq!(raw_body(b"hello\r\nendstream\r\nendobj"), Some(&b"hello"[..]));
+    assert_eq!(raw_body(b"hello\nendstream"), Some(&b"hello"[..]));
+    assert_eq!(raw_body(b"hello"), None);
+}
+
+#[test]
+fn test_decode_flate() {
+    // zlib compressed "BT /F1 12 Tf ET"
+    let compressed = [
+        0x78, 0x9c, 0x73, 0x0a, 0x51, 0xd0, 0x77, 0x33, 0x54, 0x30, 0x34, 0x52,
+        0x08, 0x49, 0x53, 0x70, 0x0d, 0x01, 0x00, 0x1a, 0x85, 0x03, 0x73,
+    ];
+    let dict = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::name("FlateDecode"),
+    });
+    assert_eq!(decode(&dict, &compressed).unwrap(), b"BT /F1 12 Tf ET".to_vec());
+}
+
+#[test]
+fn test_streaming_decoder() {
+    let compressed = [
+        0x78, 0x9c, 0x73, 0x0a, 0x51, 0xd0, 0x77, 0x33, 0x54, 0x30, 0x34, 0x52,
+        0x08, 0x49, 0x53, 0x70, 0x0d, 0x01, 0x00, 0x1a, 0x85, 0x03, 0x73,
+    ];
+    let dict = DictNode::Dict(hashmap!{
+        "Filter".to_string() => DictNode::name("FlateDecode"),
+    });
+    let mut reader = decoder(&dict, &compressed[..]).unwrap();
+    let mut first = [0; 3];
+    reader.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"BT ");
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"/F1 12 Tf ET".to_vec());
+}
+
+#[test]
+fn test_decode_unfiltered() {
+    let dict = DictNode::Dict(hashmap!{ "Length".to_string() => DictNode::Int(5) });
+    assert_eq!(decode(&dict, b"hello").unwrap(), b"hello".to_vec());
+    assert_eq!(length(&dict), Some(5));
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/*
+ * How close extracted text is to a reference transcription, for measuring
+ * changes to the extraction heuristics on a corpus instead of eyeballing
+ * a few pages.
+ *
+ *   let mut total = Accuracy::default();
+ *   for (extracted, reference) in corpus {
+ *       total.add(&accuracy::compare(&extracted, &reference));
+ *   }
+ *   println!("{}", total);
+ *
+ * Three numbers, since extraction goes wrong in different ways:
+ *
+ *   - character accuracy, one less the edit distance between the texts
+ *     over the reference's length: wrong glyph mappings, lost spaces
+ *   - word accuracy, the same counting whole words
+ *   - bag of words, the F1 score of the words both have whatever their
+ *     order: the text is there even if columns came out interleaved
+ *
+ * Layout isn't what's being measured, so runs of whitespace count as a
+ * single space and line breaks are spaces too. Pages are compared one to
+ * one, split at form feeds, which is how the text command ends each page;
+ * a reference without form feeds is one long page. Edit distances take
+ * time in the product of the lengths, which is fine page by page.
+ *
+ * The counts are kept rather than the ratios, so a corpus adds up with
+ * long documents weighing more than short ones.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Accuracy {
+    // edits from the extracted text to the reference, and the reference's
+    // length, in characters
+    pub character_errors: usize,
+    pub characters: usize,
+    // the same in words
+    pub word_errors: usize,
+    pub words: usize,
+    // words both texts have, in any order, and how many the extracted text
+    // has
+    pub matched_words: usize,
+    pub extracted_words: usize,
+}
+
+impl Accuracy {
+    pub fn character_accuracy(&self) -> f64 {
+        ratio_left(self.character_errors, self.characters)
+    }
+
+    pub fn word_accuracy(&self) -> f64 {
+        ratio_left(self.word_errors, self.words)
+    }
+
+    pub fn bag_of_words(&self) -> f64 {
+        let total = self.words + self.extracted_words;
+        if total == 0 {
+            1.0
+        } else {
+            2.0 * self.matched_words as f64 / total as f64
+        }
+    }
+
+    pub fn add(&mut self, other: &Accuracy) {
+        self.character_errors += other.character_errors;
+        self.characters += other.characters;
+        self.word_errors += other.word_errors;
+        self.words += other.words;
+        self.matched_words += other.matched_words;
+        self.extracted_words += other.extracted_words;
+    }
+}
+
+// "characters 98.2%, words 95.0%, bag of words 97.1%"
+impl fmt::Display for Accuracy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "characters {:.1}%, words {:.1}%, bag of words {:.1}%",
+            self.character_accuracy() * 100.0, self.word_accuracy() * 100.0, self.bag_of_words() * 100.0)
+    }
+}
+
+// Compares `extracted` with `reference` page by page.
+pub fn compare(extracted: &str, reference: &str) -> Accuracy {
+    let extracted: Vec<&str> = extracted.trim_end_matches('\x0c').split('\x0c').collect();
+    let reference: Vec<&str> = reference.trim_end_matches('\x0c').split('\x0c').collect();
+    let mut accuracy = Accuracy::default();
+    for index in 0..extracted.len().max(reference.len()) {
+        // a page missing on either side is all errors
+        let page = compare_page(extracted.get(index).unwrap_or(&""), reference.get(index).unwrap_or(&""));
+        accuracy.add(&page);
+    }
+    accuracy
+}
+
+fn compare_page(extracted: &str, reference: &str) -> Accuracy {
+    let extracted_words: Vec<&str> = extracted.split_whitespace().collect();
+    let reference_words: Vec<&str> = reference.split_whitespace().collect();
+    let extracted_chars: Vec<char> = extracted_words.join(" ").chars().collect();
+    let reference_chars: Vec<char> = reference_words.join(" ").chars().collect();
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for &word in &reference_words {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    let mut matched_words = 0;
+    for word in &extracted_words {
+        if let Some(count) = counts.get_mut(word).filter(|count| **count > 0) {
+            *count -= 1;
+            matched_words += 1;
+        }
+    }
+
+    Accuracy {
+        character_errors: edit_distance(&extracted_chars, &reference_chars),
+        characters: reference_chars.len(),
+        word_errors: edit_distance(&extracted_words, &reference_words),
+        words: reference_words.len(),
+        matched_words,
+        extracted_words: extracted_words.len(),
+    }
+}
+
+// Levenshtein distance: insertions, deletions and substitutions.
+fn edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + if x == y { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// 1 - errors / total, not below 0; an empty reference is only matched by
+// an empty text.
+fn ratio_left(errors: usize, total: usize) -> f64 {
+    if total == 0 {
+        return if errors == 0 { 1.0 } else { 0.0 };
+    }
+    (1.0 - errors as f64 / total as f64).max(0.0)
+}
+
+#[test]
+fn test_edit_distance() {
+    let chars = |text: &str| text.chars().collect::<Vec<char>>();
+    assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+    assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+    assert_eq!(edit_distance(&chars("same"), &chars("same")), 0);
+}
+
+#[test]
+fn test_compare() {
+    // layout differences don't count
+    let accuracy = compare("Hello   world\n", "Hello world");
+    assert_eq!(accuracy.to_string(), "characters 100.0%, words 100.0%, bag of words 100.0%");
+
+    // a lost space: one character, but two words become one
+    let accuracy = compare("the quickbrown fox", "the quick brown fox");
+    assert_eq!((accuracy.character_errors, accuracy.characters), (1, 19));
+    assert_eq!((accuracy.word_errors, accuracy.words), (2, 4));
+    assert_eq!(accuracy.matched_words, 2);
+
+    // columns interleaved: every word is there, not in order
+    let accuracy = compare("one three two four", "one two three four");
+    assert_eq!(accuracy.word_errors, 2);
+    assert_eq!(accuracy.bag_of_words(), 1.0);
+
+    // pages compare one to one, a missing page is all errors
+    let accuracy = compare("first page\x0c", "first page\x0csecond page\x0c");
+    assert_eq!((accuracy.word_errors, accuracy.words), (2, 4));
+    assert_eq!(accuracy.bag_of_words(), 2.0 * 2.0 / 6.0);
+
+    let mut total = Accuracy::default();
+    total.add(&compare("a b", "a b"));
+    total.add(&compare("", "c d"));
+    assert_eq!(total.word_accuracy(), 0.5);
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use parsers::content::{Token, Tokenizer};
+use parsers::cos::DictNode;
+use text::encoding;
+
+/*
+ * ToUnicode CMaps, the part of a font that says what text its character
+ * codes stand for. They're little PostScript programs, of which only the
+ * mappings matter:
+ *
+ *   1 begincodespacerange <0000> <FFFF> endcodespacerange
+ *   2 beginbfchar
+ *   <0003> <0020>
+ *   <0011> <00660069>
+ *   endbfchar
+ *   1 beginbfrange
+ *   <0024> <003D> <0041>
+ *   endbfrange
+ *
+ * bfchar maps one code, bfrange a run of codes to a run of characters
+ * (counting up from the first) or to an array with one entry per code.
+ * Destinations are UTF-16BE, and can be more than one character, ligatures
+ * mostly. The code space ranges say how many bytes make up a code.
+ *
+ * The encoding CMaps of composite fonts are the same language, mapping
+ * codes to CIDs instead with cidchar and cidrange:
+ *
+ *   1 begincidrange
+ *   <0000> <00FF> 1
+ *   endcidrange
+ *
+ * which `cid` looks up. A file can carry both kinds of mappings, they're
+ * kept apart.
+ *
+ * The syntax is close enough to content streams for their tokenizer to read
+ * it. What doesn't tokenize ends the CMap, keeping what was read so far.
+ */
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CMap {
+    // (low, high) codes, both as many bytes long as the codes they cover
+    codespace: Vec<(Vec<u8>, Vec<u8>)>,
+    // keyed by (length in bytes, code)
+    chars: HashMap<(usize, u32), String>,
+    // (length, first code, last code, text of the first code), the text of
+    // the codes after it counting up from there
+    ranges: Vec<(usize, u32, u32, Vec<u16>)>,
+    // the same for CIDs, ranges as (length, first code, last code, CID of
+    // the first code)
+    cid_chars: HashMap<(usize, u32), u32>,
+    cid_ranges: Vec<(usize, u32, u32, u32)>,
+}
+
+impl CMap {
+    pub fn parse(data: &[u8]) -> CMap {
+        let mut cmap = CMap::default();
+        let mut operands = Vec::new();
+        for token in Tokenizer::new(data) {
+            let operator = match token {
+                Ok(Token::Operand(operand)) => {
+                    operands.push(operand);
+                    continue;
+                },
+                Ok(Token::Operator(operator)) => operator,
+                Ok(Token::InlineImage(..)) => continue,
+                Err(_) => break,
+            };
+            match operator.as_str() {
+                "endcodespacerange" => {
+                    for range in operands.chunks(2) {
+                        if let [DictNode::HexString(low), DictNode::HexString(high)] = range {
+                            if low.len() == high.len() && !low.is_empty() {
+                                cmap.codespace.push((low.clone(), high.clone()));
+                            }
+                        }
+                    }
+                },
+                "endbfchar" => {
+                    for pair in operands.chunks(2) {
+                        if let [DictNode::HexString(code), destination] = pair {
+                            if let (Some(code), Some(text)) = (code_value(code), destination_text(destination)) {
+                                cmap.chars.insert(code, text);
+                            }
+                        }
+                    }
+                },
+                "endbfrange" => {
+                    for range in operands.chunks(3) {
+                        if let [DictNode::HexString(low), DictNode::HexString(high), destination] = range {
+                            cmap.add_range(low, high, destination);
+                        }
+                    }
+                },
+                "endcidchar" => {
+                    for pair in operands.chunks(2) {
+                        if let [DictNode::HexString(code), DictNode::Int(cid)] = pair {
+                            if let (Some(code), true) = (code_value(code), *cid >= 0) {
+                                cmap.cid_chars.insert(code, *cid as u32);
+                            }
+                        }
+                    }
+                },
+                "endcidrange" => {
+                    for range in operands.chunks(3) {
+                        if let [DictNode::HexString(low), DictNode::HexString(high), DictNode::Int(cid)] = range {
+                            match (code_value(low), code_value(high)) {
+                                (Some(low), Some(high)) if low.0 == high.0 && low.1 <= high.1 && *cid >= 0 => {
+                                    cmap.cid_ranges.push((low.0, low.1, high.1, *cid as u32));
+                                },
+                                _ => (),
+                            }
+                        }
+                    }
+                },
+                _ => (),
+            }
+            operands.clear();
+        }
+        cmap
+    }
+
+    fn add_range(&mut self, low: &[u8], high: &[u8], destination: &DictNode) {
+        let ((length, low), (_, high)) = match (code_value(low), code_value(high)) {
+            (Some(low), Some(high)) if low.0 == high.0 && low.1 <= high.1 => (low, high),
+            _ => return,
+        };
+        match *destination {
+            DictNode::HexString(ref start) if start.len() >= 2 => {
+                self.ranges.push((length, low, high, utf16_units(start)));
+            },
+            DictNode::Array(ref texts) => {
+                for (code, text) in (low..=high).zip(texts) {
+                    if let Some(text) = destination_text(text) {
+                        self.chars.insert((length, code), text);
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty() && self.ranges.is_empty()
+    }
+
+    // Whether there are code to CID mappings, making it an encoding CMap.
+    pub fn has_cids(&self) -> bool {
+        !self.cid_chars.is_empty() || !self.cid_ranges.is_empty()
+    }
+
+    // The CID of a code, given as its bytes.
+    pub fn cid(&self, code: &[u8]) -> Option<u32> {
+        let key = code_value(code)?;
+        if let Some(&cid) = self.cid_chars.get(&key) {
+            return Some(cid);
+        }
+        self.cid_ranges.iter()
+            .find(|&&(length, low, high, _)| length == key.0 && low <= key.1 && key.1 <= high)
+            .map(|&(_, low, _, cid)| cid.saturating_add(key.1 - low))
+    }
+
+    // The text of a code, given as its bytes.
+    pub fn lookup(&self, code: &[u8]) -> Option<String> {
+        let key = code_value(code)?;
+        if let Some(text) = self.chars.get(&key) {
+            return Some(text.clone());
+        }
+        let &(_, low, _, ref start) = self.ranges.iter()
+            .find(|&&(length, low, high, _)| length == key.0 && low <= key.1 && key.1 <= high)?;
+        let mut units = start.clone();
+        if let Some(last) = units.last_mut() {
+            *last = last.wrapping_add((key.1 - low) as u16);
+        }
+        Some(String::from_utf16_lossy(&units))
+    }
+
+    // How many bytes the code at the start of `bytes` takes, by the code
+    // space ranges. Codes that fit none of them take as many bytes as the
+    // shortest range, which is where viewers resynchronize too.
+    pub fn code_length(&self, bytes: &[u8]) -> Option<usize> {
+        let fits = |&(low, high): &&(Vec<u8>, Vec<u8>)| {
+            bytes.len() >= low.len()
+                && bytes.iter().zip(low.iter().zip(high)).all(|(byte, (low, high))| low <= byte && byte <= high)
+        };
+        self.codespace.iter()
+            .find(fits)
+            .or_else(|| self.codespace.iter().min_by_key(|&(low, _)| low.len()))
+            .map(|(low, _)| low.len())
+    }
+}
+
+// (length, value) of a code of up to four bytes.
+fn code_value(code: &[u8]) -> Option<(usize, u32)> {
+    if code.is_empty() || code.len() > 4 {
+        return None;
+    }
+    Some((code.len(), code.iter().fold(0, |value, &byte| value << 8 | byte as u32)))
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks(2).map(|pair| match *pair {
+        [high, low] => u16::from_be_bytes([high, low]),
+        [high] => u16::from(high) << 8,
+        _ => 0,
+    }).collect()
+}
+
+// UTF-16BE text, or a glyph name in older CMaps.
+fn destination_text(destination: &DictNode) -> Option<String> {
+    match *destination {
+        DictNode::HexString(ref bytes) | DictNode::LiteralString(ref bytes) => {
+            Some(String::from_utf16_lossy(&utf16_units(bytes)))
+        },
+        DictNode::Str(ref name) => encoding::glyph_char(name).map(|c| c.to_string()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_cmap() {
+    let data = b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap
+        /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def
+        /CMapName /Adobe-Identity-UCS def
+        1 begincodespacerange <0000> <FFFF> endcodespacerange
+        3 beginbfchar
+        <0003> <0020>
+        <0011> <00660069>
+        <0012> /eacute
+        endbfchar
+        2 beginbfrange
+        <0024> <003D> <0041>
+        <0040> <0042> [<0078> <D83DDE00> <0079>]
+        endbfrange
+        endcmap CMapName currentdict /CMap defineresource pop end end";
+    let cmap = CMap::parse(data);
+    assert_eq!(cmap.lookup(&[0x00, 0x03]), Some(" ".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x11]), Some("fi".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x12]), Some("é".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x24]), Some("A".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x3d]), Some("Z".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x41]), Some("😀".to_string()));
+    assert_eq!(cmap.lookup(&[0x00, 0x3e]), None);
+    // same value, different length
+    assert_eq!(cmap.lookup(&[0x03]), None);
+    assert_eq!(cmap.code_length(&[0x00, 0x24, 0x00]), Some(2));
+    assert_eq!(cmap.code_length(&[0x24]), Some(2));
+    assert_eq!(CMap::parse(b"").code_length(&[0x24]), None);
+    assert!(!cmap.has_cids());
+}
+
+#[test]
+fn test_cid_cmap() {
+    let data = b"/CMapName /Custom-H def
+        2 begincodespacerange <00> <80> <8140> <FFFF> endcodespacerange
+        1 begincidchar <20> 1 endcidchar
+        2 begincidrange <21> <7e> 2 <8140> <817e> 633 endcidrange
+        endcmap";
+    let cmap = CMap::parse(data);
+    assert!(cmap.has_cids() && cmap.is_empty());
+    assert_eq!(cmap.cid(&[0x20]), Some(1));
+    assert_eq!(cmap.cid(&[0x41]), Some(34));
+    assert_eq!(cmap.cid(&[0x81, 0x41]), Some(634));
+    assert_eq!(cmap.cid(&[0x81, 0x7f]), None);
+    // mixed one and two byte codes
+    assert_eq!(cmap.code_length(&[0x41, 0x81]), Some(1));
+    assert_eq!(cmap.code_length(&[0x81, 0x41]), Some(2));
+}
@@ -0,0 +1,236 @@
+use parsers::cos::DictNode;
+
+/*
+ * Encodings of simple fonts, which map each byte of a shown string to a
+ * glyph. A font either names one of the standard encodings or gives a
+ * dictionary with a /BaseEncoding and /Differences on top of it:
+ *
+ *   /Encoding << /BaseEncoding /WinAnsiEncoding
+ *                /Differences [128 /bullet /endash 146 /quoteright] >>
+ *
+ * /Differences is a list of runs, each a code followed by the glyph names
+ * for it and the codes after it. Glyph names are turned into text with the
+ * names of the Latin glyphs the standard encodings use, plus the uniXXXX
+ * and uXXXX[XX] forms that spell out a code point. Anything else (a0, g17,
+ * glyph names of some symbol font) has no text, which is what ToUnicode
+ * CMaps are for.
+ *
+ * Symbol and ZapfDingbats come with their own built-in encodings, which
+ * aren't here: their text isn't much use as text anyway.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BaseEncoding {
+    Standard,
+    WinAnsi,
+    MacRoman,
+}
+
+impl BaseEncoding {
+    pub fn from_name(name: &str) -> Option<BaseEncoding> {
+        match name.trim_start_matches('/') {
+            "StandardEncoding" => Some(BaseEncoding::Standard),
+            "WinAnsiEncoding" => Some(BaseEncoding::WinAnsi),
+            "MacRomanEncoding" => Some(BaseEncoding::MacRoman),
+            _ => None,
+        }
+    }
+
+    pub fn decode(self, code: u8) -> Option<char> {
+        let c = match (self, code) {
+            (_, 0..=0x1f) | (_, 0x7f) => return None,
+            // the standard encoding has curly quotes where ASCII doesn't
+            (BaseEncoding::Standard, b'\'') => '\u{2019}',
+            (BaseEncoding::Standard, b'`') => '\u{2018}',
+            (_, 0x20..=0x7e) => code as char,
+            (BaseEncoding::Standard, _) => STANDARD[code as usize - 0x80],
+            (BaseEncoding::WinAnsi, _) => WIN_ANSI[code as usize - 0x80],
+            (BaseEncoding::MacRoman, _) => MAC_ROMAN[code as usize - 0x80],
+        };
+        if c == '\0' {
+            None
+        } else {
+            Some(c)
+        }
+    }
+}
+
+// A simple font's encoding, resolved to the text of each code.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Encoding([Option<char>; 256]);
+
+impl Encoding {
+    pub fn new(base: BaseEncoding) -> Encoding {
+        let mut chars = [None; 256];
+        for (code, c) in chars.iter_mut().enumerate() {
+            *c = base.decode(code as u8);
+        }
+        Encoding(chars)
+    }
+
+    // Reads a font's /Encoding, a name or a dictionary, falling back to
+    // `base` for what it doesn't say. References have to be resolved
+    // already.
+    pub fn from_node(node: Option<&DictNode>, base: BaseEncoding) -> Encoding {
+        let node = match node {
+            Some(node) => node,
+            None => return Encoding::new(base),
+        };
+        if let Some(name) = node.as_name() {
+            return Encoding::new(BaseEncoding::from_name(name).unwrap_or(base));
+        }
+        let base = node.get("BaseEncoding")
+            .and_then(DictNode::as_name)
+            .and_then(BaseEncoding::from_name)
+            .unwrap_or(base);
+        let mut encoding = Encoding::new(base);
+        if let Some(differences) = node.get("Differences").and_then(DictNode::as_array) {
+            encoding.apply_differences(differences);
+        }
+        encoding
+    }
+
+    pub fn apply_differences(&mut self, differences: &[DictNode]) {
+        let mut code = None;
+        for item in differences {
+            match *item {
+                DictNode::Int(start) => code = Some(start),
+                DictNode::Str(ref name) => {
+                    if let Some(at) = code.filter(|&at| (0..256).contains(&at)) {
+                        self.0[at as usize] = glyph_char(name);
+                    }
+                    code = code.map(|at| at + 1);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    pub fn get(&self, code: u8) -> Option<char> {
+        self.0[code as usize]
+    }
+}
+
+// The text of a glyph name, with or without its slash. Suffixes like the
+// ".sc" of small caps or ".alt" don't change what the glyph reads as.
+pub fn glyph_char(name: &str) -> Option<char> {
+    let name = name.trim_start_matches('/');
+    let name = name.split('.').next().unwrap_or(name);
+    if let Ok(index) = GLYPHS.binary_search_by_key(&name, |&(glyph, _)| glyph) {
+        return Some(GLYPHS[index].1);
+    }
+    let hex = match (name.strip_prefix("uni"), name.strip_prefix('u')) {
+        (Some(hex), _) if hex.len() == 4 => hex,
+        (_, Some(hex)) if (4..=6).contains(&hex.len()) => hex,
+        _ => return None,
+    };
+    u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32)
+}
+
+// 0x80 to 0xff of each encoding, '\0' where a code has no glyph.
+const MAC_ROMAN: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è',
+    'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü',
+    '†', '°', '¢', '£', '§', '•', '¶', 'ß', '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø',
+    '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø',
+    '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…', '\u{a0}', 'À', 'Ã', 'Õ', 'Œ', 'œ',
+    '–', '—', '“', '”', '‘', '’', '÷', '◊', 'ÿ', 'Ÿ', '⁄', '¤', '‹', '›', 'ﬁ', 'ﬂ',
+    '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô',
+    '\u{f8ff}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+const WIN_ANSI: [char; 128] = [
+    '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+    '\0', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+    '\u{a0}', '¡', '¢', '£', '¤', '¥', '¦', '§', '¨', '©', 'ª', '«', '¬', '\u{ad}', '®', '¯',
+    '°', '±', '²', '³', '´', 'µ', '¶', '·', '¸', '¹', 'º', '»', '¼', '½', '¾', '¿',
+    'À', 'Á', 'Â', 'Ã', 'Ä', 'Å', 'Æ', 'Ç', 'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï',
+    'Ð', 'Ñ', 'Ò', 'Ó', 'Ô', 'Õ', 'Ö', '×', 'Ø', 'Ù', 'Ú', 'Û', 'Ü', 'Ý', 'Þ', 'ß',
+    'à', 'á', 'â', 'ã', 'ä', 'å', 'æ', 'ç', 'è', 'é', 'ê', 'ë', 'ì', 'í', 'î', 'ï',
+    'ð', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', '÷', 'ø', 'ù', 'ú', 'û', 'ü', 'ý', 'þ', 'ÿ',
+];
+
+const STANDARD: [char; 128] = [
+    '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0',
+    '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0',
+    '\0', '¡', '¢', '£', '⁄', '¥', 'ƒ', '§', '¤', '\'', '“', '«', '‹', '›', 'ﬁ', 'ﬂ',
+    '\0', '–', '†', '‡', '·', '\0', '¶', '•', '‚', '„', '”', '»', '…', '‰', '\0', '¿',
+    '\0', '`', '´', 'ˆ', '˜', '¯', '˘', '˙', '¨', '\0', '˚', '¸', '\0', '˝', '˛', 'ˇ',
+    '—', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0',
+    '\0', 'Æ', '\0', 'ª', '\0', '\0', '\0', '\0', 'Ł', 'Ø', 'Œ', 'º', '\0', '\0', '\0', '\0',
+    '\0', 'æ', '\0', '\0', '\0', 'ı', '\0', '\0', 'ł', 'ø', 'œ', 'ß', '\0', '\0', '\0', '\0',
+];
+
+const GLYPHS: &[(&str, char)] = &[
+    ("A", 'A'), ("AE", 'Æ'), ("Aacute", 'Á'), ("Acircumflex", 'Â'), ("Adieresis", 'Ä'), ("Agrave", 'À'),
+    ("Aring", 'Å'), ("Atilde", 'Ã'), ("B", 'B'), ("C", 'C'), ("Ccedilla", 'Ç'), ("D", 'D'), ("Delta", '∆'),
+    ("E", 'E'), ("Eacute", 'É'), ("Ecircumflex", 'Ê'), ("Edieresis", 'Ë'), ("Egrave", 'È'), ("Eth", 'Ð'),
+    ("Euro", '€'), ("F", 'F'), ("G", 'G'), ("H", 'H'), ("I", 'I'), ("Iacute", 'Í'), ("Icircumflex", 'Î'),
+    ("Idieresis", 'Ï'), ("Igrave", 'Ì'), ("J", 'J'), ("K", 'K'), ("L", 'L'), ("Lslash", 'Ł'), ("M", 'M'), ("N", 'N'),
+    ("Ntilde", 'Ñ'), ("O", 'O'), ("OE", 'Œ'), ("Oacute", 'Ó'), ("Ocircumflex", 'Ô'), ("Odieresis", 'Ö'),
+    ("Ograve", 'Ò'), ("Omega", 'Ω'), ("Oslash", 'Ø'), ("Otilde", 'Õ'), ("P", 'P'), ("Q", 'Q'), ("R", 'R'), ("S", 'S'),
+    ("Scaron", 'Š'), ("T", 'T'), ("Thorn", 'Þ'), ("U", 'U'), ("Uacute", 'Ú'), ("Ucircumflex", 'Û'),
+    ("Udieresis", 'Ü'), ("Ugrave", 'Ù'), ("V", 'V'), ("W", 'W'), ("X", 'X'), ("Y", 'Y'), ("Yacute", 'Ý'),
+    ("Ydieresis", 'Ÿ'), ("Z", 'Z'), ("Zcaron", 'Ž'), ("a", 'a'), ("aacute", 'á'), ("acircumflex", 'â'),
+    ("acute", '´'), ("adieresis", 'ä'), ("ae", 'æ'), ("agrave", 'à'), ("ampersand", '&'), ("apple", '\u{f8ff}'),
+    ("approxequal", '≈'), ("aring", 'å'), ("asciicircum", '^'), ("asciitilde", '~'), ("asterisk", '*'), ("at", '@'),
+    ("atilde", 'ã'), ("b", 'b'), ("backslash", '\\'), ("bar", '|'), ("braceleft", '{'), ("braceright", '}'),
+    ("bracketleft", '['), ("bracketright", ']'), ("breve", '˘'), ("brokenbar", '¦'), ("bullet", '•'), ("c", 'c'),
+    ("caron", 'ˇ'), ("ccedilla", 'ç'), ("cedilla", '¸'), ("cent", '¢'), ("circumflex", 'ˆ'), ("colon", ':'),
+    ("comma", ','), ("copyright", '©'), ("currency", '¤'), ("d", 'd'), ("dagger", '†'), ("daggerdbl", '‡'),
+    ("degree", '°'), ("dieresis", '¨'), ("divide", '÷'), ("dollar", '$'), ("dotaccent", '˙'), ("dotlessi", 'ı'),
+    ("e", 'e'), ("eacute", 'é'), ("ecircumflex", 'ê'), ("edieresis", 'ë'), ("egrave", 'è'), ("eight", '8'),
+    ("ellipsis", '…'), ("emdash", '—'), ("endash", '–'), ("equal", '='), ("eth", 'ð'), ("exclam", '!'),
+    ("exclamdown", '¡'), ("f", 'f'), ("fi", 'ﬁ'), ("five", '5'), ("fl", 'ﬂ'), ("florin", 'ƒ'), ("four", '4'),
+    ("fraction", '⁄'), ("g", 'g'), ("germandbls", 'ß'), ("grave", '`'), ("greater", '>'), ("greaterequal", '≥'),
+    ("guillemotleft", '«'), ("guillemotright", '»'), ("guilsinglleft", '‹'), ("guilsinglright", '›'), ("h", 'h'),
+    ("hungarumlaut", '˝'), ("hyphen", '-'), ("i", 'i'), ("iacute", 'í'), ("icircumflex", 'î'), ("idieresis", 'ï'),
+    ("igrave", 'ì'), ("infinity", '∞'), ("integral", '∫'), ("j", 'j'), ("k", 'k'), ("l", 'l'), ("less", '<'),
+    ("lessequal", '≤'), ("logicalnot", '¬'), ("lozenge", '◊'), ("lslash", 'ł'), ("m", 'm'), ("macron", '¯'),
+    ("minus", '−'), ("mu", 'µ'), ("multiply", '×'), ("n", 'n'), ("nbspace", '\u{a0}'), ("nine", '9'),
+    ("notequal", '≠'), ("ntilde", 'ñ'), ("numbersign", '#'), ("o", 'o'), ("oacute", 'ó'), ("ocircumflex", 'ô'),
+    ("odieresis", 'ö'), ("oe", 'œ'), ("ogonek", '˛'), ("ograve", 'ò'), ("one", '1'), ("onehalf", '½'),
+    ("onequarter", '¼'), ("onesuperior", '¹'), ("ordfeminine", 'ª'), ("ordmasculine", 'º'), ("oslash", 'ø'),
+    ("otilde", 'õ'), ("p", 'p'), ("paragraph", '¶'), ("parenleft", '('), ("parenright", ')'), ("partialdiff", '∂'),
+    ("percent", '%'), ("period", '.'), ("periodcentered", '·'), ("perthousand", '‰'), ("pi", 'π'), ("plus", '+'),
+    ("plusminus", '±'), ("product", '∏'), ("q", 'q'), ("question", '?'), ("questiondown", '¿'), ("quotedbl", '"'),
+    ("quotedblbase", '„'), ("quotedblleft", '“'), ("quotedblright", '”'), ("quoteleft", '‘'), ("quoteright", '’'),
+    ("quotesinglbase", '‚'), ("quotesingle", '\''), ("r", 'r'), ("radical", '√'), ("registered", '®'), ("ring", '˚'),
+    ("s", 's'), ("scaron", 'š'), ("section", '§'), ("semicolon", ';'), ("seven", '7'), ("sfthyphen", '\u{ad}'),
+    ("six", '6'), ("slash", '/'), ("space", ' '), ("sterling", '£'), ("summation", '∑'), ("t", 't'), ("thorn", 'þ'),
+    ("three", '3'), ("threequarters", '¾'), ("threesuperior", '³'), ("tilde", '˜'), ("trademark", '™'), ("two", '2'),
+    ("twosuperior", '²'), ("u", 'u'), ("uacute", 'ú'), ("ucircumflex", 'û'), ("udieresis", 'ü'), ("ugrave", 'ù'),
+    ("underscore", '_'), ("v", 'v'), ("w", 'w'), ("x", 'x'), ("y", 'y'), ("yacute", 'ý'), ("ydieresis", 'ÿ'),
+    ("yen", '¥'), ("z", 'z'), ("zcaron", 'ž'), ("zero", '0'),
+];
+
+#[test]
+fn test_base_encodings() {
+    assert_eq!(BaseEncoding::WinAnsi.decode(0x80), Some('€'));
+    assert_eq!(BaseEncoding::WinAnsi.decode(0x81), None);
+    assert_eq!(BaseEncoding::MacRoman.decode(0x8e), Some('é'));
+    assert_eq!(BaseEncoding::Standard.decode(b'\''), Some('’'));
+    assert_eq!(BaseEncoding::Standard.decode(0xe1), Some('Æ'));
+    assert_eq!(BaseEncoding::Standard.decode(b'A'), Some('A'));
+    assert_eq!(BaseEncoding::Standard.decode(0x0a), None);
+}
+
+#[test]
+fn test_differences() {
+    let dict = DictNode::Dict(hashmap!{
+        "BaseEncoding".to_string() => DictNode::name("WinAnsiEncoding"),
+        "Differences".to_string() => DictNode::Array(vec![
+            DictNode::Int(65), DictNode::name("eacute"), DictNode::name("uni263A"),
+            DictNode::Int(200), DictNode::name("g17"), DictNode::name("A.sc"),
+        ]),
+    });
+    let encoding = Encoding::from_node(Some(&dict), BaseEncoding::Standard);
+    assert_eq!(encoding.get(65), Some('é'));
+    assert_eq!(encoding.get(66), Some('☺'));
+    assert_eq!(encoding.get(67), Some('C'));
+    assert_eq!(encoding.get(200), None);
+    assert_eq!(encoding.get(201), Some('A'));
+    assert_eq!(encoding.get(0x80), Some('€'));
+    assert_eq!(glyph_char("u1F600"), Some('😀'));
+    assert_eq!(glyph_char("quotedblleft"), Some('“'));
+}
@@ -0,0 +1,991 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::rc::Rc;
+use std::time::Instant;
+
+use document::Document;
+use error::Result;
+use fonts::CidWidths;
+use fonts::standard::StandardFont;
+use graphics::{Color, ColorFamily, ColorState, Matrix, IDENTITY};
+use pages::{self, Page};
+use pages::coords::Origin;
+use parsers::content::ops::{Op, TextItem};
+use parsers::content::Operations;
+use parsers::cos::DictNode;
+use timings::Phase;
+
+use self::cmap::CMap;
+use self::encoding::{BaseEncoding, Encoding};
+
+pub mod accuracy;
+pub mod cmap;
+pub mod encoding;
+
+/*
+ * Text extraction: running a page's content streams, decoding what the
+ * text showing operators (Tj, TJ, ' and ") show with their font's encoding,
+ * and putting the pieces back together into lines.
+ *
+ *   let text = doc.extract_text(&pages::pages(&mut doc)?[0])?;
+ *
+ * Each shown string becomes a `TextSpan` placed on the page by the text
+ * and transformation matrices. Spans are taken in content stream order,
+ * which is nearly always reading order, and a new line starts when a span
+ * leaves the previous one's baseline. Within a line, a gap of more than a
+ * fraction of the font size reads as a space, since plenty of files never
+ * show a space character and move the text along with TJ or Td instead.
+ *
+ * Characters come from the font's ToUnicode CMap when it has one, and from
+ * its encoding (see the encoding module) otherwise. Codes neither says
+ * anything about come out as U+FFFD. Form XObjects are followed, so text
+ * drawn by a letterhead or stamp shows up too.
+ *
+ * Simple fonts measure codes with their /Widths. A standard font that has
+ * none, as the standard 14 usually don't, uses the Adobe metrics in
+ * fonts::standard for printable ASCII, and /MissingWidth or a guess for
+ * the rest.
+ *
+ * Composite (Type0) fonts split strings into codes of one to four bytes
+ * with their encoding CMap, and the CIDs those codes map to give the
+ * widths (from the descendant font's /W). Identity-H and -V, by far the
+ * most common, make every code two bytes and its own CID. The only
+ * predefined CMaps we know more about are the UCS-2 and UTF-16 ones, whose
+ * codes are the text itself; with the others codes are split as the
+ * ToUnicode CMap says. Otherwise CIDs don't give text, so a composite font
+ * without a ToUnicode CMap mostly comes out as U+FFFD. Vertical writing is
+ * read as if it were horizontal.
+ *
+ * Pages turned by /Rotate are read the way a viewer shows them: `spans`
+ * (and so `extract`) places text on the upright page, see
+ * `Page::display_matrix`, so a landscape page's lines still run left to
+ * right. `spans_from` does the same with y going down from the top left
+ * corner, for image and UI coordinates, see pages::coords. `raw_spans`
+ * and `interpret` stay in default user space, where the page's boxes and
+ * paths are.
+ *
+ * There's no attempt at columns, tables or text rotated on the page: lines
+ * come out in the order the file draws them, as a whole.
+ *
+ * How much of a page came out as real text is in its `Coverage`. Codes
+ * that map to nothing, to control characters or to the private use area
+ * (where symbol fonts and broken CMaps put things) count as unmapped, and
+ * a page that's mostly unmapped is a better job for OCR than for us.
+ */
+
+// Gaps wider than this, in ems, are spaces.
+const SPACE_GAP: f64 = 0.15;
+// Baselines further apart than this, in ems, are different lines.
+const LINE_GAP: f64 = 0.5;
+// Form XObjects can nest, and reference each other in circles in broken
+// files.
+const MAX_FORM_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextSpan {
+    pub text: String,
+    // where the text starts and ends on its baseline, in default user space
+    // or on the displayed page from either origin, depending on where it
+    // came from
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+    // the font size as it ends up on the page
+    pub size: f64,
+    // /BaseFont, or the name of the font in the page's resources
+    pub font: String,
+    // the fill color it was shown in, which is what shows unless the
+    // render mode strokes the glyphs or doesn't paint them at all
+    pub color: Color,
+    // Tr: 0 fills the glyphs, 1 strokes them, 2 both, 3 neither, and 4 to
+    // 7 the same and add them to the clipping path
+    pub render_mode: i64,
+}
+
+impl TextSpan {
+    // How far the text goes along its baseline.
+    pub fn width(&self) -> f64 {
+        (self.end.0 - self.start.0).hypot(self.end.1 - self.start.1)
+    }
+
+    // {"text", "x", "y", "width", "height", "font", "size", "color"}, with
+    // (x, y) where the text starts on its baseline, the height one em and
+    // the color "#rrggbb", or null for a pattern or a space that doesn't
+    // come down to RGB.
+    pub fn to_json(&self) -> String {
+        let color = match self.color.to_rgb() {
+            Some((r, g, b)) => {
+                let byte = |value: f64| (value * 255.0).round() as u8;
+                format!("\"#{:02x}{:02x}{:02x}\"", byte(r), byte(g), byte(b))
+            },
+            None => "null".to_string(),
+        };
+        format!("{{\"text\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"font\": {}, \"size\": {}, \"color\": {}}}",
+            json_string(&self.text), json_number(self.start.0), json_number(self.start.1), json_number(self.width()),
+            json_number(self.size), json_string(&self.font), json_number(self.size), color)
+    }
+}
+
+// What running a page's content collects: the text, and what the features
+// module counts while we're at it.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PageContent {
+    pub spans: Vec<TextSpan>,
+    // the transformation matrix of each image drawn, XObject or inline,
+    // which maps the unit square onto where the image goes
+    pub images: Vec<Matrix>,
+    // how many times each operator ran, in form XObjects too
+    pub operators: HashMap<String, usize>,
+    pub coverage: Coverage,
+    // the character codes shown with each font, by the font's reference,
+    // for fonts that are indirect objects
+    pub codes: HashMap<(i64, i64), BTreeSet<Vec<u8>>>,
+}
+
+// How much of what a page shows could be turned into text.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Coverage {
+    // character codes shown, and how many of them mapped to text
+    pub glyphs: usize,
+    pub mapped_glyphs: usize,
+    // strings shown, and how many of them mapped entirely
+    pub runs: usize,
+    pub mapped_runs: usize,
+    // unmapped codes by font
+    pub unmapped_fonts: BTreeMap<String, usize>,
+}
+
+impl Coverage {
+    // The fraction of runs that mapped, 1 for pages without text.
+    pub fn fraction(&self) -> f64 {
+        if self.runs == 0 {
+            return 1.0;
+        }
+        self.mapped_runs as f64 / self.runs as f64
+    }
+
+    pub fn glyph_fraction(&self) -> f64 {
+        if self.glyphs == 0 {
+            return 1.0;
+        }
+        self.mapped_glyphs as f64 / self.glyphs as f64
+    }
+}
+
+// The text of a page, one line per line of text.
+pub fn extract(doc: &mut Document, page: &Page) -> Result<String> {
+    Ok(layout(&spans(doc, page)?))
+}
+
+// The strings a page shows, in the order it shows them, on the page as
+// it's displayed.
+pub fn spans(doc: &mut Document, page: &Page) -> Result<Vec<TextSpan>> {
+    spans_from(doc, page, Origin::BottomLeft)
+}
+
+// The same with the origin at `origin`. From the top left, a span's y is
+// how far its baseline is below the top of the page.
+pub fn spans_from(doc: &mut Document, page: &Page, origin: Origin) -> Result<Vec<TextSpan>> {
+    Ok(interpret_from(doc, page, page.coords(origin).matrix)?.spans)
+}
+
+// The same in default user space, without the page's rotation applied.
+pub fn raw_spans(doc: &mut Document, page: &Page) -> Result<Vec<TextSpan>> {
+    Ok(interpret(doc, page)?.spans)
+}
+
+// How much of the page's text could be extracted, see the top of the file.
+pub fn coverage(doc: &mut Document, page: &Page) -> Result<Coverage> {
+    Ok(interpret(doc, page)?.coverage)
+}
+
+// Spans along with the rest of `PageContent`, in default user space.
+pub fn interpret(doc: &mut Document, page: &Page) -> Result<PageContent> {
+    interpret_from(doc, page, IDENTITY)
+}
+
+// Runs the page's content with `ctm` as the initial transformation matrix.
+fn interpret_from(doc: &mut Document, page: &Page, ctm: Matrix) -> Result<PageContent> {
+    // reading fonts and decoding streams count as parsing and decoding, the
+    // rest is text
+    let start = Instant::now();
+    let elsewhere = |doc: &Document| doc.timings().get(Phase::Parse) + doc.timings().get(Phase::Decode);
+    let before = elsewhere(doc);
+
+    let content = pages::content(doc, page)?;
+    let mut interpreter = Interpreter::new(ctm);
+    let mut extractor = Extractor { doc, fonts: HashMap::new(), forms: Vec::new(), images: Vec::new(), operators: HashMap::new() };
+    extractor.run(&mut interpreter, &content, &page.resources)?;
+    let (images, operators) = (extractor.images, extractor.operators);
+
+    let other = elsewhere(doc).saturating_sub(before);
+    doc.timings_mut().add(Phase::Text, start.elapsed().saturating_sub(other));
+    Ok(PageContent { spans: interpreter.spans, images, operators, coverage: interpreter.coverage, codes: interpreter.codes })
+}
+
+// Spans for layout analysis, as a JSON array with an object per page:
+//
+//   [{"page": 1, "crop_box": [0, 0, 612, 792], "rotate": 0, "spans": [
+//     {"text": "Hello", "x": 72, "y": 700, "width": 27.8, "height": 12, "font": "Helvetica", "size": 12}
+//   ]}]
+//
+// Spans are as given: on the displayed page from `spans` or `spans_from`,
+// in default user space from `raw_spans`, where the crop box and rotation
+// apply.
+pub fn json(pages: &[(&Page, Vec<TextSpan>)]) -> String {
+    let mut json = String::from("[");
+    for (index, &(page, ref spans)) in pages.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let crop_box = page.crop_box.iter().map(|&n| json_number(n)).collect::<Vec<_>>().join(", ");
+        json.push_str(&format!("\n{{\"page\": {}, \"crop_box\": [{}], \"rotate\": {}, \"spans\": [",
+            page.index + 1, crop_box, page.rotate));
+        for (index, span) in spans.iter().enumerate() {
+            json.push_str(if index > 0 { ",\n  " } else { "\n  " });
+            json.push_str(&span.to_json());
+        }
+        json.push_str("\n]}");
+    }
+    json.push_str("\n]\n");
+    json
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Rounded to a thousandth of a point, which is plenty and keeps float noise
+// out. JSON has no NaN or infinity, degenerate matrices get 0.
+fn json_number(n: f64) -> String {
+    let rounded = (n * 1000.0).round() / 1000.0;
+    if !rounded.is_finite() || rounded == 0.0 {
+        return "0".to_string();
+    }
+    format!("{}", rounded)
+}
+
+// Joins spans into lines, see the top of the file.
+pub fn layout(spans: &[TextSpan]) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut previous: Option<&TextSpan> = None;
+    for span in spans {
+        if let Some(previous) = previous {
+            let em = span.size.max(previous.size);
+            if (span.start.1 - previous.end.1).abs() > em * LINE_GAP {
+                lines.push(line.trim_end().to_string());
+                line.clear();
+            } else if span.start.0 - previous.end.0 > em * SPACE_GAP
+                && !line.ends_with(char::is_whitespace)
+                && !span.text.starts_with(char::is_whitespace) {
+                line.push(' ');
+            }
+        }
+        line.push_str(&span.text);
+        previous = Some(span);
+    }
+    lines.push(line.trim_end().to_string());
+
+    let mut text = String::new();
+    for line in lines.iter().skip_while(|line| line.is_empty()) {
+        text.extend(line.chars().flat_map(normalize));
+        text.push('\n');
+    }
+    // no blank lines at the end either
+    let end = text.trim_end().len();
+    text.truncate(end);
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+// Whether a decoded character is text rather than an artifact of a
+// missing or broken mapping.
+fn usable(c: char) -> bool {
+    (!c.is_control() || c.is_whitespace()) && !('\u{e000}'..='\u{f8ff}').contains(&c) && c != '\u{fffd}'
+}
+
+// Unfolds the ligatures fonts like to use, which nobody searches for, and
+// turns non-breaking spaces into spaces.
+fn normalize(c: char) -> Vec<char> {
+    match c {
+        '\u{fb00}' => vec!['f', 'f'],
+        '\u{fb01}' => vec!['f', 'i'],
+        '\u{fb02}' => vec!['f', 'l'],
+        '\u{fb03}' => vec!['f', 'f', 'i'],
+        '\u{fb04}' => vec!['f', 'f', 'l'],
+        '\u{fb05}' | '\u{fb06}' => vec!['s', 't'],
+        '\u{a0}' => vec![' '],
+        c => vec![c],
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Font {
+    name: String,
+    // where the font was read from
+    reference: Option<(i64, i64)>,
+    encoding: Encoding,
+    to_unicode: Option<CMap>,
+    // for Type0 fonts
+    cid: Option<Cid>,
+    first_char: i64,
+    widths: Vec<f64>,
+    // the standard font whose widths a simple font without /Widths has
+    standard: Option<StandardFont>,
+    // for codes without a width, in glyph space
+    default_width: f64,
+    // glyph space to text space, 1/1000 except for Type3 fonts
+    scale: f64,
+}
+
+// How a composite font reads codes.
+#[derive(Debug, PartialEq, Clone)]
+struct Cid {
+    // an embedded encoding CMap
+    cmap: Option<CMap>,
+    // Identity-H or -V
+    identity: bool,
+    // a UCS-2 or UTF-16 CMap
+    unicode: bool,
+    widths: CidWidths,
+}
+
+impl Cid {
+    // `font` resolved all the way down, `cmap` the embedded encoding CMap
+    // if there is one.
+    fn new(font: &DictNode, cmap: Option<CMap>) -> Cid {
+        let name = font.get("Encoding").and_then(DictNode::as_name);
+        let cmap = cmap.filter(CMap::has_cids);
+        let descendant = font.get("DescendantFonts").and_then(DictNode::as_array).and_then(<[DictNode]>::first);
+        Cid {
+            identity: cmap.is_none() && name.is_none_or(|name| name == "Identity-H" || name == "Identity-V"),
+            unicode: name.is_some_and(|name| name.starts_with("Uni") && (name.contains("UCS2") || name.contains("UTF16"))),
+            cmap,
+            widths: descendant.map(CidWidths::from_dict).unwrap_or_default(),
+        }
+    }
+
+    fn cid(&self, code: &[u8]) -> u32 {
+        match self.cmap {
+            Some(ref cmap) => cmap.cid(code).unwrap_or(0),
+            None => code.iter().fold(0u32, |value, &byte| value << 8 | byte as u32),
+        }
+    }
+}
+
+impl Font {
+    // `font` and `descriptor` have to be resolved all the way down.
+    // `encoding_cmap` is a composite font's embedded encoding CMap.
+    fn new(font: &DictNode, descriptor: Option<&DictNode>, to_unicode: Option<CMap>, encoding_cmap: Option<CMap>) -> Font {
+        let subtype = font.get("Subtype").and_then(DictNode::as_name).unwrap_or("Type1");
+        let name = font.get("BaseFont").and_then(DictNode::as_name).unwrap_or("").to_string();
+        // the spec's default is the standard encoding, but TrueType fonts
+        // without one are nearly always meant as Windows text
+        let base = if subtype == "TrueType" { BaseEncoding::WinAnsi } else { BaseEncoding::Standard };
+        let widths = font.get("Widths").and_then(DictNode::as_array).unwrap_or(&[]);
+        let default_width = match descriptor.and_then(|descriptor| descriptor.get("MissingWidth")).and_then(DictNode::as_float) {
+            Some(width) => width,
+            None if subtype == "Type0" => 1000.0,
+            None if name.contains("Courier") => 600.0,
+            None => 500.0,
+        };
+        let scale = match subtype {
+            "Type3" => font.get("FontMatrix").and_then(Matrix::from_node).map_or(0.001, |matrix| matrix.a),
+            _ => 0.001,
+        };
+        let standard = match subtype {
+            "Type0" | "Type3" => None,
+            _ if widths.is_empty() => StandardFont::from_name(&name),
+            _ => None,
+        };
+        Font {
+            name,
+            reference: None,
+            encoding: Encoding::from_node(font.get("Encoding"), base),
+            to_unicode: to_unicode.filter(|cmap| !cmap.is_empty()),
+            cid: if subtype == "Type0" { Some(Cid::new(font, encoding_cmap)) } else { None },
+            first_char: font.get("FirstChar").and_then(DictNode::as_int).unwrap_or(0),
+            widths: widths.iter().map(|width| width.as_float().unwrap_or(default_width)).collect(),
+            standard,
+            default_width,
+            scale,
+        }
+    }
+
+    // For text shown before any Tf, or with a font that isn't there.
+    fn fallback() -> Font {
+        Font::new(&DictNode::Dict(HashMap::new()), None, None, None)
+    }
+
+    // Splits a shown string into character codes.
+    fn codes<'a>(&self, bytes: &'a [u8]) -> Vec<&'a [u8]> {
+        let cid = match self.cid {
+            Some(ref cid) => cid,
+            None => return bytes.chunks(1).collect(),
+        };
+        let mut codes = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let length = match cid.cmap {
+                Some(ref cmap) => cmap.code_length(rest),
+                None if cid.identity || cid.unicode => Some(2),
+                None => self.to_unicode.as_ref().and_then(|cmap| cmap.code_length(rest)),
+            };
+            let length = length.unwrap_or(2).min(rest.len());
+            let (code, after) = rest.split_at(length);
+            codes.push(code);
+            rest = after;
+        }
+        codes
+    }
+
+    fn text(&self, code: &[u8]) -> Option<String> {
+        if let Some(text) = self.to_unicode.as_ref().and_then(|cmap| cmap.lookup(code)) {
+            return Some(text);
+        }
+        match (code, &self.cid) {
+            (&[byte], None) => self.encoding.get(byte).map(|c| c.to_string()),
+            (_, Some(cid)) if cid.unicode => {
+                let units: Vec<u16> = code.chunks(2).map(|pair| pair.iter().fold(0, |unit, &byte| unit << 8 | byte as u16)).collect();
+                Some(String::from_utf16_lossy(&units))
+            },
+            _ => None,
+        }
+    }
+
+    // The standard font's width for `code`, by the printable ASCII
+    // character the font's encoding makes of it.
+    fn standard_width(&self, code: u8) -> Option<f64> {
+        let c = self.encoding.get(code).filter(|c| (' '..='~').contains(c))?;
+        self.standard?.width(c as u8)
+    }
+
+    // How far the code moves the text along, in text space for a font size
+    // of 1.
+    fn width(&self, code: &[u8]) -> f64 {
+        let width = match (code, &self.cid) {
+            (_, Some(cid)) => cid.widths.get(cid.cid(code)),
+            (&[byte], None) if byte as i64 >= self.first_char => {
+                self.widths.get((byte as i64 - self.first_char) as usize).cloned()
+                    .or_else(|| self.standard_width(byte))
+                    .unwrap_or(self.default_width)
+            },
+            _ => self.default_width,
+        };
+        width * self.scale
+    }
+}
+
+// The parts of the graphics state text extraction needs, saved by q and
+// restored by Q.
+#[derive(Debug, Clone)]
+struct State {
+    ctm: Matrix,
+    font: Option<Rc<Font>>,
+    size: f64,
+    char_spacing: f64,
+    word_spacing: f64,
+    // Tz, as a fraction
+    horizontal_scaling: f64,
+    leading: f64,
+    render_mode: i64,
+    colors: ColorState,
+}
+
+// Follows the operators that move text around and collects what gets
+// shown. Fonts and form XObjects need the document, `Extractor` takes care
+// of those.
+struct Interpreter {
+    state: State,
+    stack: Vec<State>,
+    text_matrix: Matrix,
+    line_matrix: Matrix,
+    spans: Vec<TextSpan>,
+    coverage: Coverage,
+    codes: HashMap<(i64, i64), BTreeSet<Vec<u8>>>,
+}
+
+impl Interpreter {
+    fn new(ctm: Matrix) -> Interpreter {
+        Interpreter {
+            state: State {
+                ctm,
+                font: None,
+                size: 0.0,
+                char_spacing: 0.0,
+                word_spacing: 0.0,
+                horizontal_scaling: 1.0,
+                leading: 0.0,
+                render_mode: 0,
+                colors: ColorState::default(),
+            },
+            stack: Vec::new(),
+            text_matrix: IDENTITY,
+            line_matrix: IDENTITY,
+            spans: Vec::new(),
+            coverage: Coverage::default(),
+            codes: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Save => self.stack.push(self.state.clone()),
+            Op::Restore => {
+                // unbalanced Qs are common enough, they're ignored
+                if let Some(state) = self.stack.pop() {
+                    self.state = state;
+                }
+            },
+            Op::Transform(ref matrix) => {
+                if let Some(matrix) = Matrix::from_numbers(matrix) {
+                    self.state.ctm = matrix * self.state.ctm;
+                }
+            },
+            Op::BeginText => {
+                self.text_matrix = IDENTITY;
+                self.line_matrix = IDENTITY;
+            },
+            Op::SetCharSpacing(spacing) => self.state.char_spacing = spacing,
+            Op::SetWordSpacing(spacing) => self.state.word_spacing = spacing,
+            Op::SetHorizontalScaling(scaling) => self.state.horizontal_scaling = scaling / 100.0,
+            Op::SetLeading(leading) => self.state.leading = leading,
+            Op::SetRenderMode(mode) => self.state.render_mode = mode,
+            Op::SetFont { size, .. } => self.state.size = size,
+            Op::MoveText(x, y) => self.next_line(x, y),
+            Op::MoveTextSetLeading(x, y) => {
+                self.state.leading = -y;
+                self.next_line(x, y);
+            },
+            Op::SetTextMatrix(ref matrix) => {
+                if let Some(matrix) = Matrix::from_numbers(matrix) {
+                    self.text_matrix = matrix;
+                    self.line_matrix = matrix;
+                }
+            },
+            Op::NextLine => self.next_line(0.0, -self.state.leading),
+            Op::ShowText(ref string) => self.show(string),
+            Op::NextLineShowText(ref string) => {
+                self.next_line(0.0, -self.state.leading);
+                self.show(string);
+            },
+            Op::NextLineShowTextSpaced { word_spacing, char_spacing, ref text } => {
+                self.state.word_spacing = word_spacing;
+                self.state.char_spacing = char_spacing;
+                self.next_line(0.0, -self.state.leading);
+                self.show(text);
+            },
+            Op::ShowTextAdjusted(ref items) => {
+                for item in items {
+                    match *item {
+                        TextItem::Adjust(adjustment) => self.advance(-adjustment / 1000.0 * self.state.size * self.state.horizontal_scaling),
+                        TextItem::Text(ref string) => self.show(string),
+                    }
+                }
+            },
+            _ => self.state.colors.apply(op),
+        }
+    }
+
+    fn next_line(&mut self, x: f64, y: f64) {
+        self.line_matrix = Matrix::translate(x, y) * self.line_matrix;
+        self.text_matrix = self.line_matrix;
+    }
+
+    fn advance(&mut self, x: f64) {
+        self.text_matrix = Matrix::translate(x, 0.0) * self.text_matrix;
+    }
+
+    // Where the text matrix puts the next glyph on the page.
+    fn position(&self) -> (f64, f64) {
+        (self.text_matrix * self.state.ctm).apply(0.0, 0.0)
+    }
+
+    fn show(&mut self, string: &[u8]) {
+        let font = self.state.font.clone().unwrap_or_else(|| Rc::new(Font::fallback()));
+        let start = self.position();
+        let mut text = String::new();
+        let mut unmapped = 0;
+        let codes = font.codes(string);
+        for &code in &codes {
+            match font.text(code) {
+                Some(decoded) => {
+                    if !decoded.chars().all(usable) {
+                        unmapped += 1;
+                    }
+                    text.push_str(&decoded);
+                },
+                None => {
+                    unmapped += 1;
+                    text.push('\u{fffd}');
+                },
+            }
+            // word spacing only applies to the single byte code 32
+            let word_spacing = if code == b" " { self.state.word_spacing } else { 0.0 };
+            let width = font.width(code) * self.state.size + self.state.char_spacing + word_spacing;
+            self.advance(width * self.state.horizontal_scaling);
+        }
+        if let (Some(reference), false) = (font.reference, codes.is_empty()) {
+            self.codes.entry(reference).or_default().extend(codes.iter().map(|code| code.to_vec()));
+        }
+        if !codes.is_empty() {
+            self.coverage.glyphs += codes.len();
+            self.coverage.mapped_glyphs += codes.len() - unmapped;
+            self.coverage.runs += 1;
+            if unmapped == 0 {
+                self.coverage.mapped_runs += 1;
+            } else {
+                *self.coverage.unmapped_fonts.entry(font.name.clone()).or_insert(0) += unmapped;
+            }
+        }
+        if text.is_empty() {
+            return;
+        }
+        self.spans.push(TextSpan {
+            text,
+            start,
+            end: self.position(),
+            size: self.state.size.abs() * (self.text_matrix * self.state.ctm).vertical_scale(),
+            font: font.name.clone(),
+            color: self.state.colors.fill.clone(),
+            render_mode: self.state.render_mode,
+        });
+    }
+}
+
+// Runs content streams through an `Interpreter`, reading the fonts and
+// form XObjects they use from the document.
+struct Extractor<'a> {
+    doc: &'a mut Document,
+    // by reference, so each font is only read once
+    fonts: HashMap<(i64, i64), Rc<Font>>,
+    // the form XObjects being run, innermost last
+    forms: Vec<(i64, i64)>,
+    // for PageContent
+    images: Vec<Matrix>,
+    operators: HashMap<String, usize>,
+}
+
+impl<'a> Extractor<'a> {
+    fn run(&mut self, interpreter: &mut Interpreter, content: &[u8], resources: &DictNode) -> Result<()> {
+        for operation in Operations::new(content) {
+            // keep what was shown up to a syntax error
+            let operation = match operation {
+                Ok(operation) => operation,
+                Err(_) => break,
+            };
+            *self.operators.entry(operation.operator.clone()).or_insert(0) += 1;
+            let op = Op::parse(operation);
+            match op {
+                Op::SetFont { ref name, .. } => {
+                    interpreter.state.font = self.font(resources, name)?;
+                    interpreter.apply(&op);
+                },
+                // spaces in the resources need the document to look up
+                Op::SetFillColorSpace(ref name) => interpreter.state.colors.set_space(true, ColorFamily::named(self.doc, name, resources)?),
+                Op::SetStrokeColorSpace(ref name) => interpreter.state.colors.set_space(false, ColorFamily::named(self.doc, name, resources)?),
+                Op::PaintXObject(ref name) => self.xobject(interpreter, resources, name)?,
+                Op::InlineImage { .. } => self.images.push(interpreter.state.ctm),
+                _ => interpreter.apply(&op),
+            }
+        }
+        Ok(())
+    }
+
+    fn font(&mut self, resources: &DictNode, name: &str) -> Result<Option<Rc<Font>>> {
+        let node = match resources.get_resolved(self.doc, "Font")? {
+            Some(fonts) => match fonts.get(name) {
+                Some(node) => node.clone(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        if let Some(font) = node.as_reference().and_then(|reference| self.fonts.get(&reference)) {
+            return Ok(Some(font.clone()));
+        }
+        let dict = self.doc.resolve(&node)?;
+        if dict.as_dict().is_none() {
+            return Ok(None);
+        }
+        let to_unicode = match dict.get("ToUnicode") {
+            Some(stream) => self.doc.referenced_stream(stream)?.map(|(_, data)| CMap::parse(&data)),
+            None => None,
+        };
+        // a composite font's /Encoding can be a CMap stream
+        let composite = dict.get("Subtype").is_some_and(|subtype| subtype.is_name("Type0"));
+        let encoding_cmap = match dict.get("Encoding") {
+            Some(stream) if composite => self.doc.referenced_stream(stream)?.map(|(_, data)| CMap::parse(&data)),
+            _ => None,
+        };
+        let mut resolved = HashMap::new();
+        for key in &["Subtype", "BaseFont", "Encoding", "FirstChar", "Widths", "FontMatrix", "DescendantFonts"] {
+            if let Some(value) = dict.get(key) {
+                resolved.insert(key.to_string(), self.doc.resolve_all(value)?);
+            }
+        }
+        let descriptor = dict.get_resolved(self.doc, "FontDescriptor")?;
+        let mut font = Font::new(&DictNode::Dict(resolved), descriptor.as_ref(), to_unicode, encoding_cmap);
+        if font.name.is_empty() {
+            font.name = name.to_string();
+        }
+        font.reference = node.as_reference();
+        let font = Rc::new(font);
+        if let Some(reference) = node.as_reference() {
+            self.fonts.insert(reference, font.clone());
+        }
+        Ok(Some(font))
+    }
+
+    // Runs a form XObject, and notes where an image XObject goes.
+    fn xobject(&mut self, interpreter: &mut Interpreter, resources: &DictNode, name: &str) -> Result<()> {
+        let node = match resources.get_resolved(self.doc, "XObject")? {
+            Some(xobjects) => match xobjects.get(name).and_then(DictNode::as_reference) {
+                Some(reference) => reference,
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        if self.forms.contains(&node) || self.forms.len() >= MAX_FORM_DEPTH {
+            return Ok(());
+        }
+        // images are streams too, check before decoding anything
+        let dict = self.doc.resolve(&DictNode::ObjectReference(node.0, node.1))?;
+        match dict.get("Subtype") {
+            Some(subtype) if subtype.is_name("Form") => (),
+            Some(subtype) if subtype.is_name("Image") => {
+                self.images.push(interpreter.state.ctm);
+                return Ok(());
+            },
+            _ => return Ok(()),
+        }
+        let content = match self.doc.referenced_stream(&DictNode::ObjectReference(node.0, node.1))? {
+            Some((_, content)) => content,
+            None => return Ok(()),
+        };
+        let matrix = dict.get_resolved(self.doc, "Matrix")?.and_then(|matrix| Matrix::from_node(&matrix)).unwrap_or(IDENTITY);
+        // forms without resources of their own use the page's
+        let form_resources = match dict.get_resolved(self.doc, "Resources")? {
+            Some(form_resources @ DictNode::Dict(_)) => form_resources,
+            _ => resources.clone(),
+        };
+
+        let depth = interpreter.stack.len();
+        interpreter.stack.push(interpreter.state.clone());
+        let (text_matrix, line_matrix) = (interpreter.text_matrix, interpreter.line_matrix);
+        interpreter.state.ctm = matrix * interpreter.state.ctm;
+        self.forms.push(node);
+        let result = self.run(interpreter, &content, &form_resources);
+        self.forms.pop();
+        // whatever the form left on the stack goes with it
+        interpreter.stack.truncate(depth + 1);
+        if let Some(state) = interpreter.stack.pop() {
+            interpreter.state = state;
+        }
+        interpreter.text_matrix = text_matrix;
+        interpreter.line_matrix = line_matrix;
+        result
+    }
+}
+
+#[cfg(test)]
+fn run(fonts: Vec<Font>, content: &[u8]) -> Vec<TextSpan> {
+    interpreter(fonts, content).spans
+}
+
+#[cfg(test)]
+fn interpreter(fonts: Vec<Font>, content: &[u8]) -> Interpreter {
+    let fonts: Vec<Rc<Font>> = fonts.into_iter().map(Rc::new).collect();
+    let mut interpreter = Interpreter::new(IDENTITY);
+    for op in Operations::new(content).ops() {
+        let op = op.unwrap();
+        if let Op::SetFont { ref name, .. } = op {
+            // /F1 is the first font and so on
+            interpreter.state.font = fonts.get(name[1..].parse::<usize>().unwrap() - 1).cloned();
+        }
+        interpreter.apply(&op);
+    }
+    interpreter
+}
+
+#[cfg(test)]
+fn span(text: &str, start: (f64, f64), end: (f64, f64), size: f64) -> TextSpan {
+    TextSpan { text: text.to_string(), start, end, size, font: "Helvetica".to_string(), color: Color::Gray(0.0), render_mode: 0 }
+}
+
+#[test]
+fn test_layout() {
+    let spans = vec![
+        span("Hello", (72.0, 700.0), (100.0, 700.0), 10.0),
+        // TJ moved it along without a space character
+        span("world", (103.0, 700.0), (130.0, 700.0), 10.0),
+        span("!", (130.2, 700.0), (133.0, 700.0), 10.0),
+        span("Next line ", (72.0, 688.0), (120.0, 688.0), 10.0),
+        span(" \u{fb01}ne", (120.0, 688.0), (140.0, 688.0), 10.0),
+        span("   ", (72.0, 100.0), (80.0, 100.0), 10.0),
+    ];
+    assert_eq!(layout(&spans), "Hello world!\nNext line  fine\n");
+    assert_eq!(layout(&[]), "");
+}
+
+#[test]
+fn test_json() {
+    use fixtures;
+
+    // float noise in the end position gets rounded away
+    let hello = span("Say \"hi\"\n\u{1}", (72.0, 700.0), (99.80000000001, 700.0), 12.0);
+    assert_eq!(hello.to_json(),
+        "{\"text\": \"Say \\\"hi\\\"\\n\\u0001\", \"x\": 72, \"y\": 700, \"width\": 27.8, \"height\": 12, \"font\": \"Helvetica\", \"size\": 12, \"color\": \"#000000\"}");
+    assert_eq!(json_number(f64::NAN), "0");
+    assert_eq!(json_number(-0.0001), "0");
+
+    let page = Page { index: 1, ..fixtures::page(pages::DEFAULT_MEDIA_BOX, 90) };
+    let hi = span("hi", (1.0, 2.0), (3.0, 2.0), 1.5);
+    assert_eq!(json(&[(&page, vec![hi.clone(), hi]), (&page, Vec::new())]), format!(
+        "[\n{{\"page\": 2, \"crop_box\": [0, 0, 612, 792], \"rotate\": 90, \"spans\": [\n  {},\n  {}\n]}},\n\
+         {{\"page\": 2, \"crop_box\": [0, 0, 612, 792], \"rotate\": 90, \"spans\": [\n]}}\n]\n",
+        "{\"text\": \"hi\", \"x\": 1, \"y\": 2, \"width\": 2, \"height\": 1.5, \"font\": \"Helvetica\", \"size\": 1.5, \"color\": \"#000000\"}",
+        "{\"text\": \"hi\", \"x\": 1, \"y\": 2, \"width\": 2, \"height\": 1.5, \"font\": \"Helvetica\", \"size\": 1.5, \"color\": \"#000000\"}"));
+}
+
+#[test]
+fn test_show() {
+    let widths = DictNode::Array(vec![DictNode::Int(250), DictNode::Int(600), DictNode::Int(500)]);
+    let font = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("TrueType"),
+        "BaseFont".to_string() => DictNode::name("Arial"),
+        "FirstChar".to_string() => DictNode::Int(32),
+        "Widths".to_string() => widths,
+    });
+    let fonts = vec![Font::new(&font, None, None, None)];
+    let spans = run(fonts, b"BT /F1 10 Tf 2 0 0 2 100 700 Tm ( !\"\x80) Tj [(A) -500 (B)] TJ 0 -20 TD (C) Tj T* (D) Tj (E) ' ET");
+    assert_eq!(spans.iter().map(|span| span.text.as_str()).collect::<Vec<_>>(), vec![" !\"€", "A", "B", "C", "D", "E"]);
+    // 250 + 600 + 500 + 500 thousandths of 10pt, twice as big
+    assert_eq!(spans[0].start, (100.0, 700.0));
+    assert_eq!(spans[0].end, (137.0, 700.0));
+    assert_eq!(spans[0].size, 20.0);
+    // the TJ adjustment pushes B half an em further along
+    assert_eq!(spans[2].start.0 - spans[1].end.0, 10.0);
+    assert_eq!(spans[3].start, (100.0, 660.0));
+    // T* and ' use the leading TD set
+    assert_eq!(spans[4].start, (100.0, 620.0));
+    assert_eq!(spans[5].start, (100.0, 580.0));
+    assert_eq!(spans[0].font, "Arial");
+}
+
+#[test]
+fn test_standard_widths() {
+    let helvetica = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Type1"),
+        "BaseFont".to_string() => DictNode::name("Helvetica"),
+    });
+    let fonts = vec![Font::new(&helvetica, None, None, None)];
+    let spans = run(fonts, b"BT /F1 12 Tf 72 720 Td (Hello ) Tj (World) Tj ET");
+    // H e l l o and a space: 722 + 556 + 222 + 222 + 556 + 278 thousandths of 12pt
+    assert!((spans[1].start.0 - 102.672).abs() < 1e-9);
+    // W o r l d: 944 + 556 + 333 + 222 + 556
+    assert!((spans[1].end.0 - spans[1].start.0 - 31.332).abs() < 1e-9);
+
+    // codes past printable ASCII, and fonts that aren't standard, fall back
+    let unknown = DictNode::Dict(hashmap!{ "BaseFont".to_string() => DictNode::name("Frutiger") });
+    let fonts = vec![Font::new(&helvetica, None, None, None), Font::new(&unknown, None, None, None)];
+    let spans = run(fonts, b"BT /F1 10 Tf (\xe9) Tj /F2 10 Tf (H) Tj ET");
+    assert_eq!(spans[0].end.0 - spans[0].start.0, 5.0);
+    assert_eq!(spans[1].end.0 - spans[1].start.0, 5.0);
+}
+
+#[test]
+fn test_show_state() {
+    let composite = DictNode::Dict(hashmap!{ "Subtype".to_string() => DictNode::name("Type0") });
+    let cmap = CMap::parse(b"1 begincodespacerange <0000> <FFFF> endcodespacerange 1 beginbfchar <0102> <0041> endbfchar");
+    let fonts = vec![Font::fallback(), Font::new(&composite, None, Some(cmap), None)];
+    let spans = run(fonts, b"q 1 0 0 1 50 50 cm 1 0 0 rg BT /F1 12 Tf 2 Tc (ab) Tj ET Q BT /F2 10 Tf 3 Tr <01020304> Tj ET");
+    assert_eq!(spans[0].start, (50.0, 50.0));
+    // 500 thousandths of 12pt plus 2 of spacing, twice
+    assert_eq!(spans[0].end, (66.0, 50.0));
+    assert_eq!(spans[0].color, Color::Rgb(1.0, 0.0, 0.0));
+    assert_eq!(spans[1].text, "A\u{fffd}");
+    assert_eq!(spans[1].start, (0.0, 0.0));
+    assert_eq!(spans[1].end, (20.0, 0.0));
+    // Q took the red away
+    assert_eq!(spans[1].color, Color::Gray(0.0));
+    assert_eq!((spans[0].render_mode, spans[1].render_mode), (0, 3));
+}
+
+#[test]
+fn test_coverage() {
+    let composite = DictNode::Dict(hashmap!{ "Subtype".to_string() => DictNode::name("Type0") });
+    let cmap = CMap::parse(b"1 begincodespacerange <0000> <FFFF> endcodespacerange 2 beginbfchar <0102> <0041> <0103> <E001> endbfchar");
+    let mut symbols = Font::new(&composite, None, Some(cmap), None);
+    symbols.name = "Symbols".to_string();
+    let fonts = vec![Font::fallback(), symbols];
+    let coverage = interpreter(fonts, b"BT /F1 12 Tf (ab) Tj () Tj /F2 10 Tf <0102> Tj <01020304> Tj <0103> Tj ET").coverage;
+    // the empty string isn't a run
+    assert_eq!((coverage.runs, coverage.mapped_runs), (4, 2));
+    assert_eq!((coverage.glyphs, coverage.mapped_glyphs), (6, 4));
+    assert_eq!(coverage.unmapped_fonts, btreemap!{ "Symbols".to_string() => 2 });
+    assert_eq!(coverage.fraction(), 0.5);
+    assert_eq!(Coverage::default().fraction(), 1.0);
+}
+
+#[test]
+fn test_cid_fonts() {
+    let descendant = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("CIDFontType2"),
+        "W".to_string() => DictNode::Array(vec![DictNode::Int(34), DictNode::Array(vec![DictNode::Int(600)])]),
+        "DW".to_string() => DictNode::Int(500),
+    });
+    let composite = |encoding: &str| DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Type0"),
+        "Encoding".to_string() => DictNode::name(encoding),
+        "DescendantFonts".to_string() => DictNode::Array(vec![descendant.clone()]),
+    });
+    // one byte codes below 0x81, two byte ones from there
+    let encoding = CMap::parse(b"2 begincodespacerange <00> <80> <8140> <FFFF> endcodespacerange
+        1 begincidrange <20> <7e> 1 endcidrange 1 begincidchar <8140> 100 endcidchar");
+    let to_unicode = CMap::parse(b"1 beginbfchar <8140> <3042> endbfchar 1 beginbfrange <20> <7e> <0020> endbfrange");
+    let mixed = Font::new(&composite("Custom-H"), None, Some(to_unicode), Some(encoding));
+    let unicode = Font::new(&composite("UniJIS-UCS2-H"), None, None, None);
+    let spans = run(vec![mixed, unicode], b"BT /F1 10 Tf (A\x81\x40B) Tj ET BT /F2 10 Tf <30423044> Tj ET");
+    assert_eq!(spans[0].text, "A\u{3042}B");
+    // A is CID 34, 600 wide, the others take /DW
+    assert_eq!(spans[0].end.0, 16.0);
+    assert_eq!(spans[1].text, "\u{3042}\u{3044}");
+    assert_eq!(spans[1].end.0, 10.0);
+}
+
+#[test]
+fn test_shown_codes() {
+    let composite = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Type0"),
+        "Encoding".to_string() => DictNode::name("Custom-H"),
+        "DescendantFonts".to_string() => DictNode::Array(vec![DictNode::Dict(hashmap!{
+            "Subtype".to_string() => DictNode::name("CIDFontType2"),
+        })]),
+    });
+    // one byte codes below 0x81, two byte ones from there
+    let encoding = CMap::parse(b"2 begincodespacerange <00> <80> <8140> <FFFF> endcodespacerange
+        1 begincidrange <20> <7e> 1 endcidrange 1 begincidchar <8140> 100 endcidchar");
+    let mut mixed = Font::new(&composite, None, None, Some(encoding));
+    mixed.reference = Some((7, 0));
+    let simple = Font::new(&DictNode::Dict(HashMap::new()), None, None, None);
+
+    // each code once per font, split the way the encoding splits them, and
+    // only for fonts that came from an object
+    let interpreter = interpreter(vec![mixed, simple], b"BT /F1 10 Tf (A\x81\x40B) Tj (BA) Tj /F2 10 Tf (C) Tj ET");
+    let shown: BTreeSet<Vec<u8>> = vec![b"A".to_vec(), b"\x81\x40".to_vec(), b"B".to_vec()].into_iter().collect();
+    assert_eq!(interpreter.codes, hashmap!{ (7, 0) => shown });
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/*
+ * Where the time goes when processing a document, so a slow file can be
+ * pinned on parsing or on decoding streams. Phases don't overlap: time
+ * spent decoding the xref stream counts as decode rather than xref, for
+ * instance.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Phase {
+    // finding and reading the xref table(s) and trailer
+    XRef,
+    // parsing object dictionaries
+    Parse,
+    // running stream filters
+    Decode,
+    // turning content streams into text
+    Text,
+    // writing output files
+    Write,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Timings {
+    pub xref: Duration,
+    pub parse: Duration,
+    pub decode: Duration,
+    pub text: Duration,
+    pub write: Duration,
+}
+
+impl Timings {
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        *self.phase_mut(phase) += elapsed;
+    }
+
+    // Runs `f`, counting the time it takes towards `phase`.
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: Phase, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    // Adds up timings collected separately, e.g. on another thread.
+    pub fn merge(&mut self, other: &Timings) {
+        self.xref += other.xref;
+        self.parse += other.parse;
+        self.decode += other.decode;
+        self.text += other.text;
+        self.write += other.write;
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::XRef => self.xref,
+            Phase::Parse => self.parse,
+            Phase::Decode => self.decode,
+            Phase::Text => self.text,
+            Phase::Write => self.write,
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.xref + self.parse + self.decode + self.text + self.write
+    }
+
+    fn phase_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::XRef => &mut self.xref,
+            Phase::Parse => &mut self.parse,
+            Phase::Decode => &mut self.decode,
+            Phase::Text => &mut self.text,
+            Phase::Write => &mut self.write,
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total().as_secs_f64();
+        let phases = [
+            ("xref", Phase::XRef),
+            ("parse", Phase::Parse),
+            ("decode", Phase::Decode),
+            ("text", Phase::Text),
+            ("write", Phase::Write),
+        ];
+        for &(name, phase) in &phases {
+            let seconds = self.get(phase).as_secs_f64();
+            let share = if total > 0.0 { seconds / total * 100.0 } else { 0.0 };
+            writeln!(f, "{:<8}{:>10.3}s {:>5.1}%", name, seconds, share)?;
+        }
+        write!(f, "{:<8}{:>10.3}s", "total", total)
+    }
+}
+
+#[test]
+fn test_timings() {
+    let mut timings = Timings::default();
+    timings.add(Phase::Parse, Duration::from_millis(30));
+    timings.add(Phase::Decode, Duration::from_millis(10));
+    assert_eq!(timings.time(Phase::Write, || 42), 42);
+    timings.add(Phase::Parse, Duration::from_millis(20));
+    assert_eq!(timings.parse, Duration::from_millis(50));
+    assert!(timings.total() >= Duration::from_millis(60));
+
+    let report = timings.to_string();
+    assert!(report.contains("parse        0.050s"));
+    assert!(report.lines().last().unwrap().starts_with("total"));
+}
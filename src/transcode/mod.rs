@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use document::{Document, Options};
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+use parsers::xref::{XRefEntry, XRefTable};
+
+/*
+ * Rewrites the cross-reference representation of a file and nothing else.
+ *
+ * The original bytes are copied through untouched and a single new xref
+ * section, covering every object and without a /Prev, is appended after
+ * them: readers go by the last startxref, so the old sections just become
+ * dead weight. The only other edits are the ones the target format forces:
+ *
+ *   - xref streams need PDF 1.5, older headers are bumped in place (same
+ *     length, so no offsets move)
+ *   - classic tables can't point into object streams, objects living in one
+ *     are written back out as plain objects after the original data
+ *
+ * Offsets are u64 throughout. A table entry has ten digits for one, so
+ * past 9999999999 bytes (about 9.3 GB) only an xref stream will do: asking
+ * for a table there is an error, and `preferred_format` picks a stream.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum XRefFormat {
+    Table,
+    Stream,
+}
+
+// Trailer keys that describe the document rather than the xref section they
+// happened to be written with.
+const TRAILER_KEYS: &[&str] = &["Root", "Info", "ID", "Encrypt"];
+
+// The largest offset a table entry's ten digits hold.
+pub(crate) const MAX_TABLE_OFFSET: u64 = 9_999_999_999;
+
+pub fn transcode(input: &Path, output: &Path, format: XRefFormat) -> Result<()> {
+    let mut doc = Document::open(input, Options::default())?;
+    let mut data = fs::read(input)?;
+    if format == XRefFormat::Stream {
+        bump_version(&mut data, (1, 5));
+    }
+    let xref = doc.xref.clone();
+    append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// Appends a single xref section for `xref` in `format` to `data`, which
+// holds the file `doc` was opened from plus anything already appended to it.
+pub(crate) fn append_xref(doc: &mut Document, data: &mut Vec<u8>, mut xref: XRefTable, format: XRefFormat) -> Result<()> {
+    if !data.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    if format == XRefFormat::Table {
+        let compressed: Vec<(usize, XRefEntry)> = xref.entries()
+            .filter(|&(_, entry)| !entry.is_free && entry.object_stream.is_some())
+            .map(|(id, entry)| (id, entry.clone()))
+            .collect();
+        // they'd come out decrypted, and writing them encrypted again isn't
+        // something we do
+        if !compressed.is_empty() && doc.is_encrypted() {
+            return Err(PdfError::Encryption("objects in object streams can't be moved out of an encrypted file".to_string()));
+        }
+        for (id, entry) in compressed {
+            let object = doc.object(&entry)?;
+            append_object(data, &mut xref, id as u64, &object)?;
+        }
+        if !fits_table(&xref) {
+            return Err(PdfError::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                "offsets past 9999999999 don't fit an xref table, it takes an xref stream")));
+        }
+    }
+
+    let trailer = section_trailer(&doc.trailer);
+    let section = match format {
+        XRefFormat::Table => format_table(&xref, trailer, data.len() as u64),
+        XRefFormat::Stream => format_stream(&xref, trailer, data.len() as u64),
+    };
+    data.extend(section);
+    Ok(())
+}
+
+// Appends `object` to `data` as object `id` and points `xref` at it.
+pub(crate) fn append_object(data: &mut Vec<u8>, xref: &mut XRefTable, id: u64, object: &DictNode) -> Result<()> {
+    if !data.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    xref.set(id as usize, XRefEntry::in_use(data.len() as u64, 0));
+    write!(data, "{} 0 obj\n{}\nendobj\n", id, object)?;
+    Ok(())
+}
+
+// Same for a stream object, `dict` should carry the data's /Length.
+pub(crate) fn append_stream(data: &mut Vec<u8>, xref: &mut XRefTable, id: u64, dict: &DictNode, stream: &[u8]) -> Result<()> {
+    if !data.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    xref.set(id as usize, XRefEntry::in_use(data.len() as u64, 0));
+    write!(data, "{} 0 obj\n{}\nstream\n", id, dict)?;
+    data.extend_from_slice(stream);
+    data.extend_from_slice(b"\nendstream\nendobj\n");
+    Ok(())
+}
+
+// The format to append a section in for files that get rewritten without
+// asking for one: an xref stream if some objects live in object streams,
+// or some offset is too long for a table, since a table can't point at
+// them, a table otherwise.
+pub(crate) fn preferred_format(xref: &XRefTable) -> XRefFormat {
+    if xref.entries().any(|(_, entry)| !entry.is_free && entry.object_stream.is_some()) || !fits_table(xref) {
+        XRefFormat::Stream
+    } else {
+        XRefFormat::Table
+    }
+}
+
+// Whether every offset in `xref` fits a table entry.
+pub(crate) fn fits_table(xref: &XRefTable) -> bool {
+    xref.entries().all(|(_, entry)| entry.is_free || entry.object_stream.is_some() || entry.offset <= MAX_TABLE_OFFSET)
+}
+
+// The PDF version from the "%PDF-M.m" header, if there is one.
+pub fn header_version(data: &[u8]) -> Option<(u8, u8)> {
+    match data.get(..8) {
+        Some(&[b'%', b'P', b'D', b'F', b'-', major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit() => {
+            Some((major - b'0', minor - b'0'))
+        },
+        _ => None,
+    }
+}
+
+// Raises the header version to at least `version`.
+pub(crate) fn bump_version(data: &mut [u8], version: (u8, u8)) {
+    if header_version(data).is_some_and(|current| current < version) {
+        set_version(data, version);
+    }
+}
+
+// Rewrites the header version in place, files without a header are left
+// alone.
+pub(crate) fn set_version(data: &mut [u8], version: (u8, u8)) {
+    if header_version(data).is_some() {
+        data[5] = b'0' + version.0;
+        data[7] = b'0' + version.1;
+    }
+}
+
+fn section_trailer(trailer: &DictNode) -> HashMap<String, DictNode> {
+    match *trailer {
+        DictNode::Dict(ref map) => map.iter()
+            .filter(|&(key, _)| TRAILER_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+// A classic "xref" table with a single subsection and its trailer, to be
+// written at `offset`.
+pub(crate) fn format_table(xref: &XRefTable, mut trailer: HashMap<String, DictNode>, offset: u64) -> Vec<u8> {
+    let size = xref.len().max(1);
+    let mut out = format!("xref\n0 {}\n", size);
+    for id in 0..size {
+        match xref.get(id) {
+            Some(entry) if !entry.is_free => out.push_str(&format!("{:010} {:05} n\r\n", entry.offset, entry.generation_id)),
+            _ if id == 0 => out.push_str("0000000000 65535 f\r\n"),
+            _ => out.push_str("0000000000 00000 f\r\n"),
+        }
+    }
+    trailer.insert("Size".to_string(), DictNode::Int(size as i64));
+    out.push_str(&format!("trailer\n{}\nstartxref\n{}\n%%EOF\n", DictNode::Dict(trailer), offset));
+    out.into_bytes()
+}
+
+// An uncompressed xref stream, stored as a new object numbered after every
+// existing one, to be written at `offset`.
+pub(crate) fn format_stream(xref: &XRefTable, mut trailer: HashMap<String, DictNode>, offset: u64) -> Vec<u8> {
+    let id = xref.len().max(1);
+    let size = id + 1;
+    let mut entries: Vec<(u8, u64, u64)> = (0..id)
+        .map(|id| match xref.get(id) {
+            Some(entry) if !entry.is_free => match entry.object_stream {
+                Some(stream) => (2, stream, entry.offset),
+                None => (1, entry.offset, entry.generation_id),
+            },
+            _ if id == 0 => (0, 0, 65535),
+            _ => (0, 0, 0),
+        })
+        .collect();
+    entries.push((1, offset, 0));
+
+    let widths = [
+        1,
+        byte_width(entries.iter().map(|entry| entry.1).max().unwrap_or(0)),
+        byte_width(entries.iter().map(|entry| entry.2).max().unwrap_or(0)),
+    ];
+    let mut body = Vec::new();
+    for (kind, field, extra) in entries {
+        body.push(kind);
+        push_be(&mut body, field, widths[1]);
+        push_be(&mut body, extra, widths[2]);
+    }
+
+    trailer.insert("Type".to_string(), DictNode::name("XRef"));
+    trailer.insert("Size".to_string(), DictNode::Int(size as i64));
+    trailer.insert("W".to_string(), DictNode::Array(widths.iter().map(|&width| DictNode::Int(width as i64)).collect()));
+    trailer.insert("Length".to_string(), DictNode::Int(body.len() as i64));
+
+    let mut out = format!("{} 0 obj\n{}\nstream\n", id, DictNode::Dict(trailer)).into_bytes();
+    out.extend(body);
+    out.extend(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", offset).into_bytes());
+    out
+}
+
+// How many bytes it takes to store `value` big-endian, at least one.
+pub(crate) fn byte_width(value: u64) -> usize {
+    (1..8).find(|&width| value >> (8 * width) == 0).unwrap_or(8)
+}
+
+pub(crate) fn push_be(out: &mut Vec<u8>, value: u64, width: usize) {
+    for shift in (0..width).rev() {
+        out.push((value >> (8 * shift)) as u8);
+    }
+}
+
+#[test]
+fn test_header_version() {
+    let mut data = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    assert_eq!(header_version(&data), Some((1, 4)));
+    bump_version(&mut data, (1, 5));
+    assert_eq!(&data[..8], b"%PDF-1.5");
+    bump_version(&mut data, (1, 3));
+    assert_eq!(&data[..8], b"%PDF-1.5");
+    assert_eq!(header_version(b"garbage"), None);
+}
+
+#[test]
+fn test_format_table() {
+    let xref = XRefTable::new(vec![XRefEntry::free(), XRefEntry::in_use(15, 0), XRefEntry::free(), XRefEntry::in_use(120, 2)]);
+    let trailer = hashmap!{ "Root".to_string() => DictNode::reference(1) };
+    let table = String::from_utf8(format_table(&xref, trailer, 400)).unwrap();
+    assert_eq!(table, "xref\n0 4\n\
+                       0000000000 65535 f\r\n\
+                       0000000015 00000 n\r\n\
+                       0000000000 00000 f\r\n\
+                       0000000120 00002 n\r\n\
+                       trailer\n<< /Root 1 0 R /Size 4 >>\nstartxref\n400\n%%EOF\n");
+}
+
+#[test]
+fn test_preferred_format() {
+    let mut xref = XRefTable::new(vec![XRefEntry::free(), XRefEntry::in_use(15, 0), XRefEntry::in_use(MAX_TABLE_OFFSET, 0)]);
+    assert_eq!(preferred_format(&xref), XRefFormat::Table);
+    // past 2^32 still fits ten digits
+    xref.set(2, XRefEntry::in_use(5_000_000_000, 0));
+    assert_eq!(preferred_format(&xref), XRefFormat::Table);
+    xref.set(3, XRefEntry::in_use(MAX_TABLE_OFFSET + 1, 0));
+    assert!(!fits_table(&xref));
+    assert_eq!(preferred_format(&xref), XRefFormat::Stream);
+    xref.set(3, XRefEntry::compressed(1, 0));
+    assert_eq!(preferred_format(&xref), XRefFormat::Stream);
+
+    // and the stream's fields are wide enough for them
+    xref.set(3, XRefEntry::in_use(0x0123_4567_89AB, 0));
+    let stream = format_stream(&xref, HashMap::new(), 0x0123_4567_9000);
+    assert!(String::from_utf8_lossy(&stream).contains("/W [1 6 2]"));
+}
+
+#[test]
+fn test_format_stream() {
+    let xref = XRefTable::new(vec![XRefEntry::free(), XRefEntry::in_use(15, 0), XRefEntry::compressed(3, 0), XRefEntry::in_use(70000, 0)]);
+    let trailer = hashmap!{ "Root".to_string() => DictNode::reference(1) };
+    let stream = format_stream(&xref, trailer, 80000);
+    let text = String::from_utf8_lossy(&stream);
+    assert!(text.starts_with("4 0 obj\n<< /Length 30 /Root 1 0 R /Size 5 /Type /XRef /W [1 3 2] >>\nstream\n"));
+    assert!(text.ends_with("\nendstream\nendobj\nstartxref\n80000\n%%EOF\n"));
+
+    let start = text.find(">>\nstream\n").unwrap() + 10;
+    let dict = DictNode::Dict(hashmap!{
+        "Size".to_string() => DictNode::Int(5),
+        "W".to_string() => DictNode::Array(vec![DictNode::Int(1), DictNode::Int(3), DictNode::Int(2)]),
+    });
+    let parsed = ::parsers::xref::parse_stream(&dict, &stream[start..start + 30]).unwrap();
+    assert_eq!(parsed.get(2), Some(&XRefEntry::compressed(3, 0)));
+    assert_eq!(parsed.get(3), Some(&XRefEntry::in_use(70000, 0)));
+    assert_eq!(parsed.get(4), Some(&XRefEntry::in_use(80000, 0)));
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use document::Document;
+use error::Result;
+use parsers::cos::DictNode;
+
+/*
+ * Builders for name trees (/Dests, /EmbeddedFiles...) and number trees
+ * (/PageLabels...), so everything that writes one shares the same
+ * balancing and /Limits bookkeeping.
+ *
+ *   let mut dests = TreeBuilder::new();
+ *   dests.insert(b"chapter1".to_vec(), destination);
+ *   let tree = dests.build(next_free_id);
+ *
+ * Keys are sorted (names bytewise, numbers numerically) and a key inserted
+ * twice keeps its last value. Small trees fit entirely in the root, bigger
+ * ones get leaves of at most `fanout` entries under intermediate nodes of at
+ * most `fanout` kids. The root is returned on its own, for the caller to
+ * put in place or write out, and every other node comes numbered for
+ * writing as an indirect object.
+ *
+ * `read_name_tree` goes the other way, flattening a name tree in a document
+ * into its entries. /Limits aren't trusted, every node is read.
+ */
+
+// How deep `read_name_tree` follows /Kids, trees are rarely more than
+// three or four levels.
+const MAX_DEPTH: usize = 32;
+
+pub const DEFAULT_FANOUT: usize = 64;
+
+// What can key a tree, and how it's written.
+pub trait TreeKey: Ord + Clone {
+    // "Names" or "Nums", the leaf array's key
+    fn array_key() -> &'static str;
+    fn to_node(&self) -> DictNode;
+}
+
+impl TreeKey for Vec<u8> {
+    fn array_key() -> &'static str {
+        "Names"
+    }
+
+    fn to_node(&self) -> DictNode {
+        DictNode::LiteralString(self.clone())
+    }
+}
+
+impl TreeKey for i64 {
+    fn array_key() -> &'static str {
+        "Nums"
+    }
+
+    fn to_node(&self) -> DictNode {
+        DictNode::Int(*self)
+    }
+}
+
+pub type NameTreeBuilder = TreeBuilder<Vec<u8>>;
+pub type NumberTreeBuilder = TreeBuilder<i64>;
+
+pub struct TreeBuilder<K: TreeKey> {
+    entries: BTreeMap<K, DictNode>,
+    fanout: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Tree {
+    pub root: DictNode,
+    // (object number, node), numbered upwards from the id given to `build`
+    pub nodes: Vec<(u64, DictNode)>,
+}
+
+// A node built but not placed yet: its object number, the first and last
+// keys below it, and the node itself.
+struct Built<K> {
+    id: u64,
+    first: K,
+    last: K,
+    node: HashMap<String, DictNode>,
+}
+
+impl<K: TreeKey> TreeBuilder<K> {
+    pub fn new() -> TreeBuilder<K> {
+        TreeBuilder::with_fanout(DEFAULT_FANOUT)
+    }
+
+    // `fanout` is the most entries per leaf and kids per node, at least 2.
+    pub fn with_fanout(fanout: usize) -> TreeBuilder<K> {
+        TreeBuilder { entries: BTreeMap::new(), fanout: fanout.max(2) }
+    }
+
+    pub fn insert(&mut self, key: K, value: DictNode) {
+        self.entries.insert(key, value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Lays out the tree, numbering the nodes that aren't the root from
+    // `first_id`.
+    pub fn build(self, first_id: u64) -> Tree {
+        let entries: Vec<(K, DictNode)> = self.entries.into_iter().collect();
+        if entries.len() <= self.fanout {
+            let mut root = HashMap::new();
+            root.insert(K::array_key().to_string(), flatten(&entries));
+            return Tree { root: DictNode::Dict(root), nodes: Vec::new() };
+        }
+
+        let mut next_id = first_id;
+        let mut nodes = Vec::new();
+        let mut level: Vec<Built<K>> = entries.chunks(self.fanout)
+            .map(|chunk| {
+                let mut node = HashMap::new();
+                node.insert(K::array_key().to_string(), flatten(chunk));
+                next_id += 1;
+                Built { id: next_id - 1, first: chunk[0].0.clone(), last: chunk[chunk.len() - 1].0.clone(), node }
+            })
+            .collect();
+
+        while level.len() > self.fanout {
+            let mut parents = Vec::new();
+            for chunk in chunk_owned(level, self.fanout) {
+                let mut node = HashMap::new();
+                node.insert("Kids".to_string(), kids(&chunk));
+                let parent = Built { id: next_id, first: chunk[0].first.clone(), last: chunk[chunk.len() - 1].last.clone(), node };
+                next_id += 1;
+                nodes.extend(chunk.into_iter().map(finish));
+                parents.push(parent);
+            }
+            level = parents;
+        }
+
+        let mut root = HashMap::new();
+        root.insert("Kids".to_string(), kids(&level));
+        nodes.extend(level.into_iter().map(finish));
+        nodes.sort_by_key(|&(id, _)| id);
+        Tree { root: DictNode::Dict(root), nodes }
+    }
+}
+
+impl<K: TreeKey> Default for TreeBuilder<K> {
+    fn default() -> TreeBuilder<K> {
+        TreeBuilder::new()
+    }
+}
+
+// The entries of the name tree at `root`, in the order its leaves have
+// them. Values are left as they are, references included.
+pub fn read_name_tree(doc: &mut Document, root: &DictNode) -> Result<Vec<(Vec<u8>, DictNode)>> {
+    let mut entries = Vec::new();
+    read_node(doc, root, 0, &mut HashSet::new(), &mut entries)?;
+    Ok(entries)
+}
+
+fn read_node(doc: &mut Document, node: &DictNode, depth: usize, seen: &mut HashSet<i64>,
+             entries: &mut Vec<(Vec<u8>, DictNode)>) -> Result<()> {
+    if let DictNode::ObjectReference(id, _) = *node {
+        if !seen.insert(id) {
+            return Ok(());
+        }
+    }
+    let node = doc.resolve(node)?;
+    if let Some(names) = node.get_resolved(doc, "Names")? {
+        for pair in names.as_array().unwrap_or(&[]).chunks(2) {
+            if let (Some(key), Some(value)) = (pair[0].as_string(), pair.get(1)) {
+                entries.push((key.to_vec(), value.clone()));
+            }
+        }
+    }
+    if depth < MAX_DEPTH {
+        if let Some(kids) = node.get_resolved(doc, "Kids")? {
+            for kid in kids.as_array().unwrap_or(&[]) {
+                read_node(doc, kid, depth + 1, seen, entries)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// [key1 value1 key2 value2 ...]
+fn flatten<K: TreeKey>(entries: &[(K, DictNode)]) -> DictNode {
+    DictNode::Array(entries.iter().flat_map(|(key, value)| vec![key.to_node(), value.clone()]).collect())
+}
+
+fn kids<K>(nodes: &[Built<K>]) -> DictNode {
+    DictNode::Array(nodes.iter().map(|built| DictNode::reference(built.id)).collect())
+}
+
+// Every node but the root carries the range of keys below it.
+fn finish<K: TreeKey>(built: Built<K>) -> (u64, DictNode) {
+    let mut node = built.node;
+    node.insert("Limits".to_string(), DictNode::Array(vec![built.first.to_node(), built.last.to_node()]));
+    (built.id, DictNode::Dict(node))
+}
+
+fn chunk_owned<T>(items: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut items = items.into_iter().peekable();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(size).collect());
+    }
+    chunks
+}
+
+#[test]
+fn test_small_tree() {
+    let mut tree = NumberTreeBuilder::new();
+    tree.insert(5, DictNode::name("Roman"));
+    tree.insert(0, DictNode::name("Decimal"));
+    tree.insert(5, DictNode::name("Alpha"));
+    let tree = tree.build(10);
+    assert!(tree.nodes.is_empty());
+    assert_eq!(tree.root, DictNode::Dict(hashmap!{
+        "Nums".to_string() => DictNode::Array(vec![
+            DictNode::Int(0), DictNode::name("Decimal"),
+            DictNode::Int(5), DictNode::name("Alpha"),
+        ]),
+    }));
+}
+
+#[test]
+fn test_balanced_tree() {
+    let mut tree = NameTreeBuilder::with_fanout(2);
+    for name in &["e", "a", "c", "b", "d"] {
+        tree.insert(name.as_bytes().to_vec(), DictNode::Null);
+    }
+    let tree = tree.build(100);
+    let limits = |first: &str, last: &str| DictNode::Array(vec![
+        DictNode::LiteralString(first.as_bytes().to_vec()),
+        DictNode::LiteralString(last.as_bytes().to_vec()),
+    ]);
+
+    // leaves [a b] [c d] [e], then [leaf leaf] [leaf] under the root
+    assert_eq!(tree.root, DictNode::Dict(hashmap!{ "Kids".to_string() => DictNode::Array(vec![DictNode::reference(103), DictNode::reference(104)]) }));
+    let ids: Vec<u64> = tree.nodes.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, vec![100, 101, 102, 103, 104]);
+    assert_eq!(tree.nodes[0].1, DictNode::Dict(hashmap!{
+        "Limits".to_string() => limits("a", "b"),
+        "Names".to_string() => DictNode::Array(vec![
+            DictNode::LiteralString(b"a".to_vec()), DictNode::Null,
+            DictNode::LiteralString(b"b".to_vec()), DictNode::Null,
+        ]),
+    }));
+    assert_eq!(tree.nodes[3].1, DictNode::Dict(hashmap!{
+        "Limits".to_string() => limits("a", "d"),
+        "Kids".to_string() => DictNode::Array(vec![DictNode::reference(100), DictNode::reference(101)]),
+    }));
+    assert_eq!(tree.nodes[4].1, DictNode::Dict(hashmap!{
+        "Limits".to_string() => limits("e", "e"),
+        "Kids".to_string() => DictNode::Array(vec![DictNode::reference(102)]),
+    }));
+}
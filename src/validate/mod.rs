@@ -0,0 +1,340 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use document::{self, Document, Options, Warning};
+use error::PdfError;
+use parsers::cos::DictNode;
+
+/*
+ * Structural validation, meant to be called from the test suites of
+ * programs that generate PDFs:
+ *
+ *   let report = nails_pdf::validate::validate(path, Profile::Full);
+ *   assert!(report.is_valid(), "{}", report);
+ *
+ * Every issue carries a rule id that won't change between releases, so
+ * callers can allow specific rules:
+ *
+ *   file.header        error    no %PDF- header in the first 1024 bytes
+ *   file.unreadable    error    the file couldn't be read at all
+ *   xref.startxref     error    no startxref at the end of the file
+ *   xref.unreadable    error    the xref table or stream couldn't be parsed
+ *   xref.prev          error    a /Prev xref section couldn't be read
+ *   xref.stream        error    the /XRefStm stream of a hybrid file couldn't be read
+ *   trailer.root       error    the trailer has no /Root reference
+ *   trailer.size       warning  /Size is smaller than the xref table
+ *   trailer.rebuilt    error    the trailer had to be rebuilt from a scan of the file
+ *   pages.rebuilt      error    the page tree had to be rebuilt from a scan of the file
+ *   object.offset      error    an xref offset doesn't point at "N G obj" for that object
+ *   object.syntax      error    an object couldn't be parsed
+ *   object.endobj      warning  an object is missing its endobj
+ *   object.truncated   error    an object runs into the end of the file
+ *   object.duplicate   warning  one revision defines an object number more than once
+ *   stream.filter      warning  a stream uses a filter we can't decode (full only)
+ *   stream.decode      error    a stream's data doesn't decode (full only)
+ *
+ * An incremental update redefining an object is what updates are for, only
+ * definitions within the same revision (between the same two %%EOF
+ * markers) count as duplicates.
+ */
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Profile {
+    // the file structure: header, xref, trailer and every object
+    Structure,
+    // the structure, plus decoding every stream
+    Full,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Issue {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    // the object the issue is about, if it's about one
+    pub object: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    // True when there are no errors, warnings are allowed.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Warning)
+    }
+
+    pub fn has_rule(&self, rule: &str) -> bool {
+        self.issues.iter().any(|issue| issue.rule == rule)
+    }
+
+    fn error(&mut self, rule: &'static str, object: Option<u64>, message: String) {
+        self.issues.push(Issue { rule, severity: Severity::Error, message, object });
+    }
+
+    fn warning(&mut self, rule: &'static str, object: Option<u64>, message: String) {
+        self.issues.push(Issue { rule, severity: Severity::Warning, message, object });
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.object {
+            Some(id) => write!(f, "{} [{}] object {}: {}", severity, self.rule, id, self.message),
+            None => write!(f, "{} [{}] {}", severity, self.rule, self.message),
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        write!(f, "{} errors, {} warnings", self.errors().count(), self.warnings().count())
+    }
+}
+
+pub fn validate(path: &Path, profile: Profile) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            report.error("file.unreadable", None, err.to_string());
+            return report;
+        },
+    };
+
+    let mut head = Vec::new();
+    if let Err(err) = (&mut file).take(1024).read_to_end(&mut head) {
+        report.error("file.unreadable", None, err.to_string());
+        return report;
+    }
+    if !head.windows(5).any(|window| window == b"%PDF-") {
+        report.error("file.header", None, "no %PDF- header".to_string());
+    }
+
+    // lenient, so one problem doesn't hide all the others
+    let options = Options { lenient: true, ..Default::default() };
+    let mut doc = match Document::open(path, options) {
+        Ok(doc) => doc,
+        Err(err) => {
+            report.error("xref.unreadable", None, err.to_string());
+            return report;
+        },
+    };
+
+    check_trailer(&doc, &mut report);
+    check_objects(&mut doc, &mut file, profile, &mut report);
+    if let Err(err) = doc.scan_duplicates() {
+        report.error("file.unreadable", None, err.to_string());
+    }
+    let ends = match doc.file_data() {
+        Ok(data) => revision_ends(&data),
+        Err(err) => {
+            report.error("file.unreadable", None, err.to_string());
+            Vec::new()
+        },
+    };
+
+    for warning in doc.warnings() {
+        match *warning {
+            Warning::TruncatedDocument => {
+                report.error("xref.startxref", None, "no startxref, the file is probably truncated".to_string());
+            },
+            Warning::MissingEndobj { offset } => {
+                report.warning("object.endobj", None, format!("object at offset {} has no endobj", offset));
+            },
+            Warning::TruncatedObject { offset } => {
+                report.error("object.truncated", None, format!("object at offset {} runs into the end of the file", offset));
+            },
+            Warning::DuplicateObject { id, ref offsets } => {
+                let mut revisions: Vec<usize> = offsets.iter().map(|&offset| revision(&ends, offset)).collect();
+                revisions.dedup();
+                if revisions.len() < offsets.len() {
+                    report.warning("object.duplicate", Some(id), format!("defined {} times", offsets.len()));
+                }
+            },
+            Warning::BrokenPrevChain { offset } => {
+                report.error("xref.prev", None, format!("can't read the xref section at offset {}", offset));
+            },
+            Warning::BrokenXRefStm { offset } => {
+                report.error("xref.stream", None, format!("can't read the xref stream at offset {}", offset));
+            },
+            Warning::RebuiltTrailer => {
+                report.error("trailer.rebuilt", None, "the trailer was rebuilt by scanning the file".to_string());
+            },
+            Warning::RebuiltPageTree { pages } => {
+                report.error("pages.rebuilt", None, format!("no usable page tree, {} pages found by scanning", pages));
+            },
+        }
+    }
+    report
+}
+
+fn check_trailer(doc: &Document, report: &mut ValidationReport) {
+    let trailer = match doc.trailer {
+        DictNode::Dict(ref trailer) => trailer,
+        _ => return,
+    };
+    if !matches!(trailer.get("Root"), Some(&DictNode::ObjectReference(..))) {
+        report.error("trailer.root", None, "no /Root reference".to_string());
+    }
+    match trailer.get("Size") {
+        Some(&DictNode::Int(size)) if size >= 0 && size as usize >= doc.xref.len() => (),
+        _ => report.warning("trailer.size", None, format!("/Size doesn't cover the {} xref entries", doc.xref.len())),
+    }
+}
+
+fn check_objects(doc: &mut Document, file: &mut File, profile: Profile, report: &mut ValidationReport) {
+    let entries: Vec<_> = doc.xref.entries()
+        .filter(|&(_, entry)| !entry.is_free)
+        .map(|(id, entry)| (id as u64, entry.clone()))
+        .collect();
+
+    for (id, entry) in entries {
+        if entry.object_stream.is_none() {
+            let expected = Some((id, entry.generation_id));
+            let found = header_at(file, entry.offset);
+            if found != expected {
+                report.error("object.offset", Some(id), format!("offset {} doesn't point at the object", entry.offset));
+                continue;
+            }
+        }
+        if let Err(err) = doc.object(&entry) {
+            report.error("object.syntax", Some(id), err.to_string());
+            continue;
+        }
+        if profile == Profile::Full {
+            match doc.stream(&entry) {
+                Ok(_) => (),
+                Err(PdfError::UnsupportedFilter(filter)) => {
+                    report.warning("stream.filter", Some(id), format!("can't decode {} streams", filter));
+                },
+                Err(err) => report.error("stream.decode", Some(id), err.to_string()),
+            }
+        }
+    }
+}
+
+// Where each revision ends, the offset of every %%EOF marker.
+fn revision_ends(data: &[u8]) -> Vec<u64> {
+    data.windows(5)
+        .enumerate()
+        .filter(|&(_, window)| window == b"%%EOF")
+        .map(|(offset, _)| offset as u64)
+        .collect()
+}
+
+// Which revision the object at `offset` is part of, counting from 0.
+fn revision(ends: &[u64], offset: u64) -> usize {
+    ends.iter().take_while(|&&end| end < offset).count()
+}
+
+fn header_at(file: &mut File, offset: u64) -> Option<(u64, u64)> {
+    let mut line = Vec::new();
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    file.take(64).read_to_end(&mut line).ok()?;
+    let end = line.iter().position(|&byte| byte == b'\n' || byte == b'\r').unwrap_or(line.len());
+    document::object_header(&line[..end])
+}
+
+#[test]
+fn test_report() {
+    let mut report = ValidationReport::default();
+    assert!(report.is_valid());
+    report.warning("object.duplicate", Some(4), "defined 2 times".to_string());
+    assert!(report.is_valid());
+    report.error("trailer.root", None, "no /Root reference".to_string());
+    assert!(!report.is_valid());
+    assert!(report.has_rule("trailer.root"));
+    assert_eq!(report.to_string(),
+               "warning [object.duplicate] object 4: defined 2 times\n\
+                error [trailer.root] no /Root reference\n\
+                1 errors, 1 warnings");
+}
+
+#[test]
+fn test_missing_file() {
+    let report = validate(Path::new("/nonexistent/nails-pdf.pdf"), Profile::Full);
+    assert!(report.has_rule("file.unreadable"));
+}
+
+#[test]
+fn test_fixtures() {
+    use fixtures::{self, Fixture, FixtureFile};
+    use std::collections::HashMap;
+    use std::fs;
+    use writer::{self, Writer};
+
+    let rules = |path: &Path| validate(path, Profile::Full).issues.iter().map(|issue| issue.rule).collect::<Vec<_>>();
+    // BrokenLength's stream is found by its endstream, which isn't reported
+    for &fixture in fixtures::ALL {
+        let file = fixture.write();
+        assert_eq!(rules(&file.path), Vec::<&str>::new(), "{:?}", fixture);
+    }
+
+    let file = Fixture::Simple.write();
+    let simple = Fixture::Simple.bytes();
+    let broken = |name: &str, data: &[u8]| {
+        let broken = FixtureFile { path: file.path.with_extension(format!("{}.pdf", name)) };
+        fs::write(&broken.path, data).unwrap();
+        rules(&broken.path)
+    };
+    // same length replacements, so the offsets still hold
+    let replaced = |from: &[u8], to: &[u8]| {
+        let at = simple.windows(from.len()).position(|window| window == from).unwrap();
+        let mut data = simple.clone();
+        data[at..at + to.len()].copy_from_slice(to);
+        data
+    };
+    assert_eq!(broken("header", &replaced(b"%PDF-", b"%XYZ-")), vec!["file.header"]);
+    assert_eq!(broken("startxref", &replaced(b"startxref", b"startxrex")), vec!["xref.startxref"]);
+    assert_eq!(broken("offset", &replaced(b"4 0 obj", b"4 0 ojb")), vec!["object.offset"]);
+    assert_eq!(broken("root", &replaced(b"/Root", b"/Rout")), vec!["trailer.root"]);
+
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+    let filter = HashMap::from([("Filter".to_string(), DictNode::name("FlateDecode"))]);
+    doc.set_stream(5, filter, b"not deflated".to_vec());
+    assert_eq!(broken("decode", &writer::write(&mut doc, &Default::default()).unwrap()), vec!["stream.decode"]);
+
+    // an update redefining the /Info is fine, twice over
+    let mut doc = Document::open(&file.path, Options::default()).unwrap();
+    doc.set_object(6, DictNode::Dict(HashMap::new()));
+    let updated = FixtureFile { path: file.path.with_extension("updated.pdf") };
+    fs::write(&updated.path, writer::append(&mut doc).unwrap()).unwrap();
+    let mut doc = Document::open(&updated.path, Options::default()).unwrap();
+    doc.set_object(6, DictNode::Dict(HashMap::new()));
+    assert_eq!(broken("update", &writer::append(&mut doc).unwrap()), Vec::<&str>::new());
+
+    // but not one revision defining it twice
+    let mut out = Writer::new();
+    out.object(1, &DictNode::Dict(HashMap::from([("Type".to_string(), DictNode::name("Catalog"))]))).unwrap();
+    out.object(2, &DictNode::Int(1)).unwrap();
+    out.object(2, &DictNode::Int(2)).unwrap();
+    let twice = out.finish_with(HashMap::from([("Root".to_string(), DictNode::reference(1))]));
+    assert_eq!(broken("twice", &twice), vec!["object.duplicate"]);
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use document::{Document, Options};
+use error::Result;
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use transcode::{self, XRefFormat};
+
+/*
+ * Retargets a document at another PDF version, for consumers that refuse
+ * anything newer than what they were written for.
+ *
+ * Like transcode, the original bytes stay as they are and a new xref section
+ * is appended. Going below 1.5 expands object streams and writes a classic
+ * xref table, and a catalog /Version newer than the target is dropped by
+ * appending a rewritten catalog. Features that can't be converted
+ * (transparency, JPEG 2000, AES...) are left in place and reported, the
+ * file will still open in older readers but may not look the same.
+ */
+
+// A feature in the document that the target version doesn't have.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Incompatibility {
+    pub feature: &'static str,
+    pub since: (u8, u8),
+    // the object it was found in, None for the trailer
+    pub object: Option<u64>,
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} needs PDF {}.{}", self.feature, self.since.0, self.since.1)?;
+        match self.object {
+            Some(id) => write!(f, " (object {})", id),
+            None => write!(f, " (trailer)"),
+        }
+    }
+}
+
+// Writes `input` to `output` as a PDF `version` file, returning whatever
+// couldn't be converted.
+pub fn retarget(input: &Path, output: &Path, version: (u8, u8)) -> Result<Vec<Incompatibility>> {
+    let mut doc = Document::open(input, Options::default())?;
+    let mut data = fs::read(input)?;
+    let mut xref = doc.xref.clone();
+
+    let mut incompatible = Vec::new();
+    let entries: Vec<(usize, XRefEntry)> = doc.xref.entries().map(|(id, entry)| (id, entry.clone())).collect();
+    for &(id, ref entry) in &entries {
+        if entry.is_free {
+            continue;
+        }
+        let object = doc.object(entry)?;
+        for (feature, since) in features(&object) {
+            if since > version {
+                incompatible.push(Incompatibility { feature, since, object: Some(id as u64) });
+            }
+        }
+    }
+    if let Some(since) = encryption_version(&mut doc)? {
+        if since > version {
+            incompatible.push(Incompatibility { feature: "this encryption method", since, object: None });
+        }
+    }
+
+    // the catalog's /Version wins over the header
+    if let Some((id, mut catalog)) = catalog(&mut doc)? {
+        if let DictNode::Dict(ref mut map) = catalog {
+            let newer = match map.get("Version") {
+                Some(DictNode::Str(name)) => parse_version(name.trim_start_matches('/')).is_some_and(|own| own > version),
+                _ => false,
+            };
+            if newer {
+                map.remove("Version");
+                transcode::append_object(&mut data, &mut xref, id, &catalog)?;
+            }
+        }
+    }
+
+    transcode::set_version(&mut data, version);
+    let compressed = xref.entries().any(|(_, entry)| !entry.is_free && entry.object_stream.is_some());
+    let format = if version >= (1, 5) && compressed { XRefFormat::Stream } else { XRefFormat::Table };
+    transcode::append_xref(&mut doc, &mut data, xref, format)?;
+    fs::write(output, data)?;
+    Ok(incompatible)
+}
+
+// Parses "1.4" style versions.
+pub fn parse_version(text: &str) -> Option<(u8, u8)> {
+    let mut parts = text.splitn(2, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    if major > 9 || minor > 9 {
+        return None;
+    }
+    Some((major, minor))
+}
+
+// Features used by a single object, with the version that introduced them.
+fn features(object: &DictNode) -> Vec<(&'static str, (u8, u8))> {
+    let map = match *object {
+        DictNode::Dict(ref map) => map,
+        _ => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    for filter in filters(map.get("Filter")) {
+        match filter {
+            "/JBIG2Decode" => found.push(("JBIG2 images", (1, 4))),
+            "/JPXDecode" => found.push(("JPEG 2000 images", (1, 5))),
+            "/Crypt" => found.push(("the Crypt filter", (1, 5))),
+            _ => (),
+        }
+    }
+    if map.contains_key("SMask") {
+        found.push(("soft masks", (1, 4)));
+    }
+    if let Some(DictNode::Dict(group)) = map.get("Group") {
+        if group.get("S") == Some(&DictNode::name("Transparency")) {
+            found.push(("transparency groups", (1, 4)));
+        }
+    }
+    if map.contains_key("OCProperties") {
+        found.push(("optional content", (1, 5)));
+    }
+    found
+}
+
+fn filters(filter: Option<&DictNode>) -> Vec<&str> {
+    match filter {
+        Some(DictNode::Str(name)) => vec![name.as_str()],
+        Some(DictNode::Array(names)) => names.iter()
+            .filter_map(|name| match *name {
+                DictNode::Str(ref name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// The version the document's /Encrypt dictionary needs, if it has one.
+fn encryption_version(doc: &mut Document) -> Result<Option<(u8, u8)>> {
+    let encrypt = match doc.trailer_entry("Encrypt")? {
+        Some((_, DictNode::Dict(map))) => map,
+        _ => return Ok(None),
+    };
+    let since = match encrypt.get("V") {
+        Some(&DictNode::Int(5)) => (2, 0),
+        Some(&DictNode::Int(4)) => (1, 5),
+        Some(&DictNode::Int(2)) | Some(&DictNode::Int(3)) => (1, 4),
+        _ => (1, 1),
+    };
+    Ok(Some(since))
+}
+
+fn catalog(doc: &mut Document) -> Result<Option<(u64, DictNode)>> {
+    Ok(doc.trailer_entry("Root")?.and_then(|(id, catalog)| id.map(|id| (id, catalog))))
+}
+
+#[test]
+fn test_parse_version() {
+    assert_eq!(parse_version("1.4"), Some((1, 4)));
+    assert_eq!(parse_version("2.0"), Some((2, 0)));
+    assert_eq!(parse_version("1"), None);
+    assert_eq!(parse_version("1.10"), None);
+}
+
+#[test]
+fn test_features() {
+    let image = DictNode::Dict(hashmap!{
+        "Subtype".to_string() => DictNode::name("Image"),
+        "Filter".to_string() => DictNode::Array(vec![DictNode::name("FlateDecode"), DictNode::name("JPXDecode")]),
+        "SMask".to_string() => DictNode::reference(12),
+    });
+    assert_eq!(features(&image), vec![("JPEG 2000 images", (1, 5)), ("soft masks", (1, 4))]);
+
+    let page = DictNode::Dict(hashmap!{
+        "Group".to_string() => DictNode::Dict(hashmap!{ "S".to_string() => DictNode::name("Transparency") }),
+    });
+    assert_eq!(features(&page), vec![("transparency groups", (1, 4))]);
+    assert_eq!(features(&DictNode::Int(3)), Vec::new());
+
+    let found = Incompatibility { feature: "soft masks", since: (1, 4), object: Some(12) };
+    assert_eq!(found.to_string(), "soft masks needs PDF 1.4 (object 12)");
+}
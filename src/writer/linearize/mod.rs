@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crypt::SecurityHandler;
+use error::{PdfError, Result};
+use filters;
+use parsers::cos::DictNode;
+use parsers::xref::XRefEntry;
+use transcode::XRefFormat;
+use super::{collect_references, format_section, renumber, Writer, Written};
+
+/*
+ * Linearized files, what viewers call "fast web view": the first page and
+ * everything it needs come first, under an xref section of their own, with
+ * hint tables saying where each later page starts, so a viewer reading the
+ * file over a slow connection shows page one before the rest has arrived
+ * (see annex F of the spec).
+ *
+ *   let options = SaveOptions { linearize: true, ..SaveOptions::default() };
+ *   writer::save(&mut doc, output, &options)?;
+ *
+ * The file goes: the header, the linearization parameter dictionary, the
+ * first page's xref section, the catalog and the /Encrypt dictionary if
+ * there is one, the primary hint stream, the first page's objects, then
+ * each later page's objects of its own, the objects later pages share,
+ * whatever no page uses (the page tree, outlines, /Info) and the main
+ * xref section. Objects are numbered to match, the main section's from 1
+ * and the front's after them, all at generation 0, so
+ * `SaveOptions::numbering` doesn't apply here. An encrypted file's hint
+ * stream is encrypted like every other stream.
+ *
+ * What a page uses is whatever its dictionary leads to without going up
+ * its /Parent or into another page. Whatever the first page uses is the
+ * first page's, later pages that use it too find it already there. The
+ * hint stream has the page offset and shared object tables and nothing
+ * else: each shared object is a group of its own, and content streams
+ * aren't told apart from the rest of a page, their lengths are the page's
+ * as qpdf writes them. Offsets in the tables are 32 bits, files of 4G and
+ * up are refused.
+ */
+
+// `objects`, under the numbers they were read with, as a linearized file
+// whose trailer has `trailer`'s /Root, /Info, /ID and /Encrypt. `objects`
+// come encrypted already, `security` encrypts the hint stream made here.
+pub(super) fn linearize(version: (u8, u8), objects: &BTreeMap<u64, Written>, trailer: &HashMap<String, DictNode>,
+                        security: Option<&SecurityHandler>) -> Result<Vec<u8>> {
+    let catalog = match trailer.get("Root") {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 && objects.contains_key(&(id as u64)) => id as u64,
+        _ => return Err(PdfError::Syntax { what: "document catalog", position: 0 }),
+    };
+    let (pages, mut stops) = page_tree(objects, catalog);
+    if pages.is_empty() {
+        return Err(PdfError::Syntax { what: "page tree root", position: 0 });
+    }
+    // the catalog, and the /Encrypt dictionary readers need before anything
+    let documents: Vec<u64> = Some(catalog).into_iter()
+        .chain(reference(trailer.get("Encrypt")).filter(|id| *id != catalog && objects.contains_key(id)))
+        .collect();
+    stops.extend(&pages);
+    stops.extend(&documents);
+    let uses: Vec<Vec<u64>> = pages.iter().map(|&page| used(objects, page, &stops)).collect();
+
+    // the parts of the file, after the catalog
+    let first = &uses[0];
+    let mut placed: HashSet<u64> = first.iter().cloned().collect();
+    let mut users: HashMap<u64, usize> = HashMap::new();
+    for &id in uses[1..].iter().flatten().filter(|id| !placed.contains(id)) {
+        *users.entry(id).or_insert(0) += 1;
+    }
+    let own: Vec<Vec<u64>> = uses[1..].iter()
+        .map(|page| page.iter().cloned().filter(|id| users.get(id) == Some(&1)).collect())
+        .collect();
+    let mut shared = Vec::new();
+    for &id in uses[1..].iter().flatten() {
+        if users.get(&id).is_some_and(|&count| count > 1) && placed.insert(id) {
+            shared.push(id);
+        }
+    }
+    placed.extend(own.iter().flatten());
+    placed.extend(&documents);
+    let rest: Vec<u64> = objects.keys().cloned().filter(|id| !placed.contains(id)).collect();
+
+    // the main section's objects from 1, then the parameter dictionary,
+    // the catalog, the hint stream and the first page's objects
+    let back: Vec<u64> = own.iter().flatten().chain(&shared).chain(&rest).cloned().collect();
+    let mut numbers: HashMap<u64, u64> = back.iter().enumerate().map(|(index, &id)| (id, index as u64 + 1)).collect();
+    let parameters_number = back.len() as u64 + 1;
+    for (index, &id) in documents.iter().enumerate() {
+        numbers.insert(id, parameters_number + 1 + index as u64);
+    }
+    let hint_number = parameters_number + 1 + documents.len() as u64;
+    for (index, &id) in first.iter().enumerate() {
+        numbers.insert(id, hint_number + 1 + index as u64);
+    }
+    let size = hint_number + 1 + first.len() as u64;
+
+    // everything after the hint stream, and where each object starts in it
+    let (mut body, mut starts, mut lengths) = (Vec::new(), HashMap::new(), HashMap::new());
+    for &id in first.iter().chain(&back) {
+        let written = object(numbers[&id], &renumber(&objects[&id].object, &numbers), objects[&id].data.as_deref());
+        starts.insert(id, body.len() as u64);
+        lengths.insert(id, written.len() as u64);
+        body.extend(written);
+    }
+    let first_end: u64 = first.iter().map(|id| lengths[id]).sum();
+    let length = |id: &u64| lengths[id];
+    let (mut document_objects, mut document_starts) = (Vec::new(), Vec::new());
+    for &id in &documents {
+        document_starts.push(document_objects.len() as u64);
+        document_objects.extend(object(numbers[&id], &renumber(&objects[&id].object, &numbers), objects[&id].data.as_deref()));
+    }
+    let trailer: HashMap<String, DictNode> = trailer.iter().map(|(key, value)| (key.clone(), renumber(value, &numbers))).collect();
+
+    // groups in the shared object table: each of the first page's objects,
+    // then each shared object
+    let groups: Vec<u64> = first.iter().chain(&shared).map(length).collect();
+    let group_index: HashMap<u64, u64> = first.iter().chain(&shared).enumerate().map(|(index, &id)| (id, index as u64)).collect();
+    let mut hinted = vec![PageHint { objects: first.len() as u64, length: first_end, shared: Vec::new() }];
+    for (page, own) in uses[1..].iter().zip(&own) {
+        let mut shared: Vec<u64> = page.iter().filter_map(|id| group_index.get(id).cloned()).collect();
+        shared.sort();
+        hinted.push(PageHint { objects: own.len() as u64, length: own.iter().map(length).sum(), shared });
+    }
+
+    // The front's length depends on offsets past it and the other way
+    // round. Each pass lays the file out with the front and the hint
+    // stream padded to what the last pass needed, until they fit and /L
+    // comes out as it was assumed.
+    let (mut front_length, mut hint_length, mut file_length) = (0u64, 0u64, 0u64);
+    loop {
+        let base = front_length + hint_length;
+        // offsets in the hint tables are as if the hint stream wasn't there
+        let first_shared = shared.first().map_or((0, 0), |id| (numbers[id], front_length + starts[id]));
+        let (tables, shared_offset) = hint_tables(front_length, &hinted, &groups, first.len() as u64, first_shared);
+        let mut data = filters::flate_encode(&tables);
+        if let Some(security) = security {
+            data = security.encrypt(hint_number, 0, &data);
+        }
+        let dict = DictNode::Dict(HashMap::from([
+            ("Filter".to_string(), DictNode::name("FlateDecode")),
+            ("Length".to_string(), DictNode::Int(data.len() as i64)),
+            ("S".to_string(), DictNode::Int(shared_offset as i64)),
+        ]));
+        let mut hint = object(hint_number, &dict, Some(&data));
+
+        let main_offset = base + body.len() as u64;
+        let first_page = numbers[&pages[0]];
+        let parameters = DictNode::Dict(HashMap::from([
+            ("Linearized".to_string(), DictNode::Int(1)),
+            ("L".to_string(), DictNode::Int(file_length as i64)),
+            ("H".to_string(), DictNode::Array(vec![DictNode::Int(front_length as i64), DictNode::Int(hint.len() as i64)])),
+            ("O".to_string(), DictNode::Int(first_page as i64)),
+            ("E".to_string(), DictNode::Int((base + first_end) as i64)),
+            ("N".to_string(), DictNode::Int(pages.len() as i64)),
+            ("T".to_string(), DictNode::Int((main_offset + format!("xref\n0 {}", back.len() + 1).len() as u64) as i64)),
+        ]));
+        let mut front = Writer::with_version(version.max((1, 2))).data;
+        let parameters_offset = front.len() as u64;
+        front.extend(object(parameters_number, &parameters, None));
+        let first_xref = front.len() as u64;
+
+        let mut entries: BTreeMap<u64, XRefEntry> = back.iter().map(|id| (numbers[id], XRefEntry::in_use(base + starts[id], 0))).collect();
+        entries.insert(0, XRefEntry::free());
+        let size_only = HashMap::from([("Size".to_string(), DictNode::Int(back.len() as i64 + 1))]);
+        let main = format_section(entries, size_only, first_xref, XRefFormat::Table);
+        let actual_length = main_offset + main.len() as u64;
+
+        let mut entries: BTreeMap<u64, XRefEntry> = first.iter().map(|id| (numbers[id], XRefEntry::in_use(base + starts[id], 0))).collect();
+        entries.insert(parameters_number, XRefEntry::in_use(parameters_offset, 0));
+        for (id, start) in documents.iter().zip(&document_starts) {
+            let offset = front_length.saturating_sub(document_objects.len() as u64) + start;
+            entries.insert(numbers[id], XRefEntry::in_use(offset, 0));
+        }
+        entries.insert(hint_number, XRefEntry::in_use(front_length, 0));
+        let mut first_trailer = trailer.clone();
+        first_trailer.insert("Size".to_string(), DictNode::Int(size as i64));
+        first_trailer.insert("Prev".to_string(), DictNode::Int(main_offset as i64));
+        // the real startxref is at the end of the file
+        front.extend(format_section(entries, first_trailer, 0, XRefFormat::Table));
+
+        let needed = front.len() as u64 + document_objects.len() as u64;
+        if needed <= front_length && hint.len() as u64 <= hint_length && actual_length == file_length {
+            if file_length > u32::MAX as u64 {
+                return Err(PdfError::InvalidArgument(format!("{} bytes is too big to linearize", file_length)));
+            }
+            front.resize((front_length as usize) - document_objects.len(), b'\n');
+            front.extend(document_objects);
+            hint.resize(hint_length as usize, b'\n');
+            front.extend(hint);
+            front.extend(body);
+            front.extend(main);
+            return Ok(front);
+        }
+        front_length = front_length.max(needed);
+        hint_length = hint_length.max(hint.len() as u64);
+        file_length = actual_length;
+    }
+}
+
+// The pages of `catalog`'s page tree in order, and the tree's other nodes.
+// Pages written straight into a /Kids array have no object to place and
+// are left out.
+fn page_tree(objects: &BTreeMap<u64, Written>, catalog: u64) -> (Vec<u64>, HashSet<u64>) {
+    let (mut pages, mut nodes, mut seen) = (Vec::new(), HashSet::new(), HashSet::new());
+    let mut stack: Vec<u64> = reference(objects[&catalog].object.get("Pages")).into_iter().collect();
+    while let Some(id) = stack.pop() {
+        let node = match objects.get(&id) {
+            Some(written) if seen.insert(id) => &written.object,
+            _ => continue,
+        };
+        match node.get("Kids").and_then(DictNode::as_array) {
+            Some(kids) if !node.get("Type").is_some_and(|kind| kind.is_name("Page")) => {
+                nodes.insert(id);
+                stack.extend(kids.iter().rev().filter_map(|kid| reference(Some(kid))));
+            },
+            _ => pages.push(id),
+        }
+    }
+    (pages, nodes)
+}
+
+fn reference(node: Option<&DictNode>) -> Option<u64> {
+    match node {
+        Some(&DictNode::ObjectReference(id, _)) if id >= 0 => Some(id as u64),
+        _ => None,
+    }
+}
+
+// Everything `page` leads to without going into `stops` or up a /Parent,
+// the page first and the rest depth-first, in the order `numbering` walks.
+fn used(objects: &BTreeMap<u64, Written>, page: u64, stops: &HashSet<u64>) -> Vec<u64> {
+    let (mut order, mut seen) = (Vec::new(), HashSet::new());
+    let mut stack = vec![page];
+    while let Some(id) = stack.pop() {
+        let written = match objects.get(&id) {
+            Some(written) if (id == page || !stops.contains(&id)) && seen.insert(id) => written,
+            _ => continue,
+        };
+        order.push(id);
+        let mut references = Vec::new();
+        match written.object {
+            DictNode::Dict(ref map) => {
+                let mut keys: Vec<&String> = map.keys().filter(|&key| key != "Parent").collect();
+                keys.sort();
+                for key in keys {
+                    collect_references(&map[key], &mut references);
+                }
+            },
+            ref object => collect_references(object, &mut references),
+        }
+        stack.extend(references.into_iter().rev());
+    }
+    order
+}
+
+fn object(number: u64, object: &DictNode, data: Option<&[u8]>) -> Vec<u8> {
+    let mut out = format!("{} 0 obj\n{}\n", number, object).into_bytes();
+    if let Some(data) = data {
+        out.extend_from_slice(b"stream\n");
+        out.extend_from_slice(data);
+        out.extend_from_slice(b"\nendstream\n");
+    }
+    out.extend_from_slice(b"endobj\n");
+    out
+}
+
+// What the page offset hint table says about a page: how many objects its
+// section has and how long it is, and the shared object groups it uses.
+struct PageHint {
+    objects: u64,
+    length: u64,
+    shared: Vec<u64>,
+}
+
+// The page offset hint table then the shared object hint table, and where
+// the second starts. `first_page` is where the first page's section starts,
+// `groups` the shared object groups' lengths, `first_groups` of them the
+// first page's, and `first_shared` the number and offset of the first
+// object in the shared objects section, zeros if there's none.
+fn hint_tables(first_page: u64, pages: &[PageHint], groups: &[u64], first_groups: u64, first_shared: (u64, u64)) -> (Vec<u8>, u64) {
+    let min_objects = pages.iter().map(|page| page.objects).min().unwrap_or(0);
+    let objects_bits = width(pages.iter().map(|page| page.objects - min_objects).max().unwrap_or(0));
+    let min_length = pages.iter().map(|page| page.length).min().unwrap_or(0);
+    let length_bits = width(pages.iter().map(|page| page.length - min_length).max().unwrap_or(0));
+    let shared_bits = width(pages.iter().map(|page| page.shared.len() as u64).max().unwrap_or(0));
+    let identifier_bits = width(groups.len().saturating_sub(1) as u64);
+
+    let mut bits = Bits::default();
+    bits.write(min_objects, 32);
+    bits.write(first_page, 32);
+    bits.write(objects_bits as u64, 16);
+    bits.write(min_length, 32);
+    bits.write(length_bits as u64, 16);
+    // content streams start where their page does
+    bits.write(0, 32);
+    bits.write(0, 16);
+    bits.write(min_length, 32);
+    bits.write(length_bits as u64, 16);
+    bits.write(shared_bits as u64, 16);
+    bits.write(identifier_bits as u64, 16);
+    // no numerators, over a denominator of 1
+    bits.write(0, 16);
+    bits.write(1, 16);
+    bits.each(pages, |page, bits| bits.write(page.objects - min_objects, objects_bits));
+    bits.each(pages, |page, bits| bits.write(page.length - min_length, length_bits));
+    bits.each(pages, |page, bits| bits.write(page.shared.len() as u64, shared_bits));
+    bits.each(pages, |page, bits| page.shared.iter().for_each(|&group| bits.write(group, identifier_bits)));
+    // numerators and content offsets, in no bits
+    bits.each(pages, |_, _| ());
+    bits.each(pages, |_, _| ());
+    bits.each(pages, |page, bits| bits.write(page.length - min_length, length_bits));
+    let shared_offset = bits.data.len() as u64;
+
+    let min_group = groups.iter().cloned().min().unwrap_or(0);
+    let group_bits = width(groups.iter().map(|group| group - min_group).max().unwrap_or(0));
+    bits.write(first_shared.0, 32);
+    bits.write(first_shared.1, 32);
+    bits.write(first_groups, 32);
+    bits.write(groups.len() as u64, 32);
+    // one object a group
+    bits.write(0, 16);
+    bits.write(min_group, 32);
+    bits.write(group_bits as u64, 16);
+    for group in groups {
+        bits.write(group - min_group, group_bits);
+    }
+    bits.flush();
+    // no signatures
+    for _ in groups {
+        bits.write(0, 1);
+    }
+    bits.flush();
+    (bits.data, shared_offset)
+}
+
+// The bits it takes to write numbers up to `max`.
+fn width(max: u64) -> u32 {
+    64 - max.leading_zeros()
+}
+
+// Hint table fields, most significant bit first. Each item of a table
+// starts on a byte.
+#[derive(Default)]
+struct Bits {
+    data: Vec<u8>,
+    byte: u8,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            self.byte = self.byte << 1 | (value >> bit & 1) as u8;
+            self.count += 1;
+            if self.count == 8 {
+                self.data.push(self.byte);
+                self.byte = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    // One item of the page offset table, for every page.
+    fn each<F: Fn(&PageHint, &mut Bits)>(&mut self, pages: &[PageHint], item: F) {
+        for page in pages {
+            item(page, self);
+        }
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+#[test]
+fn test_bits() {
+    let mut bits = Bits::default();
+    bits.write(5, 3);
+    bits.flush();
+    bits.write(0x1234, 16);
+    bits.write(1, 1);
+    bits.flush();
+    assert_eq!(bits.data, vec![0xa0, 0x12, 0x34, 0x80]);
+    assert_eq!((width(0), width(1), width(255), width(256)), (0, 1, 8, 9));
+}
+
+#[test]
+fn test_linearize() {
+    let dict = |entries: &[(&str, DictNode)]| DictNode::Dict(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect());
+    let written = |object, data: Option<&[u8]>| Written { generation: 0, object, data: data.map(<[u8]>::to_vec) };
+    let page = |content, fonts: &[u64]| dict(&[
+        ("Type", DictNode::name("Page")), ("Parent", DictNode::reference(2)), ("Contents", DictNode::reference(content)),
+        ("Resources", dict(&[("Font", dict(&fonts.iter().map(|&font| ("F", DictNode::reference(font))).collect::<Vec<_>>()))])),
+    ]);
+    let content = |text: &[u8]| written(dict(&[("Length", DictNode::Int(text.len() as i64))]), Some(text));
+
+    // pages 3, 4 and 5; font 20 is on all of them, 21 on the last two, and
+    // 9 is the document info
+    let objects: BTreeMap<u64, Written> = vec![
+        (1, written(dict(&[("Type", DictNode::name("Catalog")), ("Pages", DictNode::reference(2))]), None)),
+        (2, written(dict(&[("Type", DictNode::name("Pages")), ("Count", DictNode::Int(3)),
+                           ("Kids", DictNode::Array(vec![DictNode::reference(3), DictNode::reference(4), DictNode::reference(5)]))]), None)),
+        (3, written(page(10, &[20]), None)),
+        (4, written(page(11, &[21]), None)),
+        (5, written(page(12, &[21]), None)),
+        (10, content(b"BT /F 12 Tf (one) Tj ET")),
+        (11, content(b"BT /F 12 Tf (two) Tj ET")),
+        (12, content(b"BT /F 12 Tf (three) Tj ET")),
+        (20, written(dict(&[("Type", DictNode::name("Font")), ("BaseFont", DictNode::name("Helvetica"))]), None)),
+        (21, written(dict(&[("Type", DictNode::name("Font")), ("BaseFont", DictNode::name("Times-Roman"))]), None)),
+        (9, written(dict(&[("Title", DictNode::Str("(linear)".to_string()))]), None)),
+    ].into_iter().collect();
+    let trailer = HashMap::from([("Root".to_string(), DictNode::reference(1)), ("Info".to_string(), DictNode::reference(9))]);
+    let data = linearize((1, 4), &objects, &trailer, None).unwrap();
+    // one char a byte, so offsets into it are offsets into the file
+    let text: String = data.iter().map(|&byte| if byte.is_ascii() { byte as char } else { '?' }).collect();
+
+    // 1 to 7 are pages 4 and 5 each with its contents, font 21, the page
+    // tree and the info; 8 the parameters, 9 the catalog, 10 the hint stream, 11
+    // to 13 page 3, its contents and font 20
+    let header = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n8 0 obj\n<< /E ";
+    assert_eq!(&data[..header.len()], header);
+    let value = |key: &str| -> u64 {
+        let start = text.find(&format!("/{} ", key)).unwrap() + key.len() + 2;
+        text[start..].trim_start_matches('[').split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap()
+    };
+    assert_eq!(value("L"), data.len() as u64);
+    assert_eq!((value("O"), value("N")), (11, 3));
+    assert!(text[value("E") as usize..].starts_with("1 0 obj\n<< /Contents 2 0 R /Parent 6 0 R /Resources << /Font << /F 5 0 R >> >> /Type /Page >>"));
+    let hint = value("H") as usize;
+    assert!(text[hint..].trim_start().starts_with("10 0 obj\n<< /Filter /FlateDecode"));
+    assert_eq!(&data[value("T") as usize..value("T") as usize + 19], b"\n0000000000 65535 f");
+
+    // both sections' offsets point at their objects, the main one's is
+    // the first section's /Prev and the file's startxref the first one's
+    let sections: Vec<usize> = text.match_indices("\nxref\n").map(|(at, _)| at + 1).collect();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(value("Prev"), sections[1] as u64);
+    assert!(text.ends_with(&format!("startxref\n{}\n%%EOF\n", sections[0])));
+    assert!(text[sections[0]..].starts_with("xref\n8 6\n"));
+    assert!(text[sections[1]..].starts_with("xref\n0 8\n"));
+    for &section in &sections {
+        let mut lines = text[section + 5..].lines();
+        let subsection: Vec<usize> = lines.next().unwrap().split(' ').map(|n| n.parse().unwrap()).collect();
+        for (number, line) in (subsection[0]..).zip(lines.take(subsection[1])) {
+            if line.ends_with('n') {
+                let offset: usize = line[..10].parse().unwrap();
+                assert!(text[offset..].starts_with(&format!("{} 0 obj\n", number)), "object {}", number);
+            }
+        }
+    }
+    assert!(text.contains("trailer\n<< /Info 7 0 R /Prev"));
+    assert!(text.contains("trailer\n<< /Size 8 >>"));
+
+    // the page offset table: the first page has 3 objects and the others
+    // 2; the shared object table has the first page's 3 objects, then font
+    // 21, object 5
+    let start = text[hint..].find("stream\n").unwrap() + hint + 7;
+    let length = text[hint..].split("/Length ").nth(1).unwrap().split(' ').next().unwrap().parse::<usize>().unwrap();
+    let tables = filters::flate_decode(&data[start..start + length]).unwrap();
+    assert_eq!(&tables[..4], &[0, 0, 0, 2]);
+    assert_eq!(u32::from_be_bytes([tables[4], tables[5], tables[6], tables[7]]) as u64, value("H"));
+    assert_eq!(u16::from_be_bytes([tables[8], tables[9]]), 1);
+    let shared = value("S") as usize;
+    assert_eq!(&tables[shared..shared + 4], &[0, 0, 0, 5]);
+    assert_eq!(&tables[shared + 8..shared + 16], &[0, 0, 0, 3, 0, 0, 0, 4]);
+}
+
+#[test]
+fn test_linearize_fixtures() {
+    use std::fs;
+
+    use document::{Document, Options};
+    use fixtures::{Fixture, FixtureFile};
+    use info;
+    use pages;
+    use text;
+    use writer::{save, SaveOptions};
+
+    for fixture in [Fixture::Simple, Fixture::XRefStream, Fixture::ObjectStream, Fixture::Cjk] {
+        let file = fixture.write();
+        let linearized = FixtureFile { path: file.path.with_extension("linearized.pdf") };
+        let mut doc = Document::open(&file.path, Options::default()).unwrap();
+        save(&mut doc, &linearized.path, &SaveOptions { linearize: true, ..SaveOptions::default() }).unwrap();
+
+        // the parameters come first and have the file's length right
+        let data = fs::read(&linearized.path).unwrap();
+        let front = String::from_utf8_lossy(&data[..data.len().min(1024)]).into_owned();
+        assert!(front.contains("/Linearized 1"), "{:?}", fixture);
+        assert!(front.contains(&format!("/L {} ", data.len())), "{:?}", fixture);
+        assert!(front.contains("/N 1 "), "{:?}", fixture);
+
+        let mut doc = Document::open(&linearized.path, Options::default()).unwrap();
+        assert_eq!(info::info(&mut doc).unwrap().unwrap().title.as_deref(), Some("Fixture"));
+        let page = pages::pages(&mut doc).unwrap().remove(0);
+        assert!(text::extract(&mut doc, &page).unwrap().contains(fixture.text()), "{:?}", fixture);
+    }
+}
@@ -0,0 +1,771 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+
+use compress;
+use crypt::{self, Encryption, SecurityHandler};
+use document::Document;
+use document::edit::EditedObject;
+use error::{PdfError, Result};
+use parsers::cos::DictNode;
+use parsers::xref::{XRefEntry, XRefTable};
+use pipeline;
+use transcode::{self, XRefFormat};
+
+pub mod linearize;
+
+/*
+ * Writing files: a document written out whole, and the `Writer` the
+ * rewrites lay their objects out with.
+ *
+ *   let mut doc = Document::open(input, Options::default())?;
+ *   doc.set_object(info_id, info);
+ *   writer::save(&mut doc, Path::new("out.pdf"), &SaveOptions::default())?;
+ *
+ * Every object in use is written once, in object number order and under
+ * the number and generation it has: edited objects as edited (see the
+ * document's edit module), the rest as they were read, streams with their
+ * data still encoded and a direct /Length. Objects that lived in object
+ * streams come out as plain objects, and the object streams and xref
+ * streams themselves are left behind, a single classic xref table covers
+ * everything, unless the file is too big for its ten digit offsets (see
+ * the transcode module), where it takes an xref stream. The trailer keeps
+ * /Root, /Info and /ID. Earlier revisions and whatever no xref entry
+ * points at are gone.
+ *
+ * None of the input's layout survives, dictionary keys come out sorted
+ * and every object is "N G obj" on its own line, so writing out a file
+ * this wrote gives the same bytes back.
+ *
+ * Which numbers objects are written under is up to `SaveOptions`. Tools
+ * downstream disagree: signers and incremental updaters want the numbers
+ * a document already has, diff tools want the same document numbered the
+ * same way whatever produced it. `Numbering::Preserve` keeps them, the
+ * others renumber from 1 in a depth-first walk from the catalog, grouped
+ * by type or not, and set every generation to 0. Renumbering is a
+ * function of the objects and nothing else, so it's deterministic; only
+ * objects nothing leads to keep their old relative order, after the rest.
+ * `SaveOptions::compress` recompresses streams on the way out as well (see
+ * the compress module), and `SaveOptions::linearize` lays the file out
+ * for viewers that show it as it downloads (see the linearize module).
+ *
+ * Encrypted documents are refused: their objects are read decrypted and
+ * would be written out that way under an /Encrypt that no longer fits.
+ * `SaveOptions::encryption` encrypts what's written instead, with AES-256
+ * under new passwords (see the crypt module); the file gets a new /ID,
+ * keeping the first string of the old one.
+ *
+ * `append` writes an incremental update instead: the file's bytes as they
+ * are, the edited objects after them, and a new xref section listing only
+ * those, whose /Prev points back at the newest section already there.
+ * Nothing before the old end of file moves, so signatures over it still
+ * hold, and small edits to big files only cost what they change:
+ *
+ *   doc.set_object(id, annotation);
+ *   writer::save_incremental(&mut doc, Path::new("annotated.pdf"))?;
+ *
+ * The section is a table or an xref stream, whichever the newest one is,
+ * since readers that only know tables can't follow a /Prev into a stream
+ * and the other way round makes no sense, or a stream once the offsets
+ * outgrow a table. Removed objects get a free entry at the next
+ * generation. Documents whose xref was rebuilt from a scan have no section
+ * to point back at, theirs lists every object and has no /Prev.
+ */
+
+// Trailer keys that describe the document, rather than its xref.
+const TRAILER_KEYS: &[&str] = &["Root", "Info", "ID"];
+// The same for an update, which has to keep the document's encryption.
+const UPDATE_TRAILER_KEYS: &[&str] = &["Root", "Info", "ID", "Encrypt"];
+
+// A file as it's written, objects in the order they're numbered.
+pub(crate) struct Writer {
+    pub(crate) data: Vec<u8>,
+    pub(crate) xref: XRefTable,
+    next_id: u64,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Writer {
+        Writer::with_version((1, 7))
+    }
+
+    pub(crate) fn with_version(version: (u8, u8)) -> Writer {
+        let mut data = format!("%PDF-{}.{}\n", version.0, version.1).into_bytes();
+        data.extend_from_slice(b"%\xe2\xe3\xcf\xd3\n");
+        Writer { data, xref: XRefTable::new(vec![XRefEntry::free()]), next_id: 1 }
+    }
+
+    // Objects to append to a file that has `data` and `xref` already.
+    pub(crate) fn appending(data: Vec<u8>, xref: XRefTable) -> Writer {
+        let next_id = xref.len().max(1) as u64;
+        Writer { data, xref, next_id }
+    }
+
+    // The next free object number.
+    pub(crate) fn reserve(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    pub(crate) fn object(&mut self, id: u64, object: &DictNode) -> Result<()> {
+        transcode::append_object(&mut self.data, &mut self.xref, id, object)
+    }
+
+    pub(crate) fn stream(&mut self, id: u64, dict: &DictNode, data: &[u8]) -> Result<()> {
+        transcode::append_stream(&mut self.data, &mut self.xref, id, dict, data)
+    }
+
+    // Object `id` at `generation`, with its data if it's a stream, in which
+    // case `object` should carry its /Length.
+    pub(crate) fn versioned(&mut self, id: u64, generation: u64, object: &DictNode, data: Option<&[u8]>) -> Result<()> {
+        if !self.data.ends_with(b"\n") {
+            self.data.push(b'\n');
+        }
+        self.xref.set(id as usize, XRefEntry::in_use(self.data.len() as u64, generation));
+        self.next_id = self.next_id.max(id + 1);
+        write!(self.data, "{} {} obj\n{}\n", id, generation, object)?;
+        if let Some(data) = data {
+            self.data.extend_from_slice(b"stream\n");
+            self.data.extend_from_slice(data);
+            self.data.extend_from_slice(b"\nendstream\n");
+        }
+        self.data.extend_from_slice(b"endobj\n");
+        Ok(())
+    }
+
+    // The file, with its xref table and trailer.
+    pub(crate) fn finish(self, root: u64) -> Vec<u8> {
+        self.finish_with(HashMap::from([("Root".to_string(), DictNode::reference(root))]))
+    }
+
+    // The same with the trailer entries given. Files too big for a table
+    // end with an xref stream, and say PDF 1.5.
+    pub(crate) fn finish_with(mut self, trailer: HashMap<String, DictNode>) -> Vec<u8> {
+        let offset = self.data.len() as u64;
+        let section = match transcode::preferred_format(&self.xref) {
+            XRefFormat::Table => transcode::format_table(&self.xref, trailer, offset),
+            XRefFormat::Stream => {
+                transcode::bump_version(&mut self.data, (1, 5));
+                transcode::format_stream(&self.xref, trailer, offset)
+            },
+        };
+        self.data.extend(section);
+        self.data
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SaveOptions {
+    pub numbering: Numbering,
+    // deflate streams that are uncompressed or only ASCII encoded, and
+    // shorten redundant filter chains
+    pub compress: bool,
+    // first page first, with hint tables, for viewers reading the file as
+    // it downloads; objects are numbered the way that needs
+    pub linearize: bool,
+    // passwords and permissions to encrypt the file with
+    pub encryption: Option<Encryption>,
+}
+
+// How objects are numbered in a file written out whole.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Numbering {
+    // the numbers and generations they have, which keeps references from
+    // outside the file (and an incremental update made later) meaningful
+    #[default]
+    Preserve,
+    // 1, 2, 3... in the order a depth-first walk from the trailer's /Root
+    // and /Info comes across them
+    DepthFirst,
+    // the same, grouped by type: the catalog, the page tree, pages,
+    // annotations, fonts and so on, see TYPE_ORDER
+    ByType,
+}
+
+// The order `Numbering::ByType` puts /Types in. Other types come after
+// these in alphabetical order, then objects without a /Type, then streams
+// without one (content streams mostly).
+const TYPE_ORDER: &[&str] = &[
+    "Catalog", "Pages", "Page", "Outlines", "Outline", "Annot", "Action", "Font", "FontDescriptor", "Encoding",
+    "XObject", "ExtGState", "Pattern", "Shading", "Metadata",
+];
+
+// An object to write, under whatever number it ends up with.
+struct Written {
+    generation: u64,
+    object: DictNode,
+    data: Option<Vec<u8>>,
+}
+
+pub fn save(doc: &mut Document, output: &Path, options: &SaveOptions) -> Result<()> {
+    let data = write(doc, options)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// The document as a new file, edits included.
+pub fn write(doc: &mut Document, options: &SaveOptions) -> Result<Vec<u8>> {
+    if doc.is_encrypted() {
+        return Err(PdfError::Encryption("encrypted documents can't be written out whole".to_string()));
+    }
+    let version = doc.header_version()?.unwrap_or((1, 7));
+    let edits: HashMap<u64, Option<EditedObject>> = doc.edits().into_iter()
+        .map(|(id, edited)| (id, edited.cloned()))
+        .collect();
+    let mut ids: BTreeSet<u64> = doc.xref.entries()
+        .filter(|&(_, entry)| !entry.is_free)
+        .map(|(id, _)| id as u64)
+        .collect();
+    ids.extend(edits.keys());
+
+    let mut read = Vec::new();
+    for id in ids {
+        let entry = doc.xref.get(id as usize).filter(|entry| !entry.is_free).cloned();
+        // objects in object streams are at generation 0
+        let generation = entry.as_ref().filter(|entry| entry.object_stream.is_none()).map_or(0, |entry| entry.generation_id);
+        let (object, data) = match (edits.get(&id), entry) {
+            (Some(Some(edited)), _) => (edited.object.clone(), edited.data.clone()),
+            (Some(None), _) | (None, None) => continue,
+            (None, Some(entry)) => doc.raw_object(&entry)?,
+        };
+        read.push((id, generation, object, data));
+    }
+    if options.compress {
+        read = recompressed(read)?;
+    }
+
+    let mut objects = BTreeMap::new();
+    for (id, generation, object, data) in read {
+        let object = match (object, &data) {
+            (DictNode::Dict(mut dict), Some(data)) => {
+                if dict.get("Type").is_some_and(|kind| kind.is_name("XRef") || kind.is_name("ObjStm")) {
+                    continue;
+                }
+                dict.insert("Length".to_string(), DictNode::Int(data.len() as i64));
+                DictNode::Dict(dict)
+            },
+            (object, _) => object,
+        };
+        objects.insert(id, Written { generation, object, data });
+    }
+
+    let mut trailer = trailer(&doc.trailer, TRAILER_KEYS);
+    let security = options.encryption.as_ref().map(|encryption| encrypt(&mut objects, &mut trailer, encryption));
+    if options.linearize {
+        return linearize::linearize(version, &objects, &trailer, security.as_ref());
+    }
+    let mut out = Writer::with_version(version);
+    if options.numbering == Numbering::Preserve {
+        for (id, written) in &objects {
+            out.versioned(*id, written.generation, &written.object, written.data.as_deref())?;
+        }
+        return Ok(out.finish_with(trailer));
+    }
+
+    let roots: Vec<u64> = ["Root", "Info"].iter()
+        .filter_map(|&key| match trailer.get(key) {
+            Some(&DictNode::ObjectReference(id, _)) if id >= 0 => Some(id as u64),
+            _ => None,
+        })
+        .collect();
+    let numbers = numbering(&objects, &roots, options.numbering);
+    let mut renumbered: Vec<(u64, &Written)> = objects.iter().map(|(id, written)| (numbers[id], written)).collect();
+    renumbered.sort_by_key(|&(number, _)| number);
+    for (number, written) in renumbered {
+        out.versioned(number, 0, &renumber(&written.object, &numbers), written.data.as_deref())?;
+    }
+    for value in trailer.values_mut() {
+        *value = renumber(value, &numbers);
+    }
+    Ok(out.finish_with(trailer))
+}
+
+// An object as read to be written: number, generation, the object and its
+// stream data.
+type ReadObject = (u64, u64, DictNode, Option<Vec<u8>>);
+
+// `compress::recompress` over the streams among `objects`, on a pool of
+// threads. Those it can't make smaller stay as they were.
+fn recompressed(objects: Vec<ReadObject>) -> Result<Vec<ReadObject>> {
+    let recompress = |(id, generation, object, data): ReadObject| {
+        match data.as_ref().and_then(|data| compress::recompress(&object, data)) {
+            Some((dict, data)) => (id, generation, DictNode::Dict(dict), Some(data)),
+            None => (id, generation, object, data),
+        }
+    };
+    let mut recompressed = Vec::with_capacity(objects.len());
+    pipeline::map_parallel(objects.into_iter().map(Ok), pipeline::default_workers(), recompress, |object| {
+        recompressed.push(object);
+        Ok(())
+    })?;
+    Ok(recompressed)
+}
+
+// Encrypts the strings and streams of `objects`, and adds the /Encrypt
+// dictionary under the next free number and a new /ID to `trailer`. The
+// numbers objects are encrypted under don't matter, AES-256 has no per
+// object keys, so renumbering them afterwards is fine.
+fn encrypt(objects: &mut BTreeMap<u64, Written>, trailer: &mut HashMap<String, DictNode>, encryption: &Encryption) -> SecurityHandler {
+    let (dict, handler) = encryption.security_handler();
+    for (&id, written) in objects.iter_mut() {
+        let object = mem::replace(&mut written.object, DictNode::Null);
+        written.object = handler.encrypt_strings(id, written.generation, object);
+        if let Some(ref mut data) = written.data {
+            if handler.encrypts_stream(id, &written.object) {
+                *data = handler.encrypt(id, written.generation, data);
+                if let DictNode::Dict(ref mut map) = written.object {
+                    map.insert("Length".to_string(), DictNode::Int(data.len() as i64));
+                }
+            }
+        }
+    }
+    let id = objects.keys().next_back().map_or(1, |&id| id + 1);
+    objects.insert(id, Written { generation: 0, object: dict, data: None });
+    trailer.insert("Encrypt".to_string(), DictNode::reference(id));
+
+    // the first string says which document this is, and stays; the second
+    // which version of it
+    let first = match trailer.get("ID").and_then(DictNode::as_array).and_then(|ids| ids.first()) {
+        Some(first) if first.as_string().is_some() => first.clone(),
+        _ => DictNode::HexString(crypt::random_bytes(16)),
+    };
+    trailer.insert("ID".to_string(), DictNode::Array(vec![first, DictNode::HexString(crypt::random_bytes(16))]));
+    handler
+}
+
+// The new number of each of `objects`, from 1, walking depth-first from
+// `roots` and then from whatever they don't lead to, in the order of the
+// old numbers. Dictionaries are walked in key order, arrays in order.
+fn numbering(objects: &BTreeMap<u64, Written>, roots: &[u64], numbering: Numbering) -> HashMap<u64, u64> {
+    let mut order = Vec::new();
+    let mut seen = BTreeSet::new();
+    for &start in roots.iter().chain(objects.keys()) {
+        // an explicit stack, outline items chained by /Next go deep
+        let mut stack = vec![start];
+        while let Some(id) = stack.pop() {
+            let written = match objects.get(&id) {
+                Some(written) if seen.insert(id) => written,
+                _ => continue,
+            };
+            order.push(id);
+            let mut references = Vec::new();
+            collect_references(&written.object, &mut references);
+            stack.extend(references.into_iter().rev());
+        }
+    }
+    if numbering == Numbering::ByType {
+        // stable, so each group keeps the depth-first order
+        order.sort_by_key(|id| type_rank(&objects[id]));
+    }
+    order.into_iter().enumerate().map(|(index, id)| (id, index as u64 + 1)).collect()
+}
+
+fn collect_references(node: &DictNode, references: &mut Vec<u64>) {
+    match *node {
+        DictNode::ObjectReference(id, _) if id >= 0 => references.push(id as u64),
+        DictNode::Array(ref items) => {
+            for item in items {
+                collect_references(item, references);
+            }
+        },
+        DictNode::Dict(ref map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys {
+                collect_references(&map[key], references);
+            }
+        },
+        _ => (),
+    }
+}
+
+// Where an object goes with `Numbering::ByType`: its place in TYPE_ORDER,
+// then other types by name, then untyped objects, then untyped streams.
+fn type_rank(written: &Written) -> (usize, String) {
+    match written.object.get("Type").and_then(DictNode::as_name) {
+        Some(kind) => match TYPE_ORDER.iter().position(|&known| known == kind) {
+            Some(position) => (position, String::new()),
+            None => (TYPE_ORDER.len(), kind.to_string()),
+        },
+        None if written.data.is_none() => (TYPE_ORDER.len() + 1, String::new()),
+        None => (TYPE_ORDER.len() + 2, String::new()),
+    }
+}
+
+// `node` with references going by `numbers`, at generation 0. References
+// to objects that aren't written are null, as they'd read anyway.
+fn renumber(node: &DictNode, numbers: &HashMap<u64, u64>) -> DictNode {
+    match *node {
+        DictNode::ObjectReference(id, _) => match numbers.get(&(id as u64)).filter(|_| id >= 0) {
+            Some(&number) => DictNode::reference(number),
+            None => DictNode::Null,
+        },
+        DictNode::Array(ref items) => DictNode::Array(items.iter().map(|item| renumber(item, numbers)).collect()),
+        DictNode::Dict(ref map) => DictNode::Dict(map.iter().map(|(key, value)| (key.clone(), renumber(value, numbers))).collect()),
+        ref node => node.clone(),
+    }
+}
+
+pub fn save_incremental(doc: &mut Document, output: &Path) -> Result<()> {
+    let data = append(doc)?;
+    fs::write(output, data)?;
+    Ok(())
+}
+
+// The file the document was opened from, with its edits appended as an
+// incremental update.
+pub fn append(doc: &mut Document) -> Result<Vec<u8>> {
+    let edits: Vec<(u64, Option<EditedObject>)> = doc.edits().into_iter()
+        .map(|(id, edited)| (id, edited.cloned()))
+        .collect();
+    if doc.is_encrypted() && !edits.is_empty() {
+        return Err(PdfError::Encryption("edited objects would be appended unencrypted".to_string()));
+    }
+    let data = doc.file_data()?;
+    let prev = doc.startxref();
+    let newest = prev.and_then(|offset| usize::try_from(offset).ok()).and_then(|offset| data.get(offset..));
+    let mut format = match newest {
+        Some(section) if !section.trim_ascii_start().starts_with(b"xref") => XRefFormat::Stream,
+        Some(_) => XRefFormat::Table,
+        // the whole xref goes into the update, entries in object streams
+        // included, and only a stream can list those
+        None => transcode::preferred_format(&doc.xref),
+    };
+
+    let mut out = Writer::appending(data, doc.xref.clone());
+    let mut section = BTreeMap::new();
+    for (id, edited) in edits {
+        let old = doc.xref.get(id as usize).filter(|entry| !entry.is_free).cloned();
+        let generation = old.as_ref().filter(|entry| entry.object_stream.is_none()).map_or(0, |entry| entry.generation_id);
+        let entry = match edited {
+            Some(edited) => {
+                out.versioned(id, generation, &edited.object, edited.data.as_deref())?;
+                XRefEntry::in_use(out.xref.get(id as usize).map_or(0, |entry| entry.offset), generation)
+            },
+            // removing what wasn't there changes nothing
+            None if old.is_none() => continue,
+            None => XRefEntry { generation_id: generation + 1, ..XRefEntry::free() },
+        };
+        out.xref.set(id as usize, entry.clone());
+        section.insert(id, entry);
+    }
+    if prev.is_none() {
+        section = out.xref.entries().map(|(id, entry)| (id as u64, entry.clone())).collect();
+    }
+    // the update's offsets can be too long for a table even when the
+    // file's weren't
+    if section.values().any(|entry| !entry.is_free && entry.object_stream.is_none() && entry.offset > transcode::MAX_TABLE_OFFSET) {
+        format = XRefFormat::Stream;
+    }
+
+    let mut data = out.data;
+    if !data.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    let mut trailer = trailer(&doc.trailer, UPDATE_TRAILER_KEYS);
+    let size = out.xref.len().max(1) as u64 + if format == XRefFormat::Stream { 1 } else { 0 };
+    trailer.insert("Size".to_string(), DictNode::Int(size as i64));
+    if let Some(prev) = prev {
+        trailer.insert("Prev".to_string(), DictNode::Int(prev as i64));
+    }
+    let offset = data.len() as u64;
+    data.extend(format_section(section, trailer, offset, format));
+    Ok(data)
+}
+
+// An xref section listing `entries`, each run of consecutive object numbers
+// a subsection, with `trailer`, to be written at `offset`. An xref stream
+// is object /Size - 1, and lists itself.
+fn format_section(mut entries: BTreeMap<u64, XRefEntry>, mut trailer: HashMap<String, DictNode>, offset: u64,
+                  format: XRefFormat) -> Vec<u8> {
+    let size = trailer.get("Size").and_then(DictNode::as_int).unwrap_or(1).max(1) as u64;
+    if format == XRefFormat::Stream {
+        entries.insert(size - 1, XRefEntry::in_use(offset, 0));
+    }
+    // (first object number, entries) for each subsection
+    let mut runs: Vec<(u64, Vec<XRefEntry>)> = Vec::new();
+    for (id, entry) in entries {
+        match runs.last_mut() {
+            Some(&mut (first, ref mut run)) if first + run.len() as u64 == id => run.push(entry),
+            _ => runs.push((id, vec![entry])),
+        }
+    }
+
+    if format == XRefFormat::Table {
+        let mut out = "xref\n".to_string();
+        for (first, run) in &runs {
+            out.push_str(&format!("{} {}\n", first, run.len()));
+            for entry in run {
+                let (field, kind) = if entry.is_free { (0, 'f') } else { (entry.offset, 'n') };
+                out.push_str(&format!("{:010} {:05} {}\r\n", field, entry.generation_id, kind));
+            }
+        }
+        out.push_str(&format!("trailer\n{}\nstartxref\n{}\n%%EOF\n", DictNode::Dict(trailer), offset));
+        return out.into_bytes();
+    }
+
+    let rows: Vec<(u8, u64, u64)> = runs.iter()
+        .flat_map(|(_, run)| run.iter())
+        .map(|entry| match (entry.is_free, entry.object_stream) {
+            (true, _) => (0, 0, entry.generation_id),
+            (false, Some(stream)) => (2, stream, entry.offset),
+            (false, None) => (1, entry.offset, entry.generation_id),
+        })
+        .collect();
+    let widths = [
+        1,
+        transcode::byte_width(rows.iter().map(|row| row.1).max().unwrap_or(0)),
+        transcode::byte_width(rows.iter().map(|row| row.2).max().unwrap_or(0)),
+    ];
+    let mut body = Vec::new();
+    for (kind, field, extra) in rows {
+        body.push(kind);
+        transcode::push_be(&mut body, field, widths[1]);
+        transcode::push_be(&mut body, extra, widths[2]);
+    }
+    let index = runs.iter().flat_map(|(first, run)| vec![DictNode::Int(*first as i64), DictNode::Int(run.len() as i64)]);
+    trailer.insert("Type".to_string(), DictNode::name("XRef"));
+    trailer.insert("Index".to_string(), DictNode::Array(index.collect()));
+    trailer.insert("W".to_string(), DictNode::Array(widths.iter().map(|&width| DictNode::Int(width as i64)).collect()));
+    trailer.insert("Length".to_string(), DictNode::Int(body.len() as i64));
+
+    let mut out = format!("{} 0 obj\n{}\nstream\n", size - 1, DictNode::Dict(trailer)).into_bytes();
+    out.extend(body);
+    out.extend(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", offset).into_bytes());
+    out
+}
+
+fn trailer(trailer: &DictNode, keys: &[&str]) -> HashMap<String, DictNode> {
+    match *trailer {
+        DictNode::Dict(ref map) => map.iter()
+            .filter(|&(key, _)| keys.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+#[test]
+fn test_writer() {
+    let mut out = Writer::with_version((1, 4));
+    out.versioned(1, 0, &DictNode::Dict(hashmap!{ "Type".to_string() => DictNode::name("Catalog") }), None).unwrap();
+    out.versioned(3, 2, &DictNode::Dict(hashmap!{ "Length".to_string() => DictNode::Int(3) }), Some(b"abc")).unwrap();
+    assert_eq!(out.reserve(), 4);
+    let data = out.finish_with(trailer(&DictNode::Dict(hashmap!{
+        "Root".to_string() => DictNode::reference(1),
+        "Prev".to_string() => DictNode::Int(1234),
+    }), TRAILER_KEYS));
+
+    let header = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+    assert_eq!(&data[..header.len()], header);
+    let text = String::from_utf8_lossy(&data[header.len()..]);
+    assert_eq!(text, "1 0 obj\n<< /Type /Catalog >>\nendobj\n\
+                      3 2 obj\n<< /Length 3 >>\nstream\nabc\nendstream\nendobj\n\
+                      xref\n0 4\n\
+                      0000000000 65535 f\r\n\
+                      0000000015 00000 n\r\n\
+                      0000000000 00000 f\r\n\
+                      0000000051 00002 n\r\n\
+                      trailer\n<< /Root 1 0 R /Size 4 >>\nstartxref\n103\n%%EOF\n");
+    assert!(data[15..].starts_with(b"1 0 obj"));
+    assert!(data[51..].starts_with(b"3 2 obj"));
+    assert!(data[103..].starts_with(b"xref"));
+}
+
+#[test]
+fn test_round_trip() {
+    use document::Options;
+    use fixtures::{Fixture, FixtureFile};
+
+    // writing out a file this wrote gives the same bytes back, whatever
+    // the input looked like and however objects are numbered
+    for fixture in [Fixture::Simple, Fixture::XRefStream, Fixture::ObjectStream, Fixture::Cjk] {
+        for numbering in [Numbering::Preserve, Numbering::DepthFirst, Numbering::ByType] {
+            let options = SaveOptions { numbering, ..SaveOptions::default() };
+            let file = fixture.write();
+            let mut doc = Document::open(&file.path, Options::default()).unwrap();
+            let written = FixtureFile { path: file.path.with_extension("written.pdf") };
+            let first = write(&mut doc, &options).unwrap();
+            fs::write(&written.path, &first).unwrap();
+
+            let mut doc = Document::open(&written.path, Options::default()).unwrap();
+            let second = write(&mut doc, &options).unwrap();
+            assert!(first == second, "{:?} with {:?} changed when written again", fixture, numbering);
+        }
+    }
+}
+
+#[test]
+fn test_format_section() {
+    let entries: BTreeMap<u64, XRefEntry> = [
+        (3, XRefEntry::in_use(900, 0)),
+        (4, XRefEntry { generation_id: 1, ..XRefEntry::free() }),
+        (12, XRefEntry::in_use(1000, 2)),
+    ].iter().cloned().collect();
+    let trailer = hashmap!{
+        "Root".to_string() => DictNode::reference(1),
+        "Size".to_string() => DictNode::Int(13),
+        "Prev".to_string() => DictNode::Int(500),
+    };
+    let table = String::from_utf8(format_section(entries.clone(), trailer.clone(), 1100, XRefFormat::Table)).unwrap();
+    assert_eq!(table, "xref\n3 2\n\
+                       0000000900 00000 n\r\n\
+                       0000000000 00001 f\r\n\
+                       12 1\n\
+                       0000001000 00002 n\r\n\
+                       trailer\n<< /Prev 500 /Root 1 0 R /Size 13 >>\nstartxref\n1100\n%%EOF\n");
+
+    // as a stream, object 12 is taken, the stream would be 13
+    let mut trailer = trailer;
+    trailer.insert("Size".to_string(), DictNode::Int(14));
+    let stream = format_section(entries, trailer, 1100, XRefFormat::Stream);
+    let text = String::from_utf8_lossy(&stream);
+    assert!(text.starts_with("13 0 obj\n<< /Index [3 2 12 2] /Length 16 /Prev 500 /Root 1 0 R /Size 14 /Type /XRef /W [1 2 1] >>\nstream\n"));
+    let start = text.find(">>\nstream\n").unwrap() + 10;
+    let dict = DictNode::Dict(hashmap!{
+        "Index".to_string() => DictNode::Array([3, 2, 12, 2].iter().map(|&n| DictNode::Int(n)).collect()),
+        "W".to_string() => DictNode::Array(vec![DictNode::Int(1), DictNode::Int(2), DictNode::Int(1)]),
+    });
+    let parsed = ::parsers::xref::parse_stream(&dict, &stream[start..start + 16]).unwrap();
+    assert_eq!(parsed.get(3), Some(&XRefEntry::in_use(900, 0)));
+    assert_eq!(parsed.get(4).map(|entry| entry.is_free), Some(true));
+    assert_eq!(parsed.get(12), Some(&XRefEntry::in_use(1000, 2)));
+    assert_eq!(parsed.get(13), Some(&XRefEntry::in_use(1100, 0)));
+}
+
+#[test]
+fn test_numbering() {
+    let dict = |entries: &[(&str, DictNode)]| DictNode::Dict(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect());
+    let written = |object, data: Option<&[u8]>| Written { generation: 0, object, data: data.map(<[u8]>::to_vec) };
+
+    // 10 is the catalog, 3 its page tree with pages 7 and 5, 7 uses font 2
+    // and content 9, and 4 is an orphan
+    let objects: BTreeMap<u64, Written> = vec![
+        (10, written(dict(&[("Type", DictNode::name("Catalog")), ("Pages", DictNode::reference(3))]), None)),
+        (3, written(dict(&[("Type", DictNode::name("Pages")), ("Kids", DictNode::Array(vec![DictNode::reference(7), DictNode::reference(5)]))]), None)),
+        (7, written(dict(&[("Type", DictNode::name("Page")), ("Parent", DictNode::reference(3)), ("Contents", DictNode::reference(9)),
+                           ("Resources", dict(&[("Font", dict(&[("F1", DictNode::reference(2))]))]))]), None)),
+        (5, written(dict(&[("Type", DictNode::name("Page")), ("Parent", DictNode::reference(3))]), None)),
+        (9, written(dict(&[("Length", DictNode::Int(2))]), Some(b"q Q"))),
+        (2, written(dict(&[("Type", DictNode::name("Font"))]), None)),
+        (4, written(DictNode::Int(42), None)),
+    ].into_iter().collect();
+
+    let numbers = numbering(&objects, &[10], Numbering::DepthFirst);
+    // /Contents comes before /Resources
+    let order = |numbers: &HashMap<u64, u64>| {
+        let mut ids: Vec<u64> = numbers.keys().cloned().collect();
+        ids.sort_by_key(|id| numbers[id]);
+        ids
+    };
+    assert_eq!(order(&numbers), vec![10, 3, 7, 9, 2, 5, 4]);
+    assert_eq!(order(&numbering(&objects, &[10], Numbering::ByType)), vec![10, 3, 7, 5, 2, 4, 9]);
+
+    assert_eq!(renumber(&dict(&[("Kids", DictNode::Array(vec![DictNode::reference(7), DictNode::reference(8)]))]), &numbers).to_string(),
+               "<< /Kids [3 0 R null] >>");
+}
+
+#[test]
+fn test_encrypt() {
+    let written = |object, data: Option<&[u8]>| Written { generation: 0, object, data: data.map(<[u8]>::to_vec) };
+    let mut objects: BTreeMap<u64, Written> = vec![
+        (1, written(DictNode::Dict(hashmap!{ "Title".to_string() => DictNode::LiteralString(b"Secret".to_vec()) }), None)),
+        (4, written(DictNode::Dict(hashmap!{ "Length".to_string() => DictNode::Int(5) }), Some(b"BT ET"))),
+    ].into_iter().collect();
+    let mut trailer = hashmap!{
+        "Info".to_string() => DictNode::reference(1),
+        "ID".to_string() => DictNode::Array(vec![DictNode::HexString(vec![1; 16]), DictNode::HexString(vec![1; 16])]),
+    };
+    encrypt(&mut objects, &mut trailer, &Encryption { user_password: b"user".to_vec(), ..Encryption::default() });
+
+    // the /Encrypt dictionary comes after the rest, and the /ID keeps its
+    // first string
+    assert_eq!(trailer.get("Encrypt"), Some(&DictNode::reference(5)));
+    let ids = trailer["ID"].as_array().unwrap();
+    assert_eq!(ids[0], DictNode::HexString(vec![1; 16]));
+    assert_ne!(ids[1], ids[0]);
+
+    let handler = crypt::SecurityHandler::new(&objects[&5].object, Some(5), &[1; 16], b"user").unwrap();
+    assert_eq!(handler.decrypt_strings(1, 0, objects[&1].object.clone()).get("Title"), Some(&DictNode::LiteralString(b"Secret".to_vec())));
+    let stream = &objects[&4];
+    assert_eq!(stream.object.get("Length"), Some(&DictNode::Int(32)));
+    assert_eq!(handler.decrypt(4, 0, stream.data.as_ref().unwrap()), b"BT ET");
+}
+
+#[test]
+fn test_append_object_streams() {
+    use document::Options;
+    use fixtures::{Fixture, FixtureFile};
+    use index_cache;
+    use pages;
+    use text;
+
+    // retitles the document and reads the update back
+    let update = |doc: &mut Document, path: &Path| {
+        doc.set_object(6, DictNode::Dict(hashmap!{ "Title".to_string() => DictNode::LiteralString(b"Updated".to_vec()) }));
+        let updated = FixtureFile { path: path.with_extension("updated.pdf") };
+        fs::write(&updated.path, append(doc).unwrap()).unwrap();
+        let mut doc = Document::open(&updated.path, Options::default()).unwrap();
+        let info = doc.resolve(&DictNode::reference(6)).unwrap();
+        assert_eq!(info.get("Title"), Some(&DictNode::LiteralString(b"Updated".to_vec())));
+        let page = pages::pages(&mut doc).unwrap().remove(0);
+        assert!(text::extract(&mut doc, &page).unwrap().contains("Hello, world"));
+    };
+
+    // a startxref leading nowhere, so the xref is rebuilt from a scan and
+    // all of it, object stream entries included, goes into the update
+    let file = Fixture::ObjectStream.write();
+    let mut data = fs::read(&file.path).unwrap();
+    let end = data.windows(9).rposition(|window| window == b"startxref").unwrap();
+    data.truncate(end);
+    data.extend_from_slice(b"startxref\n1\n%%EOF\n");
+    fs::write(&file.path, data).unwrap();
+    let mut doc = Document::open_by_scanning(&file.path, Options::default()).unwrap();
+    assert_eq!(doc.startxref(), None);
+    update(&mut doc, &file.path);
+
+    // the xref read from the index the first open left
+    let file = Fixture::ObjectStream.write();
+    let cached = Options { index_cache: true, ..Options::default() };
+    let sidecar = FixtureFile { path: index_cache::sidecar_path(&file.path) };
+    Document::open(&file.path, cached.clone()).unwrap();
+    assert!(sidecar.path.exists());
+    let mut doc = Document::open(&file.path, cached).unwrap();
+    assert!(doc.startxref().is_some());
+    update(&mut doc, &file.path);
+}
+
+#[test]
+fn test_save_encrypted() {
+    use document::Options;
+    use fixtures::{Fixture, FixtureFile};
+    use info;
+    use pages;
+    use text;
+
+    let file = Fixture::Simple.write();
+    let encryption = Encryption { user_password: b"user".to_vec(), owner_password: b"owner".to_vec(), ..Encryption::default() };
+    let password = |password: &str| Options { password: Some(password.to_string()), ..Options::default() };
+    for &linearize in &[false, true] {
+        let mut doc = Document::open(&file.path, Options::default()).unwrap();
+        let saved = FixtureFile { path: file.path.with_extension(if linearize { "linearized.pdf" } else { "encrypted.pdf" }) };
+        let options = SaveOptions { linearize, encryption: Some(encryption.clone()), ..SaveOptions::default() };
+        save(&mut doc, &saved.path, &options).unwrap();
+        assert_eq!(String::from_utf8_lossy(&fs::read(&saved.path).unwrap()).contains("/Linearized 1"), linearize);
+
+        assert!(Document::open(&saved.path, password("wrong")).is_err());
+        let mut doc = Document::open(&saved.path, password("user")).unwrap();
+        assert!(doc.is_encrypted());
+        assert_eq!(info::info(&mut doc).unwrap().unwrap().title.as_deref(), Some("Fixture"));
+        let page = pages::pages(&mut doc).unwrap().remove(0);
+        assert!(text::extract(&mut doc, &page).unwrap().contains("Hello, world"));
+        // every stream decrypts and decodes, the hint stream included
+        let entries: Vec<XRefEntry> = doc.xref.entries().filter(|(_, entry)| !entry.is_free).map(|(_, entry)| entry.clone()).collect();
+        for entry in entries {
+            doc.stream(&entry).unwrap();
+        }
+    }
+}